@@ -0,0 +1,153 @@
+//! Benchmarks of [`Persister`] backends with standard workloads
+//!
+//! Every backend runs the same workloads on 1k and 100k notes, so that backends
+//! can be compared with each other and regressions show up before a release:
+//! - `read`: looking up single notes and querying the notes of a user by tag
+//! - `insert`: adding a note to a filled backend
+//! - `bulk_insert`: importing a batch of [`BATCH`] notes into a filled backend
+//! - `mixed`: nine reads followed by one update
+//! - `tag_heavy`: notes with many tags from a large pool of tags
+//!
+//! New backends only need to be added to [`backends`].
+//! Run a subset of the benchmarks with e.g. `cargo bench -- memory/read`.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use note_demo::models::note::Draft;
+use note_demo::models::query::NoteQuery;
+use note_demo::models::{Id, User, Visibility};
+use note_demo::persistence::memory::InMemoryStorage;
+use note_demo::persistence::Persister;
+
+/// The number of notes in the backend
+const SIZES: [usize; 2] = [1_000, 100_000];
+
+/// The notes are spread evenly across this number of users
+const USERS: usize = 10;
+
+/// The number of distinct tags in the `tag_heavy` workload
+const TAG_POOL: usize = 1_000;
+
+/// The number of tags of every note in the `tag_heavy` workload
+const TAGS_PER_NOTE: usize = 20;
+
+/// The number of notes of one import in the `bulk_insert` workload
+const BATCH: usize = 100;
+
+/// Generates a note with `tags` random tags out of `pool`
+fn draft(rng: &mut StdRng, number: usize, tags: usize, pool: usize) -> Draft {
+    Draft::new(
+        format!("Note {number}"),
+        format!("The body of note {number} with some words to search for"),
+        (0..tags)
+            .map(|_| format!("tag{}", rng.gen_range(0..pool)))
+            .collect(),
+        Visibility::Private,
+    )
+}
+
+/// Creates a backend with `count` notes and returns it together with its users
+fn populate<P>(new: fn() -> P, count: usize, tags: usize, pool: usize) -> (P, Vec<User>)
+where
+    P: for<'a> Persister<'a>,
+{
+    let mut rng = StdRng::seed_from_u64(count as u64);
+    let mut data = new();
+    let users = (0..USERS)
+        .map(|number| data.add_user(format!("user{number}"), None).clone())
+        .collect::<Vec<User>>();
+    for number in 0..count {
+        let draft = draft(&mut rng, number, tags, pool);
+        let _ = data.add_note(draft, &users[number % USERS]);
+    }
+    (data, users)
+}
+
+/// Runs all workloads against the backend that is created with `new`
+fn workloads<P>(c: &mut Criterion, backend: &str, new: fn() -> P)
+where
+    P: for<'a> Persister<'a>,
+{
+    let mut rng = StdRng::seed_from_u64(0);
+    for size in SIZES {
+        let (mut data, users) = populate(new, size, 3, 50);
+
+        let mut group = c.benchmark_group(format!("{backend}/read"));
+        group.bench_function(BenchmarkId::new("note", size), |b| {
+            b.iter(|| black_box(data.note(Id(rng.gen_range(0..size)))))
+        });
+        group.bench_function(BenchmarkId::new("query_by_tag", size), |b| {
+            let query = NoteQuery::new(Some("tag7".to_string()), None, None, None);
+            b.iter(|| black_box(data.query_notes(&users[0], &query).count()))
+        });
+        group.finish();
+
+        let mut group = c.benchmark_group(format!("{backend}/mixed"));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                for _ in 0..9 {
+                    black_box(data.note(Id(rng.gen_range(0..size))));
+                }
+                let id = Id(rng.gen_range(0..size));
+                let draft = draft(&mut rng, size, 3, 50);
+                black_box(data.update_note(draft, id));
+            })
+        });
+        group.finish();
+
+        // runs last, because every iteration adds notes
+        let mut group = c.benchmark_group(format!("{backend}/insert"));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                let draft = draft(&mut rng, size, 3, 50);
+                black_box(data.add_note(draft, &users[0]).id());
+            })
+        });
+        group.finish();
+
+        let mut group = c.benchmark_group(format!("{backend}/bulk_insert"));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                let drafts = (0..BATCH)
+                    .map(|number| draft(&mut rng, size + number, 3, 50))
+                    .collect();
+                black_box(data.add_notes_bulk(drafts, &users[0]).len());
+            })
+        });
+        group.finish();
+
+        let (mut data, users) = populate(new, size, TAGS_PER_NOTE, TAG_POOL);
+        let mut group = c.benchmark_group(format!("{backend}/tag_heavy"));
+        group.bench_function(BenchmarkId::new("tagged_notes", size), |b| {
+            b.iter(|| {
+                let label = format!("tag{}", rng.gen_range(0..TAG_POOL));
+                let tag = data.tag(&label).cloned();
+                black_box(tag.map(|tag| data.tagged_notes(&tag).count()))
+            })
+        });
+        group.bench_function(BenchmarkId::new("tag_stats", size), |b| {
+            b.iter(|| black_box(data.tag_stats(&users[0]).len()))
+        });
+        group.bench_function(BenchmarkId::new("insert", size), |b| {
+            b.iter(|| {
+                let draft = draft(&mut rng, size, TAGS_PER_NOTE, TAG_POOL);
+                black_box(data.add_note(draft, &users[0]).id());
+            })
+        });
+        group.finish();
+    }
+}
+
+/// All backends that are benchmarked
+fn backends(c: &mut Criterion) {
+    workloads(c, "memory", InMemoryStorage::default);
+}
+
+criterion_group! {
+    name = benches;
+    // the backends with 100k notes are slow to fill, fewer samples keep the runtime acceptable
+    config = Criterion::default().sample_size(10);
+    targets = backends
+}
+criterion_main!(benches);
@@ -0,0 +1,3385 @@
+//! The HTTP API: all handlers and the [`Router`] that connects them
+//!
+//! [`state`] and [`router`] set up the complete app, so that the binary and the
+//! integration tests in `tests/` serve the same endpoints.
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::{post, put};
+use axum::Json;
+use axum::Router;
+use chrono::{TimeDelta, Utc};
+use futures_util::stream::{self, Stream, StreamExt};
+use models::fieldset::{FieldSet, SparseView};
+use models::geo::NearQuery;
+use models::note::{BulkResult, BulkTag, Draft, MergeNotes, NoteList, NoteView, ViewedNote};
+use models::preferences::Preferences;
+use models::profile::{Profile, PublicProfile};
+use models::query::{
+    AsOfQuery, CountOptions, DateRange, GroupBy, ListOptions, NoteCount, NoteQuery, SavedSearch,
+    SearchDraft, SearchQuery, SortKey, StreamQuery, UnreadQuery,
+};
+use models::{Tag, TagMeta, TagStats};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+use tower::ServiceBuilder;
+use tracing::{info, warn};
+
+use axum::body::{Bytes, StreamBody};
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::DefaultBodyLimit;
+use axum::extract::OriginalUri;
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware;
+use axum::routing::{delete, get};
+
+use models::note::Note;
+
+use persistence::memory::InMemoryStorage;
+use persistence::supervisor::{Readiness, Supervisor};
+use persistence::{Capabilities, Compaction, Persister, StorageStats};
+
+use crate::activitypub::{
+    ActivityKind, Federation, InboxActivity, WebFingerQuery, ACTIVITY_JSON, JRD_JSON,
+};
+use crate::auth::oidc::{self, Callback, OidcClient};
+use crate::auth::session::{Session, Sessions};
+use crate::auth::{Admin, CurrentUser};
+use crate::cache::CacheMetrics;
+use crate::capture::{CaptureRequest, Capturer};
+use crate::config::{AccessLogMode, Config};
+use crate::dates;
+use crate::events::Entry;
+use crate::indexer::{Index, IndexEvent, Indexer};
+use crate::jobs::{JobQueue, VolatileStore};
+use crate::json::{self, StrictJson};
+use crate::mailer::{LogMailer, Mailer, SmtpMailer};
+use crate::models::access::AccessEntry;
+use crate::models::audit::AuditEntry;
+use crate::models::autosave::WorkingCopy;
+use crate::models::backup::{self, Backup, RestoreMode, RestoreOptions, RestoreReport};
+use crate::models::comment::{Comment, CommentDraft};
+use crate::models::export::{AccountDeletion, ComplianceExport};
+use crate::models::integration::{Integrations, ShareQuery};
+use crate::models::job::JobRecord;
+use crate::models::lifecycle;
+use crate::models::moderation::Flag;
+use crate::models::review::{Review, ReviewQuery};
+use crate::models::tag_rule::TagRules;
+use crate::models::template::Templates;
+use crate::models::token::{IssuedToken, ServiceToken, TokenDraft, TokenInfo};
+use crate::models::trash::{EmptyTrash, PurgedNotes, TrashConfirmation, TrashQuery};
+use crate::models::undo::{UndoDeletion, UndoToken};
+use crate::models::usage::{DailyUsage, UsageTotals};
+use crate::models::{Access, Id, User, Visibility, VisibilityFilter};
+use crate::moderation::Screening;
+use crate::notifier::LogNotifier;
+use crate::policy::{Action, Policy};
+use crate::report::{ReportFormat, ReportQuery};
+use crate::revisions::{EditConflict, Revision, RevisionInfo};
+use crate::share::Message;
+use crate::stats::rollup::DailyStats;
+use crate::stats::tag_graph::TagGraphView;
+use crate::tasks::{
+    collect_tags, federate, index_restored, record_mentions, AcceptFollow, CheckIntegrity,
+    CollectTags, Deliver, ExpireNotes, ExpireUndoTokens, ExpireWorkingCopies, Notify, PublishSite,
+    PurgeUser, Restore, SendDigest, SendDigests, ShareNote,
+};
+
+use crate::auth::ANONYMOUS_USER;
+use crate::bus::{EventBus, LocalBus, RedisBus};
+use crate::integrity::{IntegrityReport, Repair};
+use crate::{
+    auth, csv_export, idempotency, integrity, jobs, layers, models, moderation, msgpack, pdf,
+    persistence, report, site, AppState,
+};
+
+/// Creates the state with an empty [`InMemoryStorage`] and registers all background jobs
+///
+/// The jobs are not started, see [`JobQueue::start`].
+pub fn state(config: Config) -> AppState<InMemoryStorage> {
+    let state = AppState {
+        data: Arc::new(Mutex::new(
+            InMemoryStorage::default().with_note_limit(config.note_limit),
+        )),
+        indexer: Indexer::spawn(
+            config.search,
+            config.recent_notes,
+            match &config.event_bus {
+                Some(event_bus) => {
+                    Arc::new(RedisBus::new(event_bus).expect("invalid event bus configuration"))
+                        as Arc<dyn EventBus>
+                }
+                None => Arc::new(LocalBus::default()),
+            },
+        ),
+        config: Arc::new(config.clone()),
+        sessions: match &config.session_secret {
+            Some(secret) => Sessions::new(secret.as_bytes(), config.session_ttl),
+            None => {
+                warn!("NOTE_SESSION_SECRET is not set, sessions are invalid after a restart");
+                Sessions::random(config.session_ttl)
+            }
+        },
+        oidc: config
+            .oidc
+            .clone()
+            .map(|oidc| Arc::new(OidcClient::new(oidc))),
+        federation: config.activitypub.as_ref().map(|activitypub| {
+            Arc::new(Federation::new(activitypub).expect("invalid ActivityPub configuration"))
+        }),
+        notifier: Arc::new(LogNotifier),
+        mailer: match &config.smtp {
+            Some(smtp) => Arc::new(SmtpMailer::new(smtp).expect("invalid SMTP configuration"))
+                as Arc<dyn Mailer>,
+            None => Arc::new(LogMailer),
+        },
+        jobs: JobQueue::new(Arc::new(VolatileStore), jobs::BACKOFF),
+        storage: Supervisor::new(config.health.clone()),
+        capturer: Capturer::new(config.capture.clone()),
+    };
+    state.jobs.register::<PurgeUser>();
+    state.jobs.register::<ExpireUndoTokens>();
+    state.jobs.register::<ExpireWorkingCopies>();
+    state.jobs.register::<ExpireNotes>();
+    state.jobs.register::<Deliver>();
+    state.jobs.register::<AcceptFollow>();
+    state.jobs.register::<Notify>();
+    state.jobs.register::<CollectTags>();
+    state.jobs.register::<Restore>();
+    state.jobs.register::<PublishSite>();
+    state.jobs.register::<SendDigests>();
+    state.jobs.register::<SendDigest>();
+    state.jobs.register::<CheckIntegrity>();
+    state.jobs.register::<ShareNote>();
+    if state.config.smtp.is_some() {
+        SendDigests::start(&state);
+    }
+    state
+}
+
+/// Returns the routes of all endpoints, including the compression and body size layers
+pub fn router<P>(state: AppState<P>) -> Router
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    let config = state.config.clone();
+    Router::new()
+        .route("/", get(root))
+        .route("/readyz", get(readyz))
+        .route("/notes", get(notes))
+        .route("/notes/count", get(count_notes))
+        .route("/notes/tag/:tag_label", get(tagged_notes))
+        .route("/notes/tag/:tag_label/export", get(tag_export))
+        .route("/notes/search", get(search))
+        .route("/notes/near", get(notes_near))
+        .route("/notes/tags", post(bulk_tag))
+        .route("/notes/merge", post(merge_notes))
+        .route("/notes/import", post(import_notes))
+        .route("/capture", post(capture_page))
+        .route("/notes/import/restore", post(restore_notes))
+        .route("/notes/export", get(export_notes))
+        .route("/mentions", get(mentions))
+        .route("/events", get(events))
+        .route("/notes/favorites", get(favorites))
+        .route("/notes/trash", delete(empty_trash))
+        .route("/notes/trash/confirm", post(confirm_empty_trash))
+        .route("/notes/recent", get(recent_notes))
+        .route(
+            "/note/:id/favorite",
+            post(add_favorite).delete(remove_favorite),
+        )
+        .route(
+            "/note/:id",
+            get(get_note).put(edit_note).delete(delete_note),
+        )
+        .route("/note/slug/:slug", get(note_by_slug))
+        .route("/note/:id/related", get(related_notes))
+        .route("/note/:id/pdf", get(note_pdf))
+        .route("/note/:id/send", post(share_note))
+        .route(
+            "/note/:id/lock-content",
+            post(lock_note).delete(unlock_note),
+        )
+        .route(
+            "/note/:id/archive",
+            post(archive_note).delete(unarchive_note),
+        )
+        .route("/note/:id/comments", get(comments).post(add_comment))
+        .route("/note/:id/revisions", get(revisions))
+        .route("/note/:id/access-log", get(access_log))
+        .route("/note/:id/draft", get(working_copy).put(save_working_copy))
+        .route("/note/:id/draft/commit", post(commit_working_copy))
+        .route("/note/:id/revisions/:number", get(revision))
+        .route("/comment/:id", delete(delete_comment))
+        .route("/export.csv", get(export_csv))
+        .route("/note", post(add_note))
+        .route("/undo/:token", post(undo))
+        .route("/tags", get(tags))
+        .route("/tags/graph", get(tag_graph))
+        .route("/tag/:id", put(edit_tag))
+        .route("/searches", get(searches).post(add_search))
+        .route("/searches/:id/notes", get(search_notes))
+        .route("/stats/daily", get(daily_stats))
+        .route("/review", get(review))
+        .route("/me/usage", get(usage))
+        .route("/me/audit-log", get(audit_log))
+        .route("/me", get(me).put(set_profile).delete(delete_me))
+        .route("/me/tokens", get(service_tokens).post(add_service_token))
+        .route("/me/tokens/:id", delete(revoke_service_token))
+        .route("/user/:id", get(user_profile))
+        .route("/me/preferences", get(preferences).put(set_preferences))
+        .route("/me/tag-rules", get(tag_rules).put(set_tag_rules))
+        .route("/me/templates", get(templates).put(set_templates))
+        .route("/me/integrations", get(integrations).put(set_integrations))
+        .route("/admin/jobs", get(admin_jobs))
+        .route("/admin/capabilities", get(admin_capabilities))
+        .route("/admin/storage", get(admin_storage))
+        .route("/admin/compact", post(admin_compact))
+        .route("/admin/cache", get(admin_cache))
+        .route("/admin/usage", get(admin_usage))
+        .route("/admin/impersonate/:user_id", post(impersonate))
+        .route("/admin/users/:id/export", get(compliance_export))
+        .route("/admin/integrity", get(admin_integrity))
+        .route("/admin/integrity/fix", post(admin_integrity_fix))
+        .route("/admin/backup", post(admin_backup))
+        .route("/admin/moderation/queue", get(moderation_queue))
+        .route("/admin/moderation/:id/approve", post(approve_note))
+        .route("/admin/moderation/:id/reject", post(reject_note))
+        .route("/admin/restore", post(admin_restore))
+        .route("/admin/publish-site", post(admin_publish_site))
+        .route("/auth/oidc/login", get(oidc_login))
+        .route("/auth/oidc/callback", get(oidc_callback))
+        .route("/p/:slug", get(note_page))
+        .route("/sitemap.xml", get(sitemap))
+        .route("/.well-known/webfinger", get(webfinger))
+        .route("/users/:id", get(actor))
+        .route("/users/:id/outbox", get(outbox))
+        .route("/users/:id/followers", get(followers))
+        .route("/users/:id/inbox", post(inbox))
+        .route_layer(middleware::from_fn_with_state(
+            config.clone(),
+            layers::timeout,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_scope,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            layers::record_audit,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            config.clone(),
+            layers::cache_control,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.storage.clone(),
+            layers::reject_writes,
+        ))
+        .route_layer(middleware::from_fn(msgpack::negotiate))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            layers::record_usage,
+        ))
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(layers::decompression_error))
+                .layer(layers::decompression(&config.compression)),
+        )
+        .layer(DefaultBodyLimit::max(config.max_body_size))
+        .layer(layers::compression(&config.compression))
+        .with_state(state)
+}
+
+/// Returns the health of the storage backend, `503 Service Unavailable` during outages
+///
+/// The progress of building the indexes at startup is included, but requests are answered
+/// while the indexes are built, so it does not affect the status code.
+async fn readyz<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+) -> Response {
+    info!("GET /readyz");
+    let status = Readiness {
+        storage: state.storage.status(),
+        index: state.indexer.warm_up_status(),
+    };
+    if state.storage.is_up() {
+        info!("--> 200");
+        return Json(status).into_response();
+    }
+    let retry_after = state.storage.retry_after(&Utc::now()).as_secs();
+    info!("--> 503");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(status),
+    )
+        .into_response()
+}
+
+/// Used for debugging => Returns all notes of all users, including deleted ones, only to admins
+async fn root<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+) -> Result<Json<Vec<Note>>, (StatusCode, String)> {
+    info!("GET / [admin {}]", usize::from(admin.id()));
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = data
+        .notes_with(VisibilityFilter::All)
+        .cloned()
+        .collect::<Vec<Note>>();
+    info!("--> 200 [{} notes]", res.len());
+    Ok(Json(res))
+}
+
+/// Returns all notes from the user sending the request, optionally
+/// filtered by the [`NoteQuery`] in the query string
+///
+/// Only active notes are returned, unless the query selects another
+/// [`NoteState`](models::query::NoteState), e.g. `state=deleted` for a trash view.
+async fn notes<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<NoteQuery>,
+    Query(unread): Query<UnreadQuery>,
+    Query(as_of): Query<AsOfQuery>,
+    Query(options): Query<ListOptions>,
+    Query(fields): Query<FieldSet>,
+) -> Result<Json<SparseView<NoteList>>, (StatusCode, String)> {
+    info!("GET /notes/");
+    if let Some(at) = as_of.as_of() {
+        if query != NoteQuery::default() || unread != UnreadQuery::default() {
+            info!("--> 400");
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "as_of can't be combined with filters".to_string(),
+            ));
+        }
+        let data = state.data.lock().expect("mutex was poisoned");
+        let res = NoteList::new(
+            options.paginate(data.notes_as_of(&user, at), user.preferences().per_page()),
+            options.full(),
+            state.config.preview_length,
+            user.id(),
+        )
+        .with_groups(options.group(), &Utc::now(), user.preferences().timezone());
+        info!("--> 200 [{} notes as of {}]", res.len(), at);
+        return Ok(Json(fields.view(res)));
+    }
+    let query = query
+        .with_default_sort(user.preferences().sort())
+        .normalized();
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = state
+        .indexer
+        .queries()
+        .get_or_insert_with(*user.id(), &query, || {
+            data.query_notes(&user, &query)
+                .map(|note| *note.id())
+                .collect()
+        })
+        .into_iter()
+        .filter_map(|id| data.note_with(id, query.state().filter()))
+        .filter(|note| unread.matches(note, data.viewed_at(user.id(), note.id())))
+        .cloned()
+        .collect::<Vec<Note>>();
+    let res = NoteList::new(
+        options.paginate(res, user.preferences().per_page()),
+        options.full(),
+        state.config.preview_length,
+        user.id(),
+    )
+    .with_views(|id| data.viewed_at(user.id(), id))
+    .with_groups(options.group(), &Utc::now(), user.preferences().timezone());
+    info!("--> 200 [{} notes]", res.len());
+    Ok(Json(fields.view(res)))
+}
+
+/// Returns the number of notes that `GET /notes` would return with the same query, without pages
+///
+/// The estimate of the backend is used if it has one, unless `exact=true` is requested.
+async fn count_notes<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<NoteQuery>,
+    Query(options): Query<CountOptions>,
+) -> Json<NoteCount> {
+    info!("GET /notes/count");
+    let query = query.normalized();
+    let data = state.data.lock().expect("mutex was poisoned");
+    let estimate = (!options.exact())
+        .then(|| data.estimate_count(&user, &query))
+        .flatten();
+    let res = match estimate {
+        Some(count) => NoteCount {
+            count,
+            exact: false,
+        },
+        None => NoteCount {
+            count: state
+                .indexer
+                .queries()
+                .get_or_insert_with(*user.id(), &query, || {
+                    data.query_notes(&user, &query)
+                        .map(|note| *note.id())
+                        .collect()
+                })
+                .into_iter()
+                .filter(|id| data.note_with(*id, query.state().filter()).is_some())
+                .count(),
+            exact: true,
+        },
+    };
+    info!("--> 200 [{} notes]", res.count);
+    Json(res)
+}
+
+/// Returns the active notes that mention the user sending the request, most recently updated first
+///
+/// Notes of other users are only included if their visibility allows listing them.
+async fn mentions<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(options): Query<ListOptions>,
+    Query(fields): Query<FieldSet>,
+) -> Result<Json<SparseView<NoteList>>, (StatusCode, String)> {
+    info!("GET /mentions");
+    let data = state.data.lock().expect("mutex was poisoned");
+    let mut res = data
+        .mentions(&user)
+        .into_iter()
+        .filter(|note| {
+            VisibilityFilter::Active.includes(note, &Utc::now())
+                && Policy::new(&state.config.admin_users).can(user.id(), Action::List, *note)
+        })
+        .cloned()
+        .collect::<Vec<Note>>();
+    res.sort_by(|a, b| b.updated().cmp(a.updated()));
+    let res = NoteList::new(
+        options.paginate(res, user.preferences().per_page()),
+        options.full(),
+        state.config.preview_length,
+        user.id(),
+    )
+    .with_views(|id| data.viewed_at(user.id(), id));
+    info!("--> 200 [{} notes]", res.len());
+    Ok(Json(fields.view(res)))
+}
+
+/// Returns the favorite notes of the user sending the request, in the order of their Ids
+///
+/// Notes of other users are only included while the user may read them.
+async fn favorites<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(options): Query<ListOptions>,
+    Query(fields): Query<FieldSet>,
+) -> Result<Json<SparseView<NoteList>>, (StatusCode, String)> {
+    info!("GET /notes/favorites");
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = data
+        .favorites(user.id())
+        .into_iter()
+        .filter(|note| Policy::new(&state.config.admin_users).can(user.id(), Action::Read, *note))
+        .cloned()
+        .collect::<Vec<Note>>();
+    let res = NoteList::new(
+        options.paginate(res, user.preferences().per_page()),
+        options.full(),
+        state.config.preview_length,
+        user.id(),
+    )
+    .with_views(|id| data.viewed_at(user.id(), id));
+    info!("--> 200 [{} notes]", res.len());
+    Ok(Json(fields.view(res)))
+}
+
+/// Returns the notes that the user sending the request viewed or edited most recently,
+/// most recent first
+///
+/// The list is maintained by the [`Indexer`], so changes appear with a short delay.
+/// Notes of other users are only included while the user may read them.
+async fn recent_notes<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(options): Query<ListOptions>,
+    Query(fields): Query<FieldSet>,
+) -> Result<Json<SparseView<NoteList>>, (StatusCode, String)> {
+    info!("GET /notes/recent");
+    let ids = state.indexer.recent().of(user.id());
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = ids
+        .into_iter()
+        .filter_map(|id| data.note_with(id, VisibilityFilter::Active))
+        .filter(|note| Policy::new(&state.config.admin_users).can(user.id(), Action::Read, *note))
+        .cloned()
+        .collect::<Vec<Note>>();
+    let res = NoteList::new(
+        options.paginate(res, user.preferences().per_page()),
+        options.full(),
+        state.config.preview_length,
+        user.id(),
+    )
+    .with_views(|id| data.viewed_at(user.id(), id));
+    info!("--> 200 [{} notes]", res.len());
+    Ok(Json(fields.view(res)))
+}
+
+/// Marks a note as favorite of the user sending the request
+///
+/// All notes that the user may read can be favorites, see [`authorized_note`].
+async fn add_favorite<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    info!("POST /note/{}/favorite", id);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let note = *authorized_note(&*data, &state.config, &user, id.into(), Action::Read)?.id();
+    data.set_favorite(*user.id(), note, true);
+    info!("--> 204");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Removes a note from the favorites of the user sending the request
+async fn remove_favorite<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    info!("DELETE /note/{}/favorite", id);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    if !data.set_favorite(*user.id(), id.into(), false) {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note is not a favorite".to_string()));
+    }
+    info!("--> 204");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Streams the lifecycle events of the notes of the user sending the request as server-sent events
+///
+/// Clients that reconnect with a `Last-Event-ID` header first receive the events they
+/// missed, as long as they are still in the [`Journal`](events::Journal).
+async fn events<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    info!("GET /events");
+    let last_id = match headers.get("last-event-id") {
+        None => None,
+        Some(value) => match value.to_str().ok().and_then(|id| id.trim().parse().ok()) {
+            Some(id) => Some(id),
+            None => {
+                info!("--> 400");
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "Invalid Last-Event-ID header".to_string(),
+                ));
+            }
+        },
+    };
+    let (backlog, receiver) = state.indexer.journal().subscribe(last_id);
+    info!("--> 200 [{} events to resume]", backlog.len());
+    let live = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(entry) => return Some((entry, receiver)),
+                Err(RecvError::Lagged(count)) => warn!("SSE client missed {} events", count),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let user = *user.id();
+    let stream = stream::iter(backlog)
+        .chain(live)
+        .filter(move |entry| std::future::ready(entry.user == user))
+        .map(|entry: Entry| {
+            Ok(Event::default()
+                .id(entry.id.to_string())
+                .event(entry.event.name())
+                .json_data(&entry.event)
+                .expect("events can be serialized"))
+        });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Returns all notes of the user sending the request within a radius around a location
+async fn notes_near<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<NearQuery>,
+    Query(options): Query<ListOptions>,
+    Query(fields): Query<FieldSet>,
+) -> Result<Json<SparseView<NoteList>>, (StatusCode, String)> {
+    info!("GET /notes/near");
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = data
+        .notes_near(&user, query.center(), query.radius_m())
+        .into_iter()
+        .cloned()
+        .collect::<Vec<Note>>();
+    let res = NoteList::new(
+        options.paginate(res, user.preferences().per_page()),
+        options.full(),
+        state.config.preview_length,
+        user.id(),
+    )
+    .with_views(|id| data.viewed_at(user.id(), id));
+    info!("--> 200 [{} notes]", res.len());
+    Ok(Json(fields.view(res)))
+}
+
+/// Returns the daily activity of the user sending the request
+async fn daily_stats<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(range): Query<DateRange>,
+) -> Result<Json<Vec<DailyStats>>, (StatusCode, String)> {
+    info!("GET /stats/daily");
+    let timezone = user.preferences().timezone();
+    let (from, to) = range.resolve(dates::today(timezone));
+    if from > to {
+        info!("--> 400");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "`from` must not be after `to`".to_string(),
+        ));
+    }
+    let res = state.indexer.rollup().query(user.id(), from, to, timezone);
+    info!("--> 200 [{} days]", res.len());
+    Ok(Json(res))
+}
+
+/// Groups the active notes of the user sending the request into the ones that were created,
+/// edited or left untouched in a period, e.g. for a weekly review
+async fn review<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<ReviewQuery>,
+) -> Result<Json<Review>, (StatusCode, String)> {
+    info!("GET /review");
+    let timezone = user.preferences().timezone();
+    let Some((from, to)) = query
+        .period
+        .bounds(query.date.unwrap_or_else(|| dates::today(timezone)))
+    else {
+        info!("--> 400");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The period is out of the range of dates".to_string(),
+        ));
+    };
+    let activity = state
+        .indexer
+        .rollup()
+        .activity(user.id(), from, to, timezone);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = Review::new(
+        query.period,
+        (from, to),
+        data.user_notes(&user),
+        activity,
+        state.config.preview_length,
+        user.id(),
+        timezone,
+    );
+    info!("--> 200");
+    Ok(Json(res))
+}
+
+/// Returns the requests that admins sent on behalf of the user sending the request, most
+/// recent first, see [`impersonate`]
+async fn audit_log<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+) -> Json<Vec<AuditEntry>> {
+    info!("GET /me/audit-log");
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = data
+        .audit_log(user.id())
+        .into_iter()
+        .rev()
+        .cloned()
+        .collect::<Vec<AuditEntry>>();
+    info!("--> 200 [{} entries]", res.len());
+    Json(res)
+}
+
+/// Returns the API usage of the user sending the request per day, see [`layers::record_usage`]
+///
+/// The range defaults to the last 30 days, days without requests are omitted.
+async fn usage<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(range): Query<DateRange>,
+) -> Result<Json<Vec<DailyUsage>>, (StatusCode, String)> {
+    info!("GET /me/usage");
+    let (from, to) = range.resolve(dates::today(user.preferences().timezone()));
+    if from > to {
+        info!("--> 400");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "`from` must not be after `to`".to_string(),
+        ));
+    }
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = data.usage(user.id(), from, to);
+    info!("--> 200 [{} days]", res.len());
+    Ok(Json(res))
+}
+
+/// Returns a single note that the user sending the request may read, see [`authorized_note`]
+///
+/// The view is recorded, the response contains the time of the previous view.
+/// Notes that were merged into another note redirect to it.
+async fn get_note<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+    Query(fields): Query<FieldSet>,
+) -> Result<Response, (StatusCode, String)> {
+    info!("GET /note/{}", id);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    if let Some(target) =
+        merged_target(&*data, data.note_with(id.into(), VisibilityFilter::Deleted)).filter(
+            |target| Policy::new(&state.config.admin_users).can(user.id(), Action::Read, *target),
+        )
+    {
+        info!("--> 308");
+        let target = usize::from(target.id());
+        return Ok(Redirect::permanent(&format!("/note/{target}")).into_response());
+    }
+    let note = authorized_note(&*data, &state.config, &user, id.into(), Action::Read)?.clone();
+    let viewed_at = data.set_viewed(*user.id(), *note.id(), Utc::now());
+    record_access(&state.config, &mut *data, &note, user.id());
+    state.indexer.send(IndexEvent::Viewed {
+        user: *user.id(),
+        note: *note.id(),
+    });
+    let etag = etag(&*data, note.id());
+    info!("--> 200");
+    Ok((
+        [(header::ETAG, etag)],
+        Json(fields.view(ViewedNote::new(NoteView::new(note, user.id()), viewed_at))),
+    )
+        .into_response())
+}
+
+/// Returns the active note that the deleted note `source` was merged into
+///
+/// Follows the merges if the target was merged into another note later on.
+fn merged_target<'a, P: for<'b> persistence::Persister<'b>>(
+    data: &'a P,
+    source: Option<&Note>,
+) -> Option<&'a Note> {
+    let mut target = *source?.merged_into()?;
+    loop {
+        if let Some(note) = data.note_with(target, VisibilityFilter::Active) {
+            return Some(note);
+        }
+        target = *data
+            .note_with(target, VisibilityFilter::Deleted)?
+            .merged_into()?;
+    }
+}
+
+/// Returns the deleted note with the slug
+fn deleted_by_slug<'a, P: for<'b> persistence::Persister<'b>>(
+    data: &'a P,
+    slug: &str,
+) -> Option<&'a Note> {
+    data.notes_with(VisibilityFilter::Deleted)
+        .find(|note| note.slug() == Some(slug))
+}
+
+/// Returns a single note by its slug
+///
+/// Notes of other users are returned if their visibility allows it, see [`authorized_note`].
+/// Notes that were merged into another note redirect to it.
+async fn note_by_slug<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(slug): Path<String>,
+    Query(fields): Query<FieldSet>,
+) -> Result<Response, (StatusCode, String)> {
+    info!("GET /note/slug/{}", slug);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    if let Some(target) = merged_target(&*data, deleted_by_slug(&*data, &slug)).filter(|target| {
+        Policy::new(&state.config.admin_users).can(user.id(), Action::Read, *target)
+    }) {
+        info!("--> 308");
+        let target = usize::from(target.id());
+        return Ok(Redirect::permanent(&format!("/note/{target}")).into_response());
+    }
+    let Some(note) = data.note_by_slug(&slug).cloned() else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note does not exist".to_string()));
+    };
+    Policy::new(&state.config.admin_users).check(user.id(), Action::Read, &note)?;
+    record_access(&state.config, &mut *data, &note, user.id());
+    info!("--> 200");
+    Ok(Json(fields.view(NoteView::new(note, user.id()))).into_response())
+}
+
+/// Returns a single note that the user sending the request may read as PDF document
+async fn note_pdf<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    info!("GET /note/{}/pdf", id);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let note = authorized_note(&*data, &state.config, &user, id.into(), Action::Read)?;
+    let document = pdf::render(note);
+    info!("--> 200 [{} bytes]", document.len());
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"note-{id}.pdf\""),
+            ),
+        ],
+        document,
+    ))
+}
+
+/// Queues a [`ShareNote`] job that sends a summary of the note to a connected chat service
+///
+/// The summary links to the public page of the note if anonymous visitors may read it.
+async fn share_note<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+    Query(query): Query<ShareQuery>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<JobRecord>), (StatusCode, String)> {
+    info!("POST /note/{}/send?target={}", id, query.target.name());
+    if !user.integrations().is_connected(query.target) {
+        info!("--> 422");
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{} is not connected", query.target.name()),
+        ));
+    }
+    let message = {
+        let data = state.data.lock().expect("mutex was poisoned");
+        let note = authorized_note(&*data, &state.config, &user, id.into(), Action::Read)?;
+        let link = note
+            .slug()
+            .filter(|_| note.visibility().allows(&ANONYMOUS_USER, Access::Link))
+            .map(|slug| format!("{}/p/{slug}", base_url(&state, &headers)));
+        Message::new(note, link.as_deref(), state.config.preview_length)
+    };
+    let id = state
+        .jobs
+        .enqueue(&ShareNote::new(*user.id(), query.target, message));
+    let Some(job) = state.jobs.jobs().into_iter().find(|job| job.id() == &id) else {
+        info!("--> 500");
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Job was not queued".to_string(),
+        ));
+    };
+    info!("--> 202");
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Returns the active note if the [`Policy`] allows the action of the user
+fn authorized_note<'a, P: for<'b> persistence::Persister<'b>>(
+    data: &'a P,
+    config: &Config,
+    user: &User,
+    id: Id,
+    action: Action,
+) -> Result<&'a Note, (StatusCode, String)> {
+    let Some(note) = data.note_with(id, VisibilityFilter::Active) else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note does not exist".to_string()));
+    };
+    Policy::new(&config.admin_users).check(user.id(), action, note)?;
+    Ok(note)
+}
+
+/// Merges notes of the user sending the request into the first of them
+///
+/// The first note gets the bodies and tags of the others, see [`Note::merge`].
+/// The other notes are deleted, links to them redirect to the merged note.
+async fn merge_notes<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(merge): StrictJson<MergeNotes>,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    info!("POST /notes/merge [{} notes]", merge.notes().len());
+    if let Err(err) = merge.validate() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, err));
+    }
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let mut sources = vec![];
+    for id in merge.notes() {
+        let note = authorized_note(&*data, &state.config, &user, *id, Action::Edit)?;
+        if note.locked() {
+            info!("--> 409");
+            return Err((StatusCode::CONFLICT, "Note is locked".to_string()));
+        }
+        // all but the first note are deleted
+        if !sources.is_empty() {
+            check_transition(&state.config, note, lifecycle::Event::Delete)?;
+        }
+        sources.push(note.clone());
+    }
+    let target = sources.remove(0);
+    let note = data
+        .merge_notes(*target.id(), merge.notes().get(1..).unwrap_or_default())
+        .clone();
+    record_mentions(&state, &mut *data, &note);
+    collect_tags(&state);
+    let followers = data.followers(user.id());
+    for source in sources {
+        if source.visibility() == &Visibility::Public {
+            federate(&state, &followers, ActivityKind::Delete, &source);
+        }
+        state
+            .indexer
+            .send(IndexEvent::transition(lifecycle::Event::Delete, source));
+    }
+    if note.visibility() == &Visibility::Public {
+        federate(&state, &followers, ActivityKind::Update, &note);
+    }
+    state.indexer.send(IndexEvent::Updated(note.clone()));
+    info!("--> 200");
+    Ok(Json(note))
+}
+
+/// Checks that the configured [`Lifecycle`](crate::models::lifecycle::Lifecycle) allows the
+/// event for the note
+///
+/// Archived notes whose expiry has passed can't become active again, they would expire at once.
+fn check_transition(
+    config: &Config,
+    note: &Note,
+    event: lifecycle::Event,
+) -> Result<(), (StatusCode, String)> {
+    let next = config
+        .lifecycle
+        .next(note.state(), event, note.archived())
+        .map_err(|err| {
+            info!("--> 409");
+            (StatusCode::CONFLICT, err)
+        })?;
+    if next == lifecycle::State::Active && note.expires_at().is_some_and(|at| at <= &Utc::now()) {
+        info!("--> 409");
+        return Err((StatusCode::CONFLICT, "Note has expired".to_string()));
+    }
+    Ok(())
+}
+
+/// Records that the user opened a note of another user, depending on [`AccessLogMode`]
+fn record_access<P: for<'a> persistence::Persister<'a>>(
+    config: &Config,
+    data: &mut P,
+    note: &Note,
+    user: &Id,
+) {
+    let user = match config.access_log {
+        AccessLogMode::Off => return,
+        _ if note.user() == user => return,
+        AccessLogMode::Anonymized => None,
+        AccessLogMode::Full => Some(*user),
+    };
+    data.record_access(AccessEntry::new(*note.id(), user, Utc::now()));
+}
+
+/// Returns who opened a note of the user sending the request and when, most recent first
+async fn access_log<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<Vec<AccessEntry>>, (StatusCode, String)> {
+    info!("GET /note/{}/access-log", id);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let note = *authorized_note(&*data, &state.config, &user, id.into(), Action::Inspect)?.id();
+    let res = data
+        .access_log(&note)
+        .into_iter()
+        .rev()
+        .cloned()
+        .collect::<Vec<AccessEntry>>();
+    info!("--> 200 [{} entries]", res.len());
+    Ok(Json(res))
+}
+
+/// Returns the revisions of a note of the user sending the request, oldest first
+async fn revisions<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<Vec<RevisionInfo>>, (StatusCode, String)> {
+    info!("GET /note/{}/revisions", id);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let note = *authorized_note(&*data, &state.config, &user, id.into(), Action::Inspect)?.id();
+    let res = data.revisions(&note);
+    info!("--> 200 [{} revisions]", res.len());
+    Ok(Json(res))
+}
+
+/// Returns a single revision of a note of the user sending the request, including its body
+async fn revision<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path((id, number)): Path<(usize, usize)>,
+) -> Result<Json<Revision>, (StatusCode, String)> {
+    info!("GET /note/{}/revisions/{}", id, number);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let note = *authorized_note(&*data, &state.config, &user, id.into(), Action::Inspect)?.id();
+    let Some(res) = data.revision(&note, number) else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Revision does not exist".to_string()));
+    };
+    info!("--> 200");
+    Ok(Json(res))
+}
+
+/// Returns the comments of a note, oldest first
+///
+/// Comments can be read by all users who may read the note, see [`authorized_note`].
+async fn comments<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<Vec<Comment>>, (StatusCode, String)> {
+    info!("GET /note/{}/comments", id);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let note = authorized_note(&*data, &state.config, &user, id.into(), Action::Read)?;
+    let res = data
+        .comments(note.id())
+        .into_iter()
+        .cloned()
+        .collect::<Vec<Comment>>();
+    info!("--> 200 [{} comments]", res.len());
+    Ok(Json(res))
+}
+
+/// Adds a comment of the user sending the request to a note
+async fn add_comment<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+    StrictJson(draft): StrictJson<CommentDraft>,
+) -> Result<Json<Comment>, (StatusCode, String)> {
+    info!("POST /note/{}/comments", id);
+    if let Err(err) = draft.validate() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, err));
+    }
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let note = *authorized_note(&*data, &state.config, &user, id.into(), Action::Comment)?.id();
+    let comment = data.add_comment(note, draft, &user).clone();
+    info!("--> 200");
+    Ok(Json(comment))
+}
+
+/// Deletes a comment
+///
+/// Comments can be deleted by their author and by the owner of the note.
+async fn delete_comment<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<Comment>, (StatusCode, String)> {
+    info!("DELETE /comment/{}", id);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let Some(comment) = data.comment(id.into()) else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Comment does not exist".to_string()));
+    };
+    let note = data.note_with(*comment.note(), VisibilityFilter::All);
+    Policy::new(&state.config.admin_users).check_comment(user.id(), comment, note)?;
+    let comment = comment.clone();
+    data.delete_comment(id.into());
+    info!("--> 200");
+    Ok(Json(comment))
+}
+
+/// Returns the metadata of all notes from the user sending the request as CSV
+///
+/// The rows are encoded while the response is streamed to the client.
+async fn export_csv<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+) -> impl IntoResponse {
+    info!("GET /export.csv");
+    let notes = state
+        .data
+        .lock()
+        .expect("mutex was poisoned")
+        .user_notes(&user)
+        .cloned()
+        .collect::<Vec<Note>>();
+    info!("--> 200 [{} notes]", notes.len());
+    let rows = std::iter::once(csv_export::header())
+        .chain(notes.into_iter().map(|note| csv_export::row(&note)))
+        .map(Ok::<_, Infallible>);
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"notes.csv\"",
+            ),
+        ],
+        StreamBody::new(stream::iter(rows)),
+    )
+}
+
+/// Creates a new note and stores it
+///
+/// Public notes are delivered to the followers of the user.
+async fn add_note<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    headers: HeaderMap,
+    StrictJson(draft): StrictJson<Draft>,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    info!("POST /note/{}", draft.title());
+    let draft = validate_draft(&state.config, &user, draft)?
+        .with_default_visibility(user.preferences().visibility());
+    let key = idempotency::key(&headers)?;
+    let window = state.config.idempotency_window;
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    if let Some(key) = &key {
+        if let Some(note) = idempotency::replay(&*data, &user, key, &draft, window)? {
+            info!("--> 200");
+            return Ok(Json(note));
+        }
+    }
+    let note = store_note(&state, &mut *data, &user, draft.clone())?;
+    if let Some(key) = key {
+        idempotency::remember(&mut *data, &user, key, &draft, &note, window);
+    }
+    info!("--> 200");
+    Ok(Json(note))
+}
+
+/// Normalizes the title, validates the draft and its fields against the [`Templates`] of the user
+/// and applies the [`TagRules`] of the user to its visibility
+fn validate_draft(
+    config: &Config,
+    user: &User,
+    draft: Draft,
+) -> Result<Draft, (StatusCode, String)> {
+    draft
+        .normalize(config.max_title_length, config.title_icons)
+        .and_then(|draft| draft.validate().map(|_| draft))
+        .and_then(|draft| {
+            user.templates()
+                .check(draft.template(), draft.fields())
+                .map(|_| draft)
+        })
+        .and_then(|draft| user.tag_rules().apply(draft))
+        .map_err(|err| {
+            info!("--> 422");
+            (StatusCode::UNPROCESSABLE_ENTITY, err)
+        })
+}
+
+/// Screens and stores a new note of a validated draft, then indexes and federates it
+fn store_note<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    state: &AppState<P>,
+    data: &mut P,
+    user: &User,
+    draft: Draft,
+) -> Result<Note, (StatusCode, String)> {
+    let since = Utc::now()
+        - TimeDelta::from_std(state.config.moderation.burst_window)
+            .expect("burst window is out of range");
+    let recent = data
+        .user_notes(user)
+        .filter(|note| note.created() >= &since)
+        .count();
+    let (screened, screening) = moderation::screen(&state.config.moderation, draft, recent);
+    reserve_notes(state, data, 1)?;
+    let note = data.add_note(screened, user).clone();
+    flag_note(data, screening, &note);
+    record_mentions(state, data, &note);
+    ExpireNotes::schedule(state, &note);
+    if note.visibility() == &Visibility::Public {
+        federate(
+            state,
+            &data.followers(user.id()),
+            ActivityKind::Create,
+            &note,
+        );
+    }
+    state.indexer.send(IndexEvent::transition(
+        lifecycle::Event::Create,
+        note.clone(),
+    ));
+    Ok(note)
+}
+
+/// Fetches a web page and stores it as bookmark with its readable content, see [`crate::capture`]
+async fn capture_page<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(request): StrictJson<CaptureRequest>,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    info!("POST /capture [{}]", request.url());
+    let page = state
+        .capturer
+        .fetch(request.url())
+        .await
+        .inspect_err(|(status, _)| info!("--> {}", status.as_u16()))?;
+    let draft = validate_draft(&state.config, &user, page.draft(&request))?
+        .with_default_visibility(user.preferences().visibility());
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let note = store_note(&state, &mut *data, &user, draft)?;
+    info!("--> 200");
+    Ok(Json(note))
+}
+
+/// Makes room for `count` new notes, see [`persistence::Persister::reserve_notes`]
+///
+/// Evicted notes are removed from the index. If the storage is full, the request
+/// is answered with `507 Insufficient Storage`.
+fn reserve_notes<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    state: &AppState<P>,
+    data: &mut P,
+    count: usize,
+) -> Result<(), (StatusCode, String)> {
+    let Some(evicted) = data.reserve_notes(count) else {
+        info!("--> 507");
+        return Err((
+            StatusCode::INSUFFICIENT_STORAGE,
+            "The maximum number of notes is reached".to_string(),
+        ));
+    };
+    if !evicted.is_empty() {
+        warn!("Evicted {} notes to make room for new notes", evicted.len());
+        for note in evicted {
+            state.indexer.send(IndexEvent::Deleted(note));
+        }
+        collect_tags(state);
+    }
+    Ok(())
+}
+
+/// Creates all notes of the payload at once, e.g. to migrate from another app
+///
+/// The drafts are validated before any note is created. Imported notes are not
+/// federated, so that followers are not flooded with old notes.
+async fn import_notes<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(drafts): StrictJson<Vec<Draft>>,
+) -> Result<Json<Vec<Note>>, (StatusCode, String)> {
+    info!("POST /notes/import [{} notes]", drafts.len());
+    let drafts = drafts
+        .into_iter()
+        .enumerate()
+        .map(|(index, draft)| {
+            validate_draft(&state.config, &user, draft)
+                .map_err(|(status, err)| (status, format!("Note {index}: {err}")))
+        })
+        .collect::<Result<Vec<Draft>, (StatusCode, String)>>()?;
+    // the burst heuristic does not apply, imports create many notes at once
+    let (drafts, screenings): (Vec<Draft>, Vec<Screening>) = drafts
+        .into_iter()
+        .map(|draft| {
+            let draft = draft.with_default_visibility(user.preferences().visibility());
+            moderation::screen(&state.config.moderation, draft, 0)
+        })
+        .unzip();
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    reserve_notes(&state, &mut *data, drafts.len())?;
+    let ids = data.add_notes_bulk(drafts, &user);
+    let notes = ids
+        .into_iter()
+        .filter_map(|id| data.note_with(id, VisibilityFilter::All).cloned())
+        .collect::<Vec<Note>>();
+    for (note, screening) in notes.iter().zip(screenings) {
+        flag_note(&mut *data, screening, note);
+        record_mentions(&state, &mut *data, note);
+        ExpireNotes::schedule(&state, note);
+        state.indexer.send(IndexEvent::Added(note.clone()));
+    }
+    info!("--> 200 [{} notes]", notes.len());
+    Ok(Json(notes))
+}
+
+/// Returns all notes of the user sending the request, including archived and deleted ones
+///
+/// The notes can be restored without any loss with `POST /notes/import/restore`.
+async fn export_notes<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+) -> impl IntoResponse {
+    info!("GET /notes/export");
+    let data = state.data.lock().expect("mutex was poisoned");
+    let mut notes = data
+        .notes_with(VisibilityFilter::All)
+        .filter(|note| note.user() == user.id())
+        .cloned()
+        .collect::<Vec<Note>>();
+    notes.sort_by(|a, b| SortKey::Id.compare(a, b));
+    info!("--> 200 [{} notes]", notes.len());
+    (
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"notes.json\"",
+        )],
+        Json(notes),
+    )
+}
+
+/// Restores notes of `GET /notes/export` with their Ids, timestamps, states and tags
+///
+/// Unlike [`import_notes`], nothing is changed or remapped. Notes and tags that exist with the
+/// same Id must be identical, otherwise nothing is restored and the conflicts are returned with
+/// `409 Conflict`. The notes are validated like new notes and public notes are screened by
+/// [`moderation`], quarantined notes are restored as private notes.
+async fn restore_notes<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(notes): StrictJson<Vec<Note>>,
+) -> Result<Json<Vec<Note>>, (StatusCode, String)> {
+    info!("POST /notes/import/restore [{} notes]", notes.len());
+    if let Some(note) = notes.iter().find(|note| note.user() != user.id()) {
+        info!("--> 403");
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Note {} belongs to other user", usize::from(note.id())),
+        ));
+    }
+    for note in &notes {
+        // deleted notes are validated like the notes they were before
+        let draft = match note.visibility() {
+            Visibility::Deleted => Draft::from(note).with_visibility(Visibility::Private),
+            _ => Draft::from(note),
+        };
+        validate_draft(&state.config, &user, draft)
+            .map_err(|(status, err)| (status, format!("Note {}: {err}", usize::from(note.id()))))?;
+    }
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let Some(stored) = data.user(*user.id()).cloned() else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "User does not exist".to_string()));
+    };
+    let mut tags = BTreeMap::new();
+    for tag in notes.iter().flat_map(Note::tags) {
+        tags.entry(usize::from(tag.id()))
+            .or_insert_with(|| tag.clone());
+    }
+    let backup = Backup::new(
+        vec![stored],
+        notes,
+        tags.into_values().collect(),
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let errors = backup.validate();
+    if !errors.is_empty() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, errors.join("\n")));
+    }
+    let conflicts = data.merge_conflicts(&backup);
+    if !conflicts.is_empty() {
+        info!("--> 409 [{} conflicts]", conflicts.len());
+        return Err((StatusCode::CONFLICT, conflicts.join("\n")));
+    }
+    let missing = backup
+        .notes()
+        .iter()
+        .filter(|note| data.note_with(*note.id(), VisibilityFilter::All).is_none())
+        .count();
+    reserve_notes(&state, &mut *data, missing)?;
+    let (restored, screenings): (Vec<Note>, Vec<Screening>) = backup
+        .notes()
+        .iter()
+        .filter(|note| data.note_with(*note.id(), VisibilityFilter::All).is_none())
+        .map(|note| {
+            let (draft, screening) =
+                moderation::screen(&state.config.moderation, Draft::from(note), 0);
+            let mut note = note.clone();
+            if let Some(visibility) = draft.visibility() {
+                note.set_visibility(visibility.clone());
+            }
+            (note, screening)
+        })
+        .unzip();
+    let screened = Backup::new(
+        backup.users().to_vec(),
+        restored.clone(),
+        backup.tags().to_vec(),
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    );
+    data.restore(&screened, RestoreMode::Merge, &mut |_| {});
+    for (note, screening) in restored.iter().zip(screenings) {
+        flag_note(&mut *data, screening, note);
+    }
+    index_restored(&state, &mut *data, &restored);
+    info!("--> 200 [{} notes restored]", restored.len());
+    Ok(Json(restored))
+}
+
+/// Modifies an existing note of the user sending the request
+///
+/// Edits with an outdated `If-Match` revision are rejected with a merge proposal, see [`check_revision`].
+async fn edit_note<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+    headers: HeaderMap,
+    StrictJson(draft): StrictJson<Draft>,
+) -> Result<Response, (StatusCode, String)> {
+    info!("PUT /note/{}", id);
+    let draft = validate_draft(&state.config, &user, draft)?;
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let Some(note) = data.note_with(id.into(), VisibilityFilter::Active) else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note does not exist".to_string()));
+    };
+    Policy::new(&state.config.admin_users).check(user.id(), Action::Edit, note)?;
+    if note.locked() {
+        info!("--> 409");
+        return Err((StatusCode::CONFLICT, "Note is locked".to_string()));
+    }
+    if let Some(conflict) = check_revision(&*data, &headers, note, &draft)? {
+        return Ok(conflict);
+    }
+    let note = moderated_edit(&state, &mut *data, &user, id.into(), draft);
+    let etag = etag(&*data, note.id());
+    info!("--> 200");
+    Ok(([(header::ETAG, etag)], Json(note)).into_response())
+}
+
+/// Returns the `ETag` of a note, the number of its latest revision
+fn etag<P: for<'a> persistence::Persister<'a>>(data: &P, note: &Id) -> String {
+    format!("\"{}\"", data.revisions(note).len())
+}
+
+/// Compares the revision of the `If-Match` header with the latest revision of the note
+///
+/// Returns a `412 Precondition Failed` response with an [`EditConflict`] if the
+/// edit was based on an older revision. Edits without `If-Match` or with `If-Match: *`
+/// always pass.
+fn check_revision<P: for<'a> persistence::Persister<'a>>(
+    data: &P,
+    headers: &HeaderMap,
+    note: &Note,
+    draft: &Draft,
+) -> Result<Option<Response>, (StatusCode, String)> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value.to_str().unwrap_or_default().trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    let Ok(base) = value
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse::<usize>()
+    else {
+        info!("--> 400 [invalid If-Match]");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Invalid If-Match header".to_string(),
+        ));
+    };
+    if base == data.revisions(note.id()).len() {
+        return Ok(None);
+    }
+    let conflict = EditConflict::new(note.clone(), data.revision(note.id(), base), draft);
+    info!("--> 412");
+    Ok(Some(
+        (StatusCode::PRECONDITION_FAILED, Json(conflict)).into_response(),
+    ))
+}
+
+/// Screens the edit with the spam heuristics of [`moderation`] before it is applied
+fn moderated_edit<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    state: &AppState<P>,
+    data: &mut P,
+    user: &User,
+    id: Id,
+    draft: Draft,
+) -> Note {
+    let draft = match data.note(id) {
+        Some(note) if draft.visibility().is_none() => {
+            draft.with_visibility(note.visibility().clone())
+        }
+        _ => draft,
+    };
+    let (draft, screening) = moderation::screen(&state.config.moderation, draft, 0);
+    let note = apply_edit(state, data, user, id, draft);
+    flag_note(data, screening, &note);
+    note
+}
+
+/// Adds the note to the moderation queue if the [`Screening`] found any spam
+fn flag_note<P: for<'a> persistence::Persister<'a>>(
+    data: &mut P,
+    screening: Screening,
+    note: &Note,
+) {
+    if let Some(flag) = screening.flag(note) {
+        info!(
+            "Flagged note {} [{} reasons]",
+            usize::from(note.id()),
+            flag.reasons().len()
+        );
+        data.set_flag(flag);
+    }
+}
+
+/// Replaces the content of the note and notifies mentioned users, followers and the indexer
+///
+/// The caller must check that the user may edit the note.
+fn apply_edit<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    state: &AppState<P>,
+    data: &mut P,
+    user: &User,
+    id: Id,
+    draft: Draft,
+) -> Note {
+    let was_public = data
+        .note(id)
+        .is_some_and(|note| note.visibility() == &Visibility::Public);
+    let note = data.update_note(draft, id).clone();
+    record_mentions(state, data, &note);
+    ExpireNotes::schedule(state, &note);
+    collect_tags(state);
+    let activity = match (was_public, note.visibility() == &Visibility::Public) {
+        (true, true) => Some(ActivityKind::Update),
+        (false, true) => Some(ActivityKind::Create),
+        (true, false) => Some(ActivityKind::Delete),
+        (false, false) => None,
+    };
+    if let Some(activity) = activity {
+        federate(state, &data.followers(user.id()), activity, &note);
+    }
+    state.indexer.send(IndexEvent::Updated(note.clone()));
+    note
+}
+
+/// Returns the working copy of a note of the user sending the request
+async fn working_copy<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<WorkingCopy>, (StatusCode, String)> {
+    info!("GET /note/{}/draft", id);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let note = *authorized_note(&*data, &state.config, &user, id.into(), Action::Edit)?.id();
+    let Some(copy) = data
+        .working_copy(&note)
+        .filter(|copy| !copy.expired(&Utc::now()))
+    else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note has no draft".to_string()));
+    };
+    info!("--> 200");
+    Ok(Json(copy.clone()))
+}
+
+/// Stores the working copy of a note of the user sending the request, e.g. for autosaving
+///
+/// The note itself and its revisions don't change until the working copy is committed.
+/// The body is only validated by the commit, so that unfinished markup can be saved.
+async fn save_working_copy<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+    StrictJson(draft): StrictJson<Draft>,
+) -> Result<Json<WorkingCopy>, (StatusCode, String)> {
+    info!("PUT /note/{}/draft", id);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let note = authorized_note(&*data, &state.config, &user, id.into(), Action::Edit)?;
+    if note.locked() {
+        info!("--> 409");
+        return Err((StatusCode::CONFLICT, "Note is locked".to_string()));
+    }
+    let expires = Utc::now()
+        + TimeDelta::from_std(state.config.draft_ttl).expect("draft lifetime is out of range");
+    let copy = WorkingCopy::new(*note.id(), *user.id(), draft, expires);
+    if data.set_working_copy(copy.clone()).is_none() {
+        state.jobs.schedule(&ExpireWorkingCopies, expires);
+    }
+    info!("--> 200 [{} words]", copy.word_count());
+    Ok(Json(copy))
+}
+
+/// Replaces the content of the note with its working copy and removes the working copy
+///
+/// Like [`edit_note`], the commit is rejected with a merge proposal if the `If-Match` revision is outdated.
+async fn commit_working_copy<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    info!("POST /note/{}/draft/commit", id);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let note = authorized_note(&*data, &state.config, &user, id.into(), Action::Edit)?;
+    if note.locked() {
+        info!("--> 409");
+        return Err((StatusCode::CONFLICT, "Note is locked".to_string()));
+    }
+    let Some(copy) = data
+        .working_copy(note.id())
+        .filter(|copy| !copy.expired(&Utc::now()))
+    else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note has no draft".to_string()));
+    };
+    let draft = validate_draft(&state.config, &user, copy.draft().clone())?;
+    if let Some(conflict) = check_revision(&*data, &headers, note, &draft)? {
+        return Ok(conflict);
+    }
+    data.remove_working_copy(&id.into());
+    let note = moderated_edit(&state, &mut *data, &user, id.into(), draft);
+    let etag = etag(&*data, note.id());
+    info!("--> 200");
+    Ok(([(header::ETAG, etag)], Json(note)).into_response())
+}
+
+/// Deletes an existing note of the user sending the request
+///
+/// The deletion can be undone with the token of the response until it expires.
+async fn delete_note<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<UndoDeletion>, (StatusCode, String)> {
+    info!("DELETE /note/{}", id);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let Some(note) = data.note_with(id.into(), VisibilityFilter::Active) else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note does not exist".to_string()));
+    };
+    Policy::new(&state.config.admin_users).check(user.id(), Action::Delete, note)?;
+    if note.locked() {
+        info!("--> 409");
+        return Err((StatusCode::CONFLICT, "Note is locked".to_string()));
+    }
+    check_transition(&state.config, note, lifecycle::Event::Delete)?;
+    let note = note.clone();
+    let expires = Utc::now()
+        + TimeDelta::from_std(state.config.undo_window).expect("undo window is out of range");
+    // the token belongs to the owner of the note, also if an admin deletes it
+    let token = UndoToken::new(*note.id(), *note.user(), note.visibility().clone(), expires);
+    let res = UndoDeletion::from(&token);
+    data.delete_note(id.into());
+    data.add_undo_token(token);
+    state.jobs.schedule(&ExpireUndoTokens, expires);
+    collect_tags(&state);
+    if note.visibility() == &Visibility::Public {
+        federate(
+            &state,
+            &data.followers(note.user()),
+            ActivityKind::Delete,
+            &note,
+        );
+    }
+    state
+        .indexer
+        .send(IndexEvent::transition(lifecycle::Event::Delete, note));
+    info!("--> 200");
+    Ok(Json(res))
+}
+
+/// Returns a token to purge the notes that are in the trash of the user sending the request
+async fn confirm_empty_trash<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+) -> Json<EmptyTrash> {
+    info!("POST /notes/trash/confirm");
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let notes = data
+        .notes_with(VisibilityFilter::Deleted)
+        .filter(|note| note.user() == user.id())
+        .map(|note| *note.id())
+        .collect::<Vec<Id>>();
+    let expires = Utc::now()
+        + TimeDelta::from_std(state.config.trash_confirmation_ttl)
+            .expect("trash confirmation lifetime is out of range");
+    let confirmation = TrashConfirmation::new(*user.id(), notes, expires);
+    let res = EmptyTrash::from(&confirmation);
+    data.add_trash_confirmation(confirmation);
+    info!("--> 200 [{} notes]", res.notes());
+    Json(res)
+}
+
+/// Permanently purges the notes that were in the trash when the token was issued
+///
+/// Notes that were restored in the meantime are kept.
+async fn empty_trash<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<TrashQuery>,
+) -> Result<Json<PurgedNotes>, (StatusCode, String)> {
+    info!("DELETE /notes/trash");
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let Some(confirmation) = data.trash_confirmation(&query.token) else {
+        info!("--> 404");
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Confirmation token does not exist".to_string(),
+        ));
+    };
+    Policy::new(&state.config.admin_users).check(user.id(), Action::Edit, confirmation)?;
+    let expired = confirmation.expires() < &Utc::now();
+    let confirmation = data
+        .remove_trash_confirmation(&query.token)
+        .expect("token exists");
+    if expired {
+        info!("--> 410");
+        return Err((
+            StatusCode::GONE,
+            "Confirmation token is expired".to_string(),
+        ));
+    }
+    let purged = data.purge_notes(confirmation.notes());
+    info!("--> 200 [{} notes purged]", purged);
+    Ok(Json(PurgedNotes { purged }))
+}
+
+/// Makes a note of the user sending the request read-only until it is unlocked
+///
+/// Locked notes can't be edited or deleted, e.g. to keep meeting minutes or
+/// published content from drifting.
+async fn lock_note<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    info!("POST /note/{}/lock-content", id);
+    set_locked(&state, &user, id.into(), true)
+}
+
+/// Makes a locked note of the user sending the request editable again
+async fn unlock_note<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    info!("DELETE /note/{}/lock-content", id);
+    set_locked(&state, &user, id.into(), false)
+}
+
+fn set_locked<P: for<'a> persistence::Persister<'a>>(
+    state: &AppState<P>,
+    user: &User,
+    id: Id,
+    locked: bool,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    authorized_note(&*data, &state.config, user, id, Action::Edit)?;
+    data.set_locked(id, locked);
+    let note = data.note(id).cloned().expect("note exists");
+    info!("--> 200");
+    Ok(Json(note))
+}
+
+/// Hides a note of the user sending the request from `GET /notes`
+///
+/// Archived notes can still be read, edited and listed with `GET /notes?state=archived`.
+async fn archive_note<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    info!("POST /note/{}/archive", id);
+    set_archived(&state, &user, id.into(), true)
+}
+
+/// Moves an archived note of the user sending the request back to the active notes
+async fn unarchive_note<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    info!("DELETE /note/{}/archive", id);
+    set_archived(&state, &user, id.into(), false)
+}
+
+fn set_archived<P: for<'a> persistence::Persister<'a>>(
+    state: &AppState<P>,
+    user: &User,
+    id: Id,
+    archived: bool,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    let event = if archived {
+        lifecycle::Event::Archive
+    } else {
+        lifecycle::Event::Unarchive
+    };
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    check_transition(
+        &state.config,
+        authorized_note(&*data, &state.config, user, id, Action::Edit)?,
+        event,
+    )?;
+    data.set_archived(id, archived);
+    let note = data.note(id).cloned().expect("note exists");
+    state
+        .indexer
+        .send(IndexEvent::transition(event, note.clone()));
+    info!("--> 200");
+    Ok(Json(note))
+}
+
+/// Restores a deleted note with an undo token from its deletion
+async fn undo<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(token): Path<String>,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    info!("POST /undo");
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let Some(undo) = data.undo_token(&token) else {
+        info!("--> 404");
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Undo token does not exist".to_string(),
+        ));
+    };
+    // admins may undo their deletions of notes of other users
+    Policy::new(&state.config.admin_users).check(user.id(), Action::Delete, undo)?;
+    if undo.expires() < &Utc::now() {
+        info!("--> 410");
+        return Err((StatusCode::GONE, "Undo token is expired".to_string()));
+    }
+    let undo = data.remove_undo_token(&token).expect("token exists");
+    if !data.restore_note(*undo.note(), undo.visibility().clone()) {
+        info!("--> 409");
+        return Err((StatusCode::CONFLICT, "Note is not deleted".to_string()));
+    }
+    let Some(note) = data.note(*undo.note()).cloned() else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note does not exist".to_string()));
+    };
+    if note.visibility() == &Visibility::Public {
+        federate(
+            &state,
+            &data.followers(note.user()),
+            ActivityKind::Create,
+            &note,
+        );
+    }
+    state.indexer.send(IndexEvent::transition(
+        lifecycle::Event::Restore,
+        note.clone(),
+    ));
+    info!("--> 200");
+    Ok(Json(note))
+}
+
+/// Adds and removes tags of many notes of the user sending the request
+///
+/// Notes that don't exist or belong to other users are skipped, the response
+/// contains the outcome for every requested note.
+async fn bulk_tag<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(change): StrictJson<BulkTag>,
+) -> Result<Json<Vec<BulkResult>>, (StatusCode, String)> {
+    info!("POST /notes/tags [{} notes]", change.notes().len());
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let mut results = vec![];
+    let mut ids = vec![];
+    let policy = Policy::new(&state.config.admin_users);
+    for id in change.notes() {
+        let Some(note) = data.note_with(*id, VisibilityFilter::Active) else {
+            results.push(BulkResult::error(*id, 404, "Note does not exist"));
+            continue;
+        };
+        if let Some((status, err)) = policy.denial(user.id(), Action::Edit, note) {
+            results.push(BulkResult::error(*id, status.as_u16(), &err));
+        } else if note.locked() {
+            results.push(BulkResult::error(*id, 409, "Note is locked"));
+        } else {
+            results.push(BulkResult::ok(*id));
+            ids.push(*id);
+        }
+    }
+    for id in data.bulk_tag(&ids, change.add(), change.remove()) {
+        let Some(note) = data.note(id) else {
+            continue;
+        };
+        // the tag rules apply to the new tags, see `TagRules::apply`
+        match user.tag_rules().matching(note.tags().map(Tag::label)) {
+            Some(rule) if rule.visibility() != note.visibility() => {
+                let draft = Draft::from(note).with_visibility(rule.visibility().clone());
+                apply_edit(&state, &mut *data, &user, id, draft);
+            }
+            _ => state.indexer.send(IndexEvent::Updated(note.clone())),
+        }
+    }
+    if !change.remove().is_empty() {
+        collect_tags(&state);
+    }
+    info!("--> 200");
+    Ok(Json(results))
+}
+
+/// Returns all notes from the user sending the request with the provided tag
+async fn tagged_notes<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(tag_label): Path<String>,
+    Query(options): Query<ListOptions>,
+    Query(fields): Query<FieldSet>,
+) -> Result<Json<SparseView<NoteList>>, (StatusCode, String)> {
+    info!("GET /notes/tag/{}", tag_label);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let Some(tag) = data.tag(&tag_label) else {
+        info!("--> 400");
+        return Err((StatusCode::BAD_REQUEST, "Tag does not exist".to_string()));
+    };
+
+    // the same notes as `GET /notes?tag=<label>&sort=id`
+    let query = NoteQuery::new(Some(tag_label), None, None, Some(SortKey::Id.into()));
+    let res = state
+        .indexer
+        .queries()
+        .get_or_insert_with(*user.id(), &query, || {
+            data.tagged_notes(tag)
+                .filter(|note| note.user() == user.id())
+                .map(|note| *note.id())
+                .collect()
+        })
+        .into_iter()
+        .filter_map(|id| data.note(id))
+        .cloned()
+        .collect::<Vec<Note>>();
+    let res = NoteList::new(
+        options.paginate(res, user.preferences().per_page()),
+        options.full(),
+        state.config.preview_length,
+        user.id(),
+    )
+    .with_views(|id| data.viewed_at(user.id(), id));
+    info!("--> 200 [{} notes]", res.len());
+    Ok(Json(fields.view(res)))
+}
+
+/// Returns all notes of the user under the tag as one document with a table of contents
+///
+/// The notes are ordered like `GET /notes?tag=<label>`, see [`report`].
+async fn tag_export<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(tag_label): Path<String>,
+    Query(query): Query<ReportQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    info!("GET /notes/tag/{}/export", tag_label);
+    let data = state.data.lock().expect("mutex was poisoned");
+    if data.tag(&tag_label).is_none() {
+        info!("--> 400");
+        return Err((StatusCode::BAD_REQUEST, "Tag does not exist".to_string()));
+    };
+
+    let filter = NoteQuery::new(Some(tag_label.clone()), None, None, query.sort)
+        .with_default_sort(user.preferences().sort());
+    let notes = data
+        .query_notes(&user, &filter)
+        .cloned()
+        .collect::<Vec<Note>>();
+    let (content_type, document) = match query.format {
+        ReportFormat::Pdf => ("application/pdf", pdf::render_report(&tag_label, &notes)),
+        ReportFormat::Md => (
+            "text/markdown; charset=utf-8",
+            report::markdown(&tag_label, &notes).into_bytes(),
+        ),
+    };
+    info!("--> 200 [{} notes, {} bytes]", notes.len(), document.len());
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                report::content_disposition(&tag_label, query.format),
+            ),
+        ],
+        document,
+    ))
+}
+
+/// Returns all tags with the number of notes of the user sending the request
+async fn tags<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+) -> Result<Json<Vec<TagStats>>, (StatusCode, String)> {
+    info!("GET /tags/");
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = data.tag_stats(&user);
+    info!("--> 200 [{} tags]", res.len());
+    Ok(Json(res))
+}
+
+/// Returns how often the tags are used together in the notes of the user sending the request
+///
+/// The graph is maintained by the background indexer and is eventually consistent.
+async fn tag_graph<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+) -> Json<TagGraphView> {
+    info!("GET /tags/graph");
+    let res = if state.indexer.is_ready() {
+        state.indexer.index().tag_graph(user.id())
+    } else {
+        let data = state.data.lock().expect("mutex was poisoned");
+        Index::scan(state.config.search, data.user_notes(&user)).tag_graph(user.id())
+    };
+    info!("--> 200");
+    Json(res)
+}
+
+/// Changes the color and description of a tag that is used by notes of the user sending the request
+///
+/// Tags are shared by all users, so the change is visible to everyone using the tag.
+async fn edit_tag<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+    StrictJson(meta): StrictJson<TagMeta>,
+) -> Result<Json<Tag>, (StatusCode, String)> {
+    info!("PUT /tag/{}", id);
+    if let Err(err) = meta.validate() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, err));
+    }
+    let id = Id::from(id);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    if !data.tags().any(|tag| tag.id() == &id) {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Tag does not exist".to_string()));
+    }
+    if !data
+        .user_notes(&user)
+        .any(|note| note.tags().any(|tag| tag.id() == &id))
+    {
+        info!("--> 403");
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Tag is not used by your notes".to_string(),
+        ));
+    }
+    let Some(tag) = data.update_tag(id, meta).cloned() else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Tag does not exist".to_string()));
+    };
+    info!("--> 200");
+    Ok(Json(tag))
+}
+
+/// Returns all notes from the user sending the request that contain all words of the search
+///
+/// The default search uses the search index, which is updated in the background and might
+/// not include the latest changes yet. Searches with other fields, case or match mode
+/// check all notes of the user instead, like all searches while the index is built at startup.
+///
+/// The results are narrowed by the other filters of the [`NoteQuery`], e.g. `tag` or
+/// `created_after`, and sorted by its `sort`. With `stream=true` the results are sent as
+/// NDJSON while the notes are still checked, see [`stream_search`].
+async fn search<P>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Query(search): Query<SearchQuery>,
+    Query(query): Query<NoteQuery>,
+    Query(options): Query<ListOptions>,
+    Query(stream): Query<StreamQuery>,
+) -> Result<Response, (StatusCode, String)>
+where
+    P: for<'a> persistence::Persister<'a> + Send + 'static,
+{
+    info!("GET /notes/search/{}", search.q());
+    // `q` is the text of the search, the other filters of the query narrow the results
+    let filter = query.without_text();
+    if stream.stream() {
+        return Ok(stream_search(state, user, search, filter, options).into_response());
+    }
+    let data = state.data.lock().expect("mutex was poisoned");
+    let mut res = if search.is_indexed() && data.capabilities().full_text_search {
+        data.search_notes(&user, search.q())
+            .into_iter()
+            .filter(|note| filter.matches(note))
+            .cloned()
+            .collect::<Vec<Note>>()
+    } else if search.is_indexed() && state.indexer.is_ready() {
+        let ids = state.indexer.index().search(search.q());
+        ids.into_iter()
+            .filter_map(|id| data.note_with(id, filter.state().filter()))
+            .filter(|note| note.user() == user.id() && filter.matches(note))
+            .cloned()
+            .collect::<Vec<Note>>()
+    } else {
+        // the cheap filters select the candidates, only their text is matched
+        data.query_notes(&user, &filter)
+            .filter(|note| search.matches(note, &state.config.search))
+            .cloned()
+            .collect::<Vec<Note>>()
+    };
+    let sort = filter.sort();
+    res.sort_by(|a, b| sort.compare(a, b));
+    let res = NoteList::new(
+        options.paginate(res, user.preferences().per_page()),
+        options.full(),
+        state.config.preview_length,
+        user.id(),
+    )
+    .with_views(|id| data.viewed_at(user.id(), id))
+    .with_groups(options.group(), &Utc::now(), user.preferences().timezone());
+    info!("--> 200 [{} notes]", res.len());
+    Ok(Json(res).into_response())
+}
+
+/// The number of candidates that a streamed search checks while it holds the lock
+const SEARCH_STREAM_BATCH: usize = 100;
+
+/// The state of a streamed search between two batches of candidates
+struct SearchStream<P>
+where
+    P: for<'a> persistence::Persister<'a>,
+{
+    state: AppState<P>,
+    user: User,
+    search: SearchQuery,
+    filter: NoteQuery,
+    full: bool,
+    group: Option<GroupBy>,
+    /// The hits found by the index or the backend, `None` if the text of each note is matched
+    hits: Option<HashSet<Id>>,
+    batches: std::vec::IntoIter<Vec<Id>>,
+    /// The number of hits on the pages before the requested one
+    skip: usize,
+    /// The number of hits still missing on the requested page, `None` without pagination
+    take: Option<usize>,
+}
+
+impl<P> SearchStream<P>
+where
+    P: for<'a> persistence::Persister<'a>,
+{
+    /// Checks the candidates of the batch and returns the hits on the requested page
+    fn matches(&mut self, batch: Vec<Id>) -> Vec<Note> {
+        let data = self.state.data.lock().expect("mutex was poisoned");
+        let mut notes = vec![];
+        for id in batch {
+            let Some(note) = data.note_with(id, self.filter.state().filter()) else {
+                continue;
+            };
+            let hit = match &self.hits {
+                Some(hits) => hits.contains(&id),
+                None => self.search.matches(note, &self.state.config.search),
+            };
+            if !hit {
+                continue;
+            }
+            if self.skip > 0 {
+                self.skip -= 1;
+                continue;
+            }
+            if let Some(take) = self.take.as_mut() {
+                if *take == 0 {
+                    break;
+                }
+                *take -= 1;
+            }
+            notes.push(note.clone());
+        }
+        notes
+    }
+
+    /// Returns the NDJSON lines of the next batch with hits, `None` when the page is complete
+    async fn next(&mut self) -> Option<String> {
+        while self.take != Some(0) {
+            let batch = self.batches.next()?;
+            let notes = self.matches(batch);
+            if !notes.is_empty() {
+                let data = self.state.data.lock().expect("mutex was poisoned");
+                let list = NoteList::new(
+                    notes,
+                    self.full,
+                    self.state.config.preview_length,
+                    self.user.id(),
+                )
+                .with_views(|id| data.viewed_at(self.user.id(), id))
+                .with_groups(
+                    self.group,
+                    &Utc::now(),
+                    self.user.preferences().timezone(),
+                );
+                return Some(list.to_ndjson());
+            }
+            // let other requests use the storage between the batches
+            tokio::task::yield_now().await;
+        }
+        None
+    }
+}
+
+/// Streams the results of a search as NDJSON, one note per line, see [`search`]
+///
+/// The candidates of the other filters are already sorted, so each hit can be sent as soon as
+/// it is found. Their text is checked in batches and the storage is only locked for one batch
+/// at a time. The stream ends when the requested page is complete, the remaining candidates
+/// are not checked then.
+fn stream_search<P>(
+    state: AppState<P>,
+    user: User,
+    search: SearchQuery,
+    filter: NoteQuery,
+    options: ListOptions,
+) -> impl IntoResponse
+where
+    P: for<'a> persistence::Persister<'a> + Send + 'static,
+{
+    let (candidates, hits) = {
+        let data = state.data.lock().expect("mutex was poisoned");
+        let candidates = data
+            .query_notes(&user, &filter)
+            .map(|note| *note.id())
+            .collect::<Vec<Id>>();
+        let hits = if search.is_indexed() && data.capabilities().full_text_search {
+            Some(
+                data.search_notes(&user, search.q())
+                    .into_iter()
+                    .map(|note| *note.id())
+                    .collect::<HashSet<Id>>(),
+            )
+        } else if search.is_indexed() && state.indexer.is_ready() {
+            Some(state.indexer.index().search(search.q()))
+        } else {
+            None
+        };
+        (candidates, hits)
+    };
+    info!("--> 200 [streaming, {} candidates]", candidates.len());
+    let (skip, take) = options.window(user.preferences().per_page());
+    let batches = candidates
+        .chunks(SEARCH_STREAM_BATCH)
+        .map(<[Id]>::to_vec)
+        .collect::<Vec<Vec<Id>>>();
+    let search = SearchStream {
+        state,
+        user,
+        search,
+        filter,
+        full: options.full(),
+        group: options.group(),
+        hits,
+        batches: batches.into_iter(),
+        skip,
+        take,
+    };
+    let lines = stream::unfold(search, |mut search| async move {
+        let lines = search.next().await?;
+        Some((Ok::<_, Infallible>(lines), search))
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(lines),
+    )
+}
+
+/// Returns notes of the user sending the request that share tags with the note,
+/// most related first
+async fn related_notes<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+    Query(options): Query<ListOptions>,
+    Query(fields): Query<FieldSet>,
+) -> Result<Json<SparseView<NoteList>>, (StatusCode, String)> {
+    info!("GET /note/{}/related", id);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let Some(note) = data.note_with(id.into(), VisibilityFilter::Active) else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note does not exist".to_string()));
+    };
+    Policy::new(&state.config.admin_users).check(user.id(), Action::Inspect, note)?;
+    let ids = if state.indexer.is_ready() {
+        state.indexer.index().related(note.id())
+    } else {
+        let notes = data.user_notes(&user).chain([note]);
+        Index::scan(state.config.search, notes).related(note.id())
+    };
+    let res = ids
+        .into_iter()
+        .filter_map(|id| data.note_with(id, VisibilityFilter::Active))
+        .filter(|note| note.user() == user.id())
+        .cloned()
+        .collect::<Vec<Note>>();
+    let res = NoteList::new(
+        options.paginate(res, user.preferences().per_page()),
+        options.full(),
+        state.config.preview_length,
+        user.id(),
+    )
+    .with_views(|id| data.viewed_at(user.id(), id));
+    info!("--> 200 [{} notes]", res.len());
+    Ok(Json(fields.view(res)))
+}
+
+/// Returns all saved searches of the user sending the request
+async fn searches<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+) -> Result<Json<Vec<SavedSearch>>, (StatusCode, String)> {
+    info!("GET /searches/");
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = data
+        .user_searches(&user)
+        .cloned()
+        .collect::<Vec<SavedSearch>>();
+    info!("--> 200 [{} searches]", res.len());
+    Ok(Json(res))
+}
+
+/// Stores a [`NoteQuery`] under a name for the user sending the request
+async fn add_search<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    headers: HeaderMap,
+    StrictJson(draft): StrictJson<SearchDraft>,
+) -> Result<Json<SavedSearch>, (StatusCode, String)> {
+    info!("POST /searches/{}", draft.name());
+    let key = idempotency::key(&headers)?;
+    let window = state.config.idempotency_window;
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    if let Some(key) = &key {
+        if let Some(search) = idempotency::replay(&*data, &user, key, &draft, window)? {
+            info!("--> 200");
+            return Ok(Json(search));
+        }
+    }
+    let search = data.add_search(draft.clone(), &user).clone();
+    if let Some(key) = key {
+        idempotency::remember(&mut *data, &user, key, &draft, &search, window);
+    }
+    info!("--> 200");
+    Ok(Json(search))
+}
+
+/// Executes a saved search and returns the currently matching notes
+async fn search_notes<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+    Query(options): Query<ListOptions>,
+    Query(fields): Query<FieldSet>,
+) -> Result<Json<SparseView<NoteList>>, (StatusCode, String)> {
+    info!("GET /searches/{}/notes", id);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let Some(search) = data.search(id.into()) else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Search does not exist".to_string()));
+    };
+    Policy::new(&state.config.admin_users).check(user.id(), Action::Read, search)?;
+    let query = search
+        .query()
+        .clone()
+        .with_default_sort(user.preferences().sort());
+    let res = data
+        .query_notes(&user, &query)
+        .cloned()
+        .collect::<Vec<Note>>();
+    let res = NoteList::new(
+        options.paginate(res, user.preferences().per_page()),
+        options.full(),
+        state.config.preview_length,
+        user.id(),
+    )
+    .with_views(|id| data.viewed_at(user.id(), id));
+    info!("--> 200 [{} notes]", res.len());
+    Ok(Json(fields.view(res)))
+}
+
+/// Returns the preferences of the user sending the request
+async fn preferences(CurrentUser(user): CurrentUser) -> Json<Preferences> {
+    info!("GET /me/preferences");
+    info!("--> 200");
+    Json(user.preferences().clone())
+}
+
+/// Replaces the preferences of the user sending the request
+async fn set_preferences<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(preferences): StrictJson<Preferences>,
+) -> Result<Json<Preferences>, (StatusCode, String)> {
+    info!("PUT /me/preferences");
+    if let Err(err) = preferences.validate() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, err));
+    }
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    data.set_preferences(*user.id(), preferences.clone());
+    info!("--> 200");
+    Ok(Json(preferences))
+}
+
+/// Returns the tag rules of the user sending the request
+async fn tag_rules(CurrentUser(user): CurrentUser) -> Json<TagRules> {
+    info!("GET /me/tag-rules");
+    info!("--> 200");
+    Json(user.tag_rules().clone())
+}
+
+/// Replaces the tag rules of the user sending the request
+///
+/// The rules apply to notes that are created or edited afterwards, existing notes are not changed.
+async fn set_tag_rules<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(rules): StrictJson<TagRules>,
+) -> Result<Json<TagRules>, (StatusCode, String)> {
+    info!("PUT /me/tag-rules [{} rules]", rules.rules().len());
+    if let Err(err) = rules.validate() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, err));
+    }
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    data.set_tag_rules(*user.id(), rules.clone());
+    info!("--> 200");
+    Ok(Json(rules))
+}
+
+/// Returns the note templates of the user sending the request
+async fn templates(CurrentUser(user): CurrentUser) -> Json<Templates> {
+    info!("GET /me/templates");
+    info!("--> 200");
+    Json(user.templates().clone())
+}
+
+/// Replaces the note templates of the user sending the request
+///
+/// Existing notes keep their fields, they are checked against the new templates when they are
+/// edited the next time.
+async fn set_templates<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(templates): StrictJson<Templates>,
+) -> Result<Json<Templates>, (StatusCode, String)> {
+    info!(
+        "PUT /me/templates [{} templates]",
+        templates.templates().len()
+    );
+    if let Err(err) = templates.validate() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, err));
+    }
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    data.set_templates(*user.id(), templates.clone());
+    info!("--> 200");
+    Ok(Json(templates))
+}
+
+/// Returns the chat services that the user sending the request connected
+async fn integrations(CurrentUser(user): CurrentUser) -> Json<Integrations> {
+    info!("GET /me/integrations");
+    info!("--> 200");
+    Json(user.integrations().clone())
+}
+
+/// Replaces the chat services of the user sending the request
+async fn set_integrations<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(integrations): StrictJson<Integrations>,
+) -> Result<Json<Integrations>, (StatusCode, String)> {
+    info!("PUT /me/integrations");
+    if let Err(err) = integrations.validate() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, err));
+    }
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    data.set_integrations(*user.id(), integrations.clone());
+    info!("--> 200");
+    Ok(Json(integrations))
+}
+
+/// Returns the account of the user sending the request, with preferences and profile
+async fn me(CurrentUser(user): CurrentUser) -> Json<User> {
+    info!("GET /me");
+    info!("--> 200");
+    Json(user)
+}
+
+/// Replaces the profile of the user sending the request
+async fn set_profile<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(profile): StrictJson<Profile>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    info!("PUT /me");
+    if let Err(err) = profile.validate() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, err));
+    }
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    data.set_profile(*user.id(), profile);
+    let user = data.user(*user.id()).cloned().expect("user exists");
+    info!("--> 200");
+    Ok(Json(user))
+}
+
+/// Returns the public profile of a user, e.g. to show the author of a note or comment
+///
+/// Accounts that are scheduled for deletion are not found.
+async fn user_profile<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(_user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<PublicProfile>, (StatusCode, String)> {
+    info!("GET /user/{}", id);
+    let data = state.data.lock().expect("mutex was poisoned");
+    let Some(user) = data.user(id.into()).filter(|user| user.is_active()) else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "User does not exist".to_string()));
+    };
+    info!("--> 200");
+    Ok(Json(PublicProfile::from(user)))
+}
+
+/// Returns the service tokens of the user sending the request, without their secrets
+async fn service_tokens<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+) -> Json<Vec<TokenInfo>> {
+    info!("GET /me/tokens");
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = data
+        .service_tokens(user.id())
+        .into_iter()
+        .map(TokenInfo::from)
+        .collect::<Vec<TokenInfo>>();
+    info!("--> 200 [{} tokens]", res.len());
+    Json(res)
+}
+
+/// Creates a service token for scripts and integrations
+///
+/// The secret is only part of this response, it can't be retrieved later.
+async fn add_service_token<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    StrictJson(draft): StrictJson<TokenDraft>,
+) -> Result<Json<IssuedToken>, (StatusCode, String)> {
+    info!("POST /me/tokens");
+    if let Err(err) = draft.validate() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, err));
+    }
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let token = data.add_service_token(&user, draft, ServiceToken::generate_secret());
+    info!("--> 200");
+    Ok(Json(IssuedToken::from(token)))
+}
+
+/// Revokes a service token of the user sending the request
+async fn revoke_service_token<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<usize>,
+) -> Result<Json<TokenInfo>, (StatusCode, String)> {
+    info!("DELETE /me/tokens/{}", id);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let Some(token) = data
+        .service_tokens(user.id())
+        .into_iter()
+        .find(|token| token.id() == &Id::from(id))
+        .map(TokenInfo::from)
+    else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Token does not exist".to_string()));
+    };
+    data.revoke_service_token(id.into());
+    info!("--> 200");
+    Ok(Json(token))
+}
+
+/// Schedules the deletion of the account of the user sending the request
+///
+/// The user can't log in anymore after this request and all data will be
+/// purged after the grace period. The response contains a full export of all
+/// data of the user.
+async fn delete_me<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    CurrentUser(user): CurrentUser,
+) -> Result<(StatusCode, Json<AccountDeletion>), (StatusCode, String)> {
+    info!("DELETE /me");
+    if *user.id() == ANONYMOUS_USER {
+        info!("--> 403");
+        return Err((
+            StatusCode::FORBIDDEN,
+            "The anonymous account can't be deleted".to_string(),
+        ));
+    }
+    let purge_at = Utc::now()
+        + TimeDelta::from_std(state.config.deletion_grace_period)
+            .expect("grace period is out of range");
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    data.schedule_user_deletion(*user.id(), purge_at);
+    state.jobs.schedule(&PurgeUser::new(*user.id()), purge_at);
+    let user = data.user(*user.id()).cloned().unwrap_or(user);
+    let export = data.export_user(&user);
+    info!("--> 202");
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(AccountDeletion::new(purge_at, export)),
+    ))
+}
+
+/// Returns all pending, running and failed background jobs
+async fn admin_jobs<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+) -> Result<Json<Vec<JobRecord>>, (StatusCode, String)> {
+    info!("GET /admin/jobs [admin {}]", usize::from(admin.id()));
+    let res = state.jobs.jobs();
+    info!("--> 200 [{} jobs]", res.len());
+    Ok(Json(res))
+}
+
+/// Returns the optional features of the storage backend
+async fn admin_capabilities<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+) -> Result<Json<Capabilities>, (StatusCode, String)> {
+    info!(
+        "GET /admin/capabilities [admin {}]",
+        usize::from(admin.id())
+    );
+    let data = state.data.lock().expect("mutex was poisoned");
+    info!("--> 200");
+    Ok(Json(data.capabilities()))
+}
+
+/// Returns statistics about the data in the storage backend
+async fn admin_storage<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+) -> Result<Json<StorageStats>, (StatusCode, String)> {
+    info!("GET /admin/storage [admin {}]", usize::from(admin.id()));
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = data.stats();
+    info!("--> 200 [{} notes, {} bytes]", res.notes, res.bytes);
+    Ok(Json(res))
+}
+
+/// Returns the hit rate and size of the query cache
+async fn admin_cache<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+) -> Json<CacheMetrics> {
+    info!("GET /admin/cache [admin {}]", usize::from(admin.id()));
+    let res = state.indexer.queries().metrics();
+    info!("--> 200 [{} entries]", res.entries);
+    Json(res)
+}
+
+/// Returns everything stored about a user, including the history of their notes and the audit
+/// log, e.g. for a legal hold
+async fn compliance_export<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+    Path(id): Path<usize>,
+) -> Result<Json<ComplianceExport>, (StatusCode, String)> {
+    info!(
+        "GET /admin/users/{}/export [admin {}]",
+        id,
+        usize::from(admin.id())
+    );
+    let data = state.data.lock().expect("mutex was poisoned");
+    let Some(user) = data.user(id.into()) else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "User does not exist".to_string()));
+    };
+    let export = data.export_user(user);
+    let notes = export
+        .notes()
+        .iter()
+        .map(|note| *note.id())
+        .collect::<BTreeSet<Id>>();
+    let revisions = notes
+        .iter()
+        .map(|note| {
+            let revisions = data
+                .revisions(note)
+                .iter()
+                .filter_map(|info| data.revision(note, info.number))
+                .collect();
+            (*note, revisions)
+        })
+        .collect();
+    let mut access_log = notes
+        .iter()
+        .flat_map(|note| data.access_log(note))
+        .cloned()
+        .collect::<Vec<AccessEntry>>();
+    access_log.sort_by(|a, b| b.at().cmp(a.at()));
+    let flags = data
+        .flags()
+        .into_iter()
+        .filter(|flag| notes.contains(flag.note()))
+        .cloned()
+        .collect();
+    let audit_log = data
+        .audit_log(user.id())
+        .into_iter()
+        .rev()
+        .cloned()
+        .collect();
+    info!("--> 200 [{} notes]", notes.len());
+    Ok(Json(ComplianceExport {
+        exported_at: Utc::now(),
+        exported_by: *admin.id(),
+        export,
+        revisions,
+        access_log,
+        flags,
+        audit_log,
+    }))
+}
+
+/// Returns the public URL of the app, used to recognize absolute links to notes
+fn public_url<P: for<'a> persistence::Persister<'a>>(state: &AppState<P>) -> Option<&str> {
+    state
+        .config
+        .activitypub
+        .as_ref()
+        .map(|activitypub| activitypub.base_url.as_str())
+}
+
+/// Issues a short-lived session of the user to the admin sending the request, e.g. to
+/// reproduce a problem for support without sharing credentials
+///
+/// Other admins can't be impersonated. The session is limited to the routes of
+/// [`auth::impersonation_allows`] and all requests with it are added to the audit log of
+/// the user, see [`layers::record_audit`].
+async fn impersonate<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+    Path(id): Path<usize>,
+) -> Result<Json<Session>, (StatusCode, String)> {
+    info!(
+        "POST /admin/impersonate/{} [admin {}]",
+        id,
+        usize::from(admin.id())
+    );
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let Some(user) = data.user(id.into()).cloned() else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "User does not exist".to_string()));
+    };
+    if Policy::new(&state.config.admin_users).is_admin(user.id()) {
+        info!("--> 403");
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Admins can't be impersonated".to_string(),
+        ));
+    }
+    if !user.is_active() {
+        info!("--> 409");
+        return Err((
+            StatusCode::CONFLICT,
+            "Account is scheduled for deletion".to_string(),
+        ));
+    }
+    let session = state
+        .sessions
+        .impersonate(&user, admin.id(), state.config.impersonation_ttl);
+    data.record_audit(AuditEntry::new(
+        *user.id(),
+        *admin.id(),
+        format!("POST /admin/impersonate/{id}"),
+        StatusCode::OK.as_u16(),
+        Utc::now(),
+    ));
+    info!("--> 200");
+    Ok(Json(session))
+}
+
+/// Returns the API usage of all users with requests in the range, most requests first
+async fn admin_usage<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+    Query(range): Query<DateRange>,
+) -> Result<Json<Vec<UsageTotals>>, (StatusCode, String)> {
+    info!("GET /admin/usage [admin {}]", usize::from(admin.id()));
+    let (from, to) = range.resolve(dates::today(admin.preferences().timezone()));
+    if from > to {
+        info!("--> 400");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "`from` must not be after `to`".to_string(),
+        ));
+    }
+    let data = state.data.lock().expect("mutex was poisoned");
+    let mut res = data
+        .users()
+        .filter_map(|user| {
+            let usage = data.usage(user.id(), from, to);
+            (!usage.is_empty())
+                .then(|| UsageTotals::new(*user.id(), user.name().to_string(), &usage))
+        })
+        .collect::<Vec<UsageTotals>>();
+    res.sort_by_key(|totals| std::cmp::Reverse(totals.requests()));
+    info!("--> 200 [{} users]", res.len());
+    Ok(Json(res))
+}
+
+/// Checks all notes for broken links, dangling merges and orphaned tags
+async fn admin_integrity<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+) -> Json<IntegrityReport> {
+    info!("GET /admin/integrity [admin {}]", usize::from(admin.id()));
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = integrity::check(&*data, public_url(&state), Utc::now());
+    info!("--> 200 [{} problems]", res.problems.len());
+    Json(res)
+}
+
+/// Fixes the problems of [`admin_integrity`] that don't need a decision of the owner
+async fn admin_integrity_fix<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+) -> Json<Repair> {
+    info!(
+        "POST /admin/integrity/fix [admin {}]",
+        usize::from(admin.id())
+    );
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let res = integrity::fix(&mut *data, public_url(&state), Utc::now());
+    info!("--> 200 [{} tags removed]", res.removed_tags);
+    Json(res)
+}
+
+/// Reclaims unused storage of the backend
+///
+/// All other requests wait until the compaction is finished.
+async fn admin_compact<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+) -> Result<Json<Compaction>, (StatusCode, String)> {
+    info!("POST /admin/compact [admin {}]", usize::from(admin.id()));
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    if !data.capabilities().compaction {
+        info!("--> 501");
+        return Err((
+            StatusCode::NOT_IMPLEMENTED,
+            "The storage backend does not support compaction".to_string(),
+        ));
+    }
+    let before = data.stats();
+    data.compact();
+    let after = data.stats();
+    info!("--> 200 [{} -> {} bytes]", before.bytes, after.bytes);
+    Ok(Json(Compaction { before, after }))
+}
+
+/// Returns all flagged notes that wait for a review, see [`moderation`]
+async fn moderation_queue<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+) -> Json<Vec<Flag>> {
+    info!(
+        "GET /admin/moderation/queue [admin {}]",
+        usize::from(admin.id())
+    );
+    let data = state.data.lock().expect("mutex was poisoned");
+    let res = data.flags().into_iter().cloned().collect::<Vec<Flag>>();
+    info!("--> 200 [{} flags]", res.len());
+    Json(res)
+}
+
+/// Removes the note from the moderation queue
+///
+/// Quarantined notes get the visibility that their owner requested.
+async fn approve_note<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+    Path(id): Path<usize>,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    info!(
+        "POST /admin/moderation/{}/approve [admin {}]",
+        id,
+        usize::from(admin.id())
+    );
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let Some(flag) = data.remove_flag(&id.into()) else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note is not flagged".to_string()));
+    };
+    let Some(note) = data.note(id.into()).cloned() else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note does not exist".to_string()));
+    };
+    let note = match flag.quarantined() {
+        // the owner might have changed the visibility in the meantime
+        Some(visibility) if note.visibility() == &Visibility::Private => {
+            let owner = data
+                .user(*note.user())
+                .cloned()
+                .expect("flagged notes have an owner");
+            let draft = Draft::from(&note).with_visibility(visibility.clone());
+            apply_edit(&state, &mut *data, &owner, id.into(), draft)
+        }
+        _ => note,
+    };
+    info!("--> 200");
+    Ok(Json(note))
+}
+
+/// Removes the note from the moderation queue and deletes it
+async fn reject_note<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+    Path(id): Path<usize>,
+) -> Result<Json<Note>, (StatusCode, String)> {
+    info!(
+        "POST /admin/moderation/{}/reject [admin {}]",
+        id,
+        usize::from(admin.id())
+    );
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    if data.remove_flag(&id.into()).is_none() {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note is not flagged".to_string()));
+    }
+    let Some(note) = data.note(id.into()).cloned() else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note does not exist".to_string()));
+    };
+    data.delete_note(id.into());
+    collect_tags(&state);
+    if note.visibility() == &Visibility::Public {
+        federate(
+            &state,
+            &data.followers(note.user()),
+            ActivityKind::Delete,
+            &note,
+        );
+    }
+    state.indexer.send(IndexEvent::transition(
+        lifecycle::Event::Delete,
+        note.clone(),
+    ));
+    info!("--> 200");
+    Ok(Json(note))
+}
+
+/// Returns a portable archive of all data, see [`Backup`]
+async fn admin_backup<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+) -> impl IntoResponse {
+    info!("POST /admin/backup [admin {}]", usize::from(admin.id()));
+    let data = state.data.lock().expect("mutex was poisoned");
+    let backup = data.backup();
+    info!("--> 200 [{} records]", backup.records());
+    (
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"backup.json\"",
+        )],
+        Json(backup),
+    )
+}
+
+/// Queues a [`PublishSite`] job that writes all public notes as static site to `NOTE_PUBLISH_DIR`
+///
+/// Returns the queued job, its progress is listed by `GET /admin/jobs`.
+async fn admin_publish_site<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<JobRecord>), (StatusCode, String)> {
+    info!(
+        "POST /admin/publish-site [admin {}]",
+        usize::from(admin.id())
+    );
+    if state.config.publish_dir.is_none() {
+        info!("--> 404");
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Publishing is not configured".to_string(),
+        ));
+    }
+    let id = state
+        .jobs
+        .enqueue(&PublishSite::new(base_url(&state, &headers)));
+    let Some(job) = state.jobs.jobs().into_iter().find(|job| job.id() == &id) else {
+        info!("--> 500");
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Job was not queued".to_string(),
+        ));
+    };
+    info!("--> 202");
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Validates an archive of `POST /admin/backup` and queues a [`Restore`] job for it
+///
+/// Archives of older schema versions are [migrated](backup::migrate) first.
+/// A dry run only returns the [`RestoreReport`], including all errors.
+async fn admin_restore<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    Admin(admin): Admin,
+    Query(options): Query<RestoreOptions>,
+    StrictJson(mut archive): StrictJson<Value>,
+) -> Result<(StatusCode, Json<RestoreReport>), (StatusCode, String)> {
+    info!(
+        "POST /admin/restore [admin {}, {:?}]",
+        usize::from(admin.id()),
+        options
+    );
+    backup::migrate(&mut archive);
+    let backup =
+        json::decode::<Backup>(archive.to_string().as_bytes()).inspect_err(|(status, _)| {
+            info!("--> {}", status.as_u16());
+        })?;
+    let mut report = backup.report(options.mode);
+    report.errors = backup.validate();
+    if options.mode == RestoreMode::Merge && report.errors.is_empty() {
+        let data = state.data.lock().expect("mutex was poisoned");
+        report.errors = data.merge_conflicts(&backup);
+    }
+    if options.dry_run {
+        info!("--> 200 [{} errors]", report.errors.len());
+        return Ok((StatusCode::OK, Json(report)));
+    }
+    if !report.errors.is_empty() {
+        info!("--> 422");
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, report.errors.join("\n")));
+    }
+    report.job = Some(state.jobs.enqueue(&Restore::new(backup, options.mode)));
+    info!("--> 202");
+    Ok((StatusCode::ACCEPTED, Json(report)))
+}
+
+/// Starts the login via the external identity provider
+///
+/// The `state` of the login is also set as cookie, which the callback requires.
+async fn oidc_login<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+) -> Result<Response, (StatusCode, String)> {
+    info!("GET /auth/oidc/login");
+    let Some(oidc) = &state.oidc else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Login is not configured".to_string()));
+    };
+    match oidc.authorize() {
+        Ok(Some(authorization)) => {
+            info!("--> 303");
+            Ok((
+                [(header::SET_COOKIE, authorization.cookie)],
+                Redirect::to(authorization.url.as_str()),
+            )
+                .into_response())
+        }
+        Ok(None) => {
+            info!("--> 503 [too many pending logins]");
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many pending logins, try again later".to_string(),
+            ))
+        }
+        Err(err) => {
+            info!("--> 500 [{}]", err);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+        }
+    }
+}
+
+/// Completes the login via the external identity provider and issues a [`Session`]
+///
+/// Users are created on their first login. The login must be completed in the browser that
+/// started it, see [`oidc::STATE_COOKIE`].
+async fn oidc_callback<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Query(callback): Query<Callback>,
+    headers: HeaderMap,
+) -> Result<Json<Session>, (StatusCode, String)> {
+    info!("GET /auth/oidc/callback");
+    let Some(oidc) = &state.oidc else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Login is not configured".to_string()));
+    };
+    let cookie = oidc::state_cookie(&headers);
+    let login = oidc.login(callback, cookie).await.map_err(|err| {
+        info!("--> 401 [{:#}]", err);
+        (StatusCode::UNAUTHORIZED, format!("Login failed: {err:#}"))
+    })?;
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    let user = match data.user_by_identity(&login.identity) {
+        Some(user) => user.clone(),
+        None => data.add_user(login.name, Some(login.identity)).clone(),
+    };
+    if !user.is_active() {
+        info!("--> 401");
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "Account is scheduled for deletion".to_string(),
+        ));
+    }
+    info!("--> 200");
+    Ok(Json(state.sessions.issue(&user)))
+}
+
+/// Returns the public URL of the app, `NOTE_PUBLIC_URL` if it is configured,
+/// otherwise derived from the `Host` header of the request
+fn base_url<P: for<'a> persistence::Persister<'a>>(
+    state: &AppState<P>,
+    headers: &HeaderMap,
+) -> String {
+    if let Some(activitypub) = &state.config.activitypub {
+        return activitypub.base_url.clone();
+    }
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost");
+    let scheme = if state.config.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    format!("{scheme}://{host}")
+}
+
+/// Returns the note as HTML page, if anonymous visitors may read it
+///
+/// Unlisted notes are rendered as well, but ask search engines not to index them.
+/// Pages of notes that were merged into another note redirect to its page.
+async fn note_page<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Path(slug): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    info!("GET /p/{}", slug);
+    let mut data = state.data.lock().expect("mutex was poisoned");
+    if let Some(target) = merged_target(&*data, deleted_by_slug(&*data, &slug))
+        .filter(|target| target.visibility().allows(&ANONYMOUS_USER, Access::Link))
+        .and_then(Note::slug)
+    {
+        info!("--> 308");
+        return Ok(Redirect::permanent(&format!("/p/{target}")).into_response());
+    }
+    let Some(note) = data
+        .note_by_slug(&slug)
+        .filter(|note| note.visibility().allows(&ANONYMOUS_USER, Access::Link))
+        .cloned()
+    else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "Note does not exist".to_string()));
+    };
+    record_access(&state.config, &mut *data, &note, &ANONYMOUS_USER);
+    let indexed = note.visibility().allows(&ANONYMOUS_USER, Access::Listing);
+    info!("--> 200");
+    Ok(Html(site::page(&note, &base_url(&state, &headers), indexed)).into_response())
+}
+
+/// Returns the sitemap with the pages of all notes that anonymous visitors can find
+async fn sitemap<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    info!("GET /sitemap.xml");
+    let data = state.data.lock().expect("mutex was poisoned");
+    let notes = data
+        .notes_with(VisibilityFilter::Active)
+        .filter(|note| note.visibility().allows(&ANONYMOUS_USER, Access::Listing))
+        .collect::<Vec<&Note>>();
+    info!("--> 200 [{} notes]", notes.len());
+    (
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        site::sitemap(notes, &base_url(&state, &headers)),
+    )
+}
+
+/// Returns the federation or 404 if it is disabled
+fn federation<P: for<'a> persistence::Persister<'a>>(
+    state: &AppState<P>,
+) -> Result<&Federation, (StatusCode, String)> {
+    state.federation.as_deref().ok_or_else(|| {
+        info!("--> 404");
+        (
+            StatusCode::NOT_FOUND,
+            "Federation is not configured".to_string(),
+        )
+    })
+}
+
+/// Returns the active user with the Id, or 404
+fn federated_user<'a, P: Persister<'a>>(
+    data: &'a P,
+    id: usize,
+) -> Result<&'a User, (StatusCode, String)> {
+    match data.user(id.into()) {
+        Some(user) if user.is_active() => Ok(user),
+        _ => {
+            info!("--> 404");
+            Err((StatusCode::NOT_FOUND, "User does not exist".to_string()))
+        }
+    }
+}
+
+/// Resolves an `acct:` handle to the ActivityPub actor of the user
+async fn webfinger<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Query(query): Query<WebFingerQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    info!("GET /.well-known/webfinger?resource={}", query.resource());
+    let federation = federation(&state)?;
+    let data = state.data.lock().expect("mutex was poisoned");
+    let user = federation.account(query.resource()).and_then(|name| {
+        data.users()
+            .find(|user| user.name() == name && user.is_active())
+    });
+    let Some(user) = user else {
+        info!("--> 404");
+        return Err((StatusCode::NOT_FOUND, "User does not exist".to_string()));
+    };
+    info!("--> 200");
+    Ok((
+        [(header::CONTENT_TYPE, JRD_JSON)],
+        Json(federation.webfinger(user)),
+    ))
+}
+
+/// Returns the ActivityPub actor document of the user
+async fn actor<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Path(id): Path<usize>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    info!("GET /users/{}", id);
+    let federation = federation(&state)?;
+    let data = state.data.lock().expect("mutex was poisoned");
+    let user = federated_user(&*data, id)?;
+    info!("--> 200");
+    Ok((
+        [(header::CONTENT_TYPE, ACTIVITY_JSON)],
+        Json(federation.actor(user)),
+    ))
+}
+
+/// Returns the public notes of the user as ActivityPub outbox, newest first
+async fn outbox<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Path(id): Path<usize>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    info!("GET /users/{}/outbox", id);
+    let federation = federation(&state)?;
+    let data = state.data.lock().expect("mutex was poisoned");
+    let user = federated_user(&*data, id)?;
+    let mut notes = data
+        .user_notes(user)
+        .filter(|note| note.visibility() == &Visibility::Public)
+        .collect::<Vec<&Note>>();
+    notes.sort_by(|a, b| b.created().cmp(a.created()));
+    info!("--> 200");
+    Ok((
+        [(header::CONTENT_TYPE, ACTIVITY_JSON)],
+        Json(federation.outbox(user, &notes)),
+    ))
+}
+
+/// Returns the number of followers of the user
+async fn followers<P: for<'a> persistence::Persister<'a>>(
+    State(state): State<AppState<P>>,
+    Path(id): Path<usize>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    info!("GET /users/{}/followers", id);
+    let federation = federation(&state)?;
+    let data = state.data.lock().expect("mutex was poisoned");
+    let user = federated_user(&*data, id)?;
+    info!("--> 200");
+    Ok((
+        [(header::CONTENT_TYPE, ACTIVITY_JSON)],
+        Json(federation.followers(user, data.followers(user.id()).len())),
+    ))
+}
+
+/// Receives activities of remote actors for the user
+///
+/// Activities must be signed by their actor with an HTTP signature, see
+/// [`Federation::verify`]. Public notes are delivered to the inbox from the actor
+/// document of a follower, never to an address in the activity.
+async fn inbox<P: for<'a> persistence::Persister<'a> + Send + 'static>(
+    State(state): State<AppState<P>>,
+    Path(id): Path<usize>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    info!("POST /users/{}/inbox", id);
+    let federation = federation(&state)?;
+    let user = {
+        let data = state.data.lock().expect("mutex was poisoned");
+        *federated_user(&*data, id)?.id()
+    };
+    let target = uri
+        .path_and_query()
+        .map_or_else(|| uri.path(), |target| target.as_str());
+    let signer = federation
+        .verify(target, &headers, &body)
+        .await
+        .map_err(|err| {
+            info!("--> 401 [{:#}]", err);
+            (
+                StatusCode::UNAUTHORIZED,
+                "Invalid or missing HTTP signature".to_string(),
+            )
+        })?;
+    let activity = serde_json::from_slice::<Value>(&body).map_err(|err| {
+        info!("--> 400 [{}]", err);
+        (StatusCode::BAD_REQUEST, format!("Invalid activity: {err}"))
+    })?;
+    let parsed = InboxActivity::parse(&activity).map_err(|err| {
+        info!("--> 400 [{}]", err);
+        (StatusCode::BAD_REQUEST, err.to_string())
+    })?;
+    match parsed {
+        InboxActivity::Follow { actor } | InboxActivity::Unfollow { actor } if actor != signer => {
+            info!("--> 401 [{} signed an activity of {}]", signer, actor);
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "Activity is not signed by its actor".to_string(),
+            ));
+        }
+        InboxActivity::Follow { actor } => {
+            state
+                .jobs
+                .enqueue(&AcceptFollow::new(user, actor, activity));
+        }
+        InboxActivity::Unfollow { actor } => {
+            let mut data = state.data.lock().expect("mutex was poisoned");
+            data.remove_follower(&user, &actor);
+        }
+        InboxActivity::Other => {}
+    }
+    info!("--> 202");
+    Ok(StatusCode::ACCEPTED)
+}
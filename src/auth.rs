@@ -0,0 +1,344 @@
+//! Groundwork for authentication
+//!
+//! Users are identified by one of
+//! - a session token in the `Authorization: Bearer <token>` header, issued after
+//!   a login via an external identity provider (see [`oidc`] and [`session`])
+//! - a [`ServiceToken`] in the same header, which is limited to the routes of its
+//!   scopes (see [`require_scope`])
+//! - the Id of the user in the [`USER_HEADER`], forwarded by an upstream
+//!   authentication middleware or proxy. Only if `NOTE_TRUST_USER_HEADER` is set, see
+//!   [`Config::trusts_user_header`](crate::config::Config::trusts_user_header)
+//!
+//! Admins can get a short-lived session of another user for support, see [`impersonator`].
+//!
+//! Requests without any of the headers are handled as the anonymous default user.
+use axum::async_trait;
+use axum::extract::{FromRequestParts, MatchedPath, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::info;
+
+use session::Sessions;
+
+use crate::models::token::{Scope, ServiceToken, TOKEN_PREFIX};
+use crate::models::{Id, User};
+use crate::persistence::Persister;
+use crate::policy::Policy;
+use crate::AppState;
+
+/// The header that contains the Id of the authenticated user, ignored unless it is trusted
+pub const USER_HEADER: &str = "x-user-id";
+
+/// The Id of the anonymous user that is used if no user is provided
+pub const ANONYMOUS_USER: Id = Id(0);
+
+/// Extracts the [`User`] sending the request
+///
+/// Unknown users and users whose accounts are scheduled for deletion are rejected.
+pub struct CurrentUser(pub User);
+
+/// Extracts the [`User`] sending the request, if it is an admin
+///
+/// Admins are configured with `NOTE_ADMIN_USERS`, all other users are rejected.
+pub struct Admin(pub User);
+
+pub mod oidc;
+pub mod session;
+
+/// Returns the secret of the [`ServiceToken`] in the `Authorization` header, if any
+fn service_token_secret(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| token.starts_with(TOKEN_PREFIX))
+}
+
+/// Returns the admin who impersonates the user with the session token of the request, if any
+///
+/// See [`Sessions::impersonate`], the user is identified like with every other session.
+pub fn impersonator(sessions: &Sessions, headers: &HeaderMap) -> Option<Id> {
+    if service_token_secret(headers).is_some() {
+        return None;
+    }
+    let token = headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+    sessions.impersonator(token.trim())
+}
+
+/// Returns `true` if admins may use the route while they impersonate a user
+///
+/// They can read everything of the user and change notes like a service token with all
+/// scopes, but neither manage the account nor use admin routes. The account and its
+/// integrations are not readable either, they contain the secrets of the integrations.
+pub fn impersonation_allows(method: &Method, route: &str) -> bool {
+    if route.starts_with("/admin/")
+        || route.starts_with("/me/tokens")
+        || route.starts_with("/me/integrations")
+        || route == "/me"
+    {
+        false
+    } else if method == Method::GET || method == Method::HEAD {
+        true
+    } else {
+        Scope::required(method, route).is_some()
+    }
+}
+
+/// Returns the known user who sent the request, without rejecting or logging invalid credentials
+///
+/// Used to attribute requests, e.g. in the usage statistics. Handlers use [`CurrentUser`].
+pub fn requester<'a, P: Persister<'a>>(
+    data: &'a P,
+    sessions: &Sessions,
+    headers: &HeaderMap,
+    trust_user_header: bool,
+) -> Option<Id> {
+    let id = if let Some(secret) = service_token_secret(headers) {
+        *data.service_token(secret)?.user()
+    } else if let Some(value) = headers.get(AUTHORIZATION) {
+        let token = value.to_str().ok()?.strip_prefix("Bearer ")?;
+        sessions.verify(token.trim())?
+    } else {
+        match headers.get(USER_HEADER).filter(|_| trust_user_header) {
+            Some(value) => Id::from(value.to_str().ok()?.trim().parse::<usize>().ok()?),
+            None => ANONYMOUS_USER,
+        }
+    };
+    data.user(id).map(|user| *user.id())
+}
+
+fn invalid_service_token() -> (StatusCode, String) {
+    info!("--> 401 [invalid service token]");
+    (
+        StatusCode::UNAUTHORIZED,
+        "Invalid or revoked service token".to_string(),
+    )
+}
+
+/// Parses the user Id from the request headers, the [`USER_HEADER`] only if it is trusted
+fn user_id(
+    parts: &Parts,
+    sessions: &Sessions,
+    trust_user_header: bool,
+) -> Result<Id, (StatusCode, String)> {
+    if let Some(value) = parts.headers.get(AUTHORIZATION) {
+        return value
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| sessions.verify(token.trim()))
+            .ok_or_else(|| {
+                info!("--> 401 [invalid session]");
+                (
+                    StatusCode::UNAUTHORIZED,
+                    "Invalid or expired session".to_string(),
+                )
+            });
+    }
+    let Some(value) = parts.headers.get(USER_HEADER).filter(|_| trust_user_header) else {
+        return Ok(ANONYMOUS_USER);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .map(Id::from)
+        .ok_or_else(|| {
+            info!("--> 400 [invalid {} header]", USER_HEADER);
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid {USER_HEADER} header"),
+            )
+        })
+}
+
+#[async_trait]
+impl<P> FromRequestParts<AppState<P>> for CurrentUser
+where
+    P: for<'a> Persister<'a> + Send,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState<P>,
+    ) -> Result<Self, Self::Rejection> {
+        let data = state.data.lock().expect("mutex was poisoned");
+        let id = match service_token_secret(&parts.headers) {
+            Some(secret) => *data
+                .service_token(secret)
+                .ok_or_else(invalid_service_token)?
+                .user(),
+            None => user_id(parts, &state.sessions, state.config.trusts_user_header())?,
+        };
+        let Some(user) = data.user(id) else {
+            info!("--> 401 [unknown user {}]", usize::from(id));
+            return Err((StatusCode::UNAUTHORIZED, "Unknown user".to_string()));
+        };
+        if !user.is_active() {
+            info!(
+                "--> 401 [user {} is scheduled for deletion]",
+                usize::from(id)
+            );
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "Account is scheduled for deletion".to_string(),
+            ));
+        }
+        Ok(CurrentUser(user.clone()))
+    }
+}
+
+#[async_trait]
+impl<P> FromRequestParts<AppState<P>> for Admin
+where
+    P: for<'a> Persister<'a> + Send,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState<P>,
+    ) -> Result<Self, Self::Rejection> {
+        let CurrentUser(user) = CurrentUser::from_request_parts(parts, state).await?;
+        if !Policy::new(&state.config.admin_users).is_admin(user.id()) {
+            info!("--> 403 [user {} is no admin]", usize::from(user.id()));
+            return Err((StatusCode::FORBIDDEN, "Admin access required".to_string()));
+        }
+        Ok(Admin(user))
+    }
+}
+
+/// Rejects requests with a [`ServiceToken`] that lacks the [`Scope`] of the route
+///
+/// Must be added with [`Router::route_layer`](axum::Router::route_layer), so that the
+/// route is known. Requests with other credentials are passed on unchanged, routes
+/// without a scope are forbidden for service tokens. Sessions of admins who impersonate
+/// a user are limited to the routes of [`impersonation_allows`].
+pub async fn require_scope<P, B>(
+    State(state): State<AppState<P>>,
+    route: Option<MatchedPath>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, String)>
+where
+    P: for<'a> Persister<'a> + Send,
+{
+    if let Some(secret) = service_token_secret(request.headers()) {
+        let required = route.and_then(|route| Scope::required(request.method(), route.as_str()));
+        let data = state.data.lock().expect("mutex was poisoned");
+        let token: &ServiceToken = data
+            .service_token(secret)
+            .ok_or_else(invalid_service_token)?;
+        match required {
+            Some(scope) if token.allows(scope) => {}
+            Some(scope) => {
+                info!("--> 403 [service token lacks scope {}]", scope.as_str());
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    format!("Service token lacks the scope {}", scope.as_str()),
+                ));
+            }
+            None => {
+                info!("--> 403 [route is not available for service tokens]");
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    "Route is not available for service tokens".to_string(),
+                ));
+            }
+        }
+    } else if impersonator(&state.sessions, request.headers()).is_some() {
+        let allowed =
+            route.is_some_and(|route| impersonation_allows(request.method(), route.as_str()));
+        if !allowed {
+            info!("--> 403 [route is not available while impersonating]");
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Route is not available while impersonating a user".to_string(),
+            ));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::Request;
+
+    use std::time::Duration;
+
+    fn parts(header: &str, value: Option<&str>) -> Parts {
+        let mut request = Request::builder();
+        if let Some(value) = value {
+            request = request.header(header, value);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn test_user_id() {
+        let sessions = Sessions::new(b"secret", Duration::from_secs(60));
+        let user_id = |value| user_id(&parts(USER_HEADER, value), &sessions, true);
+        assert_eq!(user_id(None), Ok(ANONYMOUS_USER));
+        assert_eq!(user_id(Some("12")), Ok(Id(12)));
+        assert_eq!(user_id(Some(" 3 ")), Ok(Id(3)));
+        assert!(user_id(Some("foo")).is_err());
+        assert!(user_id(Some("-1")).is_err());
+
+        // the header is ignored unless it is trusted
+        let untrusted = |value| super::user_id(&parts(USER_HEADER, value), &sessions, false);
+        assert_eq!(untrusted(Some("12")), Ok(ANONYMOUS_USER));
+        assert_eq!(untrusted(Some("foo")), Ok(ANONYMOUS_USER));
+    }
+
+    #[test]
+    fn test_impersonation_allows() {
+        assert!(impersonation_allows(&Method::GET, "/notes"));
+        assert!(impersonation_allows(&Method::GET, "/me/preferences"));
+        assert!(impersonation_allows(&Method::PUT, "/note/:id"));
+        assert!(!impersonation_allows(&Method::PUT, "/me/preferences"));
+        assert!(!impersonation_allows(&Method::DELETE, "/me"));
+        assert!(!impersonation_allows(&Method::GET, "/me/tokens"));
+        assert!(!impersonation_allows(&Method::GET, "/me/integrations"));
+        assert!(!impersonation_allows(&Method::GET, "/me"));
+        assert!(impersonation_allows(&Method::GET, "/me/audit-log"));
+        assert!(!impersonation_allows(&Method::GET, "/admin/usage"));
+        assert!(!impersonation_allows(
+            &Method::POST,
+            "/admin/impersonate/:user_id"
+        ));
+    }
+
+    #[test]
+    fn test_session_user_id() {
+        let sessions = Sessions::new(b"secret", Duration::from_secs(60));
+        let token = sessions.issue(&User::new(Id(5), "foo".to_string()));
+        let bearer = format!("Bearer {}", token.token());
+        assert_eq!(
+            user_id(&parts("authorization", Some(&bearer)), &sessions, false),
+            Ok(Id(5))
+        );
+        assert!(user_id(
+            &parts("authorization", Some("Bearer foo")),
+            &sessions,
+            false
+        )
+        .is_err());
+        assert!(user_id(
+            &parts("authorization", Some(token.token())),
+            &sessions,
+            false
+        )
+        .is_err());
+    }
+}
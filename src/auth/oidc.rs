@@ -0,0 +1,310 @@
+//! Login via an external OpenID Connect / OAuth2 identity provider
+//!
+//! Implements the authorization code flow:
+//! 1. `GET /auth/oidc/login` redirects the user to the identity provider
+//! 2. The identity provider redirects back to `GET /auth/oidc/callback` with a `code`
+//! 3. The code is exchanged for an access token, which is used to query the
+//!    userinfo endpoint of the identity provider
+//!
+//! The subject claim of the userinfo is mapped to a [`User`](crate::models::User),
+//! which is created on the first login. The `state` of a login is also stored in the
+//! [`STATE_COOKIE`] of the browser, so that only the browser that started the login can
+//! complete it.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use axum::http::header::COOKIE;
+use axum::http::HeaderMap;
+use rand::distributions::{Alphanumeric, DistString};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::OidcConfig;
+use crate::models::ExternalIdentity;
+
+/// How long a login attempt may take until the callback is received
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The maximum number of pending logins, further logins are rejected until older ones
+/// complete or time out
+const MAX_PENDING_LOGINS: usize = 10_000;
+
+/// The name of the cookie with the `state` of the login
+pub const STATE_COOKIE: &str = "oidc_state";
+
+/// The query parameters of the callback from the identity provider
+#[derive(Debug, Deserialize)]
+pub struct Callback {
+    code: String,
+    state: String,
+}
+
+/// The successful response of the token endpoint
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// A started login
+#[derive(Debug)]
+pub struct Authorization {
+    /// The URL of the identity provider to redirect to
+    pub url: Url,
+    /// The `Set-Cookie` header with the [`STATE_COOKIE`]
+    pub cookie: String,
+}
+
+/// A user that was successfully identified by the identity provider
+#[derive(Debug)]
+pub struct Login {
+    pub identity: ExternalIdentity,
+    pub name: String,
+}
+
+/// Client for the configured identity provider
+pub struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    /// The `state` parameters of all pending logins, to prevent CSRF
+    pending: Mutex<HashMap<String, Instant>>,
+}
+
+impl OidcClient {
+    /// Constructs a new [`OidcClient`]
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts a new login, `None` if there are too many pending logins
+    pub fn authorize(&self) -> Result<Option<Authorization>> {
+        let mut pending = self.pending.lock().expect("mutex was poisoned");
+        let now = Instant::now();
+        pending.retain(|_, started| now.duration_since(*started) < LOGIN_TIMEOUT);
+        if pending.len() >= MAX_PENDING_LOGINS {
+            return Ok(None);
+        }
+        let state = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let url = Url::parse_with_params(
+            &self.config.authorize_url,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.config.redirect_url),
+                ("scope", &self.config.scopes),
+                ("state", &state),
+            ],
+        )
+        .context("Invalid authorize URL")?;
+        let secure = if self.config.redirect_url.starts_with("https://") {
+            "; Secure"
+        } else {
+            ""
+        };
+        let cookie = format!(
+            "{STATE_COOKIE}={state}; Path=/auth/oidc; Max-Age={}; HttpOnly; SameSite=Lax{secure}",
+            LOGIN_TIMEOUT.as_secs()
+        );
+        pending.insert(state, now);
+        Ok(Some(Authorization { url, cookie }))
+    }
+
+    /// Completes the login with the `state` of the [`STATE_COOKIE`] of the request and returns
+    /// the identified user
+    pub async fn login(&self, callback: Callback, cookie: Option<&str>) -> Result<Login> {
+        if cookie != Some(callback.state.as_str()) {
+            return Err(anyhow!("Login was started in another browser"));
+        }
+        self.check_state(&callback.state)?;
+
+        let token = self
+            .http
+            .post(&self.config.token_url)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", &callback.code),
+                ("redirect_uri", &self.config.redirect_url),
+                ("client_id", &self.config.client_id),
+                ("client_secret", &self.config.client_secret),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Token request failed")?
+            .json::<TokenResponse>()
+            .await
+            .context("Invalid token response")?;
+
+        let userinfo = self
+            .http
+            .get(&self.config.userinfo_url)
+            .bearer_auth(token.access_token)
+            // some providers (e.g. GitHub) reject requests without user agent
+            .header(reqwest::header::USER_AGENT, "note-demo")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Userinfo request failed")?
+            .json::<Value>()
+            .await
+            .context("Invalid userinfo response")?;
+
+        self.map_userinfo(&userinfo)
+    }
+
+    /// Consumes the `state` of a pending login
+    fn check_state(&self, state: &str) -> Result<()> {
+        let mut pending = self.pending.lock().expect("mutex was poisoned");
+        match pending.remove(state) {
+            Some(started) if started.elapsed() < LOGIN_TIMEOUT => Ok(()),
+            _ => Err(anyhow!("Unknown or expired login")),
+        }
+    }
+
+    /// Maps the claims of the userinfo to a [`Login`]
+    fn map_userinfo(&self, userinfo: &Value) -> Result<Login> {
+        let subject = claim(userinfo, &self.config.subject_claim)
+            .with_context(|| format!("Userinfo has no {} claim", self.config.subject_claim))?;
+        let name = claim(userinfo, &self.config.name_claim).unwrap_or_else(|| subject.clone());
+        Ok(Login {
+            identity: ExternalIdentity::new(self.config.issuer.clone(), subject),
+            name,
+        })
+    }
+}
+
+/// Returns the `state` of the [`STATE_COOKIE`] of the request, if any
+pub fn state_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == STATE_COOKIE).then_some(value)
+        })
+}
+
+/// Returns a string or numeric claim as String
+fn claim(userinfo: &Value, name: &str) -> Option<String> {
+    match userinfo.get(name)? {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn client() -> OidcClient {
+        OidcClient::new(OidcConfig {
+            issuer: "https://idp".to_string(),
+            authorize_url: "https://idp/authorize?prompt=login".to_string(),
+            token_url: "https://idp/token".to_string(),
+            userinfo_url: "https://idp/userinfo".to_string(),
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_url: "http://127.0.0.1:3000/auth/oidc/callback".to_string(),
+            scopes: "openid profile".to_string(),
+            subject_claim: "sub".to_string(),
+            name_claim: "name".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_state_is_single_use() {
+        let client = client();
+        let authorization = client.authorize().unwrap().unwrap();
+        let url = authorization.url;
+        let params = url.query_pairs().collect::<HashMap<_, _>>();
+        assert_eq!(params["prompt"], "login");
+        assert_eq!(params["client_id"], "client");
+        assert_eq!(
+            params["redirect_uri"],
+            "http://127.0.0.1:3000/auth/oidc/callback"
+        );
+
+        let state = params["state"].to_string();
+        assert_eq!(
+            authorization.cookie,
+            format!("oidc_state={state}; Path=/auth/oidc; Max-Age=600; HttpOnly; SameSite=Lax")
+        );
+        assert!(client.check_state("foobar").is_err());
+        assert!(client.check_state(&state).is_ok());
+        assert!(client.check_state(&state).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_state_cookie_is_required() {
+        let client = client();
+        let url = client.authorize().unwrap().unwrap().url;
+        let state = url
+            .query_pairs()
+            .find(|(name, _)| name == "state")
+            .unwrap()
+            .1
+            .to_string();
+        let callback = || Callback {
+            code: "code".to_string(),
+            state: state.clone(),
+        };
+        assert!(client.login(callback(), None).await.is_err());
+        assert!(client.login(callback(), Some("other")).await.is_err());
+        // the login of the browser with the cookie is still pending
+        assert!(client.check_state(&state).is_ok());
+    }
+
+    #[test]
+    fn test_pending_logins() {
+        let client = client();
+        for _ in 0..MAX_PENDING_LOGINS {
+            assert!(client.authorize().unwrap().is_some());
+        }
+        assert!(client.authorize().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_state_cookie() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(state_cookie(&headers), None);
+        headers.insert(COOKIE, "theme=dark; oidc_state=abc".parse().unwrap());
+        assert_eq!(state_cookie(&headers), Some("abc"));
+        headers.insert(COOKIE, "oidc_state_old=abc".parse().unwrap());
+        assert_eq!(state_cookie(&headers), None);
+    }
+
+    #[test]
+    fn test_map_userinfo() {
+        let client = client();
+
+        let login = client
+            .map_userinfo(&json!({"sub": "abc", "name": "Jane"}))
+            .unwrap();
+        assert_eq!(
+            login.identity,
+            ExternalIdentity::new("https://idp".to_string(), "abc".to_string())
+        );
+        assert_eq!(login.name, "Jane");
+
+        // numeric Ids, e.g. from GitHub, and missing names
+        let login = client.map_userinfo(&json!({"sub": 42})).unwrap();
+        assert_eq!(
+            login.identity,
+            ExternalIdentity::new("https://idp".to_string(), "42".to_string())
+        );
+        assert_eq!(login.name, "42");
+
+        assert!(client.map_userinfo(&json!({"name": "Jane"})).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Session tokens issued after a successful login
+//!
+//! Sessions are stateless JSON Web Tokens, signed with a secret of the app.
+//! They are sent as `Authorization: Bearer <token>` header.
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::models::integration::Integrations;
+use crate::models::{Id, User};
+
+/// The claims of the session token
+#[derive(Debug, Deserialize, Serialize)]
+struct Claims {
+    /// The Id of the [`User`]
+    sub: usize,
+    iat: i64,
+    exp: i64,
+    /// The Id of the admin who impersonates the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    act: Option<usize>,
+}
+
+/// A session that was issued to a [`User`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Session {
+    token: String,
+    expires: DateTime<Utc>,
+    user: User,
+    /// The admin who impersonates the user, see [`Sessions::impersonate`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    impersonator: Option<Id>,
+}
+
+impl Session {
+    #[allow(dead_code)] // needed for unittests
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+/// Issues and verifies session tokens
+#[derive(Clone)]
+pub struct Sessions {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    ttl: TimeDelta,
+}
+
+impl Sessions {
+    /// Constructs a new [`Sessions`] that signs tokens with the `secret`
+    pub fn new(secret: &[u8], ttl: Duration) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            ttl: TimeDelta::from_std(ttl).expect("session lifetime is out of range"),
+        }
+    }
+
+    /// Constructs a new [`Sessions`] with a random secret
+    pub fn random(ttl: Duration) -> Self {
+        let secret: [u8; 32] = rand::random();
+        Self::new(&secret, ttl)
+    }
+
+    /// Issues a new session token for the [`User`]
+    pub fn issue(&self, user: &User) -> Session {
+        self.sign(user, self.ttl, None)
+    }
+
+    /// Issues a session token for the [`User`] to the admin, valid for `ttl`
+    ///
+    /// The token identifies the user like any other session, [`Sessions::impersonator`]
+    /// tells that the admin acts on behalf of the user. The user of the session doesn't
+    /// include the secrets of the integrations.
+    pub fn impersonate(&self, user: &User, admin: &Id, ttl: Duration) -> Session {
+        let ttl = TimeDelta::from_std(ttl).expect("impersonation lifetime is out of range");
+        let mut user = user.clone();
+        user.set_integrations(Integrations::default());
+        self.sign(&user, ttl, Some(*admin))
+    }
+
+    fn sign(&self, user: &User, ttl: TimeDelta, impersonator: Option<Id>) -> Session {
+        let now = Utc::now();
+        let expires = now + ttl;
+        let claims = Claims {
+            sub: user.id().into(),
+            iat: now.timestamp(),
+            exp: expires.timestamp(),
+            act: impersonator.map(usize::from),
+        };
+        let token = encode(&Header::default(), &claims, &self.encoding)
+            .expect("session token can always be encoded");
+        Session {
+            token,
+            expires,
+            user: user.clone(),
+            impersonator,
+        }
+    }
+
+    fn claims(&self, token: &str) -> Option<Claims> {
+        let claims = decode::<Claims>(token, &self.decoding, &Validation::default())
+            .ok()?
+            .claims;
+        // sessions of admins end exactly when they expire, without the leeway of other sessions
+        if claims.act.is_some() {
+            let mut validation = Validation::default();
+            validation.leeway = 0;
+            decode::<Claims>(token, &self.decoding, &validation).ok()?;
+        }
+        Some(claims)
+    }
+
+    /// Returns the Id of the [`User`] if the token is valid and not expired
+    pub fn verify(&self, token: &str) -> Option<Id> {
+        self.claims(token).map(|claims| claims.sub.into())
+    }
+
+    /// Returns the Id of the admin if the token is a valid session to impersonate a user
+    pub fn impersonator(&self, token: &str) -> Option<Id> {
+        self.claims(token)?.act.map(Id::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_issue_and_verify() {
+        let sessions = Sessions::new(b"secret", Duration::from_secs(60));
+        let session = sessions.issue(&User::new(Id(3), "foo".to_string()));
+        assert_eq!(sessions.verify(session.token()), Some(Id(3)));
+        assert!(sessions.verify("foobar").is_none());
+
+        let other = Sessions::new(b"other secret", Duration::from_secs(60));
+        assert!(other.verify(session.token()).is_none());
+        assert!(sessions.impersonator(session.token()).is_none());
+    }
+
+    #[test]
+    fn test_impersonate() {
+        let sessions = Sessions::new(b"secret", Duration::from_secs(60 * 60));
+        let user = User::new(Id(3), "foo".to_string());
+        let session = sessions.impersonate(&user, &Id(1), Duration::from_secs(60));
+        assert_eq!(sessions.verify(session.token()), Some(Id(3)));
+        assert_eq!(sessions.impersonator(session.token()), Some(Id(1)));
+        assert!(session.expires < Utc::now() + TimeDelta::seconds(61));
+        let value = serde_json::to_value(&session).unwrap();
+        assert_eq!(value["impersonator"], 1);
+        assert!(sessions.impersonator("foobar").is_none());
+    }
+
+    #[test]
+    fn test_expired_session() {
+        let sessions = Sessions::new(b"secret", Duration::from_secs(0));
+        let mut session = sessions.issue(&User::default());
+        // The default validation allows 60 seconds of leeway
+        let claims = Claims {
+            sub: 0,
+            iat: 0,
+            exp: (Utc::now() - TimeDelta::seconds(120)).timestamp(),
+            act: None,
+        };
+        session.token = encode(&Header::default(), &claims, &sessions.encoding).unwrap();
+        assert!(sessions.verify(session.token()).is_none());
+
+        // within the leeway, but impersonations have none
+        let expired = |act| Claims {
+            sub: 0,
+            iat: 0,
+            exp: (Utc::now() - TimeDelta::seconds(30)).timestamp(),
+            act,
+        };
+        let token = encode(&Header::default(), &expired(None), &sessions.encoding).unwrap();
+        assert_eq!(sessions.verify(&token), Some(Id(0)));
+        let token = encode(&Header::default(), &expired(Some(1)), &sessions.encoding).unwrap();
+        assert!(sessions.verify(&token).is_none());
+        assert!(sessions.impersonator(&token).is_none());
+    }
+}
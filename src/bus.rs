@@ -0,0 +1,198 @@
+//! Distributes [`IndexEvent`]s between the instances of a deployment
+//!
+//! Every instance keeps its own [`Journal`](crate::events::Journal),
+//! [`QueryCache`](crate::cache::QueryCache) and [`Index`](crate::indexer::Index). The
+//! [`Indexer`](crate::indexer::Indexer) publishes all events on an [`EventBus`] and applies the
+//! events of other instances, so that `GET /events` streams changes that were made via another
+//! instance and no instance serves stale query results.
+//!
+//! A single instance uses the [`LocalBus`]. Instances behind a load balancer share a Redis
+//! pub/sub channel with the [`RedisBus`]. Redis pub/sub doesn't buffer messages: events that are
+//! published while an instance is disconnected are lost for it.
+use std::fmt;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{info, warn};
+
+use crate::config::EventBusConfig;
+use crate::indexer::IndexEvent;
+
+/// Delay before reconnecting to Redis after the connection was lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// An [`IndexEvent`] and the instance that committed it
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BusMessage {
+    /// Identifies the instance, so that it can skip its own messages
+    pub origin: String,
+    pub event: IndexEvent,
+}
+
+/// Receives all messages of the bus, including the ones of its own instance
+pub type Handler = Box<dyn Fn(BusMessage) + Send + Sync>;
+
+/// Delivers [`BusMessage`]s to all subscribed instances
+pub trait EventBus: fmt::Debug + Send + Sync {
+    /// Sends the message without waiting for the delivery
+    fn publish(&self, message: BusMessage);
+
+    fn subscribe(&self, handler: Handler);
+}
+
+/// Delivers messages to the subscribers in the same process
+///
+/// Several [`Indexer`](crate::indexer::Indexer)s can share a bus, e.g. to test a deployment
+/// with multiple instances.
+#[derive(Default)]
+pub struct LocalBus {
+    handlers: RwLock<Vec<Handler>>,
+}
+
+impl fmt::Debug for LocalBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let handlers = self.handlers.read().expect("handlers lock was poisoned");
+        f.debug_struct("LocalBus")
+            .field("handlers", &handlers.len())
+            .finish()
+    }
+}
+
+impl EventBus for LocalBus {
+    fn publish(&self, message: BusMessage) {
+        for handler in self
+            .handlers
+            .read()
+            .expect("handlers lock was poisoned")
+            .iter()
+        {
+            handler(message.clone());
+        }
+    }
+
+    fn subscribe(&self, handler: Handler) {
+        self.handlers
+            .write()
+            .expect("handlers lock was poisoned")
+            .push(handler);
+    }
+}
+
+/// Shares the messages of all instances via a Redis pub/sub channel
+///
+/// Messages are encoded as JSON. The connections are established in the background and
+/// reestablished after failures, so the bus must be created on the tokio runtime.
+#[derive(Debug)]
+pub struct RedisBus {
+    client: redis::Client,
+    channel: String,
+    outgoing: UnboundedSender<String>,
+}
+
+impl RedisBus {
+    pub fn new(config: &EventBusConfig) -> Result<Self, redis::RedisError> {
+        let client = redis::Client::open(config.url.as_str())?;
+        let (outgoing, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(publish(client.clone(), config.channel.clone(), receiver));
+        Ok(Self {
+            client,
+            channel: config.channel.clone(),
+            outgoing,
+        })
+    }
+}
+
+/// Publishes the queued messages, messages are dropped while Redis is unavailable
+async fn publish(client: redis::Client, channel: String, mut receiver: UnboundedReceiver<String>) {
+    let mut connection = None;
+    while let Some(payload) = receiver.recv().await {
+        if connection.is_none() {
+            match client.get_multiplexed_async_connection().await {
+                Ok(established) => connection = Some(established),
+                Err(err) => warn!("Unable to connect to the event bus: {}", err),
+            }
+        }
+        if let Some(established) = connection.as_mut() {
+            if let Err(err) = established.publish::<_, _, ()>(&channel, payload).await {
+                warn!("Unable to publish to the event bus: {}", err);
+                connection = None;
+            }
+        }
+    }
+}
+
+/// Passes the messages of the channel to the handler until the connection is lost
+async fn receive(
+    client: &redis::Client,
+    channel: &str,
+    handler: &Handler,
+) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(channel).await?;
+    info!("Subscribed to the event bus channel {}", channel);
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let payload = message.get_payload::<String>()?;
+        match serde_json::from_str::<BusMessage>(&payload) {
+            Ok(message) => handler(message),
+            Err(err) => warn!("Ignoring invalid message of the event bus: {}", err),
+        }
+    }
+    Ok(())
+}
+
+impl EventBus for RedisBus {
+    fn publish(&self, message: BusMessage) {
+        let payload = serde_json::to_string(&message).expect("bus messages are serializable");
+        if self.outgoing.send(payload).is_err() {
+            warn!("Event bus publisher is not running, other instances are out of date");
+        }
+    }
+
+    fn subscribe(&self, handler: Handler) {
+        let client = self.client.clone();
+        let channel = self.channel.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = receive(&client, &channel, &handler).await {
+                    warn!("Lost the connection to the event bus: {}", err);
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+    use crate::models::Id;
+
+    #[test]
+    fn test_message_encoding() {
+        // other instances must be able to decode all events, as sent via Redis
+        for event in [
+            IndexEvent::Updated(example_note()),
+            IndexEvent::UserPurged(Id(3)),
+            IndexEvent::Viewed {
+                user: Id(1),
+                note: Id(2),
+            },
+        ] {
+            let message = BusMessage {
+                origin: "a".to_string(),
+                event,
+            };
+            let encoded = serde_json::to_string(&message).unwrap();
+            assert_eq!(
+                serde_json::from_str::<BusMessage>(&encoded).unwrap(),
+                message
+            );
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! A cache of the results of note queries, e.g. for `GET /notes/tag/work`
+//!
+//! Every entry belongs to a single user and stores the Ids of the matching notes,
+//! in the order of the response. Queries only return notes of the user, so an
+//! [`IndexEvent`] for a note invalidates all entries of its owner. The cache keeps
+//! at most [`QUERY_CACHE_CAPACITY`] entries and drops the oldest entry first.
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::indexer::IndexEvent;
+use crate::models::query::NoteQuery;
+use crate::models::Id;
+
+/// The maximum number of cached query results
+pub const QUERY_CACHE_CAPACITY: usize = 1000;
+
+type Key = (Id, NoteQuery);
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<Key, Vec<Id>>,
+    /// The keys of all entries, oldest first
+    order: VecDeque<Key>,
+}
+
+/// The hit rate and size of the [`QueryCache`]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CacheMetrics {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// The share of lookups that were answered from the cache, between 0 and 1
+    pub hit_rate: f64,
+}
+
+/// Cached query results per user
+#[derive(Debug)]
+pub struct QueryCache {
+    inner: Mutex<Inner>,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        Self::new(QUERY_CACHE_CAPACITY)
+    }
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached result of the query, or caches the result of `compute`
+    ///
+    /// The query must be normalized, see [`NoteQuery::normalized`], so that
+    /// equivalent queries share an entry.
+    pub fn get_or_insert_with<F: FnOnce() -> Vec<Id>>(
+        &self,
+        user: Id,
+        query: &NoteQuery,
+        compute: F,
+    ) -> Vec<Id> {
+        let mut inner = self.inner.lock().expect("query cache lock was poisoned");
+        let key = (user, query.clone());
+        if let Some(ids) = inner.entries.get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return ids.clone();
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let ids = compute();
+        if self.capacity > 0 {
+            if inner.order.len() >= self.capacity {
+                if let Some(oldest) = inner.order.pop_front() {
+                    inner.entries.remove(&oldest);
+                }
+            }
+            inner.order.push_back(key.clone());
+            inner.entries.insert(key, ids.clone());
+        }
+        ids
+    }
+
+    /// Drops all entries that the event can affect
+    pub fn invalidate(&self, event: &IndexEvent) {
+        let user = match event {
+            IndexEvent::Added(note)
+            | IndexEvent::Updated(note)
+            | IndexEvent::Deleted(note)
+            | IndexEvent::Restored(note)
+            | IndexEvent::Archived(note) => *note.user(),
+            IndexEvent::UserPurged(user) => *user,
+            // views don't change which notes match a query
+            IndexEvent::Viewed { .. } => return,
+        };
+        let mut inner = self.inner.lock().expect("query cache lock was poisoned");
+        inner.entries.retain(|(owner, _), _| owner != &user);
+        inner.order.retain(|(owner, _)| owner != &user);
+    }
+
+    pub fn metrics(&self) -> CacheMetrics {
+        let entries = self
+            .inner
+            .lock()
+            .expect("query cache lock was poisoned")
+            .entries
+            .len();
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        CacheMetrics {
+            entries,
+            hits,
+            misses,
+            hit_rate: if lookups == 0 {
+                0.0
+            } else {
+                hits as f64 / lookups as f64
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+
+    fn tagged(label: &str) -> NoteQuery {
+        NoteQuery::new(Some(label.to_string()), None, None, None).normalized()
+    }
+
+    #[test]
+    fn test_cache_hits_and_invalidation() {
+        let cache = QueryCache::default();
+        let note = example_note();
+        let owner = *note.user();
+        let other = Id(usize::from(owner) + 1);
+
+        assert_eq!(
+            cache.get_or_insert_with(owner, &tagged("a"), || vec![Id(1)]),
+            vec![Id(1)]
+        );
+        assert_eq!(
+            cache.get_or_insert_with(owner, &tagged("a"), || unreachable!()),
+            vec![Id(1)]
+        );
+        cache.get_or_insert_with(other, &tagged("a"), Vec::new);
+        let metrics = cache.metrics();
+        assert_eq!((metrics.entries, metrics.hits, metrics.misses), (2, 1, 2));
+        assert!((metrics.hit_rate - 1.0 / 3.0).abs() < f64::EPSILON);
+
+        cache.invalidate(&IndexEvent::Updated(note));
+        assert_eq!(cache.metrics().entries, 1);
+        assert_eq!(
+            cache.get_or_insert_with(owner, &tagged("a"), || vec![Id(2)]),
+            vec![Id(2)]
+        );
+    }
+
+    #[test]
+    fn test_capacity() {
+        let cache = QueryCache::new(2);
+        for label in ["a", "b", "c"] {
+            cache.get_or_insert_with(Id(1), &tagged(label), Vec::new);
+        }
+        assert_eq!(cache.metrics().entries, 2);
+        cache.get_or_insert_with(Id(1), &tagged("c"), || unreachable!());
+        cache.get_or_insert_with(Id(1), &tagged("a"), || vec![Id(1)]);
+
+        let disabled = QueryCache::new(0);
+        disabled.get_or_insert_with(Id(1), &tagged("a"), Vec::new);
+        assert_eq!(disabled.metrics().entries, 0);
+    }
+}
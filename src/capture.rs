@@ -0,0 +1,594 @@
+//! Bookmarks of web pages with their readable content, created by `POST /capture`
+//!
+//! The [`Capturer`] fetches the page server-side and [`extract`]s its title and the
+//! text of its paragraphs, headings, list items and quotes, similar to the reader mode
+//! of browsers: scripts, navigation, headers, footers and sidebars are dropped and
+//! only the `<article>` (or `<main>`) is read if the page has one.
+//!
+//! Pages on loopback and private networks are rejected, unless
+//! [`CaptureConfig::allow_private`] is set, and so are redirects to them. Host names are
+//! resolved by the [`PublicResolver`], so the addresses that are checked are the ones that
+//! are connected to.
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::{CONTENT_TYPE, LOCATION, USER_AGENT};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::config::CaptureConfig;
+use crate::models::note::Draft;
+
+/// The tag of all captured pages
+pub const CAPTURE_TAG: &str = "web";
+
+/// Maximum number of redirects that are followed
+const MAX_REDIRECTS: usize = 5;
+
+/// Elements whose content is never part of the readable content
+const SKIPPED: [&str; 11] = [
+    "script", "style", "noscript", "template", "svg", "nav", "header", "footer", "aside", "form",
+    "button",
+];
+
+/// The payload of `POST /capture`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CaptureRequest {
+    /// The HTTP(S) URL of the page
+    url: String,
+    /// Text that the user selected on the page, quoted at the top of the note
+    #[serde(default)]
+    selection: Option<String>,
+}
+
+impl CaptureRequest {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+/// The readable content of a web page
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Page {
+    pub title: Option<String>,
+    /// The blocks of text as Markdown, e.g. `## Heading` or `- item`
+    pub blocks: Vec<String>,
+}
+
+impl Page {
+    /// Builds the draft of a bookmark note of the page
+    ///
+    /// The title falls back to the URL, the selection is quoted before the content.
+    pub fn draft(&self, request: &CaptureRequest) -> Draft {
+        let mut blocks = vec![];
+        if let Some(selection) = request
+            .selection
+            .as_deref()
+            .map(str::trim)
+            .filter(|selection| !selection.is_empty())
+        {
+            let quote = selection
+                .lines()
+                .map(|line| format!("> {}", line.trim()).trim_end().to_string())
+                .collect::<Vec<String>>()
+                .join("\n");
+            blocks.push(quote);
+        }
+        blocks.extend(self.blocks.iter().cloned());
+        Draft::bookmark(
+            self.title.clone().unwrap_or_else(|| request.url.clone()),
+            blocks.join("\n\n"),
+            request.url.clone(),
+            vec![CAPTURE_TAG.to_string()],
+        )
+    }
+}
+
+/// Fetches web pages for `POST /capture`
+#[derive(Clone, Debug)]
+pub struct Capturer {
+    config: CaptureConfig,
+    http: reqwest::Client,
+}
+
+impl Capturer {
+    pub fn new(config: CaptureConfig) -> Self {
+        let mut http = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .timeout(config.timeout);
+        if !config.allow_private {
+            http = http.dns_resolver(Arc::new(PublicResolver));
+        }
+        let http = http.build().expect("invalid HTTP client configuration");
+        Self { config, http }
+    }
+
+    /// Fetches the page and extracts its content
+    ///
+    /// Redirects are followed manually, so that every target is checked. Pages that
+    /// are not HTML, e.g. PDFs, are captured without title and content.
+    pub async fn fetch(&self, url: &str) -> Result<Page, (StatusCode, String)> {
+        let mut url = match Url::parse(url) {
+            Ok(url) if ["http", "https"].contains(&url.scheme()) => url,
+            _ => {
+                return Err((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "url must be an HTTP(S) URL".to_string(),
+                ))
+            }
+        };
+        let mut redirects = 0;
+        let mut response = loop {
+            self.check_host(&url).await?;
+            let response = self
+                .http
+                .get(url.clone())
+                .header(USER_AGENT, "note-demo")
+                .send()
+                .await
+                .map_err(|err| bad_gateway(format!("Unable to fetch {url}: {err}")))?;
+            let Some(location) = response
+                .headers()
+                .get(LOCATION)
+                .filter(|_| response.status().is_redirection())
+            else {
+                break response;
+            };
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                return Err(bad_gateway(format!("Too many redirects for {url}")));
+            }
+            url = location
+                .to_str()
+                .ok()
+                .and_then(|location| url.join(location).ok())
+                .filter(|url| ["http", "https"].contains(&url.scheme()))
+                .ok_or_else(|| bad_gateway(format!("Invalid redirect of {url}")))?;
+        };
+        if !response.status().is_success() {
+            return Err(bad_gateway(format!(
+                "{url} responded with {}",
+                response.status()
+            )));
+        }
+        let html = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_none_or(|value| value.contains("html"));
+        let mut body = vec![];
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| bad_gateway(format!("Unable to fetch {url}: {err}")))?
+        {
+            if body.len() + chunk.len() > self.config.max_size {
+                return Err(bad_gateway(format!(
+                    "{url} is larger than {} bytes",
+                    self.config.max_size
+                )));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(if html {
+            extract(&String::from_utf8_lossy(&body))
+        } else {
+            Page::default()
+        })
+    }
+
+    /// Rejects hosts that resolve to loopback or private addresses, unless they are allowed
+    async fn check_host(&self, url: &Url) -> Result<(), (StatusCode, String)> {
+        if self.config.allow_private {
+            return Ok(());
+        }
+        check_public(url).await
+    }
+}
+
+/// Resolves host names to their public addresses only, for HTTP clients that must not reach
+/// loopback or private networks
+///
+/// [`check_public`] resolves the host of a URL on its own, so without this resolver a DNS
+/// server could answer with a public address for the check and a private one for the request
+/// (DNS rebinding). Hosts without public addresses fail to resolve.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .collect::<Vec<SocketAddr>>();
+            if addresses.is_empty() || !addresses.iter().all(|address| is_public(address.ip())) {
+                return Err(format!("{} is not on a public network", name.as_str()).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Rejects URLs whose host resolves to loopback or private addresses
+///
+/// Requests must still be sent with a client that uses the [`PublicResolver`], because the
+/// host may resolve to other addresses the next time.
+pub async fn check_public(url: &Url) -> Result<(), (StatusCode, String)> {
+    let host = url.host_str().unwrap_or_default();
+    let addresses = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => {
+            let port = url.port_or_known_default().unwrap_or(80);
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|err| bad_gateway(format!("Unable to resolve {host}: {err}")))?
+                .map(|address| address.ip())
+                .collect()
+        }
+    };
+    if addresses.is_empty() || !addresses.into_iter().all(is_public) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{url} is not on a public network"),
+        ));
+    }
+    Ok(())
+}
+
+fn bad_gateway(message: String) -> (StatusCode, String) {
+    (StatusCode::BAD_GATEWAY, message)
+}
+
+/// Returns `false` for loopback, private, link-local and other non-global addresses
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // shared address space of carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        || a == 0)
+}
+
+/// A tag of an HTML document, e.g. `<p class="x">` or `</p>`
+struct Tag<'a> {
+    name: String,
+    closing: bool,
+    source: &'a str,
+}
+
+/// Splits the HTML into text and tags, comments and doctypes are dropped
+fn tokens(html: &str) -> Vec<Result<Tag<'_>, &str>> {
+    let mut tokens = vec![];
+    let mut rest = html;
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            tokens.push(Err(rest));
+            break;
+        };
+        if start > 0 {
+            tokens.push(Err(&rest[..start]));
+        }
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment
+                .find("-->")
+                .map_or("", |end| &comment[end + "-->".len()..]);
+            continue;
+        }
+        let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+        let source = &rest[..end];
+        rest = &rest[end..];
+        let inner = source.trim_start_matches('<').trim_end_matches('>');
+        let closing = inner.starts_with('/');
+        let name = inner
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if name.starts_with('!') || name.starts_with('?') {
+            continue;
+        }
+        tokens.push(Ok(Tag {
+            name,
+            closing,
+            source,
+        }));
+    }
+    tokens
+}
+
+/// Returns the value of the attribute of the tag, e.g. `content` of `<meta content="x">`
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut offset = 0;
+    while let Some(found) = lower[offset..].find(name) {
+        let start = offset + found;
+        offset = start + name.len();
+        let preceded = lower[..start].ends_with(|c: char| c.is_whitespace());
+        let rest = lower[offset..].trim_start();
+        if !preceded || !rest.starts_with('=') {
+            continue;
+        }
+        let value = tag[tag.len() - rest.len() + 1..].trim_start();
+        let (quote, value) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => (Some(quote), &value[1..]),
+            _ => (None, value),
+        };
+        let end = value
+            .find(|c: char| match quote {
+                Some(quote) => c == quote,
+                None => c.is_whitespace() || c == '>' || c == '/',
+            })
+            .unwrap_or(value.len());
+        return Some(decode(&value[..end]));
+    }
+    None
+}
+
+/// Decodes the most common character references, e.g. `&amp;` or `&#39;`
+fn decode(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => entity
+                        .strip_prefix('#')
+                        .and_then(|dec| dec.parse().ok())
+                        .and_then(char::from_u32),
+                },
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                res.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                res.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// Collapses all whitespace to single spaces
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Returns the Markdown prefix of a block element, `None` for other elements
+fn block_prefix(name: &str) -> Option<&'static str> {
+    match name {
+        "p" | "pre" | "dd" | "figcaption" => Some(""),
+        "h1" | "h2" => Some("## "),
+        "h3" | "h4" | "h5" | "h6" => Some("### "),
+        "li" => Some("- "),
+        "blockquote" => Some("> "),
+        _ => None,
+    }
+}
+
+/// Extracts the title and the readable content of an HTML page, see the [module documentation](self)
+///
+/// The title is the `og:title`, otherwise the `<title>` of the page.
+pub fn extract(html: &str) -> Page {
+    let tokens = tokens(html);
+    let mut title = None;
+    let mut og_title = None;
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Ok(tag) if tag.name == "meta" && og_title.is_none() => {
+                let property = attribute(tag.source, "property").or(attribute(tag.source, "name"));
+                if property.as_deref() == Some("og:title") {
+                    og_title = attribute(tag.source, "content").map(|title| collapse(&title));
+                }
+            }
+            Ok(tag) if tag.name == "title" && !tag.closing && title.is_none() => {
+                if let Some(Err(text)) = tokens.get(index + 1) {
+                    title = Some(collapse(&decode(text)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // only read the article or main element if the page has one
+    let scope = ["article", "main"]
+        .iter()
+        .find_map(|name| {
+            let start = tokens
+                .iter()
+                .position(|token| matches!(token, Ok(tag) if tag.name == *name && !tag.closing))?;
+            let end = tokens
+                .iter()
+                .rposition(|token| matches!(token, Ok(tag) if tag.name == *name && tag.closing))
+                .filter(|end| *end > start)
+                .unwrap_or(tokens.len());
+            Some(&tokens[start..end])
+        })
+        .unwrap_or(&tokens);
+
+    let mut blocks = vec![];
+    let mut skipped: Vec<&str> = vec![];
+    let mut block: Option<(&str, String)> = None;
+    for token in scope {
+        match token {
+            Ok(tag) if SKIPPED.contains(&tag.name.as_str()) => {
+                if !tag.closing && !tag.source.ends_with("/>") {
+                    skipped.push(&tag.name);
+                } else if let Some(index) = skipped.iter().rposition(|name| *name == tag.name) {
+                    skipped.truncate(index);
+                }
+            }
+            _ if !skipped.is_empty() => {}
+            Ok(tag) => {
+                if let Some(prefix) = block_prefix(&tag.name) {
+                    if let Some((prefix, text)) = block.take() {
+                        push_block(&mut blocks, prefix, &text);
+                    }
+                    if !tag.closing {
+                        block = Some((prefix, String::new()));
+                    }
+                } else if tag.name == "br" {
+                    if let Some((_, text)) = &mut block {
+                        text.push(' ');
+                    }
+                }
+            }
+            Err(text) => {
+                if let Some((_, block)) = &mut block {
+                    block.push_str(&decode(text));
+                }
+            }
+        }
+    }
+    if let Some((prefix, text)) = block {
+        push_block(&mut blocks, prefix, &text);
+    }
+    Page {
+        title: og_title.or(title).filter(|title| !title.is_empty()),
+        blocks,
+    }
+}
+
+fn push_block(blocks: &mut Vec<String>, prefix: &str, text: &str) {
+    let text = collapse(text);
+    if !text.is_empty() {
+        blocks.push(format!("{prefix}{text}"));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let html = r#"<!DOCTYPE html>
+<html><head>
+<title>Fallback</title>
+<meta property="og:title" content="Fish &amp; Chips">
+<script>var p = "<p>not content</p>";</script>
+</head><body>
+<nav><ul><li>Home</li></ul></nav>
+<p>Outside of the article</p>
+<article>
+  <h1>Fish &amp; Chips</h1>
+  <!-- <p>commented</p> -->
+  <p>The <em>best</em>
+     recipe.</p>
+  <aside><p>Advertisement</p></aside>
+  <ul><li>Fish</li><li>Potatoes &#x1F954;</li></ul>
+  <p>  </p>
+</article>
+<footer><p>Imprint</p></footer>
+</body></html>"#;
+        assert_eq!(
+            extract(html),
+            Page {
+                title: Some("Fish & Chips".to_string()),
+                blocks: vec![
+                    "## Fish & Chips".to_string(),
+                    "The best recipe.".to_string(),
+                    "- Fish".to_string(),
+                    "- Potatoes 🥔".to_string(),
+                ],
+            }
+        );
+
+        let page = extract("<title>\n  Plain  </title><p>One<br>Two</p>");
+        assert_eq!(page.title.as_deref(), Some("Plain"));
+        assert_eq!(page.blocks, vec!["One Two".to_string()]);
+        assert_eq!(extract("just text"), Page::default());
+    }
+
+    #[test]
+    fn test_draft() {
+        let request = CaptureRequest {
+            url: "https://example.com/fish".to_string(),
+            selection: Some("Best\nrecipe ".to_string()),
+        };
+        let page = Page {
+            title: None,
+            blocks: vec!["Content".to_string()],
+        };
+        let draft = page.draft(&request);
+        assert_eq!(draft.title(), "https://example.com/fish");
+        assert_eq!(draft.body(), "> Best\n> recipe\n\nContent");
+        assert_eq!(draft.tags(), &vec!["web".to_string()]);
+        assert!(draft.validate().is_ok());
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.0.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_public_resolver() {
+        let resolve = |host: &str| PublicResolver.resolve(host.parse().unwrap());
+        let addresses = resolve("93.184.216.34").await.unwrap().collect::<Vec<_>>();
+        assert_eq!(addresses, vec!["93.184.216.34:0".parse().unwrap()]);
+        assert!(resolve("localhost").await.is_err());
+        assert!(resolve("127.0.0.1").await.is_err());
+        assert!(resolve("169.254.169.254").await.is_err());
+    }
+}
@@ -0,0 +1,940 @@
+//! Runtime configuration of the app
+//!
+//! All settings are read from environment variables with a `NOTE_` prefix,
+//! in the same way as `NOTE_VERBOSITY` configures the logging.
+//! Every setting has a default, so the app runs without any configuration.
+use std::collections::BTreeMap;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use axum::http::HeaderValue;
+
+use crate::models::lifecycle::Lifecycle;
+use crate::models::Id;
+
+/// Reads and parses the environment variable `key`, falls back to `default` if it is not set
+fn var_or<T: FromStr>(key: &str, default: T) -> Result<T>
+where
+    <T as FromStr>::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(key) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid value for {key}: {value}")),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Settings for compressing responses and decompressing request bodies
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CompressionConfig {
+    /// Enables `gzip`
+    pub gzip: bool,
+    /// Enables `br` (Brotli)
+    pub br: bool,
+    /// Responses smaller than this are sent uncompressed
+    pub min_size: u16,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            gzip: true,
+            br: true,
+            min_size: 1024,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Reads `NOTE_COMPRESSION` (comma-separated list of algorithms, `none` disables
+    /// compression) and `NOTE_COMPRESSION_MIN_SIZE` (in bytes)
+    fn from_env() -> Result<Self> {
+        let default = Self::default();
+        let mut config = Self {
+            min_size: var_or("NOTE_COMPRESSION_MIN_SIZE", default.min_size)?,
+            ..default
+        };
+        if let Ok(algorithms) = env::var("NOTE_COMPRESSION") {
+            config.set_algorithms(&algorithms)?;
+        }
+        Ok(config)
+    }
+
+    /// Enables exactly the algorithms listed in `algorithms`
+    fn set_algorithms(&mut self, algorithms: &str) -> Result<()> {
+        self.gzip = false;
+        self.br = false;
+        for algorithm in algorithms.split(',').map(str::trim) {
+            match algorithm.to_lowercase().as_str() {
+                "gzip" => self.gzip = true,
+                "br" | "brotli" => self.br = true,
+                "none" | "" => {}
+                other => return Err(anyhow!("Unknown compression algorithm: {other}")),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `Cache-Control` headers of responses, by the kind of route
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CacheControlConfig {
+    /// Content that never changes under its URL, e.g. attachment blobs
+    pub immutable: HeaderValue,
+    /// Pages that anonymous visitors can read, e.g. `/p/<slug>` and the sitemap
+    pub public: HeaderValue,
+    /// All other responses, which depend on the user sending the request
+    pub private: HeaderValue,
+}
+
+impl Default for CacheControlConfig {
+    fn default() -> Self {
+        Self {
+            immutable: HeaderValue::from_static("public, max-age=31536000, immutable"),
+            public: HeaderValue::from_static("public, max-age=300"),
+            private: HeaderValue::from_static("no-store"),
+        }
+    }
+}
+
+impl CacheControlConfig {
+    /// Reads `NOTE_CACHE_CONTROL_IMMUTABLE`, `NOTE_CACHE_CONTROL_PUBLIC` and `NOTE_CACHE_CONTROL_PRIVATE`
+    fn from_env() -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            immutable: var_or("NOTE_CACHE_CONTROL_IMMUTABLE", default.immutable)?,
+            public: var_or("NOTE_CACHE_CONTROL_PUBLIC", default.public)?,
+            private: var_or("NOTE_CACHE_CONTROL_PRIVATE", default.private)?,
+        })
+    }
+}
+
+/// How long requests may take before they are answered with `504 Gateway Timeout`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TimeoutConfig {
+    /// The timeout of all routes without an override
+    pub default: Duration,
+    /// Overrides by route, e.g. `/notes/import`
+    pub routes: BTreeMap<String, Duration>,
+}
+
+impl Default for TimeoutConfig {
+    /// Short timeouts for CRUD routes, long ones for exports, imports and backups
+    fn default() -> Self {
+        let long = Duration::from_secs(120);
+        Self {
+            default: Duration::from_secs(10),
+            routes: [
+                "/notes/import",
+                "/notes/import/restore",
+                "/notes/export",
+                "/export.csv",
+                "/note/:id/pdf",
+                "/admin/backup",
+                "/admin/restore",
+            ]
+            .into_iter()
+            .map(|route| (route.to_string(), long))
+            .collect(),
+        }
+    }
+}
+
+impl TimeoutConfig {
+    /// Reads `NOTE_REQUEST_TIMEOUT` (in seconds) and `NOTE_ROUTE_TIMEOUTS`, a comma-separated
+    /// list of `route=seconds` that is merged with the default overrides
+    fn from_env() -> Result<Self> {
+        let default = Self::default();
+        let mut config = Self {
+            default: Duration::from_secs(var_or(
+                "NOTE_REQUEST_TIMEOUT",
+                default.default.as_secs(),
+            )?),
+            ..default
+        };
+        if let Ok(routes) = env::var("NOTE_ROUTE_TIMEOUTS") {
+            config.set_routes(&routes)?;
+        }
+        Ok(config)
+    }
+
+    /// Adds or replaces the overrides of `routes`, e.g. `/notes/import=300,/export.csv=60`
+    fn set_routes(&mut self, routes: &str) -> Result<()> {
+        for entry in routes
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (route, seconds) = entry.split_once('=').ok_or_else(|| {
+                anyhow!("Invalid route timeout `{entry}`, expected route=seconds")
+            })?;
+            let seconds = seconds
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid seconds in route timeout `{entry}`"))?;
+            self.routes
+                .insert(route.trim().to_string(), Duration::from_secs(seconds));
+        }
+        Ok(())
+    }
+
+    /// Returns the timeout of the route, e.g. `/note/:id`
+    pub fn of(&self, route: &str) -> Duration {
+        self.routes.get(route).copied().unwrap_or(self.default)
+    }
+}
+
+/// The steps that normalize the text of notes and search queries, see [`crate::search`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SearchConfig {
+    /// Decomposes the text (NFKD), e.g. `ﬁ` into `fi` and `é` into `e` and a combining accent
+    pub nfkd: bool,
+    /// Removes combining marks, so that `café` matches `cafe`
+    pub diacritics: bool,
+    /// Folds the case, so that `Straße` matches `STRASSE`
+    pub case: bool,
+    /// Transliterates German umlauts before all other steps, so that `Müller` matches `Mueller`
+    /// instead of `Muller`
+    pub umlauts: bool,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            nfkd: true,
+            diacritics: true,
+            case: true,
+            umlauts: false,
+        }
+    }
+}
+
+impl SearchConfig {
+    /// Reads `NOTE_SEARCH_NORMALIZATION` (comma-separated list of steps, `none` disables
+    /// the normalization)
+    fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+        if let Ok(steps) = env::var("NOTE_SEARCH_NORMALIZATION") {
+            config.set_steps(&steps)?;
+        }
+        Ok(config)
+    }
+
+    /// Enables exactly the steps listed in `steps`
+    fn set_steps(&mut self, steps: &str) -> Result<()> {
+        *self = Self {
+            nfkd: false,
+            diacritics: false,
+            case: false,
+            umlauts: false,
+        };
+        for step in steps.split(',').map(str::trim) {
+            match step.to_lowercase().as_str() {
+                "nfkd" => self.nfkd = true,
+                "diacritics" => self.diacritics = true,
+                "case" => self.case = true,
+                "umlauts" => self.umlauts = true,
+                "none" | "" => {}
+                other => return Err(anyhow!("Unknown normalization step: {other}")),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What happens to public notes that look like spam, see [`crate::moderation`]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ModerationAction {
+    /// Notes are not checked
+    Off,
+    /// Notes stay public and are added to the moderation queue
+    #[default]
+    Flag,
+    /// Notes are kept private until an admin approves them
+    Quarantine,
+}
+
+/// Settings of the [`Supervisor`](crate::persistence::supervisor::Supervisor) of the storage backend
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HealthConfig {
+    /// Time between two health checks, also the delay before the first reconnect attempt
+    pub interval: Duration,
+    /// The maximum delay between two reconnect attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl HealthConfig {
+    /// Reads `NOTE_HEALTH_CHECK_INTERVAL` and `NOTE_RECONNECT_MAX_BACKOFF`
+    fn from_env() -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            interval: Duration::from_secs(var_or(
+                "NOTE_HEALTH_CHECK_INTERVAL",
+                default.interval.as_secs(),
+            )?),
+            max_backoff: Duration::from_secs(var_or(
+                "NOTE_RECONNECT_MAX_BACKOFF",
+                default.max_backoff.as_secs(),
+            )?),
+        })
+    }
+}
+
+/// Settings of `POST /capture`, which fetches web pages, see [`crate::capture`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CaptureConfig {
+    /// How long fetching a page may take, including redirects
+    pub timeout: Duration,
+    /// Maximum size of a page in bytes
+    pub max_size: usize,
+    /// Allow pages on loopback and private networks, which are rejected by default
+    /// so that users can't probe internal services
+    pub allow_private: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_size: 2 * 1024 * 1024,
+            allow_private: false,
+        }
+    }
+}
+
+impl CaptureConfig {
+    /// Reads `NOTE_CAPTURE_TIMEOUT`, `NOTE_CAPTURE_MAX_SIZE` and `NOTE_CAPTURE_ALLOW_PRIVATE`
+    fn from_env() -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            timeout: Duration::from_secs(var_or(
+                "NOTE_CAPTURE_TIMEOUT",
+                default.timeout.as_secs(),
+            )?),
+            max_size: var_or("NOTE_CAPTURE_MAX_SIZE", default.max_size)?,
+            allow_private: var_or("NOTE_CAPTURE_ALLOW_PRIVATE", default.allow_private)?,
+        })
+    }
+}
+
+/// Settings for the spam heuristics of public notes
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ModerationConfig {
+    pub action: ModerationAction,
+    /// Maximum number of links in title and body
+    pub max_links: usize,
+    /// Words that are not allowed in public notes, compared case-insensitive
+    pub banned_words: Vec<String>,
+    /// Maximum number of notes a user creates within the `burst_window`
+    pub burst_notes: usize,
+    pub burst_window: Duration,
+    /// Number of tags that every note may have, regardless of its body
+    pub base_tags: usize,
+    /// One more tag is allowed per this number of bytes of the body
+    pub bytes_per_tag: usize,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            action: ModerationAction::default(),
+            max_links: 10,
+            banned_words: vec![],
+            burst_notes: 10,
+            burst_window: Duration::from_secs(60),
+            base_tags: 5,
+            bytes_per_tag: 100,
+        }
+    }
+}
+
+impl ModerationConfig {
+    /// Reads `NOTE_MODERATION` (`off`, `flag` or `quarantine`) and all `NOTE_MODERATION_*` variables
+    fn from_env() -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            action: match env::var("NOTE_MODERATION") {
+                Ok(action) => match action.trim().to_lowercase().as_str() {
+                    "off" => ModerationAction::Off,
+                    "flag" => ModerationAction::Flag,
+                    "quarantine" => ModerationAction::Quarantine,
+                    other => return Err(anyhow!("Invalid value for NOTE_MODERATION: {other}")),
+                },
+                Err(_) => default.action,
+            },
+            max_links: var_or("NOTE_MODERATION_MAX_LINKS", default.max_links)?,
+            banned_words: match env::var("NOTE_MODERATION_BANNED_WORDS") {
+                Ok(words) => words
+                    .split(',')
+                    .map(|word| word.trim().to_lowercase())
+                    .filter(|word| !word.is_empty())
+                    .collect(),
+                Err(_) => default.banned_words,
+            },
+            burst_notes: var_or("NOTE_MODERATION_BURST_NOTES", default.burst_notes)?,
+            burst_window: Duration::from_secs(var_or(
+                "NOTE_MODERATION_BURST_WINDOW",
+                default.burst_window.as_secs(),
+            )?),
+            base_tags: var_or("NOTE_MODERATION_BASE_TAGS", default.base_tags)?,
+            bytes_per_tag: var_or("NOTE_MODERATION_BYTES_PER_TAG", default.bytes_per_tag)?,
+        })
+    }
+}
+
+/// What happens to new notes when the maximum number of notes is stored
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum NoteLimitMode {
+    /// New notes are rejected
+    #[default]
+    Reject,
+    /// Deleted notes and then the least recently used public notes are removed permanently
+    /// to make room
+    Evict,
+}
+
+/// A cap on the number of notes in the [`InMemoryStorage`](crate::persistence::memory::InMemoryStorage),
+/// e.g. for public demo deployments
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NoteLimit {
+    /// Maximum number of stored notes, including deleted ones
+    pub max_notes: usize,
+    pub mode: NoteLimitMode,
+}
+
+impl NoteLimit {
+    /// Reads `NOTE_MAX_NOTES` and `NOTE_MAX_NOTES_MODE` (`reject` or `evict`),
+    /// returns `None` if no maximum is set
+    fn from_env() -> Result<Option<Self>> {
+        let Ok(max_notes) = env::var("NOTE_MAX_NOTES") else {
+            return Ok(None);
+        };
+        let max_notes = max_notes
+            .parse()
+            .with_context(|| format!("Invalid value for NOTE_MAX_NOTES: {max_notes}"))?;
+        let mode = match env::var("NOTE_MAX_NOTES_MODE") {
+            Ok(mode) => match mode.trim().to_lowercase().as_str() {
+                "reject" => NoteLimitMode::Reject,
+                "evict" => NoteLimitMode::Evict,
+                other => return Err(anyhow!("Invalid value for NOTE_MAX_NOTES_MODE: {other}")),
+            },
+            Err(_) => NoteLimitMode::default(),
+        };
+        Ok(Some(Self { max_notes, mode }))
+    }
+}
+
+/// Which accesses to shared notes are recorded for their owners, see `GET /note/:id/access-log`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AccessLogMode {
+    /// Nothing is recorded
+    Off,
+    /// Only the time of each access is recorded, not who opened the note
+    #[default]
+    Anonymized,
+    /// The time and the user of each access are recorded
+    Full,
+}
+
+impl AccessLogMode {
+    /// Reads `NOTE_ACCESS_LOG` (`off`, `anonymized` or `full`)
+    fn from_env() -> Result<Self> {
+        match env::var("NOTE_ACCESS_LOG") {
+            Ok(mode) => match mode.trim().to_lowercase().as_str() {
+                "off" => Ok(Self::Off),
+                "anonymized" => Ok(Self::Anonymized),
+                "full" => Ok(Self::Full),
+                other => Err(anyhow!("Invalid value for NOTE_ACCESS_LOG: {other}")),
+            },
+            Err(_) => Ok(Self::default()),
+        }
+    }
+}
+
+/// What happens to notes once their `expires_at` has passed, see [`ExpireNotes`](crate::tasks::ExpireNotes)
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ExpiryAction {
+    /// The note is removed permanently with its comments and revisions
+    #[default]
+    Delete,
+    /// The note is archived, so that its owner can still find it
+    Archive,
+}
+
+impl ExpiryAction {
+    /// Reads `NOTE_EXPIRY_ACTION` (`delete` or `archive`)
+    fn from_env() -> Result<Self> {
+        match env::var("NOTE_EXPIRY_ACTION") {
+            Ok(action) => match action.trim().to_lowercase().as_str() {
+                "delete" => Ok(Self::Delete),
+                "archive" => Ok(Self::Archive),
+                other => Err(anyhow!("Invalid value for NOTE_EXPIRY_ACTION: {other}")),
+            },
+            Err(_) => Ok(Self::default()),
+        }
+    }
+}
+
+/// Settings for serving the app via HTTPS
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TlsConfig {
+    /// Path to the PEM encoded certificate (chain)
+    pub cert: PathBuf,
+    /// Path to the PEM encoded private key
+    pub key: PathBuf,
+    /// If set, a plain HTTP server listens on this address and redirects all requests to HTTPS
+    pub redirect_address: Option<SocketAddr>,
+}
+
+impl TlsConfig {
+    /// Reads `NOTE_TLS_CERT`, `NOTE_TLS_KEY` and `NOTE_TLS_REDIRECT_ADDRESS`
+    ///
+    /// TLS is disabled unless both certificate and key are configured.
+    fn from_env() -> Result<Option<Self>> {
+        match (env::var("NOTE_TLS_CERT"), env::var("NOTE_TLS_KEY")) {
+            (Ok(cert), Ok(key)) => Ok(Some(Self {
+                cert: cert.into(),
+                key: key.into(),
+                redirect_address: match env::var("NOTE_TLS_REDIRECT_ADDRESS") {
+                    Ok(address) => Some(address.trim().parse().with_context(|| {
+                        format!("Invalid value for NOTE_TLS_REDIRECT_ADDRESS: {address}")
+                    })?),
+                    Err(_) => None,
+                },
+            })),
+            (Err(_), Err(_)) => Ok(None),
+            _ => Err(anyhow!(
+                "NOTE_TLS_CERT and NOTE_TLS_KEY must be configured together"
+            )),
+        }
+    }
+}
+
+/// Parses a comma-separated list of user Ids
+fn parse_ids(value: &str) -> Result<Vec<Id>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| Ok(Id(id.parse::<usize>()?)))
+        .collect()
+}
+
+/// Reads the required environment variable `key`
+fn required(key: &str) -> Result<String> {
+    env::var(key).with_context(|| format!("{key} must be configured"))
+}
+
+/// Settings for the login via an external OpenID Connect / OAuth2 identity provider
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OidcConfig {
+    /// Identifies the identity provider, users are linked to the provider and their subject
+    pub issuer: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// The URL of the callback endpoint of this app, as registered at the identity provider
+    pub redirect_url: String,
+    /// Space-separated list of scopes
+    pub scopes: String,
+    /// The userinfo claim with the unique Id of the user, e.g. `sub` or `id` for GitHub
+    pub subject_claim: String,
+    /// The userinfo claim used as name of newly created users
+    pub name_claim: String,
+}
+
+impl OidcConfig {
+    /// Reads all `NOTE_OIDC_*` variables. The login is disabled unless
+    /// `NOTE_OIDC_CLIENT_ID` is configured.
+    fn from_env() -> Result<Option<Self>> {
+        let Ok(client_id) = env::var("NOTE_OIDC_CLIENT_ID") else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            issuer: required("NOTE_OIDC_ISSUER")?,
+            authorize_url: required("NOTE_OIDC_AUTHORIZE_URL")?,
+            token_url: required("NOTE_OIDC_TOKEN_URL")?,
+            userinfo_url: required("NOTE_OIDC_USERINFO_URL")?,
+            client_id,
+            client_secret: required("NOTE_OIDC_CLIENT_SECRET")?,
+            redirect_url: required("NOTE_OIDC_REDIRECT_URL")?,
+            scopes: var_or("NOTE_OIDC_SCOPES", "openid profile".to_string())?,
+            subject_claim: var_or("NOTE_OIDC_SUBJECT_CLAIM", "sub".to_string())?,
+            name_claim: var_or("NOTE_OIDC_NAME_CLAIM", "name".to_string())?,
+        }))
+    }
+}
+
+/// Settings for publishing public notes via ActivityPub
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ActivityPubConfig {
+    /// The public URL of the app, e.g. `https://notes.example.com`
+    pub base_url: String,
+    /// Path to the PEM encoded RSA private key that signs all deliveries
+    pub key: PathBuf,
+}
+
+impl ActivityPubConfig {
+    /// Reads `NOTE_PUBLIC_URL` and `NOTE_ACTIVITYPUB_KEY`
+    ///
+    /// Federation is disabled unless `NOTE_PUBLIC_URL` is configured.
+    fn from_env() -> Result<Option<Self>> {
+        let Ok(base_url) = env::var("NOTE_PUBLIC_URL") else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            base_url: base_url.trim().trim_end_matches('/').to_string(),
+            key: required("NOTE_ACTIVITYPUB_KEY")?.into(),
+        }))
+    }
+}
+
+/// Settings of the SMTP server that sends emails, e.g. digests
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    /// Upgrades the connection with `STARTTLS`, only disable it for local relays
+    pub starttls: bool,
+    /// Credentials for the server, emails are sent without authentication if not set
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The sender of all emails, e.g. `Notes <notes@example.com>`
+    pub from: String,
+}
+
+impl SmtpConfig {
+    /// Reads all `NOTE_SMTP_*` variables. Emails are only logged unless
+    /// `NOTE_SMTP_HOST` is configured.
+    fn from_env() -> Result<Option<Self>> {
+        let Ok(host) = env::var("NOTE_SMTP_HOST") else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            host,
+            port: var_or("NOTE_SMTP_PORT", 587)?,
+            starttls: var_or("NOTE_SMTP_STARTTLS", true)?,
+            username: env::var("NOTE_SMTP_USERNAME").ok(),
+            password: env::var("NOTE_SMTP_PASSWORD").ok(),
+            from: required("NOTE_SMTP_FROM")?,
+        }))
+    }
+}
+
+/// Settings of the Redis pub/sub channel that connects the instances of a deployment
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EventBusConfig {
+    /// The URL of the Redis server, e.g. `redis://redis.internal:6379/`
+    pub url: String,
+    /// All instances of a deployment must use the same channel
+    pub channel: String,
+}
+
+impl EventBusConfig {
+    /// Reads `NOTE_EVENT_BUS_URL` and `NOTE_EVENT_BUS_CHANNEL`. Events stay in the
+    /// process unless `NOTE_EVENT_BUS_URL` is configured.
+    fn from_env() -> Result<Option<Self>> {
+        let Ok(url) = env::var("NOTE_EVENT_BUS_URL") else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            url,
+            channel: var_or("NOTE_EVENT_BUS_CHANNEL", "note-demo:events".to_string())?,
+        }))
+    }
+}
+
+/// The complete configuration of the app
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// The address the server listens on
+    pub address: SocketAddr,
+    /// Serve HTTPS instead of HTTP if set
+    pub tls: Option<TlsConfig>,
+    pub compression: CompressionConfig,
+    pub cache_control: CacheControlConfig,
+    /// Number of characters of the body that list endpoints include as preview
+    pub preview_length: usize,
+    /// Maximum length of note titles in characters (grapheme clusters)
+    pub max_title_length: usize,
+    /// Moves a leading emoji of a note title into the `icon` field
+    pub title_icons: bool,
+    /// Maximum size of (decompressed) request bodies in bytes
+    pub max_body_size: usize,
+    pub timeouts: TimeoutConfig,
+    /// Time between scheduling an account deletion and purging all data of the user
+    pub deletion_grace_period: Duration,
+    /// Secret to sign session tokens. A random secret is used if not set, which
+    /// invalidates all sessions on restart.
+    pub session_secret: Option<String>,
+    /// How long session tokens are valid
+    pub session_ttl: Duration,
+    /// How long the sessions that admins get to impersonate a user are valid
+    pub impersonation_ttl: Duration,
+    /// Enables the login via an external identity provider if set
+    pub oidc: Option<OidcConfig>,
+    /// Identifies users by the Id in the `x-user-id` header, which must only be enabled
+    /// behind an authentication proxy that sets it. Ignored with `oidc`, see
+    /// [`Config::trusts_user_header`]
+    pub trust_user_header: bool,
+    /// How long responses are stored for `Idempotency-Key`s
+    pub idempotency_window: Duration,
+    /// How long the deletion of a note can be undone
+    pub undo_window: Duration,
+    /// The optional transitions between the states of notes
+    pub lifecycle: Lifecycle,
+    pub expiry_action: ExpiryAction,
+    /// How long a confirmation to empty the trash is valid
+    pub trash_confirmation_ttl: Duration,
+    /// Count the requests, mutations and transferred bytes of every user per day
+    pub track_usage: bool,
+    /// How long autosaved working copies of notes are kept after their last change
+    pub draft_ttl: Duration,
+    /// Keep tags that are not used by any active note anymore, e.g. for history
+    pub keep_tags: bool,
+    /// Time between a change of tags and the removal of unused tags, so that
+    /// many changes are collected at once
+    pub tag_gc_delay: Duration,
+    /// Time between the integrity checks of all notes, which only log the problems they
+    /// find, `0` disables them
+    pub integrity_interval: Duration,
+    /// Number of notes that `GET /notes/recent` keeps per user
+    pub recent_notes: usize,
+    /// Number of notes that are read at once while the indexes are built at startup
+    pub warm_up_batch: usize,
+    pub access_log: AccessLogMode,
+    /// Caps the number of stored notes if set
+    pub note_limit: Option<NoteLimit>,
+    pub health: HealthConfig,
+    /// Users with access to the `/admin` endpoints
+    pub admin_users: Vec<Id>,
+    pub moderation: ModerationConfig,
+    pub search: SearchConfig,
+    pub capture: CaptureConfig,
+    /// Directory that `POST /admin/publish-site` writes the static site to, publishing
+    /// is disabled if not set
+    pub publish_dir: Option<PathBuf>,
+    /// Enables the federation of public notes if set
+    pub activitypub: Option<ActivityPubConfig>,
+    /// Sends emails via SMTP if set, otherwise they are only logged
+    pub smtp: Option<SmtpConfig>,
+    /// Shares the events with other instances via Redis if set, see [`crate::bus`]
+    pub event_bus: Option<EventBusConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            tls: None,
+            compression: CompressionConfig::default(),
+            cache_control: CacheControlConfig::default(),
+            preview_length: 200,
+            max_title_length: 200,
+            title_icons: false,
+            max_body_size: 1024 * 1024,
+            timeouts: TimeoutConfig::default(),
+            deletion_grace_period: Duration::from_secs(7 * 24 * 60 * 60),
+            session_secret: None,
+            session_ttl: Duration::from_secs(24 * 60 * 60),
+            impersonation_ttl: Duration::from_secs(15 * 60),
+            oidc: None,
+            trust_user_header: false,
+            idempotency_window: Duration::from_secs(24 * 60 * 60),
+            undo_window: Duration::from_secs(30),
+            lifecycle: Lifecycle::default(),
+            expiry_action: ExpiryAction::default(),
+            trash_confirmation_ttl: Duration::from_secs(300),
+            track_usage: true,
+            draft_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            keep_tags: false,
+            tag_gc_delay: Duration::from_secs(60),
+            integrity_interval: Duration::from_secs(24 * 60 * 60),
+            recent_notes: 20,
+            warm_up_batch: 1000,
+            access_log: AccessLogMode::default(),
+            note_limit: None,
+            health: HealthConfig::default(),
+            admin_users: vec![],
+            moderation: ModerationConfig::default(),
+            search: SearchConfig::default(),
+            capture: CaptureConfig::default(),
+            publish_dir: None,
+            activitypub: None,
+            smtp: None,
+            event_bus: None,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the [`Config`] from the `NOTE_*` environment variables
+    pub fn from_env() -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            address: var_or("NOTE_ADDRESS", default.address)?,
+            tls: TlsConfig::from_env()?,
+            compression: CompressionConfig::from_env()?,
+            cache_control: CacheControlConfig::from_env()?,
+            preview_length: var_or("NOTE_PREVIEW_LENGTH", default.preview_length)?,
+            max_title_length: var_or("NOTE_MAX_TITLE_LENGTH", default.max_title_length)?,
+            title_icons: var_or("NOTE_TITLE_ICONS", default.title_icons)?,
+            max_body_size: var_or("NOTE_MAX_BODY_SIZE", default.max_body_size)?,
+            timeouts: TimeoutConfig::from_env()?,
+            deletion_grace_period: Duration::from_secs(var_or(
+                "NOTE_DELETION_GRACE_PERIOD",
+                default.deletion_grace_period.as_secs(),
+            )?),
+            session_secret: env::var("NOTE_SESSION_SECRET").ok(),
+            session_ttl: Duration::from_secs(var_or(
+                "NOTE_SESSION_TTL",
+                default.session_ttl.as_secs(),
+            )?),
+            impersonation_ttl: Duration::from_secs(var_or(
+                "NOTE_IMPERSONATION_TTL",
+                default.impersonation_ttl.as_secs(),
+            )?),
+            oidc: OidcConfig::from_env()?,
+            trust_user_header: var_or("NOTE_TRUST_USER_HEADER", default.trust_user_header)?,
+            idempotency_window: Duration::from_secs(var_or(
+                "NOTE_IDEMPOTENCY_WINDOW",
+                default.idempotency_window.as_secs(),
+            )?),
+            undo_window: Duration::from_secs(var_or(
+                "NOTE_UNDO_WINDOW",
+                default.undo_window.as_secs(),
+            )?),
+            lifecycle: Lifecycle {
+                delete_archived: var_or("NOTE_DELETE_ARCHIVED", default.lifecycle.delete_archived)?,
+            },
+            expiry_action: ExpiryAction::from_env()?,
+            trash_confirmation_ttl: Duration::from_secs(var_or(
+                "NOTE_TRASH_CONFIRMATION_TTL",
+                default.trash_confirmation_ttl.as_secs(),
+            )?),
+            track_usage: var_or("NOTE_TRACK_USAGE", default.track_usage)?,
+            draft_ttl: Duration::from_secs(var_or("NOTE_DRAFT_TTL", default.draft_ttl.as_secs())?),
+            keep_tags: var_or("NOTE_KEEP_TAGS", default.keep_tags)?,
+            tag_gc_delay: Duration::from_secs(var_or(
+                "NOTE_TAG_GC_DELAY",
+                default.tag_gc_delay.as_secs(),
+            )?),
+            integrity_interval: Duration::from_secs(var_or(
+                "NOTE_INTEGRITY_INTERVAL",
+                default.integrity_interval.as_secs(),
+            )?),
+            recent_notes: var_or("NOTE_RECENT_NOTES", default.recent_notes)?,
+            warm_up_batch: var_or("NOTE_WARM_UP_BATCH", default.warm_up_batch)?,
+            access_log: AccessLogMode::from_env()?,
+            note_limit: NoteLimit::from_env()?,
+            health: HealthConfig::from_env()?,
+            admin_users: match env::var("NOTE_ADMIN_USERS") {
+                Ok(users) => parse_ids(&users)
+                    .with_context(|| format!("Invalid value for NOTE_ADMIN_USERS: {users}"))?,
+                Err(_) => default.admin_users,
+            },
+            moderation: ModerationConfig::from_env()?,
+            search: SearchConfig::from_env()?,
+            capture: CaptureConfig::from_env()?,
+            publish_dir: env::var("NOTE_PUBLISH_DIR").ok().map(PathBuf::from),
+            activitypub: ActivityPubConfig::from_env()?,
+            smtp: SmtpConfig::from_env()?,
+            event_bus: EventBusConfig::from_env()?,
+        })
+    }
+
+    /// Returns `true` if the `x-user-id` header identifies users
+    ///
+    /// Never with a login via an external identity provider, where anyone could send the
+    /// header to act as another user.
+    pub fn trusts_user_header(&self) -> bool {
+        self.trust_user_header && self.oidc.is_none()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compression_algorithms() {
+        let mut config = CompressionConfig::default();
+        assert!(config.gzip);
+        assert!(config.br);
+
+        config.set_algorithms("gzip").unwrap();
+        assert!(config.gzip);
+        assert!(!config.br);
+
+        config.set_algorithms("Brotli, gzip").unwrap();
+        assert!(config.gzip);
+        assert!(config.br);
+
+        config.set_algorithms("none").unwrap();
+        assert!(!config.gzip);
+        assert!(!config.br);
+
+        assert!(config.set_algorithms("zstd").is_err());
+    }
+
+    #[test]
+    fn test_search_steps() {
+        let mut config = SearchConfig::default();
+        config.set_steps("Case, umlauts").unwrap();
+        assert_eq!(
+            config,
+            SearchConfig {
+                nfkd: false,
+                diacritics: false,
+                case: true,
+                umlauts: true,
+            }
+        );
+
+        config.set_steps("none").unwrap();
+        assert!(!config.case);
+
+        assert!(config.set_steps("nfc").is_err());
+    }
+
+    #[test]
+    fn test_route_timeouts() {
+        let mut config = TimeoutConfig::default();
+        assert_eq!(config.of("/note/:id"), Duration::from_secs(10));
+        assert_eq!(config.of("/notes/import"), Duration::from_secs(120));
+
+        config
+            .set_routes("/notes/import=300, /note/:id=1,")
+            .unwrap();
+        assert_eq!(config.of("/notes/import"), Duration::from_secs(300));
+        assert_eq!(config.of("/note/:id"), Duration::from_secs(1));
+        assert_eq!(config.of("/export.csv"), Duration::from_secs(120));
+
+        assert!(config.set_routes("/notes").is_err());
+        assert!(config.set_routes("/notes=soon").is_err());
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        assert_eq!(parse_ids("1, 2,").unwrap(), vec![Id(1), Id(2)]);
+        assert!(parse_ids("").unwrap().is_empty());
+        assert!(parse_ids("1,foo").is_err());
+    }
+}
@@ -0,0 +1,96 @@
+//! Exports the metadata of [`Note`]s as CSV, e.g. to analyze them in a spreadsheet
+//!
+//! Every note is encoded as a separate row, so that a response can be streamed
+//! row by row instead of building the complete document in memory.
+use csv::WriterBuilder;
+
+use crate::models::note::Note;
+
+/// The columns of the export
+pub const HEADER: [&str; 7] = [
+    "id",
+    "title",
+    "tags",
+    "visibility",
+    "created",
+    "updated",
+    "word_count",
+];
+
+/// Separates the labels in the `tags` column
+const TAG_SEPARATOR: &str = ";";
+
+fn encode(fields: &[String]) -> Vec<u8> {
+    let mut writer = WriterBuilder::new().from_writer(vec![]);
+    writer
+        .write_record(fields)
+        .expect("writing to a Vec does not fail");
+    writer.into_inner().expect("writing to a Vec does not fail")
+}
+
+/// Returns the header row
+pub fn header() -> Vec<u8> {
+    encode(&HEADER.map(String::from))
+}
+
+/// Returns the row of the note, including the line break
+pub fn row(note: &Note) -> Vec<u8> {
+    let mut tags = note
+        .tags()
+        .map(|tag| tag.label().to_string())
+        .collect::<Vec<String>>();
+    tags.sort();
+    encode(&[
+        usize::from(note.id()).to_string(),
+        note.title().to_string(),
+        tags.join(TAG_SEPARATOR),
+        format!("{:?}", note.visibility()),
+        note.created().to_rfc3339(),
+        note.updated().to_rfc3339(),
+        note.body().text().split_whitespace().count().to_string(),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+    use crate::models::note::{Draft, Tags};
+    use crate::models::{Id, Visibility};
+
+    #[test]
+    fn test_header() {
+        assert_eq!(
+            header(),
+            b"id,title,tags,visibility,created,updated,word_count\n"
+        );
+    }
+
+    #[test]
+    fn test_row() {
+        let note = example_note();
+        let row = String::from_utf8(row(&note)).unwrap();
+        assert_eq!(
+            row,
+            format!(
+                "1,Test-Title,tag1;tag2;tag3,Public,{},{},1\n",
+                note.created().to_rfc3339(),
+                note.updated().to_rfc3339()
+            )
+        );
+    }
+
+    #[test]
+    fn test_row_quoting() {
+        let draft = Draft::new(
+            "Apples, \"Pears\"".to_string(),
+            "Buy some\nfruit".to_string(),
+            vec![],
+            Visibility::Private,
+        );
+        let note = Note::new(draft, Id(2), Id(0), Tags::default());
+        let row = String::from_utf8(row(&note)).unwrap();
+        assert!(row.starts_with("2,\"Apples, \"\"Pears\"\"\",,Private,"));
+        assert!(row.ends_with(",3\n"));
+    }
+}
@@ -0,0 +1,164 @@
+//! Calendar dates in the timezone of a user
+//!
+//! All timestamps are stored in UTC. Whenever they are grouped by days, e.g. in reviews, daily
+//! statistics, digests or the groups of note lists, the days are the ones in the `timezone` of
+//! the user's [`Preferences`](crate::models::preferences::Preferences).
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// Returns the time in the timezone
+pub fn local(time: &DateTime<Utc>, timezone: &Tz) -> DateTime<Tz> {
+    time.with_timezone(timezone)
+}
+
+/// Returns the day of the time in the timezone
+pub fn local_date(time: &DateTime<Utc>, timezone: &Tz) -> NaiveDate {
+    local(time, timezone).date_naive()
+}
+
+/// Returns the current day in the timezone
+pub fn today(timezone: &Tz) -> NaiveDate {
+    local_date(&Utc::now(), timezone)
+}
+
+/// Returns the first moment of the day in the timezone
+///
+/// Days that start with a daylight saving time transition begin at the first valid time.
+pub fn start_of_day(date: NaiveDate, timezone: &Tz) -> DateTime<Utc> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    (0..8)
+        .map(|quarter| midnight + TimeDelta::minutes(15 * quarter))
+        .find_map(|time| timezone.from_local_datetime(&time).earliest())
+        .map_or_else(|| midnight.and_utc(), |start| start.with_timezone(&Utc))
+}
+
+/// Returns the Monday of the week of the day, `None` if it is before [`NaiveDate::MIN`]
+pub fn week_start(date: NaiveDate) -> Option<NaiveDate> {
+    date.checked_sub_days(Days::new(date.weekday().num_days_from_monday().into()))
+}
+
+/// Returns the first day of the month of the day
+pub fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).expect("every month has a first day")
+}
+
+/// How long ago something happened, in calendar days of the user, e.g. to group lists
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recency {
+    /// Today or in the future
+    Today,
+    Yesterday,
+    /// Earlier in the week (Monday to Sunday) of today
+    ThisWeek,
+    /// Earlier in the month of today
+    ThisMonth,
+    Earlier,
+}
+
+impl Recency {
+    /// Returns the group of `time` relative to `now`, both seen in the timezone
+    pub fn of(time: &DateTime<Utc>, now: &DateTime<Utc>, timezone: &Tz) -> Self {
+        let date = local_date(time, timezone);
+        let today = local_date(now, timezone);
+        if date >= today {
+            Self::Today
+        } else if today.pred_opt() == Some(date) {
+            Self::Yesterday
+        } else if week_start(today).is_some_and(|start| date >= start) {
+            Self::ThisWeek
+        } else if date >= month_start(today) {
+            Self::ThisMonth
+        } else {
+            Self::Earlier
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn time(text: &str) -> DateTime<Utc> {
+        text.parse().unwrap()
+    }
+
+    fn day(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_local_date() {
+        let time = time("2024-05-15T23:30:00Z");
+        assert_eq!(local_date(&time, &Tz::UTC), day(5, 15));
+        assert_eq!(local_date(&time, &Tz::Europe__Berlin), day(5, 16));
+        assert_eq!(local_date(&time, &Tz::America__Los_Angeles), day(5, 15));
+        assert_eq!(
+            local(&time, &Tz::Asia__Kolkata).to_rfc3339(),
+            "2024-05-16T05:00:00+05:30"
+        );
+    }
+
+    #[test]
+    fn test_start_of_day() {
+        assert_eq!(
+            start_of_day(day(5, 16), &Tz::Europe__Berlin),
+            time("2024-05-15T22:00:00Z")
+        );
+        assert_eq!(
+            start_of_day(day(5, 16), &Tz::UTC),
+            time("2024-05-16T00:00:00Z")
+        );
+        // the clocks were set from midnight to 1:00 at the start of daylight saving time
+        assert_eq!(
+            start_of_day(
+                NaiveDate::from_ymd_opt(2018, 11, 4).unwrap(),
+                &Tz::America__Sao_Paulo
+            ),
+            time("2018-11-04T03:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_bounds() {
+        // a Wednesday
+        assert_eq!(week_start(day(5, 15)), Some(day(5, 13)));
+        assert_eq!(week_start(day(5, 13)), Some(day(5, 13)));
+        assert_eq!(week_start(day(5, 19)), Some(day(5, 13)));
+        assert_eq!(week_start(NaiveDate::MIN), None);
+        assert_eq!(month_start(day(5, 15)), day(5, 1));
+    }
+
+    #[test]
+    fn test_recency() {
+        // a Thursday, 1:30 in Berlin
+        let now = time("2024-05-16T23:30:00Z");
+        let berlin = Tz::Europe__Berlin;
+        for (text, utc, local) in [
+            ("2024-05-17T00:00:00Z", Recency::Today, Recency::Today),
+            ("2024-05-16T22:30:00Z", Recency::Today, Recency::Today),
+            ("2024-05-16T21:30:00Z", Recency::Today, Recency::Yesterday),
+            (
+                "2024-05-15T12:00:00Z",
+                Recency::Yesterday,
+                Recency::ThisWeek,
+            ),
+            ("2024-05-13T12:00:00Z", Recency::ThisWeek, Recency::ThisWeek),
+            (
+                "2024-05-12T12:00:00Z",
+                Recency::ThisMonth,
+                Recency::ThisMonth,
+            ),
+            ("2024-04-30T22:30:00Z", Recency::Earlier, Recency::ThisMonth),
+            ("2023-05-16T12:00:00Z", Recency::Earlier, Recency::Earlier),
+        ] {
+            assert_eq!(Recency::of(&time(text), &now, &Tz::UTC), utc, "{text}");
+            assert_eq!(Recency::of(&time(text), &now, &berlin), local, "{text}");
+        }
+        assert_eq!(
+            serde_json::to_value(Recency::ThisWeek).unwrap(),
+            "this_week"
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Email digests of the recent notes of a user, sent by the [`SendDigests`](crate::tasks::SendDigests) job
+//!
+//! Users opt in with the `digest` and `email` [`Preferences`](crate::models::preferences::Preferences).
+//! Digests are sent at [`DIGEST_HOUR`] in the timezone of the user, weekly digests on Mondays.
+//! A digest lists
+//! - the notes that the user created since the previous digest
+//! - the notes that mention the user and changed since the previous digest
+//!
+//! Digests without any notes are not sent.
+use chrono::{DateTime, Datelike, Timelike, Utc, Weekday};
+
+use crate::dates;
+use crate::mailer::Mail;
+use crate::models::note::Note;
+use crate::models::preferences::DigestSchedule;
+use crate::models::{Id, User, VisibilityFilter};
+use crate::persistence::Persister;
+use crate::policy::{Action, Policy};
+
+/// The local hour at which digests are sent
+pub const DIGEST_HOUR: u32 = 8;
+
+/// Returns `true` if the user gets a digest in the hour of `now`
+pub fn is_due(user: &User, now: &DateTime<Utc>) -> bool {
+    let preferences = user.preferences();
+    if preferences.email().is_none() {
+        return false;
+    }
+    let local = dates::local(now, preferences.timezone());
+    let day = match preferences.digest() {
+        DigestSchedule::Off => false,
+        DigestSchedule::Daily => true,
+        DigestSchedule::Weekly => local.weekday() == Weekday::Mon,
+    };
+    day && local.hour() == DIGEST_HOUR
+}
+
+/// A note listed in a [`Digest`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Entry {
+    id: Id,
+    title: String,
+}
+
+impl From<&Note> for Entry {
+    fn from(note: &Note) -> Self {
+        Self {
+            id: *note.id(),
+            title: note.title().to_string(),
+        }
+    }
+}
+
+/// The notes of a user since the previous digest, see the [module documentation](self)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Digest {
+    since: DateTime<Utc>,
+    created: Vec<Entry>,
+    mentions: Vec<Entry>,
+}
+
+impl Digest {
+    /// Collects the notes of the user since `since`
+    pub fn collect<'a, P: Persister<'a>>(data: &'a P, user: &User, since: DateTime<Utc>) -> Self {
+        let mut created = data
+            .user_notes(user)
+            .filter(|note| note.created() >= &since)
+            .collect::<Vec<&Note>>();
+        created.sort_by(|a, b| a.created().cmp(b.created()));
+        // admins have no override for lists
+        let policy = Policy::new(&[]);
+        let mut mentions = data
+            .mentions(user)
+            .into_iter()
+            .filter(|note| {
+                VisibilityFilter::Active.includes(note, &Utc::now())
+                    && policy.can(user.id(), Action::List, *note)
+                    && note.updated() >= &since
+            })
+            .collect::<Vec<&Note>>();
+        mentions.sort_by(|a, b| b.updated().cmp(a.updated()));
+        Self {
+            since,
+            created: created.into_iter().map(Entry::from).collect(),
+            mentions: mentions.into_iter().map(Entry::from).collect(),
+        }
+    }
+
+    /// Returns `true` if the digest does not list any notes
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.mentions.is_empty()
+    }
+
+    /// Renders the digest as email to the address in the preferences of the user
+    pub fn mail(&self, user: &User) -> Option<Mail> {
+        let to = user.preferences().email()?;
+        let since =
+            dates::local(&self.since, user.preferences().timezone()).format("%Y-%m-%d %H:%M");
+        let mut body = format!(
+            "Hello {},\n\nthese are your notes since {since}.\n",
+            user.name()
+        );
+        for (heading, entries) in [
+            ("New notes", &self.created),
+            ("Notes that mention you", &self.mentions),
+        ] {
+            if entries.is_empty() {
+                continue;
+            }
+            body.push_str(&format!("\n{heading}:\n"));
+            for entry in entries {
+                body.push_str(&format!(
+                    "- {} (/note/{})\n",
+                    entry.title,
+                    usize::from(entry.id)
+                ));
+            }
+        }
+        Some(Mail {
+            to: to.to_string(),
+            subject: format!(
+                "Your notes: {} new, {} mentions",
+                self.created.len(),
+                self.mentions.len()
+            ),
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::models::note::Draft;
+    use crate::models::preferences::Preferences;
+    use crate::models::Visibility;
+    use crate::persistence::memory::InMemoryStorage;
+
+    fn preferences(json: &str) -> Preferences {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_is_due() {
+        let mut user = User::new(Id(1), "alice".to_string());
+        // Monday, 08:30 in Berlin
+        let monday = Utc.with_ymd_and_hms(2024, 1, 1, 7, 30, 0).unwrap();
+        assert!(!is_due(&user, &monday));
+
+        user.set_preferences(preferences(
+            r#"{"digest": "weekly", "email": "alice@example.com", "timezone": "Europe/Berlin"}"#,
+        ));
+        assert!(is_due(&user, &monday));
+        assert!(!is_due(&user, &(monday + chrono::TimeDelta::hours(1))));
+        assert!(!is_due(&user, &(monday + chrono::TimeDelta::days(1))));
+
+        user.set_preferences(preferences(
+            r#"{"digest": "daily", "email": "alice@example.com"}"#,
+        ));
+        assert!(!is_due(&user, &monday));
+        assert!(is_due(
+            &user,
+            &(monday + chrono::TimeDelta::days(1) + chrono::TimeDelta::hours(1))
+        ));
+    }
+
+    #[test]
+    fn test_digest() {
+        let mut data = InMemoryStorage::default();
+        let mut alice = data.add_user("alice".to_string(), None).clone();
+        let bob = data.add_user("bob".to_string(), None).clone();
+        let since = Utc::now();
+        let created = *data
+            .add_note(
+                Draft::new(
+                    "Own".to_string(),
+                    String::new(),
+                    vec![],
+                    Visibility::Private,
+                ),
+                &alice,
+            )
+            .id();
+        for visibility in [Visibility::Public, Visibility::Private] {
+            let id = *data
+                .add_note(
+                    Draft::new("Hi @alice".to_string(), String::new(), vec![], visibility),
+                    &bob,
+                )
+                .id();
+            data.set_mentions(id, [*alice.id()].into());
+        }
+
+        let digest = Digest::collect(&data, &alice, since);
+        assert_eq!(
+            digest.created,
+            vec![Entry {
+                id: created,
+                title: "Own".to_string()
+            }]
+        );
+        assert_eq!(digest.mentions.len(), 1);
+        assert!(Digest::collect(&data, &bob, since).mentions.is_empty());
+        assert!(Digest::collect(&data, &alice, Utc::now()).is_empty());
+
+        assert!(digest.mail(&alice).is_none());
+        alice.set_preferences(preferences(
+            r#"{"digest": "daily", "email": "alice@example.com"}"#,
+        ));
+        let mail = digest.mail(&alice).unwrap();
+        assert_eq!(mail.to, "alice@example.com");
+        assert_eq!(mail.subject, "Your notes: 1 new, 1 mentions");
+        assert!(mail.body.contains("New notes:\n- Own (/note/0)\n"));
+        assert!(mail
+            .body
+            .contains("Notes that mention you:\n- Hi @alice (/note/1)\n"));
+    }
+}
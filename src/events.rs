@@ -0,0 +1,214 @@
+//! A journal of recent note changes that clients can follow with `GET /events`
+//!
+//! Every committed change gets an increasing sequence number. The [`Journal`]
+//! keeps the last [`JOURNAL_CAPACITY`] entries, so that clients which lost their
+//! connection can resume after the last entry they received (the `Last-Event-ID`
+//! of server-sent events). Older changes are lost for them.
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+use crate::indexer::IndexEvent;
+use crate::models::note::Note;
+use crate::models::Id;
+
+/// The number of entries that are kept for resuming clients
+pub const JOURNAL_CAPACITY: usize = 1000;
+
+/// A change in the lifecycle of a [`Note`], as sent to its owner
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NoteEvent {
+    Created {
+        note: Note,
+    },
+    Updated {
+        note: Note,
+    },
+    Deleted {
+        id: Id,
+    },
+    /// A deleted note was restored
+    Restored {
+        note: Note,
+    },
+    Archived {
+        note: Note,
+    },
+    Unarchived {
+        note: Note,
+    },
+}
+
+impl NoteEvent {
+    /// Converts an [`IndexEvent`], returns `None` for events that don't concern a single note
+    pub fn from_index(event: &IndexEvent) -> Option<Self> {
+        match event {
+            IndexEvent::Added(note) => Some(Self::Created { note: note.clone() }),
+            IndexEvent::Updated(note) => Some(Self::Updated { note: note.clone() }),
+            IndexEvent::Deleted(note) => Some(Self::Deleted { id: *note.id() }),
+            IndexEvent::Restored(note) => Some(Self::Restored { note: note.clone() }),
+            IndexEvent::Archived(note) if note.archived() => {
+                Some(Self::Archived { note: note.clone() })
+            }
+            IndexEvent::Archived(note) => Some(Self::Unarchived { note: note.clone() }),
+            IndexEvent::UserPurged(_) | IndexEvent::Viewed { .. } => None,
+        }
+    }
+
+    /// The name of the event, e.g. to register listeners with `EventSource.addEventListener`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Created { .. } => "created",
+            Self::Updated { .. } => "updated",
+            Self::Deleted { .. } => "deleted",
+            Self::Restored { .. } => "restored",
+            Self::Archived { .. } => "archived",
+            Self::Unarchived { .. } => "unarchived",
+        }
+    }
+}
+
+/// A [`NoteEvent`] with its sequence number and the owner of the note
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub id: u64,
+    pub user: Id,
+    pub event: NoteEvent,
+}
+
+#[derive(Debug)]
+struct Inner {
+    entries: VecDeque<Entry>,
+    next_id: u64,
+}
+
+/// The recent [`NoteEvent`]s and a channel for new ones
+#[derive(Debug)]
+pub struct Journal {
+    inner: Mutex<Inner>,
+    sender: Sender<Entry>,
+    capacity: usize,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new(JOURNAL_CAPACITY)
+    }
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            inner: Mutex::new(Inner {
+                entries: VecDeque::with_capacity(capacity),
+                next_id: 1,
+            }),
+            sender,
+            capacity,
+        }
+    }
+
+    /// Appends the event of a note of `user` and sends it to all subscribers
+    pub fn publish(&self, user: Id, event: NoteEvent) {
+        let mut inner = self.inner.lock().expect("journal lock was poisoned");
+        let entry = Entry {
+            id: inner.next_id,
+            user,
+            event,
+        };
+        inner.next_id += 1;
+        if inner.entries.len() == self.capacity {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(entry.clone());
+        // fails only if nobody is subscribed
+        let _ = self.sender.send(entry);
+    }
+
+    /// Publishes the [`NoteEvent`] of a committed change, if it concerns a single note
+    pub fn record(&self, event: &IndexEvent) {
+        let user = match event {
+            IndexEvent::Added(note)
+            | IndexEvent::Updated(note)
+            | IndexEvent::Deleted(note)
+            | IndexEvent::Restored(note)
+            | IndexEvent::Archived(note) => *note.user(),
+            IndexEvent::UserPurged(_) | IndexEvent::Viewed { .. } => return,
+        };
+        if let Some(note_event) = NoteEvent::from_index(event) {
+            self.publish(user, note_event);
+        }
+    }
+
+    /// Returns the kept entries after `last_id` and a receiver for all later entries
+    ///
+    /// No entry is missed or returned twice between the two.
+    pub fn subscribe(&self, last_id: Option<u64>) -> (Vec<Entry>, Receiver<Entry>) {
+        let inner = self.inner.lock().expect("journal lock was poisoned");
+        let backlog = match last_id {
+            Some(last_id) => inner
+                .entries
+                .iter()
+                .filter(|entry| entry.id > last_id)
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+        (backlog, self.sender.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+
+    fn deleted(id: usize) -> NoteEvent {
+        NoteEvent::Deleted { id: Id(id) }
+    }
+
+    #[test]
+    fn test_from_index() {
+        let note = example_note();
+        assert_eq!(
+            NoteEvent::from_index(&IndexEvent::Deleted(note.clone())),
+            Some(deleted(1))
+        );
+        assert_eq!(
+            NoteEvent::from_index(&IndexEvent::Added(note.clone())).map(|event| event.name()),
+            Some("created")
+        );
+        assert!(NoteEvent::from_index(&IndexEvent::UserPurged(Id(1))).is_none());
+
+        let value = serde_json::to_value(deleted(3)).unwrap();
+        assert_eq!(value, serde_json::json!({"type": "deleted", "id": 3}));
+    }
+
+    #[test]
+    fn test_resume() {
+        let journal = Journal::new(3);
+        journal.publish(Id(0), deleted(1));
+        journal.publish(Id(1), deleted(2));
+
+        let (backlog, mut receiver) = journal.subscribe(None);
+        assert!(backlog.is_empty());
+        let (backlog, _) = journal.subscribe(Some(1));
+        assert_eq!(backlog.len(), 1);
+        assert_eq!(backlog[0].id, 2);
+        assert_eq!(backlog[0].user, Id(1));
+
+        journal.publish(Id(0), deleted(3));
+        assert_eq!(receiver.try_recv().unwrap().id, 3);
+
+        // only the last entries are kept
+        journal.publish(Id(0), deleted(4));
+        let (backlog, _) = journal.subscribe(Some(0));
+        let ids = backlog.iter().map(|entry| entry.id).collect::<Vec<u64>>();
+        assert_eq!(ids, vec![2, 3, 4]);
+    }
+}
@@ -0,0 +1,153 @@
+//! Support for the `Idempotency-Key` header on mutations
+//!
+//! Clients on flaky networks retry requests whose response got lost. If the
+//! request contains an `Idempotency-Key` header, the response is stored and
+//! replayed for retries with the same key, instead of e.g. creating a duplicate note.
+use std::time::Duration;
+
+use axum::http::{HeaderMap, StatusCode};
+use chrono::{TimeDelta, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::info;
+
+use crate::models::idempotency::IdempotencyRecord;
+use crate::models::User;
+use crate::persistence::Persister;
+
+/// The header containing the idempotency key
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// The maximum length of an idempotency key
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Returns the idempotency key of the request, if present
+pub fn key(headers: &HeaderMap) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str().map(str::trim) {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => Ok(Some(key.to_string())),
+        _ => {
+            info!("--> 400 [invalid idempotency key]");
+            Err((
+                StatusCode::BAD_REQUEST,
+                "Invalid Idempotency-Key header".to_string(),
+            ))
+        }
+    }
+}
+
+/// Returns the stored response if the request with this key was already processed
+/// within the `window`
+///
+/// Fails if the key was used for a request with a different payload.
+pub fn replay<P, Req, Res>(
+    data: &P,
+    user: &User,
+    key: &str,
+    request: &Req,
+    window: Duration,
+) -> Result<Option<Res>, (StatusCode, String)>
+where
+    P: for<'a> Persister<'a>,
+    Req: Serialize,
+    Res: DeserializeOwned,
+{
+    let Some(record) = data.idempotency_record(user, key) else {
+        return Ok(None);
+    };
+    if Utc::now() - *record.created() > window_delta(window) {
+        return Ok(None);
+    }
+    if record.request() != &to_value(request) {
+        info!("--> 422 [idempotency key re-used]");
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Idempotency-Key was already used for a different request".to_string(),
+        ));
+    }
+    let response = serde_json::from_value(record.response().clone())
+        .expect("stored response must be deserializable");
+    info!("--> replaying response for idempotency key {}", key);
+    Ok(Some(response))
+}
+
+/// Stores the response to the request with this key and removes all expired records
+pub fn remember<P, Req, Res>(
+    data: &mut P,
+    user: &User,
+    key: String,
+    request: &Req,
+    response: &Res,
+    window: Duration,
+) where
+    P: for<'a> Persister<'a>,
+    Req: Serialize,
+    Res: Serialize,
+{
+    data.expire_idempotency_records(&(Utc::now() - window_delta(window)));
+    data.add_idempotency_record(IdempotencyRecord::new(
+        *user.id(),
+        key,
+        to_value(request),
+        to_value(response),
+    ));
+}
+
+fn to_value<T: Serialize>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).expect("payloads can always be serialized")
+}
+
+fn window_delta(window: Duration) -> TimeDelta {
+    TimeDelta::from_std(window).expect("idempotency window is out of range")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::{Draft, Note};
+    use crate::persistence::memory::InMemoryStorage;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_key() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(key(&headers), Ok(None));
+        headers.insert(IDEMPOTENCY_KEY, "abc".parse().unwrap());
+        assert_eq!(key(&headers), Ok(Some("abc".to_string())));
+        headers.insert(IDEMPOTENCY_KEY, " ".parse().unwrap());
+        assert!(key(&headers).is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut data = InMemoryStorage::default();
+        let user = User::default();
+        let draft = Draft::default();
+
+        let replayed: Option<Note> = replay(&data, &user, "abc", &draft, WINDOW).unwrap();
+        assert!(replayed.is_none());
+
+        let note = data.add_note(draft.clone(), &user).clone();
+        remember(&mut data, &user, "abc".to_string(), &draft, &note, WINDOW);
+
+        let replayed: Option<Note> = replay(&data, &user, "abc", &draft, WINDOW).unwrap();
+        assert_eq!(replayed, Some(note));
+
+        // the key is scoped to the user
+        let other = User::new(1.into(), "other".to_string());
+        let replayed: Option<Note> = replay(&data, &other, "abc", &draft, WINDOW).unwrap();
+        assert!(replayed.is_none());
+
+        let other_draft = Draft::new("Foo".into(), "".into(), vec![], Default::default());
+        let replayed: Result<Option<Note>, _> = replay(&data, &user, "abc", &other_draft, WINDOW);
+        assert_eq!(replayed.unwrap_err().0, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // expired records are ignored
+        let replayed: Option<Note> =
+            replay(&data, &user, "abc", &draft, Duration::from_secs(0)).unwrap();
+        assert!(replayed.is_none());
+    }
+}
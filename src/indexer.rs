@@ -0,0 +1,651 @@
+//! Derived data structures that are maintained in the background
+//!
+//! Handlers only send an [`IndexEvent`] after a mutation was committed to the
+//! [`Persister`](crate::persistence::Persister). A background thread receives the events
+//! and updates the [`Index`], so that the write path does not get slower as the
+//! index grows. The index is eventually consistent with the data storage.
+//!
+//! The same events also update the [`DailyRollup`] of the note activity, are
+//! recorded in the [`Journal`] for clients of `GET /events` and invalidate the
+//! affected entries of the [`QueryCache`].
+//!
+//! Events are shared with the other instances of a deployment via an [`EventBus`]. The
+//! events of other instances are applied like local ones, but not published again.
+//!
+//! The notes that exist at startup are indexed in batches by the same thread, see
+//! [`warm_up`](crate::tasks::warm_up). Until the [`WarmUp`] is complete, the index is
+//! incomplete and handlers scan the notes instead.
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::bus::{BusMessage, EventBus};
+use crate::cache::QueryCache;
+use crate::config::SearchConfig;
+use crate::events::Journal;
+use crate::models::lifecycle::Event;
+use crate::models::note::Note;
+use crate::models::Id;
+use crate::search;
+use crate::stats::recent::RecentNotes;
+use crate::stats::rollup::DailyRollup;
+use crate::stats::tag_graph::{TagGraph, TagGraphView};
+
+/// A committed change of a [`Note`] that must be reflected in the [`Index`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum IndexEvent {
+    Added(Note),
+    Updated(Note),
+    Deleted(Note),
+    /// A deleted note was restored
+    Restored(Note),
+    /// A note was archived or unarchived, see [`Note::archived`]
+    Archived(Note),
+    /// All data of the user was removed permanently
+    UserPurged(Id),
+    /// A user read a note, only relevant for [`RecentNotes`]
+    Viewed {
+        user: Id,
+        note: Id,
+    },
+}
+
+impl IndexEvent {
+    /// The event of a [`Lifecycle`](crate::models::lifecycle::Lifecycle) transition of the note
+    pub fn transition(event: Event, note: Note) -> Self {
+        match event {
+            Event::Create => Self::Added(note),
+            Event::Archive | Event::Unarchive => Self::Archived(note),
+            Event::Delete => Self::Deleted(note),
+            Event::Restore => Self::Restored(note),
+        }
+    }
+}
+
+/// The progress of indexing the notes that existed at startup, part of `GET /readyz`
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct WarmUp {
+    /// `true` once all notes are indexed
+    pub ready: bool,
+    pub indexed: usize,
+    pub total: usize,
+}
+
+impl Default for WarmUp {
+    /// Without notes to index, the index is ready right away
+    fn default() -> Self {
+        Self {
+            ready: true,
+            indexed: 0,
+            total: 0,
+        }
+    }
+}
+
+/// The work of the indexer thread, in the order it was queued
+#[derive(Debug)]
+enum Work {
+    Event(Box<IndexEvent>),
+    /// Notes that existed at startup, only added to the [`Index`]
+    WarmUp(Vec<Note>),
+    /// All notes that existed at startup were queued
+    WarmedUp,
+}
+
+/// Splits a text into lowercase words
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// The indexed data of a single [`Note`], needed to remove it from the [`Index`] again
+#[derive(Debug, Default)]
+struct IndexedNote {
+    user: Id,
+    words: HashSet<String>,
+    tags: HashSet<String>,
+}
+
+/// In-memory indexes of all active notes
+///
+/// - a full-text search index, mapping every word to the notes containing it.
+///   Words are normalized by the [`search`] pipeline, both in notes and queries.
+/// - a tag index, mapping every tag label to the notes tagged with it.
+///   It also provides the number of notes per tag.
+/// - related notes, computed from the tag index
+/// - the [`TagGraph`] of every user
+#[derive(Debug, Default)]
+pub struct Index {
+    notes: HashMap<Id, IndexedNote>,
+    words: HashMap<String, HashSet<Id>>,
+    tags: HashMap<String, HashSet<Id>>,
+    graphs: HashMap<Id, TagGraph>,
+    config: SearchConfig,
+}
+
+impl Index {
+    /// Creates an empty index that normalizes words as configured
+    pub fn new(config: SearchConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Builds an index of only the given notes
+    ///
+    /// Used instead of the shared index while it is not [ready](Indexer::is_ready). Tags are
+    /// weighted by their use in these notes only, so related notes may be ordered differently.
+    pub fn scan<'n>(config: SearchConfig, notes: impl IntoIterator<Item = &'n Note>) -> Self {
+        let mut index = Self::new(config);
+        for note in notes {
+            index.remove(note.id());
+            index.insert(note);
+        }
+        index
+    }
+
+    /// Applies a single [`IndexEvent`]
+    pub fn apply(&mut self, event: IndexEvent) {
+        match event {
+            IndexEvent::Added(note)
+            | IndexEvent::Updated(note)
+            | IndexEvent::Restored(note)
+            | IndexEvent::Archived(note) => {
+                self.remove(note.id());
+                self.insert(&note);
+            }
+            IndexEvent::Deleted(note) => self.remove(note.id()),
+            IndexEvent::UserPurged(user) => {
+                let ids = self
+                    .notes
+                    .iter()
+                    .filter(|(_, indexed)| indexed.user == user)
+                    .map(|(id, _)| *id)
+                    .collect::<Vec<Id>>();
+                for id in ids {
+                    self.remove(&id);
+                }
+            }
+            IndexEvent::Viewed { .. } => {}
+        }
+    }
+
+    fn insert(&mut self, note: &Note) {
+        let id = *note.id();
+        let indexed = IndexedNote {
+            user: *note.user(),
+            words: search::words(&self.config, note.title())
+                .chain(search::words(&self.config, &note.body().text()))
+                .collect(),
+            tags: note.tags().map(|tag| tag.label().to_string()).collect(),
+        };
+        for word in &indexed.words {
+            self.words.entry(word.clone()).or_default().insert(id);
+        }
+        for tag in &indexed.tags {
+            self.tags.entry(tag.clone()).or_default().insert(id);
+        }
+        self.graphs
+            .entry(indexed.user)
+            .or_default()
+            .add(&indexed.tags);
+        self.notes.insert(id, indexed);
+    }
+
+    fn remove(&mut self, id: &Id) {
+        let Some(indexed) = self.notes.remove(id) else {
+            return;
+        };
+        if let Some(graph) = self.graphs.get_mut(&indexed.user) {
+            graph.remove(&indexed.tags);
+            if graph.is_empty() {
+                self.graphs.remove(&indexed.user);
+            }
+        }
+        remove_from(&mut self.words, indexed.words, id);
+        remove_from(&mut self.tags, indexed.tags, id);
+    }
+
+    /// Returns the Ids of all notes that contain every word of `text`
+    ///
+    /// Returns an empty set if `text` does not contain any words.
+    pub fn search(&self, text: &str) -> HashSet<Id> {
+        let mut result: Option<HashSet<Id>> = None;
+        for word in search::words(&self.config, text) {
+            let ids = self.words.get(&word).cloned().unwrap_or_default();
+            result = Some(match result {
+                Some(previous) => previous.intersection(&ids).copied().collect(),
+                None => ids,
+            });
+        }
+        result.unwrap_or_default()
+    }
+
+    /// Returns the number of notes tagged with the label
+    pub fn tag_count(&self, label: &str) -> usize {
+        self.tags.get(label).map_or(0, HashSet::len)
+    }
+
+    /// Returns the co-occurrence of the tags in the notes of the user
+    pub fn tag_graph(&self, user: &Id) -> TagGraphView {
+        self.graphs
+            .get(user)
+            .map(TagGraph::view)
+            .unwrap_or_default()
+    }
+
+    /// Returns the Ids of notes that share tags with the note `id`, most related first
+    ///
+    /// Rare tags are a stronger indication of a relation than tags that are used
+    /// for many notes, so every shared tag is weighted by the inverse of its [`Index::tag_count`].
+    pub fn related(&self, id: &Id) -> Vec<Id> {
+        let Some(indexed) = self.notes.get(id) else {
+            return vec![];
+        };
+        let mut scores: HashMap<Id, f64> = HashMap::new();
+        for tag in &indexed.tags {
+            let weight = 1.0 / self.tag_count(tag) as f64;
+            for other in self.tags.get(tag).into_iter().flatten() {
+                if other != id {
+                    *scores.entry(*other).or_default() += weight;
+                }
+            }
+        }
+        let mut res = scores.into_iter().collect::<Vec<(Id, f64)>>();
+        res.sort_by(|a, b| {
+            b.1.total_cmp(&a.1)
+                .then_with(|| usize::from(a.0).cmp(&usize::from(b.0)))
+        });
+        res.into_iter().map(|(id, _)| id).collect()
+    }
+}
+
+/// Removes `id` from the entries of all `keys` and drops entries that became empty
+fn remove_from(map: &mut HashMap<String, HashSet<Id>>, keys: HashSet<String>, id: &Id) {
+    for key in keys {
+        if let Some(ids) = map.get_mut(&key) {
+            ids.remove(id);
+            if ids.is_empty() {
+                map.remove(&key);
+            }
+        }
+    }
+}
+
+/// Handle to the background indexer
+///
+/// The handle is cheap to clone and can be shared between all handlers.
+#[derive(Clone, Debug)]
+pub struct Indexer {
+    sender: Sender<Work>,
+    index: Arc<RwLock<Index>>,
+    warm_up: Arc<RwLock<WarmUp>>,
+    rollup: Arc<RwLock<DailyRollup>>,
+    recent: Arc<RwLock<RecentNotes>>,
+    journal: Arc<Journal>,
+    queries: Arc<QueryCache>,
+    /// Identifies this instance on the [`EventBus`]
+    instance: Arc<str>,
+    bus: Arc<dyn EventBus>,
+}
+
+/// Records the event in the [`Journal`], invalidates the [`QueryCache`] and queues it for the thread
+fn dispatch(sender: &Sender<Work>, journal: &Journal, queries: &QueryCache, event: IndexEvent) {
+    journal.record(&event);
+    queries.invalidate(&event);
+    if sender.send(Work::Event(Box::new(event))).is_err() {
+        warn!("Indexer thread is not running, index is out of date");
+    }
+}
+
+impl Indexer {
+    /// Spawns the background thread that updates the [`Index`], [`DailyRollup`] and [`RecentNotes`]
+    ///
+    /// `recent` is the number of recent notes that are kept per user. The events of other
+    /// instances on the `bus` are applied as well.
+    pub fn spawn(config: SearchConfig, recent: usize, bus: Arc<dyn EventBus>) -> Self {
+        let (sender, receiver) = mpsc::channel::<Work>();
+        let index = Arc::new(RwLock::new(Index::new(config)));
+        let warm_up = Arc::new(RwLock::new(WarmUp::default()));
+        let rollup = Arc::new(RwLock::new(DailyRollup::default()));
+        let recent = Arc::new(RwLock::new(RecentNotes::new(recent)));
+        let worker_index = index.clone();
+        let worker_rollup = rollup.clone();
+        let worker_recent = recent.clone();
+        let worker_warm_up = warm_up.clone();
+        thread::Builder::new()
+            .name("indexer".to_string())
+            .spawn(move || {
+                // the loop ends once all senders are dropped
+                for work in receiver {
+                    let event = match work {
+                        Work::Event(event) => *event,
+                        Work::WarmUp(notes) => {
+                            debug!("Indexing {} existing notes", notes.len());
+                            let mut index = worker_index.write().expect("index lock was poisoned");
+                            for note in &notes {
+                                index.remove(note.id());
+                                index.insert(note);
+                            }
+                            worker_warm_up
+                                .write()
+                                .expect("warm-up lock was poisoned")
+                                .indexed += notes.len();
+                            continue;
+                        }
+                        Work::WarmedUp => {
+                            let mut warm_up =
+                                worker_warm_up.write().expect("warm-up lock was poisoned");
+                            // notes that were deleted during the warm-up are not counted
+                            warm_up.indexed = warm_up.total;
+                            warm_up.ready = true;
+                            info!("Indexed {} existing notes", warm_up.total);
+                            continue;
+                        }
+                    };
+                    debug!("Indexing {:?}", event);
+                    worker_rollup
+                        .write()
+                        .expect("rollup lock was poisoned")
+                        .apply(&event);
+                    worker_recent
+                        .write()
+                        .expect("recent notes lock was poisoned")
+                        .apply(&event);
+                    worker_index
+                        .write()
+                        .expect("index lock was poisoned")
+                        .apply(event);
+                }
+            })
+            .expect("unable to spawn indexer thread");
+        let indexer = Self {
+            sender,
+            index,
+            warm_up,
+            rollup,
+            recent,
+            journal: Arc::new(Journal::default()),
+            queries: Arc::new(QueryCache::default()),
+            instance: format!("{:016x}", rand::random::<u64>()).into(),
+            bus,
+        };
+        // the handler must not hold the bus, otherwise the thread would never end
+        let (sender, journal, queries, instance) = (
+            indexer.sender.clone(),
+            indexer.journal.clone(),
+            indexer.queries.clone(),
+            indexer.instance.clone(),
+        );
+        indexer.bus.subscribe(Box::new(move |message: BusMessage| {
+            if *message.origin != *instance {
+                dispatch(&sender, &journal, &queries, message.event);
+            }
+        }));
+        indexer
+    }
+
+    /// Queues an [`IndexEvent`] without waiting for the index to be updated
+    ///
+    /// The event is recorded in the [`Journal`] and invalidates the [`QueryCache`] immediately.
+    /// It is published on the [`EventBus`] for the other instances.
+    pub fn send(&self, event: IndexEvent) {
+        self.bus.publish(BusMessage {
+            origin: self.instance.to_string(),
+            event: event.clone(),
+        });
+        dispatch(&self.sender, &self.journal, &self.queries, event);
+    }
+
+    /// Starts indexing `total` notes that existed before the indexer, see [`Indexer::warm_up`]
+    pub fn begin_warm_up(&self, total: usize) {
+        *self.warm_up.write().expect("warm-up lock was poisoned") = WarmUp {
+            ready: false,
+            indexed: 0,
+            total,
+        };
+    }
+
+    /// Queues a batch of existing notes for the [`Index`]
+    ///
+    /// Unlike [`Indexer::send`], the notes are not recorded as events, counted as activity or
+    /// published. They are queued behind the events of earlier changes, so the batch must be
+    /// read while holding the lock of the storage to not overwrite newer changes.
+    pub fn warm_up(&self, notes: Vec<Note>) {
+        if self.sender.send(Work::WarmUp(notes)).is_err() {
+            warn!("Indexer thread is not running, index is out of date");
+        }
+    }
+
+    /// Marks the [`Index`] as ready once all queued batches are indexed
+    pub fn finish_warm_up(&self) {
+        if self.sender.send(Work::WarmedUp).is_err() {
+            warn!("Indexer thread is not running, index is out of date");
+        }
+    }
+
+    pub fn warm_up_status(&self) -> WarmUp {
+        *self.warm_up.read().expect("warm-up lock was poisoned")
+    }
+
+    /// Returns `true` if the [`Index`] contains all notes, i.e. it is not warming up
+    pub fn is_ready(&self) -> bool {
+        self.warm_up_status().ready
+    }
+
+    /// Provides read access to the current state of the [`Index`]
+    pub fn index(&self) -> RwLockReadGuard<'_, Index> {
+        self.index.read().expect("index lock was poisoned")
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn queries(&self) -> &QueryCache {
+        &self.queries
+    }
+
+    /// Provides read access to the current state of the [`RecentNotes`]
+    pub fn recent(&self) -> RwLockReadGuard<'_, RecentNotes> {
+        self.recent.read().expect("recent notes lock was poisoned")
+    }
+
+    /// Provides read access to the current state of the [`DailyRollup`]
+    pub fn rollup(&self) -> RwLockReadGuard<'_, DailyRollup> {
+        self.rollup.read().expect("rollup lock was poisoned")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::LocalBus;
+    use crate::models::note::test_note::example_note;
+    use crate::models::note::{Draft, Tags};
+    use crate::models::{Tag, Visibility};
+
+    fn note(id: usize, body: &str, tags: &[&str]) -> Note {
+        let mut note_tags = Tags::default();
+        for (tag_id, label) in tags.iter().enumerate() {
+            note_tags.insert(Tag::new(tag_id.into(), label.to_string()));
+        }
+        Note::new(
+            Draft::new(
+                "Title".to_string(),
+                body.to_string(),
+                vec![],
+                Visibility::Public,
+            ),
+            id.into(),
+            Id(0),
+            note_tags,
+        )
+    }
+
+    #[test]
+    fn test_words() {
+        assert_eq!(
+            words("Hello, World! it's me").collect::<Vec<String>>(),
+            vec!["hello", "world", "it", "s", "me"]
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let mut index = Index::default();
+        index.apply(IndexEvent::Added(example_note()));
+        index.apply(IndexEvent::Added(note(2, "Buy milk", &[])));
+
+        assert_eq!(index.search("test"), HashSet::from([Id(1)]));
+        assert_eq!(index.search("TITLE"), HashSet::from([Id(1), Id(2)]));
+        assert_eq!(index.search("title milk"), HashSet::from([Id(2)]));
+        assert!(index.search("milkshake").is_empty());
+        assert!(index.search("").is_empty());
+
+        index.apply(IndexEvent::Added(note(3, "Café in München", &[])));
+        assert_eq!(index.search("cafe MUNCHEN"), HashSet::from([Id(3)]));
+        assert_eq!(index.search("Münchén"), HashSet::from([Id(3)]));
+        index.apply(IndexEvent::Deleted(note(3, "", &[])));
+
+        index.apply(IndexEvent::Updated(note(2, "Buy bread", &[])));
+        assert!(index.search("milk").is_empty());
+        assert_eq!(index.search("bread"), HashSet::from([Id(2)]));
+
+        index.apply(IndexEvent::Deleted(note(2, "Buy bread", &[])));
+        assert!(index.search("bread").is_empty());
+        assert!(!index.words.contains_key("bread"));
+
+        index.apply(IndexEvent::UserPurged(Id(12)));
+        assert!(index.search("test").is_empty());
+        assert!(index.notes.is_empty());
+    }
+
+    #[test]
+    fn test_search_umlauts() {
+        let mut index = Index::new(SearchConfig {
+            umlauts: true,
+            ..SearchConfig::default()
+        });
+        index.apply(IndexEvent::Added(note(1, "Grüße von Müller", &[])));
+        assert_eq!(index.search("Mueller gruesse"), HashSet::from([Id(1)]));
+        assert_eq!(index.search("MÜLLER"), HashSet::from([Id(1)]));
+        assert!(index.search("Muller").is_empty());
+    }
+
+    #[test]
+    fn test_tags_and_related() {
+        let mut index = Index::default();
+        index.apply(IndexEvent::Added(note(0, "", &["common", "rare"])));
+        index.apply(IndexEvent::Added(note(1, "", &["common"])));
+        index.apply(IndexEvent::Added(note(2, "", &["common", "rare"])));
+        index.apply(IndexEvent::Added(note(3, "", &["other"])));
+
+        assert_eq!(index.tag_count("common"), 3);
+        assert_eq!(index.tag_count("rare"), 2);
+        assert_eq!(index.tag_count("foobar"), 0);
+
+        assert_eq!(index.related(&Id(0)), vec![Id(2), Id(1)]);
+        assert_eq!(index.related(&Id(1)), vec![Id(0), Id(2)]);
+        assert!(index.related(&Id(3)).is_empty());
+        assert!(index.related(&Id(666)).is_empty());
+
+        index.apply(IndexEvent::Deleted(note(2, "", &["common", "rare"])));
+        assert_eq!(index.tag_count("common"), 2);
+        assert_eq!(index.related(&Id(0)), vec![Id(1)]);
+
+        let graph = serde_json::to_value(index.tag_graph(&Id(0))).unwrap();
+        assert_eq!(graph["nodes"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            graph["edges"],
+            serde_json::json!([{"source": "common", "target": "rare", "weight": 1}])
+        );
+        index.apply(IndexEvent::UserPurged(Id(0)));
+        assert_eq!(index.tag_graph(&Id(0)), TagGraphView::default());
+        assert!(index.graphs.is_empty());
+    }
+
+    #[test]
+    fn test_background_indexer() {
+        let indexer = Indexer::spawn(SearchConfig::default(), 0, Arc::new(LocalBus::default()));
+        indexer.send(IndexEvent::Added(example_note()));
+        // the index is updated asynchronously
+        for _ in 0..100 {
+            if !indexer.index().search("test").is_empty() {
+                return;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("Note was not indexed");
+    }
+
+    #[test]
+    fn test_warm_up() {
+        let indexer = Indexer::spawn(SearchConfig::default(), 0, Arc::new(LocalBus::default()));
+        assert!(indexer.is_ready());
+        indexer.begin_warm_up(3);
+        assert_eq!(
+            indexer.warm_up_status(),
+            WarmUp {
+                ready: false,
+                indexed: 0,
+                total: 3
+            }
+        );
+        indexer.warm_up(vec![example_note(), note(2, "Buy milk", &[])]);
+        indexer.send(IndexEvent::Deleted(note(2, "Buy milk", &[])));
+        indexer.finish_warm_up();
+        for _ in 0..100 {
+            if indexer.is_ready() {
+                assert_eq!(indexer.warm_up_status().indexed, 3);
+                assert_eq!(indexer.index().search("test"), HashSet::from([Id(1)]));
+                assert!(indexer.index().search("milk").is_empty());
+                // existing notes are not new activity
+                let (entries, _) = indexer.journal().subscribe(Some(0));
+                assert_eq!(entries.len(), 1);
+                return;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("Warm-up did not finish");
+    }
+
+    #[test]
+    fn test_scan() {
+        let notes = [
+            note(0, "", &["common", "rare"]),
+            note(1, "", &["common"]),
+            note(2, "", &["common", "rare"]),
+        ];
+        let index = Index::scan(SearchConfig::default(), &notes);
+        assert_eq!(index.related(&Id(0)), vec![Id(2), Id(1)]);
+        assert_eq!(index.tag_count("common"), 3);
+    }
+
+    #[test]
+    fn test_shared_bus() {
+        let bus = Arc::new(LocalBus::default());
+        let first = Indexer::spawn(SearchConfig::default(), 0, bus.clone());
+        let second = Indexer::spawn(SearchConfig::default(), 0, bus);
+        let note = example_note();
+        first.send(IndexEvent::Added(note.clone()));
+        for indexer in [&first, &second] {
+            let (entries, _) = indexer.journal().subscribe(Some(0));
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].user, *note.user());
+        }
+        for _ in 0..100 {
+            if !second.index().search("test").is_empty() {
+                return;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("Note of the other instance was not indexed");
+    }
+}
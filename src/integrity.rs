@@ -0,0 +1,278 @@
+//! Consistency checks of the stored notes, the response of `GET /admin/integrity`
+//!
+//! The checks find references that lead nowhere:
+//! - Links in the body of an active note to a note that doesn't exist or was deleted,
+//!   e.g. `[see](/note/12)`, `/note/slug/<slug>` or the public page `/p/<slug>`.
+//!   Links to merged notes are fine, they lead to the note that the source was merged into.
+//! - Deleted notes whose merge target was deleted as well, so `GET /note/:id` can't
+//!   redirect to it anymore.
+//! - Tags that are not used by any active note, e.g. because `NOTE_KEEP_TAGS` is set.
+//!
+//! Only orphaned tags can be fixed automatically (with `POST /admin/integrity/fix`), the
+//! other problems need a decision of the owner of the note.
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::note::Note;
+use crate::models::{Id, Visibility, VisibilityFilter};
+use crate::persistence::Persister;
+
+/// A reference to another note in the body of a note
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Link {
+    Id(Id),
+    Slug(String),
+}
+
+/// A problem found by [`check`]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Problem {
+    /// The body of the note links to a note that doesn't exist or was deleted
+    BrokenLink { note: Id, user: Id, link: String },
+    /// The note was merged into a note that was deleted without being merged itself
+    DanglingMerge { note: Id, user: Id, merged_into: Id },
+    /// The tag isn't used by any active note
+    OrphanedTag { tag: Id, label: String },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct IntegrityReport {
+    pub checked_at: DateTime<Utc>,
+    /// Number of checked notes, including deleted ones
+    pub notes: usize,
+    pub problems: Vec<Problem>,
+}
+
+/// The result of `POST /admin/integrity/fix`
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Repair {
+    pub removed_tags: usize,
+    /// The problems that remain after the fixes
+    pub report: IntegrityReport,
+}
+
+/// Returns the internal links in the text, as written, and the notes they refer to
+///
+/// Absolute links are only recognized if they start with `base_url`, the public URL of the app.
+pub fn links(text: &str, base_url: Option<&str>) -> Vec<(String, Link)> {
+    let mut links = vec![];
+    for prefix in ["/note/", "/p/"] {
+        for (index, _) in text.match_indices(prefix) {
+            let start = match base_url {
+                Some(base_url) if text[..index].ends_with(base_url) => index - base_url.len(),
+                _ => index,
+            };
+            // relative links start after a delimiter, other matches are part of external URLs
+            let standalone = text[..start]
+                .chars()
+                .next_back()
+                .is_none_or(|c| c.is_whitespace() || ['(', '<', '[', '"', '\'', '='].contains(&c));
+            if !standalone {
+                continue;
+            }
+            let path = text[index..]
+                .split(|c: char| {
+                    c.is_whitespace() || [')', '>', ']', '"', '\'', '#', '?'].contains(&c)
+                })
+                .next()
+                .unwrap_or_default();
+            let mut segments = path[prefix.len()..].split('/');
+            let link = match (prefix, segments.next(), segments.next()) {
+                ("/note/", Some("slug"), Some(slug)) if !slug.is_empty() => {
+                    Link::Slug(slug.to_string())
+                }
+                ("/note/", Some(id), _) => match id.parse::<usize>() {
+                    Ok(id) => Link::Id(Id(id)),
+                    Err(_) => continue,
+                },
+                ("/p/", Some(slug), _) if !slug.is_empty() => Link::Slug(slug.to_string()),
+                _ => continue,
+            };
+            links.push((text[start..index + path.len()].to_string(), link));
+        }
+    }
+    links
+}
+
+/// Returns whether the note leads to an active note, directly or by following its merges
+fn resolves<'a, P: Persister<'a>>(data: &'a P, mut note: &'a Note) -> bool {
+    let mut visited = HashSet::new();
+    while note.visibility() == &Visibility::Deleted {
+        let Some(target) = note
+            .merged_into()
+            .filter(|target| visited.insert(**target))
+            .and_then(|target| data.note_with(*target, VisibilityFilter::All))
+        else {
+            return false;
+        };
+        note = target;
+    }
+    true
+}
+
+/// Checks all notes and tags of the storage backend
+pub fn check<'a, P: Persister<'a>>(
+    data: &'a P,
+    base_url: Option<&str>,
+    now: DateTime<Utc>,
+) -> IntegrityReport {
+    let mut problems = vec![];
+    let mut notes = 0;
+    for note in data.notes_with(VisibilityFilter::All) {
+        notes += 1;
+        if note.visibility() == &Visibility::Deleted {
+            if let Some(target) = note.merged_into() {
+                if !resolves(data, note) {
+                    problems.push(Problem::DanglingMerge {
+                        note: *note.id(),
+                        user: *note.user(),
+                        merged_into: *target,
+                    });
+                }
+            }
+            continue;
+        }
+        for (link, target) in links(&note.body().text(), base_url) {
+            let target = match target {
+                Link::Id(id) => data.note_with(id, VisibilityFilter::All),
+                Link::Slug(slug) => data
+                    .notes_with(VisibilityFilter::All)
+                    .find(|note| note.slug() == Some(slug.as_str())),
+            };
+            if !target.is_some_and(|target| resolves(data, target)) {
+                problems.push(Problem::BrokenLink {
+                    note: *note.id(),
+                    user: *note.user(),
+                    link,
+                });
+            }
+        }
+    }
+    let used = data
+        .notes()
+        .flat_map(|note| note.tags().map(|tag| *tag.id()))
+        .collect::<HashSet<Id>>();
+    problems.extend(
+        data.tags()
+            .filter(|tag| !used.contains(tag.id()))
+            .map(|tag| Problem::OrphanedTag {
+                tag: *tag.id(),
+                label: tag.label().to_string(),
+            }),
+    );
+    IntegrityReport {
+        checked_at: now,
+        notes,
+        problems,
+    }
+}
+
+/// Fixes the problems that don't need a decision of the owner, i.e. removes orphaned tags
+pub fn fix<P: for<'a> Persister<'a>>(
+    data: &mut P,
+    base_url: Option<&str>,
+    now: DateTime<Utc>,
+) -> Repair {
+    let removed_tags = data.collect_tags();
+    Repair {
+        removed_tags,
+        report: check(data, base_url, now),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::Draft;
+    use crate::models::User;
+    use crate::persistence::memory::InMemoryStorage;
+
+    #[test]
+    fn test_links() {
+        let text =
+            "See [one](/note/1), [two](/note/2/pdf) and <https://notes.example.com/p/fish/>.\n\
+            [three](/note/slug/three#top) /p/four https://other.example.com/note/5 /note/x";
+        assert_eq!(
+            links(text, Some("https://notes.example.com")),
+            vec![
+                ("/note/1".to_string(), Link::Id(Id(1))),
+                ("/note/2/pdf".to_string(), Link::Id(Id(2))),
+                (
+                    "/note/slug/three".to_string(),
+                    Link::Slug("three".to_string())
+                ),
+                (
+                    "https://notes.example.com/p/fish/".to_string(),
+                    Link::Slug("fish".to_string())
+                ),
+                ("/p/four".to_string(), Link::Slug("four".to_string())),
+            ]
+        );
+        assert_eq!(links(text, None).len(), 4);
+    }
+
+    #[test]
+    fn test_check() {
+        let mut data = InMemoryStorage::default();
+        let user = User::default();
+        let draft = |title: &str, body: &str, tags: &[&str]| {
+            Draft::new(
+                title.to_string(),
+                body.to_string(),
+                tags.iter().map(|tag| tag.to_string()).collect(),
+                Visibility::Private,
+            )
+        };
+        let target = *data.add_note(draft("Target", "", &[]), &user).id();
+        let source = *data.add_note(draft("Source", "", &[]), &user).id();
+        let gone = *data.add_note(draft("Gone", "", &["old"]), &user).id();
+        let linking = *data
+            .add_note(
+                draft(
+                    "Links",
+                    &format!(
+                        "[a](/note/{}) [b](/note/{}) [c](/note/{}) [d](/note/99)",
+                        target.0, source.0, gone.0
+                    ),
+                    &["new"],
+                ),
+                &user,
+            )
+            .id();
+        data.merge_notes(target, &[source]);
+        data.delete_note(gone);
+
+        let report = check(&data, None, Utc::now());
+        assert_eq!(report.notes, 4);
+        assert_eq!(
+            report.problems,
+            vec![
+                Problem::BrokenLink {
+                    note: linking,
+                    user: *user.id(),
+                    link: format!("/note/{}", gone.0),
+                },
+                Problem::BrokenLink {
+                    note: linking,
+                    user: *user.id(),
+                    link: "/note/99".to_string(),
+                },
+                Problem::OrphanedTag {
+                    tag: data.tag("old").map(|tag| *tag.id()).unwrap(),
+                    label: "old".to_string(),
+                },
+            ]
+        );
+
+        data.delete_note(target);
+        let report = check(&data, None, Utc::now());
+        assert!(report.problems.contains(&Problem::DanglingMerge {
+            note: source,
+            user: *user.id(),
+            merged_into: target,
+        }));
+    }
+}
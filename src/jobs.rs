@@ -0,0 +1,450 @@
+//! An in-process queue for background work
+//!
+//! A [`Job`] is a serializable payload with an async `run` method. Jobs are
+//! queued with [`JobQueue::enqueue`] or [`JobQueue::schedule`] and executed on the
+//! tokio runtime. Failed jobs are retried with an exponential backoff until
+//! [`Job::MAX_ATTEMPTS`] is reached, afterwards they are kept as failed for inspection.
+//!
+//! Every change of a job is passed to the [`JobStore`], which can persist the
+//! jobs so that they survive a restart. Jobs are identified by their
+//! [`Job::NAME`] to deserialize them again.
+//!
+//! Long-running jobs can publish their [`Progress`] with [`report_progress`].
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use axum::async_trait;
+use chrono::{TimeDelta, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, warn};
+
+use crate::models::job::{JobRecord, JobStatus, Progress};
+use crate::models::Id;
+
+/// The delay before the first retry of a failed job, doubled for every further attempt
+pub const BACKOFF: Duration = Duration::from_secs(1);
+
+/// The maximum delay between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// A unit of background work that needs the context `C` to run, e.g. the app state
+#[async_trait]
+pub trait Job<C: Sync>: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// Identifies the kind of job, must be unique
+    const NAME: &'static str;
+
+    /// The number of attempts before a job is marked as failed
+    const MAX_ATTEMPTS: u32 = 5;
+
+    async fn run(&self, context: &C) -> Result<(), String>;
+}
+
+/// Persistence hook for the [`JobQueue`]
+pub trait JobStore: Send + Sync {
+    /// Stores a new or changed job
+    fn save(&self, record: &JobRecord);
+
+    /// Removes a job that completed successfully
+    fn remove(&self, id: &Id);
+
+    /// Loads all stored jobs on startup
+    fn load(&self) -> Vec<JobRecord>;
+}
+
+/// A [`JobStore`] that does not store anything, all queued jobs are lost on restart
+pub struct VolatileStore;
+
+impl JobStore for VolatileStore {
+    fn save(&self, _record: &JobRecord) {}
+
+    fn remove(&self, _id: &Id) {}
+
+    fn load(&self) -> Vec<JobRecord> {
+        vec![]
+    }
+}
+
+/// Updates the [`Progress`] of a job
+type Reporter = Arc<dyn Fn(Progress) + Send + Sync>;
+
+tokio::task_local! {
+    /// The [`Reporter`] of the job that runs on the current task
+    static REPORTER: Reporter;
+}
+
+/// Records the progress of the job that runs on the current task
+///
+/// Does nothing when it is called outside of a job.
+pub fn report_progress(done: usize, total: usize) {
+    let _ = REPORTER.try_with(|reporter| reporter(Progress { done, total }));
+}
+
+type JobFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+
+/// Deserializes the payload of a job and runs it
+type Runner<C> = Arc<dyn Fn(Value, C) -> JobFuture + Send + Sync>;
+
+struct Inner<C> {
+    runners: RwLock<HashMap<&'static str, Runner<C>>>,
+    records: Mutex<BTreeMap<Id, JobRecord>>,
+    next_id: Mutex<usize>,
+    store: Arc<dyn JobStore>,
+    backoff: Duration,
+    sender: UnboundedSender<Id>,
+    receiver: Mutex<Option<UnboundedReceiver<Id>>>,
+}
+
+/// Handle to the job queue
+///
+/// The handle is cheap to clone and can be shared between all handlers.
+pub struct JobQueue<C> {
+    inner: Arc<Inner<C>>,
+}
+
+// Clone is manually implemented because Derive would require `C: Clone`
+impl<C> Clone for JobQueue<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<C: Clone + Send + Sync + 'static> JobQueue<C> {
+    /// Creates the queue with all jobs of the [`JobStore`]
+    ///
+    /// No jobs are executed before the queue is [started](`JobQueue::start`).
+    pub fn new(store: Arc<dyn JobStore>, backoff: Duration) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let records = store
+            .load()
+            .into_iter()
+            .map(|record| (*record.id(), record))
+            .collect::<BTreeMap<Id, JobRecord>>();
+        let next_id = records.keys().last().map_or(0, |id| usize::from(id) + 1);
+        Self {
+            inner: Arc::new(Inner {
+                runners: RwLock::new(HashMap::new()),
+                records: Mutex::new(records),
+                next_id: Mutex::new(next_id),
+                store,
+                backoff,
+                sender,
+                receiver: Mutex::new(Some(receiver)),
+            }),
+        }
+    }
+
+    /// Registers the [`Job`] type, so that queued jobs of this type can be executed
+    pub fn register<J: Job<C>>(&self) {
+        let runner: Runner<C> = Arc::new(|payload, context| {
+            Box::pin(async move {
+                let job: J = serde_json::from_value(payload)
+                    .map_err(|err| format!("Invalid payload: {err}"))?;
+                job.run(&context).await
+            })
+        });
+        self.inner
+            .runners
+            .write()
+            .expect("runners lock was poisoned")
+            .insert(J::NAME, runner);
+    }
+
+    /// Queues the job for immediate execution
+    pub fn enqueue<J: Job<C>>(&self, job: &J) -> Id {
+        self.schedule(job, Utc::now())
+    }
+
+    /// Queues the job for execution at `run_at`
+    pub fn schedule<J: Job<C>>(&self, job: &J, run_at: chrono::DateTime<Utc>) -> Id {
+        let id = {
+            let mut next_id = self.inner.next_id.lock().expect("mutex was poisoned");
+            *next_id += 1;
+            Id(*next_id - 1)
+        };
+        let payload = serde_json::to_value(job).expect("jobs can always be serialized");
+        let record = JobRecord::new(id, J::NAME.to_string(), payload, J::MAX_ATTEMPTS, run_at);
+        self.inner.store.save(&record);
+        self.inner
+            .records
+            .lock()
+            .expect("mutex was poisoned")
+            .insert(id, record);
+        dispatch(&self.inner, id, &run_at);
+        id
+    }
+
+    /// Returns all pending, running and failed jobs
+    pub fn jobs(&self) -> Vec<JobRecord> {
+        let records = self.inner.records.lock().expect("mutex was poisoned");
+        records.values().cloned().collect()
+    }
+
+    /// Starts executing the queued jobs on the tokio runtime
+    ///
+    /// Pending jobs that were loaded from the [`JobStore`] are re-queued.
+    /// Calling `start` more than once has no effect.
+    pub fn start(&self, context: C) {
+        let Some(mut receiver) = self
+            .inner
+            .receiver
+            .lock()
+            .expect("mutex was poisoned")
+            .take()
+        else {
+            return;
+        };
+        let stored = self
+            .jobs()
+            .into_iter()
+            .filter(|record| record.status() != JobStatus::Failed)
+            .collect::<Vec<JobRecord>>();
+        for record in stored {
+            dispatch(&self.inner, *record.id(), record.run_at());
+        }
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            while let Some(id) = receiver.recv().await {
+                tokio::spawn(execute(inner.clone(), context.clone(), id));
+            }
+        });
+    }
+}
+
+/// Sends the job to the worker at `run_at`
+fn dispatch<C>(inner: &Inner<C>, id: Id, run_at: &chrono::DateTime<Utc>) {
+    let sender = inner.sender.clone();
+    let delay = (*run_at - Utc::now()).to_std().unwrap_or_default();
+    if delay.is_zero() {
+        let _ = sender.send(id);
+        return;
+    }
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let _ = sender.send(id);
+    });
+}
+
+/// Returns the delay before the next attempt, after `attempts` failed attempts
+fn backoff(base: Duration, attempts: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// Runs a single attempt of the job `id` and records the outcome
+async fn execute<C: Clone + Send + Sync + 'static>(inner: Arc<Inner<C>>, context: C, id: Id) {
+    let (name, payload) = {
+        let mut records = inner.records.lock().expect("mutex was poisoned");
+        let Some(record) = records.get_mut(&id) else {
+            return;
+        };
+        record.start();
+        inner.store.save(record);
+        (record.name().to_string(), record.payload().clone())
+    };
+    let runner = inner
+        .runners
+        .read()
+        .expect("runners lock was poisoned")
+        .get(name.as_str())
+        .cloned();
+    let reporter: Reporter = {
+        let inner = inner.clone();
+        Arc::new(move |progress| {
+            let mut records = inner.records.lock().expect("mutex was poisoned");
+            if let Some(record) = records.get_mut(&id) {
+                record.set_progress(progress);
+                inner.store.save(record);
+            }
+        })
+    };
+    let result = match runner {
+        Some(runner) => REPORTER.scope(reporter, runner(payload, context)).await,
+        None => Err(format!("No runner registered for {name}")),
+    };
+
+    let mut records = inner.records.lock().expect("mutex was poisoned");
+    match result {
+        Ok(()) => {
+            debug!("Job {} [{}] succeeded", usize::from(id), name);
+            records.remove(&id);
+            inner.store.remove(&id);
+        }
+        Err(err) => {
+            let Some(record) = records.get_mut(&id) else {
+                return;
+            };
+            warn!(
+                "Job {} [{}] failed (attempt {}): {}",
+                usize::from(id),
+                name,
+                record.attempts(),
+                err
+            );
+            let delay = backoff(inner.backoff, record.attempts());
+            let retry_at =
+                Utc::now() + TimeDelta::from_std(delay).expect("backoff is always in range");
+            record.fail(err, retry_at);
+            inner.store.save(record);
+            if record.status() == JobStatus::Pending {
+                dispatch(&inner, id, &retry_at);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Counts the attempts of all jobs
+    type Context = Arc<AtomicU32>;
+
+    #[derive(Deserialize, Serialize)]
+    struct Flaky {
+        /// The number of attempts that fail
+        failures: u32,
+    }
+
+    #[async_trait]
+    impl Job<Context> for Flaky {
+        const NAME: &'static str = "flaky";
+        const MAX_ATTEMPTS: u32 = 3;
+
+        async fn run(&self, context: &Context) -> Result<(), String> {
+            let attempt = context.fetch_add(1, Ordering::SeqCst) + 1;
+            if attempt <= self.failures {
+                Err(format!("attempt {attempt} failed"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    /// Reports its progress and fails, so that the progress is kept
+    #[derive(Deserialize, Serialize)]
+    struct Steps;
+
+    #[async_trait]
+    impl Job<Context> for Steps {
+        const NAME: &'static str = "steps";
+        const MAX_ATTEMPTS: u32 = 1;
+
+        async fn run(&self, _context: &Context) -> Result<(), String> {
+            report_progress(1, 3);
+            report_progress(2, 3);
+            Err("step 3 failed".to_string())
+        }
+    }
+
+    /// Waits until the queue contains no pending or running jobs
+    async fn settle(queue: &JobQueue<Context>) {
+        for _ in 0..200 {
+            if queue
+                .jobs()
+                .iter()
+                .all(|job| job.status() == JobStatus::Failed)
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("Jobs did not finish");
+    }
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_secs(1);
+        assert_eq!(backoff(base, 1), Duration::from_secs(1));
+        assert_eq!(backoff(base, 3), Duration::from_secs(4));
+        assert_eq!(backoff(base, 30), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let queue = JobQueue::new(Arc::new(VolatileStore), Duration::from_millis(1));
+        queue.register::<Flaky>();
+        let attempts = Context::default();
+        queue.start(attempts.clone());
+
+        queue.enqueue(&Flaky { failures: 2 });
+        settle(&queue).await;
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert!(queue.jobs().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed() {
+        let queue = JobQueue::new(Arc::new(VolatileStore), Duration::from_millis(1));
+        queue.register::<Flaky>();
+        queue.start(Context::default());
+
+        let id = queue.enqueue(&Flaky { failures: 5 });
+        settle(&queue).await;
+        let jobs = queue.jobs();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id(), &id);
+        assert_eq!(jobs[0].status(), JobStatus::Failed);
+        assert_eq!(jobs[0].attempts(), 3);
+        assert_eq!(jobs[0].last_error(), Some("attempt 3 failed"));
+    }
+
+    #[tokio::test]
+    async fn test_progress() {
+        let queue = JobQueue::new(Arc::new(VolatileStore), Duration::from_millis(1));
+        queue.register::<Steps>();
+        queue.start(Context::default());
+
+        queue.enqueue(&Steps);
+        settle(&queue).await;
+        assert_eq!(
+            queue.jobs()[0].progress(),
+            Some(&Progress { done: 2, total: 3 })
+        );
+        // outside of jobs, the progress is ignored
+        report_progress(1, 1);
+    }
+
+    #[tokio::test]
+    async fn test_store() {
+        struct Store(Mutex<BTreeMap<Id, JobRecord>>);
+        impl JobStore for Store {
+            fn save(&self, record: &JobRecord) {
+                self.0.lock().unwrap().insert(*record.id(), record.clone());
+            }
+            fn remove(&self, id: &Id) {
+                self.0.lock().unwrap().remove(id);
+            }
+            fn load(&self) -> Vec<JobRecord> {
+                self.0.lock().unwrap().values().cloned().collect()
+            }
+        }
+        let store = Arc::new(Store(Mutex::default()));
+
+        // the queue is not started, the job is only stored
+        let queue = JobQueue::<Context>::new(store.clone(), Duration::from_millis(1));
+        queue.enqueue(&Flaky { failures: 0 });
+        assert_eq!(store.load().len(), 1);
+
+        // the job is restored and executed after a "restart"
+        let queue = JobQueue::new(store.clone(), Duration::from_millis(1));
+        queue.register::<Flaky>();
+        let attempts = Context::default();
+        queue.start(attempts.clone());
+        settle(&queue).await;
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert!(store.load().is_empty());
+
+        // Ids are not re-used
+        assert_eq!(queue.enqueue(&Flaky { failures: 0 }), Id(1));
+    }
+}
@@ -0,0 +1,153 @@
+//! A JSON extractor that rejects unknown fields
+//!
+//! [`axum::Json`] silently ignores fields that the target type does not know,
+//! so a typo like `"visiblity"` in a request goes unnoticed. [`StrictJson`]
+//! rejects these requests with `422 Unprocessable Entity` and lists the
+//! offending keys instead. The size of the body is limited by the
+//! [`DefaultBodyLimit`](axum::extract::DefaultBodyLimit) of the router.
+//!
+//! Bodies can also be sent as MessagePack, see [`msgpack`].
+use axum::async_trait;
+use axum::body::{Bytes, HttpBody};
+use axum::extract::FromRequest;
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use axum::BoxError;
+use serde::de::DeserializeOwned;
+use serde_json::error::Category;
+use tracing::info;
+
+use crate::msgpack;
+
+/// Deserializes the JSON body of a request, like [`axum::Json`], but fails on unknown fields
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StrictJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for StrictJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let packed = msgpack::is_msgpack(req.headers());
+        if !packed && !is_json(req.headers()) {
+            info!("--> 415");
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected request with `Content-Type: application/json`".to_string(),
+            ));
+        }
+        let body = Bytes::from_request(req, state).await.map_err(|err| {
+            info!("--> {}", err.status().as_u16());
+            (err.status(), err.body_text())
+        })?;
+        let decoded = if packed {
+            msgpack::to_json(&body).and_then(|json| decode(&json))
+        } else {
+            decode(&body)
+        };
+        decoded.map(StrictJson).inspect_err(|(status, _)| {
+            info!("--> {}", status.as_u16());
+        })
+    }
+}
+
+/// Returns the media type of the `Content-Type` header without parameters, in lowercase
+pub fn content_type(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())?;
+    Some(
+        content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase(),
+    )
+}
+
+/// Returns `true` if the content type is `application/json` or ends with `+json`
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(essence) = content_type(headers) else {
+        return false;
+    };
+    essence == "application/json"
+        || (essence.starts_with("application/") && essence.ends_with("+json"))
+}
+
+/// Deserializes `body` and fails if it contains fields that `T` does not know
+pub fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, (StatusCode, String)> {
+    let mut unknown = vec![];
+    let mut deserializer = serde_json::Deserializer::from_slice(body);
+    let value = serde_ignored::deserialize(&mut deserializer, |path| {
+        // `Option`s are part of the path as `?`, but not of the JSON document
+        let path = path.to_string();
+        let segments = path.split('.').filter(|segment| *segment != "?");
+        unknown.push(segments.collect::<Vec<&str>>().join("."));
+    })
+    .and_then(|value| deserializer.end().map(|_| value))
+    .map_err(|err| {
+        let status = match err.classify() {
+            Category::Data => StatusCode::UNPROCESSABLE_ENTITY,
+            Category::Io | Category::Syntax | Category::Eof => StatusCode::BAD_REQUEST,
+        };
+        (status, format!("Invalid JSON body: {err}"))
+    })?;
+    if !unknown.is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Unknown fields: {}", unknown.join(", ")),
+        ));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::{BulkTag, Draft};
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_decode() {
+        let draft: Draft = decode(br#"{"title": "Title", "body": "Body", "tags": ["a"]}"#).unwrap();
+        assert_eq!(draft.title(), "Title");
+
+        let (status, message) = decode::<Draft>(
+            br#"{"title": "", "body": "", "tags": [], "visiblity": "Public", "location": {"lat": 1, "lon": 2, "alt": 3}}"#,
+        )
+        .unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message, "Unknown fields: visiblity, location.alt");
+
+        let (status, _) = decode::<Draft>(br#"{"title": "", "body": ""}"#).unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = decode::<Draft>(br#"{"title": "#).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) =
+            decode::<BulkTag>(br#"{"notes": [], "add": [], "remove": []} []"#).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_is_json() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_json(&headers));
+        for (content_type, expected) in [
+            ("application/json", true),
+            ("application/json; charset=utf-8", true),
+            ("application/activity+json", true),
+            ("text/plain", false),
+            ("application/jsonp", false),
+        ] {
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+            assert_eq!(is_json(&headers), expected, "{content_type}");
+        }
+    }
+}
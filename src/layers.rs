@@ -0,0 +1,355 @@
+//! Middleware layers that are wrapped around the whole [`Router`](axum::Router)
+use std::sync::Arc;
+
+use axum::body::HttpBody;
+use axum::extract::{MatchedPath, State};
+use axum::http::{header, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use chrono_tz::Tz;
+use serde::Serialize;
+use tower_http::compression::predicate::{And, NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::RequestDecompressionLayer;
+use tracing::info;
+
+use crate::config::{CacheControlConfig, CompressionConfig, Config};
+use crate::dates;
+use crate::models::audit::AuditEntry;
+use crate::models::usage::DailyUsage;
+use crate::persistence::supervisor::Supervisor;
+use crate::persistence::Persister;
+use crate::{auth, AppState};
+
+/// The predicate deciding which responses are compressed
+pub type CompressionPredicate = And<And<SizeAbove, NotForContentType>, NotForContentType>;
+
+/// Compresses responses with the enabled algorithms
+///
+/// Small responses, images and event streams are never compressed.
+pub fn compression(config: &CompressionConfig) -> CompressionLayer<CompressionPredicate> {
+    let predicate = SizeAbove::new(config.min_size)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::const_new("text/event-stream"));
+    CompressionLayer::new()
+        .gzip(config.gzip)
+        .br(config.br)
+        .no_deflate()
+        .compress_when(predicate)
+}
+
+/// Decompresses request bodies (e.g. large bulk imports) with the enabled algorithms
+///
+/// The layer is fallible and must be wrapped in a
+/// [`HandleErrorLayer`](axum::error_handling::HandleErrorLayer) using [`decompression_error`]
+pub fn decompression(config: &CompressionConfig) -> RequestDecompressionLayer {
+    RequestDecompressionLayer::new()
+        .gzip(config.gzip)
+        .br(config.br)
+        .no_deflate()
+}
+
+/// Converts errors of the [`decompression`] layer into a response
+pub async fn decompression_error(err: BoxError) -> (StatusCode, String) {
+    info!("--> 400 [{}]", err);
+    (
+        StatusCode::BAD_REQUEST,
+        format!("Request body could not be decompressed: {err}"),
+    )
+}
+
+/// The kinds of routes with different [`CacheControlConfig`] policies
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CachePolicy {
+    /// Content that never changes under its URL. No route serves such content yet,
+    /// it is meant for attachment blobs.
+    Immutable,
+    /// Pages that are the same for every visitor
+    Public,
+    /// Responses that depend on the user sending the request
+    Private,
+}
+
+impl CachePolicy {
+    /// Returns the policy of the route, e.g. `/p/:slug`
+    pub fn of(route: &str) -> Self {
+        match route {
+            "/p/:slug" | "/sitemap.xml" => CachePolicy::Public,
+            _ => CachePolicy::Private,
+        }
+    }
+
+    fn header<'a>(&self, config: &'a CacheControlConfig) -> &'a header::HeaderValue {
+        match self {
+            CachePolicy::Immutable => &config.immutable,
+            CachePolicy::Public => &config.public,
+            CachePolicy::Private => &config.private,
+        }
+    }
+}
+
+/// Sets the `Cache-Control` header of responses by the [`CachePolicy`] of their route
+///
+/// Must be added with [`Router::route_layer`](axum::Router::route_layer), so that the
+/// route is known. Headers set by handlers are kept. Errors of public routes are not
+/// cached like the pages themselves, they get the private policy.
+pub async fn cache_control<B>(
+    State(config): State<Arc<Config>>,
+    route: Option<MatchedPath>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(request).await;
+    let policy = match route {
+        Some(route) if response.status().is_success() || response.status().is_redirection() => {
+            CachePolicy::of(route.as_str())
+        }
+        _ => CachePolicy::Private,
+    };
+    response
+        .headers_mut()
+        .entry(header::CACHE_CONTROL)
+        .or_insert_with(|| policy.header(&config.cache_control).clone());
+    response
+}
+
+/// A problem details object (RFC 9457), sent as `application/problem+json`
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    kind: String,
+    title: String,
+    status: u16,
+    detail: String,
+}
+
+impl Problem {
+    /// Constructs a [`Problem`] without a specific type, titled by the status code
+    pub fn new(status: StatusCode, detail: String) -> Self {
+        Self {
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail,
+        }
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let body = serde_json::to_string(&self).expect("problems can always be serialized");
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            body,
+        )
+            .into_response()
+    }
+}
+
+/// Answers requests that take longer than the [`TimeoutConfig`](crate::config::TimeoutConfig)
+/// of their route with `504 Gateway Timeout`
+///
+/// Must be added with [`Router::route_layer`](axum::Router::route_layer), so that the
+/// route is known. Only the handler is limited, the body of a streamed response (e.g.
+/// `/events`) can take longer. Handlers are only interrupted while they wait, e.g. for I/O.
+pub async fn timeout<B>(
+    State(config): State<Arc<Config>>,
+    route: Option<MatchedPath>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let duration = match &route {
+        Some(route) => config.timeouts.of(route.as_str()),
+        None => config.timeouts.default,
+    };
+    match tokio::time::timeout(duration, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            info!("--> 504 [after {:?}]", duration);
+            Problem::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                format!("The request did not complete within {duration:?}"),
+            )
+            .into_response()
+        }
+    }
+}
+
+/// Rejects all requests that change data with `503 Service Unavailable` while the
+/// storage backend is down, see [`Supervisor`]
+///
+/// The `Retry-After` header tells clients when the next reconnect is attempted.
+/// Reads are still passed to the handlers.
+pub async fn reject_writes<B>(
+    State(supervisor): State<Supervisor>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let read = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if read || supervisor.is_up() {
+        return next.run(request).await;
+    }
+    let retry_after = supervisor.retry_after(&chrono::Utc::now()).as_secs();
+    info!("--> 503 [storage is down, retry after {}s]", retry_after);
+    let mut response = Problem::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "The storage backend is not available, changes can't be saved".to_string(),
+    )
+    .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, retry_after.into());
+    response
+}
+
+/// Adds requests that an admin sent while impersonating a user to the audit log of the user
+///
+/// Must be added with [`Router::route_layer`](axum::Router::route_layer) outside of
+/// [`auth::require_scope`], so that rejected requests are recorded as well.
+pub async fn record_audit<P, B>(
+    State(state): State<AppState<P>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response
+where
+    P: for<'a> Persister<'a> + Send,
+{
+    let Some(admin) = auth::impersonator(&state.sessions, request.headers()) else {
+        return next.run(request).await;
+    };
+    let user = {
+        let data = state.data.lock().expect("mutex was poisoned");
+        auth::requester(
+            &*data,
+            &state.sessions,
+            request.headers(),
+            state.config.trusts_user_header(),
+        )
+    };
+    let action = format!("{} {}", request.method(), request.uri().path());
+    let response = next.run(request).await;
+    if let Some(user) = user {
+        info!(
+            "[admin {} impersonating user {}]",
+            usize::from(admin),
+            usize::from(user)
+        );
+        let entry = AuditEntry::new(
+            user,
+            admin,
+            action,
+            response.status().as_u16(),
+            chrono::Utc::now(),
+        );
+        let mut data = state.data.lock().expect("mutex was poisoned");
+        data.record_audit(entry);
+    }
+    response
+}
+
+/// Adds the request to the [`DailyUsage`] of the user who sent it, unless `NOTE_TRACK_USAGE` is off
+///
+/// Must be added with [`Router::route_layer`](axum::Router::route_layer), so that only requests
+/// to known routes are counted. Requests with invalid credentials or of unknown users are not
+/// counted. The size of streamed request bodies is taken from their `Content-Length` header.
+pub async fn record_usage<P, B: HttpBody>(
+    State(state): State<AppState<P>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response
+where
+    P: for<'a> Persister<'a> + Send,
+{
+    if !state.config.track_usage {
+        return next.run(request).await;
+    }
+    let user = {
+        let data = state.data.lock().expect("mutex was poisoned");
+        auth::requester(
+            &*data,
+            &state.sessions,
+            request.headers(),
+            state.config.trusts_user_header(),
+        )
+    };
+    let mutation = !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    let received = request.body().size_hint().exact().unwrap_or_else(|| {
+        request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0)
+    });
+    let response = next.run(request).await;
+    if let Some(user) = user {
+        let sent = response.body().size_hint().exact().unwrap_or(0);
+        let mut data = state.data.lock().expect("mutex was poisoned");
+        let timezone = data
+            .user(user)
+            .map_or(Tz::UTC, |user| *user.preferences().timezone());
+        let usage = DailyUsage::request(dates::today(&timezone), mutation, received, sent);
+        data.record_usage(user, usage);
+    }
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::config::TimeoutConfig;
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let mut timeouts = TimeoutConfig {
+            default: Duration::from_millis(10),
+            ..TimeoutConfig::default()
+        };
+        timeouts
+            .routes
+            .insert("/slow/long".to_string(), Duration::from_secs(10));
+        let config = Arc::new(Config {
+            timeouts,
+            ..Config::default()
+        });
+        let slow = || async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            "done"
+        };
+        let router = Router::new()
+            .route("/slow", get(slow))
+            .route("/slow/long", get(slow))
+            .route_layer(middleware::from_fn_with_state(config, timeout));
+
+        let request = |uri| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let res = router.clone().oneshot(request("/slow")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let problem = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(problem["status"], 504);
+        assert_eq!(problem["title"], "Gateway Timeout");
+
+        let res = router.oneshot(request("/slow/long")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
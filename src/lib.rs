@@ -0,0 +1,97 @@
+//! A small note taking app, built as a proof of concept for `axum`
+//!
+//! The binary only reads the configuration and starts the server or the terminal UI.
+//! The library contains everything else, so that benchmarks and integration tests
+//! can use the HTTP API, the models and the storage backends directly.
+use std::sync::{Arc, Mutex};
+
+use crate::activitypub::Federation;
+use crate::auth::oidc::OidcClient;
+use crate::auth::session::Sessions;
+use crate::capture::Capturer;
+use crate::config::Config;
+use crate::indexer::Indexer;
+use crate::jobs::JobQueue;
+use crate::mailer::Mailer;
+use crate::notifier::Notifier;
+use crate::persistence::supervisor::Supervisor;
+use crate::persistence::Persister;
+
+pub mod activitypub;
+pub mod app;
+pub mod auth;
+pub mod bus;
+pub mod cache;
+pub mod capture;
+pub mod config;
+pub mod csv_export;
+pub mod dates;
+pub mod digest;
+pub mod events;
+pub mod idempotency;
+pub mod indexer;
+pub mod integrity;
+pub mod jobs;
+pub mod json;
+pub mod layers;
+pub mod mailer;
+pub mod models;
+pub mod moderation;
+pub mod msgpack;
+pub mod notifier;
+pub mod pdf;
+pub mod persistence;
+pub mod policy;
+pub mod publish;
+pub mod render;
+pub mod report;
+pub mod revisions;
+pub mod search;
+pub mod server;
+pub mod share;
+pub mod site;
+pub mod stats;
+pub mod tasks;
+pub mod tui;
+
+/// The shared state of all handlers and background jobs
+pub struct AppState<P>
+where
+    P: for<'a> Persister<'a>,
+{
+    // Using the std::sync::Mutex here instead of axum's async Mutex because
+    // this PoC does not use IO-heavy operations.
+    // https://docs.rs/tokio/1.25.0/tokio/sync/struct.Mutex.html#which-kind-of-mutex-should-you-use
+    pub data: Arc<Mutex<P>>,
+    pub indexer: Indexer,
+    pub config: Arc<Config>,
+    pub sessions: Sessions,
+    pub oidc: Option<Arc<OidcClient>>,
+    pub federation: Option<Arc<Federation>>,
+    pub notifier: Arc<dyn Notifier>,
+    pub mailer: Arc<dyn Mailer>,
+    pub jobs: JobQueue<AppState<P>>,
+    /// The health of the storage backend
+    pub storage: Supervisor,
+    /// Fetches web pages for `POST /capture`
+    pub capturer: Capturer,
+}
+
+// Clone is manually implemented because Derive does not work with the trait
+impl<P: for<'a> Persister<'a>> Clone for AppState<P> {
+    fn clone(&self) -> Self {
+        AppState {
+            data: self.data.clone(),
+            indexer: self.indexer.clone(),
+            config: self.config.clone(),
+            sessions: self.sessions.clone(),
+            oidc: self.oidc.clone(),
+            federation: self.federation.clone(),
+            notifier: self.notifier.clone(),
+            mailer: self.mailer.clone(),
+            jobs: self.jobs.clone(),
+            storage: self.storage.clone(),
+            capturer: self.capturer.clone(),
+        }
+    }
+}
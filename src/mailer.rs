@@ -0,0 +1,88 @@
+//! Delivery of emails, e.g. the [digests](crate::digest)
+//!
+//! Emails are sent by background jobs, so a failing [`Mailer`] is retried
+//! without slowing down the request.
+use anyhow::{Context, Result};
+use axum::async_trait;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use tracing::info;
+
+use crate::config::SmtpConfig;
+
+/// A plain text email
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// A channel to send emails
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), String>;
+}
+
+/// A [`Mailer`] that only logs all emails
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        info!("Email to {}: {}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+/// A [`Mailer`] that sends all emails via an SMTP server
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &SmtpConfig) -> Result<Self> {
+        let mut builder = if config.starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .with_context(|| format!("Invalid SMTP host: {}", config.host))?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+        };
+        builder = builder.port(config.port);
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            from: config
+                .from
+                .parse()
+                .with_context(|| format!("Invalid sender address: {}", config.from))?,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        let to = mail
+            .to
+            .parse::<Mailbox>()
+            .map_err(|err| format!("Invalid address {}: {err}", mail.to))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())
+            .map_err(|err| format!("Invalid email: {err}"))?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|err| format!("Unable to send the email to {}: {err}", mail.to))?;
+        Ok(())
+    }
+}
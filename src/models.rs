@@ -1,12 +1,51 @@
 //! Contains the main data structures used in the app
 //!
 //! The data structures try to use a style that could support both relational and document-based databases
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::auth::ANONYMOUS_USER;
+use crate::models::integration::Integrations;
+use crate::models::preferences::Preferences;
+use crate::models::profile::Profile;
+use crate::models::tag_rule::TagRules;
+use crate::models::template::Templates;
+
+pub mod access;
+pub mod audit;
+pub mod autosave;
+pub mod backup;
+pub mod body;
+pub mod comment;
+pub mod export;
+pub mod fieldset;
+pub mod follower;
+pub mod format;
+pub mod geo;
+pub mod idempotency;
+pub mod integration;
+pub mod job;
+pub mod kind;
+pub mod lifecycle;
+pub mod mention;
+pub mod moderation;
 pub mod note;
+pub mod preferences;
+pub mod profile;
+pub mod query;
+pub mod review;
+pub mod tag_rule;
+pub mod template;
+pub mod title;
+pub mod token;
+pub mod trash;
+pub mod undo;
+pub mod usage;
 
 /// Id represents a foreign and/or primary key
-#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
 pub struct Id(pub usize);
 
 impl From<Id> for usize {
@@ -27,17 +66,47 @@ impl From<usize> for Id {
     }
 }
 
+/// The maximum number of characters of a [`Tag`] description
+pub const MAX_TAG_DESCRIPTION: usize = 500;
+
 /// Tags are labels added to individual notes
+///
+/// Tags are shared by all users. Every note contains a copy of its tags, including
+/// the optional color and description, so that clients can style them.
 #[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct Tag {
     id: Id,
     label: String,
+    /// A color to display the tag with, as `#rrggbb`
+    #[serde(default)]
+    color: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
 }
 
 impl Tag {
-    /// Constructs a new [`Tag`]
+    /// Constructs a new [`Tag`] without color and description
     pub fn new(id: Id, label: String) -> Self {
-        Self { id, label }
+        Self {
+            id,
+            label,
+            color: None,
+            description: None,
+        }
+    }
+
+    /// Replaces the color and description
+    pub fn set_meta(&mut self, meta: TagMeta) {
+        self.color = meta.color;
+        self.description = meta.description;
+    }
+
+    pub fn color(&self) -> Option<&str> {
+        self.color.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
     }
 
     /// Returns the primary key of the [`Tag`]
@@ -51,30 +120,293 @@ impl Tag {
     }
 }
 
+/// The payload of `PUT /tag/:id`, the metadata of a [`Tag`]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TagMeta {
+    #[serde(default)]
+    color: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+impl TagMeta {
+    pub fn new(color: Option<String>, description: Option<String>) -> Self {
+        Self { color, description }
+    }
+
+    /// Checks that the color is a hex color like `#1e90ff` and that the
+    /// description is not longer than [`MAX_TAG_DESCRIPTION`]
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(color) = &self.color {
+            let valid = color.len() == 7
+                && color.starts_with('#')
+                && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+            if !valid {
+                return Err(format!("Invalid color `{color}`, expected e.g. `#1e90ff`"));
+            }
+        }
+        if let Some(description) = &self.description {
+            if description.chars().count() > MAX_TAG_DESCRIPTION {
+                return Err(format!(
+                    "The description must not be longer than {MAX_TAG_DESCRIPTION} characters"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Usage statistics of a [`Tag`] for a single [`User`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TagStats {
+    #[serde(flatten)]
+    tag: Tag,
+    /// Number of active notes of the user with this tag
+    count: usize,
+    /// The latest update of a note of the user with this tag
+    last_used: Option<DateTime<Utc>>,
+}
+
+impl TagStats {
+    /// Constructs [`TagStats`] for a [`Tag`] without any notes
+    pub fn new(tag: Tag) -> Self {
+        Self {
+            tag,
+            count: 0,
+            last_used: None,
+        }
+    }
+
+    /// Adds a note that was last updated at `used`
+    pub fn add_use(&mut self, used: &DateTime<Utc>) {
+        self.count += 1;
+        if self.last_used.is_none_or(|last| &last < used) {
+            self.last_used = Some(*used);
+        }
+    }
+
+    pub fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    #[allow(dead_code)] // needed for unittests
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    #[allow(dead_code)] // needed for unittests
+    pub fn last_used(&self) -> Option<&DateTime<Utc>> {
+        self.last_used.as_ref()
+    }
+}
+
+/// The identity of a [`User`] at an external identity provider
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct ExternalIdentity {
+    /// The identity provider, e.g. `https://accounts.google.com`
+    issuer: String,
+    /// The unique Id of the user at the identity provider
+    subject: String,
+}
+
+impl ExternalIdentity {
+    /// Constructs a new [`ExternalIdentity`]
+    pub fn new(issuer: String, subject: String) -> Self {
+        Self { issuer, subject }
+    }
+}
+
 /// User management is not built in yet and this struct acts only as a placeholder
-#[derive(Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct User {
     id: Id,
     name: String,
+    /// Users that log in via an external identity provider are linked to their identity
+    identity: Option<ExternalIdentity>,
+    /// If set, the account is scheduled for deletion and all data of the
+    /// user will be purged at this time
+    purge_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    preferences: Preferences,
+    #[serde(default)]
+    profile: Profile,
+    /// Rules that set the visibility of notes by their tags
+    #[serde(default)]
+    tag_rules: TagRules,
+    /// Templates of notes with typed fields
+    #[serde(default)]
+    templates: Templates,
+    /// Chat services that notes can be sent to
+    #[serde(default)]
+    integrations: Integrations,
 }
 
 impl User {
+    /// Constructs a new active [`User`]
+    pub fn new(id: Id, name: String) -> Self {
+        Self {
+            id,
+            name,
+            identity: None,
+            purge_at: None,
+            preferences: Preferences::default(),
+            profile: Profile::default(),
+            tag_rules: TagRules::default(),
+            templates: Templates::default(),
+            integrations: Integrations::default(),
+        }
+    }
+
+    /// Links the [`User`] to an [`ExternalIdentity`]
+    pub fn with_identity(mut self, identity: ExternalIdentity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
     /// Returns the primary key of the [`User`]
     pub fn id(&self) -> &Id {
         &self.id
     }
+
+    /// Returns the name of the [`User`]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the [`ExternalIdentity`], if the user logs in via an identity provider
+    pub fn identity(&self) -> Option<&ExternalIdentity> {
+        self.identity.as_ref()
+    }
+
+    /// Returns the time when the account will be purged, if it is scheduled for deletion
+    pub fn purge_at(&self) -> Option<&DateTime<Utc>> {
+        self.purge_at.as_ref()
+    }
+
+    /// Schedules the account for deletion
+    ///
+    /// The user can't log in anymore and all data will be purged at `purge_at`
+    pub fn schedule_deletion(&mut self, purge_at: DateTime<Utc>) {
+        self.purge_at = Some(purge_at);
+    }
+
+    pub fn preferences(&self) -> &Preferences {
+        &self.preferences
+    }
+
+    pub fn set_preferences(&mut self, preferences: Preferences) {
+        self.preferences = preferences;
+    }
+
+    /// Returns the [`Profile`] that other users see, e.g. as author of notes
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = profile;
+    }
+
+    pub fn tag_rules(&self) -> &TagRules {
+        &self.tag_rules
+    }
+
+    pub fn set_tag_rules(&mut self, rules: TagRules) {
+        self.tag_rules = rules;
+    }
+
+    pub fn templates(&self) -> &Templates {
+        &self.templates
+    }
+
+    pub fn set_templates(&mut self, templates: Templates) {
+        self.templates = templates;
+    }
+
+    pub fn integrations(&self) -> &Integrations {
+        &self.integrations
+    }
+
+    pub fn set_integrations(&mut self, integrations: Integrations) {
+        self.integrations = integrations;
+    }
+
+    /// Returns `true` unless the account is scheduled for deletion
+    pub fn is_active(&self) -> bool {
+        self.purge_at.is_none()
+    }
 }
 
-/// [Notes](`note::Note`) can have different types of visibility to be either private or public
+/// [Notes](`note::Note`) can have different types of visibility that define which
+/// other users can read them. The owner can always read their notes.
 /// For simplicity, Visibility can also be used to soft-delete `Note`s.
 #[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum Visibility {
+    /// Only the owner
     #[default]
     Private,
+    /// Everybody who knows the Id or slug of the note, but it is not listed
+    Unlisted,
+    /// All signed-in users, but not the anonymous user
+    Workspace,
+    /// Everybody, also remote followers via ActivityPub
     Public,
     Deleted,
 }
 
+/// How a user accesses a note of another user
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    /// The note is requested by its Id or slug, e.g. `GET /note/:id`
+    Link,
+    /// The note is part of a list, e.g. `GET /mentions`
+    Listing,
+}
+
+impl Visibility {
+    /// Returns `true` if a user who does not own the note may read it
+    pub fn allows(&self, user: &Id, access: Access) -> bool {
+        match self {
+            Visibility::Private | Visibility::Deleted => false,
+            Visibility::Unlisted => access == Access::Link,
+            Visibility::Workspace => user != &ANONYMOUS_USER,
+            Visibility::Public => true,
+        }
+    }
+}
+
+/// Selects [Notes](`note::Note`) by their [`Visibility`]
+///
+/// Deleted notes are only soft-deleted, the filter defines if they should be
+/// included in a lookup or not.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum VisibilityFilter {
+    /// All notes that are not deleted
+    #[default]
+    Active,
+    /// Only deleted notes, e.g. for a trash view
+    Deleted,
+    /// All notes, regardless of their visibility
+    All,
+}
+
+impl VisibilityFilter {
+    /// Returns `true` if a note with the given [`Visibility`] passes the filter
+    pub fn matches(&self, visibility: &Visibility) -> bool {
+        match self {
+            VisibilityFilter::Active => visibility != &Visibility::Deleted,
+            VisibilityFilter::Deleted => visibility == &Visibility::Deleted,
+            VisibilityFilter::All => true,
+        }
+    }
+
+    /// Returns `true` if the note passes the filter, expired notes are not active anymore
+    pub fn includes(&self, note: &note::Note, now: &DateTime<Utc>) -> bool {
+        self.matches(note.visibility())
+            && !(self == &VisibilityFilter::Active && note.is_expired(now))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -95,6 +427,24 @@ mod test {
         assert_ne!(Id(12), Id(66))
     }
 
+    #[test]
+    fn test_tag_meta() {
+        let meta = |color: &str| TagMeta::new(Some(color.to_string()), None);
+        assert!(TagMeta::default().validate().is_ok());
+        assert!(meta("#1e90FF").validate().is_ok());
+        assert!(meta("1e90ff").validate().is_err());
+        assert!(meta("#1e90f").validate().is_err());
+        assert!(meta("#1e90fg").validate().is_err());
+        assert!(meta("#1e90é").validate().is_err());
+        let long = TagMeta::new(None, Some("a".repeat(MAX_TAG_DESCRIPTION + 1)));
+        assert!(long.validate().is_err());
+
+        let mut tag = Tag::new(Id(1), "foo".to_string());
+        tag.set_meta(meta("#000000"));
+        assert_eq!(tag.color(), Some("#000000"));
+        assert!(tag.description().is_none());
+    }
+
     #[test]
     fn test_tag_equality() {
         let tag_a = Tag::new(Id(12), "foobar".to_string());
@@ -108,4 +458,66 @@ mod test {
         assert_ne!(tag_a, Tag::new(Id(12), "foo".to_string()));
         assert_ne!(tag_a, Tag::new(Id(1), "foobar".to_string()));
     }
+
+    #[test]
+    fn test_visibility_filter() {
+        assert!(VisibilityFilter::Active.matches(&Visibility::Public));
+        assert!(VisibilityFilter::Active.matches(&Visibility::Private));
+        assert!(!VisibilityFilter::Active.matches(&Visibility::Deleted));
+
+        assert!(!VisibilityFilter::Deleted.matches(&Visibility::Public));
+        assert!(!VisibilityFilter::Deleted.matches(&Visibility::Private));
+        assert!(VisibilityFilter::Deleted.matches(&Visibility::Deleted));
+
+        assert!(VisibilityFilter::All.matches(&Visibility::Public));
+        assert!(VisibilityFilter::All.matches(&Visibility::Private));
+        assert!(VisibilityFilter::All.matches(&Visibility::Deleted));
+    }
+
+    #[test]
+    fn test_visibility_allows() {
+        let user = Id(1);
+        assert!(!Visibility::Private.allows(&user, Access::Link));
+        assert!(!Visibility::Deleted.allows(&user, Access::Link));
+
+        assert!(Visibility::Unlisted.allows(&user, Access::Link));
+        assert!(!Visibility::Unlisted.allows(&user, Access::Listing));
+
+        assert!(Visibility::Workspace.allows(&user, Access::Listing));
+        assert!(!Visibility::Workspace.allows(&ANONYMOUS_USER, Access::Link));
+
+        assert!(Visibility::Public.allows(&ANONYMOUS_USER, Access::Listing));
+
+        // stored notes with the old variants are still valid
+        let visibility: Visibility = serde_json::from_str(r#""Public""#).unwrap();
+        assert_eq!(visibility, Visibility::Public);
+        let visibility: Visibility = serde_json::from_str(r#""Unlisted""#).unwrap();
+        assert_eq!(visibility, Visibility::Unlisted);
+    }
+
+    #[test]
+    fn test_tag_stats() {
+        let mut stats = TagStats::new(Tag::new(Id(1), "foo".to_string()));
+        assert_eq!(stats.count(), 0);
+        assert!(stats.last_used().is_none());
+
+        let early = Utc::now();
+        let late = early + chrono::Duration::seconds(10);
+        stats.add_use(&late);
+        stats.add_use(&early);
+        assert_eq!(stats.count(), 2);
+        assert_eq!(stats.last_used(), Some(&late));
+    }
+
+    #[test]
+    fn test_user_deletion() {
+        let mut user = User::new(Id(1), "foo".to_string());
+        assert!(user.is_active());
+        assert!(user.purge_at().is_none());
+
+        let purge_at = Utc::now();
+        user.schedule_deletion(purge_at);
+        assert!(!user.is_active());
+        assert_eq!(user.purge_at(), Some(&purge_at));
+    }
 }
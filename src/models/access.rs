@@ -0,0 +1,38 @@
+//! The access log of shared notes, so that owners see who opened them, see `GET /note/:id/access-log`
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::Id;
+
+/// Number of entries that are kept per note, older entries are dropped
+pub const ACCESS_LOG_CAPACITY: usize = 100;
+
+/// A user other than the owner opened a note
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccessEntry {
+    note: Id,
+    /// The user who opened the note, `None` if the access log is anonymized.
+    /// Anonymous visitors of public pages are the user `0`.
+    user: Option<Id>,
+    at: DateTime<Utc>,
+}
+
+impl AccessEntry {
+    /// Constructs a new [`AccessEntry`]
+    pub fn new(note: Id, user: Option<Id>, at: DateTime<Utc>) -> Self {
+        Self { note, user, at }
+    }
+
+    /// Returns the Id of the opened [`Note`](crate::models::note::Note)
+    pub fn note(&self) -> &Id {
+        &self.note
+    }
+
+    pub fn user(&self) -> Option<&Id> {
+        self.user.as_ref()
+    }
+
+    pub fn at(&self) -> &DateTime<Utc> {
+        &self.at
+    }
+}
@@ -0,0 +1,45 @@
+//! The audit log of requests that admins sent on behalf of a user, see `GET /me/audit-log`
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::Id;
+
+/// Number of entries that are kept per user, older entries are dropped
+pub const AUDIT_LOG_CAPACITY: usize = 1000;
+
+/// An admin impersonated the user, or sent a request while impersonating them
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AuditEntry {
+    /// The impersonated user
+    user: Id,
+    admin: Id,
+    /// The method and path of the request, e.g. `PUT /note/3`
+    request: String,
+    status: u16,
+    at: DateTime<Utc>,
+}
+
+impl AuditEntry {
+    /// Constructs a new [`AuditEntry`]
+    pub fn new(user: Id, admin: Id, request: String, status: u16, at: DateTime<Utc>) -> Self {
+        Self {
+            user,
+            admin,
+            request,
+            status,
+            at,
+        }
+    }
+
+    pub fn user(&self) -> &Id {
+        &self.user
+    }
+
+    pub fn admin(&self) -> &Id {
+        &self.admin
+    }
+
+    pub fn at(&self) -> &DateTime<Utc> {
+        &self.at
+    }
+}
@@ -0,0 +1,80 @@
+//! Unsaved working copies of notes, so that editors can autosave every few seconds
+//!
+//! A working copy is stored separately from its [`Note`](crate::models::note::Note)
+//! and does not create revisions until it is committed.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::note::Draft;
+use crate::models::Id;
+
+/// The working copy of a note, stored with `PUT /note/:id/draft`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct WorkingCopy {
+    note: Id,
+    user: Id,
+    draft: Draft,
+    /// The number of words of the body, for a live word count in editors
+    word_count: usize,
+    saved: DateTime<Utc>,
+    expires: DateTime<Utc>,
+}
+
+impl WorkingCopy {
+    pub fn new(note: Id, user: Id, draft: Draft, expires: DateTime<Utc>) -> Self {
+        Self {
+            note,
+            user,
+            word_count: draft.body().split_whitespace().count(),
+            draft,
+            saved: Utc::now(),
+            expires,
+        }
+    }
+
+    pub fn note(&self) -> &Id {
+        &self.note
+    }
+
+    pub fn user(&self) -> &Id {
+        &self.user
+    }
+
+    pub fn draft(&self) -> &Draft {
+        &self.draft
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.word_count
+    }
+
+    pub fn expires(&self) -> &DateTime<Utc> {
+        &self.expires
+    }
+
+    /// Returns `true` if the working copy expired before `now`
+    pub fn expired(&self, now: &DateTime<Utc>) -> bool {
+        &self.expires < now
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Visibility;
+
+    #[test]
+    fn test_working_copy() {
+        let draft = Draft::new(
+            "Title".to_string(),
+            "Three short\nwords".to_string(),
+            vec![],
+            Visibility::Private,
+        );
+        let now = Utc::now();
+        let copy = WorkingCopy::new(Id(1), Id(2), draft, now);
+        assert_eq!(copy.word_count(), 3);
+        assert!(!copy.expired(&now));
+        assert!(copy.expired(&(now + chrono::TimeDelta::seconds(1))));
+    }
+}
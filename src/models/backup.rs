@@ -0,0 +1,412 @@
+//! Portable archives of all data, for `POST /admin/backup` and `POST /admin/restore`
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::models::comment::Comment;
+use crate::models::follower::Follower;
+use crate::models::note::Note;
+use crate::models::query::SavedSearch;
+use crate::models::{Id, Tag, User};
+
+/// Upgrades an archive from one schema version to the next
+type Migration = fn(&mut Map<String, Value>);
+
+/// The migrations of older archives, the first one upgrades version 1 to version 2
+///
+/// Every incompatible change of the format appends a migration, which also increases
+/// the [`SCHEMA_VERSION`], so that older archives can still be restored.
+const MIGRATIONS: &[Migration] = &[];
+
+/// The version of the archive format, increased on every incompatible change
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// The highest Id of a restored record, so that the Ids of new records can't run out
+pub const MAX_ID: usize = u32::MAX as usize;
+
+/// Upgrades an archive of an older schema version to the [`SCHEMA_VERSION`]
+///
+/// Archives of unknown or newer versions are not changed, [`Backup::validate`] reports them.
+pub fn migrate(archive: &mut Value) {
+    upgrade(archive, MIGRATIONS);
+}
+
+/// Applies the `migrations` that follow the schema version of the archive, one after another
+fn upgrade(archive: &mut Value, migrations: &[Migration]) {
+    let Some(object) = archive.as_object_mut() else {
+        return;
+    };
+    let Some(mut version) = object.get("schema_version").and_then(Value::as_u64) else {
+        return;
+    };
+    while let Some(migration) = version
+        .checked_sub(1)
+        .and_then(|index| migrations.get(usize::try_from(index).ok()?))
+    {
+        migration(object);
+        version += 1;
+        object.insert("schema_version".to_string(), version.into());
+    }
+}
+
+/// All users with their notes, tags, saved searches, comments, followers and favorites
+///
+/// Revisions, sessions, service tokens, views and other short-lived data are not part of a backup.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Backup {
+    schema_version: u32,
+    created: DateTime<Utc>,
+    users: Vec<User>,
+    /// All notes, including deleted ones
+    notes: Vec<Note>,
+    tags: Vec<Tag>,
+    searches: Vec<SavedSearch>,
+    comments: Vec<Comment>,
+    followers: Vec<Follower>,
+    /// The favorite notes (second Id) of each user (first Id)
+    favorites: Vec<(Id, Id)>,
+}
+
+/// How `POST /admin/restore` treats the existing data
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreMode {
+    /// All existing data is removed first
+    #[default]
+    Replace,
+    /// Records of the archive are added to the existing data. Records that
+    /// exist already must be identical.
+    Merge,
+}
+
+/// The query of `POST /admin/restore`, e.g. `?mode=merge&dry_run=true`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RestoreOptions {
+    #[serde(default)]
+    pub mode: RestoreMode,
+    /// Only validate the archive, without restoring it
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// The outcome of the validation of a [`Backup`]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RestoreReport {
+    pub mode: RestoreMode,
+    pub users: usize,
+    pub notes: usize,
+    pub tags: usize,
+    pub searches: usize,
+    pub comments: usize,
+    pub followers: usize,
+    pub favorites: usize,
+    /// Everything that prevents the restore, empty if the archive can be restored
+    pub errors: Vec<String>,
+    /// The job that restores the archive, unless it was a dry run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<Id>,
+}
+
+impl Backup {
+    /// Constructs a new [`Backup`] with the current [`SCHEMA_VERSION`]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        users: Vec<User>,
+        notes: Vec<Note>,
+        tags: Vec<Tag>,
+        searches: Vec<SavedSearch>,
+        comments: Vec<Comment>,
+        followers: Vec<Follower>,
+        favorites: Vec<(Id, Id)>,
+    ) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            created: Utc::now(),
+            users,
+            notes,
+            tags,
+            searches,
+            comments,
+            followers,
+            favorites,
+        }
+    }
+
+    pub fn users(&self) -> &[User] {
+        &self.users
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    pub fn searches(&self) -> &[SavedSearch] {
+        &self.searches
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    pub fn followers(&self) -> &[Follower] {
+        &self.followers
+    }
+
+    pub fn favorites(&self) -> &[(Id, Id)] {
+        &self.favorites
+    }
+
+    /// The number of records, to report the progress of a restore
+    pub fn records(&self) -> usize {
+        self.users.len()
+            + self.notes.len()
+            + self.tags.len()
+            + self.searches.len()
+            + self.comments.len()
+            + self.followers.len()
+            + self.favorites.len()
+    }
+
+    /// Returns an empty [`RestoreReport`] with the number of records of the archive
+    pub fn report(&self, mode: RestoreMode) -> RestoreReport {
+        RestoreReport {
+            mode,
+            users: self.users.len(),
+            notes: self.notes.len(),
+            tags: self.tags.len(),
+            searches: self.searches.len(),
+            comments: self.comments.len(),
+            followers: self.followers.len(),
+            favorites: self.favorites.len(),
+            errors: vec![],
+            job: None,
+        }
+    }
+
+    /// Checks that the archive is complete on its own
+    ///
+    /// The schema version must be supported, Ids must be unique and at most [`MAX_ID`],
+    /// slugs must be unique and all references must point to records of the archive.
+    pub fn validate(&self) -> Vec<String> {
+        if self.schema_version != SCHEMA_VERSION {
+            return vec![format!(
+                "Unsupported schema version {}, expected {SCHEMA_VERSION}",
+                self.schema_version
+            )];
+        }
+        let mut errors = Vec::new();
+        let users = unique_ids("user", self.users.iter().map(User::id), &mut errors);
+        let notes = unique_ids("note", self.notes.iter().map(Note::id), &mut errors);
+        unique_ids(
+            "search",
+            self.searches.iter().map(SavedSearch::id),
+            &mut errors,
+        );
+        unique_ids(
+            "comment",
+            self.comments.iter().map(Comment::id),
+            &mut errors,
+        );
+        let mut tags = HashMap::new();
+        for tag in &self.tags {
+            if tags.insert(tag.id(), tag).is_some() {
+                errors.push(format!("Duplicate tag {}", usize::from(tag.id())));
+            }
+            check_range("tag", tag.id(), &mut errors);
+        }
+        let mut slugs = HashSet::new();
+        for slug in self.notes.iter().filter_map(Note::slug) {
+            if !slugs.insert(slug) {
+                errors.push(format!("Duplicate slug `{slug}`"));
+            }
+        }
+        for note in &self.notes {
+            if !users.contains(note.user()) {
+                errors.push(format!(
+                    "Note {} belongs to the unknown user {}",
+                    usize::from(note.id()),
+                    usize::from(note.user())
+                ));
+            }
+            if let Some(tag) = note.tags().find(|tag| tags.get(tag.id()) != Some(tag)) {
+                errors.push(format!(
+                    "Note {} has the unknown tag `{}`",
+                    usize::from(note.id()),
+                    tag.label()
+                ));
+            }
+        }
+        for search in &self.searches {
+            if !users.contains(search.user()) {
+                errors.push(format!(
+                    "Search {} belongs to the unknown user {}",
+                    usize::from(search.id()),
+                    usize::from(search.user())
+                ));
+            }
+        }
+        for comment in &self.comments {
+            if !users.contains(comment.author()) || !notes.contains(comment.note()) {
+                errors.push(format!(
+                    "Comment {} references an unknown note or author",
+                    usize::from(comment.id())
+                ));
+            }
+        }
+        for follower in &self.followers {
+            if !users.contains(follower.user()) {
+                errors.push(format!(
+                    "Follower `{}` follows the unknown user {}",
+                    follower.actor(),
+                    usize::from(follower.user())
+                ));
+            }
+        }
+        for (user, note) in &self.favorites {
+            if !users.contains(user) || !notes.contains(note) {
+                errors.push(format!(
+                    "Favorite note {} of user {} is unknown",
+                    usize::from(note),
+                    usize::from(user)
+                ));
+            }
+        }
+        errors
+    }
+}
+
+/// Collects the Ids and records an error for every duplicate
+fn unique_ids<'a, I: Iterator<Item = &'a Id>>(
+    kind: &str,
+    ids: I,
+    errors: &mut Vec<String>,
+) -> HashSet<&'a Id> {
+    let mut res = HashSet::new();
+    for id in ids {
+        if !res.insert(id) {
+            errors.push(format!("Duplicate {kind} {}", usize::from(id)));
+        }
+        check_range(kind, id, errors);
+    }
+    res
+}
+
+/// Records an error if the Id is above [`MAX_ID`]
+fn check_range(kind: &str, id: &Id, errors: &mut Vec<String>) {
+    if usize::from(id) > MAX_ID {
+        errors.push(format!("The Id of {kind} {} is too large", usize::from(id)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::note::{Draft, Tags};
+    use crate::models::Visibility;
+
+    #[test]
+    fn test_validate() {
+        let user = User::new(Id(1), "alice".to_string());
+        let tag = Tag::new(Id(0), "rust".to_string());
+        let mut tags = Tags::default();
+        tags.insert(tag.clone());
+        let draft = Draft::new(
+            "Title".to_string(),
+            "Body".to_string(),
+            vec![],
+            Visibility::Private,
+        );
+        let note = Note::new(draft.clone(), Id(0), *user.id(), tags.clone());
+        let backup = Backup::new(
+            vec![user.clone()],
+            vec![note.clone()],
+            vec![tag.clone()],
+            vec![],
+            vec![],
+            vec![],
+            vec![(*user.id(), *note.id())],
+        );
+        assert_eq!(backup.validate(), Vec::<String>::new());
+        assert_eq!(backup.records(), 4);
+
+        // duplicate user, missing tag and unknown favorite
+        let broken = Backup::new(
+            vec![user.clone(), user.clone()],
+            vec![note.clone()],
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+            vec![(Id(99), *note.id())],
+        );
+        assert_eq!(broken.validate().len(), 3);
+
+        // Id out of range and duplicate slug
+        let slug = Some("title".to_string());
+        let huge = Note::new(draft, Id(usize::MAX), *user.id(), tags);
+        let broken = Backup::new(
+            vec![user.clone()],
+            vec![note.clone().with_slug(slug.clone()), huge.with_slug(slug)],
+            vec![tag.clone()],
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+        );
+        assert_eq!(
+            broken.validate(),
+            vec![
+                format!("The Id of note {} is too large", usize::MAX),
+                "Duplicate slug `title`".to_string()
+            ]
+        );
+
+        let mut newer = backup;
+        newer.schema_version = SCHEMA_VERSION + 1;
+        assert_eq!(newer.validate().len(), 1);
+    }
+
+    #[test]
+    fn test_upgrade() {
+        fn rename_labels(archive: &mut Map<String, Value>) {
+            if let Some(labels) = archive.remove("labels") {
+                archive.insert("tags".to_string(), labels);
+            }
+        }
+        fn add_favorites(archive: &mut Map<String, Value>) {
+            archive.insert("favorites".to_string(), json!([]));
+        }
+        let migrations: &[Migration] = &[rename_labels, add_favorites];
+
+        let mut archive = json!({"schema_version": 1, "labels": ["rust"]});
+        upgrade(&mut archive, migrations);
+        assert_eq!(
+            archive,
+            json!({"schema_version": 3, "tags": ["rust"], "favorites": []})
+        );
+
+        let mut archive = json!({"schema_version": 2, "labels": ["rust"]});
+        upgrade(&mut archive, migrations);
+        assert_eq!(
+            archive,
+            json!({"schema_version": 3, "labels": ["rust"], "favorites": []})
+        );
+
+        for version in [0, 3, 99] {
+            let mut archive = json!({ "schema_version": version });
+            upgrade(&mut archive, migrations);
+            assert_eq!(archive, json!({ "schema_version": version }));
+        }
+        let mut archive = json!([]);
+        upgrade(&mut archive, migrations);
+        assert_eq!(archive, json!([]));
+    }
+}
@@ -0,0 +1,150 @@
+//! The text of a [`Note`](crate::models::note::Note), stored as a rope
+//!
+//! The text of large notes is split into chunks that copies of the [`Body`] share,
+//! so cloning a note, appending to its body or keeping it as latest revision doesn't
+//! copy the whole document. Bodies are serialized as plain strings.
+use std::borrow::Cow;
+use std::fmt::{self, Display};
+use std::ops::Range;
+
+use ropey::Rope;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The body of a note, see the [module documentation](self)
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Body(Rope);
+
+impl Body {
+    /// Returns the length of the text in bytes
+    pub fn len(&self) -> usize {
+        self.0.len_bytes()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.len_bytes() == 0
+    }
+
+    /// Returns the complete text, borrowed if it is stored in a single chunk
+    pub fn text(&self) -> Cow<'_, str> {
+        match self.0.slice(..).as_str() {
+            Some(text) => Cow::Borrowed(text),
+            None => Cow::Owned(self.0.to_string()),
+        }
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.0.chars()
+    }
+
+    /// Returns the lines of the text including their line breaks, like [`str::split_inclusive`]
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        // the rope yields an empty last line after a trailing line break
+        self.0
+            .lines()
+            .filter(|line| line.len_bytes() > 0)
+            .map(Cow::from)
+    }
+
+    /// Appends the text
+    pub fn push_str(&mut self, text: &str) {
+        self.0.insert(self.0.len_chars(), text);
+    }
+
+    /// Appends the text of the other body, sharing its chunks
+    pub fn append(&mut self, other: &Body) {
+        self.0.append(other.0.clone());
+    }
+
+    /// Replaces the characters in the range with `text`, only the affected chunks are copied
+    ///
+    /// Returns `false` and keeps the text if the range is out of bounds.
+    pub fn replace(&mut self, chars: Range<usize>, text: &str) -> bool {
+        if chars.start > chars.end || chars.end > self.0.len_chars() {
+            return false;
+        }
+        self.0.remove(chars.clone());
+        self.0.insert(chars.start, text);
+        true
+    }
+}
+
+impl Display for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.0.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Self(Rope::from_str(text))
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Self::from(text.as_str())
+    }
+}
+
+impl PartialEq<str> for Body {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Body {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl Serialize for Body {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // writes the chunks one after another, without joining them first
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Body {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_body() {
+        let large = "Line of a large note\n".repeat(10_000);
+        let mut body = Body::from(large.as_str());
+        assert!(matches!(body.text(), Cow::Owned(_)));
+        assert_eq!(body.len(), large.len());
+        assert_eq!(body.lines().count(), 10_000);
+        assert_eq!(body.to_string(), large);
+
+        assert!(body.replace(0..4, "Start"));
+        assert!(body.text().starts_with("Start of a large note\n"));
+        assert!(!body.replace(0..usize::MAX, ""));
+        body.append(&Body::from("End"));
+        assert_eq!(body.lines().last().unwrap(), "End");
+
+        let small = Body::from("Grüße\nfrom\n");
+        assert!(matches!(small.text(), Cow::Borrowed("Grüße\nfrom\n")));
+        assert_eq!(small.lines().collect::<Vec<_>>(), vec!["Grüße\n", "from\n"]);
+        assert_eq!(small, "Grüße\nfrom\n");
+        assert_eq!(
+            serde_json::to_string(&small).unwrap(),
+            "\"Grüße\\nfrom\\n\""
+        );
+        assert_eq!(
+            serde_json::from_str::<Body>("\"Grüße\\nfrom\\n\"").unwrap(),
+            small
+        );
+        assert!(Body::default().lines().next().is_none());
+    }
+}
@@ -0,0 +1,93 @@
+//! Comments that users write below a [`Note`](crate::models::note::Note)
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::Id;
+
+/// The maximum length of the body of a comment in characters
+pub const MAX_LENGTH: usize = 10_000;
+
+/// A comment in the discussion thread of a note
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Comment {
+    id: Id,
+    note: Id,
+    /// The user who wrote the comment
+    author: Id,
+    body: String,
+    created: DateTime<Utc>,
+}
+
+impl Comment {
+    pub fn new(id: Id, note: Id, author: Id, draft: CommentDraft) -> Self {
+        Self {
+            id,
+            note,
+            author,
+            body: draft.body,
+            created: Utc::now(),
+        }
+    }
+
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+
+    pub fn note(&self) -> &Id {
+        &self.note
+    }
+
+    pub fn author(&self) -> &Id {
+        &self.author
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn created(&self) -> &DateTime<Utc> {
+        &self.created
+    }
+}
+
+/// The payload of `POST /note/:id/comments`
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CommentDraft {
+    body: String,
+}
+
+impl CommentDraft {
+    pub fn new(body: String) -> Self {
+        Self { body }
+    }
+
+    /// Checks that the body is neither blank nor longer than [`MAX_LENGTH`]
+    pub fn validate(&self) -> Result<(), String> {
+        if self.body.trim().is_empty() {
+            return Err("The comment must not be empty".to_string());
+        }
+        if self.body.chars().count() > MAX_LENGTH {
+            return Err(format!(
+                "The comment must not be longer than {MAX_LENGTH} characters"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(CommentDraft::new("Looks good".to_string())
+            .validate()
+            .is_ok());
+        assert!(CommentDraft::new(" \n".to_string()).validate().is_err());
+        assert!(CommentDraft::new("a".repeat(MAX_LENGTH)).validate().is_ok());
+        assert!(CommentDraft::new("a".repeat(MAX_LENGTH + 1))
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Complete exports of the data of a user
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::access::AccessEntry;
+use crate::models::audit::AuditEntry;
+use crate::models::comment::Comment;
+use crate::models::moderation::Flag;
+use crate::models::note::Note;
+use crate::models::query::SavedSearch;
+use crate::models::{Id, User};
+use crate::revisions::Revision;
+
+/// All data that is stored for a [`User`]
+///
+/// The export includes deleted notes, as they are only soft-deleted.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct UserExport {
+    user: User,
+    notes: Vec<Note>,
+    searches: Vec<SavedSearch>,
+    /// The comments of the user on all notes
+    #[serde(default)]
+    comments: Vec<Comment>,
+}
+
+impl UserExport {
+    /// Constructs a new [`UserExport`]
+    pub fn new(
+        user: User,
+        notes: Vec<Note>,
+        searches: Vec<SavedSearch>,
+        comments: Vec<Comment>,
+    ) -> Self {
+        Self {
+            user,
+            notes,
+            searches,
+            comments,
+        }
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    #[allow(dead_code)] // needed for unittests
+    pub fn searches(&self) -> &[SavedSearch] {
+        &self.searches
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+}
+
+/// A [`UserExport`] with the history of the notes, for legal hold requests
+///
+/// Returned by `GET /admin/users/:id/export`. Unlike the export of `DELETE /me`, it
+/// can't be restored.
+#[derive(Clone, Debug, Serialize)]
+pub struct ComplianceExport {
+    pub exported_at: DateTime<Utc>,
+    /// The admin who requested the export
+    pub exported_by: Id,
+    #[serde(flatten)]
+    pub export: UserExport,
+    /// All revisions of every note, with their bodies, by the Id of the note
+    pub revisions: BTreeMap<Id, Vec<Revision>>,
+    /// Who else opened the notes, see [`AccessEntry`]
+    pub access_log: Vec<AccessEntry>,
+    /// Notes of the user in the moderation queue
+    pub flags: Vec<Flag>,
+    /// Requests that admins sent while impersonating the user, most recent first
+    pub audit_log: Vec<AuditEntry>,
+}
+
+/// Confirmation that an account was scheduled for deletion
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccountDeletion {
+    /// All data of the user will be purged at this time
+    purge_at: DateTime<Utc>,
+    /// All data of the user, so that nothing is lost if the user changes their mind
+    export: UserExport,
+}
+
+impl AccountDeletion {
+    /// Constructs a new [`AccountDeletion`]
+    pub fn new(purge_at: DateTime<Utc>, export: UserExport) -> Self {
+        Self { purge_at, export }
+    }
+}
@@ -0,0 +1,154 @@
+//! Sparse fieldsets, so that clients only download the fields of notes they use
+//!
+//! A [`FieldSet`] is deserialized from the query string, e.g. `/notes?fields=id,title,tags`,
+//! and wraps the response in a [`SparseView`] that drops all other fields when it is serialized.
+use std::collections::BTreeSet;
+
+use serde::ser::Error;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+
+/// All fields of notes and their summaries that can be selected
+const NOTE_FIELDS: [&str; 26] = [
+    "id",
+    "slug",
+    "title",
+    "icon",
+    "body",
+    "preview",
+    "truncated",
+    "format",
+    "kind",
+    "url",
+    "done",
+    "date",
+    "template",
+    "fields",
+    "expires_at",
+    "tags",
+    "user",
+    "visibility",
+    "location",
+    "locked",
+    "archived",
+    "merged_into",
+    "deleted",
+    "created",
+    "updated",
+    "viewed_at",
+];
+
+/// The selected fields of a list of notes, e.g. `id,title,tags`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Fields(BTreeSet<String>);
+
+impl TryFrom<String> for Fields {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut fields = BTreeSet::new();
+        for name in value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            if !NOTE_FIELDS.contains(&name) {
+                return Err(format!(
+                    "unknown field `{name}`, expected one of {}",
+                    NOTE_FIELDS.join(", ")
+                ));
+            }
+            fields.insert(name.to_string());
+        }
+        Ok(Self(fields))
+    }
+}
+
+impl From<Fields> for String {
+    fn from(fields: Fields) -> Self {
+        fields.0.into_iter().collect::<Vec<String>>().join(",")
+    }
+}
+
+impl Fields {
+    /// Removes all unselected fields of an object or of every object in an array
+    ///
+    /// The `id` is always kept, so that clients can refer to the notes.
+    fn retain(&self, value: &mut Value) {
+        match value {
+            Value::Array(items) => items.iter_mut().for_each(|item| self.retain(item)),
+            Value::Object(object) => {
+                object.retain(|key, _| key == "id" || self.0.contains(key));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The query of endpoints that return notes, e.g. `?fields=id,title,tags`
+///
+/// All fields are returned if `fields` is not set.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FieldSet {
+    fields: Option<Fields>,
+}
+
+impl FieldSet {
+    /// Wraps the response, so that only the selected fields are serialized
+    pub fn view<T>(&self, value: T) -> SparseView<T> {
+        SparseView {
+            value,
+            fields: self.fields.clone(),
+        }
+    }
+}
+
+/// A response that serializes only the fields selected by a [`FieldSet`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SparseView<T> {
+    value: T,
+    fields: Option<Fields>,
+}
+
+impl<T: Serialize> Serialize for SparseView<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(fields) = &self.fields else {
+            return self.value.serialize(serializer);
+        };
+        let mut value = serde_json::to_value(&self.value).map_err(S::Error::custom)?;
+        fields.retain(&mut value);
+        value.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+    use crate::models::note::NoteList;
+
+    fn field_set(query: &str) -> Result<FieldSet, serde_json::Error> {
+        serde_json::from_value(serde_json::json!({ "fields": query }))
+    }
+
+    #[test]
+    fn test_sparse_view() {
+        let list = NoteList::new(vec![example_note()], false, 4, example_note().user());
+        let json = serde_json::to_value(field_set("title, tags").unwrap().view(&list)).unwrap();
+        let summary = json[0].as_object().unwrap();
+        assert_eq!(
+            summary.keys().collect::<Vec<&String>>(),
+            vec!["id", "tags", "title"]
+        );
+
+        let note = example_note();
+        let full = serde_json::to_value(FieldSet::default().view(&note)).unwrap();
+        assert_eq!(full, serde_json::to_value(&note).unwrap());
+
+        let sparse = serde_json::to_value(field_set("body").unwrap().view(&note)).unwrap();
+        assert_eq!(sparse, serde_json::json!({"id": 1, "body": "Test-Body"}));
+
+        assert!(field_set("title,author").is_err());
+    }
+}
@@ -0,0 +1,35 @@
+//! Remote ActivityPub actors that follow the public notes of a [`User`](crate::models::User)
+use serde::{Deserialize, Serialize};
+
+use crate::models::Id;
+
+/// An actor on another Fediverse server that receives the public notes of a user
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Follower {
+    /// The followed user
+    user: Id,
+    /// The Id (URL) of the remote actor
+    actor: String,
+    /// The inbox of the remote actor that receives the deliveries
+    inbox: String,
+}
+
+impl Follower {
+    /// Constructs a new [`Follower`]
+    pub fn new(user: Id, actor: String, inbox: String) -> Self {
+        Self { user, actor, inbox }
+    }
+
+    /// Returns the Id of the followed [`User`](crate::models::User)
+    pub fn user(&self) -> &Id {
+        &self.user
+    }
+
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    pub fn inbox(&self) -> &str {
+        &self.inbox
+    }
+}
@@ -0,0 +1,15 @@
+//! The markup languages of note bodies
+use serde::{Deserialize, Serialize};
+
+/// The format of the body of a [`Note`](crate::models::note::Note), decides how it
+/// is validated and rendered as HTML, see [`render`](crate::render)
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyFormat {
+    /// Text without markup, line breaks are kept
+    Plaintext,
+    #[default]
+    Markdown,
+    /// A subset of AsciiDoc: sections, paragraphs, lists, listing blocks and inline formatting
+    Asciidoc,
+}
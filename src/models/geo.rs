@@ -0,0 +1,140 @@
+//! Geographic locations of [`Note`](crate::models::note::Note)s
+use serde::{Deserialize, Serialize};
+
+/// Mean radius of the earth in meters
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Unvalidated coordinates, as received in requests
+#[derive(Deserialize)]
+struct Coordinates {
+    lat: f64,
+    lon: f64,
+}
+
+/// A point on earth in WGS 84 coordinates (decimal degrees)
+///
+/// Locations are validated when they are deserialized: the latitude must be
+/// within `-90..=90` and the longitude within `-180..=180`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(try_from = "Coordinates")]
+pub struct Location {
+    lat: f64,
+    lon: f64,
+}
+
+// Validated coordinates are never `NaN`, so equality is reflexive
+impl Eq for Location {}
+
+impl TryFrom<Coordinates> for Location {
+    type Error = String;
+
+    fn try_from(value: Coordinates) -> Result<Self, Self::Error> {
+        Self::new(value.lat, value.lon)
+    }
+}
+
+impl Location {
+    /// Constructs a new [`Location`] if the coordinates are valid
+    pub fn new(lat: f64, lon: f64) -> Result<Self, String> {
+        if !(-90.0..=90.0).contains(&lat) {
+            return Err(format!("Invalid latitude: {lat}"));
+        }
+        if !(-180.0..=180.0).contains(&lon) {
+            return Err(format!("Invalid longitude: {lon}"));
+        }
+        Ok(Self { lat, lon })
+    }
+
+    /// Returns the great-circle distance to `other` in meters, using the haversine formula
+    pub fn distance_m(&self, other: &Location) -> f64 {
+        let d_lat = (other.lat - self.lat).to_radians();
+        let d_lon = (other.lon - self.lon).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2)
+            + self.lat.to_radians().cos()
+                * other.lat.to_radians().cos()
+                * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().min(1.0).asin()
+    }
+}
+
+/// Unvalidated query string of `/notes/near`
+#[derive(Deserialize)]
+struct RawNearQuery {
+    lat: f64,
+    lon: f64,
+    radius_m: f64,
+}
+
+/// A query for all notes within `radius_m` meters around a center
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "RawNearQuery")]
+pub struct NearQuery {
+    center: Location,
+    radius_m: f64,
+}
+
+impl TryFrom<RawNearQuery> for NearQuery {
+    type Error = String;
+
+    fn try_from(value: RawNearQuery) -> Result<Self, Self::Error> {
+        if !value.radius_m.is_finite() || value.radius_m < 0.0 {
+            return Err(format!("Invalid radius: {}", value.radius_m));
+        }
+        Ok(Self {
+            center: Location::new(value.lat, value.lon)?,
+            radius_m: value.radius_m,
+        })
+    }
+}
+
+impl NearQuery {
+    pub fn center(&self) -> &Location {
+        &self.center
+    }
+
+    pub fn radius_m(&self) -> f64 {
+        self.radius_m
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validation() {
+        assert!(Location::new(52.52, 13.405).is_ok());
+        assert!(Location::new(-90.0, 180.0).is_ok());
+        assert!(Location::new(90.1, 0.0).is_err());
+        assert!(Location::new(0.0, -180.1).is_err());
+        assert!(Location::new(f64::NAN, 0.0).is_err());
+
+        assert!(serde_json::from_str::<Location>(r#"{"lat": 1.5, "lon": 2}"#).is_ok());
+        assert!(serde_json::from_str::<Location>(r#"{"lat": 100, "lon": 2}"#).is_err());
+    }
+
+    #[test]
+    fn test_distance() {
+        let berlin = Location::new(52.5200, 13.4050).unwrap();
+        let paris = Location::new(48.8566, 2.3522).unwrap();
+        let distance = berlin.distance_m(&paris);
+        assert!((distance - 877_500.0).abs() < 2_000.0, "{distance}");
+        assert_eq!(berlin.distance_m(&berlin), 0.0);
+
+        // across the antimeridian
+        let west = Location::new(0.0, 179.9).unwrap();
+        let east = Location::new(0.0, -179.9).unwrap();
+        assert!(west.distance_m(&east) < 25_000.0);
+    }
+
+    #[test]
+    fn test_near_query() {
+        let query: NearQuery =
+            serde_json::from_str(r#"{"lat": 1, "lon": 2, "radius_m": 500}"#).expect("valid query");
+        assert_eq!(query.center(), &Location::new(1.0, 2.0).unwrap());
+        assert_eq!(query.radius_m(), 500.0);
+        assert!(
+            serde_json::from_str::<NearQuery>(r#"{"lat": 1, "lon": 2, "radius_m": -1}"#).is_err()
+        );
+    }
+}
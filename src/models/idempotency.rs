@@ -0,0 +1,50 @@
+//! Responses of mutations, stored to replay them when a request is retried
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::models::Id;
+
+/// The response to a mutation that was sent with an idempotency key
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IdempotencyRecord {
+    user: Id,
+    key: String,
+    /// The payload of the original request, to detect re-used keys
+    request: Value,
+    response: Value,
+    created: DateTime<Utc>,
+}
+
+impl IdempotencyRecord {
+    /// Constructs a new [`IdempotencyRecord`]
+    pub fn new(user: Id, key: String, request: Value, response: Value) -> Self {
+        Self {
+            user,
+            key,
+            request,
+            response,
+            created: Utc::now(),
+        }
+    }
+
+    pub fn user(&self) -> &Id {
+        &self.user
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn request(&self) -> &Value {
+        &self.request
+    }
+
+    pub fn response(&self) -> &Value {
+        &self.response
+    }
+
+    pub fn created(&self) -> &DateTime<Utc> {
+        &self.created
+    }
+}
@@ -0,0 +1,131 @@
+//! Chat services that a user connected to share notes, edited with `PUT /me/integrations`
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// The services that `POST /note/:id/send?target=slack` can send notes to
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Slack,
+    Matrix,
+}
+
+impl Target {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::Slack => "slack",
+            Target::Matrix => "matrix",
+        }
+    }
+}
+
+/// The query of `POST /note/:id/send`
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub struct ShareQuery {
+    pub target: Target,
+}
+
+/// An incoming webhook of a Slack channel
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct SlackWebhook {
+    /// e.g. `https://hooks.slack.com/services/T000/B000/XXXX`
+    pub webhook_url: String,
+}
+
+/// A Matrix room that messages are sent to with the access token of a (bot) user
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct MatrixRoom {
+    /// The URL of the homeserver, e.g. `https://matrix.example.org`
+    pub homeserver: String,
+    /// e.g. `!abcdefg:example.org`
+    pub room_id: String,
+    pub access_token: String,
+}
+
+/// The connected services of a user, at most one per [`Target`]
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Integrations {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    slack: Option<SlackWebhook>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    matrix: Option<MatrixRoom>,
+}
+
+impl Integrations {
+    /// Checks that Slack webhooks point to Slack and Matrix homeservers use HTTPS
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(slack) = &self.slack {
+            if !slack.webhook_url.starts_with("https://hooks.slack.com/") {
+                return Err(
+                    "webhook_url must be a Slack webhook (https://hooks.slack.com/...)".to_string(),
+                );
+            }
+        }
+        if let Some(matrix) = &self.matrix {
+            match Url::parse(&matrix.homeserver) {
+                Ok(url) if url.scheme() == "https" => {}
+                _ => return Err("homeserver must be an HTTPS URL".to_string()),
+            }
+            if !matrix.room_id.starts_with('!') || !matrix.room_id.contains(':') {
+                return Err("room_id must be a room Id like !abcdefg:example.org".to_string());
+            }
+            if matrix.access_token.trim().is_empty() {
+                return Err("access_token must not be empty".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn slack(&self) -> Option<&SlackWebhook> {
+        self.slack.as_ref()
+    }
+
+    pub fn matrix(&self) -> Option<&MatrixRoom> {
+        self.matrix.as_ref()
+    }
+
+    pub fn is_connected(&self, target: Target) -> bool {
+        match target {
+            Target::Slack => self.slack.is_some(),
+            Target::Matrix => self.matrix.is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn integrations(json: &str) -> Integrations {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Integrations::default().validate().is_ok());
+        assert!(integrations(
+            r#"{"slack": {"webhook_url": "https://hooks.slack.com/services/T/B/X"}}"#
+        )
+        .validate()
+        .is_ok());
+        assert!(
+            integrations(r#"{"slack": {"webhook_url": "http://localhost:8080/hook"}}"#)
+                .validate()
+                .is_err()
+        );
+        let matrix = |homeserver: &str, room_id: &str| {
+            integrations(&format!(
+                r#"{{"matrix": {{"homeserver": "{homeserver}", "room_id": "{room_id}", "access_token": "secret"}}}}"#
+            ))
+        };
+        assert!(matrix("https://matrix.example.org", "!abc:example.org")
+            .validate()
+            .is_ok());
+        assert!(matrix("http://matrix.example.org", "!abc:example.org")
+            .validate()
+            .is_err());
+        assert!(matrix("https://matrix.example.org", "#room:example.org")
+            .validate()
+            .is_err());
+    }
+}
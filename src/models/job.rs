@@ -0,0 +1,123 @@
+//! The state of background jobs, see [`crate::jobs`]
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::models::Id;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for its (next) execution
+    Pending,
+    Running,
+    /// All attempts failed, the job is kept for inspection
+    Failed,
+}
+
+/// The progress of a running job, e.g. the number of restored records
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Progress {
+    pub done: usize,
+    pub total: usize,
+}
+
+/// A queued job with its serialized payload
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct JobRecord {
+    id: Id,
+    /// The kind of the job, see [`Job::NAME`](crate::jobs::Job::NAME)
+    name: String,
+    payload: Value,
+    status: JobStatus,
+    attempts: u32,
+    max_attempts: u32,
+    /// The error of the last failed attempt
+    last_error: Option<String>,
+    /// The earliest time of the next attempt
+    run_at: DateTime<Utc>,
+    created: DateTime<Utc>,
+    /// The progress of the current or last attempt, if the job reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    progress: Option<Progress>,
+}
+
+impl JobRecord {
+    /// Constructs a new pending [`JobRecord`]
+    pub fn new(
+        id: Id,
+        name: String,
+        payload: Value,
+        max_attempts: u32,
+        run_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            payload,
+            status: JobStatus::Pending,
+            attempts: 0,
+            max_attempts,
+            last_error: None,
+            run_at,
+            created: Utc::now(),
+            progress: None,
+        }
+    }
+
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn payload(&self) -> &Value {
+        &self.payload
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.status
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    #[allow(dead_code)] // needed for unittests
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    pub fn run_at(&self) -> &DateTime<Utc> {
+        &self.run_at
+    }
+
+    #[allow(dead_code)] // needed for unittests
+    pub fn progress(&self) -> Option<&Progress> {
+        self.progress.as_ref()
+    }
+
+    pub fn set_progress(&mut self, progress: Progress) {
+        self.progress = Some(progress);
+    }
+
+    /// Marks the start of an attempt
+    pub fn start(&mut self) {
+        self.status = JobStatus::Running;
+        self.attempts += 1;
+    }
+
+    /// Records a failed attempt. The job is retried at `retry_at`, unless all
+    /// attempts are used up.
+    pub fn fail(&mut self, error: String, retry_at: DateTime<Utc>) {
+        self.last_error = Some(error);
+        if self.attempts < self.max_attempts {
+            self.status = JobStatus::Pending;
+            self.run_at = retry_at;
+        } else {
+            self.status = JobStatus::Failed;
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! The kinds of notes and the fields that only some kinds use
+use chrono::NaiveDate;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// What a [`Note`](crate::models::note::Note) represents, filterable with `GET /notes?kind=task`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Note,
+    /// A link to a web page, requires a `url`
+    Bookmark,
+    /// Something to do, optionally `done`
+    Task,
+    /// An entry of a journal, requires the `date` it belongs to
+    Journal,
+}
+
+impl Kind {
+    /// Checks that the fields match the kind
+    ///
+    /// Bookmarks need an HTTP(S) `url` and journal entries a `date`. Fields of
+    /// other kinds are rejected, so that a typo in the kind is not silently ignored.
+    pub fn validate(
+        &self,
+        url: Option<&str>,
+        done: Option<bool>,
+        date: Option<NaiveDate>,
+    ) -> Result<(), String> {
+        let allowed = [
+            ("url", url.is_some(), *self == Kind::Bookmark),
+            ("done", done.is_some(), *self == Kind::Task),
+            ("date", date.is_some(), *self == Kind::Journal),
+        ];
+        if let Some((field, ..)) = allowed.iter().find(|(_, set, allowed)| *set && !allowed) {
+            return Err(format!(
+                "{field} is not allowed for notes of kind {}",
+                self.name()
+            ));
+        }
+        match self {
+            Kind::Bookmark => match url.map(Url::parse) {
+                Some(Ok(url)) if ["http", "https"].contains(&url.scheme()) => Ok(()),
+                _ => Err("Bookmarks require an HTTP(S) url".to_string()),
+            },
+            Kind::Journal if date.is_none() => Err("Journal entries require a date".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Note => "note",
+            Kind::Bookmark => "bookmark",
+            Kind::Task => "task",
+            Kind::Journal => "journal",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1);
+        assert!(Kind::Note.validate(None, None, None).is_ok());
+        assert!(Kind::Note.validate(None, Some(true), None).is_err());
+        assert!(Kind::Bookmark
+            .validate(Some("https://example.com"), None, None)
+            .is_ok());
+        assert!(Kind::Bookmark.validate(None, None, None).is_err());
+        assert!(Kind::Bookmark
+            .validate(Some("ftp://example.com"), None, None)
+            .is_err());
+        assert!(Kind::Task.validate(None, None, None).is_ok());
+        assert!(Kind::Task.validate(None, Some(false), None).is_ok());
+        assert!(Kind::Task.validate(None, None, date).is_err());
+        assert!(Kind::Journal.validate(None, None, date).is_ok());
+        assert_eq!(
+            Kind::Journal.validate(None, None, None),
+            Err("Journal entries require a date".to_string())
+        );
+        assert_eq!(
+            Kind::Journal.validate(Some("https://example.com"), None, date),
+            Err("url is not allowed for notes of kind journal".to_string())
+        );
+    }
+}
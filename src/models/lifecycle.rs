@@ -0,0 +1,131 @@
+//! The lifecycle of a [`Note`](crate::models::note::Note) as a state machine
+//!
+//! ```text
+//!            create            archive
+//!   Draft ----------> Active <---------> Archived
+//!                      |  ^   unarchive     |
+//!               delete |  | restore         | delete
+//!                      v  |                 |
+//!                     Deleted <-------------+
+//! ```
+//!
+//! Notes only change their state with an [`Event`], [`Lifecycle::next`] rejects all other
+//! jumps, e.g. from `Deleted` directly to `Archived` or to another visibility. Restored
+//! notes go back to the state they had before their deletion, archived notes stay archived.
+use serde::{Deserialize, Serialize};
+
+/// The state of a note, derived from its visibility and archive flag
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    /// A [`Draft`](crate::models::note::Draft) that is not stored yet
+    Draft,
+    Active,
+    Archived,
+    /// The note is in the trash
+    Deleted,
+}
+
+/// A transition between two [`State`]s
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    Create,
+    Archive,
+    Unarchive,
+    Delete,
+    Restore,
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Create => "create",
+            Event::Archive => "archive",
+            Event::Unarchive => "unarchive",
+            Event::Delete => "delete",
+            Event::Restore => "restore",
+        }
+    }
+}
+
+/// The transitions that are allowed, `NOTE_DELETE_ARCHIVED` disables the optional one
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Lifecycle {
+    /// Archived notes can be deleted without unarchiving them first
+    pub delete_archived: bool,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            delete_archived: true,
+        }
+    }
+}
+
+impl Lifecycle {
+    /// Returns the state after the `event`, or an error if the transition is not allowed
+    ///
+    /// Archiving an archived note and unarchiving an active note don't change anything,
+    /// they are allowed so that retries succeed. `was_archived` is the state of a deleted
+    /// note before its deletion, it decides where [`Event::Restore`] leads to.
+    pub fn next(&self, from: State, event: Event, was_archived: bool) -> Result<State, String> {
+        match (from, event) {
+            (State::Draft, Event::Create) => Ok(State::Active),
+            (State::Active | State::Archived, Event::Archive) => Ok(State::Archived),
+            (State::Active | State::Archived, Event::Unarchive) => Ok(State::Active),
+            (State::Active, Event::Delete) => Ok(State::Deleted),
+            (State::Archived, Event::Delete) if self.delete_archived => Ok(State::Deleted),
+            (State::Archived, Event::Delete) => {
+                Err("Archived notes must be unarchived before they are deleted".to_string())
+            }
+            (State::Deleted, Event::Restore) if was_archived => Ok(State::Archived),
+            (State::Deleted, Event::Restore) => Ok(State::Active),
+            (State::Deleted, _) => Err("Note is deleted".to_string()),
+            (State::Draft, _) => Err("Note does not exist yet".to_string()),
+            (_, Event::Create) => Err("Note exists already".to_string()),
+            (_, Event::Restore) => Err("Note is not deleted".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let lifecycle = Lifecycle::default();
+        let next = |from, event| lifecycle.next(from, event, false);
+        assert_eq!(next(State::Draft, Event::Create), Ok(State::Active));
+        assert_eq!(next(State::Active, Event::Archive), Ok(State::Archived));
+        assert_eq!(next(State::Archived, Event::Archive), Ok(State::Archived));
+        assert_eq!(next(State::Archived, Event::Unarchive), Ok(State::Active));
+        assert_eq!(next(State::Archived, Event::Delete), Ok(State::Deleted));
+        assert_eq!(next(State::Deleted, Event::Restore), Ok(State::Active));
+        assert_eq!(
+            lifecycle.next(State::Deleted, Event::Restore, true),
+            Ok(State::Archived)
+        );
+
+        for (from, event) in [
+            (State::Deleted, Event::Archive),
+            (State::Deleted, Event::Delete),
+            (State::Active, Event::Restore),
+            (State::Active, Event::Create),
+            (State::Draft, Event::Delete),
+        ] {
+            assert!(next(from, event).is_err(), "{from:?} {event:?}");
+        }
+
+        let strict = Lifecycle {
+            delete_archived: false,
+        };
+        assert!(strict.next(State::Archived, Event::Delete, false).is_err());
+        assert_eq!(
+            strict.next(State::Active, Event::Delete, false),
+            Ok(State::Deleted)
+        );
+    }
+}
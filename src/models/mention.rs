@@ -0,0 +1,53 @@
+//! `@name` mentions of [`User`](crate::models::User)s in the body of notes
+use serde::{Deserialize, Serialize};
+
+use crate::models::Id;
+
+/// Returns the user names mentioned with `@name` in the text, without duplicates
+///
+/// Names consist of alphanumeric characters, `_`, `-` and `.`. An `@` within a
+/// word, like in an email address, is not a mention.
+pub fn mentions(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    let mut previous = None;
+    for (index, c) in text.char_indices() {
+        let starts_word = previous.is_none_or(|p: char| !p.is_alphanumeric() && p != '@');
+        previous = Some(c);
+        if c != '@' || !starts_word {
+            continue;
+        }
+        let name = text[index + 1..]
+            .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-' || c == '.'))
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches(['.', '-']);
+        if !name.is_empty() && !names.iter().any(|known| known == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// A message for a user about an event that concerns them
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Notification {
+    /// The user was mentioned in a note
+    Mention { note: Id, title: String, author: Id },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mentions() {
+        assert_eq!(
+            mentions("@alice and @bob.smith, cc @alice."),
+            vec!["alice", "bob.smith"]
+        );
+        assert_eq!(mentions("(@carol) @dave-"), vec!["carol", "dave"]);
+        assert!(mentions("mail me at alice@example.com").is_empty());
+        assert!(mentions("@ @@ @. email@").is_empty());
+    }
+}
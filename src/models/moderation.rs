@@ -0,0 +1,68 @@
+//! Public notes that look like spam, see [`crate::moderation`]
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Id, Visibility};
+
+/// Why a note was flagged
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Reason {
+    TooManyLinks {
+        links: usize,
+        max: usize,
+    },
+    BannedWord {
+        word: String,
+    },
+    /// The user created `notes` notes within `seconds`
+    Burst {
+        notes: usize,
+        seconds: u64,
+    },
+    /// The note has more tags than its body justifies
+    TooManyTags {
+        tags: usize,
+        max: usize,
+    },
+}
+
+/// A note in the moderation queue, waiting for the review of an admin
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Flag {
+    note: Id,
+    user: Id,
+    reasons: Vec<Reason>,
+    /// The visibility that the user requested for a quarantined note, which is
+    /// private until it is approved
+    quarantined: Option<Visibility>,
+    created: DateTime<Utc>,
+}
+
+impl Flag {
+    pub fn new(note: Id, user: Id, reasons: Vec<Reason>, quarantined: Option<Visibility>) -> Self {
+        Self {
+            note,
+            user,
+            reasons,
+            quarantined,
+            created: Utc::now(),
+        }
+    }
+
+    pub fn note(&self) -> &Id {
+        &self.note
+    }
+
+    pub fn user(&self) -> &Id {
+        &self.user
+    }
+
+    pub fn reasons(&self) -> &[Reason] {
+        &self.reasons
+    }
+
+    pub fn quarantined(&self) -> Option<&Visibility> {
+        self.quarantined.as_ref()
+    }
+}
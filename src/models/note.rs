@@ -1,8 +1,21 @@
 use std::{collections::HashSet, fmt::Display};
 
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 
-use crate::models::{Id, Tag, Visibility};
+use crate::dates::Recency;
+use crate::models::body::Body;
+use crate::models::format::BodyFormat;
+use crate::models::geo::Location;
+use crate::models::kind::Kind;
+use crate::models::lifecycle::{Event, Lifecycle, State};
+use crate::models::query::GroupBy;
+use crate::models::template::FieldValues;
+use crate::models::title;
+use crate::models::{Access, Id, Tag, Visibility};
+use crate::render;
+use crate::revisions::Revision;
 
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Tags(HashSet<Tag>);
@@ -15,6 +28,26 @@ impl Tags {
     pub fn contains(&self, tag: &Tag) -> bool {
         self.0.contains(tag)
     }
+
+    /// Replaces the tag with the same Id, e.g. after its metadata changed
+    ///
+    /// Returns `false` if the tag is not part of the set
+    pub fn replace(&mut self, tag: &Tag) -> bool {
+        let len = self.0.len();
+        self.0.retain(|existing| existing.id() != tag.id());
+        if self.0.len() == len {
+            return false;
+        }
+        self.0.insert(tag.clone())
+    }
+
+    /// Removes all tags with one of the `labels`, returns `true` if any tag was removed
+    pub fn remove_labels(&mut self, labels: &[String]) -> bool {
+        let len = self.0.len();
+        self.0
+            .retain(|tag| !labels.iter().any(|label| label == tag.label()));
+        self.0.len() != len
+    }
 }
 
 impl<'a> IntoIterator for &'a Tags {
@@ -36,75 +69,521 @@ impl<'a> Iterator for TagIter<'a> {
 #[derive(Clone, Default, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Draft {
     title: String,
+    /// An emoji shown in front of the title
+    #[serde(default)]
+    icon: Option<String>,
     body: String,
     tags: Vec<String>,
-    visibility: Visibility,
+    /// The visibility of new notes defaults to the [`Preferences`](crate::models::preferences::Preferences)
+    /// of the user, edited notes keep their visibility
+    visibility: Option<Visibility>,
+    #[serde(default)]
+    location: Option<Location>,
+    #[serde(default)]
+    format: BodyFormat,
+    #[serde(default)]
+    kind: Kind,
+    /// The page of a bookmark
+    #[serde(default)]
+    url: Option<String>,
+    /// Whether a task is done
+    #[serde(default)]
+    done: Option<bool>,
+    /// The day of a journal entry
+    #[serde(default)]
+    date: Option<NaiveDate>,
+    /// The name of the [`Template`](crate::models::template::Template) of the fields
+    #[serde(default)]
+    template: Option<String>,
+    #[serde(default)]
+    fields: FieldValues,
+    /// The note is removed at this time, e.g. a snippet that was shared only for a day
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
 }
 
 impl Draft {
     #[allow(dead_code)] // needed for unittests
     pub fn new(title: String, body: String, tags: Vec<String>, visibility: Visibility) -> Self {
+        Self {
+            title,
+            icon: None,
+            body,
+            tags,
+            visibility: Some(visibility),
+            location: None,
+            format: BodyFormat::default(),
+            kind: Kind::default(),
+            url: None,
+            done: None,
+            date: None,
+            template: None,
+            fields: FieldValues::new(),
+            expires_at: None,
+        }
+    }
+
+    /// Constructs the draft of a [`Kind::Bookmark`], which gets the default visibility
+    pub fn bookmark(title: String, body: String, url: String, tags: Vec<String>) -> Self {
         Self {
             title,
             body,
             tags,
-            visibility,
+            kind: Kind::Bookmark,
+            url: Some(url),
+            ..Self::default()
         }
     }
+
+    /// Sets the [`BodyFormat`] of the body
+    #[allow(dead_code)] // needed for unittests
+    pub fn with_format(mut self, format: BodyFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Checks that the visibility is not `Deleted`, that the body can be parsed in its
+    /// [`BodyFormat`] and that the fields match the [`Kind`]
+    pub fn validate(&self) -> Result<(), String> {
+        if self.visibility == Some(Visibility::Deleted) {
+            return Err(
+                "Notes are deleted with `DELETE /note/:id`, not by their visibility".to_string(),
+            );
+        }
+        if self
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+        {
+            return Err("expires_at must be in the future".to_string());
+        }
+        render::validate(self.format, &self.body)?;
+        self.kind
+            .validate(self.url.as_deref(), self.done, self.date)
+    }
+
+    /// Normalizes the title, see [`title`], and rejects titles longer than `max_length` graphemes
+    ///
+    /// With `extract_icon`, a leading emoji of the title becomes the icon, unless the draft sets one.
+    pub fn normalize(mut self, max_length: usize, extract_icon: bool) -> Result<Self, String> {
+        self.title = title::normalize(&self.title);
+        if let Some(icon) = &self.icon {
+            let icon = icon.trim();
+            if title::length(icon) != 1 || !title::is_emoji(icon) {
+                return Err("icon must be a single emoji".to_string());
+            }
+            self.icon = Some(icon.to_string());
+        } else if extract_icon {
+            if let Some((icon, rest)) = title::split_icon(&self.title) {
+                (self.icon, self.title) = (Some(icon.to_string()), rest.to_string());
+            }
+        }
+        let length = title::length(&self.title);
+        if length > max_length {
+            return Err(format!(
+                "title has {length} characters, at most {max_length} are allowed"
+            ));
+        }
+        Ok(self)
+    }
+
+    /// Attaches a [`Location`] to the draft
+    #[allow(dead_code)] // needed for unittests
+    pub fn with_location(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    /// Replaces title and body of the draft
+    pub fn with_text(mut self, title: String, body: String) -> Self {
+        self.title = title;
+        self.body = body;
+        self
+    }
+
+    /// Replaces the visibility of the draft
+    pub fn with_visibility(mut self, visibility: Visibility) -> Self {
+        self.visibility = Some(visibility);
+        self
+    }
+
+    /// Sets the visibility, unless the draft specifies it
+    pub fn with_default_visibility(mut self, visibility: &Visibility) -> Self {
+        self.visibility.get_or_insert_with(|| visibility.clone());
+        self
+    }
+
     pub fn title(&self) -> &str {
         &self.title
     }
 
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
     pub fn tags(&self) -> &Vec<String> {
         &self.tags
     }
+
+    /// The visibility of the note, `None` keeps the visibility of an edited note
+    pub fn visibility(&self) -> Option<&Visibility> {
+        self.visibility.as_ref()
+    }
+
+    pub fn template(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+
+    pub fn fields(&self) -> &FieldValues {
+        &self.fields
+    }
+
+    pub fn expires_at(&self) -> Option<&DateTime<Utc>> {
+        self.expires_at.as_ref()
+    }
 }
 
 impl From<&Note> for Draft {
     fn from(note: &Note) -> Self {
         Self {
             title: note.title().to_string(),
+            icon: note.icon.clone(),
             body: note.body().to_string(),
             tags: note.tags().map(|tag| tag.label().to_string()).collect(),
-            visibility: note.visibility().clone(),
+            visibility: Some(note.visibility().clone()),
+            location: note.location,
+            format: note.format,
+            kind: note.kind,
+            url: note.url.clone(),
+            done: note.done,
+            date: note.date,
+            template: note.template.clone(),
+            fields: note.fields.clone(),
+            expires_at: note.expires_at,
+        }
+    }
+}
+
+/// Separates the bodies of merged notes
+pub const MERGE_SEPARATOR: &str = "\n\n---\n\n";
+
+/// The maximum number of characters of a slug, without the suffix to make it unique
+const MAX_SLUG_LENGTH: usize = 64;
+
+/// Derives a URL-friendly slug from the title, e.g. `my-meeting-notes-2024`
+///
+/// Returns `None` if the title does not contain any alphanumeric characters.
+pub fn slugify(title: &str) -> Option<String> {
+    let words = title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase);
+    let mut slug = String::new();
+    for word in words {
+        let separator = usize::from(!slug.is_empty());
+        if slug.chars().count() + separator + word.chars().count() > MAX_SLUG_LENGTH {
+            if slug.is_empty() {
+                slug = word.chars().take(MAX_SLUG_LENGTH).collect();
+            }
+            break;
         }
+        if separator == 1 {
+            slug.push('-');
+        }
+        slug.push_str(&word);
     }
+    (!slug.is_empty()).then_some(slug)
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Note {
     id: Id,
+    /// A unique, human-readable identifier derived from the title at creation.
+    /// It does not change when the title is edited, to keep URLs stable.
+    #[serde(default)]
+    slug: Option<String>,
     title: String,
-    body: String,
+    /// An emoji shown in front of the title
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    icon: Option<String>,
+    body: Body,
+    /// Decides how the body is rendered, see [`render`]
+    #[serde(default)]
+    format: BodyFormat,
+    #[serde(default)]
+    kind: Kind,
+    /// The page of a bookmark
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    /// Whether a task is done, always set for tasks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    done: Option<bool>,
+    /// The day of a journal entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date: Option<NaiveDate>,
+    /// The name of the [`Template`](crate::models::template::Template) of the fields
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    template: Option<String>,
+    /// Structured data, validated against the template whenever the note is saved
+    #[serde(default, skip_serializing_if = "FieldValues::is_empty")]
+    fields: FieldValues,
+    /// Expired notes are gone for all readers, see [`Note::is_expired`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
     tags: Tags,
     user: Id,
     visibility: Visibility,
+    location: Option<Location>,
+    /// Locked notes are read-only until their owner unlocks them
+    #[serde(default)]
+    locked: bool,
+    /// Archived notes are hidden from `GET /notes`, unless they are requested explicitly
+    #[serde(default)]
+    archived: bool,
+    /// Set for deleted notes that were merged into another note, so that
+    /// links to them can be redirected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    merged_into: Option<Id>,
+    /// When the note was deleted, unknown for notes deleted before this was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted: Option<DateTime<Utc>>,
+    created: DateTime<Utc>,
+    updated: DateTime<Utc>,
 }
 
 impl Note {
     pub fn new(draft: Draft, id: Id, user: Id, tags: Tags) -> Self {
+        let now = Utc::now();
         Self {
             id,
+            slug: None,
             title: draft.title,
-            body: draft.body,
+            icon: draft.icon,
+            body: Body::from(draft.body),
+            format: draft.format,
+            kind: draft.kind,
+            url: draft.url,
+            done: (draft.kind == Kind::Task).then(|| draft.done.unwrap_or_default()),
+            date: draft.date,
+            template: draft.template,
+            fields: draft.fields,
+            expires_at: draft.expires_at,
             tags,
             user,
-            visibility: draft.visibility,
+            // new notes are never deleted, see `Draft::validate`
+            visibility: draft
+                .visibility
+                .filter(|visibility| visibility != &Visibility::Deleted)
+                .unwrap_or_default(),
+            location: draft.location,
+            locked: false,
+            archived: false,
+            merged_into: None,
+            deleted: None,
+            created: now,
+            updated: now,
+        }
+    }
+
+    /// Sets the slug of the note, the caller must ensure that it is unique
+    pub fn with_slug(mut self, slug: Option<String>) -> Self {
+        self.slug = slug;
+        self
+    }
+
+    /// Replaces the content of the note with the [`Draft`]
+    ///
+    /// The Id, owner and creation time of the note are kept. The visibility only changes
+    /// between the visibilities of notes that are not deleted, deleting and restoring
+    /// the note are [`Lifecycle`] transitions.
+    pub fn update(&mut self, draft: Draft, tags: Tags) {
+        self.title = draft.title;
+        self.icon = draft.icon;
+        self.body = Body::from(draft.body);
+        self.format = draft.format;
+        self.kind = draft.kind;
+        self.url = draft.url;
+        self.done = (draft.kind == Kind::Task).then(|| draft.done.unwrap_or_default());
+        self.date = draft.date;
+        self.template = draft.template;
+        self.fields = draft.fields;
+        self.expires_at = draft.expires_at;
+        self.tags = tags;
+        if let Some(visibility) = draft.visibility {
+            if self.state() != State::Deleted && visibility != Visibility::Deleted {
+                self.visibility = visibility;
+            }
+        }
+        self.location = draft.location;
+        self.updated = Utc::now();
+    }
+
+    /// Adds the tags `add` and removes all tags with a label in `remove`
+    ///
+    /// Returns `true` if the tags of the note changed
+    pub fn change_tags(&mut self, add: &Tags, remove: &[String]) -> bool {
+        let mut changed = self.tags.remove_labels(remove);
+        for tag in add {
+            changed |= self.tags.insert(tag.clone());
+        }
+        if changed {
+            self.updated = Utc::now();
+        }
+        changed
+    }
+
+    /// Appends the body and adds the tags of the other note
+    ///
+    /// The note keeps the older of both creation times.
+    pub fn merge(&mut self, other: &Note) {
+        self.body.push_str(MERGE_SEPARATOR);
+        self.body.append(&other.body);
+        for tag in &other.tags {
+            self.tags.insert(tag.clone());
+        }
+        self.created = self.created.min(other.created);
+        self.updated = Utc::now();
+    }
+
+    /// Deletes the note after it was merged into the note `target`
+    pub fn merge_into(&mut self, target: Id) -> Result<State, String> {
+        let state = self.delete()?;
+        self.merged_into = Some(target);
+        Ok(state)
+    }
+
+    /// The state of the note in its [`Lifecycle`]
+    pub fn state(&self) -> State {
+        if self.visibility == Visibility::Deleted {
+            State::Deleted
+        } else if self.archived {
+            State::Archived
+        } else {
+            State::Active
+        }
+    }
+
+    /// Checks that the [`Lifecycle`] allows the event, returns the next state
+    fn transition(&self, event: Event) -> Result<State, String> {
+        Lifecycle::default().next(self.state(), event, self.archived)
+    }
+
+    /// Moves the note to the trash, without changing `updated`
+    pub fn delete(&mut self) -> Result<State, String> {
+        let state = self.transition(Event::Delete)?;
+        self.visibility = Visibility::Deleted;
+        self.deleted = Some(Utc::now());
+        Ok(state)
+    }
+
+    /// Moves a deleted note back with the `visibility`
+    pub fn restore(&mut self, visibility: Visibility) -> Result<State, String> {
+        if visibility == Visibility::Deleted {
+            return Err("Restored notes can't be deleted".to_string());
         }
+        let state = self.transition(Event::Restore)?;
+        self.visibility = visibility;
+        self.deleted = None;
+        Ok(state)
+    }
+
+    pub fn deleted(&self) -> Option<&DateTime<Utc>> {
+        self.deleted.as_ref()
+    }
+
+    /// Returns `true` if the note was created before `at` and not deleted yet at that time
+    ///
+    /// Notes without a known time of deletion are treated as deleted since ever.
+    pub fn existed_at(&self, at: &DateTime<Utc>) -> bool {
+        &self.created <= at
+            && match (&self.visibility, &self.deleted) {
+                (_, Some(deleted)) => deleted > at,
+                (Visibility::Deleted, None) => false,
+                (_, None) => true,
+            }
+    }
+
+    /// Replaces the title and body with the ones of an earlier [`Revision`]
+    ///
+    /// All other fields keep their current values, they have no history.
+    pub fn at_revision(mut self, revision: Revision) -> Self {
+        self.title = revision.info.title;
+        self.body = Body::from(revision.body);
+        self.updated = revision.info.created;
+        self
+    }
+
+    /// Returns the note that this note was merged into
+    pub fn merged_into(&self) -> Option<&Id> {
+        self.merged_into.as_ref()
+    }
+
+    /// Replaces the copy of the tag with the same Id, without changing `updated`
+    pub fn replace_tag(&mut self, tag: &Tag) -> bool {
+        self.tags.replace(tag)
     }
 
     pub fn id(&self) -> &Id {
         &self.id
     }
 
+    pub fn slug(&self) -> Option<&str> {
+        self.slug.as_deref()
+    }
+
     pub fn title(&self) -> &str {
         &self.title
     }
 
-    pub fn body(&self) -> &str {
+    pub fn icon(&self) -> Option<&str> {
+        self.icon.as_deref()
+    }
+
+    pub fn body(&self) -> &Body {
         &self.body
     }
 
+    pub fn format(&self) -> BodyFormat {
+        self.format
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    /// Whether the note is a task that is done
+    pub fn done(&self) -> bool {
+        self.done.unwrap_or_default()
+    }
+
+    pub fn date(&self) -> Option<NaiveDate> {
+        self.date
+    }
+
+    pub fn template(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+
+    pub fn fields(&self) -> &FieldValues {
+        &self.fields
+    }
+
+    pub fn expires_at(&self) -> Option<&DateTime<Utc>> {
+        self.expires_at.as_ref()
+    }
+
+    /// Returns `true` if the note expired before `now` and is waiting for
+    /// [`ExpireNotes`](crate::tasks::ExpireNotes)
+    ///
+    /// Archived and deleted notes don't expire, e.g. after the expiry archived them.
+    pub fn is_expired(&self, now: &DateTime<Utc>) -> bool {
+        self.state() == State::Active
+            && self.expires_at.is_some_and(|expires_at| expires_at <= *now)
+    }
+
     pub fn tagged_with(&self, tag: &Tag) -> bool {
         self.tags.contains(tag)
     }
@@ -113,17 +592,434 @@ impl Note {
         &self.user
     }
 
-    pub fn visibility_mut(&mut self) -> &mut Visibility {
-        &mut self.visibility
+    /// Returns `true` if the user owns the note or its [`Visibility`] allows the access
+    pub fn readable_by(&self, user: &Id, access: Access) -> bool {
+        &self.user == user || self.visibility.allows(user, access)
     }
 
     pub fn visibility(&self) -> &Visibility {
         &self.visibility
     }
 
-    pub fn tags(&self) -> TagIter {
+    pub fn tags(&self) -> TagIter<'_> {
         self.tags.into_iter()
     }
+
+    pub fn location(&self) -> Option<&Location> {
+        self.location.as_ref()
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Locks or unlocks the note, without changing `updated`
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
+    /// Changes the visibility of a note that is not deleted, without changing `updated`
+    pub fn set_visibility(&mut self, visibility: Visibility) {
+        if self.state() != State::Deleted && visibility != Visibility::Deleted {
+            self.visibility = visibility;
+        }
+    }
+
+    pub fn archived(&self) -> bool {
+        self.archived
+    }
+
+    /// Archives the note or moves it back to the active notes, without changing `updated`
+    pub fn set_archived(&mut self, archived: bool) -> Result<State, String> {
+        let state = self.transition(if archived {
+            Event::Archive
+        } else {
+            Event::Unarchive
+        })?;
+        self.archived = archived;
+        Ok(state)
+    }
+
+    pub fn created(&self) -> &DateTime<Utc> {
+        &self.created
+    }
+
+    pub fn updated(&self) -> &DateTime<Utc> {
+        &self.updated
+    }
+}
+
+/// The payload of `POST /notes/tags` to change the tags of many notes at once
+///
+/// Tags are removed before the new tags are added.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BulkTag {
+    notes: Vec<Id>,
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+impl BulkTag {
+    /// The Ids of the notes to change
+    pub fn notes(&self) -> &[Id] {
+        &self.notes
+    }
+
+    /// The labels of the tags to add
+    pub fn add(&self) -> &[String] {
+        &self.add
+    }
+
+    /// The labels of the tags to remove
+    pub fn remove(&self) -> &[String] {
+        &self.remove
+    }
+}
+
+/// The payload of `POST /notes/merge`
+///
+/// The first note receives the bodies and tags of all other notes, in the given order.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MergeNotes {
+    notes: Vec<Id>,
+}
+
+impl MergeNotes {
+    /// The Ids of the notes to merge, the first one is kept
+    pub fn notes(&self) -> &[Id] {
+        &self.notes
+    }
+
+    /// Checks that at least two different notes are merged
+    pub fn validate(&self) -> Result<(), String> {
+        let unique = self.notes.iter().collect::<HashSet<&Id>>();
+        if unique.len() != self.notes.len() {
+            return Err("Every note can only be merged once".to_string());
+        }
+        if self.notes.len() < 2 {
+            return Err("At least two notes are required".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of a bulk operation for a single [`Note`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BulkResult {
+    id: Id,
+    /// The HTTP status code the operation would have if the note was changed individually
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl BulkResult {
+    pub fn ok(id: Id) -> Self {
+        Self {
+            id,
+            status: 200,
+            error: None,
+        }
+    }
+
+    pub fn error(id: Id, status: u16, error: &str) -> Self {
+        Self {
+            id,
+            status,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// The fields of a [`Note`] that every reader may see, returned for notes of other users
+///
+/// The owner, the visibility, the location and the state of the note are not part of the type,
+/// so a response can't contain them by accident.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct PublicNoteView {
+    id: Id,
+    slug: Option<String>,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    icon: Option<String>,
+    body: Body,
+    format: BodyFormat,
+    kind: Kind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    done: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<NaiveDate>,
+    tags: Tags,
+    created: DateTime<Utc>,
+    updated: DateTime<Utc>,
+}
+
+impl From<&Note> for PublicNoteView {
+    fn from(note: &Note) -> Self {
+        Self {
+            id: note.id,
+            slug: note.slug.clone(),
+            title: note.title.clone(),
+            icon: note.icon.clone(),
+            body: note.body.clone(),
+            format: note.format,
+            kind: note.kind,
+            url: note.url.clone(),
+            done: note.done,
+            date: note.date,
+            tags: note.tags.clone(),
+            created: note.created,
+            updated: note.updated,
+        }
+    }
+}
+
+/// A [`Note`] as a user sees it: complete for its owner, a [`PublicNoteView`] for everyone else
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum NoteView {
+    Own(Note),
+    Public(PublicNoteView),
+}
+
+impl NoteView {
+    pub fn new(note: Note, viewer: &Id) -> Self {
+        if &note.user == viewer {
+            Self::Own(note)
+        } else {
+            Self::Public(PublicNoteView::from(&note))
+        }
+    }
+
+    pub fn id(&self) -> &Id {
+        match self {
+            Self::Own(note) => &note.id,
+            Self::Public(view) => &view.id,
+        }
+    }
+
+    /// Returns the creation and update time
+    fn times(&self) -> (&DateTime<Utc>, &DateTime<Utc>) {
+        match self {
+            Self::Own(note) => (&note.created, &note.updated),
+            Self::Public(view) => (&view.created, &view.updated),
+        }
+    }
+}
+
+/// The fields of a [`NoteSummary`] that only the owner of the note sees
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct OwnerDetails {
+    visibility: Visibility,
+    location: Option<Location>,
+    #[serde(default)]
+    locked: bool,
+    #[serde(default)]
+    archived: bool,
+}
+
+/// A shortened representation of a [`Note`] for list endpoints
+///
+/// Only the first characters of the body are included as a preview.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NoteSummary {
+    id: Id,
+    slug: Option<String>,
+    title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    icon: Option<String>,
+    preview: String,
+    /// `true` if the preview does not contain the complete body
+    truncated: bool,
+    #[serde(default)]
+    kind: Kind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    done: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+    tags: Tags,
+    /// `None` for notes of other users
+    #[serde(flatten)]
+    details: Option<OwnerDetails>,
+    created: DateTime<Utc>,
+    updated: DateTime<Utc>,
+    /// When the requesting user viewed the note the last time
+    #[serde(default)]
+    viewed_at: Option<DateTime<Utc>>,
+    /// Only if the list is grouped, see [`NoteList::with_groups`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<Recency>,
+}
+
+impl NoteSummary {
+    /// Creates a summary with a preview of up to `length` characters of the body
+    ///
+    /// The [`OwnerDetails`] are only included if `viewer` owns the note.
+    pub fn new(note: &Note, length: usize, viewer: &Id) -> Self {
+        let mut chars = note.body.chars();
+        let preview = chars.by_ref().take(length).collect::<String>();
+        Self {
+            id: note.id,
+            slug: note.slug.clone(),
+            title: note.title.clone(),
+            icon: note.icon.clone(),
+            preview,
+            truncated: chars.next().is_some(),
+            kind: note.kind,
+            url: note.url.clone(),
+            done: note.done,
+            date: note.date,
+            expires_at: note.expires_at,
+            tags: note.tags.clone(),
+            details: (&note.user == viewer).then(|| OwnerDetails {
+                visibility: note.visibility.clone(),
+                location: note.location,
+                locked: note.locked,
+                archived: note.archived,
+            }),
+            created: note.created,
+            updated: note.updated,
+            viewed_at: None,
+            group: None,
+        }
+    }
+
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+}
+
+/// A [`NoteView`] with the time the requesting user viewed it the last time
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ViewedNote {
+    #[serde(flatten)]
+    note: NoteView,
+    /// `None` if the user never viewed the note
+    viewed_at: Option<DateTime<Utc>>,
+    /// Only if the list is grouped, see [`NoteList::with_groups`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<Recency>,
+}
+
+impl ViewedNote {
+    pub fn new(note: NoteView, viewed_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            note,
+            viewed_at,
+            group: None,
+        }
+    }
+}
+
+/// The response of list endpoints, either containing full [`Note`]s or only [`NoteSummary`]s
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum NoteList {
+    Full(Vec<ViewedNote>),
+    Summaries(Vec<NoteSummary>),
+}
+
+impl NoteList {
+    /// Creates the list with full notes if `full` is `true`, otherwise
+    /// with previews of `preview_length` characters
+    ///
+    /// Notes that `viewer` doesn't own only contain the fields of a [`PublicNoteView`].
+    pub fn new(notes: Vec<Note>, full: bool, preview_length: usize, viewer: &Id) -> Self {
+        if full {
+            Self::Full(
+                notes
+                    .into_iter()
+                    .map(|note| ViewedNote::new(NoteView::new(note, viewer), None))
+                    .collect(),
+            )
+        } else {
+            Self::Summaries(
+                notes
+                    .iter()
+                    .map(|note| NoteSummary::new(note, preview_length, viewer))
+                    .collect(),
+            )
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Full(notes) => notes.len(),
+            Self::Summaries(summaries) => summaries.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Serializes every note as a JSON document on its own line, the body of
+    /// `application/x-ndjson` responses
+    pub fn to_ndjson(&self) -> String {
+        fn lines<T: Serialize>(items: &[T]) -> String {
+            items
+                .iter()
+                .map(|item| {
+                    serde_json::to_string(item).expect("notes can always be serialized") + "\n"
+                })
+                .collect()
+        }
+        match self {
+            Self::Full(notes) => lines(notes),
+            Self::Summaries(summaries) => lines(summaries),
+        }
+    }
+
+    /// Adds the time the requesting user viewed each note the last time
+    pub fn with_views<F>(mut self, viewed_at: F) -> Self
+    where
+        F: Fn(&Id) -> Option<DateTime<Utc>>,
+    {
+        match &mut self {
+            Self::Full(notes) => notes
+                .iter_mut()
+                .for_each(|note| note.viewed_at = viewed_at(note.note.id())),
+            Self::Summaries(summaries) => summaries
+                .iter_mut()
+                .for_each(|summary| summary.viewed_at = viewed_at(&summary.id)),
+        }
+        self
+    }
+
+    /// Adds the [`Recency`] of the creation or update time of each note relative to `now`,
+    /// in calendar days of the timezone
+    ///
+    /// The groups of a list are only consecutive if it is sorted by the same time.
+    pub fn with_groups(mut self, by: Option<GroupBy>, now: &DateTime<Utc>, timezone: &Tz) -> Self {
+        let Some(by) = by else {
+            return self;
+        };
+        let group = |created: &DateTime<Utc>, updated: &DateTime<Utc>| {
+            let time = match by {
+                GroupBy::Created => created,
+                GroupBy::Updated => updated,
+            };
+            Some(Recency::of(time, now, timezone))
+        };
+        match &mut self {
+            Self::Full(notes) => notes.iter_mut().for_each(|note| {
+                let (created, updated) = note.note.times();
+                note.group = group(created, updated)
+            }),
+            Self::Summaries(summaries) => summaries
+                .iter_mut()
+                .for_each(|summary| summary.group = group(&summary.created, &summary.updated)),
+        }
+        self
+    }
 }
 
 impl Display for Note {
@@ -144,14 +1040,47 @@ pub mod test_note {
 
         Note {
             id: Id(1),
+            slug: Some("test-title".into()),
             title: "Test-Title".into(),
+            icon: None,
             body: "Test-Body".into(),
+            format: BodyFormat::Markdown,
+            kind: Kind::Note,
+            url: None,
+            done: None,
+            date: None,
+            template: None,
+            fields: FieldValues::new(),
+            expires_at: None,
             tags,
             user: Id(12),
             visibility: Visibility::Public,
+            location: None,
+            locked: false,
+            archived: false,
+            merged_into: None,
+            deleted: None,
+            created: Utc::now(),
+            updated: Utc::now(),
         }
     }
 
+    #[test]
+    fn test_slugify() {
+        assert_eq!(
+            slugify("My Meeting-Notes (2024)"),
+            Some("my-meeting-notes-2024".to_string())
+        );
+        assert_eq!(slugify("  Grüße!  "), Some("grüße".to_string()));
+        assert_eq!(slugify("?!"), None);
+        assert_eq!(slugify(""), None);
+
+        let long = slugify(&"word ".repeat(100)).unwrap();
+        assert_eq!(long.len(), 64);
+        assert!(long.ends_with("word"));
+        assert_eq!(slugify(&"a".repeat(100)), Some("a".repeat(64)));
+    }
+
     #[test]
     fn test_tags() {
         let note = example_note();
@@ -183,4 +1112,146 @@ pub mod test_note {
         assert_eq!(draft.title(), "Test-Title");
         assert_eq!(draft.tags().len(), 3);
     }
+
+    #[test]
+    fn test_update_keeps_metadata() {
+        let mut note = example_note();
+        let created = *note.created();
+        note.update(
+            Draft::new("New".into(), "Body".into(), vec![], Visibility::Private),
+            Tags::default(),
+        );
+        assert_eq!(note.id(), &Id(1));
+        assert_eq!(note.user(), &Id(12));
+        assert_eq!(note.created(), &created);
+        assert!(note.updated() >= &created);
+        assert_eq!(note.title(), "New");
+        assert_eq!(note.tags().count(), 0);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut note = example_note();
+        let mut other = example_note();
+        other.body = Body::from("Other");
+        other.created = note.created - chrono::TimeDelta::days(1);
+        other.tags = Tags::default();
+        other.tags.insert(Tag::new(2.into(), "other".to_string()));
+
+        note.merge(&other);
+        assert_eq!(note.body(), "Test-Body\n\n---\n\nOther");
+        assert_eq!(note.tags().count(), 4);
+        assert_eq!(note.created(), other.created());
+
+        other.merge_into(Id(1)).unwrap();
+        assert_eq!(other.visibility(), &Visibility::Deleted);
+        assert_eq!(other.merged_into(), Some(&Id(1)));
+
+        let merge = |notes: Vec<usize>| MergeNotes {
+            notes: notes.into_iter().map(Id).collect(),
+        };
+        assert!(merge(vec![1, 2]).validate().is_ok());
+        assert!(merge(vec![1]).validate().is_err());
+        assert!(merge(vec![1, 2, 1]).validate().is_err());
+    }
+
+    #[test]
+    fn test_summary_preview() {
+        let note = example_note();
+
+        let summary = NoteSummary::new(&note, 4, &Id(12));
+        assert_eq!(summary.preview, "Test");
+        assert!(summary.truncated);
+
+        let summary = NoteSummary::new(&note, 9, &Id(12));
+        assert_eq!(summary.preview, "Test-Body");
+        assert!(!summary.truncated);
+
+        let mut note = example_note();
+        note.body = Body::from("Grüße");
+        let summary = NoteSummary::new(&note, 3, &Id(12));
+        assert_eq!(summary.preview, "Grü");
+        assert!(summary.truncated);
+    }
+
+    #[test]
+    fn test_note_list() {
+        let notes = vec![example_note(), example_note()];
+        assert!(matches!(
+            NoteList::new(notes.clone(), true, 10, &Id(12)),
+            NoteList::Full(_)
+        ));
+        let list = NoteList::new(notes, false, 10, &Id(12));
+        assert!(matches!(list, NoteList::Summaries(_)));
+        assert_eq!(list.len(), 2);
+
+        let ndjson = list.to_ndjson();
+        assert_eq!(ndjson.lines().count(), 2);
+        assert!(ndjson.ends_with('\n'));
+        for line in ndjson.lines() {
+            let summary = serde_json::from_str::<serde_json::Value>(line).unwrap();
+            assert_eq!(summary["title"], "Test-Title");
+        }
+        assert_eq!(NoteList::Summaries(vec![]).to_ndjson(), "");
+    }
+
+    #[test]
+    fn test_viewed_note() {
+        let note = example_note();
+        let updated = note.updated;
+        let value =
+            serde_json::to_value(ViewedNote::new(NoteView::Own(note), Some(updated))).unwrap();
+        assert_eq!(value["title"], "Test-Title");
+        assert!(value["viewed_at"].is_string());
+
+        let list =
+            NoteList::new(vec![example_note()], false, 10, &Id(12)).with_views(|_| Some(updated));
+        let NoteList::Summaries(summaries) = list else {
+            panic!("expected summaries");
+        };
+        assert_eq!(summaries[0].viewed_at, Some(updated));
+    }
+
+    #[test]
+    fn test_groups() {
+        let note = example_note();
+        let now = note.updated + chrono::TimeDelta::days(1);
+        let list = NoteList::new(vec![note.clone()], false, 10, &Id(12));
+        let value = serde_json::to_value(list.clone()).unwrap();
+        assert!(value[0].get("group").is_none());
+        let value =
+            serde_json::to_value(list.with_groups(Some(GroupBy::Updated), &now, &Tz::UTC)).unwrap();
+        assert_eq!(value[0]["group"], "yesterday");
+
+        let list = NoteList::new(vec![note.clone()], true, 10, &Id(3)).with_groups(
+            Some(GroupBy::Created),
+            &note.updated,
+            &Tz::UTC,
+        );
+        let value = serde_json::to_value(list).unwrap();
+        assert_eq!(
+            value[0]["group"],
+            serde_json::to_value(Recency::of(&note.created, &note.updated, &Tz::UTC)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_public_view() {
+        let own = serde_json::to_value(NoteView::new(example_note(), &Id(12))).unwrap();
+        assert_eq!(own["user"], 12);
+        assert_eq!(own["visibility"], "Public");
+
+        let public = serde_json::to_value(NoteView::new(example_note(), &Id(3))).unwrap();
+        assert_eq!(public["title"], "Test-Title");
+        for field in ["user", "visibility", "location", "locked", "archived"] {
+            assert!(public.get(field).is_none(), "{field} is serialized");
+        }
+
+        for (viewer, owned) in [(Id(12), true), (Id(3), false)] {
+            let summary = serde_json::to_value(NoteSummary::new(&example_note(), 4, &viewer));
+            let summary = summary.unwrap();
+            assert_eq!(summary.get("visibility").is_some(), owned);
+            assert_eq!(summary.get("locked").is_some(), owned);
+        }
+    }
 }
@@ -0,0 +1,161 @@
+//! Per-user defaults that handlers apply when a request does not specify them
+use chrono::TimeDelta;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::models::query::SortOrder;
+use crate::models::Visibility;
+
+/// How often a user gets an email digest, see [`crate::digest`]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestSchedule {
+    #[default]
+    Off,
+    Daily,
+    /// On Mondays
+    Weekly,
+}
+
+impl DigestSchedule {
+    /// Returns the time that a digest covers, `None` if digests are off
+    pub fn period(&self) -> Option<TimeDelta> {
+        match self {
+            DigestSchedule::Off => None,
+            DigestSchedule::Daily => Some(TimeDelta::days(1)),
+            DigestSchedule::Weekly => Some(TimeDelta::weeks(1)),
+        }
+    }
+}
+
+/// The preferences of a [`User`](crate::models::User)
+///
+/// Missing fields are set to their defaults when deserialized.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Preferences {
+    /// Visibility of new notes
+    visibility: Visibility,
+    /// Sort order of lists of notes
+    ///
+    /// The default order is skipped, because it is serialized as empty string, which
+    /// is no valid order.
+    #[serde(skip_serializing_if = "SortOrder::is_default")]
+    sort: SortOrder,
+    /// Number of notes per page of list endpoints, all notes are returned if not set
+    per_page: Option<usize>,
+    /// The timezone of the user, e.g. `Europe/Berlin`
+    timezone: Tz,
+    /// How often the user gets an email digest
+    digest: DigestSchedule,
+    /// The address that digests are sent to
+    email: Option<String>,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            visibility: Visibility::Private,
+            sort: SortOrder::default(),
+            per_page: None,
+            timezone: Tz::UTC,
+            digest: DigestSchedule::Off,
+            email: None,
+        }
+    }
+}
+
+impl Preferences {
+    /// Checks that the preferences contain only values that can be applied
+    pub fn validate(&self) -> Result<(), String> {
+        if self.visibility == Visibility::Deleted {
+            return Err("Notes can't be deleted by default".to_string());
+        }
+        if self.per_page == Some(0) {
+            return Err("per_page must be greater than 0".to_string());
+        }
+        if let Some(email) = &self.email {
+            let valid = email
+                .split_once('@')
+                .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'))
+                && !email.contains(char::is_whitespace);
+            if !valid {
+                return Err(format!("Invalid email address: {email}"));
+            }
+        }
+        if self.digest != DigestSchedule::Off && self.email.is_none() {
+            return Err("Digests need an email address".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn visibility(&self) -> &Visibility {
+        &self.visibility
+    }
+
+    pub fn sort(&self) -> &SortOrder {
+        &self.sort
+    }
+
+    pub fn per_page(&self) -> Option<usize> {
+        self.per_page
+    }
+
+    pub fn timezone(&self) -> &Tz {
+        &self.timezone
+    }
+
+    pub fn digest(&self) -> DigestSchedule {
+        self.digest
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::query::SortKey;
+
+    #[test]
+    fn test_deserialize_partial() {
+        let preferences: Preferences =
+            serde_json::from_str(r#"{"sort": "title", "timezone": "Europe/Berlin"}"#).unwrap();
+        assert_eq!(preferences.sort(), &SortOrder::from(SortKey::Title));
+        assert_eq!(preferences.timezone(), &Tz::Europe__Berlin);
+        assert_eq!(preferences.visibility(), &Visibility::Private);
+        assert_eq!(preferences.per_page(), None);
+
+        assert!(serde_json::from_str::<Preferences>(r#"{"timezone": "Mars/Olympus"}"#).is_err());
+
+        // the default sort order is skipped
+        let json = serde_json::to_string(&Preferences::default()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Preferences>(&json).unwrap(),
+            Preferences::default()
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Preferences::default().validate().is_ok());
+
+        let preferences: Preferences = serde_json::from_str(r#"{"per_page": 0}"#).unwrap();
+        assert!(preferences.validate().is_err());
+
+        let preferences: Preferences =
+            serde_json::from_str(r#"{"visibility": "Deleted"}"#).unwrap();
+        assert!(preferences.validate().is_err());
+
+        let preferences: Preferences = serde_json::from_str(r#"{"digest": "daily"}"#).unwrap();
+        assert!(preferences.validate().is_err());
+        let preferences: Preferences =
+            serde_json::from_str(r#"{"digest": "weekly", "email": "alice@example.com"}"#).unwrap();
+        assert!(preferences.validate().is_ok());
+        let preferences: Preferences =
+            serde_json::from_str(r#"{"email": "alice at example.com"}"#).unwrap();
+        assert!(preferences.validate().is_err());
+    }
+}
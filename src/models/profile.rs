@@ -0,0 +1,134 @@
+//! The public information that a user shares with other users, e.g. as author of notes
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Id, User};
+
+/// Maximum number of characters of the display name
+const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+
+/// Maximum number of characters of the bio
+const MAX_BIO_LENGTH: usize = 500;
+
+/// The profile of a [`User`], as edited with `PUT /me`
+///
+/// Missing fields are cleared.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Profile {
+    /// The name shown instead of the login name
+    display_name: Option<String>,
+    /// URL of an image, e.g. `https://example.com/me.png`
+    avatar_url: Option<String>,
+    bio: Option<String>,
+}
+
+impl Profile {
+    /// Checks the lengths of the texts and that the avatar is an HTTP(S) URL
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(name) = &self.display_name {
+            if name.trim().is_empty() {
+                return Err("display_name must not be empty".to_string());
+            }
+            if name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+                return Err(format!(
+                    "display_name must not be longer than {MAX_DISPLAY_NAME_LENGTH} characters"
+                ));
+            }
+        }
+        if let Some(url) = &self.avatar_url {
+            match Url::parse(url) {
+                Ok(url) if ["http", "https"].contains(&url.scheme()) => {}
+                _ => return Err("avatar_url must be an HTTP(S) URL".to_string()),
+            }
+        }
+        if let Some(bio) = &self.bio {
+            if bio.chars().count() > MAX_BIO_LENGTH {
+                return Err(format!(
+                    "bio must not be longer than {MAX_BIO_LENGTH} characters"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    pub fn avatar_url(&self) -> Option<&str> {
+        self.avatar_url.as_deref()
+    }
+
+    pub fn bio(&self) -> Option<&str> {
+        self.bio.as_deref()
+    }
+}
+
+/// The subset of a [`User`] that every user can see, returned by `GET /user/:id`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PublicProfile {
+    id: Id,
+    name: String,
+    #[serde(flatten)]
+    profile: Profile,
+}
+
+impl From<&User> for PublicProfile {
+    fn from(user: &User) -> Self {
+        Self {
+            id: *user.id(),
+            name: user.name().to_string(),
+            profile: user.profile().clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let profile: Profile = serde_json::from_str(
+            r#"{"display_name": "Alice", "avatar_url": "https://example.com/a.png"}"#,
+        )
+        .unwrap();
+        assert!(profile.validate().is_ok());
+        assert_eq!(profile.bio(), None);
+
+        for invalid in [
+            r#"{"display_name": " "}"#,
+            r#"{"avatar_url": "javascript:alert(1)"}"#,
+            r#"{"avatar_url": "me.png"}"#,
+        ] {
+            let profile: Profile = serde_json::from_str(invalid).unwrap();
+            assert!(profile.validate().is_err(), "{invalid}");
+        }
+        let profile = Profile {
+            bio: Some("x".repeat(MAX_BIO_LENGTH + 1)),
+            ..Profile::default()
+        };
+        assert!(profile.validate().is_err());
+    }
+
+    #[test]
+    fn test_public_profile() {
+        let mut user = User::new(Id(3), "alice".to_string());
+        user.set_profile(Profile {
+            display_name: Some("Alice".to_string()),
+            ..Profile::default()
+        });
+        let public = serde_json::to_value(PublicProfile::from(&user)).unwrap();
+        assert_eq!(
+            public,
+            serde_json::json!({
+                "id": 3,
+                "name": "alice",
+                "display_name": "Alice",
+                "avatar_url": null,
+                "bio": null,
+            })
+        );
+    }
+}
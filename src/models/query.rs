@@ -0,0 +1,905 @@
+//! Filters that can be applied to a list of [`Note`]s
+//!
+//! A [`NoteQuery`] is deserialized straight from the query string of a request,
+//! e.g. `/notes?tag=todo&q=ui&sort=updated:desc,title`, and can be stored as a
+//! [`SavedSearch`] to be re-evaluated later.
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
+
+use crate::config::SearchConfig;
+use crate::models::kind::Kind;
+use crate::models::lifecycle::State;
+use crate::models::note::Note;
+use crate::models::template::FieldFilters;
+use crate::models::{Id, Visibility, VisibilityFilter};
+
+/// The fields by which a list of [`Note`]s can be sorted
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    #[default]
+    Id,
+    Title,
+    Created,
+    Updated,
+}
+
+impl SortKey {
+    /// Compares two [`Note`]s by the selected field
+    pub fn compare(&self, a: &Note, b: &Note) -> Ordering {
+        match self {
+            SortKey::Id => usize::from(a.id()).cmp(&usize::from(b.id())),
+            SortKey::Title => a.title().cmp(b.title()),
+            SortKey::Created => a.created().cmp(b.created()),
+            SortKey::Updated => a.updated().cmp(b.updated()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SortKey::Id => "id",
+            SortKey::Title => "title",
+            SortKey::Created => "created",
+            SortKey::Updated => "updated",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [
+            SortKey::Id,
+            SortKey::Title,
+            SortKey::Created,
+            SortKey::Updated,
+        ]
+        .into_iter()
+        .find(|key| key.name() == name)
+    }
+}
+
+/// An ordering of [`Note`]s by one or more [`SortKey`]s, e.g. `updated:desc,title:asc`
+///
+/// Keys without a direction are sorted ascending. Notes that are equal in all
+/// keys are ordered by their Id, so that the order is deterministic.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SortOrder {
+    /// The keys in order of precedence, `true` for a descending order
+    keys: Vec<(SortKey, bool)>,
+}
+
+impl SortOrder {
+    /// Returns `true` for the default order, which sorts by Id only
+    pub fn is_default(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Compares two [`Note`]s by all keys, falling back to their Ids
+    pub fn compare(&self, a: &Note, b: &Note) -> Ordering {
+        self.keys
+            .iter()
+            .map(|(key, descending)| {
+                let ordering = key.compare(a, b);
+                if *descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| SortKey::Id.compare(a, b))
+    }
+}
+
+impl From<SortKey> for SortOrder {
+    fn from(key: SortKey) -> Self {
+        Self {
+            keys: vec![(key, false)],
+        }
+    }
+}
+
+impl TryFrom<String> for SortOrder {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut keys: Vec<(SortKey, bool)> = vec![];
+        for part in value.split(',') {
+            let (name, direction) = part.trim().split_once(':').unwrap_or((part.trim(), "asc"));
+            let Some(key) = SortKey::parse(name) else {
+                return Err(format!(
+                    "unknown sort key `{name}`, expected id, title, created or updated"
+                ));
+            };
+            let descending = match direction {
+                "asc" => false,
+                "desc" => true,
+                _ => {
+                    return Err(format!(
+                        "unknown sort direction `{direction}`, expected asc or desc"
+                    ))
+                }
+            };
+            if keys.iter().any(|(existing, _)| existing == &key) {
+                return Err(format!("sort key `{name}` is used more than once"));
+            }
+            keys.push((key, descending));
+        }
+        Ok(Self { keys })
+    }
+}
+
+impl From<SortOrder> for String {
+    fn from(order: SortOrder) -> Self {
+        order
+            .keys
+            .iter()
+            .map(|(key, descending)| {
+                format!(
+                    "{}:{}",
+                    key.name(),
+                    if *descending { "desc" } else { "asc" }
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+/// The lifecycle states of [`Note`]s that a [`NoteQuery`] can select, e.g. for a trash view
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteState {
+    /// Notes that are neither archived nor deleted
+    #[default]
+    Active,
+    /// Archived notes that are not deleted
+    Archived,
+    Deleted,
+    All,
+}
+
+impl NoteState {
+    /// The [`VisibilityFilter`] that selects the notes of the state from a backend
+    pub fn filter(&self) -> VisibilityFilter {
+        match self {
+            NoteState::Active | NoteState::Archived => VisibilityFilter::Active,
+            NoteState::Deleted => VisibilityFilter::Deleted,
+            NoteState::All => VisibilityFilter::All,
+        }
+    }
+
+    /// Returns `true` if the note is in the [`State`] of its lifecycle, expired notes are not active
+    pub fn matches(&self, note: &Note) -> bool {
+        match self {
+            NoteState::Active => note.state() == State::Active && !note.is_expired(&Utc::now()),
+            NoteState::Archived => note.state() == State::Archived,
+            NoteState::Deleted => note.state() == State::Deleted,
+            NoteState::All => true,
+        }
+    }
+}
+
+/// A set of filters for [`Note`]s
+///
+/// All filters are optional and are combined with `AND`. An empty query
+/// matches every note. The dates of `created_after` etc. are days in UTC,
+/// `*_after` includes the day, `*_before` excludes it.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct NoteQuery {
+    /// Only notes tagged with this label
+    tag: Option<String>,
+    /// Only notes containing this text in their title or body
+    q: Option<String>,
+    /// Only notes with this visibility
+    visibility: Option<Visibility>,
+    /// Sort order of the results
+    sort: Option<SortOrder>,
+    /// Only notes in this state, active notes by default
+    state: Option<NoteState>,
+    /// Only notes of this kind
+    kind: Option<Kind>,
+    created_after: Option<NaiveDate>,
+    created_before: Option<NaiveDate>,
+    updated_after: Option<NaiveDate>,
+    updated_before: Option<NaiveDate>,
+    /// Only notes whose fields pass the filters, e.g. `field.rating>=4`
+    #[serde(flatten)]
+    fields: FieldFilters,
+}
+
+/// Returns `true` if the time is on or after the day `after` and before the day `before`
+fn within(time: &DateTime<Utc>, after: Option<NaiveDate>, before: Option<NaiveDate>) -> bool {
+    let day = time.date_naive();
+    after.is_none_or(|after| day >= after) && before.is_none_or(|before| day < before)
+}
+
+impl NoteQuery {
+    pub fn new(
+        tag: Option<String>,
+        q: Option<String>,
+        visibility: Option<Visibility>,
+        sort: Option<SortOrder>,
+    ) -> Self {
+        Self {
+            tag,
+            q,
+            visibility,
+            sort,
+            ..Self::default()
+        }
+    }
+
+    /// Removes the text filter, e.g. because a [`SearchQuery`] matches the text instead
+    pub fn without_text(mut self) -> Self {
+        self.q = None;
+        self
+    }
+
+    /// Selects the notes in the [`NoteState`] instead of the active notes
+    pub fn with_state(mut self, state: NoteState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    pub fn state(&self) -> NoteState {
+        self.state.unwrap_or_default()
+    }
+
+    pub fn sort(&self) -> SortOrder {
+        self.sort.clone().unwrap_or_default()
+    }
+
+    /// Sets the sort order, unless the query specifies it
+    pub fn with_default_sort(mut self, sort: &SortOrder) -> Self {
+        self.sort.get_or_insert_with(|| sort.clone());
+        self
+    }
+
+    /// Returns an equivalent query with an explicit sort order, so that equal
+    /// queries compare equal, e.g. as keys of the [`QueryCache`](crate::cache::QueryCache)
+    pub fn normalized(mut self) -> Self {
+        self.sort = Some(self.sort());
+        self.state = Some(self.state());
+        self
+    }
+
+    /// Returns `true` if the [`Note`] passes all filters of the query
+    ///
+    /// The text is matched last, because it is the most expensive filter.
+    pub fn matches(&self, note: &Note) -> bool {
+        if let Some(label) = &self.tag {
+            if !note.tags().any(|tag| tag.label() == label) {
+                return false;
+            }
+        }
+        if let Some(visibility) = &self.visibility {
+            if note.visibility() != visibility {
+                return false;
+            }
+        }
+        if self.kind.is_some_and(|kind| note.kind() != kind) {
+            return false;
+        }
+        if !self.fields.matches(note.fields()) {
+            return false;
+        }
+        if !within(note.created(), self.created_after, self.created_before)
+            || !within(note.updated(), self.updated_after, self.updated_before)
+            || !self.state().matches(note)
+        {
+            return false;
+        }
+        match &self.q {
+            Some(text) => {
+                note.title().contains(text.as_str()) || note.body().text().contains(text.as_str())
+            }
+            None => true,
+        }
+    }
+}
+
+/// Options for endpoints that return lists of [`Note`]s
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ListOptions {
+    /// Return complete notes instead of summaries with a preview of the body
+    #[serde(default)]
+    full: bool,
+    /// The page to return, starting at `1`
+    page: Option<usize>,
+    /// Number of notes per page
+    per_page: Option<usize>,
+    /// Add the [`Recency`](crate::dates::Recency) of this time to every note
+    group: Option<GroupBy>,
+}
+
+impl ListOptions {
+    pub fn full(&self) -> bool {
+        self.full
+    }
+
+    pub fn group(&self) -> Option<GroupBy> {
+        self.group
+    }
+
+    /// Returns the requested page of `items`
+    ///
+    /// All items are returned if neither the options nor `default_per_page` define the page size.
+    pub fn paginate<T>(&self, items: Vec<T>, default_per_page: Option<usize>) -> Vec<T> {
+        match self.window(default_per_page) {
+            (skip, Some(take)) => items.into_iter().skip(skip).take(take).collect(),
+            (_, None) => items,
+        }
+    }
+
+    /// Returns how many items come before the requested page and how many are on it,
+    /// `None` if all items are returned
+    pub fn window(&self, default_per_page: Option<usize>) -> (usize, Option<usize>) {
+        let Some(per_page) = self.per_page.or(default_per_page) else {
+            return (0, None);
+        };
+        let per_page = per_page.max(1);
+        let page = self.page.unwrap_or(1).max(1);
+        ((page - 1).saturating_mul(per_page), Some(per_page))
+    }
+}
+
+/// The time of a note that lists are grouped by, e.g. `?group=updated`
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Created,
+    Updated,
+}
+
+/// Options of `GET /notes/count`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CountOptions {
+    /// Count all matching notes even if the backend can estimate the number
+    #[serde(default)]
+    exact: bool,
+}
+
+impl CountOptions {
+    pub fn exact(&self) -> bool {
+        self.exact
+    }
+}
+
+/// The response of `GET /notes/count`
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NoteCount {
+    pub count: usize,
+    /// `false` if the count is an estimate of the backend
+    pub exact: bool,
+}
+
+/// The parts of a [`Note`] that a [`SearchQuery`] looks at, e.g. `title,tags`
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SearchFields {
+    title: bool,
+    body: bool,
+    tags: bool,
+}
+
+impl Default for SearchFields {
+    /// The title and the body, like the search index
+    fn default() -> Self {
+        Self {
+            title: true,
+            body: true,
+            tags: false,
+        }
+    }
+}
+
+impl SearchFields {
+    /// Returns the texts of the selected fields, every tag label is a separate text
+    fn texts<'a>(&self, note: &'a Note) -> Vec<Cow<'a, str>> {
+        let mut texts = vec![];
+        if self.title {
+            texts.push(Cow::Borrowed(note.title()));
+        }
+        if self.body {
+            texts.push(note.body().text());
+        }
+        if self.tags {
+            texts.extend(note.tags().map(|tag| Cow::Borrowed(tag.label())));
+        }
+        texts
+    }
+}
+
+impl TryFrom<String> for SearchFields {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut fields = Self {
+            title: false,
+            body: false,
+            tags: false,
+        };
+        for name in value.split(',') {
+            match name.trim() {
+                "title" => fields.title = true,
+                "body" => fields.body = true,
+                "tags" => fields.tags = true,
+                name => {
+                    return Err(format!(
+                        "unknown search field `{name}`, expected title, body or tags"
+                    ))
+                }
+            }
+        }
+        Ok(fields)
+    }
+}
+
+impl From<SearchFields> for String {
+    fn from(fields: SearchFields) -> Self {
+        [
+            (fields.title, "title"),
+            (fields.body, "body"),
+            (fields.tags, "tags"),
+        ]
+        .into_iter()
+        .filter(|(selected, _)| *selected)
+        .map(|(_, name)| name)
+        .collect::<Vec<&str>>()
+        .join(",")
+    }
+}
+
+/// Whether a [`SearchQuery`] distinguishes between upper and lower case
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Case {
+    Sensitive,
+    #[default]
+    Insensitive,
+}
+
+impl Case {
+    /// Normalizes the text with the [`crate::search`] pipeline, folding the case only
+    /// if the search is case-insensitive
+    fn normalize(&self, config: &SearchConfig, text: &str) -> String {
+        let config = SearchConfig {
+            case: *self == Case::Insensitive,
+            ..*config
+        };
+        crate::search::normalize(&config, text)
+    }
+}
+
+/// How the words of a [`SearchQuery`] must occur in the text of a [`Note`]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// A word of the text starts with the search word
+    Prefix,
+    /// The search word is anywhere in the text, also within words
+    Substring,
+    /// A word of the text is the search word
+    #[default]
+    WholeWord,
+}
+
+impl MatchMode {
+    fn matches(&self, text: &str, word: &str) -> bool {
+        match self {
+            MatchMode::Prefix => split_words(text).any(|candidate| candidate.starts_with(word)),
+            MatchMode::Substring => text.contains(word),
+            MatchMode::WholeWord => split_words(text).any(|candidate| candidate == word),
+        }
+    }
+}
+
+/// Splits the text at all characters that are neither alphanumeric nor combining marks
+fn split_words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric() && !is_combining_mark(c))
+        .filter(|word| !word.is_empty())
+}
+
+/// A full-text search for words in [`Note`]s
+///
+/// By default, all words must be present as whole words in the title or body,
+/// ignoring the case, e.g. `?q=prepare ui`. The search can be narrowed with
+/// `fields`, `case` and `match`, e.g. `?q=ui&fields=title,tags&case=sensitive&match=prefix`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SearchQuery {
+    /// The words that must be present in the note
+    q: String,
+    #[serde(default)]
+    fields: SearchFields,
+    #[serde(default)]
+    case: Case,
+    #[serde(default, rename = "match")]
+    mode: MatchMode,
+}
+
+impl SearchQuery {
+    pub fn q(&self) -> &str {
+        &self.q
+    }
+
+    /// Returns `true` if the search can be answered by the full-text index,
+    /// i.e. it looks for whole words in the title and body, ignoring the case
+    pub fn is_indexed(&self) -> bool {
+        self.fields == SearchFields::default()
+            && self.case == Case::Insensitive
+            && self.mode == MatchMode::WholeWord
+    }
+
+    /// Returns `true` if every word of the search matches one of the selected fields
+    ///
+    /// The search and the note are normalized as configured, see [`crate::search`].
+    /// Searches without any words don't match any note.
+    pub fn matches(&self, note: &Note, config: &SearchConfig) -> bool {
+        let texts = self
+            .fields
+            .texts(note)
+            .into_iter()
+            .map(|text| self.case.normalize(config, &text))
+            .collect::<Vec<String>>();
+        let q = self.case.normalize(config, &self.q);
+        let mut words = split_words(&q).peekable();
+        words.peek().is_some()
+            && words.all(|word| texts.iter().any(|text| self.mode.matches(text, word)))
+    }
+}
+
+/// Stream the results while they are found, e.g. `GET /notes/search?stream=true`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct StreamQuery {
+    #[serde(default)]
+    stream: bool,
+}
+
+impl StreamQuery {
+    pub fn stream(&self) -> bool {
+        self.stream
+    }
+}
+
+/// Only notes that changed since a point in time, e.g. `?unread_since=2023-03-01T00:00:00Z`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct UnreadQuery {
+    unread_since: Option<DateTime<Utc>>,
+}
+
+impl UnreadQuery {
+    /// Returns `true` if the note was updated after `unread_since` and the user
+    /// did not view it since, `viewed_at` is the last time the user viewed the note
+    pub fn matches(&self, note: &Note, viewed_at: Option<DateTime<Utc>>) -> bool {
+        let Some(since) = self.unread_since else {
+            return true;
+        };
+        note.updated() > &since && viewed_at.is_none_or(|viewed_at| &viewed_at < note.updated())
+    }
+}
+
+/// The notes as they were at a point in time, e.g. `?as_of=2024-06-01T00:00:00Z`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AsOfQuery {
+    as_of: Option<DateTime<Utc>>,
+}
+
+impl AsOfQuery {
+    pub fn as_of(&self) -> Option<&DateTime<Utc>> {
+        self.as_of.as_ref()
+    }
+}
+
+/// A range of days, e.g. `?from=2023-03-01&to=2023-03-31`
+///
+/// Both bounds are inclusive and optional.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DateRange {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+impl DateRange {
+    /// Number of days included if `from` is not set
+    pub const DEFAULT_DAYS: u64 = 30;
+
+    /// Returns the bounds of the range, `to` defaults to `today` and
+    /// `from` to [`DateRange::DEFAULT_DAYS`] days before `to`
+    pub fn resolve(&self, today: NaiveDate) -> (NaiveDate, NaiveDate) {
+        let to = self.to.unwrap_or(today);
+        let from = self.from.unwrap_or_else(|| {
+            to.checked_sub_days(Days::new(Self::DEFAULT_DAYS - 1))
+                .unwrap_or(NaiveDate::MIN)
+        });
+        (from, to)
+    }
+}
+
+/// A [`NoteQuery`] that a user stored under a name
+///
+/// Saved searches act like smart folders: only the query is stored and
+/// the matching notes are looked up every time the search is executed.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SavedSearch {
+    id: Id,
+    name: String,
+    user: Id,
+    query: NoteQuery,
+}
+
+impl SavedSearch {
+    /// Constructs a new [`SavedSearch`]
+    pub fn new(id: Id, name: String, user: Id, query: NoteQuery) -> Self {
+        Self {
+            id,
+            name,
+            user,
+            query,
+        }
+    }
+
+    /// Returns the primary key of the [`SavedSearch`]
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+
+    /// Returns the name of the [`SavedSearch`]
+    #[allow(dead_code)] // needed for unittests
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the Id of the [`User`](`crate::models::User`) who owns the [`SavedSearch`]
+    pub fn user(&self) -> &Id {
+        &self.user
+    }
+
+    /// Returns the stored [`NoteQuery`]
+    pub fn query(&self) -> &NoteQuery {
+        &self.query
+    }
+}
+
+/// The payload to create a new [`SavedSearch`]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SearchDraft {
+    name: String,
+    query: NoteQuery,
+}
+
+impl SearchDraft {
+    #[allow(dead_code)] // needed for unittests
+    pub fn new(name: String, query: NoteQuery) -> Self {
+        Self { name, query }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Splits the draft into its name and [`NoteQuery`]
+    pub fn into_parts(self) -> (String, NoteQuery) {
+        (self.name, self.query)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+    use crate::models::note::{Draft, Tags};
+
+    #[test]
+    fn test_date_range() {
+        let today = NaiveDate::from_ymd_opt(2023, 3, 30).unwrap();
+        let first = NaiveDate::from_ymd_opt(2023, 3, 1).unwrap();
+
+        assert_eq!(DateRange::default().resolve(today), (first, today));
+
+        let range = DateRange {
+            from: Some(first),
+            to: Some(first),
+        };
+        assert_eq!(range.resolve(today), (first, first));
+    }
+
+    #[test]
+    fn test_unread_query() {
+        let note = example_note();
+        let updated = *note.updated();
+        let before = updated - chrono::TimeDelta::seconds(1);
+
+        assert!(UnreadQuery::default().matches(&note, Some(updated)));
+        let query = UnreadQuery {
+            unread_since: Some(before),
+        };
+        assert!(query.matches(&note, None));
+        assert!(query.matches(&note, Some(before)));
+        assert!(!query.matches(&note, Some(updated)));
+        let query = UnreadQuery {
+            unread_since: Some(updated),
+        };
+        assert!(!query.matches(&note, None));
+    }
+
+    #[test]
+    fn test_search_query() {
+        let note = example_note();
+        let query = |json: &str| serde_json::from_str::<SearchQuery>(json);
+        let config = SearchConfig::default();
+        let search = |json: &str| query(json).unwrap().matches(&note, &config);
+        assert!(query(r#"{"q": "Test"}"#).unwrap().is_indexed());
+        assert!(!query(r#"{"q": "Test", "match": "prefix"}"#)
+            .unwrap()
+            .is_indexed());
+
+        assert!(search(r#"{"q": "test"}"#));
+        assert!(!search(r#"{"q": "tes"}"#));
+        assert!(!search(r#"{"q": ""}"#));
+        assert!(search(r#"{"q": "tes", "match": "prefix"}"#));
+        assert!(search(r#"{"q": "est", "match": "substring"}"#));
+        assert!(!search(r#"{"q": "test", "case": "sensitive"}"#));
+        assert!(!search(r#"{"q": "Test title", "case": "sensitive"}"#));
+        assert!(search(r#"{"q": "Test Title", "case": "sensitive"}"#));
+        assert!(!search(r#"{"q": "tag1"}"#));
+        assert!(search(r#"{"q": "tag1", "fields": "tags"}"#));
+        assert!(!search(r#"{"q": "title", "fields": "body,tags"}"#));
+        assert!(search(r#"{"q": "tëst", "match": "prefix"}"#));
+        assert!(search(r#"{"q": "TÉST", "case": "insensitive"}"#));
+        assert!(!search(r#"{"q": "TÉST", "case": "sensitive"}"#));
+        assert!(query(r#"{"q": "a", "fields": "author"}"#).is_err());
+        assert!(query(r#"{"q": "a", "match": "fuzzy"}"#).is_err());
+    }
+
+    #[test]
+    fn test_search_fields() {
+        let fields = SearchFields::try_from("tags, title".to_string()).unwrap();
+        assert_eq!(String::from(fields), "title,tags");
+        assert_eq!(String::from(SearchFields::default()), "title,body");
+    }
+
+    #[test]
+    fn test_paginate() {
+        let items = (0..10).collect::<Vec<usize>>();
+        assert_eq!(ListOptions::default().paginate(items.clone(), None), items);
+        assert_eq!(
+            ListOptions::default().paginate(items.clone(), Some(3)),
+            vec![0, 1, 2]
+        );
+        let options = ListOptions {
+            full: false,
+            page: Some(4),
+            per_page: Some(3),
+            group: None,
+        };
+        assert_eq!(options.paginate(items.clone(), Some(5)), vec![9]);
+        assert_eq!(options.window(Some(5)), (9, Some(3)));
+        assert_eq!(ListOptions::default().window(None), (0, None));
+        let options = ListOptions {
+            page: Some(5),
+            ..options
+        };
+        assert!(options.paginate(items, None).is_empty());
+    }
+
+    #[test]
+    fn test_empty_query_matches() {
+        assert!(NoteQuery::default().matches(&example_note()));
+    }
+
+    #[test]
+    fn test_date_filters() {
+        let note = example_note();
+        let day = note.created().date_naive();
+        let query = |json: serde_json::Value| serde_json::from_value::<NoteQuery>(json).unwrap();
+        assert!(query(serde_json::json!({ "created_after": day })).matches(&note));
+        assert!(!query(serde_json::json!({ "created_before": day })).matches(&note));
+        assert!(query(serde_json::json!({
+            "updated_after": day.pred_opt(),
+            "updated_before": day.succ_opt(),
+        }))
+        .matches(&note));
+        assert!(
+            !query(serde_json::json!({ "q": "Test", "created_after": day.succ_opt() }))
+                .matches(&note)
+        );
+        assert!(query(serde_json::json!({ "q": "Other" }))
+            .without_text()
+            .matches(&note));
+    }
+
+    #[test]
+    fn test_note_state() {
+        let mut note = example_note();
+        let states = |note: &Note| {
+            [
+                NoteState::Active,
+                NoteState::Archived,
+                NoteState::Deleted,
+                NoteState::All,
+            ]
+            .map(|state| NoteQuery::default().with_state(state).matches(note))
+        };
+        assert_eq!(states(&note), [true, false, false, true]);
+        note.set_archived(true).unwrap();
+        assert_eq!(states(&note), [false, true, false, true]);
+        note.delete().unwrap();
+        assert_eq!(states(&note), [false, false, true, true]);
+
+        let query: NoteQuery = serde_json::from_str(r#"{"state": "archived"}"#).unwrap();
+        assert_eq!(query.state(), NoteState::Archived);
+    }
+
+    #[test]
+    fn test_sort_order() {
+        let order = SortOrder::try_from("updated:desc, title".to_string()).unwrap();
+        assert_eq!(
+            order.keys,
+            vec![(SortKey::Updated, true), (SortKey::Title, false)]
+        );
+        assert_eq!(String::from(order), "updated:desc,title:asc");
+
+        assert!(SortOrder::try_from("pinned:desc".to_string()).is_err());
+        assert!(SortOrder::try_from("title:up".to_string()).is_err());
+        assert!(SortOrder::try_from("title,title:desc".to_string()).is_err());
+        assert!(SortOrder::try_from("".to_string()).is_err());
+
+        // old queries with a single key are still valid
+        let query: NoteQuery = serde_json::from_str(r#"{"sort": "title"}"#).unwrap();
+        assert_eq!(query.sort(), SortOrder::from(SortKey::Title));
+    }
+
+    #[test]
+    fn test_sort_order_compare() {
+        let first = example_note();
+        let note = |id: usize| {
+            let draft = Draft::new("A".into(), "".into(), vec![], Visibility::Public);
+            Note::new(draft, Id(id), Id(12), Tags::default())
+        };
+        let (second, third) = (note(2), note(3));
+        let mut notes = vec![&third, &first, &second];
+
+        let order = SortOrder::try_from("title:desc".to_string()).unwrap();
+        notes.sort_by(|a, b| order.compare(a, b));
+        // ties are ordered by Id
+        assert_eq!(notes, vec![&first, &second, &third]);
+
+        let order = SortOrder::try_from("title,id:desc".to_string()).unwrap();
+        notes.sort_by(|a, b| order.compare(a, b));
+        assert_eq!(notes, vec![&third, &second, &first]);
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let note = example_note();
+
+        let query = NoteQuery::new(Some("tag1".to_string()), None, None, None);
+        assert!(query.matches(&note));
+        let query = NoteQuery::new(Some("foobar".to_string()), None, None, None);
+        assert!(!query.matches(&note));
+
+        let query = NoteQuery::new(None, Some("Body".to_string()), None, None);
+        assert!(query.matches(&note));
+        let query = NoteQuery::new(None, Some("Title".to_string()), None, None);
+        assert!(query.matches(&note));
+        let query = NoteQuery::new(None, Some("foobar".to_string()), None, None);
+        assert!(!query.matches(&note));
+
+        let query = NoteQuery::new(None, None, Some(Visibility::Public), None);
+        assert!(query.matches(&note));
+        let query = NoteQuery::new(None, None, Some(Visibility::Private), None);
+        assert!(!query.matches(&note));
+
+        let query = NoteQuery::new(
+            Some("tag1".to_string()),
+            Some("foobar".to_string()),
+            Some(Visibility::Public),
+            None,
+        );
+        assert!(!query.matches(&note));
+    }
+}
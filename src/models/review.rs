@@ -0,0 +1,248 @@
+//! The periodic review of a user's notes, e.g. a GTD-style weekly review with `GET /review`
+use std::collections::BTreeMap;
+
+use chrono::{Days, Months, NaiveDate};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::dates;
+use crate::models::lifecycle::State;
+use crate::models::note::{Note, NoteSummary};
+use crate::models::Id;
+use crate::stats::rollup::Activity;
+
+/// The calendar period of a review
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    /// Monday to Sunday
+    #[default]
+    Week,
+    Month,
+}
+
+impl Period {
+    /// Returns the first and the last day of the period that contains `date`
+    ///
+    /// Returns `None` if the period exceeds the range of [`NaiveDate`].
+    pub fn bounds(&self, date: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+        match self {
+            Period::Day => Some((date, date)),
+            Period::Week => {
+                let from = dates::week_start(date)?;
+                Some((from, from.checked_add_days(Days::new(6))?))
+            }
+            Period::Month => {
+                let from = dates::month_start(date);
+                let to = from
+                    .checked_add_months(Months::new(1))?
+                    .checked_sub_days(Days::new(1))?;
+                Some((from, to))
+            }
+        }
+    }
+}
+
+/// The query of `GET /review`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct ReviewQuery {
+    #[serde(default)]
+    pub period: Period,
+    /// Any day of the period, today in the timezone of the user if not set
+    pub date: Option<NaiveDate>,
+}
+
+/// Which notes of a tag were created, edited or left untouched in the period
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TagBreakdown {
+    tag: String,
+    created: usize,
+    edited: usize,
+    untouched: usize,
+}
+
+/// The active notes of a user, grouped by what happened to them in a period
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Review {
+    period: Period,
+    from: NaiveDate,
+    to: NaiveDate,
+    /// Notes created in the period, including ones that were edited afterwards
+    created: Vec<NoteSummary>,
+    /// Older notes that were edited in the period
+    edited: Vec<NoteSummary>,
+    /// Older notes that were not edited in the period
+    untouched: Vec<NoteSummary>,
+    /// Sorted by label, notes without tags are not included
+    tags: Vec<TagBreakdown>,
+    /// The number of created, edited and deleted notes of the daily statistics, including
+    /// notes that were archived or deleted since
+    activity: Activity,
+}
+
+impl Review {
+    /// Groups the notes of the user by the days of their creation and update times in the
+    /// timezone, in the period from `from` to `to`, see [`Period::bounds`]
+    ///
+    /// Only active notes that existed at the end of the period are included, the buckets are
+    /// sorted from the most recently updated note.
+    pub fn new<'a>(
+        period: Period,
+        (from, to): (NaiveDate, NaiveDate),
+        notes: impl Iterator<Item = &'a Note>,
+        activity: Activity,
+        preview_length: usize,
+        viewer: &Id,
+        timezone: &Tz,
+    ) -> Self {
+        let mut notes = notes
+            .filter(|note| {
+                note.state() == State::Active && dates::local_date(note.created(), timezone) <= to
+            })
+            .collect::<Vec<&Note>>();
+        notes.sort_by(|a, b| b.updated().cmp(a.updated()).then(a.id().cmp(b.id())));
+
+        let mut review = Self {
+            period,
+            from,
+            to,
+            created: vec![],
+            edited: vec![],
+            untouched: vec![],
+            tags: vec![],
+            activity,
+        };
+        let mut tags = BTreeMap::<&str, TagBreakdown>::new();
+        for note in notes {
+            let updated = dates::local_date(note.updated(), timezone);
+            let created = dates::local_date(note.created(), timezone) >= from;
+            let edited = !created && updated >= from && updated <= to;
+            for tag in note.tags() {
+                let breakdown = tags.entry(tag.label()).or_insert_with(|| TagBreakdown {
+                    tag: tag.label().to_string(),
+                    ..TagBreakdown::default()
+                });
+                match (created, edited) {
+                    (true, _) => breakdown.created += 1,
+                    (false, true) => breakdown.edited += 1,
+                    (false, false) => breakdown.untouched += 1,
+                }
+            }
+            let summary = NoteSummary::new(note, preview_length, viewer);
+            match (created, edited) {
+                (true, _) => review.created.push(summary),
+                (false, true) => review.edited.push(summary),
+                (false, false) => review.untouched.push(summary),
+            }
+        }
+        review.tags = tags.into_values().collect();
+        review
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, day).unwrap()
+    }
+
+    fn note(id: usize, created: u32, updated: u32) -> Note {
+        let mut note = serde_json::to_value(example_note()).unwrap();
+        note["id"] = id.into();
+        note["created"] = format!("{}T12:00:00Z", day(created)).into();
+        note["updated"] = format!("{}T12:00:00Z", day(updated)).into();
+        serde_json::from_value(note).unwrap()
+    }
+
+    fn ids(notes: &[NoteSummary]) -> Vec<Id> {
+        notes.iter().map(|note| *note.id()).collect()
+    }
+
+    #[test]
+    fn test_review() {
+        let notes = [
+            note(1, 14, 14),
+            note(2, 2, 16),
+            note(3, 2, 3),
+            note(4, 2, 25),
+            // created after the period
+            note(5, 20, 20),
+        ];
+        let review = Review::new(
+            Period::Week,
+            Period::Week.bounds(day(15)).unwrap(),
+            notes.iter(),
+            Activity::default(),
+            10,
+            &Id(0),
+            &Tz::UTC,
+        );
+        assert_eq!(ids(&review.created), vec![Id(1)]);
+        assert_eq!(ids(&review.edited), vec![Id(2)]);
+        assert_eq!(ids(&review.untouched), vec![Id(4), Id(3)]);
+        assert_eq!(review.tags.len(), 3);
+        assert_eq!(
+            review.tags[0],
+            TagBreakdown {
+                tag: "tag1".to_string(),
+                created: 1,
+                edited: 1,
+                untouched: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_review_timezone() {
+        // late on Sunday in UTC, already Monday in Berlin
+        let mut late = serde_json::to_value(note(1, 12, 12)).unwrap();
+        late["created"] = "2024-05-12T23:00:00Z".into();
+        late["updated"] = "2024-05-12T23:00:00Z".into();
+        let notes = [serde_json::from_value::<Note>(late).unwrap()];
+        let review = |timezone| {
+            let bounds = Period::Week.bounds(day(15)).unwrap();
+            Review::new(
+                Period::Week,
+                bounds,
+                notes.iter(),
+                Activity::default(),
+                10,
+                &Id(0),
+                &timezone,
+            )
+        };
+        assert_eq!(ids(&review(Tz::UTC).untouched), vec![Id(1)]);
+        assert_eq!(ids(&review(Tz::Europe__Berlin).created), vec![Id(1)]);
+    }
+
+    #[test]
+    fn test_bounds() {
+        // a Wednesday
+        let date = day(15);
+        assert_eq!(Period::Day.bounds(date), Some((date, date)));
+        assert_eq!(Period::Week.bounds(date), Some((day(13), day(19))));
+        assert_eq!(Period::Week.bounds(day(13)), Some((day(13), day(19))));
+        assert_eq!(Period::Week.bounds(day(19)), Some((day(13), day(19))));
+        assert_eq!(Period::Month.bounds(date), Some((day(1), day(31))));
+        assert_eq!(
+            Period::Month
+                .bounds(NaiveDate::from_ymd_opt(2024, 2, 29).unwrap())
+                .unwrap()
+                .1,
+            NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()
+        );
+
+        // periods beyond the range of dates
+        assert_eq!(
+            Period::Day.bounds(NaiveDate::MAX),
+            Some((NaiveDate::MAX, NaiveDate::MAX))
+        );
+        assert_eq!(Period::Week.bounds(NaiveDate::MAX), None);
+        assert_eq!(Period::Month.bounds(NaiveDate::MAX), None);
+        assert_eq!(Period::Week.bounds(NaiveDate::MIN), None);
+    }
+}
@@ -0,0 +1,194 @@
+//! Per-user rules that tie the visibility of notes to their tags, edited with `PUT /me/tag-rules`
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::note::Draft;
+use crate::models::Visibility;
+
+/// Maximum number of rules per user
+const MAX_RULES: usize = 100;
+
+/// How a [`TagRule`] treats notes that request another visibility
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// The visibility of the note is changed to the one of the rule
+    #[default]
+    Adjust,
+    /// Notes that request another visibility are rejected
+    Enforce,
+}
+
+/// Notes with the tag get the visibility, e.g. `journal` is always `Private`
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct TagRule {
+    tag: String,
+    visibility: Visibility,
+    #[serde(default)]
+    action: RuleAction,
+}
+
+impl TagRule {
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn visibility(&self) -> &Visibility {
+        &self.visibility
+    }
+
+    pub fn action(&self) -> RuleAction {
+        self.action
+    }
+}
+
+/// Returns the position of the visibility from the most to the least restrictive
+fn openness(visibility: &Visibility) -> usize {
+    match visibility {
+        Visibility::Deleted | Visibility::Private => 0,
+        Visibility::Unlisted => 1,
+        Visibility::Workspace => 2,
+        Visibility::Public => 3,
+    }
+}
+
+/// The [`TagRule`]s of a user
+///
+/// If several rules match the tags of a note, the most restrictive visibility wins.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct TagRules(Vec<TagRule>);
+
+impl TagRules {
+    /// Checks that every tag has at most one rule and that no rule deletes notes
+    pub fn validate(&self) -> Result<(), String> {
+        if self.0.len() > MAX_RULES {
+            return Err(format!("At most {MAX_RULES} rules are allowed"));
+        }
+        let mut tags = HashSet::new();
+        for rule in &self.0 {
+            if rule.tag.trim().is_empty() {
+                return Err("tag must not be empty".to_string());
+            }
+            if rule.visibility == Visibility::Deleted {
+                return Err(format!("The rule of `{}` can't delete notes", rule.tag));
+            }
+            if !tags.insert(&rule.tag) {
+                return Err(format!("Tag `{}` has more than one rule", rule.tag));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rules(&self) -> &[TagRule] {
+        &self.0
+    }
+
+    /// Returns the most restrictive rule that matches one of the labels
+    pub fn matching<'b, I: IntoIterator<Item = &'b str>>(&self, labels: I) -> Option<&TagRule> {
+        let labels = labels.into_iter().collect::<HashSet<&str>>();
+        self.0
+            .iter()
+            .filter(|rule| labels.contains(rule.tag.as_str()))
+            .min_by_key(|rule| openness(&rule.visibility))
+    }
+
+    /// Sets the visibility of the draft according to the rules of its tags
+    ///
+    /// Fails if an [`RuleAction::Enforce`] rule matches and the draft requests another visibility.
+    pub fn apply(&self, draft: Draft) -> Result<Draft, String> {
+        let labels = draft.tags().iter().map(String::as_str);
+        if let Some(rule) = self.0.iter().find(|rule| {
+            rule.action == RuleAction::Enforce
+                && labels.clone().any(|label| label == rule.tag)
+                && draft
+                    .visibility()
+                    .is_some_and(|visibility| visibility != &rule.visibility)
+        }) {
+            return Err(format!(
+                "Notes tagged `{}` must be {:?}",
+                rule.tag, rule.visibility
+            ));
+        }
+        Ok(match self.matching(labels) {
+            Some(rule) => {
+                let visibility = rule.visibility.clone();
+                draft.with_visibility(visibility)
+            }
+            None => draft,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rules(json: &str) -> TagRules {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn draft(tags: &[&str], visibility: Option<Visibility>) -> Draft {
+        let tags = tags
+            .iter()
+            .map(|tag| tag.to_string())
+            .collect::<Vec<String>>();
+        match visibility {
+            Some(visibility) => Draft::new("Title".to_string(), String::new(), tags, visibility),
+            None => serde_json::from_value(serde_json::json!({
+                "title": "Title", "body": "", "tags": tags
+            }))
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(rules(r#"[{"tag": "blog", "visibility": "Public"}]"#)
+            .validate()
+            .is_ok());
+        assert!(rules(r#"[{"tag": " ", "visibility": "Public"}]"#)
+            .validate()
+            .is_err());
+        assert!(rules(r#"[{"tag": "trash", "visibility": "Deleted"}]"#)
+            .validate()
+            .is_err());
+        assert!(rules(
+            r#"[{"tag": "blog", "visibility": "Public"}, {"tag": "blog", "visibility": "Private"}]"#
+        )
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let rules = rules(
+            r#"[
+                {"tag": "journal", "visibility": "Private", "action": "enforce"},
+                {"tag": "blog", "visibility": "Public"},
+                {"tag": "team", "visibility": "Workspace"}
+            ]"#,
+        );
+        let applied = rules.apply(draft(&["blog"], None)).unwrap();
+        assert_eq!(applied.visibility(), Some(&Visibility::Public));
+        let applied = rules
+            .apply(draft(&["blog"], Some(Visibility::Private)))
+            .unwrap();
+        assert_eq!(applied.visibility(), Some(&Visibility::Public));
+        let applied = rules.apply(draft(&["blog", "team"], None)).unwrap();
+        assert_eq!(applied.visibility(), Some(&Visibility::Workspace));
+        let applied = rules.apply(draft(&["other"], None)).unwrap();
+        assert_eq!(applied.visibility(), None);
+
+        let applied = rules.apply(draft(&["journal", "blog"], None)).unwrap();
+        assert_eq!(applied.visibility(), Some(&Visibility::Private));
+        assert_eq!(
+            rules.apply(draft(&["journal"], Some(Visibility::Public))),
+            Err("Notes tagged `journal` must be Private".to_string())
+        );
+        assert!(rules
+            .apply(draft(&["journal"], Some(Visibility::Private)))
+            .is_ok());
+    }
+}
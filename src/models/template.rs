@@ -0,0 +1,454 @@
+//! Per-user templates for recurring notes with typed fields, edited with `PUT /me/templates`
+//!
+//! A note created from a template, e.g. a workout log, carries the values of the template's
+//! fields. The values are validated whenever the note is saved and can be filtered like the
+//! columns of a small database, e.g. `GET /notes?field.rating>=4`.
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+
+use chrono::NaiveDate;
+use serde::de::{self, IgnoredAny, MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Maximum number of templates per user
+const MAX_TEMPLATES: usize = 50;
+
+/// Maximum number of fields per template
+const MAX_FIELDS: usize = 20;
+
+/// The prefix of field filters in the query string, e.g. `field.rating=5`
+const FILTER_PREFIX: &str = "field.";
+
+/// The type of the values of a [`Field`]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum FieldType {
+    /// A day, e.g. `"2024-03-01"`
+    Date,
+    Number,
+    /// One of the options, e.g. `"good"`
+    Select {
+        options: Vec<String>,
+    },
+}
+
+/// A typed field of a [`Template`]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Field {
+    name: String,
+    #[serde(flatten)]
+    kind: FieldType,
+    /// Notes of the template must set the field
+    #[serde(default)]
+    required: bool,
+}
+
+impl Field {
+    /// Checks that the value has the type of the field
+    fn check(&self, value: &FieldValue) -> Result<(), String> {
+        match (&self.kind, value) {
+            (FieldType::Number, FieldValue::Number(_)) => Ok(()),
+            (FieldType::Date, FieldValue::Text(text))
+                if NaiveDate::parse_from_str(text, "%Y-%m-%d").is_ok() =>
+            {
+                Ok(())
+            }
+            (FieldType::Select { options }, FieldValue::Text(text)) if options.contains(text) => {
+                Ok(())
+            }
+            (FieldType::Number, _) => Err(format!("Field `{}` must be a number", self.name)),
+            (FieldType::Date, _) => Err(format!(
+                "Field `{}` must be a date like `2024-03-01`",
+                self.name
+            )),
+            (FieldType::Select { options }, _) => Err(format!(
+                "Field `{}` must be one of {}",
+                self.name,
+                options.join(", ")
+            )),
+        }
+    }
+}
+
+/// Returns `true` if the name can be used in the query string, e.g. `workout_type`
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The value of a field of a note, dates and options are strings
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Number(serde_json::Number),
+    Text(String),
+}
+
+impl FieldValue {
+    /// Compares the value with the value of a [`FieldFilter`]
+    ///
+    /// Numbers are compared numerically, dates and options as strings, which orders dates
+    /// by time. Returns `None` if the filter value is no number for a numeric value.
+    fn compare(&self, other: &str) -> Option<Ordering> {
+        match self {
+            FieldValue::Number(number) => number.as_f64()?.partial_cmp(&other.parse::<f64>().ok()?),
+            FieldValue::Text(text) => Some(text.as_str().cmp(other)),
+        }
+    }
+}
+
+/// The values of the fields of a note by their names
+pub type FieldValues = BTreeMap<String, FieldValue>;
+
+/// A named set of [`Field`]s, e.g. `workout` with a `date`, the `distance` and the `mood`
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Template {
+    name: String,
+    fields: Vec<Field>,
+}
+
+impl Template {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Checks that the values belong to fields of the template, have their types and
+    /// that all required fields are set
+    fn check(&self, values: &FieldValues) -> Result<(), String> {
+        for (name, value) in values {
+            let Some(field) = self.fields.iter().find(|field| &field.name == name) else {
+                return Err(format!(
+                    "Field `{name}` is not part of template `{}`",
+                    self.name
+                ));
+            };
+            field.check(value)?;
+        }
+        match self
+            .fields
+            .iter()
+            .find(|field| field.required && !values.contains_key(&field.name))
+        {
+            Some(field) => Err(format!("Field `{}` is required", field.name)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The [`Template`]s of a user
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Templates(Vec<Template>);
+
+impl Templates {
+    /// Checks that the names of templates and their fields are unique and can be queried,
+    /// and that select fields have options
+    pub fn validate(&self) -> Result<(), String> {
+        if self.0.len() > MAX_TEMPLATES {
+            return Err(format!("At most {MAX_TEMPLATES} templates are allowed"));
+        }
+        let mut templates = HashSet::new();
+        for template in &self.0 {
+            if template.name.trim().is_empty() {
+                return Err("name must not be empty".to_string());
+            }
+            if !templates.insert(&template.name) {
+                return Err(format!("Template `{}` exists twice", template.name));
+            }
+            if template.fields.len() > MAX_FIELDS {
+                return Err(format!(
+                    "Template `{}` has more than {MAX_FIELDS} fields",
+                    template.name
+                ));
+            }
+            let mut fields = HashSet::new();
+            for field in &template.fields {
+                if !valid_name(&field.name) {
+                    return Err(format!(
+                        "Field `{}` may only contain letters, digits, `_` and `-`",
+                        field.name
+                    ));
+                }
+                if !fields.insert(&field.name) {
+                    return Err(format!(
+                        "Field `{}` exists twice in template `{}`",
+                        field.name, template.name
+                    ));
+                }
+                if let FieldType::Select { options } = &field.kind {
+                    if options.is_empty() || options.iter().any(|option| option.is_empty()) {
+                        return Err(format!("Field `{}` needs options", field.name));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn templates(&self) -> &[Template] {
+        &self.0
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Template> {
+        self.0.iter().find(|template| template.name == name)
+    }
+
+    /// Checks the field values of a note against its template
+    ///
+    /// Notes without a template can't have fields.
+    pub fn check(&self, template: Option<&str>, values: &FieldValues) -> Result<(), String> {
+        match template {
+            None if values.is_empty() => Ok(()),
+            None => Err("Fields require a template".to_string()),
+            Some(name) => match self.get(name) {
+                Some(template) => template.check(values),
+                None => Err(format!("Template `{name}` does not exist")),
+            },
+        }
+    }
+}
+
+/// How a [`FieldFilter`] compares the value of a field
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Comparison {
+    /// `field.name=value`
+    Eq,
+    /// `field.name!=value`
+    Ne,
+    /// `field.name<value`
+    Lt,
+    /// `field.name<=value`
+    Le,
+    /// `field.name>value`
+    Gt,
+    /// `field.name>=value`
+    Ge,
+}
+
+impl Comparison {
+    fn accepts(&self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Eq => ordering.is_eq(),
+            Comparison::Ne => ordering.is_ne(),
+            Comparison::Lt => ordering.is_lt(),
+            Comparison::Le => ordering.is_le(),
+            Comparison::Gt => ordering.is_gt(),
+            Comparison::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+/// Compares a field of notes with a value, e.g. `field.rating>=4`
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FieldFilter {
+    field: String,
+    comparison: Comparison,
+    value: String,
+}
+
+impl FieldFilter {
+    /// Parses a parameter of the query string, returns `None` if it is no field filter
+    ///
+    /// The `=` of the query string ends the key, so `field.rating>=4` is the key
+    /// `field.rating>` with the value `4`, and `field.rating>4` is a key without value.
+    fn parse(key: &str, value: &str) -> Option<Result<Self, String>> {
+        let rest = key.strip_prefix(FILTER_PREFIX)?;
+        let (field, operator) = rest.split_at(rest.find(['<', '>', '!']).unwrap_or(rest.len()));
+        let (comparison, value) = match (operator, value) {
+            ("", value) => (Comparison::Eq, value),
+            ("!", value) => (Comparison::Ne, value),
+            ("<", value) => (Comparison::Le, value),
+            (">", value) => (Comparison::Ge, value),
+            (operator, "") if operator.starts_with('<') => (Comparison::Lt, &operator[1..]),
+            (operator, "") if operator.starts_with('>') => (Comparison::Gt, &operator[1..]),
+            _ => return Some(Err(format!("invalid field filter `{key}={value}`"))),
+        };
+        if !valid_name(field) || value.is_empty() {
+            return Some(Err(format!("invalid field filter `{key}={value}`")));
+        }
+        Some(Ok(Self {
+            field: field.to_string(),
+            comparison,
+            value: value.to_string(),
+        }))
+    }
+
+    /// The inverse of [`FieldFilter::parse`]
+    fn to_parameter(&self) -> (String, &str) {
+        let key = format!("{FILTER_PREFIX}{}", self.field);
+        match self.comparison {
+            Comparison::Eq => (key, &self.value),
+            Comparison::Ne => (key + "!", &self.value),
+            Comparison::Le => (key + "<", &self.value),
+            Comparison::Ge => (key + ">", &self.value),
+            Comparison::Lt => (format!("{key}<{}", self.value), ""),
+            Comparison::Gt => (format!("{key}>{}", self.value), ""),
+        }
+    }
+
+    /// Returns `true` if the note has the field and its value passes the comparison
+    pub fn matches(&self, values: &FieldValues) -> bool {
+        values
+            .get(&self.field)
+            .and_then(|value| value.compare(&self.value))
+            .is_some_and(|ordering| self.comparison.accepts(ordering))
+    }
+}
+
+/// The field filters of a [`NoteQuery`](crate::models::query::NoteQuery), combined with `AND`
+///
+/// Flattened into the query, so it collects all `field.*` parameters and ignores the others.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct FieldFilters(BTreeSet<FieldFilter>);
+
+impl FieldFilters {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn matches(&self, values: &FieldValues) -> bool {
+        self.0.iter().all(|filter| filter.matches(values))
+    }
+}
+
+impl Serialize for FieldFilters {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for filter in &self.0 {
+            let (key, value) = filter.to_parameter();
+            map.serialize_entry(&key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for FieldFilters {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FiltersVisitor;
+
+        impl<'de> Visitor<'de> for FiltersVisitor {
+            type Value = FieldFilters;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("field filters like `field.rating>=4`")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut filters = BTreeSet::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    if !key.starts_with(FILTER_PREFIX) {
+                        map.next_value::<IgnoredAny>()?;
+                        continue;
+                    }
+                    let value = map.next_value::<String>()?;
+                    match FieldFilter::parse(&key, &value) {
+                        Some(filter) => filters.insert(filter.map_err(de::Error::custom)?),
+                        None => continue,
+                    };
+                }
+                Ok(FieldFilters(filters))
+            }
+        }
+
+        deserializer.deserialize_map(FiltersVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn templates() -> Templates {
+        serde_json::from_value(json!([{
+            "name": "workout",
+            "fields": [
+                {"name": "day", "type": "date", "required": true},
+                {"name": "distance", "type": "number"},
+                {"name": "mood", "type": "select", "options": ["good", "bad"]},
+            ],
+        }]))
+        .unwrap()
+    }
+
+    fn values(values: serde_json::Value) -> FieldValues {
+        serde_json::from_value(values).unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(templates().validate().is_ok());
+        let twice: Templates = serde_json::from_value(json!([
+            {"name": "a", "fields": []},
+            {"name": "a", "fields": []},
+        ]))
+        .unwrap();
+        assert!(twice.validate().is_err());
+        let spaces: Templates = serde_json::from_value(json!([
+            {"name": "a", "fields": [{"name": "my rating", "type": "number"}]},
+        ]))
+        .unwrap();
+        assert!(spaces.validate().is_err());
+        let options: Templates = serde_json::from_value(json!([
+            {"name": "a", "fields": [{"name": "mood", "type": "select", "options": []}]},
+        ]))
+        .unwrap();
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let templates = templates();
+        let check = |value| templates.check(Some("workout"), &values(value));
+        assert!(check(json!({"day": "2024-03-01", "distance": 5.5, "mood": "good"})).is_ok());
+        assert!(check(json!({"day": "2024-03-01"})).is_ok());
+        assert!(check(json!({"distance": 5})).is_err());
+        assert!(check(json!({"day": "yesterday"})).is_err());
+        assert!(check(json!({"day": "2024-03-01", "distance": "far"})).is_err());
+        assert!(check(json!({"day": "2024-03-01", "mood": "great"})).is_err());
+        assert!(check(json!({"day": "2024-03-01", "weight": 80})).is_err());
+        assert!(templates.check(None, &FieldValues::new()).is_ok());
+        assert!(templates
+            .check(None, &values(json!({"distance": 5})))
+            .is_err());
+        assert!(templates.check(Some("run"), &FieldValues::new()).is_err());
+    }
+
+    #[test]
+    fn test_filters() {
+        let note = values(json!({"day": "2024-03-01", "distance": 10, "mood": "good"}));
+        assert!(filters("field.distance>=10").matches(&note));
+        assert!(filters("field.distance>9.5&field.mood=good").matches(&note));
+        assert!(filters("field.day<2024-03-02&field.mood!=bad").matches(&note));
+        assert!(!filters("field.distance<10").matches(&note));
+        assert!(!filters("field.distance<=abc").matches(&note));
+        assert!(!filters("field.weight>=1").matches(&note));
+        assert!(filters("tag=a&page=2").is_empty());
+
+        let query = filters("field.distance<=10&field.day>2024-01-01");
+        let json = serde_json::to_value(&query).unwrap();
+        assert_eq!(
+            json,
+            json!({"field.distance<": "10", "field.day>2024-01-01": ""})
+        );
+        assert_eq!(serde_json::from_value::<FieldFilters>(json).unwrap(), query);
+    }
+
+    /// Parses the field filters of a query string like [`axum::extract::Query`]
+    fn filters(query: &str) -> FieldFilters {
+        let pairs = query
+            .split('&')
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .map(|(key, value)| (key.to_string(), json!(value)))
+            .collect::<serde_json::Map<String, serde_json::Value>>();
+        serde_json::from_value(pairs.into()).unwrap()
+    }
+}
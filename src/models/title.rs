@@ -0,0 +1,85 @@
+//! Normalization of note titles, applied to every [`Draft`](crate::models::note::Draft) before it is stored
+//!
+//! Titles are trimmed, runs of whitespace (including line breaks) are collapsed into a single
+//! space and the length is counted in grapheme clusters, so that an emoji or a letter with
+//! combining accents counts as one character, no matter how many bytes it takes.
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Zero width joiner, combines emoji into a single glyph, e.g. 👩‍💻
+const ZWJ: char = '\u{200D}';
+/// Variation selector that requests the emoji presentation of a character, e.g. ❤️
+const EMOJI_PRESENTATION: char = '\u{FE0F}';
+
+/// Trims the title and collapses all whitespace into single spaces
+pub fn normalize(title: &str) -> String {
+    title.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// The number of grapheme clusters of the title
+pub fn length(title: &str) -> usize {
+    title.graphemes(true).count()
+}
+
+/// Returns `true` if the character is shown as emoji by default
+///
+/// Covers the pictographic blocks of Unicode, characters that are only emoji with a variation
+/// selector, like ❤️ or 1️⃣, are recognized by [`is_emoji`].
+fn is_pictographic(c: char) -> bool {
+    matches!(c,
+        '\u{1F000}'..='\u{1FAFF}'
+        | '\u{2600}'..='\u{27BF}'
+        | '\u{2B05}'..='\u{2B55}'
+        | '\u{231A}'..='\u{23FF}'
+    )
+}
+
+/// Returns `true` if the grapheme cluster is a single emoji, including sequences like 👩‍💻 or 🇩🇪
+pub fn is_emoji(grapheme: &str) -> bool {
+    let mut chars = grapheme.chars();
+    match chars.next() {
+        Some(first) if is_pictographic(first) => chars
+            .all(|c| is_pictographic(c) || c == ZWJ || c == EMOJI_PRESENTATION || c == '\u{20E3}'),
+        Some(first) if !first.is_whitespace() => grapheme.contains(EMOJI_PRESENTATION),
+        _ => false,
+    }
+}
+
+/// Splits a leading emoji off the normalized title, e.g. `"🚀 Launch"` into `("🚀", "Launch")`
+///
+/// Returns `None` if the title does not start with an emoji or consists of the emoji alone,
+/// so that a note never ends up without a title.
+pub fn split_icon(title: &str) -> Option<(&str, &str)> {
+    let icon = title.graphemes(true).next()?;
+    if !is_emoji(icon) {
+        return None;
+    }
+    let rest = title[icon.len()..].trim_start();
+    (!rest.is_empty()).then_some((icon, rest))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("  My \t meeting\n\nnotes "), "My meeting notes");
+        assert_eq!(normalize(" \n "), "");
+        assert_eq!(length("Café"), 4);
+        assert_eq!(length("Cafe\u{301}"), 4);
+        assert_eq!(length("👩‍💻 Code"), 6);
+    }
+
+    #[test]
+    fn test_split_icon() {
+        assert_eq!(split_icon("🚀 Launch"), Some(("🚀", "Launch")));
+        assert_eq!(split_icon("👩‍💻Code"), Some(("👩‍💻", "Code")));
+        assert_eq!(split_icon("🇩🇪 Travel"), Some(("🇩🇪", "Travel")));
+        assert_eq!(split_icon("❤️ Favorites"), Some(("❤️", "Favorites")));
+        assert_eq!(split_icon("1️⃣ First"), Some(("1️⃣", "First")));
+        assert_eq!(split_icon("1 First"), None);
+        assert_eq!(split_icon("Launch 🚀"), None);
+        assert_eq!(split_icon("🚀"), None);
+        assert_eq!(split_icon(""), None);
+    }
+}
@@ -0,0 +1,232 @@
+//! Service account tokens for scripts and integrations
+//!
+//! A service token acts on behalf of the [`User`](crate::models::User) who created it,
+//! but only for routes that require one of its [`Scope`]s. Tokens are sent as
+//! `Authorization: Bearer <secret>` header, like sessions, and are recognized by the
+//! [`TOKEN_PREFIX`] of their secret.
+use std::collections::BTreeSet;
+
+use axum::http::Method;
+use chrono::{DateTime, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+
+use crate::models::Id;
+
+/// The prefix of all secrets, distinguishes service tokens from session tokens
+pub const TOKEN_PREFIX: &str = "nt_";
+
+/// Maximum number of characters of the name of a token
+const MAX_NAME_LENGTH: usize = 64;
+
+/// A permission that is granted to a [`ServiceToken`]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Scope {
+    /// Read notes, mentions and the CSV export
+    #[serde(rename = "notes:read")]
+    NotesRead,
+    /// Create, modify and delete notes
+    #[serde(rename = "notes:write")]
+    NotesWrite,
+    /// List tags
+    #[serde(rename = "tags:read")]
+    TagsRead,
+}
+
+impl Scope {
+    /// Returns the scope a token needs for the route, e.g. `/note/:id`
+    ///
+    /// Routes without a scope, e.g. to manage the account or its tokens, can't be
+    /// used with service tokens at all.
+    pub fn required(method: &Method, route: &str) -> Option<Self> {
+        let notes = route == "/note"
+            || route.starts_with("/note/")
+            || route.starts_with("/notes")
+            || route == "/undo/:token";
+        if method == Method::GET || method == Method::HEAD {
+            match route {
+                "/tags" | "/tags/graph" => Some(Scope::TagsRead),
+                "/mentions" | "/export.csv" => Some(Scope::NotesRead),
+                _ if notes => Some(Scope::NotesRead),
+                _ => None,
+            }
+        } else if notes {
+            Some(Scope::NotesWrite)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::NotesRead => "notes:read",
+            Scope::NotesWrite => "notes:write",
+            Scope::TagsRead => "tags:read",
+        }
+    }
+}
+
+/// A token that grants a subset of the permissions of its user
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ServiceToken {
+    id: Id,
+    user: Id,
+    name: String,
+    scopes: BTreeSet<Scope>,
+    secret: String,
+    created: DateTime<Utc>,
+}
+
+impl ServiceToken {
+    pub fn new(id: Id, user: Id, draft: TokenDraft, secret: String) -> Self {
+        Self {
+            id,
+            user,
+            name: draft.name,
+            scopes: draft.scopes,
+            secret,
+            created: Utc::now(),
+        }
+    }
+
+    /// Creates a new random secret for a token
+    pub fn generate_secret() -> String {
+        format!(
+            "{TOKEN_PREFIX}{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 40)
+        )
+    }
+
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+
+    pub fn user(&self) -> &Id {
+        &self.user
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+/// The payload of `POST /me/tokens`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TokenDraft {
+    name: String,
+    scopes: BTreeSet<Scope>,
+}
+
+impl TokenDraft {
+    pub fn new(name: String, scopes: BTreeSet<Scope>) -> Self {
+        Self { name, scopes }
+    }
+
+    /// Checks that the token has a name and at least one scope
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name must not be empty".to_string());
+        }
+        if self.name.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!(
+                "name must not be longer than {MAX_NAME_LENGTH} characters"
+            ));
+        }
+        if self.scopes.is_empty() {
+            return Err("At least one scope is required".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A [`ServiceToken`] without its secret, returned by `GET /me/tokens`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TokenInfo {
+    id: Id,
+    name: String,
+    scopes: BTreeSet<Scope>,
+    created: DateTime<Utc>,
+}
+
+impl From<&ServiceToken> for TokenInfo {
+    fn from(token: &ServiceToken) -> Self {
+        Self {
+            id: token.id,
+            name: token.name.clone(),
+            scopes: token.scopes.clone(),
+            created: token.created,
+        }
+    }
+}
+
+/// The response to `POST /me/tokens`, the only time the secret is shown
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IssuedToken {
+    #[serde(flatten)]
+    info: TokenInfo,
+    secret: String,
+}
+
+impl From<&ServiceToken> for IssuedToken {
+    fn from(token: &ServiceToken) -> Self {
+        Self {
+            info: TokenInfo::from(token),
+            secret: token.secret.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_required_scope() {
+        let required = |method: Method, route| Scope::required(&method, route);
+        assert_eq!(required(Method::GET, "/notes"), Some(Scope::NotesRead));
+        assert_eq!(required(Method::GET, "/note/:id"), Some(Scope::NotesRead));
+        assert_eq!(required(Method::GET, "/export.csv"), Some(Scope::NotesRead));
+        assert_eq!(required(Method::POST, "/note"), Some(Scope::NotesWrite));
+        assert_eq!(required(Method::PUT, "/note/:id"), Some(Scope::NotesWrite));
+        assert_eq!(
+            required(Method::POST, "/notes/tags"),
+            Some(Scope::NotesWrite)
+        );
+        assert_eq!(required(Method::GET, "/tags"), Some(Scope::TagsRead));
+        assert_eq!(required(Method::GET, "/tags/graph"), Some(Scope::TagsRead));
+        assert_eq!(required(Method::PUT, "/tag/:id"), None);
+        assert_eq!(required(Method::GET, "/me"), None);
+        assert_eq!(required(Method::POST, "/me/tokens"), None);
+        assert_eq!(required(Method::GET, "/admin/jobs"), None);
+    }
+
+    #[test]
+    fn test_draft() {
+        let draft: TokenDraft =
+            serde_json::from_str(r#"{"name": "CI", "scopes": ["notes:read", "tags:read"]}"#)
+                .unwrap();
+        assert!(draft.validate().is_ok());
+        let token = ServiceToken::new(Id(1), Id(2), draft, ServiceToken::generate_secret());
+        assert!(token.secret().starts_with(TOKEN_PREFIX));
+        assert!(token.allows(Scope::NotesRead));
+        assert!(!token.allows(Scope::NotesWrite));
+
+        assert!(TokenDraft::new(" ".to_string(), token.scopes.clone())
+            .validate()
+            .is_err());
+        assert!(TokenDraft::new("CI".to_string(), BTreeSet::new())
+            .validate()
+            .is_err());
+        assert!(
+            serde_json::from_str::<TokenDraft>(r#"{"name": "CI", "scopes": ["admin"]}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Confirmations to empty the trash with `DELETE /notes/trash`
+//!
+//! Purging deleted notes can't be undone, so it takes two requests: `POST /notes/trash/confirm`
+//! lists the deleted notes of the user and returns a short-lived token, the token then purges
+//! exactly these notes. Notes that are deleted after the confirmation stay in the trash.
+use chrono::{DateTime, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+
+use crate::models::Id;
+
+/// Allows a user to purge the deleted notes that were in the trash at the time of the confirmation
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TrashConfirmation {
+    token: String,
+    user: Id,
+    notes: Vec<Id>,
+    expires: DateTime<Utc>,
+}
+
+impl TrashConfirmation {
+    /// Creates a new random token
+    pub fn new(user: Id, notes: Vec<Id>, expires: DateTime<Utc>) -> Self {
+        Self {
+            token: Alphanumeric.sample_string(&mut rand::thread_rng(), 32),
+            user,
+            notes,
+            expires,
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn user(&self) -> &Id {
+        &self.user
+    }
+
+    /// The deleted notes that are purged with the token
+    pub fn notes(&self) -> &[Id] {
+        &self.notes
+    }
+
+    pub fn expires(&self) -> &DateTime<Utc> {
+        &self.expires
+    }
+}
+
+/// The response of `POST /notes/trash/confirm`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EmptyTrash {
+    /// Purges the notes via `DELETE /notes/trash?token=<token>`
+    token: String,
+    /// The number of notes in the trash
+    notes: usize,
+    expires: DateTime<Utc>,
+}
+
+impl EmptyTrash {
+    pub fn notes(&self) -> usize {
+        self.notes
+    }
+}
+
+impl From<&TrashConfirmation> for EmptyTrash {
+    fn from(confirmation: &TrashConfirmation) -> Self {
+        Self {
+            token: confirmation.token.clone(),
+            notes: confirmation.notes.len(),
+            expires: confirmation.expires,
+        }
+    }
+}
+
+/// The query of `DELETE /notes/trash`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct TrashQuery {
+    pub token: String,
+}
+
+/// The response of `DELETE /notes/trash`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PurgedNotes {
+    pub purged: usize,
+}
@@ -0,0 +1,67 @@
+//! Tokens to undo the deletion of a [`Note`](crate::models::note::Note)
+use chrono::{DateTime, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Id, Visibility};
+
+/// Allows the owner to restore a deleted note until the token expires
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct UndoToken {
+    token: String,
+    note: Id,
+    user: Id,
+    /// The visibility of the note before it was deleted
+    visibility: Visibility,
+    expires: DateTime<Utc>,
+}
+
+impl UndoToken {
+    /// Creates a new random token
+    pub fn new(note: Id, user: Id, visibility: Visibility, expires: DateTime<Utc>) -> Self {
+        Self {
+            token: Alphanumeric.sample_string(&mut rand::thread_rng(), 32),
+            note,
+            user,
+            visibility,
+            expires,
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn note(&self) -> &Id {
+        &self.note
+    }
+
+    pub fn user(&self) -> &Id {
+        &self.user
+    }
+
+    pub fn visibility(&self) -> &Visibility {
+        &self.visibility
+    }
+
+    pub fn expires(&self) -> &DateTime<Utc> {
+        &self.expires
+    }
+}
+
+/// The response to the deletion of a note
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct UndoDeletion {
+    /// Restores the note via `POST /undo/:token`
+    token: String,
+    expires: DateTime<Utc>,
+}
+
+impl From<&UndoToken> for UndoDeletion {
+    fn from(token: &UndoToken) -> Self {
+        Self {
+            token: token.token.clone(),
+            expires: token.expires,
+        }
+    }
+}
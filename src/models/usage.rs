@@ -0,0 +1,104 @@
+//! The API usage of every user, aggregated per day, see `GET /me/usage` and `GET /admin/usage`
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::models::Id;
+
+/// The requests of a single user on a single day in the timezone of the user
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DailyUsage {
+    date: NaiveDate,
+    requests: u64,
+    /// Requests that may change data, i.e. all but `GET`, `HEAD` and `OPTIONS`
+    mutations: u64,
+    /// Size of the request bodies, as sent by the clients
+    bytes_received: u64,
+    /// Size of the uncompressed response bodies, streamed responses are not counted
+    bytes_sent: u64,
+}
+
+impl DailyUsage {
+    /// The usage of a single request
+    pub fn request(date: NaiveDate, mutation: bool, bytes_received: u64, bytes_sent: u64) -> Self {
+        Self {
+            date,
+            requests: 1,
+            mutations: mutation.into(),
+            bytes_received,
+            bytes_sent,
+        }
+    }
+
+    pub fn date(&self) -> &NaiveDate {
+        &self.date
+    }
+
+    /// Adds the usage of another period, e.g. of another request on the same day
+    pub fn add(&mut self, other: &DailyUsage) {
+        self.requests += other.requests;
+        self.mutations += other.mutations;
+        self.bytes_received += other.bytes_received;
+        self.bytes_sent += other.bytes_sent;
+    }
+}
+
+/// The usage of a user in a range of days, an entry of `GET /admin/usage`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct UsageTotals {
+    user: Id,
+    name: String,
+    /// Number of days with any requests
+    days: usize,
+    requests: u64,
+    mutations: u64,
+    bytes_received: u64,
+    bytes_sent: u64,
+}
+
+impl UsageTotals {
+    /// Sums up the daily usage of the user
+    pub fn new(user: Id, name: String, usage: &[DailyUsage]) -> Self {
+        let mut totals = Self {
+            user,
+            name,
+            days: usage.len(),
+            requests: 0,
+            mutations: 0,
+            bytes_received: 0,
+            bytes_sent: 0,
+        };
+        for day in usage {
+            totals.requests += day.requests;
+            totals.mutations += day.mutations;
+            totals.bytes_received += day.bytes_received;
+            totals.bytes_sent += day.bytes_sent;
+        }
+        totals
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_totals() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let mut first = DailyUsage::request(date, false, 0, 100);
+        first.add(&DailyUsage::request(date, true, 20, 50));
+        assert_eq!(first.requests, 2);
+        assert_eq!(first.mutations, 1);
+        let second = DailyUsage::request(date.succ_opt().unwrap(), true, 10, 5);
+
+        let totals = UsageTotals::new(Id(1), "alice".to_string(), &[first, second]);
+        assert_eq!(totals.days, 2);
+        assert_eq!(totals.requests(), 3);
+        assert_eq!(totals.mutations, 2);
+        assert_eq!(totals.bytes_received, 30);
+        assert_eq!(totals.bytes_sent, 155);
+    }
+}
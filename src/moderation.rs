@@ -0,0 +1,165 @@
+//! Heuristics that detect spam in public notes
+//!
+//! Drafts that make a note public are checked when the note is created or edited.
+//! Notes that trip a heuristic are flagged for a review at `GET /admin/moderation/queue`
+//! or kept private until an admin approves them, depending on the [`ModerationAction`].
+use std::collections::BTreeSet;
+
+use crate::config::{ModerationAction, ModerationConfig};
+use crate::indexer::words;
+use crate::models::moderation::{Flag, Reason};
+use crate::models::note::{Draft, Note};
+use crate::models::Visibility;
+
+/// The outcome of [`screen`], turned into a [`Flag`] once the note is stored
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Screening {
+    reasons: Vec<Reason>,
+    /// The requested visibility of a quarantined note
+    quarantined: Option<Visibility>,
+}
+
+impl Screening {
+    /// Returns the [`Flag`] for the stored note, unless no heuristic tripped
+    pub fn flag(self, note: &Note) -> Option<Flag> {
+        (!self.reasons.is_empty())
+            .then(|| Flag::new(*note.id(), *note.user(), self.reasons, self.quarantined))
+    }
+}
+
+/// Checks a draft that makes a note public, `recent` is the number of notes
+/// that the user created within the burst window before
+///
+/// Returns the draft to store, which is private if the note is quarantined.
+pub fn screen(config: &ModerationConfig, draft: Draft, recent: usize) -> (Draft, Screening) {
+    if config.action == ModerationAction::Off || draft.visibility() != Some(&Visibility::Public) {
+        return (draft, Screening::default());
+    }
+    let reasons = check(config, &draft, recent);
+    if reasons.is_empty() || config.action == ModerationAction::Flag {
+        return (
+            draft,
+            Screening {
+                reasons,
+                quarantined: None,
+            },
+        );
+    }
+    (
+        draft.with_visibility(Visibility::Private),
+        Screening {
+            reasons,
+            quarantined: Some(Visibility::Public),
+        },
+    )
+}
+
+/// Returns all heuristics that the draft trips
+fn check(config: &ModerationConfig, draft: &Draft, recent: usize) -> Vec<Reason> {
+    let mut reasons = Vec::new();
+    let text = format!("{}\n{}", draft.title(), draft.body());
+    let links = text.matches("http://").count() + text.matches("https://").count();
+    if links > config.max_links {
+        reasons.push(Reason::TooManyLinks {
+            links,
+            max: config.max_links,
+        });
+    }
+    let banned = words(&text)
+        .filter(|word| config.banned_words.contains(word))
+        .collect::<BTreeSet<String>>();
+    reasons.extend(banned.into_iter().map(|word| Reason::BannedWord { word }));
+    if recent >= config.burst_notes {
+        reasons.push(Reason::Burst {
+            notes: recent + 1,
+            seconds: config.burst_window.as_secs(),
+        });
+    }
+    let max_tags = config.base_tags
+        + draft
+            .body()
+            .len()
+            .checked_div(config.bytes_per_tag)
+            .unwrap_or(0);
+    if draft.tags().len() > max_tags {
+        reasons.push(Reason::TooManyTags {
+            tags: draft.tags().len(),
+            max: max_tags,
+        });
+    }
+    reasons
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn public(body: &str, tags: &[&str]) -> Draft {
+        Draft::new(
+            "Title".to_string(),
+            body.to_string(),
+            tags.iter().map(|tag| tag.to_string()).collect(),
+            Visibility::Public,
+        )
+    }
+
+    #[test]
+    fn test_check() {
+        let config = ModerationConfig {
+            max_links: 1,
+            banned_words: vec!["casino".to_string()],
+            burst_notes: 3,
+            base_tags: 1,
+            bytes_per_tag: 10,
+            ..ModerationConfig::default()
+        };
+        assert!(check(&config, &public("Hello https://example.com", &["a"]), 2).is_empty());
+        assert_eq!(
+            check(
+                &config,
+                &public("Best Casino: http://a.example https://b.example", &[]),
+                3
+            ),
+            vec![
+                Reason::TooManyLinks { links: 2, max: 1 },
+                Reason::BannedWord {
+                    word: "casino".to_string()
+                },
+                Reason::Burst {
+                    notes: 4,
+                    seconds: 60
+                },
+            ]
+        );
+        assert_eq!(
+            check(&config, &public("short", &["a", "b"]), 0),
+            vec![Reason::TooManyTags { tags: 2, max: 1 }]
+        );
+        assert!(check(&config, &public("a body with 20 bytes", &["a", "b"]), 0).is_empty());
+    }
+
+    #[test]
+    fn test_screen() {
+        let config = ModerationConfig {
+            banned_words: vec!["spam".to_string()],
+            action: ModerationAction::Quarantine,
+            ..ModerationConfig::default()
+        };
+        let (draft, screening) = screen(&config, public("spam", &[]), 0);
+        assert_eq!(draft.visibility(), Some(&Visibility::Private));
+        assert_eq!(screening.quarantined, Some(Visibility::Public));
+
+        let private = public("spam", &[]).with_visibility(Visibility::Private);
+        let (_, screening) = screen(&config, private, 0);
+        assert_eq!(screening, Screening::default());
+
+        let config = ModerationConfig {
+            action: ModerationAction::Flag,
+            ..config
+        };
+        let (draft, screening) = screen(&config, public("spam", &[]), 0);
+        assert_eq!(draft.visibility(), Some(&Visibility::Public));
+        assert_eq!(screening.reasons.len(), 1);
+        assert!(screening.quarantined.is_none());
+    }
+}
@@ -0,0 +1,144 @@
+//! MessagePack as an alternative to JSON on the wire, e.g. for sync clients with many notes
+//!
+//! Handlers only deal with JSON. [`StrictJson`](crate::json::StrictJson) accepts request bodies
+//! with `Content-Type: application/msgpack` and [`negotiate`] encodes JSON responses as
+//! MessagePack if the client asks for it with `Accept: application/msgpack`. Both convert the
+//! documents via [`serde_json::Value`], so maps keep their field names and both formats carry
+//! exactly the same data.
+use axum::body::{self, Full, HttpBody};
+use axum::http::{header, HeaderMap, HeaderValue, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::json::content_type;
+
+/// The media type of MessagePack documents
+pub const MSGPACK: &str = "application/msgpack";
+
+/// Media types that older clients use for MessagePack
+const ALIASES: [&str; 2] = ["application/x-msgpack", "application/vnd.msgpack"];
+
+fn is_msgpack_type(media_type: &str) -> bool {
+    media_type == MSGPACK || ALIASES.contains(&media_type)
+}
+
+/// Returns `true` if the body of the request is MessagePack
+pub fn is_msgpack(headers: &HeaderMap) -> bool {
+    content_type(headers).is_some_and(|media_type| is_msgpack_type(&media_type))
+}
+
+/// Returns `true` if the `Accept` header asks for MessagePack, unless with `q=0`
+pub fn accepts_msgpack(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+            let rejected = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            is_msgpack_type(&media_type) && !rejected
+        })
+}
+
+/// Converts a MessagePack document into JSON, fails with `400 Bad Request`
+pub fn to_json(body: &[u8]) -> Result<Vec<u8>, (StatusCode, String)> {
+    let value = rmp_serde::from_slice::<Value>(body).map_err(|err| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid MessagePack body: {err}"),
+        )
+    })?;
+    Ok(serde_json::to_vec(&value).expect("JSON values can always be serialized"))
+}
+
+/// Converts a JSON document into MessagePack
+pub fn from_json(body: &[u8]) -> Result<Vec<u8>, String> {
+    let value = serde_json::from_slice::<Value>(body).map_err(|err| err.to_string())?;
+    rmp_serde::to_vec(&value).map_err(|err| err.to_string())
+}
+
+/// Encodes `application/json` responses as MessagePack for clients that accept it
+///
+/// Other responses, e.g. errors as plain text, HTML pages or server-sent events, are passed
+/// through unchanged. JSON responses get `Vary: Accept`, so that caches keep both encodings.
+pub async fn negotiate<B>(request: Request<B>, next: Next<B>) -> Response {
+    let packed = accepts_msgpack(request.headers());
+    let mut response = next.run(request).await;
+    if content_type(response.headers()).as_deref() != Some("application/json") {
+        return response;
+    }
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept"));
+    if !packed {
+        return response;
+    }
+    let (mut parts, mut body) = response.into_parts();
+    let mut json = Vec::new();
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => json.extend_from_slice(&chunk),
+            Err(err) => {
+                warn!("Unable to read the response body: {err}");
+                info!("--> 500");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+    let packed = match from_json(&json) {
+        Ok(packed) => packed,
+        Err(err) => {
+            warn!("Unable to encode the response as MessagePack: {err}");
+            return Response::from_parts(parts, body::boxed(Full::from(json)));
+        }
+    };
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(MSGPACK));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, body::boxed(Full::from(packed)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_accepts_msgpack() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_msgpack(&headers));
+        for (accept, expected) in [
+            ("application/msgpack", true),
+            ("application/json, application/x-msgpack;q=0.9", true),
+            ("application/msgpack;q=0", false),
+            ("application/json", false),
+            ("*/*", false),
+        ] {
+            headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+            assert_eq!(accepts_msgpack(&headers), expected, "{accept}");
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let value = json!({"title": "Title", "tags": ["a"], "done": null, "id": 3, "lat": 1.5});
+        let json = serde_json::to_vec(&value).unwrap();
+        let packed = from_json(&json).unwrap();
+        assert!(packed.len() < json.len());
+        let unpacked = to_json(&packed).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&unpacked).unwrap(), value);
+
+        let (status, _) = to_json(&[0xc1]).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,27 @@
+//! Delivery of [`Notification`]s to users
+//!
+//! Notifications are sent by the [`Notify`](crate::tasks::Notify) job, so a
+//! failing [`Notifier`] is retried without slowing down the request.
+use tracing::info;
+
+use crate::models::mention::Notification;
+use crate::models::User;
+
+/// A channel to notify users, e.g. via email or push notifications
+pub trait Notifier: Send + Sync {
+    fn notify(&self, user: &User, notification: &Notification) -> Result<(), String>;
+}
+
+/// A [`Notifier`] that only logs all notifications
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, user: &User, notification: &Notification) -> Result<(), String> {
+        info!(
+            "Notification for user {}: {:?}",
+            usize::from(user.id()),
+            notification
+        );
+        Ok(())
+    }
+}
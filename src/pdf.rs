@@ -0,0 +1,587 @@
+//! Renders a [`Note`] as PDF document
+//!
+//! The body is parsed as Markdown and laid out on A4 pages, below a header with
+//! the title, tags and timestamps of the note. Reports combine several notes
+//! after a table of contents, see [`render_report`]. Only the standard PDF fonts are
+//! used, so no font files must be embedded. These fonts support the Windows-1252
+//! character set, all other characters are replaced by `?`.
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag};
+
+use crate::models::note::Note;
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 56.0;
+const TEXT_SIZE: f32 = 11.0;
+const INDENT: f32 = 16.0;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Font {
+    Regular,
+    Bold,
+    Mono,
+}
+
+impl Font {
+    const ALL: [Font; 3] = [Font::Regular, Font::Bold, Font::Mono];
+
+    /// The name of the font in the resources of a page
+    fn name(&self) -> Name<'static> {
+        match self {
+            Font::Regular => Name(b"F1"),
+            Font::Bold => Name(b"F2"),
+            Font::Mono => Name(b"F3"),
+        }
+    }
+
+    fn base_font(&self) -> Name<'static> {
+        match self {
+            Font::Regular => Name(b"Helvetica"),
+            Font::Bold => Name(b"Helvetica-Bold"),
+            Font::Mono => Name(b"Courier"),
+        }
+    }
+
+    /// The (average) width of a character, relative to the font size
+    ///
+    /// Good enough for wrapping lines, without the need of the metrics of every glyph.
+    fn char_width(&self) -> f32 {
+        match self {
+            Font::Regular => 0.5,
+            Font::Bold => 0.55,
+            Font::Mono => 0.6,
+        }
+    }
+}
+
+/// A single element of the laid out document
+#[derive(Clone, Debug, PartialEq)]
+enum Item {
+    Line {
+        text: String,
+        font: Font,
+        size: f32,
+        indent: f32,
+        gray: f32,
+    },
+    /// Vertical space in points
+    Space(f32),
+    /// A horizontal line across the page
+    Rule,
+}
+
+impl Item {
+    fn height(&self) -> f32 {
+        match self {
+            Item::Line { size, .. } => size * 1.4,
+            Item::Space(height) => *height,
+            Item::Rule => 12.0,
+        }
+    }
+}
+
+/// A block of text that is wrapped into [`Item::Line`]s
+struct Block {
+    font: Font,
+    size: f32,
+    indent: f32,
+    gray: f32,
+}
+
+impl Block {
+    fn text(font: Font, size: f32) -> Self {
+        Self {
+            font,
+            size,
+            indent: 0.0,
+            gray: 0.0,
+        }
+    }
+
+    /// Wraps the text to the width of the page and appends the lines to `items`
+    fn wrap(&self, text: &str, items: &mut Vec<Item>) {
+        let width = PAGE_WIDTH - 2.0 * MARGIN - self.indent;
+        let max_chars = ((width / (self.font.char_width() * self.size)) as usize).max(1);
+        for paragraph in text.lines() {
+            let lines = if self.font == Font::Mono {
+                split_chars(paragraph, max_chars)
+            } else {
+                wrap_words(paragraph, max_chars)
+            };
+            for text in lines {
+                items.push(Item::Line {
+                    text,
+                    font: self.font,
+                    size: self.size,
+                    indent: self.indent,
+                    gray: self.gray,
+                });
+            }
+        }
+    }
+}
+
+/// Splits `text` into lines of at most `max_chars` characters, breaking at whitespace if possible
+fn wrap_words(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let line_len = line.chars().count();
+        let word_len = word.chars().count();
+        if line_len > 0 && line_len + 1 + word_len <= max_chars {
+            line.push(' ');
+            line.push_str(word);
+            continue;
+        }
+        if line_len > 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+        let mut parts = split_chars(word, max_chars);
+        line = parts.pop().unwrap_or_default();
+        lines.extend(parts);
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Splits `text` into chunks of `max_chars` characters
+fn split_chars(text: &str, max_chars: usize) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<char>>();
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+    chars
+        .chunks(max_chars)
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
+
+/// Encodes the text in the `WinAnsiEncoding` of the standard fonts
+fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' | '\u{a0}'..='\u{ff}' => c as u8,
+            '€' => 0x80,
+            '…' => 0x85,
+            '‘' => 0x91,
+            '’' => 0x92,
+            '“' => 0x93,
+            '”' => 0x94,
+            '•' => 0x95,
+            '–' => 0x96,
+            '—' => 0x97,
+            '\t' => b' ',
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Lays out the header of the document with the title, tags and timestamps of the note
+fn header(note: &Note, items: &mut Vec<Item>) {
+    Block::text(Font::Bold, 20.0).wrap(note.title(), items);
+    items.push(Item::Space(4.0));
+    let meta = Block {
+        gray: 0.4,
+        ..Block::text(Font::Regular, 9.0)
+    };
+    let mut tags = note
+        .tags()
+        .map(|tag| tag.label().to_string())
+        .collect::<Vec<String>>();
+    if !tags.is_empty() {
+        tags.sort();
+        meta.wrap(&format!("Tags: {}", tags.join(", ")), items);
+    }
+    meta.wrap(
+        &format!(
+            "Created: {}   Updated: {}",
+            note.created().format("%Y-%m-%d %H:%M UTC"),
+            note.updated().format("%Y-%m-%d %H:%M UTC")
+        ),
+        items,
+    );
+    items.push(Item::Rule);
+}
+
+/// Lays out the Markdown `body`
+fn body(body: &str, items: &mut Vec<Item>) {
+    let mut text = String::new();
+    let mut block = Block::text(Font::Regular, TEXT_SIZE);
+    let mut quote_depth = 0;
+    // the next number of each (nested) list, `None` for unordered lists
+    let mut lists: Vec<Option<u64>> = vec![];
+
+    // the marker of a list item is prepended to its first line
+    let mut marker: Option<String> = None;
+
+    let flush =
+        |text: &mut String, marker: &mut Option<String>, block: &Block, items: &mut Vec<Item>| {
+            if !text.trim().is_empty() {
+                // keep the indentation of code
+                let content = match block.font {
+                    Font::Mono => text.trim_end(),
+                    _ => text.trim(),
+                };
+                let text = marker.take().unwrap_or_default() + content;
+                block.wrap(&text, items);
+            }
+            text.clear();
+        };
+
+    for event in Parser::new(body) {
+        match event {
+            Event::Start(tag) if is_block(&tag) => {
+                flush(&mut text, &mut marker, &block, items);
+                block = match tag {
+                    Tag::Heading(level, ..) => {
+                        let size = match level {
+                            HeadingLevel::H1 => 16.0,
+                            HeadingLevel::H2 => 14.0,
+                            _ => 12.0,
+                        };
+                        items.push(Item::Space(size * 0.5));
+                        Block::text(Font::Bold, size)
+                    }
+                    Tag::CodeBlock(_) => Block::text(Font::Mono, TEXT_SIZE - 1.0),
+                    _ => Block::text(Font::Regular, TEXT_SIZE),
+                };
+                match tag {
+                    Tag::BlockQuote => quote_depth += 1,
+                    Tag::List(start) => lists.push(start),
+                    Tag::Item => {
+                        marker = Some(match lists.last_mut() {
+                            Some(Some(number)) => {
+                                *number += 1;
+                                format!("{}. ", *number - 1)
+                            }
+                            _ => "• ".to_string(),
+                        })
+                    }
+                    _ => {}
+                }
+                // the content of list items is indented by the level of the list
+                block.indent = INDENT * (lists.len().saturating_sub(1) + quote_depth) as f32;
+                block.gray = if quote_depth > 0 { 0.4 } else { 0.0 };
+            }
+            Event::End(tag) if is_block(&tag) => {
+                flush(&mut text, &mut marker, &block, items);
+                match tag {
+                    Tag::BlockQuote => quote_depth -= 1,
+                    Tag::List(_) => {
+                        lists.pop();
+                    }
+                    Tag::Paragraph | Tag::Heading(..) | Tag::CodeBlock(_) if lists.is_empty() => {
+                        items.push(Item::Space(TEXT_SIZE * 0.5))
+                    }
+                    _ => {}
+                }
+            }
+            // inline formatting is not supported, only the text is rendered
+            Event::Start(_) | Event::End(_) => {}
+            Event::Text(content) | Event::Code(content) | Event::Html(content) => {
+                text.push_str(&content)
+            }
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak => text.push('\n'),
+            Event::Rule => {
+                flush(&mut text, &mut marker, &block, items);
+                items.push(Item::Rule);
+            }
+            Event::TaskListMarker(done) => text.push_str(if done { "[x] " } else { "[ ] " }),
+            Event::FootnoteReference(label) => text.push_str(&format!("[{label}]")),
+        }
+    }
+    flush(&mut text, &mut marker, &block, items);
+}
+
+/// Returns `true` for tags of block elements, `false` for inline elements
+fn is_block(tag: &Tag) -> bool {
+    !matches!(
+        tag,
+        Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)
+    )
+}
+
+/// Distributes the items onto pages
+fn paginate(items: Vec<Item>) -> Vec<Vec<Item>> {
+    let mut pages = vec![vec![]];
+    let mut y = PAGE_HEIGHT - MARGIN;
+    for item in items {
+        if y - item.height() < MARGIN {
+            pages.push(vec![]);
+            y = PAGE_HEIGHT - MARGIN;
+            if matches!(item, Item::Space(_) | Item::Rule) {
+                continue;
+            }
+        }
+        y -= item.height();
+        pages.last_mut().expect("there is always a page").push(item);
+    }
+    pages
+}
+
+/// Writes the content stream of a single page
+fn page_content(items: &[Item]) -> Vec<u8> {
+    let mut content = Content::new();
+    let mut y = PAGE_HEIGHT - MARGIN;
+    for item in items {
+        y -= item.height();
+        match item {
+            Item::Line {
+                text,
+                font,
+                size,
+                indent,
+                gray,
+            } => {
+                content.set_fill_gray(*gray);
+                content.begin_text();
+                content.set_font(font.name(), *size);
+                content.next_line(MARGIN + indent, y + size * 0.3);
+                content.show(Str(&encode(text)));
+                content.end_text();
+            }
+            Item::Rule => {
+                let y = y + item.height() / 2.0;
+                content.set_stroke_gray(0.6);
+                content.set_line_width(0.5);
+                content.move_to(MARGIN, y);
+                content.line_to(PAGE_WIDTH - MARGIN, y);
+                content.stroke();
+            }
+            Item::Space(_) => {}
+        }
+    }
+    content.finish()
+}
+
+/// Renders the note as a PDF document
+pub fn render(note: &Note) -> Vec<u8> {
+    let mut items = vec![];
+    header(note, &mut items);
+    body(&note.body().text(), &mut items);
+    write(note.title(), &paginate(items))
+}
+
+/// Lays out the table of contents of a report, `first_pages` are the page numbers of the notes
+fn contents(title: &str, notes: &[Note], first_pages: &[usize]) -> Vec<Item> {
+    let mut items = vec![];
+    Block::text(Font::Bold, 20.0).wrap(title, &mut items);
+    items.push(Item::Space(8.0));
+    Block::text(Font::Bold, 14.0).wrap("Contents", &mut items);
+    items.push(Item::Space(4.0));
+    let entry = Block::text(Font::Regular, TEXT_SIZE);
+    for (number, (note, page)) in notes.iter().zip(first_pages).enumerate() {
+        entry.wrap(
+            &format!("{}. {} ... {page}", number + 1, note.title()),
+            &mut items,
+        );
+    }
+    items
+}
+
+/// Renders the notes as a single PDF document with a table of contents, every note starts
+/// on a new page
+pub fn render_report(title: &str, notes: &[Note]) -> Vec<u8> {
+    let notes_pages = notes
+        .iter()
+        .map(|note| {
+            let mut items = vec![];
+            header(note, &mut items);
+            body(&note.body().text(), &mut items);
+            paginate(items)
+        })
+        .collect::<Vec<Vec<Vec<Item>>>>();
+    // the page numbers in the contents depend on the length of the contents
+    let mut contents_pages = paginate(contents(title, notes, &vec![0; notes.len()]));
+    loop {
+        let mut first_page = contents_pages.len() + 1;
+        let first_pages = notes_pages
+            .iter()
+            .map(|pages| {
+                let page = first_page;
+                first_page += pages.len();
+                page
+            })
+            .collect::<Vec<usize>>();
+        let pages = paginate(contents(title, notes, &first_pages));
+        if pages.len() == contents_pages.len() {
+            contents_pages = pages;
+            break;
+        }
+        contents_pages = pages;
+    }
+    let pages = contents_pages
+        .into_iter()
+        .chain(notes_pages.into_iter().flatten())
+        .collect::<Vec<Vec<Item>>>();
+    write(title, &pages)
+}
+
+/// Writes the pages as a PDF document
+fn write(title: &str, pages: &[Vec<Item>]) -> Vec<u8> {
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let info_id = Ref::new(3);
+    let font_ids = [Ref::new(4), Ref::new(5), Ref::new(6)];
+    // every page consists of the page itself and its content stream
+    let page_ids = (0..pages.len() as i32)
+        .map(|i| (Ref::new(7 + 2 * i), Ref::new(8 + 2 * i)))
+        .collect::<Vec<(Ref, Ref)>>();
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id)
+        .kids(page_ids.iter().map(|(page_id, _)| *page_id))
+        .count(pages.len() as i32);
+    pdf.document_info(info_id)
+        .title(TextStr(title))
+        .creator(TextStr(env!("CARGO_PKG_NAME")));
+    for (font, id) in Font::ALL.iter().zip(font_ids) {
+        pdf.type1_font(id)
+            .base_font(font.base_font())
+            .encoding_predefined(Name(b"WinAnsiEncoding"));
+    }
+    for (items, (page_id, content_id)) in pages.iter().zip(page_ids) {
+        let mut page = pdf.page(page_id);
+        page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+            .parent(page_tree_id)
+            .contents(content_id);
+        let mut resources = page.resources();
+        let mut fonts = resources.fonts();
+        for (font, id) in Font::ALL.iter().zip(font_ids) {
+            fonts.pair(font.name(), id);
+        }
+        fonts.finish();
+        resources.finish();
+        page.finish();
+        pdf.stream(content_id, &page_content(items));
+    }
+    pdf.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+    use crate::models::note::Draft;
+    use crate::models::{Id, Visibility};
+
+    fn lines(items: &[Item]) -> Vec<&str> {
+        items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Line { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_wrap_words() {
+        assert_eq!(wrap_words("a bb ccc dddd", 6), vec!["a bb", "ccc", "dddd"]);
+        assert_eq!(wrap_words("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(wrap_words("", 3), vec![""]);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("Hello"), b"Hello");
+        assert_eq!(encode("Grüße – €"), b"Gr\xfc\xdfe \x96 \x80");
+        assert_eq!(encode("日本"), b"??");
+    }
+
+    #[test]
+    fn test_markdown_body() {
+        let mut items = vec![];
+        body(
+            "# Heading\n\nSome *emphasized*\ntext\n\n- one\n- two\n\n1. first\n\n2. second\n\n```\n  let x = 1;\n```",
+            &mut items,
+        );
+        assert_eq!(
+            lines(&items),
+            vec![
+                "Heading",
+                "Some emphasized text",
+                "• one",
+                "• two",
+                "1. first",
+                "2. second",
+                "  let x = 1;"
+            ]
+        );
+        assert!(items.contains(&Item::Line {
+            text: "  let x = 1;".to_string(),
+            font: Font::Mono,
+            size: TEXT_SIZE - 1.0,
+            indent: 0.0,
+            gray: 0.0
+        }));
+    }
+
+    #[test]
+    fn test_render() {
+        let pdf = render(&example_note());
+        assert!(pdf.starts_with(b"%PDF-"));
+        let content = String::from_utf8_lossy(&pdf);
+        assert!(content.contains("(Test-Title)"));
+        assert!(content.contains("(Test-Body)"));
+        assert!(content.contains("Tags: tag1, tag2, tag3"));
+    }
+
+    #[test]
+    fn test_pagination() {
+        let body = vec!["A paragraph"; 100].join("\n\n");
+        let note = Note::new(
+            Draft::new("Long".to_string(), body, vec![], Visibility::Private),
+            Id(0),
+            Id(0),
+            Default::default(),
+        );
+        let mut items = vec![];
+        header(&note, &mut items);
+        super::body(&note.body().text(), &mut items);
+        let pages = paginate(items);
+        assert!(pages.len() > 1);
+        // title and timestamps are the only lines of the header
+        assert_eq!(
+            pages.iter().map(|page| lines(page).len()).sum::<usize>(),
+            100 + 2
+        );
+        let count = format!("/Count {}", pages.len());
+        assert!(String::from_utf8_lossy(&render(&note)).contains(&count));
+    }
+
+    #[test]
+    fn test_report() {
+        let body = vec!["A paragraph"; 100].join("\n\n");
+        let long = Note::new(
+            Draft::new("Long".to_string(), body, vec![], Visibility::Private),
+            Id(0),
+            Id(0),
+            Default::default(),
+        );
+        let notes = [long, example_note()];
+        let items = contents("Project", &notes, &[2, 5]);
+        assert_eq!(
+            lines(&items),
+            vec![
+                "Project",
+                "Contents",
+                "1. Long ... 2",
+                "2. Test-Title ... 5"
+            ]
+        );
+
+        let pdf = render_report("Project", &notes);
+        let content = String::from_utf8_lossy(&pdf);
+        // one page of contents, three pages of the long note and one of the example note
+        assert!(content.contains("/Count 5"));
+        assert!(content.contains("(1. Long ... 2)"));
+        assert!(content.contains("(2. Test-Title ... 5)"));
+        assert!(content.contains("(Test-Body)"));
+    }
+}
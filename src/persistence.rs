@@ -1,8 +1,93 @@
+pub mod codec;
 pub mod memory;
+pub mod migrate;
+pub mod replicated;
+pub mod supervisor;
+#[cfg(any(test, feature = "testsuite"))]
+pub mod testsuite;
 
 use crate::models::note::{Draft, Note};
+use crate::models::query::{NoteQuery, SavedSearch, SearchDraft};
 
-use crate::models::{Id, Tag, User};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+
+use crate::models::access::AccessEntry;
+use crate::models::audit::AuditEntry;
+use crate::models::autosave::WorkingCopy;
+use crate::models::backup::{Backup, RestoreMode};
+use crate::models::comment::{Comment, CommentDraft};
+use crate::models::export::UserExport;
+use crate::models::follower::Follower;
+use crate::models::geo::Location;
+use crate::models::idempotency::IdempotencyRecord;
+use crate::models::integration::Integrations;
+use crate::models::moderation::Flag;
+use crate::models::preferences::Preferences;
+use crate::models::profile::Profile;
+use crate::models::tag_rule::TagRules;
+use crate::models::template::Templates;
+use crate::models::token::{ServiceToken, TokenDraft};
+use crate::models::trash::TrashConfirmation;
+use crate::models::undo::UndoToken;
+use crate::models::usage::DailyUsage;
+use crate::models::{
+    ExternalIdentity, Id, Tag, TagMeta, TagStats, User, Visibility, VisibilityFilter,
+};
+use crate::revisions::{Revision, RevisionInfo};
+
+/// The state of the connection of a [`Persister`] backend, see [`Persister::health`]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Health {
+    #[default]
+    Up,
+    /// The backend can't answer requests, e.g. because the database is not reachable
+    Down { reason: String },
+}
+
+/// Optional features of a [`Persister`] backend
+///
+/// The HTTP layer checks the capabilities before it relies on a feature and
+/// falls back to its own implementation if the backend does not support it.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Capabilities {
+    /// The backend provides an efficient [`Persister::search_notes`]. Otherwise,
+    /// the in-memory [`Index`](crate::indexer::Index) is used.
+    pub full_text_search: bool,
+    /// [`Persister::notes_near`] is backed by a spatial index
+    pub spatial_queries: bool,
+    /// Changes can be grouped in transactions
+    pub transactions: bool,
+    /// Results can be streamed instead of being collected in memory
+    pub streaming: bool,
+    /// Unused storage can be reclaimed with [`Persister::compact`]
+    pub compaction: bool,
+}
+
+/// Statistics about the data of a [`Persister`] backend, see [`Persister::stats`]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct StorageStats {
+    /// Number of notes, including deleted ones
+    pub notes: usize,
+    /// Number of soft-deleted notes
+    pub deleted_notes: usize,
+    pub tags: usize,
+    pub users: usize,
+    /// The (approximate) number of bytes used by the data
+    pub bytes: usize,
+    /// Number of entries of the backend-specific indexes, by their name
+    pub indexes: BTreeMap<String, usize>,
+}
+
+/// The [`StorageStats`] before and after [`Persister::compact`]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Compaction {
+    pub before: StorageStats,
+    pub after: StorageStats,
+}
 
 /// The `Persister` trait links the actual business logic from the data
 /// storage logic.
@@ -25,29 +110,547 @@ pub trait Persister<'a> {
 
     type TagIter: Iterator<Item = &'a Tag>;
 
-    fn notes(&'a self) -> Self::NoteIter;
+    type SearchIter: Iterator<Item = &'a SavedSearch>;
+
+    type UserIter: Iterator<Item = &'a User>;
+
+    /// Returns all notes that pass the [`VisibilityFilter`], see [`VisibilityFilter::includes`]
+    ///
+    /// Expired notes are only returned with [`VisibilityFilter::All`] until they are removed.
+    fn notes_with(&'a self, filter: VisibilityFilter) -> Self::NoteIter;
 
     fn tags(&'a self) -> Self::TagIter;
 
+    fn searches(&'a self) -> Self::SearchIter;
+
+    fn users(&'a self) -> Self::UserIter;
+
     fn add_note(&mut self, draft: Draft, user: &User) -> &Note;
 
     fn update_note(&mut self, draft: Draft, id: Id) -> &Note;
 
     fn delete_note(&mut self, id: Id) -> bool;
 
+    /// Merges the `sources` into the note `target`, see [`Note::merge`]
+    ///
+    /// The sources are deleted and remember the target, see [`Note::merge_into`].
+    fn merge_notes(&mut self, target: Id, sources: &[Id]) -> &Note;
+
+    /// Returns the revisions of the title and body of the note, oldest first
+    ///
+    /// Adding and updating a note creates a revision if its title or body changed.
+    fn revisions(&self, note: &Id) -> Vec<RevisionInfo>;
+
+    /// Reconstructs the revision of the note with the `number`, starting at 1
+    fn revision(&self, note: &Id, number: usize) -> Option<Revision>;
+
+    /// Locks or unlocks the note, see [`Note::locked`]
+    ///
+    /// Returns `false` if the note does not exist.
+    fn set_locked(&mut self, id: Id, locked: bool) -> bool;
+
+    /// Archives the note or moves it back to the active notes, see [`Note::archived`]
+    ///
+    /// Returns `false` if the note does not exist.
+    fn set_archived(&mut self, id: Id, archived: bool) -> bool;
+
+    /// Restores a deleted note with its previous visibility
+    fn restore_note(&mut self, id: Id, visibility: Visibility) -> bool;
+
+    /// Permanently removes the deleted notes with their comments, revisions and other data
+    ///
+    /// Active notes are not removed. Returns the number of removed notes.
+    fn purge_notes(&mut self, ids: &[Id]) -> usize;
+
+    /// Removes all tags that are not used by any active note and returns their number
+    ///
+    /// Deleted notes keep their tags, [`restore_note`](Persister::restore_note)
+    /// adds collected tags again.
+    fn collect_tags(&mut self) -> usize;
+
+    /// Adds the tags with the labels `add` to all notes with the `ids` and removes the
+    /// tags with the labels `remove`
+    ///
+    /// Returns the Ids of the notes that changed. SQL backends can apply the
+    /// change with one statement.
+    fn bulk_tag(&mut self, ids: &[Id], add: &[String], remove: &[String]) -> Vec<Id>;
+
     fn user_notes(&'a self, user: &User) -> Self::NoteIter;
 
     fn tagged_notes(&'a self, tag: &Tag) -> Self::NoteIter;
 
+    /// Returns all notes of the user that match the [`NoteQuery`], sorted
+    ///
+    /// The [`NoteState`](crate::models::query::NoteState) of the query selects
+    /// active, archived or deleted notes.
+    /// as requested by the query
+    fn query_notes(&'a self, user: &User, query: &NoteQuery) -> Self::NoteIter;
+
+    #[allow(dead_code)] // not used by the API yet
     fn add_tag(&mut self, label: String) -> Id;
 
-    fn note(&'a self, id: Id) -> Option<&Note> {
-        self.notes().find(|note| note.id() == &id)
+    /// Replaces the color and description of the tag, also in all notes with the tag
+    ///
+    /// Returns `None` if the tag does not exist
+    fn update_tag(&mut self, id: Id, meta: TagMeta) -> Option<&Tag>;
+
+    fn add_search(&mut self, draft: SearchDraft, user: &User) -> &SavedSearch;
+
+    fn user_searches(&'a self, user: &User) -> Self::SearchIter;
+
+    /// Creates a new user, optionally linked to an [`ExternalIdentity`]
+    fn add_user(&mut self, name: String, identity: Option<ExternalIdentity>) -> &User;
+
+    /// Schedules the account of the user for deletion, see [`User::schedule_deletion`]
+    fn schedule_user_deletion(&mut self, id: Id, purge_at: DateTime<Utc>) -> bool;
+
+    /// Replaces the [`Preferences`] of the user
+    fn set_preferences(&mut self, id: Id, preferences: Preferences) -> bool;
+
+    /// Replaces the [`Profile`] of the user
+    fn set_profile(&mut self, id: Id, profile: Profile) -> bool;
+
+    /// Replaces the [`TagRules`] of the user
+    fn set_tag_rules(&mut self, id: Id, rules: TagRules) -> bool;
+
+    /// Replaces the [`Templates`] of the user
+    fn set_templates(&mut self, id: Id, templates: Templates) -> bool;
+
+    /// Replaces the connected chat services of the user
+    fn set_integrations(&mut self, id: Id, integrations: Integrations) -> bool;
+
+    /// Returns the stored response for the idempotency key of the user
+    fn idempotency_record(&'a self, user: &User, key: &str) -> Option<&'a IdempotencyRecord>;
+
+    /// Stores a response for an idempotency key, replacing an existing one with the same key
+    fn add_idempotency_record(&mut self, record: IdempotencyRecord);
+
+    /// Removes all stored responses that were created before `before`
+    fn expire_idempotency_records(&mut self, before: &DateTime<Utc>) -> usize;
+
+    /// Returns when the user viewed the note the last time
+    fn viewed_at(&self, user: &Id, note: &Id) -> Option<DateTime<Utc>>;
+
+    /// Records that the user viewed the note at `at` and returns the previous time
+    fn set_viewed(&mut self, user: Id, note: Id, at: DateTime<Utc>) -> Option<DateTime<Utc>>;
+
+    /// Returns the [`UndoToken`], even if it is expired
+    fn undo_token(&'a self, token: &str) -> Option<&'a UndoToken>;
+
+    fn add_undo_token(&mut self, token: UndoToken);
+
+    fn remove_undo_token(&mut self, token: &str) -> Option<UndoToken>;
+
+    /// Removes all tokens that expired before `before`, their deletions can't be undone anymore
+    fn expire_undo_tokens(&mut self, before: &DateTime<Utc>) -> usize;
+
+    /// Returns the [`TrashConfirmation`], even if it is expired
+    fn trash_confirmation(&'a self, token: &str) -> Option<&'a TrashConfirmation>;
+
+    /// Stores the [`TrashConfirmation`], expired confirmations may be removed
+    fn add_trash_confirmation(&mut self, confirmation: TrashConfirmation);
+
+    /// Removes the [`TrashConfirmation`], even if it is expired
+    fn remove_trash_confirmation(&mut self, token: &str) -> Option<TrashConfirmation>;
+
+    /// Returns the [`WorkingCopy`] of the note, even if it is expired
+    fn working_copy(&'a self, note: &Id) -> Option<&'a WorkingCopy>;
+
+    /// Stores the working copy of its note and returns the previous one
+    fn set_working_copy(&mut self, copy: WorkingCopy) -> Option<WorkingCopy>;
+
+    fn remove_working_copy(&mut self, note: &Id) -> Option<WorkingCopy>;
+
+    /// Removes all working copies that expired before `before`
+    fn expire_working_copies(&mut self, before: &DateTime<Utc>) -> usize;
+
+    /// Returns all flagged notes that wait for a review, in the order of their Ids
+    fn flags(&'a self) -> Vec<&'a Flag>;
+
+    fn flag(&'a self, note: &Id) -> Option<&'a Flag>;
+
+    /// Stores the [`Flag`] of its note and returns the previous one
+    fn set_flag(&mut self, flag: Flag) -> Option<Flag>;
+
+    fn remove_flag(&mut self, note: &Id) -> Option<Flag>;
+
+    /// Returns the [`ServiceToken`] with the secret
+    fn service_token(&'a self, secret: &str) -> Option<&'a ServiceToken>;
+
+    /// Returns all service tokens of the user, in the order of their Ids
+    fn service_tokens(&'a self, user: &Id) -> Vec<&'a ServiceToken>;
+
+    /// Stores a new service token of the user with the secret
+    ///
+    /// The secret is generated by the caller, so that it is the same in every backend.
+    fn add_service_token(
+        &mut self,
+        user: &User,
+        draft: TokenDraft,
+        secret: String,
+    ) -> &ServiceToken;
+
+    fn revoke_service_token(&mut self, id: Id) -> bool;
+
+    /// Replaces the users mentioned in the note and returns the newly mentioned ones
+    fn set_mentions(&mut self, note: Id, users: HashSet<Id>) -> Vec<Id>;
+
+    /// Returns all notes that mention the user, regardless of their visibility
+    fn mentions(&'a self, user: &User) -> Vec<&'a Note>;
+
+    /// Returns the active notes that the user marked as favorite, in the order of their Ids
+    fn favorites(&'a self, user: &Id) -> Vec<&'a Note>;
+
+    /// Adds the note to or removes it from the favorites of the user
+    ///
+    /// Returns `true` if the favorites changed.
+    fn set_favorite(&mut self, user: Id, note: Id, favorite: bool) -> bool;
+
+    fn comment(&'a self, id: Id) -> Option<&'a Comment>;
+
+    /// Returns the comments of the note, oldest first
+    fn comments(&'a self, note: &Id) -> Vec<&'a Comment>;
+
+    /// Returns all comments that the user wrote, on any note
+    fn user_comments(&'a self, user: &Id) -> Vec<&'a Comment>;
+
+    fn add_comment(&mut self, note: Id, draft: CommentDraft, author: &User) -> &Comment;
+
+    fn delete_comment(&mut self, id: Id) -> bool;
+
+    /// Returns all remote actors that follow the user
+    fn followers(&'a self, user: &Id) -> Vec<&'a Follower>;
+
+    /// Stores a [`Follower`], replacing an existing one with the same actor
+    fn add_follower(&mut self, follower: Follower);
+
+    fn remove_follower(&mut self, user: &Id, actor: &str) -> bool;
+
+    /// Returns the access log of the note, oldest first
+    fn access_log(&'a self, note: &Id) -> Vec<&'a AccessEntry>;
+
+    /// Appends the entry to the access log of its note
+    ///
+    /// Only the last [`ACCESS_LOG_CAPACITY`](crate::models::access::ACCESS_LOG_CAPACITY)
+    /// entries of every note are kept.
+    fn record_access(&mut self, entry: AccessEntry);
+
+    /// Returns the audit log of the user, oldest first
+    fn audit_log(&'a self, user: &Id) -> Vec<&'a AuditEntry>;
+
+    /// Appends the entry to the audit log of its user
+    ///
+    /// Only the last [`AUDIT_LOG_CAPACITY`](crate::models::audit::AUDIT_LOG_CAPACITY)
+    /// entries of every user are kept.
+    fn record_audit(&mut self, entry: AuditEntry);
+
+    /// Returns the usage of the user for all days with requests between `from` and `to` (inclusive)
+    fn usage(&'a self, user: &Id, from: NaiveDate, to: NaiveDate) -> Vec<DailyUsage>;
+
+    /// Adds the usage to the stored usage of the user on the same day
+    fn record_usage(&mut self, user: Id, usage: DailyUsage);
+
+    /// Permanently removes the user and all of their data
+    ///
+    /// This cascades to all notes (including soft-deleted ones) with their comments and access logs,
+    /// saved searches, favorites, comments and accesses of the user and all tags that are not used by notes
+    /// of other users anymore. Service tokens of the user are revoked.
+    fn purge_user(&mut self, id: Id) -> bool;
+
+    /// Restores a validated [`Backup`], keeping the Ids of all records
+    ///
+    /// [`RestoreMode::Replace`] removes all existing data first, including the
+    /// data that is not part of backups. [`RestoreMode::Merge`] only adds the records
+    /// that don't exist yet. `progress` is called with the number of restored records.
+    fn restore(&mut self, backup: &Backup, mode: RestoreMode, progress: &mut dyn FnMut(usize));
+
+    /// Makes room for `count` new notes in backends with a limited capacity
+    ///
+    /// Returns the notes that were removed permanently to make room, or `None` if
+    /// the backend can't store that many more notes. The default is a backend without limit.
+    fn reserve_notes(&mut self, _count: usize) -> Option<Vec<Note>> {
+        Some(vec![])
+    }
+
+    /// Returns the optional features that the backend supports
+    ///
+    /// The default is a backend without any optional features.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Checks the connection of the backend, e.g. with a ping to the database server
+    ///
+    /// The default is a backend that is always available, like the in-memory storage.
+    fn health(&self) -> Health {
+        Health::Up
+    }
+
+    /// Replaces a broken connection, called by the [`Supervisor`](supervisor::Supervisor)
+    /// while the backend is down
+    fn reconnect(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Returns statistics about the stored data
+    ///
+    /// The default implementation counts the rows and estimates the bytes from the
+    /// text of the notes. Backends should override it with their own numbers, e.g.
+    /// the page count of an SQLite database and the sizes of its indexes.
+    fn stats(&'a self) -> StorageStats {
+        let mut stats = StorageStats {
+            tags: self.tags().count(),
+            users: self.users().count(),
+            ..StorageStats::default()
+        };
+        for note in self.notes_with(VisibilityFilter::All) {
+            stats.notes += 1;
+            if note.visibility() == &Visibility::Deleted {
+                stats.deleted_notes += 1;
+            }
+            stats.bytes += note.title().len() + note.body().len();
+        }
+        stats
+    }
+
+    /// Reclaims unused storage, e.g. with `VACUUM` in SQLite or by writing a
+    /// snapshot and truncating the log of a file backend
+    ///
+    /// Only used if the backend announces [`Capabilities::compaction`]. The
+    /// default implementation does nothing.
+    fn compact(&mut self) {}
+
+    /// Creates a note for every draft and returns their Ids, in the order of the drafts
+    ///
+    /// Used by imports that create thousands of notes at once. SQL backends can
+    /// insert them with a multi-row `INSERT` or `COPY`. The default implementation
+    /// adds the notes one by one.
+    fn add_notes_bulk(&mut self, drafts: Vec<Draft>, user: &User) -> Vec<Id> {
+        drafts
+            .into_iter()
+            .map(|draft| *self.add_note(draft, user).id())
+            .collect()
+    }
+
+    /// Returns the active notes of the user that contain all words of `text`
+    ///
+    /// Only used if the backend announces [`Capabilities::full_text_search`]. The
+    /// default implementation does not find anything.
+    fn search_notes(&'a self, _user: &User, _text: &str) -> Vec<&'a Note> {
+        vec![]
+    }
+
+    /// Returns an estimate of the number of notes that [`query_notes`](Persister::query_notes)
+    /// would return, if the backend can estimate it cheaply
+    ///
+    /// `GET /notes/count` uses the estimate unless an exact count is requested. SQL backends
+    /// can take the row estimate of the query plan (`EXPLAIN`) or keep a HyperLogLog sketch
+    /// per user. The default has no estimate, so all notes are counted.
+    fn estimate_count(&'a self, _user: &User, _query: &NoteQuery) -> Option<usize> {
+        None
+    }
+
+    /// Returns the notes of the user as they were at the time `at`, for `GET /notes?as_of=...`
+    ///
+    /// The default looks up the last revision before `at` of every note that existed then.
+    /// Only the title and body have revisions, all other fields keep their current values.
+    /// Event-sourced backends can replay their events up to `at` instead.
+    fn notes_as_of(&'a self, user: &User, at: &DateTime<Utc>) -> Vec<Note> {
+        self.notes_with(VisibilityFilter::All)
+            .filter(|note| note.user() == user.id() && note.existed_at(at))
+            .filter_map(|note| {
+                let revisions = self.revisions(note.id());
+                match revisions
+                    .iter()
+                    .rev()
+                    .find(|revision| &revision.created <= at)
+                {
+                    Some(revision) => self
+                        .revision(note.id(), revision.number)
+                        .map(|revision| note.clone().at_revision(revision)),
+                    // backends without revisions only know the current version
+                    None => (revisions.is_empty() && note.updated() <= at).then(|| note.clone()),
+                }
+            })
+            .collect()
+    }
+
+    /// Returns all active (= not deleted) notes
+    #[allow(dead_code)] // convenience default, only used in unittests for now
+    fn notes(&'a self) -> Self::NoteIter {
+        self.notes_with(VisibilityFilter::Active)
+    }
+
+    /// Returns the note with the `id` if it passes the [`VisibilityFilter`]
+    fn note_with(&'a self, id: Id, filter: VisibilityFilter) -> Option<&'a Note> {
+        self.notes_with(filter).find(|note| note.id() == &id)
     }
 
-    fn tag(&'a self, label: &str) -> Option<&Tag> {
+    /// Returns the active note with the slug
+    fn note_by_slug(&'a self, slug: &str) -> Option<&'a Note> {
+        self.notes_with(VisibilityFilter::Active)
+            .find(|note| note.slug() == Some(slug))
+    }
+
+    /// Returns the note with the `id` unless it is deleted
+    fn note(&'a self, id: Id) -> Option<&'a Note> {
+        self.note_with(id, VisibilityFilter::Active)
+    }
+
+    fn tag(&'a self, label: &str) -> Option<&'a Tag> {
         self.tags().find(|tag| tag.label() == label)
     }
+
+    fn search(&'a self, id: Id) -> Option<&'a SavedSearch> {
+        self.searches().find(|search| search.id() == &id)
+    }
+
+    fn user(&'a self, id: Id) -> Option<&'a User> {
+        self.users().find(|user| user.id() == &id)
+    }
+
+    /// Returns the user that is linked to the [`ExternalIdentity`]
+    fn user_by_identity(&'a self, identity: &ExternalIdentity) -> Option<&'a User> {
+        self.users().find(|user| user.identity() == Some(identity))
+    }
+
+    /// Collects all data of the user
+    fn export_user(&'a self, user: &User) -> UserExport {
+        let notes = self
+            .notes_with(VisibilityFilter::All)
+            .filter(|note| note.user() == user.id())
+            .cloned()
+            .collect();
+        let searches = self.user_searches(user).cloned().collect();
+        let comments = self.user_comments(user.id()).into_iter().cloned().collect();
+        UserExport::new(user.clone(), notes, searches, comments)
+    }
+
+    /// Collects all data that is needed to restore the storage, see [`Backup`]
+    fn backup(&'a self) -> Backup {
+        let users = self.users().cloned().collect::<Vec<User>>();
+        let mut searches = Vec::new();
+        let mut comments = Vec::new();
+        let mut followers = Vec::new();
+        let mut favorites = Vec::new();
+        for user in &users {
+            searches.extend(self.user_searches(user).cloned());
+            comments.extend(self.user_comments(user.id()).into_iter().cloned());
+            followers.extend(self.followers(user.id()).into_iter().cloned());
+            favorites.extend(
+                self.favorites(user.id())
+                    .into_iter()
+                    .map(|note| (*user.id(), *note.id())),
+            );
+        }
+        comments.sort_by_key(|comment: &Comment| usize::from(comment.id()));
+        Backup::new(
+            users,
+            self.notes_with(VisibilityFilter::All).cloned().collect(),
+            self.tags().cloned().collect(),
+            searches,
+            comments,
+            followers,
+            favorites,
+        )
+    }
+
+    /// Returns the records of the [`Backup`] that conflict with the stored data
+    /// in [`RestoreMode::Merge`]
+    ///
+    /// Records with the Id of a stored record must be identical, tag labels and
+    /// slugs must stay unique.
+    fn merge_conflicts(&'a self, backup: &Backup) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        for user in backup.users() {
+            if self.user(*user.id()).is_some_and(|stored| stored != user) {
+                conflicts.push(format!("User {} differs", usize::from(user.id())));
+            }
+        }
+        for tag in backup.tags() {
+            if self
+                .tags()
+                .any(|stored| (stored.id() == tag.id()) != (stored.label() == tag.label()))
+            {
+                conflicts.push(format!("Tag `{}` differs", tag.label()));
+            }
+        }
+        for note in backup.notes() {
+            match self.note_with(*note.id(), VisibilityFilter::All) {
+                Some(stored) if stored != note => {
+                    conflicts.push(format!("Note {} differs", usize::from(note.id())));
+                }
+                Some(_) => {}
+                None => {
+                    if note.slug().is_some()
+                        && self
+                            .notes_with(VisibilityFilter::All)
+                            .any(|stored| stored.slug() == note.slug())
+                    {
+                        conflicts.push(format!(
+                            "The slug of note {} is taken",
+                            usize::from(note.id())
+                        ));
+                    }
+                }
+            }
+        }
+        for search in backup.searches() {
+            if self
+                .search(*search.id())
+                .is_some_and(|stored| stored != search)
+            {
+                conflicts.push(format!("Search {} differs", usize::from(search.id())));
+            }
+        }
+        for comment in backup.comments() {
+            if self
+                .comment(*comment.id())
+                .is_some_and(|stored| stored != comment)
+            {
+                conflicts.push(format!("Comment {} differs", usize::from(comment.id())));
+            }
+        }
+        conflicts
+    }
+
+    /// Returns the active notes of the user within `radius_m` meters around `center`,
+    /// closest first
+    ///
+    /// The default implementation computes the distance of every note of the user.
+    /// Backends with spatial indexes (e.g. PostGIS) should override it.
+    fn notes_near(&'a self, user: &User, center: &Location, radius_m: f64) -> Vec<&'a Note> {
+        let mut res = self
+            .user_notes(user)
+            .filter_map(|note| {
+                let distance = center.distance_m(note.location()?);
+                (distance <= radius_m).then_some((note, distance))
+            })
+            .collect::<Vec<(&Note, f64)>>();
+        res.sort_by(|a, b| a.1.total_cmp(&b.1));
+        res.into_iter().map(|(note, _)| note).collect()
+    }
+
+    /// Returns the [`TagStats`] of all tags for the active notes of the user
+    ///
+    /// The default implementation scans all notes of the user once. Backends
+    /// with a query engine should override it, e.g. with a `GROUP BY`.
+    fn tag_stats(&'a self, user: &User) -> Vec<TagStats> {
+        let mut stats = self
+            .tags()
+            .map(|tag| (*tag.id(), TagStats::new(tag.clone())))
+            .collect::<HashMap<Id, TagStats>>();
+        for note in self.user_notes(user) {
+            for tag in note.tags() {
+                if let Some(tag_stats) = stats.get_mut(tag.id()) {
+                    tag_stats.add_use(note.updated());
+                }
+            }
+        }
+        let mut res = stats.into_values().collect::<Vec<TagStats>>();
+        res.sort_by_key(|tag_stats| usize::from(tag_stats.tag().id()));
+        res
+    }
 }
 
 #[cfg(test)]
@@ -60,12 +663,65 @@ mod test {
     impl<'a> Persister<'a> for A {
         type NoteIter = std::slice::Iter<'a, Note>;
         type TagIter = std::slice::Iter<'a, Tag>;
-        fn notes(&'a self) -> Self::NoteIter {
+        type SearchIter = std::slice::Iter<'a, SavedSearch>;
+        type UserIter = std::slice::Iter<'a, User>;
+        fn notes_with(&'a self, _filter: VisibilityFilter) -> Self::NoteIter {
             self.0.iter()
         }
         fn tags(&'a self) -> Self::TagIter {
             self.1.iter()
         }
+        fn searches(&'a self) -> Self::SearchIter {
+            unimplemented!()
+        }
+        fn users(&'a self) -> Self::UserIter {
+            [].iter()
+        }
+        fn add_user(&mut self, _name: String, _identity: Option<ExternalIdentity>) -> &User {
+            unimplemented!()
+        }
+        fn schedule_user_deletion(&mut self, _id: Id, _purge_at: DateTime<Utc>) -> bool {
+            unimplemented!()
+        }
+        fn set_preferences(&mut self, _id: Id, _preferences: Preferences) -> bool {
+            unimplemented!()
+        }
+        fn set_profile(&mut self, _id: Id, _profile: Profile) -> bool {
+            unimplemented!()
+        }
+        fn set_tag_rules(&mut self, _id: Id, _rules: TagRules) -> bool {
+            unimplemented!()
+        }
+
+        fn set_templates(&mut self, _id: Id, _templates: Templates) -> bool {
+            unimplemented!()
+        }
+        fn set_integrations(&mut self, _id: Id, _integrations: Integrations) -> bool {
+            unimplemented!()
+        }
+        fn purge_user(&mut self, _id: Id) -> bool {
+            unimplemented!()
+        }
+        fn idempotency_record(&'a self, _user: &User, _key: &str) -> Option<&'a IdempotencyRecord> {
+            unimplemented!()
+        }
+        fn add_idempotency_record(&mut self, _record: IdempotencyRecord) {
+            unimplemented!()
+        }
+        fn expire_idempotency_records(&mut self, _before: &DateTime<Utc>) -> usize {
+            unimplemented!()
+        }
+        fn viewed_at(&self, _user: &Id, _note: &Id) -> Option<DateTime<Utc>> {
+            unimplemented!()
+        }
+        fn set_viewed(
+            &mut self,
+            _user: Id,
+            _note: Id,
+            _at: DateTime<Utc>,
+        ) -> Option<DateTime<Utc>> {
+            unimplemented!()
+        }
         fn add_note(&mut self, _draft: Draft, _user: &User) -> &Note {
             unimplemented!()
         }
@@ -75,15 +731,201 @@ mod test {
         fn add_tag(&mut self, _label: String) -> Id {
             unimplemented!()
         }
+        fn revisions(&self, _note: &Id) -> Vec<RevisionInfo> {
+            unimplemented!()
+        }
+        fn revision(&self, _note: &Id, _number: usize) -> Option<Revision> {
+            unimplemented!()
+        }
+        fn update_tag(&mut self, _id: Id, _meta: TagMeta) -> Option<&Tag> {
+            unimplemented!()
+        }
         fn user_notes(&'a self, _user: &User) -> Self::NoteIter {
             unimplemented!()
         }
         fn tagged_notes(&'a self, _tag: &Tag) -> Self::NoteIter {
             unimplemented!()
         }
+        fn query_notes(&'a self, _user: &User, _query: &NoteQuery) -> Self::NoteIter {
+            unimplemented!()
+        }
+        fn add_search(&mut self, _draft: SearchDraft, _user: &User) -> &SavedSearch {
+            unimplemented!()
+        }
+        fn user_searches(&'a self, _user: &User) -> Self::SearchIter {
+            unimplemented!()
+        }
         fn delete_note(&mut self, _id: Id) -> bool {
             unimplemented!()
         }
+        fn merge_notes(&mut self, _target: Id, _sources: &[Id]) -> &Note {
+            unimplemented!()
+        }
+        fn restore_note(&mut self, _id: Id, _visibility: Visibility) -> bool {
+            unimplemented!()
+        }
+        fn purge_notes(&mut self, _ids: &[Id]) -> usize {
+            unimplemented!()
+        }
+        fn set_locked(&mut self, _id: Id, _locked: bool) -> bool {
+            unimplemented!()
+        }
+        fn set_archived(&mut self, _id: Id, _archived: bool) -> bool {
+            unimplemented!()
+        }
+        fn collect_tags(&mut self) -> usize {
+            unimplemented!()
+        }
+        fn undo_token(&'a self, _token: &str) -> Option<&'a UndoToken> {
+            unimplemented!()
+        }
+        fn add_undo_token(&mut self, _token: UndoToken) {
+            unimplemented!()
+        }
+        fn remove_undo_token(&mut self, _token: &str) -> Option<UndoToken> {
+            unimplemented!()
+        }
+        fn expire_undo_tokens(&mut self, _before: &DateTime<Utc>) -> usize {
+            unimplemented!()
+        }
+        fn trash_confirmation(&'a self, _token: &str) -> Option<&'a TrashConfirmation> {
+            unimplemented!()
+        }
+        fn add_trash_confirmation(&mut self, _confirmation: TrashConfirmation) {
+            unimplemented!()
+        }
+        fn remove_trash_confirmation(&mut self, _token: &str) -> Option<TrashConfirmation> {
+            unimplemented!()
+        }
+        fn working_copy(&'a self, _note: &Id) -> Option<&'a WorkingCopy> {
+            unimplemented!()
+        }
+        fn set_working_copy(&mut self, _copy: WorkingCopy) -> Option<WorkingCopy> {
+            unimplemented!()
+        }
+        fn remove_working_copy(&mut self, _note: &Id) -> Option<WorkingCopy> {
+            unimplemented!()
+        }
+        fn expire_working_copies(&mut self, _before: &DateTime<Utc>) -> usize {
+            unimplemented!()
+        }
+        fn flags(&'a self) -> Vec<&'a Flag> {
+            unimplemented!()
+        }
+        fn flag(&'a self, _note: &Id) -> Option<&'a Flag> {
+            unimplemented!()
+        }
+        fn set_flag(&mut self, _flag: Flag) -> Option<Flag> {
+            unimplemented!()
+        }
+        fn remove_flag(&mut self, _note: &Id) -> Option<Flag> {
+            unimplemented!()
+        }
+        fn service_token(&'a self, _secret: &str) -> Option<&'a ServiceToken> {
+            unimplemented!()
+        }
+        fn service_tokens(&'a self, _user: &Id) -> Vec<&'a ServiceToken> {
+            unimplemented!()
+        }
+        fn add_service_token(
+            &mut self,
+            _user: &User,
+            _draft: TokenDraft,
+            _secret: String,
+        ) -> &ServiceToken {
+            unimplemented!()
+        }
+        fn revoke_service_token(&mut self, _id: Id) -> bool {
+            unimplemented!()
+        }
+        fn set_mentions(&mut self, _note: Id, _users: HashSet<Id>) -> Vec<Id> {
+            unimplemented!()
+        }
+        fn mentions(&'a self, _user: &User) -> Vec<&'a Note> {
+            unimplemented!()
+        }
+        fn favorites(&'a self, _user: &Id) -> Vec<&'a Note> {
+            unimplemented!()
+        }
+        fn set_favorite(&mut self, _user: Id, _note: Id, _favorite: bool) -> bool {
+            unimplemented!()
+        }
+        fn comment(&'a self, _id: Id) -> Option<&'a Comment> {
+            unimplemented!()
+        }
+        fn comments(&'a self, _note: &Id) -> Vec<&'a Comment> {
+            unimplemented!()
+        }
+        fn user_comments(&'a self, _user: &Id) -> Vec<&'a Comment> {
+            unimplemented!()
+        }
+        fn add_comment(&mut self, _note: Id, _draft: CommentDraft, _author: &User) -> &Comment {
+            unimplemented!()
+        }
+        fn delete_comment(&mut self, _id: Id) -> bool {
+            unimplemented!()
+        }
+        fn followers(&'a self, _user: &Id) -> Vec<&'a Follower> {
+            unimplemented!()
+        }
+        fn add_follower(&mut self, _follower: Follower) {
+            unimplemented!()
+        }
+        fn remove_follower(&mut self, _user: &Id, _actor: &str) -> bool {
+            unimplemented!()
+        }
+        fn access_log(&'a self, _note: &Id) -> Vec<&'a AccessEntry> {
+            unimplemented!()
+        }
+        fn record_access(&mut self, _entry: AccessEntry) {
+            unimplemented!()
+        }
+        fn audit_log(&'a self, _user: &Id) -> Vec<&'a AuditEntry> {
+            unimplemented!()
+        }
+        fn record_audit(&mut self, _entry: AuditEntry) {
+            unimplemented!()
+        }
+        fn usage(&'a self, _user: &Id, _from: NaiveDate, _to: NaiveDate) -> Vec<DailyUsage> {
+            unimplemented!()
+        }
+        fn record_usage(&mut self, _user: Id, _usage: DailyUsage) {
+            unimplemented!()
+        }
+        fn bulk_tag(&mut self, _ids: &[Id], _add: &[String], _remove: &[String]) -> Vec<Id> {
+            unimplemented!()
+        }
+        fn restore(
+            &mut self,
+            _backup: &Backup,
+            _mode: RestoreMode,
+            _progress: &mut dyn FnMut(usize),
+        ) {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn test_capabilities_default() {
+        let foo = A(vec![example_note()], vec![]);
+        assert_eq!(foo.capabilities(), Capabilities::default());
+        assert!(!foo.capabilities().full_text_search);
+        assert!(foo.search_notes(&User::default(), "Test").is_empty());
+    }
+
+    #[test]
+    fn test_stats_default() {
+        let mut deleted = example_note();
+        deleted.delete().unwrap();
+        let foo = A(
+            vec![example_note(), deleted],
+            vec![Tag::new(Id(1), "foo".to_string())],
+        );
+        let stats = foo.stats();
+        assert_eq!(stats.notes, 2);
+        assert_eq!(stats.deleted_notes, 1);
+        assert_eq!(stats.tags, 1);
+        assert_eq!(stats.bytes, 2 * "Test-TitleTest-Body".len());
     }
 
     #[test]
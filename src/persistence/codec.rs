@@ -0,0 +1,267 @@
+//! Compression of note bodies for backends that serialize them, e.g. file, SQL or S3 backends
+//!
+//! A backend encodes the body with a [`CompressionCodec`] before it writes a note and decodes
+//! it after reading, so the rest of the app only sees the text. Every encoded body starts with
+//! a tag byte that tells how it was stored. Bodies that were written uncompressed, e.g. small
+//! ones or before compression was enabled, can therefore always be read.
+//!
+//! Notes are short, so [`ZstdCodec`] compresses them with a dictionary that is trained on the
+//! existing notes with [`train_dictionary`]. The backend must store the dictionary next to the
+//! notes, bodies can't be decoded without it.
+//!
+//! The in-memory backend keeps bodies as text, because it hands out references to its notes.
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use crate::models::note::Note;
+
+/// The body is stored as UTF-8 text
+const RAW: u8 = 0;
+/// The body is a zstd frame, compressed with the dictionary of the codec if it has one
+const ZSTD: u8 = 1;
+
+/// Encodes note bodies for storage and decodes them again
+pub trait CompressionCodec: Debug + Send + Sync {
+    fn encode(&self, body: &str) -> Vec<u8>;
+
+    /// Decodes a body written by [`CompressionCodec::encode`] of any codec with the same
+    /// dictionary
+    fn decode(&self, bytes: &[u8]) -> Result<String, String>;
+}
+
+/// Stores bodies as they are
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Uncompressed;
+
+impl CompressionCodec for Uncompressed {
+    fn encode(&self, body: &str) -> Vec<u8> {
+        raw(body)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String, String> {
+        match bytes.split_first() {
+            Some((&RAW, text)) => utf8(text.to_vec()),
+            Some((tag, _)) => Err(format!("Unsupported body encoding {tag}")),
+            None => Err("Empty body encoding".to_string()),
+        }
+    }
+}
+
+fn raw(body: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(body.len() + 1);
+    bytes.push(RAW);
+    bytes.extend_from_slice(body.as_bytes());
+    bytes
+}
+
+fn utf8(bytes: Vec<u8>) -> Result<String, String> {
+    String::from_utf8(bytes).map_err(|err| format!("Invalid body: {err}"))
+}
+
+/// The dictionary of a [`ZstdCodec`], prepared once for all bodies
+struct Dictionary {
+    bytes: Vec<u8>,
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+impl Debug for Dictionary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dictionary")
+            .field("bytes", &self.bytes.len())
+            .finish()
+    }
+}
+
+/// Compresses bodies with zstd, bodies shorter than [`ZstdCodec::min_size`] are stored as they are
+#[derive(Clone, Debug)]
+pub struct ZstdCodec {
+    level: i32,
+    min_size: usize,
+    dictionary: Option<Arc<Dictionary>>,
+}
+
+impl Default for ZstdCodec {
+    fn default() -> Self {
+        Self {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            min_size: 64,
+            dictionary: None,
+        }
+    }
+}
+
+impl ZstdCodec {
+    /// Sets the compression level, from `1` (fastest) to `22` (smallest)
+    pub fn level(mut self, level: i32) -> Self {
+        self.level = level;
+        self.dictionary = self
+            .dictionary
+            .map(|dictionary| Arc::new(prepare(dictionary.bytes.clone(), level)));
+        self
+    }
+
+    /// Sets the size in bytes below which bodies are not compressed, because the frame
+    /// header would take more space than compression saves
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Compresses with a dictionary of [`train_dictionary`]
+    pub fn dictionary(mut self, bytes: Vec<u8>) -> Self {
+        self.dictionary = Some(Arc::new(prepare(bytes, self.level)));
+        self
+    }
+
+    /// Returns the dictionary that the backend must store to decode the bodies again
+    pub fn dictionary_bytes(&self) -> Option<&[u8]> {
+        self.dictionary
+            .as_ref()
+            .map(|dictionary| dictionary.bytes.as_slice())
+    }
+
+    fn compress(&self, body: &str) -> std::io::Result<Vec<u8>> {
+        let mut bytes = vec![ZSTD];
+        let mut encoder = match &self.dictionary {
+            Some(dictionary) => {
+                zstd::Encoder::with_prepared_dictionary(&mut bytes, &dictionary.encoder)?
+            }
+            None => zstd::Encoder::new(&mut bytes, self.level)?,
+        };
+        encoder.write_all(body.as_bytes())?;
+        encoder.finish()?;
+        Ok(bytes)
+    }
+
+    fn decompress(&self, frame: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut text = vec![];
+        match &self.dictionary {
+            Some(dictionary) => {
+                zstd::Decoder::with_prepared_dictionary(frame, &dictionary.decoder)?
+                    .read_to_end(&mut text)?
+            }
+            None => zstd::Decoder::new(frame)?.read_to_end(&mut text)?,
+        };
+        Ok(text)
+    }
+}
+
+fn prepare(bytes: Vec<u8>, level: i32) -> Dictionary {
+    Dictionary {
+        encoder: EncoderDictionary::copy(&bytes, level),
+        decoder: DecoderDictionary::copy(&bytes),
+        bytes,
+    }
+}
+
+impl CompressionCodec for ZstdCodec {
+    /// Stores the body as it is if it is small or compression would not make it smaller
+    fn encode(&self, body: &str) -> Vec<u8> {
+        if body.len() < self.min_size {
+            return raw(body);
+        }
+        match self.compress(body) {
+            Ok(bytes) if bytes.len() <= body.len() => bytes,
+            _ => raw(body),
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String, String> {
+        match bytes.split_first() {
+            Some((&ZSTD, frame)) => self
+                .decompress(frame)
+                .map_err(|err| format!("Unable to decompress body: {err}"))
+                .and_then(utf8),
+            _ => Uncompressed.decode(bytes),
+        }
+    }
+}
+
+/// Trains a zstd dictionary of up to `max_size` bytes on the bodies of the notes
+///
+/// Fails if there are too few bodies to learn from, the backend should compress without a
+/// dictionary then and train it again later.
+pub fn train_dictionary<'n>(
+    notes: impl IntoIterator<Item = &'n Note>,
+    max_size: usize,
+) -> Result<Vec<u8>, String> {
+    let samples = notes
+        .into_iter()
+        .map(|note| note.body().text().into_owned().into_bytes())
+        .filter(|body| !body.is_empty())
+        .collect::<Vec<Vec<u8>>>();
+    zstd::dict::from_samples(&samples, max_size).map_err(|err| {
+        format!(
+            "Unable to train dictionary on {} bodies: {err}",
+            samples.len()
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+
+    fn note(body: String) -> Note {
+        let mut note = serde_json::to_value(example_note()).unwrap();
+        note["body"] = body.into();
+        serde_json::from_value(note).unwrap()
+    }
+
+    fn body(n: usize) -> String {
+        format!(
+            "# Meeting {n}\n\n- [ ] Prepare the agenda for project {}\n- [x] Send the minutes to the team\n\nNext meeting on day {}\n",
+            n % 7,
+            n % 28
+        )
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let codec = ZstdCodec::default();
+        let long = "All work and no play makes Jack a dull boy. ".repeat(20);
+        let encoded = codec.encode(&long);
+        assert_eq!(encoded[0], ZSTD);
+        assert!(encoded.len() < long.len() / 4);
+        assert_eq!(codec.decode(&encoded).unwrap(), long);
+
+        // small bodies are not worth it
+        let encoded = codec.encode("Buy milk");
+        assert_eq!(encoded, b"\0Buy milk");
+        assert_eq!(codec.decode(&encoded).unwrap(), "Buy milk");
+        assert_eq!(Uncompressed.decode(&encoded).unwrap(), "Buy milk");
+        assert_eq!(codec.decode(&Uncompressed.encode("")).unwrap(), "");
+
+        assert!(Uncompressed.decode(&codec.encode(&long)).is_err());
+        assert!(codec.decode(&[ZSTD, 1, 2, 3]).is_err());
+        assert!(codec.decode(&[]).is_err());
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let notes = (0..500).map(|n| note(body(n))).collect::<Vec<Note>>();
+        let dictionary = train_dictionary(&notes, 4096).unwrap();
+        assert!(dictionary.len() <= 4096);
+
+        let plain = ZstdCodec::default();
+        let trained = ZstdCodec::default().dictionary(dictionary.clone());
+        assert_eq!(trained.dictionary_bytes(), Some(dictionary.as_slice()));
+        let text = body(1000);
+        let encoded = trained.encode(&text);
+        assert!(encoded.len() < plain.encode(&text).len());
+        assert_eq!(trained.decode(&encoded).unwrap(), text);
+        assert_eq!(
+            trained.level(19).decode(&encoded).unwrap(),
+            text,
+            "the level does not matter for decoding"
+        );
+        assert!(plain.decode(&encoded).is_err());
+
+        assert!(train_dictionary(&notes[..1], 4096).is_err());
+    }
+}
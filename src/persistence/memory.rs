@@ -1,16 +1,137 @@
-use crate::models::note::{Draft, Note, Tags};
-use crate::models::{Id, Tag, User, Visibility};
+mod table;
 
-use crate::persistence::Persister;
+use std::collections::btree_map;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 
-#[derive(Debug, Default)]
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::config::{NoteLimit, NoteLimitMode};
+use crate::models::access::{AccessEntry, ACCESS_LOG_CAPACITY};
+use crate::models::audit::{AuditEntry, AUDIT_LOG_CAPACITY};
+use crate::models::autosave::WorkingCopy;
+use crate::models::backup::{Backup, RestoreMode};
+use crate::models::comment::{Comment, CommentDraft};
+use crate::models::follower::Follower;
+use crate::models::idempotency::IdempotencyRecord;
+use crate::models::integration::Integrations;
+use crate::models::lifecycle::State;
+use crate::models::moderation::Flag;
+use crate::models::note::{slugify, Draft, Note, Tags};
+use crate::models::preferences::Preferences;
+use crate::models::profile::Profile;
+use crate::models::query::{NoteQuery, SavedSearch, SearchDraft};
+use crate::models::tag_rule::TagRules;
+use crate::models::template::Templates;
+use crate::models::token::{ServiceToken, TokenDraft};
+use crate::models::trash::TrashConfirmation;
+use crate::models::undo::UndoToken;
+use crate::models::usage::DailyUsage;
+use crate::models::{ExternalIdentity, Id, Tag, TagMeta, User, Visibility, VisibilityFilter};
+
+use crate::persistence::{Capabilities, Persister, StorageStats};
+use crate::revisions::{Revision, RevisionInfo, RevisionLog};
+
+use table::Table;
+
+#[derive(Debug)]
 pub struct InMemoryStorage {
-    notes: Vec<Note>,
-    tags: Vec<Tag>,
+    notes: Table<Note>,
+    tags: Table<Tag>,
+    searches: Table<SavedSearch>,
+    users: Table<User>,
+    comments: Table<Comment>,
+    idempotency: HashMap<(Id, String), IdempotencyRecord>,
+    undo_tokens: HashMap<String, UndoToken>,
+    trash_confirmations: HashMap<String, TrashConfirmation>,
+    service_tokens: Table<ServiceToken>,
+    /// The working copy of each note
+    working_copies: HashMap<Id, WorkingCopy>,
+    /// The flags of notes in the moderation queue
+    flags: BTreeMap<Id, Flag>,
+    followers: Vec<Follower>,
+    /// The users mentioned in each note
+    mentions: HashMap<Id, HashSet<Id>>,
+    /// The favorite notes (second Id) of each user (first Id)
+    favorites: BTreeSet<(Id, Id)>,
+    /// When a user (first Id) viewed a note (second Id) the last time
+    views: HashMap<(Id, Id), DateTime<Utc>>,
+    /// The access log of each note, oldest first
+    access_log: HashMap<Id, VecDeque<AccessEntry>>,
+    /// The audit log of each user, oldest first
+    audit_log: HashMap<Id, VecDeque<AuditEntry>>,
+    revisions: HashMap<Id, RevisionLog>,
+    /// The API usage of each user per day
+    usage: BTreeMap<(Id, NaiveDate), DailyUsage>,
+    note_limit: Option<NoteLimit>,
+}
+
+impl Default for InMemoryStorage {
+    /// Creates an empty storage with only the anonymous default user
+    fn default() -> Self {
+        let mut users = Table::default();
+        users.insert_with(|id| User::new(id, "anonymous".to_string()));
+        Self {
+            notes: Table::default(),
+            tags: Table::default(),
+            searches: Table::default(),
+            users,
+            comments: Table::default(),
+            idempotency: HashMap::new(),
+            undo_tokens: HashMap::new(),
+            trash_confirmations: HashMap::new(),
+            service_tokens: Table::default(),
+            working_copies: HashMap::new(),
+            flags: BTreeMap::new(),
+            followers: vec![],
+            mentions: HashMap::new(),
+            favorites: BTreeSet::new(),
+            views: HashMap::new(),
+            access_log: HashMap::new(),
+            audit_log: HashMap::new(),
+            revisions: HashMap::new(),
+            usage: BTreeMap::new(),
+            note_limit: None,
+        }
+    }
 }
 
 impl InMemoryStorage {
-    fn map_tags(&mut self, labels: &Vec<String>) -> Tags {
+    /// Caps the number of stored notes, see [`Persister::reserve_notes`]
+    pub fn with_note_limit(mut self, limit: Option<NoteLimit>) -> Self {
+        self.note_limit = limit;
+        self
+    }
+
+    /// Returns the Ids of the notes that may be evicted, in the order of eviction
+    ///
+    /// Deleted notes come first, least recently changed first, followed by the public notes,
+    /// least recently updated or viewed first.
+    fn eviction_candidates(&self) -> Vec<Id> {
+        let mut last_views: HashMap<Id, DateTime<Utc>> = HashMap::new();
+        for ((_, note), at) in &self.views {
+            let last = last_views.entry(*note).or_insert(*at);
+            *last = (*last).max(*at);
+        }
+        let mut notes = self
+            .notes
+            .iter()
+            .filter_map(|note| {
+                let deleted = match note.visibility() {
+                    Visibility::Deleted => true,
+                    Visibility::Public => false,
+                    _ => return None,
+                };
+                let used = last_views
+                    .get(note.id())
+                    .map_or(*note.updated(), |viewed| (*viewed).max(*note.updated()));
+                Some((!deleted, used, *note.id()))
+            })
+            .collect::<Vec<(bool, DateTime<Utc>, Id)>>();
+        notes.sort();
+        notes.into_iter().map(|(_, _, id)| id).collect()
+    }
+
+    fn map_tags(&mut self, labels: &[String]) -> Tags {
         let mut tags = Tags::default();
         for label in labels {
             if let Some(tag) = self
@@ -20,52 +141,98 @@ impl InMemoryStorage {
             {
                 tags.insert(tag.clone());
             } else {
-                let tag = Tag::new(self.tags.len().into(), label.to_string());
+                let tag = self.tags.insert_with(|id| Tag::new(id, label.to_string()));
                 tags.insert(tag.clone());
-                self.tags.push(tag);
             }
         }
         tags
     }
+
+    /// Returns the slug for the title, with a numeric suffix if the slug is already taken
+    ///
+    /// Slugs of deleted notes are not re-used.
+    fn unique_slug(&self, title: &str) -> Option<String> {
+        let slug = slugify(title)?;
+        let taken = |candidate: &str| self.notes.iter().any(|note| note.slug() == Some(candidate));
+        if !taken(&slug) {
+            return Some(slug);
+        }
+        (2..)
+            .map(|suffix| format!("{slug}-{suffix}"))
+            .find(|candidate| !taken(candidate))
+    }
 }
 
 // we have to use two references `&&Note` because we're using `active`
 // as a closure and have no control over the input
 fn active(note: &&Note) -> bool {
-    note.visibility() != &Visibility::Deleted
+    VisibilityFilter::Active.includes(note, &Utc::now())
 }
 
 impl<'a> Persister<'a> for InMemoryStorage {
     type NoteIter = std::vec::IntoIter<&'a Note>;
-    type TagIter = std::slice::Iter<'a, Tag>;
+    type TagIter = btree_map::Values<'a, Id, Tag>;
+    type SearchIter = std::vec::IntoIter<&'a SavedSearch>;
+    type UserIter = btree_map::Values<'a, Id, User>;
 
-    fn notes(&'a self) -> Self::NoteIter {
-        let res = self.notes.iter().filter(active).collect::<Vec<&Note>>();
+    fn notes_with(&'a self, filter: VisibilityFilter) -> Self::NoteIter {
+        let now = Utc::now();
+        let res = self
+            .notes
+            .iter()
+            .filter(|note| filter.includes(note, &now))
+            .collect::<Vec<&Note>>();
         res.into_iter()
     }
 
+    fn note_with(&'a self, id: Id, filter: VisibilityFilter) -> Option<&'a Note> {
+        // the notes are stored by their Id, so there is no need to scan all notes
+        self.notes
+            .get(&id)
+            .filter(|note| filter.includes(note, &Utc::now()))
+    }
+
     fn tags(&'a self) -> Self::TagIter {
         self.tags.iter()
     }
 
+    fn searches(&'a self) -> Self::SearchIter {
+        let res = self.searches.iter().collect::<Vec<&SavedSearch>>();
+        res.into_iter()
+    }
+
+    fn users(&'a self) -> Self::UserIter {
+        self.users.iter()
+    }
+
+    fn user(&'a self, id: Id) -> Option<&'a User> {
+        self.users.get(&id)
+    }
+
     fn add_note(&mut self, draft: Draft, user: &User) -> &Note {
-        let id = Id(self.notes.len());
         let tags = self.map_tags(draft.tags());
-        let note = Note::new(draft, id, *user.id(), tags);
-        self.notes.push(note);
-        self.note(id)
-            .expect("Note was just added and must be present")
+        let slug = self.unique_slug(draft.title());
+        let note = self
+            .notes
+            .insert_with(|id| Note::new(draft, id, *user.id(), tags).with_slug(slug));
+        self.revisions.entry(*note.id()).or_default().push(
+            note.title(),
+            note.body(),
+            *note.updated(),
+        );
+        note
     }
 
     fn update_note(&mut self, draft: Draft, id: Id) -> &Note {
-        let index: usize = id.into();
         let tags = self.map_tags(draft.tags());
-        if let Some(note) = self.notes.get_mut(index) {
-            let user = note.user();
+        if let Some(note) = self.notes.get_mut(&id) {
             // in this PoC, we don't update fields individually, but simply
-            // replace the whole `Note`
-            let new_note = Note::new(draft, index.into(), *user, tags);
-            *note = new_note;
+            // replace the whole content of the `Note`
+            note.update(draft, tags);
+            self.revisions
+                .entry(id)
+                .or_default()
+                .push(note.title(), note.body(), *note.updated());
             note
         } else {
             // TODO: Error handling
@@ -73,16 +240,129 @@ impl<'a> Persister<'a> for InMemoryStorage {
         }
     }
 
+    fn revisions(&self, note: &Id) -> Vec<RevisionInfo> {
+        self.revisions
+            .get(note)
+            .map(RevisionLog::revisions)
+            .unwrap_or_default()
+    }
+
+    fn revision(&self, note: &Id, number: usize) -> Option<Revision> {
+        self.revisions.get(note)?.get(number)
+    }
+
     fn delete_note(&mut self, id: Id) -> bool {
-        let idx: usize = id.into();
-        if let Some(item) = self.notes.get_mut(idx) {
-            *item.visibility_mut() = Visibility::Deleted;
+        self.notes
+            .get_mut(&id)
+            .is_some_and(|note| note.delete().is_ok())
+    }
+
+    fn merge_notes(&mut self, target: Id, sources: &[Id]) -> &Note {
+        // notes that can't be deleted, e.g. deleted already, are not merged
+        let merged = sources
+            .iter()
+            .filter_map(|id| {
+                let note = self.notes.get_mut(id)?;
+                let source = note.clone();
+                note.merge_into(target).ok().map(|_| source)
+            })
+            .collect::<Vec<Note>>();
+        if let Some(note) = self.notes.get_mut(&target) {
+            for source in &merged {
+                note.merge(source);
+            }
+            self.revisions.entry(target).or_default().push(
+                note.title(),
+                note.body(),
+                *note.updated(),
+            );
+            note
+        } else {
+            // TODO: Error handling
+            panic!("Note does not exist")
+        }
+    }
+
+    fn set_locked(&mut self, id: Id, locked: bool) -> bool {
+        if let Some(note) = self.notes.get_mut(&id) {
+            note.set_locked(locked);
             true
         } else {
             false
         }
     }
 
+    fn set_archived(&mut self, id: Id, archived: bool) -> bool {
+        self.notes
+            .get_mut(&id)
+            .is_some_and(|note| note.set_archived(archived).is_ok())
+    }
+
+    fn restore_note(&mut self, id: Id, visibility: Visibility) -> bool {
+        let labels = match self.notes.get(&id) {
+            Some(note) if note.state() == State::Deleted && visibility != Visibility::Deleted => {
+                note.tags()
+                    .map(|tag| tag.label().to_string())
+                    .collect::<Vec<String>>()
+            }
+            _ => return false,
+        };
+        // the tags might have been collected while the note was deleted
+        let tags = self.map_tags(&labels);
+        let note = self.notes.get_mut(&id).expect("note exists");
+        note.change_tags(&tags, &labels);
+        note.restore(visibility).is_ok()
+    }
+
+    fn purge_notes(&mut self, ids: &[Id]) -> usize {
+        let purged = ids
+            .iter()
+            .filter(|id| {
+                self.notes
+                    .get(id)
+                    .is_some_and(|note| note.visibility() == &Visibility::Deleted)
+            })
+            .copied()
+            .collect::<HashSet<Id>>();
+        for id in &purged {
+            self.notes.remove(id);
+        }
+        if purged.is_empty() {
+            return 0;
+        }
+        self.comments
+            .retain(|comment| !purged.contains(comment.note()));
+        self.undo_tokens
+            .retain(|_, token| !purged.contains(token.note()));
+        // removes the remaining data of the purged notes
+        self.compact();
+        purged.len()
+    }
+
+    fn collect_tags(&mut self) -> usize {
+        let used_tags = self
+            .notes
+            .iter()
+            .filter(|note| note.visibility() != &Visibility::Deleted)
+            .flat_map(|note| note.tags().map(|tag| *tag.id()))
+            .collect::<HashSet<Id>>();
+        let count = self.tags.len();
+        self.tags.retain(|tag| used_tags.contains(tag.id()));
+        count - self.tags.len()
+    }
+
+    fn bulk_tag(&mut self, ids: &[Id], add: &[String], remove: &[String]) -> Vec<Id> {
+        let add = self.map_tags(add);
+        ids.iter()
+            .filter(|id| {
+                self.notes
+                    .get_mut(id)
+                    .is_some_and(|note| note.change_tags(&add, remove))
+            })
+            .copied()
+            .collect()
+    }
+
     fn user_notes(&'a self, user: &User) -> Self::NoteIter {
         let userid = user.id();
         let res = self
@@ -94,7 +374,7 @@ impl<'a> Persister<'a> for InMemoryStorage {
         res.into_iter()
     }
 
-    fn tagged_notes(&'a self, tag: &Tag) -> <Self as Persister>::NoteIter {
+    fn tagged_notes(&'a self, tag: &Tag) -> <Self as Persister<'a>>::NoteIter {
         let res = self
             .notes
             .iter()
@@ -105,20 +385,605 @@ impl<'a> Persister<'a> for InMemoryStorage {
     }
 
     fn add_tag(&mut self, label: String) -> Id {
-        for existing_tag in &self.tags {
+        for existing_tag in self.tags.iter() {
             if existing_tag.label() == label {
                 return *existing_tag.id();
             }
         }
-        let id = Id(self.tags.len());
-        self.tags.push(Tag::new(id, label));
-        id
+        *self.tags.insert_with(|id| Tag::new(id, label)).id()
+    }
+
+    fn update_tag(&mut self, id: Id, meta: TagMeta) -> Option<&Tag> {
+        let tag = self.tags.get_mut(&id)?;
+        tag.set_meta(meta);
+        let tag = tag.clone();
+        let ids = self
+            .notes
+            .iter()
+            .filter(|note| note.tags().any(|existing| existing.id() == &id))
+            .map(|note| *note.id())
+            .collect::<Vec<Id>>();
+        for note in ids {
+            if let Some(note) = self.notes.get_mut(&note) {
+                note.replace_tag(&tag);
+            }
+        }
+        self.tags.get(&id)
+    }
+
+    fn query_notes(&'a self, user: &User, query: &NoteQuery) -> Self::NoteIter {
+        let sort = query.sort();
+        let mut res = self
+            .notes
+            .iter()
+            .filter(|note| note.user() == user.id())
+            .filter(|note| query.matches(note))
+            .collect::<Vec<&Note>>();
+        res.sort_by(|a, b| sort.compare(a, b));
+        res.into_iter()
+    }
+
+    fn add_search(&mut self, draft: SearchDraft, user: &User) -> &SavedSearch {
+        let (name, query) = draft.into_parts();
+        self.searches
+            .insert_with(|id| SavedSearch::new(id, name, *user.id(), query))
+    }
+
+    fn user_searches(&'a self, user: &User) -> Self::SearchIter {
+        let userid = user.id();
+        let res = self
+            .searches
+            .iter()
+            .filter(|search| search.user() == userid)
+            .collect::<Vec<&SavedSearch>>();
+        res.into_iter()
+    }
+
+    fn add_user(&mut self, name: String, identity: Option<ExternalIdentity>) -> &User {
+        self.users.insert_with(|id| match identity {
+            Some(identity) => User::new(id, name).with_identity(identity),
+            None => User::new(id, name),
+        })
+    }
+
+    fn schedule_user_deletion(&mut self, id: Id, purge_at: DateTime<Utc>) -> bool {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.schedule_deletion(purge_at);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_preferences(&mut self, id: Id, preferences: Preferences) -> bool {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.set_preferences(preferences);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_profile(&mut self, id: Id, profile: Profile) -> bool {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.set_profile(profile);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_tag_rules(&mut self, id: Id, rules: TagRules) -> bool {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.set_tag_rules(rules);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_templates(&mut self, id: Id, templates: Templates) -> bool {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.set_templates(templates);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_integrations(&mut self, id: Id, integrations: Integrations) -> bool {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.set_integrations(integrations);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn idempotency_record(&'a self, user: &User, key: &str) -> Option<&'a IdempotencyRecord> {
+        self.idempotency.get(&(*user.id(), key.to_string()))
+    }
+
+    fn add_idempotency_record(&mut self, record: IdempotencyRecord) {
+        self.idempotency
+            .insert((*record.user(), record.key().to_string()), record);
+    }
+
+    fn expire_idempotency_records(&mut self, before: &DateTime<Utc>) -> usize {
+        let count = self.idempotency.len();
+        self.idempotency
+            .retain(|_, record| record.created() >= before);
+        count - self.idempotency.len()
+    }
+
+    fn undo_token(&'a self, token: &str) -> Option<&'a UndoToken> {
+        self.undo_tokens.get(token)
+    }
+
+    fn add_undo_token(&mut self, token: UndoToken) {
+        self.undo_tokens.insert(token.token().to_string(), token);
+    }
+
+    fn remove_undo_token(&mut self, token: &str) -> Option<UndoToken> {
+        self.undo_tokens.remove(token)
+    }
+
+    fn expire_undo_tokens(&mut self, before: &DateTime<Utc>) -> usize {
+        let count = self.undo_tokens.len();
+        self.undo_tokens
+            .retain(|_, token| token.expires() >= before);
+        count - self.undo_tokens.len()
+    }
+
+    fn trash_confirmation(&'a self, token: &str) -> Option<&'a TrashConfirmation> {
+        self.trash_confirmations.get(token)
+    }
+
+    fn add_trash_confirmation(&mut self, confirmation: TrashConfirmation) {
+        let now = Utc::now();
+        self.trash_confirmations
+            .retain(|_, confirmation| confirmation.expires() > &now);
+        self.trash_confirmations
+            .insert(confirmation.token().to_string(), confirmation);
+    }
+
+    fn remove_trash_confirmation(&mut self, token: &str) -> Option<TrashConfirmation> {
+        self.trash_confirmations.remove(token)
+    }
+
+    fn working_copy(&'a self, note: &Id) -> Option<&'a WorkingCopy> {
+        self.working_copies.get(note)
+    }
+
+    fn set_working_copy(&mut self, copy: WorkingCopy) -> Option<WorkingCopy> {
+        self.working_copies.insert(*copy.note(), copy)
+    }
+
+    fn remove_working_copy(&mut self, note: &Id) -> Option<WorkingCopy> {
+        self.working_copies.remove(note)
+    }
+
+    fn expire_working_copies(&mut self, before: &DateTime<Utc>) -> usize {
+        let count = self.working_copies.len();
+        self.working_copies.retain(|_, copy| !copy.expired(before));
+        count - self.working_copies.len()
+    }
+
+    fn flags(&'a self) -> Vec<&'a Flag> {
+        self.flags.values().collect()
+    }
+
+    fn flag(&'a self, note: &Id) -> Option<&'a Flag> {
+        self.flags.get(note)
+    }
+
+    fn set_flag(&mut self, flag: Flag) -> Option<Flag> {
+        self.flags.insert(*flag.note(), flag)
+    }
+
+    fn remove_flag(&mut self, note: &Id) -> Option<Flag> {
+        self.flags.remove(note)
+    }
+
+    fn service_token(&'a self, secret: &str) -> Option<&'a ServiceToken> {
+        self.service_tokens
+            .iter()
+            .find(|token| token.secret() == secret)
+    }
+
+    fn service_tokens(&'a self, user: &Id) -> Vec<&'a ServiceToken> {
+        self.service_tokens
+            .iter()
+            .filter(|token| token.user() == user)
+            .collect()
+    }
+
+    fn add_service_token(
+        &mut self,
+        user: &User,
+        draft: TokenDraft,
+        secret: String,
+    ) -> &ServiceToken {
+        self.service_tokens
+            .insert_with(|id| ServiceToken::new(id, *user.id(), draft, secret))
+    }
+
+    fn revoke_service_token(&mut self, id: Id) -> bool {
+        self.service_tokens.remove(&id).is_some()
+    }
+
+    fn set_mentions(&mut self, note: Id, users: HashSet<Id>) -> Vec<Id> {
+        let previous = self.mentions.remove(&note).unwrap_or_default();
+        let mut added = users.difference(&previous).copied().collect::<Vec<Id>>();
+        added.sort_by_key(|id| usize::from(id));
+        if !users.is_empty() {
+            self.mentions.insert(note, users);
+        }
+        added
+    }
+
+    fn mentions(&'a self, user: &User) -> Vec<&'a Note> {
+        self.notes
+            .iter()
+            .filter(|note| {
+                self.mentions
+                    .get(note.id())
+                    .is_some_and(|users| users.contains(user.id()))
+            })
+            .collect()
+    }
+
+    fn followers(&'a self, user: &Id) -> Vec<&'a Follower> {
+        self.followers
+            .iter()
+            .filter(|follower| follower.user() == user)
+            .collect()
+    }
+
+    fn add_follower(&mut self, follower: Follower) {
+        self.remove_follower(follower.user(), follower.actor());
+        self.followers.push(follower);
+    }
+
+    fn remove_follower(&mut self, user: &Id, actor: &str) -> bool {
+        let count = self.followers.len();
+        self.followers
+            .retain(|follower| follower.user() != user || follower.actor() != actor);
+        count != self.followers.len()
+    }
+
+    fn access_log(&'a self, note: &Id) -> Vec<&'a AccessEntry> {
+        self.access_log
+            .get(note)
+            .map(|entries| entries.iter().collect())
+            .unwrap_or_default()
+    }
+
+    fn record_access(&mut self, entry: AccessEntry) {
+        let entries = self.access_log.entry(*entry.note()).or_default();
+        entries.push_back(entry);
+        while entries.len() > ACCESS_LOG_CAPACITY {
+            entries.pop_front();
+        }
+    }
+
+    fn audit_log(&'a self, user: &Id) -> Vec<&'a AuditEntry> {
+        self.audit_log
+            .get(user)
+            .map(|entries| entries.iter().collect())
+            .unwrap_or_default()
+    }
+
+    fn record_audit(&mut self, entry: AuditEntry) {
+        let entries = self.audit_log.entry(*entry.user()).or_default();
+        entries.push_back(entry);
+        while entries.len() > AUDIT_LOG_CAPACITY {
+            entries.pop_front();
+        }
+    }
+
+    fn usage(&'a self, user: &Id, from: NaiveDate, to: NaiveDate) -> Vec<DailyUsage> {
+        if from > to {
+            return vec![];
+        }
+        self.usage
+            .range((*user, from)..=(*user, to))
+            .map(|(_, usage)| usage.clone())
+            .collect()
+    }
+
+    fn record_usage(&mut self, user: Id, usage: DailyUsage) {
+        match self.usage.get_mut(&(user, *usage.date())) {
+            Some(stored) => stored.add(&usage),
+            None => {
+                self.usage.insert((user, *usage.date()), usage);
+            }
+        }
+    }
+
+    fn favorites(&'a self, user: &Id) -> Vec<&'a Note> {
+        self.favorites
+            .range((*user, Id(0))..=(*user, Id(usize::MAX)))
+            .filter_map(|(_, note)| self.note(*note))
+            .collect()
+    }
+
+    fn set_favorite(&mut self, user: Id, note: Id, favorite: bool) -> bool {
+        if favorite {
+            self.favorites.insert((user, note))
+        } else {
+            self.favorites.remove(&(user, note))
+        }
+    }
+
+    fn comment(&'a self, id: Id) -> Option<&'a Comment> {
+        self.comments.get(&id)
+    }
+
+    fn comments(&'a self, note: &Id) -> Vec<&'a Comment> {
+        self.comments
+            .iter()
+            .filter(|comment| comment.note() == note)
+            .collect()
+    }
+
+    fn user_comments(&'a self, user: &Id) -> Vec<&'a Comment> {
+        self.comments
+            .iter()
+            .filter(|comment| comment.author() == user)
+            .collect()
+    }
+
+    fn add_comment(&mut self, note: Id, draft: CommentDraft, author: &User) -> &Comment {
+        self.comments
+            .insert_with(|id| Comment::new(id, note, *author.id(), draft))
+    }
+
+    fn delete_comment(&mut self, id: Id) -> bool {
+        self.comments.remove(&id).is_some()
+    }
+
+    fn viewed_at(&self, user: &Id, note: &Id) -> Option<DateTime<Utc>> {
+        self.views.get(&(*user, *note)).copied()
+    }
+
+    fn set_viewed(&mut self, user: Id, note: Id, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.views.insert((user, note), at)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            compaction: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn stats(&'a self) -> StorageStats {
+        let notes = self.notes.iter();
+        let bytes = notes
+            .clone()
+            .map(|note| note.title().len() + note.body().len() + note.slug().map_or(0, str::len))
+            .chain(
+                self.tags
+                    .iter()
+                    .map(|tag| tag.label().len() + tag.description().map_or(0, str::len)),
+            )
+            .chain(self.comments.iter().map(|comment| comment.body().len()))
+            .chain(self.revisions.values().map(RevisionLog::bytes))
+            .sum();
+        let indexes = [
+            (
+                "revisions",
+                self.revisions.values().map(RevisionLog::len).sum(),
+            ),
+            ("views", self.views.len()),
+            ("mentions", self.mentions.values().map(HashSet::len).sum()),
+            ("favorites", self.favorites.len()),
+            ("idempotency_records", self.idempotency.len()),
+            ("undo_tokens", self.undo_tokens.len()),
+            ("usage", self.usage.len()),
+            (
+                "audit_log",
+                self.audit_log.values().map(VecDeque::len).sum(),
+            ),
+            ("service_tokens", self.service_tokens.len()),
+            ("working_copies", self.working_copies.len()),
+            ("flags", self.flags.len()),
+        ]
+        .into_iter()
+        .map(|(name, count)| (name.to_string(), count))
+        .collect();
+        StorageStats {
+            notes: self.notes.len(),
+            deleted_notes: notes
+                .filter(|note| note.visibility() == &Visibility::Deleted)
+                .count(),
+            tags: self.tags.len(),
+            users: self.users.len(),
+            bytes,
+            indexes,
+        }
+    }
+
+    /// Evicts deleted notes first and then the least recently used public notes, see
+    /// [`NoteLimitMode::Evict`]
+    fn reserve_notes(&mut self, count: usize) -> Option<Vec<Note>> {
+        let Some(limit) = self.note_limit else {
+            return Some(vec![]);
+        };
+        let excess = (self.notes.len() + count).saturating_sub(limit.max_notes);
+        if excess == 0 {
+            return Some(vec![]);
+        }
+        let candidates = self.eviction_candidates();
+        if limit.mode == NoteLimitMode::Reject || candidates.len() < excess {
+            return None;
+        }
+        let evicted = candidates[..excess]
+            .iter()
+            .filter_map(|id| self.notes.remove(id))
+            .collect::<Vec<Note>>();
+        let ids = evicted
+            .iter()
+            .map(|note| *note.id())
+            .collect::<HashSet<Id>>();
+        self.comments
+            .retain(|comment| !ids.contains(comment.note()));
+        self.undo_tokens
+            .retain(|_, token| !ids.contains(token.note()));
+        // removes the remaining data of the evicted notes
+        self.compact();
+        Some(evicted)
+    }
+
+    /// Removes data of notes that don't exist anymore and releases unused memory
+    fn compact(&mut self) {
+        let notes = self
+            .notes
+            .iter()
+            .map(|note| *note.id())
+            .collect::<HashSet<Id>>();
+        self.revisions.retain(|note, _| notes.contains(note));
+        self.mentions.retain(|note, _| notes.contains(note));
+        self.views.retain(|(_, note), _| notes.contains(note));
+        self.access_log.retain(|note, _| notes.contains(note));
+        self.favorites.retain(|(_, note)| notes.contains(note));
+        self.working_copies.retain(|note, _| notes.contains(note));
+        self.flags.retain(|note, _| notes.contains(note));
+        self.revisions.shrink_to_fit();
+        self.mentions.shrink_to_fit();
+        self.views.shrink_to_fit();
+        self.access_log.shrink_to_fit();
+        self.idempotency.shrink_to_fit();
+        self.undo_tokens.shrink_to_fit();
+        self.working_copies.shrink_to_fit();
+        self.followers.shrink_to_fit();
+    }
+
+    fn purge_user(&mut self, id: Id) -> bool {
+        if self.users.remove(&id).is_none() {
+            return false;
+        }
+        self.notes.retain(|note| note.user() != &id);
+        let notes = self
+            .notes
+            .iter()
+            .map(|note| *note.id())
+            .collect::<HashSet<Id>>();
+        self.mentions.retain(|note, users| {
+            users.remove(&id);
+            notes.contains(note) && !users.is_empty()
+        });
+        self.views
+            .retain(|(user, note), _| user != &id && notes.contains(note));
+        self.access_log.retain(|note, entries| {
+            entries.retain(|entry| entry.user() != Some(&id));
+            notes.contains(note) && !entries.is_empty()
+        });
+        self.revisions.retain(|note, _| notes.contains(note));
+        self.comments
+            .retain(|comment| comment.author() != &id && notes.contains(comment.note()));
+        self.favorites
+            .retain(|(user, note)| user != &id && notes.contains(note));
+        self.searches.retain(|search| search.user() != &id);
+        self.idempotency.retain(|(user, _), _| user != &id);
+        self.undo_tokens.retain(|_, token| token.user() != &id);
+        self.usage.retain(|(user, _), _| user != &id);
+        self.audit_log.remove(&id);
+        self.trash_confirmations
+            .retain(|_, confirmation| confirmation.user() != &id);
+        self.service_tokens.retain(|token| token.user() != &id);
+        self.working_copies
+            .retain(|note, copy| copy.user() != &id && notes.contains(note));
+        self.flags.retain(|note, _| notes.contains(note));
+        self.followers.retain(|follower| follower.user() != &id);
+        let used_tags = self
+            .notes
+            .iter()
+            .flat_map(|note| note.tags().map(|tag| *tag.id()))
+            .collect::<HashSet<Id>>();
+        self.tags.retain(|tag| used_tags.contains(tag.id()));
+        true
+    }
+
+    fn restore(&mut self, backup: &Backup, mode: RestoreMode, progress: &mut dyn FnMut(usize)) {
+        if mode == RestoreMode::Replace {
+            // the Ids of the removed rows are not re-used for new rows
+            self.notes.clear();
+            self.tags.clear();
+            self.searches.clear();
+            self.users.clear();
+            self.comments.clear();
+            self.service_tokens.clear();
+            self.idempotency.clear();
+            self.undo_tokens.clear();
+            self.usage.clear();
+            self.trash_confirmations.clear();
+            self.working_copies.clear();
+            self.flags.clear();
+            self.followers.clear();
+            self.mentions.clear();
+            self.favorites.clear();
+            self.views.clear();
+            self.access_log.clear();
+            self.audit_log.clear();
+            self.revisions.clear();
+        }
+        let mut restored = 0;
+        let mut step = || {
+            restored += 1;
+            progress(restored);
+        };
+        for user in backup.users() {
+            if !self.users.contains(user.id()) {
+                self.users.insert(*user.id(), user.clone());
+            }
+            step();
+        }
+        for tag in backup.tags() {
+            if !self.tags.contains(tag.id()) {
+                self.tags.insert(*tag.id(), tag.clone());
+            }
+            step();
+        }
+        for note in backup.notes() {
+            if !self.notes.contains(note.id()) {
+                self.notes.insert(*note.id(), note.clone());
+            }
+            step();
+        }
+        for search in backup.searches() {
+            if !self.searches.contains(search.id()) {
+                self.searches.insert(*search.id(), search.clone());
+            }
+            step();
+        }
+        for comment in backup.comments() {
+            if !self.comments.contains(comment.id()) {
+                self.comments.insert(*comment.id(), comment.clone());
+            }
+            step();
+        }
+        for follower in backup.followers() {
+            if !self.followers.iter().any(|existing| {
+                existing.user() == follower.user() && existing.actor() == follower.actor()
+            }) {
+                self.followers.push(follower.clone());
+            }
+            step();
+        }
+        for favorite in backup.favorites() {
+            self.favorites.insert(*favorite);
+            step();
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::models::geo::Location;
+    use crate::models::query::SortKey;
 
     #[test]
     fn add_notes() {
@@ -220,6 +1085,26 @@ mod test {
         assert_eq!(data.notes().len(), 2);
     }
 
+    #[test]
+    fn visibility_filters() {
+        let mut data = InMemoryStorage::default();
+
+        let _ = data.add_note(Draft::default(), &User::default());
+        let _ = data.add_note(Draft::default(), &User::default());
+        assert!(data.delete_note(Id(1)));
+
+        assert_eq!(data.notes_with(VisibilityFilter::Active).len(), 1);
+        assert_eq!(data.notes_with(VisibilityFilter::Deleted).len(), 1);
+        assert_eq!(data.notes_with(VisibilityFilter::All).len(), 2);
+
+        assert!(data.note(Id(0)).is_some());
+        assert!(data.note(Id(1)).is_none());
+        assert!(data.note_with(Id(0), VisibilityFilter::Deleted).is_none());
+        assert!(data.note_with(Id(1), VisibilityFilter::Deleted).is_some());
+        assert!(data.note_with(Id(1), VisibilityFilter::All).is_some());
+        assert!(data.note_with(Id(2), VisibilityFilter::All).is_none());
+    }
+
     #[test]
     fn tagged_notes() {
         let mut data = InMemoryStorage::default();
@@ -249,4 +1134,646 @@ mod test {
         assert_eq!(data.tagged_notes(data.tag("foo").unwrap()).len(), 2);
         assert_eq!(data.tagged_notes(data.tag("bar").unwrap()).len(), 1);
     }
+
+    #[test]
+    fn purge_user() {
+        let mut data = InMemoryStorage::default();
+        let other = User::new(Id(1), "other".to_string());
+
+        let _ = data.add_note(
+            Draft::new(
+                "Foo".to_string(),
+                "Foo".to_string(),
+                vec!["foo".to_string(), "bar".to_string()],
+                Visibility::Public,
+            ),
+            &User::default(),
+        );
+        let _ = data.add_note(
+            Draft::new(
+                "Foo".to_string(),
+                "Foo".to_string(),
+                vec!["foo".to_string()],
+                Visibility::Public,
+            ),
+            &other,
+        );
+        let _ = data.add_note(Draft::default(), &User::default());
+        data.delete_note(Id(2));
+        let _ = data.add_search(SearchDraft::default(), &User::default());
+        let _ = data.add_search(SearchDraft::default(), &other);
+
+        let export = data.export_user(&User::default());
+        assert_eq!(export.notes().len(), 2);
+        assert_eq!(export.searches().len(), 1);
+
+        assert!(data.schedule_user_deletion(Id(0), Utc::now()));
+        assert!(!data.user(Id(0)).unwrap().is_active());
+        assert!(!data.schedule_user_deletion(Id(666), Utc::now()));
+
+        assert!(data.purge_user(Id(0)));
+        assert!(data.user(Id(0)).is_none());
+        assert_eq!(data.notes.len(), 1);
+        assert_eq!(data.searches.len(), 1);
+        assert_eq!(data.tags.len(), 1);
+        assert!(data.tag("foo").is_some());
+        assert!(data.tag("bar").is_none());
+
+        // Ids are not re-used after purging
+        assert_eq!(data.add_note(Draft::default(), &other).id(), &Id(3));
+
+        assert!(!data.purge_user(Id(0)));
+    }
+
+    #[test]
+    fn users() {
+        let mut data = InMemoryStorage::default();
+        assert!(data.user(Id(0)).is_some());
+
+        let identity = ExternalIdentity::new("https://idp".to_string(), "123".to_string());
+        assert!(data.user_by_identity(&identity).is_none());
+
+        let user = data
+            .add_user("foo".to_string(), Some(identity.clone()))
+            .clone();
+        assert_eq!(user.id(), &Id(1));
+        assert_eq!(data.user_by_identity(&identity), Some(&user));
+        assert!(data
+            .user_by_identity(&ExternalIdentity::new(
+                "https://other-idp".to_string(),
+                "123".to_string()
+            ))
+            .is_none());
+
+        let preferences: Preferences = serde_json::from_str(r#"{"per_page": 20}"#).unwrap();
+        assert!(data.set_preferences(*user.id(), preferences.clone()));
+        assert_eq!(data.user(*user.id()).unwrap().preferences(), &preferences);
+        assert!(!data.set_preferences(Id(666), preferences));
+    }
+
+    #[test]
+    fn default_visibility() {
+        let mut data = InMemoryStorage::default();
+        let draft: Draft =
+            serde_json::from_str(r#"{"title": "", "body": "", "tags": []}"#).unwrap();
+
+        let note = data
+            .add_note(
+                draft.clone().with_default_visibility(&Visibility::Public),
+                &User::default(),
+            )
+            .clone();
+        assert_eq!(note.visibility(), &Visibility::Public);
+
+        // edits keep the visibility
+        let note = data.update_note(draft, *note.id());
+        assert_eq!(note.visibility(), &Visibility::Public);
+    }
+
+    #[test]
+    fn tag_stats() {
+        let mut data = InMemoryStorage::default();
+
+        let _ = data.add_note(
+            Draft::new(
+                "Foo".to_string(),
+                "Foo".to_string(),
+                vec!["foo".to_string(), "bar".to_string()],
+                Visibility::Public,
+            ),
+            &User::default(),
+        );
+        let last = data
+            .add_note(
+                Draft::new(
+                    "Foo".to_string(),
+                    "Foo".to_string(),
+                    vec!["foo".to_string()],
+                    Visibility::Public,
+                ),
+                &User::default(),
+            )
+            .updated()
+            .to_owned();
+        data.delete_note(Id(0));
+
+        let stats = data.tag_stats(&User::default());
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].tag().label(), "foo");
+        assert_eq!(stats[0].count(), 1);
+        assert_eq!(stats[0].last_used(), Some(&last));
+        assert_eq!(stats[1].tag().label(), "bar");
+        assert_eq!(stats[1].count(), 0);
+        assert!(stats[1].last_used().is_none());
+    }
+
+    #[test]
+    fn query_notes() {
+        let mut data = InMemoryStorage::default();
+
+        let _ = data.add_note(
+            Draft::new(
+                "Zoo".to_string(),
+                "Foo".to_string(),
+                vec!["foo".to_string()],
+                Visibility::Public,
+            ),
+            &User::default(),
+        );
+        let _ = data.add_note(
+            Draft::new(
+                "Bar".to_string(),
+                "Foo".to_string(),
+                vec!["foo".to_string(), "bar".to_string()],
+                Visibility::Private,
+            ),
+            &User::default(),
+        );
+        let _ = data.add_note(Draft::default(), &User::default());
+
+        let query = NoteQuery::new(
+            Some("foo".to_string()),
+            None,
+            None,
+            Some(SortKey::Title.into()),
+        );
+        let res = data
+            .query_notes(&User::default(), &query)
+            .collect::<Vec<&Note>>();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].title(), "Bar");
+        assert_eq!(res[1].title(), "Zoo");
+
+        let query = NoteQuery::new(None, None, Some(Visibility::Public), None);
+        assert_eq!(data.query_notes(&User::default(), &query).len(), 1);
+
+        assert_eq!(
+            data.query_notes(&User::default(), &NoteQuery::default())
+                .len(),
+            3
+        );
+
+        data.delete_note(Id(0));
+        assert_eq!(
+            data.query_notes(&User::default(), &NoteQuery::default())
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn mentions() {
+        let mut data = InMemoryStorage::default();
+        let bob = data.add_user("bob".to_string(), None).clone();
+        let _ = data.add_note(Draft::default(), &User::default());
+        let _ = data.add_note(Draft::default(), &bob);
+
+        assert_eq!(
+            data.set_mentions(Id(0), HashSet::from([Id(1)])),
+            vec![Id(1)]
+        );
+        assert_eq!(
+            data.set_mentions(Id(1), HashSet::from([Id(0), Id(1)])),
+            vec![Id(0), Id(1)]
+        );
+        assert!(data.set_mentions(Id(1), HashSet::from([Id(0)])).is_empty());
+        assert_eq!(data.mentions(&bob).len(), 1);
+        assert_eq!(data.mentions(&User::default())[0].id(), &Id(1));
+
+        assert!(data.set_mentions(Id(0), HashSet::new()).is_empty());
+        assert!(data.mentions(&bob).is_empty());
+        assert_eq!(
+            data.set_mentions(Id(0), HashSet::from([Id(1)])),
+            vec![Id(1)]
+        );
+
+        // purging bob removes the mentions of bob and in the notes of bob
+        assert!(data.purge_user(*bob.id()));
+        assert!(data.mentions.is_empty());
+    }
+
+    #[test]
+    fn followers() {
+        let mut data = InMemoryStorage::default();
+        let follower =
+            |actor: &str, inbox: &str| Follower::new(Id(0), actor.to_string(), inbox.to_string());
+        data.add_follower(follower(
+            "https://a.example/users/x",
+            "https://a.example/inbox",
+        ));
+        data.add_follower(follower(
+            "https://b.example/users/y",
+            "https://b.example/inbox",
+        ));
+        data.add_follower(follower(
+            "https://a.example/users/x",
+            "https://a.example/users/x/inbox",
+        ));
+
+        let followers = data.followers(&Id(0));
+        assert_eq!(followers.len(), 2);
+        assert!(followers.contains(&&follower(
+            "https://a.example/users/x",
+            "https://a.example/users/x/inbox"
+        )));
+        assert!(data.followers(&Id(1)).is_empty());
+
+        assert!(data.remove_follower(&Id(0), "https://b.example/users/y"));
+        assert!(!data.remove_follower(&Id(0), "https://b.example/users/y"));
+        assert!(!data.remove_follower(&Id(1), "https://a.example/users/x"));
+        assert_eq!(data.followers(&Id(0)).len(), 1);
+
+        assert!(data.purge_user(Id(0)));
+        assert!(data.followers(&Id(0)).is_empty());
+    }
+
+    #[test]
+    fn undo_delete() {
+        let mut data = InMemoryStorage::default();
+        let _ = data.add_note(Draft::default(), &User::default());
+        assert!(!data.restore_note(Id(0), Visibility::Public));
+
+        data.delete_note(Id(0));
+        let token = UndoToken::new(Id(0), Id(0), Visibility::Public, Utc::now());
+        data.add_undo_token(token.clone());
+        assert_eq!(data.undo_token(token.token()), Some(&token));
+
+        assert!(data.restore_note(Id(0), Visibility::Public));
+        assert_eq!(data.note(Id(0)).unwrap().visibility(), &Visibility::Public);
+        assert_eq!(data.remove_undo_token(token.token()), Some(token.clone()));
+        assert!(data.undo_token(token.token()).is_none());
+
+        data.add_undo_token(token.clone());
+        assert_eq!(data.expire_undo_tokens(token.expires()), 0);
+        assert_eq!(
+            data.expire_undo_tokens(&(*token.expires() + chrono::Duration::seconds(1))),
+            1
+        );
+        assert!(data.undo_token(token.token()).is_none());
+    }
+
+    #[test]
+    fn slugs() {
+        let mut data = InMemoryStorage::default();
+        let draft =
+            |title: &str| Draft::new(title.to_string(), String::new(), vec![], Visibility::Public);
+        let slug = |data: &mut InMemoryStorage, title: &str| {
+            data.add_note(draft(title), &User::default())
+                .slug()
+                .map(str::to_string)
+        };
+
+        assert_eq!(slug(&mut data, "My note"), Some("my-note".to_string()));
+        assert_eq!(slug(&mut data, "My Note!"), Some("my-note-2".to_string()));
+        assert_eq!(
+            slug(&mut data, "my note 2"),
+            Some("my-note-2-2".to_string())
+        );
+        assert_eq!(slug(&mut data, "???"), None);
+
+        // slugs of deleted notes are not re-used
+        data.delete_note(Id(0));
+        assert!(data.note_by_slug("my-note").is_none());
+        assert_eq!(slug(&mut data, "My note"), Some("my-note-3".to_string()));
+
+        assert_eq!(data.note_by_slug("my-note-2").unwrap().id(), &Id(1));
+
+        // the slug is stable when the title changes
+        let note = data.update_note(draft("Other"), Id(1));
+        assert_eq!(note.slug(), Some("my-note-2"));
+    }
+
+    #[test]
+    fn update_tag() {
+        let mut data = InMemoryStorage::default();
+        let id = *data
+            .add_note(
+                Draft::new(
+                    "Foo".to_string(),
+                    "Foo".to_string(),
+                    vec!["foo".to_string()],
+                    Visibility::Public,
+                ),
+                &User::default(),
+            )
+            .id();
+        let tag = *data.tag("foo").unwrap().id();
+        let updated = *data.note(id).unwrap().updated();
+
+        let meta = TagMeta::new(Some("#ff0000".to_string()), Some("Foo things".to_string()));
+        assert_eq!(data.update_tag(tag, meta).unwrap().color(), Some("#ff0000"));
+        let note = data.note(id).unwrap();
+        let copy = note.tags().next().unwrap();
+        assert_eq!(copy.description(), Some("Foo things"));
+        assert!(note.tagged_with(data.tag("foo").unwrap()));
+        assert_eq!(note.updated(), &updated);
+        assert!(data.update_tag(Id(99), TagMeta::default()).is_none());
+    }
+
+    #[test]
+    fn bulk_tag() {
+        let mut data = InMemoryStorage::default();
+        let _ = data.add_note(
+            Draft::new(
+                "Foo".to_string(),
+                "Foo".to_string(),
+                vec!["foo".to_string(), "bar".to_string()],
+                Visibility::Public,
+            ),
+            &User::default(),
+        );
+        let _ = data.add_note(Draft::default(), &User::default());
+
+        let labels = |data: &InMemoryStorage, id: Id| {
+            let mut labels = data
+                .note(id)
+                .unwrap()
+                .tags()
+                .map(|tag| tag.label().to_string())
+                .collect::<Vec<String>>();
+            labels.sort();
+            labels
+        };
+
+        let changed = data.bulk_tag(
+            &[Id(0), Id(1), Id(666)],
+            &["baz".to_string()],
+            &["foo".to_string()],
+        );
+        assert_eq!(changed, vec![Id(0), Id(1)]);
+        assert_eq!(labels(&data, Id(0)), vec!["bar", "baz"]);
+        assert_eq!(labels(&data, Id(1)), vec!["baz"]);
+        assert_eq!(data.tags().len(), 3);
+
+        // nothing changes if the tags are already applied
+        let changed = data.bulk_tag(&[Id(0), Id(1)], &["baz".to_string()], &[]);
+        assert!(changed.is_empty());
+    }
+
+    #[test]
+    fn notes_near() {
+        let mut data = InMemoryStorage::default();
+        let user = User::default();
+        let berlin = Location::new(52.5200, 13.4050).unwrap();
+
+        let _ = data.add_note(
+            Draft::default().with_location(Location::new(52.5163, 13.3777).unwrap()),
+            &user,
+        );
+        let _ = data.add_note(Draft::default().with_location(berlin), &user);
+        let _ = data.add_note(
+            Draft::default().with_location(Location::new(48.8566, 2.3522).unwrap()),
+            &user,
+        );
+        let _ = data.add_note(Draft::default(), &user);
+        let _ = data.add_note(
+            Draft::default().with_location(berlin),
+            &User::new(Id(1), "other".to_string()),
+        );
+
+        let ids = |notes: Vec<&Note>| notes.iter().map(|note| *note.id()).collect::<Vec<Id>>();
+        assert_eq!(ids(data.notes_near(&user, &berlin, 0.0)), vec![Id(1)]);
+        assert_eq!(
+            ids(data.notes_near(&user, &berlin, 5_000.0)),
+            vec![Id(1), Id(0)]
+        );
+        assert_eq!(
+            ids(data.notes_near(&user, &berlin, 1_000_000.0)),
+            vec![Id(1), Id(0), Id(2)]
+        );
+
+        data.delete_note(Id(1));
+        assert_eq!(ids(data.notes_near(&user, &berlin, 5_000.0)), vec![Id(0)]);
+    }
+
+    #[test]
+    fn saved_searches() {
+        let mut data = InMemoryStorage::default();
+
+        let search = data
+            .add_search(
+                SearchDraft::new(
+                    "Todos".to_string(),
+                    NoteQuery::new(Some("todo".to_string()), None, None, None),
+                ),
+                &User::default(),
+            )
+            .clone();
+        assert_eq!(search.id(), &Id(0));
+        assert_eq!(search.name(), "Todos");
+        assert_eq!(data.user_searches(&User::default()).len(), 1);
+        assert_eq!(data.search(Id(0)), Some(&search));
+        assert!(data.search(Id(1)).is_none());
+    }
+
+    #[test]
+    fn comments() {
+        let mut data = InMemoryStorage::default();
+        let user = User::default();
+        let alice = data.add_user("alice".to_string(), None).clone();
+        let _ = data.add_note(Draft::default(), &user);
+        let _ = data.add_note(Draft::default(), &alice);
+
+        let first = data
+            .add_comment(Id(0), CommentDraft::new("First".to_string()), &alice)
+            .clone();
+        let _ = data.add_comment(Id(0), CommentDraft::new("Second".to_string()), &user);
+        let _ = data.add_comment(Id(1), CommentDraft::new("Own".to_string()), &alice);
+        assert_eq!(first.author(), alice.id());
+        assert_eq!(data.comment(*first.id()), Some(&first));
+        let bodies = data
+            .comments(&Id(0))
+            .iter()
+            .map(|comment| comment.body())
+            .collect::<Vec<&str>>();
+        assert_eq!(bodies, vec!["First", "Second"]);
+        assert_eq!(data.user_comments(alice.id()).len(), 2);
+
+        assert!(data.delete_comment(*first.id()));
+        assert!(!data.delete_comment(*first.id()));
+        assert_eq!(data.comments(&Id(0)).len(), 1);
+
+        // purging removes the comments of the user and all comments on their notes
+        let _ = data.add_comment(Id(1), CommentDraft::new("Reply".to_string()), &user);
+        assert!(data.purge_user(*alice.id()));
+        assert_eq!(data.comments.len(), 1);
+        assert!(data.user_comments(alice.id()).is_empty());
+        assert!(data.comments(&Id(1)).is_empty());
+    }
+
+    #[test]
+    fn note_limit() {
+        let user = User::default();
+        let public = || Draft::new(String::new(), String::new(), vec![], Visibility::Public);
+        let limit = |mode| Some(NoteLimit { max_notes: 3, mode });
+
+        let mut data = InMemoryStorage::default().with_note_limit(limit(NoteLimitMode::Reject));
+        for _ in 0..3 {
+            let _ = data.add_note(public(), &user);
+        }
+        assert_eq!(data.reserve_notes(0), Some(vec![]));
+        assert!(data.reserve_notes(1).is_none());
+
+        let mut data = InMemoryStorage::default().with_note_limit(limit(NoteLimitMode::Evict));
+        let viewed = *data.add_note(public(), &user).id();
+        let _ = data.add_note(Draft::default(), &user);
+        let unused = data.add_note(public(), &user).clone();
+        let _ = data.add_comment(
+            *unused.id(),
+            CommentDraft::new("Comment".to_string()),
+            &user,
+        );
+        data.set_viewed(*user.id(), viewed, Utc::now() + chrono::TimeDelta::hours(1));
+        assert_eq!(data.reserve_notes(1), Some(vec![unused.clone()]));
+        assert!(data.note(*unused.id()).is_none());
+        assert_eq!(data.comments.len(), 0);
+        // only public and deleted notes are evicted
+        assert!(data.reserve_notes(3).is_none());
+        assert_eq!(data.notes.len(), 2);
+
+        // deleted notes are evicted first, even private ones that were used more recently
+        let private = *data.add_note(Draft::default(), &user).id();
+        data.delete_note(private);
+        let evicted = data.reserve_notes(1).unwrap();
+        assert_eq!(
+            evicted.iter().map(|note| *note.id()).collect::<Vec<Id>>(),
+            vec![private]
+        );
+        assert!(data.note(viewed).is_some());
+    }
+
+    #[test]
+    fn favorites() {
+        let mut data = InMemoryStorage::default();
+        let user = User::default();
+        let alice = data.add_user("alice".to_string(), None).clone();
+        for _ in 0..3 {
+            let _ = data.add_note(Draft::default(), &user);
+        }
+        let _ = data.add_note(Draft::default(), &alice);
+        let ids = |notes: Vec<&Note>| notes.iter().map(|note| *note.id()).collect::<Vec<Id>>();
+
+        assert!(data.set_favorite(*user.id(), Id(2), true));
+        assert!(data.set_favorite(*user.id(), Id(0), true));
+        assert!(!data.set_favorite(*user.id(), Id(0), true));
+        assert!(data.set_favorite(*alice.id(), Id(1), true));
+        assert_eq!(ids(data.favorites(user.id())), vec![Id(0), Id(2)]);
+        assert_eq!(ids(data.favorites(alice.id())), vec![Id(1)]);
+
+        // deleted notes are hidden, but stay favorites
+        data.delete_note(Id(2));
+        assert_eq!(ids(data.favorites(user.id())), vec![Id(0)]);
+        data.restore_note(Id(2), Visibility::Private);
+        assert_eq!(ids(data.favorites(user.id())), vec![Id(0), Id(2)]);
+
+        assert!(data.set_favorite(*user.id(), Id(0), false));
+        assert!(!data.set_favorite(*user.id(), Id(0), false));
+        assert_eq!(ids(data.favorites(user.id())), vec![Id(2)]);
+
+        assert!(data.purge_user(*alice.id()));
+        assert_eq!(data.favorites.len(), 1);
+    }
+
+    #[test]
+    fn views() {
+        let mut data = InMemoryStorage::default();
+        let user = User::default();
+        let _ = data.add_note(Draft::default(), &user);
+        let alice = data.add_user("alice".to_string(), None).clone();
+
+        let first = Utc::now();
+        let second = first + chrono::TimeDelta::seconds(1);
+        assert!(data.viewed_at(user.id(), &Id(0)).is_none());
+        assert!(data.set_viewed(*user.id(), Id(0), first).is_none());
+        assert_eq!(data.set_viewed(*user.id(), Id(0), second), Some(first));
+        assert_eq!(data.set_viewed(*alice.id(), Id(0), first), None);
+        assert_eq!(data.viewed_at(user.id(), &Id(0)), Some(second));
+        assert_eq!(data.viewed_at(alice.id(), &Id(0)), Some(first));
+
+        assert!(data.purge_user(*alice.id()));
+        assert!(data.viewed_at(alice.id(), &Id(0)).is_none());
+        assert_eq!(data.views.len(), 1);
+    }
+
+    #[test]
+    fn stats_and_compact() {
+        let mut data = InMemoryStorage::default();
+        let user = User::default();
+        let draft = |body: &str| {
+            Draft::new(
+                "Title".to_string(),
+                body.to_string(),
+                vec!["foo".to_string()],
+                Visibility::Private,
+            )
+        };
+        let _ = data.add_note(draft("first"), &user);
+        let _ = data.add_note(draft("second"), &user);
+        let _ = data.update_note(draft("second, edited"), Id(1));
+        data.delete_note(Id(0));
+        data.set_viewed(*user.id(), Id(7), Utc::now());
+
+        let stats = data.stats();
+        assert_eq!(stats.notes, 2);
+        assert_eq!(stats.deleted_notes, 1);
+        assert_eq!(stats.tags, 1);
+        assert_eq!(stats.users, 1);
+        assert!(stats.bytes > "Titlefirst".len() + "Titlesecond, edited".len());
+        assert_eq!(stats.indexes["revisions"], 3);
+        assert_eq!(stats.indexes["views"], 1);
+
+        assert!(data.capabilities().compaction);
+        data.compact();
+        let compacted = data.stats();
+        assert_eq!(compacted.indexes["views"], 0);
+        assert_eq!(compacted.notes, 2);
+        assert_eq!(compacted.bytes, stats.bytes);
+    }
+
+    #[test]
+    fn collect_tags() {
+        let mut data = InMemoryStorage::default();
+        let user = User::default();
+        let tags = |labels: &[&str]| labels.iter().map(|label| label.to_string()).collect();
+
+        let _ = data.add_note(
+            Draft::new(
+                "".to_string(),
+                "".to_string(),
+                tags(&["foo", "bar"]),
+                Visibility::Private,
+            ),
+            &user,
+        );
+        let _ = data.add_note(
+            Draft::new(
+                "".to_string(),
+                "".to_string(),
+                tags(&["foo", "baz"]),
+                Visibility::Private,
+            ),
+            &user,
+        );
+        assert_eq!(data.collect_tags(), 0);
+
+        data.bulk_tag(&[Id(0)], &[], &["bar".to_string()]);
+        data.delete_note(Id(1));
+        assert_eq!(data.tags.len(), 3);
+        assert_eq!(data.collect_tags(), 2);
+        assert!(data.tag("foo").is_some());
+        assert!(data.tag("bar").is_none());
+        assert!(data.tag("baz").is_none());
+
+        // restoring the note adds its tags again
+        assert!(data.restore_note(Id(1), Visibility::Private));
+        let baz = data.tag("baz").unwrap();
+        assert!(data.note(Id(1)).unwrap().tagged_with(baz));
+        assert_eq!(data.tagged_notes(baz).len(), 1);
+        assert_eq!(data.collect_tags(), 0);
+    }
+}
+
+#[cfg(test)]
+mod testsuite {
+    crate::persister_testsuite!(super::InMemoryStorage::default);
 }
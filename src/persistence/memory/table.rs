@@ -0,0 +1,102 @@
+//! A minimal table for the [`InMemoryStorage`](super::InMemoryStorage)
+use std::collections::btree_map::{self, BTreeMap};
+
+use crate::models::Id;
+
+/// Stores rows by their primary key
+///
+/// Ids are assigned incrementally and are never re-used, even after a row is
+/// removed, so that stale references can't point to a different row.
+#[derive(Debug)]
+pub struct Table<T> {
+    rows: BTreeMap<Id, T>,
+    next_id: usize,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self {
+            rows: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl<T> Table<T> {
+    /// Inserts a new row, created by `row` from the next free [`Id`]
+    pub fn insert_with<F: FnOnce(Id) -> T>(&mut self, row: F) -> &mut T {
+        let id = Id(self.next_id);
+        self.next_id = self.next_id.checked_add(1).expect("all Ids are used");
+        self.rows.entry(id).or_insert(row(id))
+    }
+
+    /// Inserts the row with a given [`Id`], e.g. from a backup
+    ///
+    /// Replaces an existing row with the same Id. Later rows get higher Ids, so the Id
+    /// must be below `usize::MAX`, see [`Backup::validate`](crate::models::backup::Backup::validate).
+    pub fn insert(&mut self, id: Id, row: T) {
+        let next = usize::from(id).checked_add(1).expect("Id is out of range");
+        self.next_id = self.next_id.max(next);
+        self.rows.insert(id, row);
+    }
+
+    /// Removes all rows, without re-using their Ids
+    pub fn clear(&mut self) {
+        self.rows.clear();
+    }
+
+    pub fn contains(&self, id: &Id) -> bool {
+        self.rows.contains_key(id)
+    }
+
+    pub fn get(&self, id: &Id) -> Option<&T> {
+        self.rows.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &Id) -> Option<&mut T> {
+        self.rows.get_mut(id)
+    }
+
+    pub fn remove(&mut self, id: &Id) -> Option<T> {
+        self.rows.remove(id)
+    }
+
+    /// Keeps only the rows for which `keep` returns `true`
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut keep: F) {
+        self.rows.retain(|_, row| keep(row))
+    }
+
+    /// Iterates all rows, ordered by their [`Id`]
+    pub fn iter(&self) -> btree_map::Values<'_, Id, T> {
+        self.rows.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_not_reused() {
+        let mut table = Table::default();
+        assert_eq!(*table.insert_with(|id| id), Id(0));
+        assert_eq!(*table.insert_with(|id| id), Id(1));
+        assert_eq!(table.len(), 2);
+
+        assert_eq!(table.remove(&Id(1)), Some(Id(1)));
+        assert!(table.get(&Id(1)).is_none());
+        assert_eq!(*table.insert_with(|id| id), Id(2));
+
+        table.retain(|id| id != &Id(0));
+        assert_eq!(table.iter().collect::<Vec<&Id>>(), vec![&Id(2)]);
+
+        table.clear();
+        table.insert(Id(5), Id(5));
+        assert!(table.contains(&Id(5)));
+        assert_eq!(*table.insert_with(|id| id), Id(6));
+    }
+}
@@ -0,0 +1,152 @@
+//! Copies all data from one backend to another, e.g. to switch to a new storage backend
+//!
+//! The data is read with [`Persister::backup`] and written with [`Persister::restore`] in
+//! [`RestoreMode::Merge`], a batch of [`BATCH_SIZE`] notes at a time. Records that the target
+//! has already are skipped, so an interrupted migration is resumed by running it again.
+//! Afterwards the notes, tags and users of both backends are compared.
+//!
+//! The binary of a backend crate calls [`migrate`] with the old and the new backend, the same
+//! way it passes its backend to [`app::router`](crate::app::router).
+use std::collections::HashSet;
+
+use tracing::info;
+
+use crate::models::backup::{Backup, RestoreMode};
+use crate::models::{Id, VisibilityFilter};
+use crate::persistence::replicated::{compare, Divergence};
+use crate::persistence::Persister;
+
+/// The number of notes that are written to the target at once
+pub const BATCH_SIZE: usize = 500;
+
+/// Copies all users, tags, notes, saved searches, comments, followers and favorites of `source`
+/// into `target`, then returns the data that still differs between both backends
+///
+/// Like archives, the migration does not copy revisions, sessions, service tokens, views,
+/// undo tokens and working copies. `progress` is called with the number of migrated notes
+/// after every batch. Nothing is copied if the target has conflicting records with the same
+/// Ids, or no room for the missing notes.
+pub fn migrate<S, T>(
+    source: &S,
+    target: &mut T,
+    progress: &mut dyn FnMut(usize),
+) -> Result<Divergence, Vec<String>>
+where
+    S: for<'a> Persister<'a>,
+    T: for<'a> Persister<'a>,
+{
+    let backup = source.backup();
+    let errors = backup.validate();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let conflicts = target.merge_conflicts(&backup);
+    if !conflicts.is_empty() {
+        return Err(conflicts);
+    }
+    let missing = backup
+        .notes()
+        .iter()
+        .filter(|note| {
+            target
+                .note_with(*note.id(), VisibilityFilter::All)
+                .is_none()
+        })
+        .count();
+    if target.reserve_notes(missing).is_none() {
+        return Err(vec![format!("The target can't store {missing} more notes")]);
+    }
+    info!(
+        "Migrating {} notes, {} are missing in the target",
+        backup.notes().len(),
+        missing
+    );
+
+    // notes reference users and tags, so they are copied first
+    let accounts = Backup::new(
+        backup.users().to_vec(),
+        vec![],
+        backup.tags().to_vec(),
+        backup.searches().to_vec(),
+        vec![],
+        backup.followers().to_vec(),
+        vec![],
+    );
+    target.restore(&accounts, RestoreMode::Merge, &mut |_| {});
+
+    let mut migrated = 0;
+    for notes in backup.notes().chunks(BATCH_SIZE) {
+        let ids = notes.iter().map(|note| *note.id()).collect::<HashSet<Id>>();
+        let batch = Backup::new(
+            vec![],
+            notes.to_vec(),
+            vec![],
+            vec![],
+            backup
+                .comments()
+                .iter()
+                .filter(|comment| ids.contains(comment.note()))
+                .cloned()
+                .collect(),
+            vec![],
+            backup
+                .favorites()
+                .iter()
+                .filter(|(_, note)| ids.contains(note))
+                .copied()
+                .collect(),
+        );
+        target.restore(&batch, RestoreMode::Merge, &mut |_| {});
+        migrated += notes.len();
+        progress(migrated);
+    }
+
+    let divergence = compare(source, target);
+    info!("Migration finished, divergence: {:?}", divergence);
+    Ok(divergence)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::Draft;
+    use crate::models::Visibility;
+    use crate::persistence::memory::InMemoryStorage;
+
+    fn draft(title: &str) -> Draft {
+        Draft::new(
+            title.to_string(),
+            String::new(),
+            vec!["migrated".to_string()],
+            Visibility::Private,
+        )
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut source = InMemoryStorage::default();
+        let user = source.add_user("alice".to_string(), None).clone();
+        for index in 0..BATCH_SIZE + 2 {
+            source.add_note(draft(&format!("Note {index}")), &user);
+        }
+        source.delete_note(Id(1));
+
+        let mut target = InMemoryStorage::default();
+        let mut batches = Vec::new();
+        let divergence = migrate(&source, &mut target, &mut |notes| batches.push(notes)).unwrap();
+        assert!(divergence.is_empty());
+        assert_eq!(batches, vec![BATCH_SIZE, BATCH_SIZE + 2]);
+        assert!(target.note_with(Id(1), VisibilityFilter::Deleted).is_some());
+        assert_eq!(target.user_notes(&user).count(), BATCH_SIZE + 1);
+
+        // a second run only copies what is missing
+        source.add_note(draft("Later"), &user);
+        assert!(migrate(&source, &mut target, &mut |_| {})
+            .unwrap()
+            .is_empty());
+
+        target.update_note(draft("Changed"), Id(0));
+        let conflicts = migrate(&source, &mut target, &mut |_| {}).unwrap_err();
+        assert_eq!(conflicts.len(), 1);
+    }
+}
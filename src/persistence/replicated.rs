@@ -0,0 +1,1041 @@
+//! A [`Persister`] that mirrors all changes of a primary backend to a second backend
+//!
+//! [`ReplicatedPersister`] reads from and writes to the primary backend only. Every
+//! mutation is also queued as [`Mutation`] for a background thread, which applies
+//! the mutations to the mirror in the same order. Writes don't wait for the mirror,
+//! so the mirror can lag behind the primary until [`ReplicatedPersister::catch_up`]
+//! returns.
+//!
+//! Both backends must start with the same data, e.g. both fresh, and assign Ids in
+//! the same way. Timestamps are taken independently by each backend and are not
+//! compared. [`ReplicatedPersister::divergence`] reports the data that differs
+//! between both backends and [`ReplicatedPersister::repair`] copies notes that exist
+//! in both backends from the primary to the mirror.
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use crate::models::access::AccessEntry;
+use crate::models::audit::AuditEntry;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::models::autosave::WorkingCopy;
+use crate::models::backup::{Backup, RestoreMode};
+use crate::models::body::Body;
+use crate::models::comment::{Comment, CommentDraft};
+use crate::models::follower::Follower;
+use crate::models::idempotency::IdempotencyRecord;
+use crate::models::integration::Integrations;
+use crate::models::moderation::Flag;
+use crate::models::note::{Draft, Note};
+use crate::models::preferences::Preferences;
+use crate::models::profile::Profile;
+use crate::models::query::{NoteQuery, SavedSearch, SearchDraft};
+use crate::models::tag_rule::TagRules;
+use crate::models::template::Templates;
+use crate::models::token::{ServiceToken, TokenDraft};
+use crate::models::trash::TrashConfirmation;
+use crate::models::undo::UndoToken;
+use crate::models::usage::DailyUsage;
+use crate::models::{ExternalIdentity, Id, Tag, TagMeta, User, Visibility, VisibilityFilter};
+use crate::persistence::{Capabilities, Health, Persister, StorageStats};
+use crate::revisions::{Revision, RevisionInfo};
+
+/// A change of the data, with all arguments of the [`Persister`] method that made it
+#[derive(Clone, Debug)]
+pub enum Mutation {
+    AddNote(Draft, User),
+    AddNotesBulk(Vec<Draft>, User),
+    UpdateNote(Draft, Id),
+    DeleteNote(Id),
+    MergeNotes(Id, Vec<Id>),
+    SetLocked(Id, bool),
+    SetArchived(Id, bool),
+    RestoreNote(Id, Visibility),
+    CollectTags,
+    BulkTag(Vec<Id>, Vec<String>, Vec<String>),
+    AddTag(String),
+    UpdateTag(Id, TagMeta),
+    AddSearch(SearchDraft, User),
+    AddUser(String, Option<ExternalIdentity>),
+    ScheduleUserDeletion(Id, DateTime<Utc>),
+    SetPreferences(Id, Preferences),
+    SetProfile(Id, Profile),
+    SetTagRules(Id, TagRules),
+    SetTemplates(Id, Templates),
+    SetIntegrations(Id, Integrations),
+    PurgeNotes(Vec<Id>),
+    RecordUsage(Id, DailyUsage),
+    AddTrashConfirmation(TrashConfirmation),
+    RemoveTrashConfirmation(String),
+    AddIdempotencyRecord(IdempotencyRecord),
+    ExpireIdempotencyRecords(DateTime<Utc>),
+    SetViewed(Id, Id, DateTime<Utc>),
+    AddUndoToken(UndoToken),
+    RemoveUndoToken(String),
+    ExpireUndoTokens(DateTime<Utc>),
+    SetWorkingCopy(WorkingCopy),
+    RemoveWorkingCopy(Id),
+    ExpireWorkingCopies(DateTime<Utc>),
+    SetFlag(Flag),
+    RemoveFlag(Id),
+    AddServiceToken(User, TokenDraft, String),
+    RevokeServiceToken(Id),
+    SetMentions(Id, HashSet<Id>),
+    SetFavorite(Id, Id, bool),
+    AddComment(Id, CommentDraft, User),
+    DeleteComment(Id),
+    AddFollower(Follower),
+    RemoveFollower(Id, String),
+    RecordAccess(AccessEntry),
+    RecordAudit(AuditEntry),
+    PurgeUser(Id),
+    Restore(Box<Backup>, RestoreMode),
+    Compact,
+    ReserveNotes(usize),
+}
+
+impl Mutation {
+    /// Returns the name of the kind of change, without its arguments, which may contain secrets
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mutation::AddNote(..) => "AddNote",
+            Mutation::AddNotesBulk(..) => "AddNotesBulk",
+            Mutation::UpdateNote(..) => "UpdateNote",
+            Mutation::DeleteNote(..) => "DeleteNote",
+            Mutation::MergeNotes(..) => "MergeNotes",
+            Mutation::SetLocked(..) => "SetLocked",
+            Mutation::SetArchived(..) => "SetArchived",
+            Mutation::RestoreNote(..) => "RestoreNote",
+            Mutation::CollectTags => "CollectTags",
+            Mutation::BulkTag(..) => "BulkTag",
+            Mutation::AddTag(..) => "AddTag",
+            Mutation::UpdateTag(..) => "UpdateTag",
+            Mutation::AddSearch(..) => "AddSearch",
+            Mutation::AddUser(..) => "AddUser",
+            Mutation::ScheduleUserDeletion(..) => "ScheduleUserDeletion",
+            Mutation::SetPreferences(..) => "SetPreferences",
+            Mutation::SetProfile(..) => "SetProfile",
+            Mutation::SetTagRules(..) => "SetTagRules",
+            Mutation::SetTemplates(..) => "SetTemplates",
+            Mutation::SetIntegrations(..) => "SetIntegrations",
+            Mutation::PurgeNotes(..) => "PurgeNotes",
+            Mutation::RecordUsage(..) => "RecordUsage",
+            Mutation::AddTrashConfirmation(..) => "AddTrashConfirmation",
+            Mutation::RemoveTrashConfirmation(..) => "RemoveTrashConfirmation",
+            Mutation::AddIdempotencyRecord(..) => "AddIdempotencyRecord",
+            Mutation::ExpireIdempotencyRecords(..) => "ExpireIdempotencyRecords",
+            Mutation::SetViewed(..) => "SetViewed",
+            Mutation::AddUndoToken(..) => "AddUndoToken",
+            Mutation::RemoveUndoToken(..) => "RemoveUndoToken",
+            Mutation::ExpireUndoTokens(..) => "ExpireUndoTokens",
+            Mutation::SetWorkingCopy(..) => "SetWorkingCopy",
+            Mutation::RemoveWorkingCopy(..) => "RemoveWorkingCopy",
+            Mutation::ExpireWorkingCopies(..) => "ExpireWorkingCopies",
+            Mutation::SetFlag(..) => "SetFlag",
+            Mutation::RemoveFlag(..) => "RemoveFlag",
+            Mutation::AddServiceToken(..) => "AddServiceToken",
+            Mutation::RevokeServiceToken(..) => "RevokeServiceToken",
+            Mutation::SetMentions(..) => "SetMentions",
+            Mutation::SetFavorite(..) => "SetFavorite",
+            Mutation::AddComment(..) => "AddComment",
+            Mutation::DeleteComment(..) => "DeleteComment",
+            Mutation::AddFollower(..) => "AddFollower",
+            Mutation::RemoveFollower(..) => "RemoveFollower",
+            Mutation::RecordAccess(..) => "RecordAccess",
+            Mutation::RecordAudit(..) => "RecordAudit",
+            Mutation::PurgeUser(..) => "PurgeUser",
+            Mutation::Restore(..) => "Restore",
+            Mutation::Compact => "Compact",
+            Mutation::ReserveNotes(..) => "ReserveNotes",
+        }
+    }
+
+    /// Makes the change in the `backend`
+    pub fn apply<P: for<'a> Persister<'a>>(self, backend: &mut P) {
+        match self {
+            Mutation::AddNote(draft, user) => {
+                backend.add_note(draft, &user);
+            }
+            Mutation::AddNotesBulk(drafts, user) => {
+                backend.add_notes_bulk(drafts, &user);
+            }
+            Mutation::UpdateNote(draft, id) => {
+                backend.update_note(draft, id);
+            }
+            Mutation::DeleteNote(id) => {
+                backend.delete_note(id);
+            }
+            Mutation::MergeNotes(target, sources) => {
+                backend.merge_notes(target, &sources);
+            }
+            Mutation::SetLocked(id, locked) => {
+                backend.set_locked(id, locked);
+            }
+            Mutation::SetArchived(id, archived) => {
+                backend.set_archived(id, archived);
+            }
+            Mutation::RestoreNote(id, visibility) => {
+                backend.restore_note(id, visibility);
+            }
+            Mutation::RecordUsage(user, usage) => backend.record_usage(user, usage),
+            Mutation::PurgeNotes(ids) => {
+                backend.purge_notes(&ids);
+            }
+            Mutation::CollectTags => {
+                backend.collect_tags();
+            }
+            Mutation::BulkTag(ids, add, remove) => {
+                backend.bulk_tag(&ids, &add, &remove);
+            }
+            Mutation::AddTag(label) => {
+                backend.add_tag(label);
+            }
+            Mutation::UpdateTag(id, meta) => {
+                backend.update_tag(id, meta);
+            }
+            Mutation::AddSearch(draft, user) => {
+                backend.add_search(draft, &user);
+            }
+            Mutation::AddUser(name, identity) => {
+                backend.add_user(name, identity);
+            }
+            Mutation::ScheduleUserDeletion(id, purge_at) => {
+                backend.schedule_user_deletion(id, purge_at);
+            }
+            Mutation::SetPreferences(id, preferences) => {
+                backend.set_preferences(id, preferences);
+            }
+            Mutation::SetProfile(id, profile) => {
+                backend.set_profile(id, profile);
+            }
+            Mutation::SetTagRules(id, rules) => {
+                backend.set_tag_rules(id, rules);
+            }
+            Mutation::SetTemplates(id, templates) => {
+                backend.set_templates(id, templates);
+            }
+            Mutation::SetIntegrations(id, integrations) => {
+                backend.set_integrations(id, integrations);
+            }
+            Mutation::AddIdempotencyRecord(record) => backend.add_idempotency_record(record),
+            Mutation::ExpireIdempotencyRecords(before) => {
+                backend.expire_idempotency_records(&before);
+            }
+            Mutation::SetViewed(user, note, at) => {
+                backend.set_viewed(user, note, at);
+            }
+            Mutation::AddUndoToken(token) => backend.add_undo_token(token),
+            Mutation::AddTrashConfirmation(confirmation) => {
+                backend.add_trash_confirmation(confirmation)
+            }
+            Mutation::RemoveTrashConfirmation(token) => {
+                backend.remove_trash_confirmation(&token);
+            }
+            Mutation::RemoveUndoToken(token) => {
+                backend.remove_undo_token(&token);
+            }
+            Mutation::ExpireUndoTokens(before) => {
+                backend.expire_undo_tokens(&before);
+            }
+            Mutation::SetWorkingCopy(copy) => {
+                backend.set_working_copy(copy);
+            }
+            Mutation::RemoveWorkingCopy(note) => {
+                backend.remove_working_copy(&note);
+            }
+            Mutation::ExpireWorkingCopies(before) => {
+                backend.expire_working_copies(&before);
+            }
+            Mutation::SetFlag(flag) => {
+                backend.set_flag(flag);
+            }
+            Mutation::RemoveFlag(note) => {
+                backend.remove_flag(&note);
+            }
+            Mutation::AddServiceToken(user, draft, secret) => {
+                backend.add_service_token(&user, draft, secret);
+            }
+            Mutation::RevokeServiceToken(id) => {
+                backend.revoke_service_token(id);
+            }
+            Mutation::SetMentions(note, users) => {
+                backend.set_mentions(note, users);
+            }
+            Mutation::SetFavorite(user, note, favorite) => {
+                backend.set_favorite(user, note, favorite);
+            }
+            Mutation::AddComment(note, draft, author) => {
+                backend.add_comment(note, draft, &author);
+            }
+            Mutation::DeleteComment(id) => {
+                backend.delete_comment(id);
+            }
+            Mutation::AddFollower(follower) => backend.add_follower(follower),
+            Mutation::RemoveFollower(user, actor) => {
+                backend.remove_follower(&user, &actor);
+            }
+            Mutation::RecordAccess(entry) => backend.record_access(entry),
+            Mutation::RecordAudit(entry) => backend.record_audit(entry),
+            Mutation::PurgeUser(id) => {
+                backend.purge_user(id);
+            }
+            Mutation::Restore(backup, mode) => backend.restore(&backup, mode, &mut |_| {}),
+            Mutation::Compact => backend.compact(),
+            Mutation::ReserveNotes(count) => {
+                backend.reserve_notes(count);
+            }
+        }
+    }
+}
+
+enum Message {
+    Apply(Box<Mutation>),
+    /// Answers once all previous mutations are applied
+    Flush(Sender<()>),
+}
+
+/// The data that differs between the primary and the mirror, or between two backends
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Divergence {
+    pub notes: Vec<Id>,
+    pub tags: Vec<Id>,
+    pub users: Vec<Id>,
+    /// The number of mutations that failed in the mirror
+    pub failed_mutations: usize,
+}
+
+impl Divergence {
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+            && self.tags.is_empty()
+            && self.users.is_empty()
+            && self.failed_mutations == 0
+    }
+}
+
+/// Reads and writes a primary backend and mirrors all changes to a second backend
+#[derive(Debug)]
+pub struct ReplicatedPersister<P, M> {
+    primary: P,
+    mirror: Arc<Mutex<M>>,
+    sender: Sender<Message>,
+    failed: Arc<AtomicUsize>,
+}
+
+impl<P: Default, M: for<'a> Persister<'a> + Default + Send + 'static> Default
+    for ReplicatedPersister<P, M>
+{
+    fn default() -> Self {
+        Self::new(P::default(), M::default())
+    }
+}
+
+impl<P, M: for<'a> Persister<'a> + Send + 'static> ReplicatedPersister<P, M> {
+    /// Spawns the background thread that applies all mutations to the mirror
+    ///
+    /// The mirror must contain the same data as the primary.
+    pub fn new(primary: P, mirror: M) -> Self {
+        let (sender, receiver) = mpsc::channel::<Message>();
+        let mirror = Arc::new(Mutex::new(mirror));
+        let failed = Arc::new(AtomicUsize::new(0));
+        let worker_mirror = mirror.clone();
+        let worker_failed = failed.clone();
+        thread::Builder::new()
+            .name("mirror".to_string())
+            .spawn(move || {
+                // the loop ends once the persister is dropped
+                for message in receiver {
+                    match message {
+                        Message::Apply(mutation) => {
+                            let mut mirror =
+                                worker_mirror.lock().expect("mirror lock was poisoned");
+                            let name = mutation.name();
+                            // a panic of the mirror must not stop the replication of later changes
+                            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                                mutation.apply(&mut *mirror)
+                            }));
+                            if res.is_err() {
+                                warn!("Mirror failed to apply {name}");
+                                worker_failed.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                        Message::Flush(done) => {
+                            // fails only if the caller stopped waiting
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })
+            .expect("unable to spawn mirror thread");
+        Self {
+            primary,
+            mirror,
+            sender,
+            failed,
+        }
+    }
+
+    /// Provides read access to the mirror, e.g. to compare it with the primary
+    pub fn mirror(&self) -> MutexGuard<'_, M> {
+        self.mirror.lock().expect("mirror lock was poisoned")
+    }
+
+    /// Waits until the mirror applied all previous mutations
+    pub fn catch_up(&self) {
+        let (done, wait) = mpsc::channel();
+        if self.sender.send(Message::Flush(done)).is_err() || wait.recv().is_err() {
+            warn!("Mirror thread is not running, mirror is out of date");
+        }
+    }
+
+    fn mirror_mutation(&self, mutation: Mutation) {
+        if self
+            .sender
+            .send(Message::Apply(Box::new(mutation)))
+            .is_err()
+        {
+            warn!("Mirror thread is not running, mirror is out of date");
+        }
+    }
+}
+
+/// The fields of a note that both backends must agree on
+#[derive(Debug, PartialEq)]
+struct NoteContent<'a> {
+    user: &'a Id,
+    title: &'a str,
+    body: &'a Body,
+    tags: BTreeSet<&'a str>,
+    visibility: &'a Visibility,
+    locked: bool,
+    archived: bool,
+    merged_into: Option<&'a Id>,
+}
+
+impl<'a> From<&'a Note> for NoteContent<'a> {
+    fn from(note: &'a Note) -> Self {
+        Self {
+            user: note.user(),
+            title: note.title(),
+            body: note.body(),
+            tags: note.tags().map(|tag| tag.label()).collect(),
+            visibility: note.visibility(),
+            locked: note.locked(),
+            archived: note.archived(),
+            merged_into: note.merged_into(),
+        }
+    }
+}
+
+/// The data of a backend that is compared, by Id
+struct Snapshot<'a> {
+    notes: BTreeMap<Id, NoteContent<'a>>,
+    tags: BTreeMap<Id, &'a str>,
+    users: BTreeMap<Id, &'a str>,
+}
+
+impl<'a> Snapshot<'a> {
+    fn new<B: Persister<'a>>(data: &'a B) -> Self {
+        Self {
+            notes: data
+                .notes_with(VisibilityFilter::All)
+                .map(|note| (*note.id(), note.into()))
+                .collect(),
+            tags: data.tags().map(|tag| (*tag.id(), tag.label())).collect(),
+            users: data.users().map(|user| (*user.id(), user.name())).collect(),
+        }
+    }
+}
+
+/// Compares the notes, tags and users of two backends, e.g. after a [`migrate`](crate::persistence::migrate)
+pub fn compare<'a, A: Persister<'a>, B: Persister<'a>>(
+    primary: &'a A,
+    mirror: &'a B,
+) -> Divergence {
+    let primary = Snapshot::new(primary);
+    let mirror = Snapshot::new(mirror);
+    Divergence {
+        notes: differences(&primary.notes, &mirror.notes),
+        tags: differences(&primary.tags, &mirror.tags),
+        users: differences(&primary.users, &mirror.users),
+        failed_mutations: 0,
+    }
+}
+
+/// Returns the Ids that are missing in either map or whose values differ
+fn differences<V: PartialEq>(primary: &BTreeMap<Id, V>, mirror: &BTreeMap<Id, V>) -> Vec<Id> {
+    primary
+        .keys()
+        .chain(mirror.keys())
+        .collect::<BTreeSet<&Id>>()
+        .into_iter()
+        .filter(|id| primary.get(id) != mirror.get(id))
+        .copied()
+        .collect()
+}
+
+impl<P: for<'a> Persister<'a>, M: for<'a> Persister<'a> + Send + 'static>
+    ReplicatedPersister<P, M>
+{
+    /// Waits for the mirror and returns the data that differs from the primary
+    pub fn divergence(&self) -> Divergence {
+        self.catch_up();
+        let mirror = self.mirror();
+        let divergence = Self::compare(&self.primary, &*mirror, &self.failed);
+        if !divergence.is_empty() {
+            warn!("Mirror diverged from primary: {:?}", divergence);
+        }
+        divergence
+    }
+
+    fn compare(primary: &P, mirror: &M, failed: &AtomicUsize) -> Divergence {
+        Divergence {
+            failed_mutations: failed.load(Ordering::Relaxed),
+            ..compare(primary, mirror)
+        }
+    }
+
+    /// Copies the diverged notes that exist in both backends from the primary to the mirror
+    ///
+    /// Resets the count of failed mutations and returns the remaining divergence,
+    /// e.g. notes that are missing in the mirror. Those require a fresh mirror.
+    pub fn repair(&mut self) -> Divergence {
+        self.catch_up();
+        let mut mirror = self.mirror.lock().expect("mirror lock was poisoned");
+        let diverged = Self::compare(&self.primary, &*mirror, &self.failed).notes;
+        for id in diverged {
+            let Some(note) = self.primary.note_with(id, VisibilityFilter::All) else {
+                continue;
+            };
+            let Some(visibility) = mirror
+                .note_with(id, VisibilityFilter::All)
+                .map(|copy| copy.visibility().clone())
+            else {
+                continue;
+            };
+            mirror.update_note(Draft::from(note), id);
+            mirror.set_locked(id, note.locked());
+            mirror.set_archived(id, note.archived());
+            if &visibility != note.visibility() {
+                if visibility != Visibility::Deleted {
+                    mirror.delete_note(id);
+                }
+                if note.visibility() != &Visibility::Deleted {
+                    mirror.restore_note(id, note.visibility().clone());
+                }
+            }
+        }
+        self.failed.store(0, Ordering::Relaxed);
+        Self::compare(&self.primary, &*mirror, &self.failed)
+    }
+}
+
+impl<'a, P: Persister<'a>, M: for<'b> Persister<'b> + Send + 'static> Persister<'a>
+    for ReplicatedPersister<P, M>
+{
+    type NoteIter = P::NoteIter;
+    type TagIter = P::TagIter;
+    type SearchIter = P::SearchIter;
+    type UserIter = P::UserIter;
+
+    fn notes_with(&'a self, filter: VisibilityFilter) -> Self::NoteIter {
+        self.primary.notes_with(filter)
+    }
+
+    fn tags(&'a self) -> Self::TagIter {
+        self.primary.tags()
+    }
+
+    fn searches(&'a self) -> Self::SearchIter {
+        self.primary.searches()
+    }
+
+    fn users(&'a self) -> Self::UserIter {
+        self.primary.users()
+    }
+
+    fn add_note(&mut self, draft: Draft, user: &User) -> &Note {
+        self.mirror_mutation(Mutation::AddNote(draft.clone(), user.clone()));
+        self.primary.add_note(draft, user)
+    }
+
+    fn update_note(&mut self, draft: Draft, id: Id) -> &Note {
+        self.mirror_mutation(Mutation::UpdateNote(draft.clone(), id));
+        self.primary.update_note(draft, id)
+    }
+
+    fn delete_note(&mut self, id: Id) -> bool {
+        self.mirror_mutation(Mutation::DeleteNote(id));
+        self.primary.delete_note(id)
+    }
+
+    fn merge_notes(&mut self, target: Id, sources: &[Id]) -> &Note {
+        self.mirror_mutation(Mutation::MergeNotes(target, sources.to_vec()));
+        self.primary.merge_notes(target, sources)
+    }
+
+    fn revisions(&self, note: &Id) -> Vec<RevisionInfo> {
+        self.primary.revisions(note)
+    }
+
+    fn revision(&self, note: &Id, number: usize) -> Option<Revision> {
+        self.primary.revision(note, number)
+    }
+
+    fn set_locked(&mut self, id: Id, locked: bool) -> bool {
+        self.mirror_mutation(Mutation::SetLocked(id, locked));
+        self.primary.set_locked(id, locked)
+    }
+
+    fn set_archived(&mut self, id: Id, archived: bool) -> bool {
+        self.mirror_mutation(Mutation::SetArchived(id, archived));
+        self.primary.set_archived(id, archived)
+    }
+
+    fn restore_note(&mut self, id: Id, visibility: Visibility) -> bool {
+        self.mirror_mutation(Mutation::RestoreNote(id, visibility.clone()));
+        self.primary.restore_note(id, visibility)
+    }
+
+    fn purge_notes(&mut self, ids: &[Id]) -> usize {
+        self.mirror_mutation(Mutation::PurgeNotes(ids.to_vec()));
+        self.primary.purge_notes(ids)
+    }
+
+    fn collect_tags(&mut self) -> usize {
+        self.mirror_mutation(Mutation::CollectTags);
+        self.primary.collect_tags()
+    }
+
+    fn bulk_tag(&mut self, ids: &[Id], add: &[String], remove: &[String]) -> Vec<Id> {
+        self.mirror_mutation(Mutation::BulkTag(
+            ids.to_vec(),
+            add.to_vec(),
+            remove.to_vec(),
+        ));
+        self.primary.bulk_tag(ids, add, remove)
+    }
+
+    fn user_notes(&'a self, user: &User) -> Self::NoteIter {
+        self.primary.user_notes(user)
+    }
+
+    fn tagged_notes(&'a self, tag: &Tag) -> Self::NoteIter {
+        self.primary.tagged_notes(tag)
+    }
+
+    fn query_notes(&'a self, user: &User, query: &NoteQuery) -> Self::NoteIter {
+        self.primary.query_notes(user, query)
+    }
+
+    fn add_tag(&mut self, label: String) -> Id {
+        self.mirror_mutation(Mutation::AddTag(label.clone()));
+        self.primary.add_tag(label)
+    }
+
+    fn update_tag(&mut self, id: Id, meta: TagMeta) -> Option<&Tag> {
+        self.mirror_mutation(Mutation::UpdateTag(id, meta.clone()));
+        self.primary.update_tag(id, meta)
+    }
+
+    fn add_search(&mut self, draft: SearchDraft, user: &User) -> &SavedSearch {
+        self.mirror_mutation(Mutation::AddSearch(draft.clone(), user.clone()));
+        self.primary.add_search(draft, user)
+    }
+
+    fn user_searches(&'a self, user: &User) -> Self::SearchIter {
+        self.primary.user_searches(user)
+    }
+
+    fn add_user(&mut self, name: String, identity: Option<ExternalIdentity>) -> &User {
+        self.mirror_mutation(Mutation::AddUser(name.clone(), identity.clone()));
+        self.primary.add_user(name, identity)
+    }
+
+    fn schedule_user_deletion(&mut self, id: Id, purge_at: DateTime<Utc>) -> bool {
+        self.mirror_mutation(Mutation::ScheduleUserDeletion(id, purge_at));
+        self.primary.schedule_user_deletion(id, purge_at)
+    }
+
+    fn set_preferences(&mut self, id: Id, preferences: Preferences) -> bool {
+        self.mirror_mutation(Mutation::SetPreferences(id, preferences.clone()));
+        self.primary.set_preferences(id, preferences)
+    }
+
+    fn set_profile(&mut self, id: Id, profile: Profile) -> bool {
+        self.mirror_mutation(Mutation::SetProfile(id, profile.clone()));
+        self.primary.set_profile(id, profile)
+    }
+
+    fn set_tag_rules(&mut self, id: Id, rules: TagRules) -> bool {
+        self.mirror_mutation(Mutation::SetTagRules(id, rules.clone()));
+        self.primary.set_tag_rules(id, rules)
+    }
+
+    fn set_templates(&mut self, id: Id, templates: Templates) -> bool {
+        self.mirror_mutation(Mutation::SetTemplates(id, templates.clone()));
+        self.primary.set_templates(id, templates)
+    }
+
+    fn set_integrations(&mut self, id: Id, integrations: Integrations) -> bool {
+        self.mirror_mutation(Mutation::SetIntegrations(id, integrations.clone()));
+        self.primary.set_integrations(id, integrations)
+    }
+
+    fn idempotency_record(&'a self, user: &User, key: &str) -> Option<&'a IdempotencyRecord> {
+        self.primary.idempotency_record(user, key)
+    }
+
+    fn add_idempotency_record(&mut self, record: IdempotencyRecord) {
+        self.mirror_mutation(Mutation::AddIdempotencyRecord(record.clone()));
+        self.primary.add_idempotency_record(record)
+    }
+
+    fn expire_idempotency_records(&mut self, before: &DateTime<Utc>) -> usize {
+        self.mirror_mutation(Mutation::ExpireIdempotencyRecords(*before));
+        self.primary.expire_idempotency_records(before)
+    }
+
+    fn viewed_at(&self, user: &Id, note: &Id) -> Option<DateTime<Utc>> {
+        self.primary.viewed_at(user, note)
+    }
+
+    fn set_viewed(&mut self, user: Id, note: Id, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.mirror_mutation(Mutation::SetViewed(user, note, at));
+        self.primary.set_viewed(user, note, at)
+    }
+
+    fn undo_token(&'a self, token: &str) -> Option<&'a UndoToken> {
+        self.primary.undo_token(token)
+    }
+
+    fn add_undo_token(&mut self, token: UndoToken) {
+        self.mirror_mutation(Mutation::AddUndoToken(token.clone()));
+        self.primary.add_undo_token(token)
+    }
+
+    fn remove_undo_token(&mut self, token: &str) -> Option<UndoToken> {
+        self.mirror_mutation(Mutation::RemoveUndoToken(token.to_string()));
+        self.primary.remove_undo_token(token)
+    }
+
+    fn expire_undo_tokens(&mut self, before: &DateTime<Utc>) -> usize {
+        self.mirror_mutation(Mutation::ExpireUndoTokens(*before));
+        self.primary.expire_undo_tokens(before)
+    }
+
+    fn trash_confirmation(&'a self, token: &str) -> Option<&'a TrashConfirmation> {
+        self.primary.trash_confirmation(token)
+    }
+
+    fn add_trash_confirmation(&mut self, confirmation: TrashConfirmation) {
+        self.mirror_mutation(Mutation::AddTrashConfirmation(confirmation.clone()));
+        self.primary.add_trash_confirmation(confirmation)
+    }
+
+    fn remove_trash_confirmation(&mut self, token: &str) -> Option<TrashConfirmation> {
+        self.mirror_mutation(Mutation::RemoveTrashConfirmation(token.to_string()));
+        self.primary.remove_trash_confirmation(token)
+    }
+
+    fn working_copy(&'a self, note: &Id) -> Option<&'a WorkingCopy> {
+        self.primary.working_copy(note)
+    }
+
+    fn set_working_copy(&mut self, copy: WorkingCopy) -> Option<WorkingCopy> {
+        self.mirror_mutation(Mutation::SetWorkingCopy(copy.clone()));
+        self.primary.set_working_copy(copy)
+    }
+
+    fn remove_working_copy(&mut self, note: &Id) -> Option<WorkingCopy> {
+        self.mirror_mutation(Mutation::RemoveWorkingCopy(*note));
+        self.primary.remove_working_copy(note)
+    }
+
+    fn expire_working_copies(&mut self, before: &DateTime<Utc>) -> usize {
+        self.mirror_mutation(Mutation::ExpireWorkingCopies(*before));
+        self.primary.expire_working_copies(before)
+    }
+
+    fn flags(&'a self) -> Vec<&'a Flag> {
+        self.primary.flags()
+    }
+
+    fn flag(&'a self, note: &Id) -> Option<&'a Flag> {
+        self.primary.flag(note)
+    }
+
+    fn set_flag(&mut self, flag: Flag) -> Option<Flag> {
+        self.mirror_mutation(Mutation::SetFlag(flag.clone()));
+        self.primary.set_flag(flag)
+    }
+
+    fn remove_flag(&mut self, note: &Id) -> Option<Flag> {
+        self.mirror_mutation(Mutation::RemoveFlag(*note));
+        self.primary.remove_flag(note)
+    }
+
+    fn service_token(&'a self, secret: &str) -> Option<&'a ServiceToken> {
+        self.primary.service_token(secret)
+    }
+
+    fn service_tokens(&'a self, user: &Id) -> Vec<&'a ServiceToken> {
+        self.primary.service_tokens(user)
+    }
+
+    fn add_service_token(
+        &mut self,
+        user: &User,
+        draft: TokenDraft,
+        secret: String,
+    ) -> &ServiceToken {
+        self.mirror_mutation(Mutation::AddServiceToken(
+            user.clone(),
+            draft.clone(),
+            secret.clone(),
+        ));
+        self.primary.add_service_token(user, draft, secret)
+    }
+
+    fn revoke_service_token(&mut self, id: Id) -> bool {
+        self.mirror_mutation(Mutation::RevokeServiceToken(id));
+        self.primary.revoke_service_token(id)
+    }
+
+    fn set_mentions(&mut self, note: Id, users: HashSet<Id>) -> Vec<Id> {
+        self.mirror_mutation(Mutation::SetMentions(note, users.clone()));
+        self.primary.set_mentions(note, users)
+    }
+
+    fn mentions(&'a self, user: &User) -> Vec<&'a Note> {
+        self.primary.mentions(user)
+    }
+
+    fn favorites(&'a self, user: &Id) -> Vec<&'a Note> {
+        self.primary.favorites(user)
+    }
+
+    fn set_favorite(&mut self, user: Id, note: Id, favorite: bool) -> bool {
+        self.mirror_mutation(Mutation::SetFavorite(user, note, favorite));
+        self.primary.set_favorite(user, note, favorite)
+    }
+
+    fn comment(&'a self, id: Id) -> Option<&'a Comment> {
+        self.primary.comment(id)
+    }
+
+    fn comments(&'a self, note: &Id) -> Vec<&'a Comment> {
+        self.primary.comments(note)
+    }
+
+    fn user_comments(&'a self, user: &Id) -> Vec<&'a Comment> {
+        self.primary.user_comments(user)
+    }
+
+    fn add_comment(&mut self, note: Id, draft: CommentDraft, author: &User) -> &Comment {
+        self.mirror_mutation(Mutation::AddComment(note, draft.clone(), author.clone()));
+        self.primary.add_comment(note, draft, author)
+    }
+
+    fn delete_comment(&mut self, id: Id) -> bool {
+        self.mirror_mutation(Mutation::DeleteComment(id));
+        self.primary.delete_comment(id)
+    }
+
+    fn followers(&'a self, user: &Id) -> Vec<&'a Follower> {
+        self.primary.followers(user)
+    }
+
+    fn add_follower(&mut self, follower: Follower) {
+        self.mirror_mutation(Mutation::AddFollower(follower.clone()));
+        self.primary.add_follower(follower)
+    }
+
+    fn remove_follower(&mut self, user: &Id, actor: &str) -> bool {
+        self.mirror_mutation(Mutation::RemoveFollower(*user, actor.to_string()));
+        self.primary.remove_follower(user, actor)
+    }
+
+    fn access_log(&'a self, note: &Id) -> Vec<&'a AccessEntry> {
+        self.primary.access_log(note)
+    }
+
+    fn record_access(&mut self, entry: AccessEntry) {
+        self.mirror_mutation(Mutation::RecordAccess(entry.clone()));
+        self.primary.record_access(entry)
+    }
+
+    fn audit_log(&'a self, user: &Id) -> Vec<&'a AuditEntry> {
+        self.primary.audit_log(user)
+    }
+
+    fn record_audit(&mut self, entry: AuditEntry) {
+        self.mirror_mutation(Mutation::RecordAudit(entry.clone()));
+        self.primary.record_audit(entry)
+    }
+
+    fn usage(&'a self, user: &Id, from: NaiveDate, to: NaiveDate) -> Vec<DailyUsage> {
+        self.primary.usage(user, from, to)
+    }
+
+    fn record_usage(&mut self, user: Id, usage: DailyUsage) {
+        self.mirror_mutation(Mutation::RecordUsage(user, usage.clone()));
+        self.primary.record_usage(user, usage)
+    }
+
+    fn purge_user(&mut self, id: Id) -> bool {
+        self.mirror_mutation(Mutation::PurgeUser(id));
+        self.primary.purge_user(id)
+    }
+
+    fn restore(&mut self, backup: &Backup, mode: RestoreMode, progress: &mut dyn FnMut(usize)) {
+        self.mirror_mutation(Mutation::Restore(Box::new(backup.clone()), mode));
+        self.primary.restore(backup, mode, progress)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.primary.capabilities()
+    }
+
+    // the mirror is updated in the background, its outages don't affect requests
+    fn health(&self) -> Health {
+        self.primary.health()
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.primary.reconnect()
+    }
+
+    fn stats(&'a self) -> StorageStats {
+        self.primary.stats()
+    }
+
+    fn compact(&mut self) {
+        self.mirror_mutation(Mutation::Compact);
+        self.primary.compact()
+    }
+
+    fn reserve_notes(&mut self, count: usize) -> Option<Vec<Note>> {
+        self.mirror_mutation(Mutation::ReserveNotes(count));
+        self.primary.reserve_notes(count)
+    }
+
+    fn add_notes_bulk(&mut self, drafts: Vec<Draft>, user: &User) -> Vec<Id> {
+        self.mirror_mutation(Mutation::AddNotesBulk(drafts.clone(), user.clone()));
+        self.primary.add_notes_bulk(drafts, user)
+    }
+
+    fn search_notes(&'a self, user: &User, text: &str) -> Vec<&'a Note> {
+        self.primary.search_notes(user, text)
+    }
+
+    fn estimate_count(&'a self, user: &User, query: &NoteQuery) -> Option<usize> {
+        self.primary.estimate_count(user, query)
+    }
+
+    fn notes_as_of(&'a self, user: &User, at: &DateTime<Utc>) -> Vec<Note> {
+        self.primary.notes_as_of(user, at)
+    }
+
+    fn note_with(&'a self, id: Id, filter: VisibilityFilter) -> Option<&'a Note> {
+        self.primary.note_with(id, filter)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::memory::InMemoryStorage;
+
+    type Replicated = ReplicatedPersister<InMemoryStorage, InMemoryStorage>;
+
+    fn draft(title: &str, tags: &[&str]) -> Draft {
+        Draft::new(
+            title.to_string(),
+            format!("Body of {title}"),
+            tags.iter().map(|tag| tag.to_string()).collect(),
+            Visibility::Private,
+        )
+    }
+
+    #[test]
+    fn test_mirror_changes() {
+        let mut data = Replicated::default();
+        let user = data.add_user("alice".to_string(), None).clone();
+        let id = *data.add_note(draft("First", &["a"]), &user).id();
+        data.add_note(draft("Second", &["b"]), &user);
+        data.update_note(draft("Changed", &["c"]), id);
+        data.set_locked(id, true);
+        data.delete_note(id);
+        data.collect_tags();
+
+        assert!(data.divergence().is_empty());
+        let mirror = data.mirror();
+        let note = mirror.note_with(id, VisibilityFilter::Deleted).unwrap();
+        assert_eq!(note.title(), "Changed");
+        assert!(note.locked());
+        assert_eq!(mirror.user_notes(&user).count(), 1);
+        assert_eq!(mirror.tags().count(), 1);
+    }
+
+    #[test]
+    fn test_divergence_and_repair() {
+        let mut data = Replicated::default();
+        let user = data.user(Id(0)).cloned().unwrap();
+        let first = *data.add_note(draft("First", &[]), &user).id();
+        let second = *data.add_note(draft("Second", &[]), &user).id();
+        data.catch_up();
+        {
+            let mut mirror = data.mirror();
+            mirror.update_note(draft("Changed", &[]), first);
+            mirror.delete_note(second);
+            mirror.add_note(draft("Only in mirror", &[]), &user);
+        }
+        let divergence = data.divergence();
+        assert_eq!(divergence.notes, vec![first, second, Id(2)]);
+        assert_eq!(divergence.failed_mutations, 0);
+
+        // notes that are missing in the primary can't be repaired
+        assert_eq!(data.repair().notes, vec![Id(2)]);
+        let mirror = data.mirror();
+        assert_eq!(mirror.note(first).unwrap().title(), "First");
+        assert!(mirror.note(second).is_some());
+    }
+
+    #[test]
+    fn test_failed_mutations() {
+        let mut data = Replicated::default();
+        let user = data.user(Id(0)).cloned().unwrap();
+        data.add_note(draft("First", &[]), &user);
+        data.catch_up();
+        data.mirror().delete_note(Id(0));
+        data.mirror().purge_user(Id(0));
+        data.update_note(draft("Changed", &[]), Id(0));
+
+        let divergence = data.divergence();
+        assert_eq!(divergence.failed_mutations, 1);
+        assert_eq!(divergence.notes, vec![Id(0)]);
+        assert_eq!(divergence.users, vec![Id(0)]);
+        assert_eq!(data.repair().failed_mutations, 0);
+        assert_eq!(
+            Mutation::RemoveUndoToken("secret".to_string()).name(),
+            "RemoveUndoToken"
+        );
+    }
+}
+
+#[cfg(test)]
+mod testsuite {
+    crate::persister_testsuite!(
+        super::ReplicatedPersister::<
+            crate::persistence::memory::InMemoryStorage,
+            crate::persistence::memory::InMemoryStorage,
+        >::default
+    );
+}
@@ -0,0 +1,210 @@
+//! Monitors the connection of the storage backend and reconnects after outages
+//!
+//! The [`Supervisor`] checks [`Persister::health`] periodically. While the backend is
+//! down, it calls [`Persister::reconnect`] with an exponential backoff until the backend
+//! is up again. Handlers don't wait for the backend during outages: `/readyz` reports
+//! the outage and writes are rejected with `503 Service Unavailable` and a `Retry-After`
+//! header by [`reject_writes`](crate::layers::reject_writes).
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::HealthConfig;
+use crate::indexer::WarmUp;
+use crate::persistence::{Health, Persister};
+
+/// The health of the backend with counters for monitoring
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct StorageStatus {
+    #[serde(flatten)]
+    pub health: Health,
+    /// The time of the last change of the health
+    pub since: DateTime<Utc>,
+    /// Number of health checks since the start
+    pub checks: usize,
+    /// Number of times the backend went down
+    pub outages: usize,
+    /// Number of reconnect attempts since the start
+    pub reconnects: usize,
+    /// The time of the next reconnect attempt while the backend is down
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<DateTime<Utc>>,
+    /// Number of failed reconnect attempts of the current outage, for the backoff
+    #[serde(skip)]
+    failures: u32,
+}
+
+/// The response of `GET /readyz`
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Readiness {
+    #[serde(flatten)]
+    pub storage: StorageStatus,
+    /// The progress of building the indexes at startup
+    pub index: WarmUp,
+}
+
+/// Handle to the health of the backend, cheap to clone
+#[derive(Clone, Debug)]
+pub struct Supervisor {
+    config: HealthConfig,
+    status: Arc<RwLock<StorageStatus>>,
+}
+
+impl Supervisor {
+    /// Creates a supervisor that considers the backend up until the first check
+    pub fn new(config: HealthConfig) -> Self {
+        Self {
+            config,
+            status: Arc::new(RwLock::new(StorageStatus {
+                health: Health::Up,
+                since: Utc::now(),
+                checks: 0,
+                outages: 0,
+                reconnects: 0,
+                retry_at: None,
+                failures: 0,
+            })),
+        }
+    }
+
+    pub fn status(&self) -> StorageStatus {
+        self.status
+            .read()
+            .expect("status lock was poisoned")
+            .clone()
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.status.read().expect("status lock was poisoned").health == Health::Up
+    }
+
+    /// Returns the time until the next reconnect attempt in whole seconds, at least one
+    pub fn retry_after(&self, now: &DateTime<Utc>) -> Duration {
+        let retry_at = self
+            .status
+            .read()
+            .expect("status lock was poisoned")
+            .retry_at;
+        let wait = retry_at
+            .and_then(|retry_at| (retry_at - *now).to_std().ok())
+            .unwrap_or_default();
+        let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        Duration::from_secs(seconds.max(1))
+    }
+
+    /// Returns the delay before the next reconnect attempt after `failures` failed attempts
+    fn backoff(&self, failures: u32) -> Duration {
+        self.config
+            .interval
+            .saturating_mul(2u32.saturating_pow(failures))
+            .min(self.config.max_backoff)
+    }
+
+    /// Records the result of a health check
+    pub fn record(&self, health: Health, now: DateTime<Utc>) {
+        let mut status = self.status.write().expect("status lock was poisoned");
+        status.checks += 1;
+        match (&status.health, &health) {
+            (Health::Up, Health::Down { reason }) => {
+                warn!("Storage backend is down: {}", reason);
+                status.outages += 1;
+                status.since = now;
+                status.failures = 0;
+            }
+            (Health::Down { .. }, Health::Up) => {
+                info!("Storage backend is up again");
+                status.since = now;
+                status.failures = 0;
+            }
+            (Health::Down { .. }, Health::Down { .. }) => status.failures += 1,
+            (Health::Up, Health::Up) => {}
+        }
+        status.retry_at = match &health {
+            Health::Up => None,
+            Health::Down { .. } => {
+                let delay = TimeDelta::from_std(self.backoff(status.failures))
+                    .expect("backoff is out of range");
+                Some(now + delay)
+            }
+        };
+        status.health = health;
+    }
+
+    /// Checks the backend and reconnects it if it is down and the backoff is over
+    pub fn check<P: for<'a> Persister<'a>>(&self, data: &mut P, now: DateTime<Utc>) {
+        let status = self.status();
+        if status.retry_at.is_some_and(|retry_at| retry_at <= now) {
+            self.status
+                .write()
+                .expect("status lock was poisoned")
+                .reconnects += 1;
+            if let Err(err) = data.reconnect() {
+                warn!("Unable to reconnect the storage backend: {}", err);
+            }
+        }
+        self.record(data.health(), now);
+    }
+
+    /// Checks the backend periodically on the tokio runtime
+    pub fn start<P>(&self, data: Arc<Mutex<P>>)
+    where
+        P: for<'a> Persister<'a> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(supervisor.config.interval);
+            loop {
+                interval.tick().await;
+                let mut data = data.lock().expect("mutex was poisoned");
+                supervisor.check(&mut *data, Utc::now());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn down() -> Health {
+        Health::Down {
+            reason: "connection refused".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let supervisor = Supervisor::new(HealthConfig {
+            interval: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(15),
+        });
+        let now = Utc::now();
+        supervisor.record(Health::Up, now);
+        assert!(supervisor.is_up());
+        assert_eq!(supervisor.retry_after(&now), Duration::from_secs(1));
+
+        supervisor.record(down(), now);
+        assert!(!supervisor.is_up());
+        assert_eq!(supervisor.retry_after(&now), Duration::from_secs(5));
+        supervisor.record(down(), now);
+        assert_eq!(supervisor.retry_after(&now), Duration::from_secs(10));
+        supervisor.record(down(), now);
+        assert_eq!(supervisor.retry_after(&now), Duration::from_secs(15));
+
+        let later = now + TimeDelta::seconds(30);
+        supervisor.record(Health::Up, later);
+        let status = supervisor.status();
+        assert_eq!(status.checks, 5);
+        assert_eq!(status.outages, 1);
+        assert_eq!(status.since, later);
+        assert_eq!(status.retry_at, None);
+
+        // a new outage starts with the initial backoff again
+        supervisor.record(down(), later);
+        assert_eq!(supervisor.retry_after(&later), Duration::from_secs(5));
+        assert_eq!(supervisor.status().outages, 2);
+    }
+}
@@ -0,0 +1,1463 @@
+//! Conformance tests for [`Persister`] implementations
+//!
+//! Every function checks one part of the contract of the trait and receives a
+//! fresh backend, which must only contain the anonymous user with `Id(0)`.
+//! [`persister_testsuite!`](crate::persister_testsuite) generates a `#[test]`
+//! for every function:
+//!
+//! ```ignore
+//! #[cfg(test)]
+//! mod testsuite {
+//!     note_demo::persister_testsuite!(super::MyBackend::default);
+//! }
+//! ```
+//!
+//! Backends outside of this crate need the `testsuite` feature.
+use std::collections::{BTreeSet, HashSet};
+
+use chrono::{NaiveDate, TimeDelta, Utc};
+use serde_json::json;
+
+use crate::models::access::{AccessEntry, ACCESS_LOG_CAPACITY};
+use crate::models::audit::{AuditEntry, AUDIT_LOG_CAPACITY};
+use crate::models::autosave::WorkingCopy;
+use crate::models::backup::RestoreMode;
+use crate::models::comment::CommentDraft;
+use crate::models::follower::Follower;
+use crate::models::geo::Location;
+use crate::models::idempotency::IdempotencyRecord;
+use crate::models::integration::Integrations;
+use crate::models::lifecycle::State;
+use crate::models::moderation::{Flag, Reason};
+use crate::models::note::{Draft, Note};
+use crate::models::preferences::Preferences;
+use crate::models::profile::Profile;
+use crate::models::query::{NoteQuery, NoteState, SearchDraft, SortKey};
+use crate::models::tag_rule::TagRules;
+use crate::models::template::Templates;
+use crate::models::token::{Scope, TokenDraft};
+use crate::models::trash::TrashConfirmation;
+use crate::models::undo::UndoToken;
+use crate::models::usage::DailyUsage;
+use crate::models::{ExternalIdentity, Id, TagMeta, User, Visibility, VisibilityFilter};
+use crate::persistence::Persister;
+
+/// Generates a `#[test]` for every conformance test of [`testsuite`](crate::persistence::testsuite)
+///
+/// `$new` creates a fresh backend, e.g. `MyBackend::default`.
+#[macro_export]
+macro_rules! persister_testsuite {
+    ($new:expr) => {
+        $crate::persister_testsuite!(@tests $new;
+            fresh_backend, unknown_ids, add_user, user_by_identity, schedule_user_deletion,
+            set_preferences, set_profile, set_tag_rules, set_templates, set_integrations, add_note, add_note_ids, add_note_tags, add_notes_bulk, update_note,
+            update_note_keeps_visibility, lock_note, archive_note, merge_notes, delete_note, delete_unknown_note, restore_note, lifecycle_transitions, purge_notes,
+            notes_ordered_by_id, notes_with_filter, expired_notes, user_notes, note_by_slug, unique_slugs,
+            note_without_slug, tagged_notes, query_by_tag, query_by_text, query_by_visibility, query_by_state,
+            query_sorted, query_multi_sorted, query_other_user, bulk_tag, bulk_tag_unknown_notes, bulk_tag_new_tags,
+            revisions, notes_as_of, add_tag, update_tag, tag_stats, collect_tags, restore_collected_tags, add_search, user_searches, favorites, comments, delete_comment, export_user, purge_user,
+            purge_unknown_user, purge_user_tags, purge_user_related_data, idempotency_records,
+            idempotency_record_replaced, expire_idempotency_records, views, undo_tokens,
+            expire_undo_tokens, trash_confirmations, working_copies, flags, service_tokens, set_mentions, mentions, followers, remove_follower,
+            access_log, audit_log, usage, reserve_notes,
+            notes_near, search_notes, backup, restore_replace, restore_merge,
+        );
+    };
+    (@tests $new:expr; $($name:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                $crate::persistence::testsuite::$name($new());
+            }
+        )*
+    };
+}
+
+fn draft(title: &str, tags: &[&str], visibility: Visibility) -> Draft {
+    Draft::new(
+        title.to_string(),
+        format!("Body of {title}"),
+        tags.iter().map(|tag| tag.to_string()).collect(),
+        visibility,
+    )
+}
+
+fn anonymous<P: for<'a> Persister<'a>>(data: &P) -> User {
+    data.user(Id(0)).cloned().expect("anonymous user exists")
+}
+
+fn ids<'a>(notes: impl IntoIterator<Item = &'a Note>) -> Vec<Id> {
+    notes.into_iter().map(|note| *note.id()).collect()
+}
+
+/// A fresh backend contains only the anonymous user
+pub fn fresh_backend<P: for<'a> Persister<'a>>(data: P) {
+    assert_eq!(ids_of_users(&data), vec![Id(0)]);
+    assert!(anonymous(&data).is_active());
+    assert_eq!(data.notes_with(VisibilityFilter::All).count(), 0);
+    assert_eq!(data.tags().count(), 0);
+    assert_eq!(data.searches().count(), 0);
+}
+
+fn ids_of_users<P: for<'a> Persister<'a>>(data: &P) -> Vec<Id> {
+    data.users().map(|user| *user.id()).collect()
+}
+
+/// Lookups of unknown Ids return `None`
+pub fn unknown_ids<P: for<'a> Persister<'a>>(data: P) {
+    assert!(data.note(Id(999)).is_none());
+    assert!(data.note_with(Id(999), VisibilityFilter::All).is_none());
+    assert!(data.user(Id(999)).is_none());
+    assert!(data.search(Id(999)).is_none());
+    assert!(data.tag("unknown").is_none());
+    assert!(data.note_by_slug("unknown").is_none());
+}
+
+pub fn add_user<P: for<'a> Persister<'a>>(mut data: P) {
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let bob = data.add_user("bob".to_string(), None).clone();
+    assert_ne!(alice.id(), &Id(0));
+    assert_ne!(alice.id(), bob.id());
+    assert_eq!(data.user(*alice.id()).unwrap().name(), "alice");
+    assert_eq!(data.users().count(), 3);
+}
+
+pub fn user_by_identity<P: for<'a> Persister<'a>>(mut data: P) {
+    let identity = ExternalIdentity::new("issuer".to_string(), "alice".to_string());
+    let alice = data
+        .add_user("alice".to_string(), Some(identity.clone()))
+        .clone();
+    assert_eq!(data.user_by_identity(&identity), Some(&alice));
+    let other = ExternalIdentity::new("other".to_string(), "alice".to_string());
+    assert!(data.user_by_identity(&other).is_none());
+}
+
+pub fn schedule_user_deletion<P: for<'a> Persister<'a>>(mut data: P) {
+    let purge_at = Utc::now();
+    assert!(data.schedule_user_deletion(Id(0), purge_at));
+    let user = anonymous(&data);
+    assert!(!user.is_active());
+    assert_eq!(user.purge_at(), Some(&purge_at));
+    assert!(!data.schedule_user_deletion(Id(999), purge_at));
+}
+
+pub fn set_preferences<P: for<'a> Persister<'a>>(mut data: P) {
+    let preferences: Preferences =
+        serde_json::from_value(json!({"sort": "title", "per_page": 5})).unwrap();
+    assert!(data.set_preferences(Id(0), preferences.clone()));
+    assert_eq!(anonymous(&data).preferences(), &preferences);
+    assert!(!data.set_preferences(Id(999), preferences));
+}
+
+pub fn set_profile<P: for<'a> Persister<'a>>(mut data: P) {
+    let profile: Profile =
+        serde_json::from_value(json!({"display_name": "Anna", "bio": "Hi"})).unwrap();
+    assert!(data.set_profile(Id(0), profile.clone()));
+    assert_eq!(anonymous(&data).profile(), &profile);
+    assert!(!data.set_profile(Id(999), profile));
+}
+
+pub fn set_tag_rules<P: for<'a> Persister<'a>>(mut data: P) {
+    let rules: TagRules =
+        serde_json::from_value(json!([{"tag": "journal", "visibility": "Private"}])).unwrap();
+    assert!(data.set_tag_rules(Id(0), rules.clone()));
+    assert_eq!(anonymous(&data).tag_rules(), &rules);
+    assert!(!data.set_tag_rules(Id(999), rules));
+}
+
+pub fn set_templates<P: for<'a> Persister<'a>>(mut data: P) {
+    let templates: Templates = serde_json::from_value(
+        json!([{"name": "workout", "fields": [{"name": "distance", "type": "number"}]}]),
+    )
+    .unwrap();
+    assert!(data.set_templates(Id(0), templates.clone()));
+    assert_eq!(anonymous(&data).templates(), &templates);
+    assert!(!data.set_templates(Id(999), templates));
+}
+
+pub fn set_integrations<P: for<'a> Persister<'a>>(mut data: P) {
+    let integrations: Integrations = serde_json::from_value(
+        json!({"slack": {"webhook_url": "https://hooks.slack.com/services/T/B/X"}}),
+    )
+    .unwrap();
+    assert!(data.set_integrations(Id(0), integrations.clone()));
+    assert_eq!(anonymous(&data).integrations(), &integrations);
+    assert!(!data.set_integrations(Id(999), integrations));
+}
+
+pub fn add_note<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let note = data
+        .add_note(draft("Title", &[], Visibility::Public), &user)
+        .clone();
+    assert_eq!(note.title(), "Title");
+    assert_eq!(note.body(), "Body of Title");
+    assert_eq!(note.user(), user.id());
+    assert_eq!(note.visibility(), &Visibility::Public);
+    assert_eq!(data.note(*note.id()), Some(&note));
+}
+
+/// Ids of notes are assigned incrementally
+pub fn add_note_ids<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let first = *data.add_note(Draft::default(), &user).id();
+    let second = *data.add_note(Draft::default(), &user).id();
+    assert!(usize::from(first) < usize::from(second));
+}
+
+/// Bulk inserts return the Ids in the order of the drafts and share tags like single inserts
+pub fn add_notes_bulk<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let existing = *data.add_note(Draft::default(), &user).id();
+    let ids = data.add_notes_bulk(
+        vec![
+            draft("First", &["a", "b"], Visibility::Private),
+            draft("Second", &["b"], Visibility::Public),
+        ],
+        &user,
+    );
+    assert_eq!(ids.len(), 2);
+    assert!(usize::from(existing) < usize::from(ids[0]));
+    assert!(usize::from(ids[0]) < usize::from(ids[1]));
+    assert_eq!(data.note(ids[0]).unwrap().title(), "First");
+    assert_eq!(data.note(ids[1]).unwrap().visibility(), &Visibility::Public);
+    assert_eq!(data.tags().count(), 2);
+    assert_eq!(data.note(ids[1]).unwrap().slug(), Some("second"));
+    assert!(data.add_notes_bulk(vec![], &user).is_empty());
+}
+
+/// Tags are created on demand and shared between notes
+pub fn add_note_tags<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let first = data
+        .add_note(draft("First", &["a", "b"], Visibility::Private), &user)
+        .clone();
+    let second = data
+        .add_note(draft("Second", &["b", "c"], Visibility::Private), &user)
+        .clone();
+    let mut labels = first
+        .tags()
+        .map(|tag| tag.label().to_string())
+        .collect::<Vec<String>>();
+    labels.sort();
+    assert_eq!(labels, vec!["a", "b"]);
+    assert_eq!(data.tags().count(), 3);
+    let b = data.tag("b").unwrap();
+    assert!(first.tagged_with(b));
+    assert!(second.tagged_with(b));
+}
+
+pub fn update_note<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let note = data
+        .add_note(draft("Old", &["old"], Visibility::Private), &user)
+        .clone();
+    let updated = data
+        .update_note(draft("New", &["new"], Visibility::Public), *note.id())
+        .clone();
+    assert_eq!(updated.id(), note.id());
+    assert_eq!(updated.user(), note.user());
+    assert_eq!(updated.title(), "New");
+    assert_eq!(updated.visibility(), &Visibility::Public);
+    assert_eq!(updated.created(), note.created());
+    assert!(updated.updated() >= note.updated());
+    assert!(updated.tagged_with(data.tag("new").unwrap()));
+    assert!(!updated.tags().any(|tag| tag.label() == "old"));
+    assert_eq!(data.note(*note.id()), Some(&updated));
+}
+
+/// Drafts without visibility keep the visibility of the note
+pub fn update_note_keeps_visibility<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let id = *data
+        .add_note(draft("Title", &[], Visibility::Public), &user)
+        .id();
+    let draft = Draft::default().with_text("Title".to_string(), "Body".to_string());
+    assert_eq!(
+        data.update_note(draft, id).visibility(),
+        &Visibility::Public
+    );
+}
+
+/// Locking a note does not change its content or update time
+pub fn lock_note<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let note = data
+        .add_note(draft("Title", &[], Visibility::Private), &user)
+        .clone();
+    assert!(!note.locked());
+    assert!(data.set_locked(*note.id(), true));
+    let locked = data.note(*note.id()).unwrap();
+    assert!(locked.locked());
+    assert_eq!(locked.updated(), note.updated());
+    assert!(data.set_locked(*note.id(), false));
+    assert!(!data.note(*note.id()).unwrap().locked());
+    assert!(!data.set_locked(Id(999), true));
+}
+
+/// Archived notes are still active notes, archiving does not change their update time
+pub fn archive_note<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let note = data
+        .add_note(draft("Title", &[], Visibility::Private), &user)
+        .clone();
+    assert!(!note.archived());
+    assert!(data.set_archived(*note.id(), true));
+    let archived = data.note(*note.id()).unwrap();
+    assert!(archived.archived());
+    assert_eq!(archived.updated(), note.updated());
+    assert_eq!(ids(data.user_notes(&user)), vec![*note.id()]);
+    assert!(data.set_archived(*note.id(), false));
+    assert!(!data.note(*note.id()).unwrap().archived());
+    assert!(!data.set_archived(Id(999), true));
+}
+
+/// Merged notes are deleted and point to the note they were merged into
+pub fn merge_notes<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let target = data
+        .add_note(draft("Target", &["a"], Visibility::Private), &user)
+        .clone();
+    let first = *data
+        .add_note(draft("First", &["b"], Visibility::Private), &user)
+        .id();
+    let second = *data
+        .add_note(draft("Second", &["a", "c"], Visibility::Private), &user)
+        .id();
+    let merged = data.merge_notes(*target.id(), &[first, second]).clone();
+    assert_eq!(merged.id(), target.id());
+    assert_eq!(merged.title(), "Target");
+    assert_eq!(
+        merged.body(),
+        "Body of Target\n\n---\n\nBody of First\n\n---\n\nBody of Second"
+    );
+    assert_eq!(merged.tags().count(), 3);
+    assert_eq!(merged.created(), target.created());
+    assert_eq!(data.note(*target.id()), Some(&merged));
+    assert_eq!(data.revisions(target.id()).len(), 2);
+    for source in [first, second] {
+        assert!(data.note(source).is_none());
+        let deleted = data.note_with(source, VisibilityFilter::Deleted).unwrap();
+        assert_eq!(deleted.merged_into(), Some(target.id()));
+    }
+}
+
+/// Deleted notes are kept in the trash
+pub fn delete_note<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let id = *data.add_note(Draft::default(), &user).id();
+    assert!(data.delete_note(id));
+    assert!(data.note(id).is_none());
+    assert!(data.note_with(id, VisibilityFilter::Deleted).is_some());
+    assert!(data
+        .note_with(id, VisibilityFilter::All)
+        .unwrap()
+        .deleted()
+        .is_some());
+    assert_eq!(
+        data.note_with(id, VisibilityFilter::All)
+            .unwrap()
+            .visibility(),
+        &Visibility::Deleted
+    );
+}
+
+pub fn delete_unknown_note<P: for<'a> Persister<'a>>(mut data: P) {
+    assert!(!data.delete_note(Id(999)));
+}
+
+/// Only deleted notes can be restored
+pub fn restore_note<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let id = *data
+        .add_note(draft("Title", &[], Visibility::Private), &user)
+        .id();
+    assert!(!data.restore_note(id, Visibility::Public));
+    assert!(data.delete_note(id));
+    assert!(data.restore_note(id, Visibility::Public));
+    assert_eq!(data.note(id).unwrap().visibility(), &Visibility::Public);
+    assert_eq!(data.note(id).unwrap().deleted(), None);
+    assert!(!data.restore_note(Id(999), Visibility::Public));
+}
+
+/// Notes only change their state with the transitions of their lifecycle
+pub fn lifecycle_transitions<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let id = *data
+        .add_note(draft("Title", &[], Visibility::Private), &user)
+        .id();
+    data.update_note(draft("Title", &[], Visibility::Deleted), id);
+    assert_eq!(data.note(id).unwrap().state(), State::Active);
+
+    assert!(data.set_archived(id, true));
+    assert!(data.delete_note(id));
+    assert!(!data.delete_note(id));
+    assert!(!data.set_archived(id, false));
+    assert!(!data.restore_note(id, Visibility::Deleted));
+    assert!(data.restore_note(id, Visibility::Private));
+    // archived notes stay archived after their restore
+    assert_eq!(data.note(id).unwrap().state(), State::Archived);
+}
+
+/// Only deleted notes are purged, with their comments
+pub fn purge_notes<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let active = *data
+        .add_note(draft("Active", &[], Visibility::Private), &user)
+        .id();
+    let deleted = *data
+        .add_note(draft("Deleted", &[], Visibility::Private), &user)
+        .id();
+    let _ = data.add_comment(deleted, CommentDraft::new("Comment".to_string()), &user);
+    assert!(data.delete_note(deleted));
+    assert_eq!(data.purge_notes(&[active, deleted, Id(999)]), 1);
+    assert!(data.note_with(deleted, VisibilityFilter::All).is_none());
+    assert!(data.comments(&deleted).is_empty());
+    assert!(data.note(active).is_some());
+    assert_eq!(data.purge_notes(&[deleted]), 0);
+}
+
+/// Notes are returned in the order of their Ids, which keeps pagination stable
+pub fn notes_ordered_by_id<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    for title in ["c", "a", "b"] {
+        let _ = data.add_note(draft(title, &[], Visibility::Private), &user);
+    }
+    let res = ids(data.notes());
+    let mut sorted = res.clone();
+    sorted.sort_by_key(|id| usize::from(id));
+    assert_eq!(res, sorted);
+    assert_eq!(ids(data.user_notes(&user)), sorted);
+}
+
+pub fn notes_with_filter<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let active = *data.add_note(Draft::default(), &user).id();
+    let deleted = *data.add_note(Draft::default(), &user).id();
+    data.delete_note(deleted);
+    assert_eq!(ids(data.notes_with(VisibilityFilter::Active)), vec![active]);
+    assert_eq!(
+        ids(data.notes_with(VisibilityFilter::Deleted)),
+        vec![deleted]
+    );
+    assert_eq!(
+        ids(data.notes_with(VisibilityFilter::All)),
+        vec![active, deleted]
+    );
+    assert_eq!(ids(data.notes()), vec![active]);
+}
+
+/// Expired notes are gone for all reads but [`VisibilityFilter::All`], even before they are removed
+pub fn expired_notes<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let expiring = |title: &str, expires_at: chrono::DateTime<Utc>| -> Draft {
+        serde_json::from_value(json!({
+            "title": title, "body": "", "tags": ["secret"], "visibility": "Public",
+            "expires_at": expires_at,
+        }))
+        .unwrap()
+    };
+    let now = Utc::now();
+    let expired = *data
+        .add_note(expiring("Expired", now - TimeDelta::seconds(1)), &user)
+        .id();
+    let expiring = *data
+        .add_note(expiring("Expiring", now + TimeDelta::hours(1)), &user)
+        .id();
+    assert_eq!(
+        ids(data.notes_with(VisibilityFilter::Active)),
+        vec![expiring]
+    );
+    assert_eq!(
+        ids(data.notes_with(VisibilityFilter::All)),
+        vec![expired, expiring]
+    );
+    assert!(data.note(expired).is_none());
+    assert!(data.note_with(expired, VisibilityFilter::All).is_some());
+    assert_eq!(ids(data.user_notes(&user)), vec![expiring]);
+    let tag = data.tag("secret").cloned().unwrap();
+    assert_eq!(ids(data.tagged_notes(&tag)), vec![expiring]);
+    assert_eq!(
+        ids(data.query_notes(&user, &NoteQuery::default())),
+        vec![expiring]
+    );
+
+    // archived notes don't expire
+    data.set_archived(expired, true);
+    assert!(data.note(expired).is_some());
+}
+
+/// Returns only the active notes of the user
+pub fn user_notes<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let other = data.add_user("other".to_string(), None).clone();
+    let own = *data.add_note(Draft::default(), &user).id();
+    let deleted = *data.add_note(Draft::default(), &user).id();
+    data.delete_note(deleted);
+    let _ = data.add_note(Draft::default(), &other);
+    assert_eq!(ids(data.user_notes(&user)), vec![own]);
+}
+
+pub fn note_by_slug<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let id = *data
+        .add_note(draft("My Note", &[], Visibility::Private), &user)
+        .id();
+    assert_eq!(data.note(id).unwrap().slug(), Some("my-note"));
+    assert_eq!(data.note_by_slug("my-note").map(Note::id), Some(&id));
+    data.delete_note(id);
+    assert!(data.note_by_slug("my-note").is_none());
+}
+
+/// Slugs are unique, even across deleted notes
+pub fn unique_slugs<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let first = *data
+        .add_note(draft("Title", &[], Visibility::Private), &user)
+        .id();
+    data.delete_note(first);
+    let second = data
+        .add_note(draft("Title", &[], Visibility::Private), &user)
+        .clone();
+    assert_eq!(second.slug(), Some("title-2"));
+}
+
+pub fn note_without_slug<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let note = data
+        .add_note(draft("!!!", &[], Visibility::Private), &user)
+        .clone();
+    assert!(note.slug().is_none());
+}
+
+/// Returns only active notes with the tag
+pub fn tagged_notes<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let tagged = *data
+        .add_note(draft("A", &["tag"], Visibility::Private), &user)
+        .id();
+    let deleted = *data
+        .add_note(draft("B", &["tag"], Visibility::Private), &user)
+        .id();
+    let _ = data.add_note(draft("C", &["other"], Visibility::Private), &user);
+    data.delete_note(deleted);
+    let tag = data.tag("tag").unwrap().clone();
+    assert_eq!(ids(data.tagged_notes(&tag)), vec![tagged]);
+}
+
+pub fn query_by_tag<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let tagged = *data
+        .add_note(draft("A", &["tag"], Visibility::Private), &user)
+        .id();
+    let _ = data.add_note(draft("B", &["other"], Visibility::Private), &user);
+    let query = NoteQuery::new(Some("tag".to_string()), None, None, None);
+    assert_eq!(ids(data.query_notes(&user, &query)), vec![tagged]);
+}
+
+pub fn query_by_text<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let milk = *data
+        .add_note(draft("Buy milk", &[], Visibility::Private), &user)
+        .id();
+    let _ = data.add_note(draft("Buy bread", &[], Visibility::Private), &user);
+    let query = NoteQuery::new(None, Some("milk".to_string()), None, None);
+    assert_eq!(ids(data.query_notes(&user, &query)), vec![milk]);
+}
+
+pub fn query_by_visibility<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let public = *data
+        .add_note(draft("A", &[], Visibility::Public), &user)
+        .id();
+    let _ = data.add_note(draft("B", &[], Visibility::Private), &user);
+    let query = NoteQuery::new(None, None, Some(Visibility::Public), None);
+    assert_eq!(ids(data.query_notes(&user, &query)), vec![public]);
+}
+
+/// The [`NoteState`] selects active, archived or deleted notes
+pub fn query_by_state<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let active = *data
+        .add_note(draft("A", &[], Visibility::Private), &user)
+        .id();
+    let archived = *data
+        .add_note(draft("B", &[], Visibility::Private), &user)
+        .id();
+    let deleted = *data
+        .add_note(draft("C", &[], Visibility::Private), &user)
+        .id();
+    data.set_archived(archived, true);
+    data.delete_note(deleted);
+    let query = |state| NoteQuery::default().with_state(state);
+    assert_eq!(
+        ids(data.query_notes(&user, &NoteQuery::default())),
+        vec![active]
+    );
+    assert_eq!(
+        ids(data.query_notes(&user, &query(NoteState::Archived))),
+        vec![archived]
+    );
+    assert_eq!(
+        ids(data.query_notes(&user, &query(NoteState::Deleted))),
+        vec![deleted]
+    );
+    assert_eq!(
+        ids(data.query_notes(&user, &query(NoteState::All))),
+        vec![active, archived, deleted]
+    );
+}
+
+pub fn query_sorted<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let b = *data
+        .add_note(draft("b", &[], Visibility::Private), &user)
+        .id();
+    let a = *data
+        .add_note(draft("a", &[], Visibility::Private), &user)
+        .id();
+    let query = NoteQuery::new(None, None, None, Some(SortKey::Title.into()));
+    assert_eq!(ids(data.query_notes(&user, &query)), vec![a, b]);
+    let query = NoteQuery::new(None, None, None, Some(SortKey::Id.into()));
+    assert_eq!(ids(data.query_notes(&user, &query)), vec![b, a]);
+}
+
+/// Notes are sorted by all keys of the [`SortOrder`](crate::models::query::SortOrder), ties by their Id
+pub fn query_multi_sorted<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let mut add = |title: &str| {
+        *data
+            .add_note(draft(title, &[], Visibility::Private), &user)
+            .id()
+    };
+    let b = add("b");
+    let a1 = add("a");
+    let a2 = add("a");
+    let query = NoteQuery::new(
+        None,
+        None,
+        None,
+        Some("title,id:desc".to_string().try_into().unwrap()),
+    );
+    assert_eq!(ids(data.query_notes(&user, &query)), vec![a2, a1, b]);
+    let query = NoteQuery::new(
+        None,
+        None,
+        None,
+        Some("title:desc".to_string().try_into().unwrap()),
+    );
+    assert_eq!(ids(data.query_notes(&user, &query)), vec![b, a1, a2]);
+}
+
+/// Queries never return notes of other users
+pub fn query_other_user<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let other = data.add_user("other".to_string(), None).clone();
+    let _ = data.add_note(draft("A", &[], Visibility::Public), &other);
+    assert_eq!(data.query_notes(&user, &NoteQuery::default()).count(), 0);
+}
+
+/// Returns only the notes that changed
+pub fn bulk_tag<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let first = *data
+        .add_note(draft("A", &["todo"], Visibility::Private), &user)
+        .id();
+    let second = *data
+        .add_note(draft("B", &["done"], Visibility::Private), &user)
+        .id();
+    let changed = data.bulk_tag(
+        &[first, second],
+        &["done".to_string()],
+        &["todo".to_string()],
+    );
+    assert_eq!(changed, vec![first]);
+    let done = data.tag("done").unwrap().clone();
+    assert_eq!(ids(data.tagged_notes(&done)), vec![first, second]);
+    assert!(!data
+        .note(first)
+        .unwrap()
+        .tags()
+        .any(|tag| tag.label() == "todo"));
+}
+
+pub fn bulk_tag_unknown_notes<P: for<'a> Persister<'a>>(mut data: P) {
+    assert!(data
+        .bulk_tag(&[Id(999)], &["tag".to_string()], &[])
+        .is_empty());
+}
+
+pub fn bulk_tag_new_tags<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let id = *data.add_note(Draft::default(), &user).id();
+    assert_eq!(data.bulk_tag(&[id], &["new".to_string()], &[]), vec![id]);
+    assert!(data.note(id).unwrap().tagged_with(data.tag("new").unwrap()));
+}
+
+/// Adding an existing label returns the existing tag
+pub fn add_tag<P: for<'a> Persister<'a>>(mut data: P) {
+    let id = data.add_tag("tag".to_string());
+    assert_eq!(data.add_tag("tag".to_string()), id);
+    assert_eq!(data.tag("tag").unwrap().id(), &id);
+    assert_eq!(data.tags().count(), 1);
+}
+
+/// The metadata of a tag is also changed in all notes with the tag
+pub fn update_tag<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let id = *data
+        .add_note(draft("A", &["tag"], Visibility::Private), &user)
+        .id();
+    let tag = *data.tag("tag").unwrap().id();
+    let meta = TagMeta::new(Some("#00ff00".to_string()), None);
+    let updated = data.update_tag(tag, meta).expect("tag exists").clone();
+    assert_eq!(updated.color(), Some("#00ff00"));
+    assert_eq!(data.tag("tag"), Some(&updated));
+    assert_eq!(data.note(id).unwrap().tags().next(), Some(&updated));
+    assert_eq!(ids(data.tagged_notes(&updated)), vec![id]);
+    assert!(data.update_tag(Id(99), TagMeta::default()).is_none());
+}
+
+/// Every change of the title or body is a revision that can be reconstructed
+pub fn revisions<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let id = *data
+        .add_note(draft("A", &[], Visibility::Private), &user)
+        .id();
+    data.update_note(draft("B", &[], Visibility::Private), id);
+    // only the tags changed
+    data.update_note(draft("B", &["tag"], Visibility::Private), id);
+    let edited = Draft::new(
+        "B".to_string(),
+        "Body of B\nwith a second line".to_string(),
+        vec![],
+        Visibility::Private,
+    );
+    data.update_note(edited, id);
+
+    let titles = data
+        .revisions(&id)
+        .into_iter()
+        .map(|revision| revision.title)
+        .collect::<Vec<String>>();
+    assert_eq!(titles, vec!["A", "B", "B"]);
+    assert_eq!(data.revision(&id, 1).unwrap().body, "Body of A");
+    assert_eq!(data.revision(&id, 2).unwrap().body, "Body of B");
+    assert_eq!(
+        data.revision(&id, 3).unwrap().body,
+        "Body of B\nwith a second line"
+    );
+    assert!(data.revision(&id, 4).is_none());
+    assert!(data.revision(&Id(99), 1).is_none());
+    assert!(data.revisions(&Id(99)).is_empty());
+}
+
+/// Notes have the title and body of their last revision before the time, deleted notes
+/// are included if they were deleted afterwards
+pub fn notes_as_of<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let tick = || {
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        Utc::now()
+    };
+    let start = tick();
+    let edited = *data
+        .add_note(draft("A", &[], Visibility::Private), &user)
+        .id();
+    let deleted = *data
+        .add_note(draft("Deleted", &[], Visibility::Private), &user)
+        .id();
+    let created = tick();
+    data.update_note(draft("B", &[], Visibility::Private), edited);
+    data.delete_note(deleted);
+    let later = *data
+        .add_note(draft("Later", &[], Visibility::Private), &user)
+        .id();
+    let now = tick();
+
+    assert!(data.notes_as_of(&user, &start).is_empty());
+    let notes = data.notes_as_of(&user, &created);
+    assert_eq!(ids(&notes), vec![edited, deleted]);
+    assert_eq!(notes[0].title(), "A");
+    assert_eq!(notes[0].body().text(), "Body of A");
+    let notes = data.notes_as_of(&user, &now);
+    assert_eq!(ids(&notes), vec![edited, later]);
+    assert_eq!(notes[0].title(), "B");
+    let other = data.add_user("other".to_string(), None).clone();
+    assert!(data.notes_as_of(&other, &now).is_empty());
+}
+
+/// Only tags of active notes are kept
+pub fn collect_tags<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let id = *data
+        .add_note(draft("A", &["kept", "changed"], Visibility::Private), &user)
+        .id();
+    let deleted = *data
+        .add_note(draft("B", &["deleted"], Visibility::Private), &user)
+        .id();
+    let _ = data.add_tag("unused".to_string());
+    data.update_note(draft("A", &["kept"], Visibility::Private), id);
+    data.delete_note(deleted);
+    assert_eq!(data.collect_tags(), 3);
+    assert_eq!(data.collect_tags(), 0);
+    let labels = data
+        .tags()
+        .map(|tag| tag.label().to_string())
+        .collect::<Vec<String>>();
+    assert_eq!(labels, vec!["kept"]);
+}
+
+/// Restored notes get their collected tags back
+pub fn restore_collected_tags<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let id = *data
+        .add_note(draft("A", &["tag"], Visibility::Private), &user)
+        .id();
+    data.delete_note(id);
+    assert_eq!(data.collect_tags(), 1);
+    assert!(data.restore_note(id, Visibility::Private));
+    let tag = data.tag("tag").unwrap().clone();
+    assert_eq!(ids(data.tagged_notes(&tag)), vec![id]);
+}
+
+/// Counts the active notes of the user per tag
+pub fn tag_stats<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let other = data.add_user("other".to_string(), None).clone();
+    let _ = data.add_note(draft("A", &["a", "b"], Visibility::Private), &user);
+    let _ = data.add_note(draft("B", &["a"], Visibility::Private), &user);
+    let deleted = *data
+        .add_note(draft("C", &["a"], Visibility::Private), &user)
+        .id();
+    data.delete_note(deleted);
+    let _ = data.add_note(draft("D", &["a", "c"], Visibility::Private), &other);
+    let stats = data.tag_stats(&user);
+    let count = |label: &str| {
+        stats
+            .iter()
+            .find(|stats| stats.tag().label() == label)
+            .map(|stats| stats.count())
+    };
+    assert_eq!(count("a"), Some(2));
+    assert_eq!(count("b"), Some(1));
+    assert_eq!(count("c"), Some(0));
+}
+
+pub fn add_search<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let query = NoteQuery::new(Some("tag".to_string()), None, None, None);
+    let search = data
+        .add_search(SearchDraft::new("Tagged".to_string(), query.clone()), &user)
+        .clone();
+    assert_eq!(search.name(), "Tagged");
+    assert_eq!(search.user(), user.id());
+    assert_eq!(search.query(), &query);
+    assert_eq!(data.search(*search.id()), Some(&search));
+}
+
+pub fn user_searches<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let other = data.add_user("other".to_string(), None).clone();
+    let own = *data
+        .add_search(
+            SearchDraft::new("Own".to_string(), NoteQuery::default()),
+            &user,
+        )
+        .id();
+    let _ = data.add_search(
+        SearchDraft::new("Other".to_string(), NoteQuery::default()),
+        &other,
+    );
+    let res = data
+        .user_searches(&user)
+        .map(|search| *search.id())
+        .collect::<Vec<Id>>();
+    assert_eq!(res, vec![own]);
+}
+
+/// Favorites are stored per user and only include active notes
+pub fn favorites<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let alice = *data.add_user("alice".to_string(), None).id();
+    let first = *data.add_note(Draft::default(), &user).id();
+    let second = *data.add_note(Draft::default(), &user).id();
+    assert!(data.set_favorite(*user.id(), second, true));
+    assert!(data.set_favorite(*user.id(), first, true));
+    assert!(!data.set_favorite(*user.id(), first, true));
+    assert!(data.set_favorite(alice, first, true));
+    assert_eq!(ids(data.favorites(user.id())), vec![first, second]);
+    data.delete_note(second);
+    assert_eq!(ids(data.favorites(user.id())), vec![first]);
+    assert!(data.set_favorite(*user.id(), first, false));
+    assert!(!data.set_favorite(*user.id(), first, false));
+    assert!(data.favorites(user.id()).is_empty());
+    assert_eq!(ids(data.favorites(&alice)), vec![first]);
+}
+
+/// Comments are returned per note, oldest first
+pub fn comments<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let note = *data.add_note(Draft::default(), &user).id();
+    let other = *data.add_note(Draft::default(), &user).id();
+    let first = *data
+        .add_comment(note, CommentDraft::new("First".to_string()), &alice)
+        .id();
+    let second = *data
+        .add_comment(note, CommentDraft::new("Second".to_string()), &user)
+        .id();
+    let _ = data.add_comment(other, CommentDraft::new("Other".to_string()), &alice);
+    let comment = data.comment(first).unwrap();
+    assert_eq!(comment.note(), &note);
+    assert_eq!(comment.author(), alice.id());
+    assert_eq!(comment.body(), "First");
+    let res = data
+        .comments(&note)
+        .iter()
+        .map(|comment| *comment.id())
+        .collect::<Vec<Id>>();
+    assert_eq!(res, vec![first, second]);
+    assert_eq!(data.user_comments(alice.id()).len(), 2);
+    assert!(data.comments(&Id(999)).is_empty());
+}
+
+pub fn delete_comment<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let note = *data.add_note(Draft::default(), &user).id();
+    let id = *data
+        .add_comment(note, CommentDraft::new("Comment".to_string()), &user)
+        .id();
+    assert!(data.delete_comment(id));
+    assert!(data.comment(id).is_none());
+    assert!(data.comments(&note).is_empty());
+    assert!(!data.delete_comment(id));
+}
+
+/// The export contains deleted notes as well
+pub fn export_user<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let _ = data.add_note(Draft::default(), &user);
+    let deleted = *data.add_note(Draft::default(), &user).id();
+    data.delete_note(deleted);
+    let _ = data.add_search(
+        SearchDraft::new("Search".to_string(), NoteQuery::default()),
+        &user,
+    );
+    let _ = data.add_comment(deleted, CommentDraft::new("Comment".to_string()), &user);
+    let export = data.export_user(&user);
+    assert_eq!(export.notes().len(), 2);
+    assert_eq!(export.searches().len(), 1);
+    assert_eq!(export.comments().len(), 1);
+}
+
+/// Purging removes the user with all notes, including deleted ones, and searches
+pub fn purge_user<P: for<'a> Persister<'a>>(mut data: P) {
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let user = anonymous(&data);
+    let _ = data.add_note(Draft::default(), &alice);
+    let deleted = *data.add_note(Draft::default(), &alice).id();
+    data.delete_note(deleted);
+    let kept = *data.add_note(Draft::default(), &user).id();
+    let _ = data.add_search(
+        SearchDraft::new("Search".to_string(), NoteQuery::default()),
+        &alice,
+    );
+    assert!(data.purge_user(*alice.id()));
+    assert!(data.user(*alice.id()).is_none());
+    assert_eq!(ids(data.notes_with(VisibilityFilter::All)), vec![kept]);
+    assert_eq!(data.searches().count(), 0);
+}
+
+pub fn purge_unknown_user<P: for<'a> Persister<'a>>(mut data: P) {
+    assert!(!data.purge_user(Id(999)));
+}
+
+/// Tags that are still used by other users are kept
+pub fn purge_user_tags<P: for<'a> Persister<'a>>(mut data: P) {
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let user = anonymous(&data);
+    let _ = data.add_note(draft("A", &["shared", "own"], Visibility::Private), &alice);
+    let _ = data.add_note(draft("B", &["shared"], Visibility::Private), &user);
+    assert!(data.purge_user(*alice.id()));
+    assert!(data.tag("shared").is_some());
+    assert!(data.tag("own").is_none());
+}
+
+/// Purging removes all other data of the user as well
+pub fn purge_user_related_data<P: for<'a> Persister<'a>>(mut data: P) {
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let note = *data.add_note(Draft::default(), &alice).id();
+    data.add_idempotency_record(IdempotencyRecord::new(
+        *alice.id(),
+        "key".to_string(),
+        json!({}),
+        json!({}),
+    ));
+    let token = UndoToken::new(note, *alice.id(), Visibility::Private, Utc::now());
+    data.add_undo_token(token.clone());
+    data.add_follower(Follower::new(
+        *alice.id(),
+        "https://a.example/users/x".to_string(),
+        "https://a.example/inbox".to_string(),
+    ));
+    data.set_mentions(note, HashSet::from([*alice.id()]));
+    let user = anonymous(&data);
+    let own = *data.add_note(Draft::default(), &user).id();
+    data.record_access(AccessEntry::new(own, Some(*alice.id()), Utc::now()));
+    data.record_access(AccessEntry::new(own, None, Utc::now()));
+    let _ = data.add_comment(own, CommentDraft::new("Comment".to_string()), &alice);
+    let _ = data.add_comment(note, CommentDraft::new("Comment".to_string()), &user);
+
+    assert!(data.purge_user(*alice.id()));
+    assert!(data.idempotency_record(&alice, "key").is_none());
+    assert!(data.undo_token(token.token()).is_none());
+    assert!(data.followers(alice.id()).is_empty());
+    assert!(data.mentions(&alice).is_empty());
+    assert!(data.comments(&own).is_empty());
+    assert!(data.user_comments(user.id()).is_empty());
+    assert_eq!(data.access_log(&own).len(), 1);
+    assert!(data.access_log(&own)[0].user().is_none());
+}
+
+/// Records are stored per user and key
+pub fn idempotency_records<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let other = data.add_user("other".to_string(), None).clone();
+    let record = IdempotencyRecord::new(*user.id(), "key".to_string(), json!(1), json!(2));
+    data.add_idempotency_record(record.clone());
+    assert_eq!(data.idempotency_record(&user, "key"), Some(&record));
+    assert!(data.idempotency_record(&user, "other").is_none());
+    assert!(data.idempotency_record(&other, "key").is_none());
+}
+
+pub fn idempotency_record_replaced<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    data.add_idempotency_record(IdempotencyRecord::new(
+        *user.id(),
+        "key".to_string(),
+        json!(1),
+        json!(1),
+    ));
+    let record = IdempotencyRecord::new(*user.id(), "key".to_string(), json!(2), json!(2));
+    data.add_idempotency_record(record.clone());
+    assert_eq!(data.idempotency_record(&user, "key"), Some(&record));
+}
+
+pub fn expire_idempotency_records<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let record = IdempotencyRecord::new(*user.id(), "key".to_string(), json!(1), json!(1));
+    data.add_idempotency_record(record.clone());
+    let created = *record.created();
+    assert_eq!(data.expire_idempotency_records(&created), 0);
+    assert_eq!(
+        data.expire_idempotency_records(&(created + TimeDelta::seconds(1))),
+        1
+    );
+    assert!(data.idempotency_record(&user, "key").is_none());
+}
+
+/// Views are stored per user and note, returning the previous view
+pub fn views<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let alice = *data.add_user("alice".to_string(), None).id();
+    let note = *data.add_note(Draft::default(), &user).id();
+    let first = Utc::now();
+    let second = first + TimeDelta::seconds(1);
+    assert!(data.set_viewed(*user.id(), note, first).is_none());
+    assert_eq!(data.set_viewed(*user.id(), note, second), Some(first));
+    assert_eq!(data.viewed_at(user.id(), &note), Some(second));
+    assert!(data.viewed_at(&alice, &note).is_none());
+    assert!(data.purge_user(alice));
+    assert_eq!(data.viewed_at(user.id(), &note), Some(second));
+}
+
+pub fn trash_confirmations<P: for<'a> Persister<'a>>(mut data: P) {
+    let confirmation =
+        TrashConfirmation::new(Id(0), vec![Id(1)], Utc::now() + TimeDelta::seconds(60));
+    data.add_trash_confirmation(confirmation.clone());
+    assert_eq!(
+        data.trash_confirmation(confirmation.token()),
+        Some(&confirmation)
+    );
+    assert!(data.remove_trash_confirmation("unknown").is_none());
+    assert_eq!(
+        data.remove_trash_confirmation(confirmation.token()),
+        Some(confirmation.clone())
+    );
+    assert!(data
+        .remove_trash_confirmation(confirmation.token())
+        .is_none());
+}
+
+pub fn undo_tokens<P: for<'a> Persister<'a>>(mut data: P) {
+    let token = UndoToken::new(Id(1), Id(0), Visibility::Public, Utc::now());
+    data.add_undo_token(token.clone());
+    assert_eq!(data.undo_token(token.token()), Some(&token));
+    assert!(data.undo_token("unknown").is_none());
+    assert_eq!(data.remove_undo_token(token.token()), Some(token.clone()));
+    assert!(data.remove_undo_token(token.token()).is_none());
+}
+
+/// Only tokens that expired before the given time are removed
+pub fn expire_undo_tokens<P: for<'a> Persister<'a>>(mut data: P) {
+    let now = Utc::now();
+    let expired = UndoToken::new(
+        Id(1),
+        Id(0),
+        Visibility::Public,
+        now - TimeDelta::seconds(1),
+    );
+    let valid = UndoToken::new(Id(2), Id(0), Visibility::Public, now);
+    data.add_undo_token(expired.clone());
+    data.add_undo_token(valid.clone());
+    assert_eq!(data.expire_undo_tokens(&now), 1);
+    assert!(data.undo_token(expired.token()).is_none());
+    assert!(data.undo_token(valid.token()).is_some());
+}
+
+/// Working copies are stored per note and expire like undo tokens
+pub fn working_copies<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let note = *data.add_note(Draft::default(), &user).id();
+    let other = *data.add_note(Draft::default(), &user).id();
+    let now = Utc::now();
+    let first = WorkingCopy::new(
+        note,
+        *user.id(),
+        draft("First", &[], Visibility::Private),
+        now,
+    );
+    let second = WorkingCopy::new(
+        note,
+        *user.id(),
+        draft("Second", &[], Visibility::Private),
+        now,
+    );
+    assert!(data.set_working_copy(first.clone()).is_none());
+    assert_eq!(data.set_working_copy(second.clone()), Some(first));
+    assert_eq!(data.working_copy(&note), Some(&second));
+    assert!(data.working_copy(&other).is_none());
+    assert_eq!(data.remove_working_copy(&note), Some(second));
+    assert!(data.remove_working_copy(&note).is_none());
+
+    let expired = WorkingCopy::new(
+        note,
+        *user.id(),
+        Draft::default(),
+        now - TimeDelta::seconds(1),
+    );
+    let _ = data.set_working_copy(expired);
+    let _ = data.set_working_copy(WorkingCopy::new(other, *user.id(), Draft::default(), now));
+    assert_eq!(data.expire_working_copies(&now), 1);
+    assert!(data.working_copy(&note).is_none());
+    assert!(data.working_copy(&other).is_some());
+
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let note = *data.add_note(Draft::default(), &alice).id();
+    let _ = data.set_working_copy(WorkingCopy::new(note, *alice.id(), Draft::default(), now));
+    assert!(data.purge_user(*alice.id()));
+    assert!(data.working_copy(&note).is_none());
+}
+
+/// A backup contains all users, notes (including deleted ones), tags and related data
+pub fn backup<P: for<'a> Persister<'a>>(mut data: P) {
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let note = *data
+        .add_note(draft("Foo", &["foo"], Visibility::Public), &alice)
+        .id();
+    let deleted = *data
+        .add_note(draft("Bar", &[], Visibility::Private), &alice)
+        .id();
+    data.delete_note(deleted);
+    data.set_favorite(*alice.id(), note, true);
+    let _ = data.add_comment(note, CommentDraft::new("Nice".to_string()), &alice);
+    let backup = data.backup();
+    assert!(backup.validate().is_empty());
+    assert_eq!(backup.users().len(), 2);
+    assert_eq!(ids(backup.notes()), vec![note, deleted]);
+    assert_eq!(backup.tags().len(), 1);
+    assert_eq!(backup.comments().len(), 1);
+    assert_eq!(backup.favorites(), &[(*alice.id(), note)]);
+    assert!(data.merge_conflicts(&backup).is_empty());
+}
+
+/// Replacing removes all data that is not in the backup, without re-using Ids
+pub fn restore_replace<P: for<'a> Persister<'a>>(mut data: P) {
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let note = *data
+        .add_note(draft("Foo", &["foo"], Visibility::Public), &alice)
+        .id();
+    let backup = data.backup();
+
+    let bob = data.add_user("bob".to_string(), None).clone();
+    let added = *data
+        .add_note(draft("Bar", &["bar"], Visibility::Public), &bob)
+        .id();
+    let _ = data.update_note(draft("Changed", &[], Visibility::Public), note);
+    let mut restored = 0;
+    data.restore(&backup, RestoreMode::Replace, &mut |count| restored = count);
+    assert_eq!(restored, backup.records());
+    assert!(data.user(*bob.id()).is_none());
+    assert_eq!(ids(data.notes_with(VisibilityFilter::All)), vec![note]);
+    assert_eq!(data.note(note).unwrap().title(), "Foo");
+    assert!(data.tag("bar").is_none());
+    assert!(data.revisions(&note).is_empty());
+
+    let new = *data.add_note(Draft::default(), &alice).id();
+    assert!(new != note && new != added);
+}
+
+/// Merging adds missing records and keeps the existing ones
+pub fn restore_merge<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let note = *data
+        .add_note(draft("Foo", &["foo"], Visibility::Public), &alice)
+        .id();
+    let backup = data.backup();
+
+    let added = *data
+        .add_note(draft("Bar", &[], Visibility::Public), &user)
+        .id();
+    assert!(data.purge_user(*alice.id()));
+    assert!(data.merge_conflicts(&backup).is_empty());
+    data.restore(&backup, RestoreMode::Merge, &mut |_| {});
+    assert_eq!(data.user(*alice.id()), Some(&alice));
+    assert_eq!(ids(data.notes()), vec![note, added]);
+    assert!(data.tag("foo").is_some());
+
+    let _ = data.update_note(draft("Changed", &[], Visibility::Public), note);
+    assert_eq!(data.merge_conflicts(&backup).len(), 1);
+}
+
+/// Flags are stored per note and removed with the notes of purged users
+pub fn flags<P: for<'a> Persister<'a>>(mut data: P) {
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let first = *data.add_note(Draft::default(), &alice).id();
+    let second = *data.add_note(Draft::default(), &alice).id();
+    let reason = |word: &str| Reason::BannedWord {
+        word: word.to_string(),
+    };
+    let flag = Flag::new(second, *alice.id(), vec![reason("spam")], None);
+    assert!(data.set_flag(flag.clone()).is_none());
+    let quarantined = Flag::new(
+        first,
+        *alice.id(),
+        vec![reason("casino")],
+        Some(Visibility::Public),
+    );
+    let _ = data.set_flag(quarantined.clone());
+    assert_eq!(data.flags(), vec![&quarantined, &flag]);
+    assert_eq!(data.flag(&second), Some(&flag));
+
+    let replaced = Flag::new(second, *alice.id(), vec![reason("ads")], None);
+    assert_eq!(data.set_flag(replaced.clone()), Some(flag));
+    assert_eq!(data.remove_flag(&first), Some(quarantined));
+    assert!(data.flag(&first).is_none());
+    assert_eq!(data.flags(), vec![&replaced]);
+
+    assert!(data.purge_user(*alice.id()));
+    assert!(data.flags().is_empty());
+}
+
+/// Service tokens are found by their secret and listed per user
+pub fn service_tokens<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let draft = TokenDraft::new("CI".to_string(), BTreeSet::from([Scope::NotesRead]));
+    let token = data
+        .add_service_token(&user, draft.clone(), "nt_first".to_string())
+        .clone();
+    let other = *data
+        .add_service_token(&alice, draft, "nt_second".to_string())
+        .id();
+    assert_ne!(token.id(), &other);
+    assert_eq!(token.user(), user.id());
+    assert_eq!(token.name(), "CI");
+    assert!(token.allows(Scope::NotesRead));
+    assert_eq!(data.service_token("nt_first"), Some(&token));
+    assert!(data.service_token("nt_unknown").is_none());
+    assert_eq!(data.service_tokens(user.id()), vec![&token]);
+
+    assert!(data.revoke_service_token(*token.id()));
+    assert!(!data.revoke_service_token(*token.id()));
+    assert!(data.service_token("nt_first").is_none());
+    assert!(data.service_tokens(user.id()).is_empty());
+    assert!(data.purge_user(*alice.id()));
+    assert!(data.service_token("nt_second").is_none());
+}
+
+/// Returns only the users that were not mentioned before
+pub fn set_mentions<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let alice = *data.add_user("alice".to_string(), None).id();
+    let note = *data.add_note(Draft::default(), &user).id();
+    assert_eq!(data.set_mentions(note, HashSet::from([alice])), vec![alice]);
+    assert!(data.set_mentions(note, HashSet::from([alice])).is_empty());
+    assert_eq!(
+        data.set_mentions(note, HashSet::from([alice, Id(0)])),
+        vec![Id(0)]
+    );
+    assert!(data.set_mentions(note, HashSet::new()).is_empty());
+    assert_eq!(data.set_mentions(note, HashSet::from([alice])), vec![alice]);
+}
+
+/// Returns the notes that mention the user, regardless of their visibility
+pub fn mentions<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let alice = data.add_user("alice".to_string(), None).clone();
+    let first = *data.add_note(Draft::default(), &user).id();
+    let second = *data.add_note(Draft::default(), &user).id();
+    data.set_mentions(first, HashSet::from([*alice.id()]));
+    data.set_mentions(second, HashSet::from([*alice.id()]));
+    data.delete_note(second);
+    assert_eq!(ids(data.mentions(&alice)), vec![first, second]);
+    assert!(data.mentions(&user).is_empty());
+    data.set_mentions(first, HashSet::new());
+    assert_eq!(ids(data.mentions(&alice)), vec![second]);
+}
+
+/// Followers are unique per user and actor
+pub fn followers<P: for<'a> Persister<'a>>(mut data: P) {
+    let follower =
+        |actor: &str, inbox: &str| Follower::new(Id(0), actor.to_string(), inbox.to_string());
+    data.add_follower(follower("https://a.example/x", "https://a.example/inbox"));
+    data.add_follower(follower("https://b.example/y", "https://b.example/inbox"));
+    data.add_follower(follower("https://a.example/x", "https://a.example/x/inbox"));
+    let followers = data.followers(&Id(0));
+    assert_eq!(followers.len(), 2);
+    assert!(followers.contains(&&follower(
+        "https://a.example/x",
+        "https://a.example/x/inbox"
+    )));
+    assert!(data.followers(&Id(1)).is_empty());
+}
+
+pub fn remove_follower<P: for<'a> Persister<'a>>(mut data: P) {
+    data.add_follower(Follower::new(
+        Id(0),
+        "https://a.example/x".to_string(),
+        "https://a.example/inbox".to_string(),
+    ));
+    assert!(!data.remove_follower(&Id(1), "https://a.example/x"));
+    assert!(data.remove_follower(&Id(0), "https://a.example/x"));
+    assert!(!data.remove_follower(&Id(0), "https://a.example/x"));
+    assert!(data.followers(&Id(0)).is_empty());
+}
+
+/// The access log is kept per note, oldest first, and bounded
+/// Usage is summed up per user and day
+pub fn usage<P: for<'a> Persister<'a>>(mut data: P) {
+    let alice = *data.add_user("alice".to_string(), None).id();
+    let day = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+    let next = day.succ_opt().unwrap();
+    data.record_usage(Id(0), DailyUsage::request(day, false, 0, 100));
+    data.record_usage(Id(0), DailyUsage::request(day, true, 20, 10));
+    data.record_usage(Id(0), DailyUsage::request(next, false, 0, 1));
+    data.record_usage(alice, DailyUsage::request(day, false, 0, 1));
+
+    let mut expected = DailyUsage::request(day, false, 0, 100);
+    expected.add(&DailyUsage::request(day, true, 20, 10));
+    assert_eq!(data.usage(&Id(0), day, day), vec![expected]);
+    assert_eq!(data.usage(&Id(0), day, next).len(), 2);
+    assert!(data.usage(&Id(0), next, day).is_empty());
+    assert!(data.purge_user(alice));
+    assert!(data.usage(&alice, day, next).is_empty());
+}
+
+pub fn access_log<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let note = *data.add_note(Draft::default(), &user).id();
+    let other = *data.add_note(Draft::default(), &user).id();
+    let start = Utc::now();
+    for minutes in 0..=ACCESS_LOG_CAPACITY as i64 {
+        data.record_access(AccessEntry::new(
+            note,
+            Some(Id(1)),
+            start + TimeDelta::minutes(minutes),
+        ));
+    }
+    data.record_access(AccessEntry::new(other, None, start));
+    let log = data.access_log(&note);
+    assert_eq!(log.len(), ACCESS_LOG_CAPACITY);
+    assert_eq!(log[0].at(), &(start + TimeDelta::minutes(1)));
+    assert!(log.windows(2).all(|pair| pair[0].at() < pair[1].at()));
+    assert_eq!(data.access_log(&other).len(), 1);
+    assert!(data.access_log(&Id(99)).is_empty());
+}
+
+/// The audit log is kept per user, oldest first, bounded and removed with the user
+pub fn audit_log<P: for<'a> Persister<'a>>(mut data: P) {
+    let alice = *data.add_user("alice".to_string(), None).id();
+    let start = Utc::now();
+    for minutes in 0..=AUDIT_LOG_CAPACITY as i64 {
+        data.record_audit(AuditEntry::new(
+            alice,
+            Id(0),
+            "GET /notes".to_string(),
+            200,
+            start + TimeDelta::minutes(minutes),
+        ));
+    }
+    data.record_audit(AuditEntry::new(
+        Id(0),
+        alice,
+        "PUT /note/1".to_string(),
+        404,
+        start,
+    ));
+    let log = data.audit_log(&alice);
+    assert_eq!(log.len(), AUDIT_LOG_CAPACITY);
+    assert_eq!(log[0].at(), &(start + TimeDelta::minutes(1)));
+    assert!(log.windows(2).all(|pair| pair[0].at() < pair[1].at()));
+    assert_eq!(data.audit_log(&Id(0)).len(), 1);
+    assert_eq!(data.audit_log(&Id(0))[0].admin(), &alice);
+    assert!(data.purge_user(alice));
+    assert!(data.audit_log(&alice).is_empty());
+}
+
+/// Fresh backends have room for new notes
+pub fn reserve_notes<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let _ = data.add_note(Draft::default(), &user);
+    assert_eq!(data.reserve_notes(10), Some(vec![]));
+    assert_eq!(data.notes_with(VisibilityFilter::All).count(), 1);
+}
+
+/// Returns the notes within the radius, closest first
+pub fn notes_near<P: for<'a> Persister<'a>>(mut data: P) {
+    let user = anonymous(&data);
+    let center = Location::new(48.137, 11.575).unwrap();
+    let mut add = |lat: f64, lon: f64| {
+        let draft = Draft::default().with_location(Location::new(lat, lon).unwrap());
+        *data.add_note(draft, &user).id()
+    };
+    let far = add(48.2, 11.6);
+    let close = add(48.138, 11.575);
+    let _ = add(52.52, 13.405);
+    let _ = data.add_note(Draft::default(), &user);
+    assert_eq!(
+        ids(data.notes_near(&user, &center, 10_000.0)),
+        vec![close, far]
+    );
+}
+
+/// Backends with [`Capabilities::full_text_search`](crate::persistence::Capabilities)
+/// find the active notes of the user containing all words
+pub fn search_notes<P: for<'a> Persister<'a>>(mut data: P) {
+    if !data.capabilities().full_text_search {
+        return;
+    }
+    let user = anonymous(&data);
+    let other = data.add_user("other".to_string(), None).clone();
+    let milk = *data
+        .add_note(draft("Buy milk", &[], Visibility::Private), &user)
+        .id();
+    let deleted = *data
+        .add_note(draft("Buy more milk", &[], Visibility::Private), &user)
+        .id();
+    data.delete_note(deleted);
+    let _ = data.add_note(draft("Buy milk", &[], Visibility::Private), &other);
+    assert_eq!(ids(data.search_notes(&user, "milk buy")), vec![milk]);
+}
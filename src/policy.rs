@@ -0,0 +1,258 @@
+//! Who may do what with the notes and other records of a user
+//!
+//! Handlers don't compare owners themselves, they ask the [`Policy`] if the user sending the
+//! request may perform an [`Action`] on a [`Resource`]. The rules are:
+//!
+//! * the owner may do everything
+//! * other users may read, list and comment on notes whose [`Visibility`] allows it
+//! * admins (`NOTE_ADMIN_USERS`) may also read, inspect and delete all notes, e.g. to moderate
+//!   them, but never edit or comment on notes of other users
+//!
+//! Rejections are consistent: records that the user may not even read are reported as
+//! `404 Not Found`, so their existence is not revealed. Records that the user can read but
+//! not change are rejected with `403 Forbidden`.
+//!
+//! [`Visibility`]: crate::models::Visibility
+use axum::http::StatusCode;
+use tracing::info;
+
+use crate::models::comment::Comment;
+use crate::models::note::Note;
+use crate::models::query::SavedSearch;
+use crate::models::trash::TrashConfirmation;
+use crate::models::undo::UndoToken;
+use crate::models::{Access, Id};
+
+/// What a user wants to do with a [`Resource`]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+    /// Open it by its Id or slug, e.g. `GET /note/:id`
+    Read,
+    /// Find it in lists, e.g. `GET /mentions`
+    List,
+    Comment,
+    /// Change the content, tags or state
+    Edit,
+    /// Move it to the trash, or restore it with the undo token of the deletion
+    Delete,
+    /// See details only meant for the owner, e.g. revisions and the access log
+    Inspect,
+}
+
+/// A record that belongs to a user
+pub trait Resource {
+    /// The name in error messages, e.g. `Note`
+    const KIND: &'static str;
+
+    fn owner(&self) -> &Id;
+
+    /// Returns `true` if the owner shares the record with the user
+    fn shared_with(&self, _user: &Id, _access: Access) -> bool {
+        false
+    }
+}
+
+impl Resource for Note {
+    const KIND: &'static str = "Note";
+
+    fn owner(&self) -> &Id {
+        self.user()
+    }
+
+    fn shared_with(&self, user: &Id, access: Access) -> bool {
+        self.visibility().allows(user, access)
+    }
+}
+
+impl Resource for Comment {
+    const KIND: &'static str = "Comment";
+
+    fn owner(&self) -> &Id {
+        self.author()
+    }
+}
+
+impl Resource for SavedSearch {
+    const KIND: &'static str = "Search";
+
+    fn owner(&self) -> &Id {
+        self.user()
+    }
+}
+
+impl Resource for UndoToken {
+    const KIND: &'static str = "Undo token";
+
+    fn owner(&self) -> &Id {
+        self.user()
+    }
+}
+
+impl Resource for TrashConfirmation {
+    const KIND: &'static str = "Confirmation token";
+
+    fn owner(&self) -> &Id {
+        self.user()
+    }
+}
+
+/// The authorization rules, see the [module documentation](self)
+#[derive(Clone, Copy, Debug)]
+pub struct Policy<'a> {
+    admins: &'a [Id],
+}
+
+impl<'a> Policy<'a> {
+    pub fn new(admins: &'a [Id]) -> Self {
+        Self { admins }
+    }
+
+    pub fn is_admin(&self, user: &Id) -> bool {
+        self.admins.contains(user)
+    }
+
+    /// Returns `true` if the user may perform the action on the resource
+    pub fn can<R: Resource>(&self, user: &Id, action: Action, resource: &R) -> bool {
+        if resource.owner() == user {
+            return true;
+        }
+        match action {
+            Action::Read => self.is_admin(user) || resource.shared_with(user, Access::Link),
+            Action::Comment => resource.shared_with(user, Access::Link),
+            Action::List => resource.shared_with(user, Access::Listing),
+            Action::Delete | Action::Inspect => self.is_admin(user),
+            Action::Edit => false,
+        }
+    }
+
+    /// Returns the status and message of the rejection if the user may not perform the action
+    pub fn denial<R: Resource>(
+        &self,
+        user: &Id,
+        action: Action,
+        resource: &R,
+    ) -> Option<(StatusCode, String)> {
+        if self.can(user, action, resource) {
+            None
+        } else if self.can(user, Action::Read, resource) {
+            Some((
+                StatusCode::FORBIDDEN,
+                format!("{} belongs to other user", R::KIND),
+            ))
+        } else {
+            Some((StatusCode::NOT_FOUND, format!("{} does not exist", R::KIND)))
+        }
+    }
+
+    /// Like [`Policy::can`], but rejects the request with `403` or `404`
+    pub fn check<R: Resource>(
+        &self,
+        user: &Id,
+        action: Action,
+        resource: &R,
+    ) -> Result<(), (StatusCode, String)> {
+        match self.denial(user, action, resource) {
+            None => Ok(()),
+            Some((status, message)) => {
+                info!("--> {}", status.as_u16());
+                Err((status, message))
+            }
+        }
+    }
+
+    /// Checks that the user may delete the comment
+    ///
+    /// Besides the author, the owner of the note can delete comments, and admins like for
+    /// every other record. The comment is visible to everybody who may read the note.
+    pub fn check_comment(
+        &self,
+        user: &Id,
+        comment: &Comment,
+        note: Option<&Note>,
+    ) -> Result<(), (StatusCode, String)> {
+        if self.can(user, Action::Delete, comment)
+            || note.is_some_and(|note| self.can(user, Action::Edit, note))
+        {
+            return Ok(());
+        }
+        match note {
+            Some(note) if self.can(user, Action::Read, note) => {
+                info!("--> 403");
+                Err((
+                    StatusCode::FORBIDDEN,
+                    "Comment belongs to other user".to_string(),
+                ))
+            }
+            _ => {
+                info!("--> 404");
+                Err((StatusCode::NOT_FOUND, "Comment does not exist".to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::ANONYMOUS_USER;
+    use crate::models::note::test_note::example_note;
+
+    fn note(owner: usize, visibility: &str) -> Note {
+        let mut note = serde_json::to_value(example_note()).unwrap();
+        note["user"] = owner.into();
+        note["visibility"] = visibility.into();
+        serde_json::from_value(note).unwrap()
+    }
+
+    #[test]
+    fn test_can() {
+        let admins = [Id(9)];
+        let policy = Policy::new(&admins);
+        let private = note(1, "Private");
+        for action in [
+            Action::Read,
+            Action::List,
+            Action::Comment,
+            Action::Edit,
+            Action::Delete,
+            Action::Inspect,
+        ] {
+            assert!(policy.can(&Id(1), action, &private), "{action:?}");
+            assert!(!policy.can(&Id(2), action, &private), "{action:?}");
+        }
+        assert!(policy.can(&Id(9), Action::Read, &private));
+        assert!(policy.can(&Id(9), Action::Delete, &private));
+        assert!(!policy.can(&Id(9), Action::Edit, &private));
+        assert!(!policy.can(&Id(9), Action::Comment, &private));
+        assert!(!policy.can(&Id(9), Action::List, &private));
+
+        let unlisted = note(1, "Unlisted");
+        assert!(policy.can(&Id(2), Action::Read, &unlisted));
+        assert!(policy.can(&Id(2), Action::Comment, &unlisted));
+        assert!(!policy.can(&Id(2), Action::List, &unlisted));
+        assert!(!policy.can(&Id(2), Action::Edit, &unlisted));
+
+        let workspace = note(1, "Workspace");
+        assert!(policy.can(&Id(2), Action::List, &workspace));
+        assert!(!policy.can(&ANONYMOUS_USER, Action::Read, &workspace));
+    }
+
+    #[test]
+    fn test_denial() {
+        let policy = Policy::new(&[]);
+        assert_eq!(
+            policy.denial(&Id(1), Action::Edit, &note(1, "Public")),
+            None
+        );
+        let (status, message) = policy
+            .denial(&Id(2), Action::Edit, &note(1, "Public"))
+            .unwrap();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(message, "Note belongs to other user");
+        let (status, message) = policy
+            .denial(&Id(2), Action::Edit, &note(1, "Private"))
+            .unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(message, "Note does not exist");
+    }
+}
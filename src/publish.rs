@@ -0,0 +1,241 @@
+//! A static HTML site with all public notes, written by `POST /admin/publish-site`
+//!
+//! The site can be served by any web server and consists of
+//! - `index.html`, listing all tags and notes
+//! - `tags/<id>.html`, listing the notes with the tag
+//! - `p/<slug>/index.html`, the page of every note, the same as `GET /p/<slug>`
+//! - `feed.xml`, an RSS feed of the most recently updated notes
+//!
+//! Links between the pages are relative to the root of the site, the feed and
+//! the canonical links of the pages use the public URL of the app.
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use crate::models::note::Note;
+use crate::models::Tag;
+use crate::site::{self, escape, fill};
+
+/// Number of notes in the RSS feed
+const FEED_LENGTH: usize = 20;
+
+const LIST_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{title}}</title>
+<link rel="alternate" type="application/rss+xml" href="/feed.xml">
+</head>
+<body>
+<h1>{{title}}</h1>
+{{tags}}<ul>
+{{notes}}</ul>
+</body>
+</html>
+"#;
+
+const LIST_ITEM: &str = "<li><a href=\"{{href}}\">{{title}}</a></li>\n";
+
+const FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+<title>Notes</title>
+<link>{{url}}</link>
+<description>Public notes</description>
+{{items}}</channel>
+</rss>
+"#;
+
+const FEED_ITEM: &str = "<item><title>{{title}}</title><link>{{url}}</link><guid>{{url}}</guid><pubDate>{{updated}}</pubDate><description>{{description}}</description></item>\n";
+
+/// Returns `true` if the slug can be used as directory name
+///
+/// Slugs created by the app always can, but restored archives might contain others.
+fn safe_slug(slug: &str) -> bool {
+    !slug.is_empty() && slug.chars().all(|c| c.is_alphanumeric() || c == '-')
+}
+
+/// Returns the root-relative link to the page of the note
+fn href(note: &Note) -> Option<String> {
+    let slug = note.slug().filter(|slug| safe_slug(slug))?;
+    Some(format!("/p/{slug}/"))
+}
+
+/// Renders a list of links to the notes
+fn list(title: &str, tags: &str, notes: &[&Note]) -> String {
+    let items = notes
+        .iter()
+        .filter_map(|note| {
+            Some(fill(
+                LIST_ITEM,
+                &[("href", &href(note)?), ("title", &escape(note.title()))],
+            ))
+        })
+        .collect::<String>();
+    fill(
+        LIST_PAGE,
+        &[("title", &escape(title)), ("tags", tags), ("notes", &items)],
+    )
+}
+
+/// Renders the RSS feed with the most recently updated notes
+fn feed(notes: &[&Note], base_url: &str) -> String {
+    let items = notes
+        .iter()
+        .take(FEED_LENGTH)
+        .filter_map(|note| {
+            let url = format!("{base_url}{}", href(note)?);
+            Some(fill(
+                FEED_ITEM,
+                &[
+                    ("title", &escape(note.title())),
+                    ("url", &escape(&url)),
+                    ("updated", &note.updated().to_rfc2822()),
+                    ("description", &escape(&note.body().text())),
+                ],
+            ))
+        })
+        .collect::<String>();
+    fill(FEED, &[("url", &escape(base_url)), ("items", &items)])
+}
+
+/// Renders all files of the site, keyed by their path relative to the root of the site
+///
+/// Notes without a slug that can be used as directory name are skipped.
+pub fn render(notes: &[Note], base_url: &str) -> BTreeMap<String, String> {
+    let mut notes = notes
+        .iter()
+        .filter(|note| href(note).is_some())
+        .collect::<Vec<&Note>>();
+    notes.sort_by(|a, b| b.updated().cmp(a.updated()));
+
+    let mut tagged: BTreeMap<String, (&Tag, Vec<&Note>)> = BTreeMap::new();
+    for note in &notes {
+        for tag in note.tags() {
+            tagged
+                .entry(tag.label().to_string())
+                .or_insert_with(|| (tag, vec![]))
+                .1
+                .push(note);
+        }
+    }
+
+    let mut files = BTreeMap::new();
+    for note in &notes {
+        if let Some(slug) = note.slug() {
+            files.insert(
+                format!("p/{slug}/index.html"),
+                site::page(note, base_url, true),
+            );
+        }
+    }
+    let mut tag_links = String::new();
+    for (label, (tag, tag_notes)) in &tagged {
+        let id = usize::from(tag.id());
+        tag_links.push_str(&fill(
+            LIST_ITEM,
+            &[
+                ("href", &format!("/tags/{id}.html")),
+                ("title", &escape(&format!("{label} ({})", tag_notes.len()))),
+            ],
+        ));
+        files.insert(format!("tags/{id}.html"), list(label, "", tag_notes));
+    }
+    let tags = if tag_links.is_empty() {
+        String::new()
+    } else {
+        format!("<h2>Tags</h2>\n<ul>\n{tag_links}</ul>\n<h2>Notes</h2>\n")
+    };
+    files.insert("index.html".to_string(), list("Notes", &tags, &notes));
+    files.insert("feed.xml".to_string(), feed(&notes, base_url));
+    files
+}
+
+/// Writes the files of the site to the directory
+///
+/// The `p` and `tags` directories are replaced, so that pages of notes that are
+/// not public anymore are removed. `progress` is called with the number of written files.
+pub fn write(
+    dir: &Path,
+    files: &BTreeMap<String, String>,
+    progress: &mut dyn FnMut(usize),
+) -> io::Result<()> {
+    for generated in ["p", "tags"] {
+        match std::fs::remove_dir_all(dir.join(generated)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    for (done, (path, content)) in files.iter().enumerate() {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, content)?;
+        progress(done + 1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::{Draft, Tags};
+    use crate::models::{Id, Visibility};
+
+    fn note(id: usize, title: &str, slug: &str, tags: &[(usize, &str)]) -> Note {
+        let mut note_tags = Tags::default();
+        for (tag_id, label) in tags {
+            note_tags.insert(Tag::new(Id(*tag_id), label.to_string()));
+        }
+        let draft = Draft::new(
+            title.to_string(),
+            "Body".to_string(),
+            vec![],
+            Visibility::Public,
+        );
+        Note::new(draft, Id(id), Id(1), note_tags).with_slug(Some(slug.to_string()))
+    }
+
+    #[test]
+    fn test_render() {
+        let notes = vec![
+            note(1, "Fish & Chips", "fish-chips", &[(1, "food")]),
+            note(2, "Pasta", "pasta", &[(1, "food"), (2, "italian")]),
+            note(3, "Escape", "../escape", &[(1, "food")]),
+        ];
+        let files = render(&notes, "https://notes.example.com");
+        assert_eq!(
+            files.keys().collect::<Vec<&String>>(),
+            vec![
+                "feed.xml",
+                "index.html",
+                "p/fish-chips/index.html",
+                "p/pasta/index.html",
+                "tags/1.html",
+                "tags/2.html",
+            ]
+        );
+        let index = &files["index.html"];
+        assert!(index.contains(r#"<a href="/tags/1.html">food (2)</a>"#));
+        assert!(index.contains(r#"<a href="/p/fish-chips/">Fish &amp; Chips</a>"#));
+        assert!(!files["tags/2.html"].contains("fish-chips"));
+        let feed = &files["feed.xml"];
+        assert_eq!(feed.matches("<item>").count(), 2);
+        assert!(feed.contains("<link>https://notes.example.com/p/pasta/</link>"));
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = std::env::temp_dir().join(format!("note-demo-publish-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("p/stale")).unwrap();
+        let files = render(&[note(1, "Pasta", "pasta", &[])], "");
+        let mut written = 0;
+        write(&dir, &files, &mut |done| written = done).unwrap();
+        assert_eq!(written, 3);
+        assert!(dir.join("p/pasta/index.html").exists());
+        assert!(!dir.join("p/stale").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+//! Validates note bodies and renders them as HTML, depending on their [`BodyFormat`]
+//!
+//! Raw HTML in bodies is always escaped and links and images only point to relative,
+//! HTTP(S) and `mailto:` URLs, so that notes can't inject scripts into pages.
+use pulldown_cmark::{html, Event, Parser, Tag};
+
+use crate::models::format::BodyFormat;
+use crate::site::escape;
+
+pub mod asciidoc;
+
+/// The schemes of the URLs that links and images may point to
+const URL_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// Checks that the body can be parsed in the format
+pub fn validate(format: BodyFormat, body: &str) -> Result<(), String> {
+    match format {
+        BodyFormat::Plaintext => match body
+            .chars()
+            .find(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+        {
+            Some(c) => Err(format!(
+                "Plain text must not contain the control character U+{:04X}",
+                c as u32
+            )),
+            None => Ok(()),
+        },
+        // Every text is valid Markdown
+        BodyFormat::Markdown => Ok(()),
+        BodyFormat::Asciidoc => asciidoc::parse(body).map(|_| ()),
+    }
+}
+
+/// Renders the body as HTML
+///
+/// Bodies that can't be parsed, e.g. because they were stored before they were
+/// validated, are rendered as plain text.
+pub fn html(format: BodyFormat, body: &str) -> String {
+    match format {
+        BodyFormat::Plaintext => plaintext(body),
+        BodyFormat::Markdown => markdown(body),
+        BodyFormat::Asciidoc => asciidoc::parse(body)
+            .map(|blocks| asciidoc::html(&blocks))
+            .unwrap_or_else(|_| plaintext(body)),
+    }
+}
+
+/// Renders every group of lines that is separated by empty lines as paragraph
+fn plaintext(body: &str) -> String {
+    let mut res = String::new();
+    let mut lines = Vec::new();
+    for line in body.lines().chain([""]) {
+        if !line.trim().is_empty() {
+            lines.push(escape(line));
+        } else if !lines.is_empty() {
+            res.push_str(&format!("<p>{}</p>\n", lines.join("<br>\n")));
+            lines.clear();
+        }
+    }
+    res
+}
+
+/// Returns `true` for relative URLs and URLs with one of the [`URL_SCHEMES`]
+fn is_safe_url(url: &str) -> bool {
+    // like browsers, ignore leading spaces and control characters and all tabs and newlines
+    let url = url
+        .trim_start_matches(|c: char| c <= ' ')
+        .replace(['\t', '\n', '\r'], "");
+    match url.find([':', '/', '?', '#']) {
+        Some(end) if url[end..].starts_with(':') => {
+            URL_SCHEMES.contains(&url[..end].to_ascii_lowercase().as_str())
+        }
+        _ => true,
+    }
+}
+
+fn markdown(body: &str) -> String {
+    let events = Parser::new(body).map(|event| match event {
+        Event::Html(html) => Event::Text(html),
+        Event::Start(Tag::Link(kind, url, title)) if !is_safe_url(&url) => {
+            Event::Start(Tag::Link(kind, "#".into(), title))
+        }
+        Event::Start(Tag::Image(kind, url, title)) if !is_safe_url(&url) => {
+            Event::Start(Tag::Image(kind, "#".into(), title))
+        }
+        event => event,
+    });
+    let mut res = String::new();
+    html::push_html(&mut res, events);
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(BodyFormat::Plaintext, "Line\n\tindented\r\n").is_ok());
+        assert!(validate(BodyFormat::Plaintext, "Bell \u{7}").is_err());
+        assert!(validate(BodyFormat::Markdown, "```\nunclosed").is_ok());
+        assert!(validate(BodyFormat::Asciidoc, "== Section\n\nText").is_ok());
+        assert!(validate(BodyFormat::Asciidoc, "----\nunclosed").is_err());
+    }
+
+    #[test]
+    fn test_html() {
+        assert_eq!(
+            html(BodyFormat::Plaintext, "a *b*\n<c>\n\n\nd"),
+            "<p>a *b*<br>\n&lt;c&gt;</p>\n<p>d</p>\n"
+        );
+        assert_eq!(
+            html(BodyFormat::Markdown, "a *b*\n<c>"),
+            "<p>a <em>b</em>\n&lt;c&gt;</p>\n"
+        );
+        assert_eq!(
+            html(BodyFormat::Asciidoc, "a *b*"),
+            "<p>a <strong>b</strong></p>\n"
+        );
+        assert_eq!(
+            html(BodyFormat::Asciidoc, "----\n*b*"),
+            "<p>----<br>\n*b*</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_link_urls() {
+        assert_eq!(
+            html(
+                BodyFormat::Markdown,
+                "[x](javascript:alert(document.cookie))"
+            ),
+            "<p><a href=\"#\">x</a></p>\n"
+        );
+        assert_eq!(
+            html(BodyFormat::Markdown, "![x](data:text/html,<script>)"),
+            "<p><img src=\"#\" alt=\"x\" /></p>\n"
+        );
+        assert_eq!(
+            html(BodyFormat::Markdown, "<JavaScript:alert(1)>"),
+            "<p><a href=\"#\">JavaScript:alert(1)</a></p>\n"
+        );
+        assert_eq!(
+            html(BodyFormat::Markdown, "[x](https://example.com/a:b)"),
+            "<p><a href=\"https://example.com/a:b\">x</a></p>\n"
+        );
+        for url in [
+            "/p/other",
+            "other#a:b",
+            "?q=a:b",
+            "mailto:a@example.com",
+            "HTTP://a",
+        ] {
+            assert!(is_safe_url(url), "{url}");
+        }
+        for url in [
+            "javascript:x",
+            " java\tscript:x",
+            "\u{1}vbscript:x",
+            "data:,x",
+        ] {
+            assert!(!is_safe_url(url), "{url}");
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! A parser for the subset of AsciiDoc that notes support
+//!
+//! - section titles, `== Title` (`=` to `======`)
+//! - paragraphs, separated by empty lines
+//! - unordered (`* item`, `- item`) and ordered (`. item`) lists
+//! - listing (`----`), literal (`....`) and comment (`////`) blocks
+//! - single-line comments, `// comment`
+//! - inline `*strong*`, `_emphasis_` and `` `monospace` `` text
+use crate::site::escape;
+
+/// The deepest section level, `======`
+const MAX_SECTION_LEVEL: usize = 6;
+
+/// A block of a parsed document
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Block {
+    /// A section title with its level, from 1 for `=` to 6
+    Section(usize, String),
+    Paragraph(Vec<String>),
+    List {
+        ordered: bool,
+        items: Vec<String>,
+    },
+    /// The content of a listing or literal block, shown as is
+    Listing(Vec<String>),
+}
+
+/// Parses the document into its blocks
+///
+/// Fails on delimited blocks that are not closed and on sections that are too deep.
+pub fn parse(text: &str) -> Result<Vec<Block>, String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Block> = None;
+    let mut lines = text.lines().enumerate();
+    while let Some((number, line)) = lines.next() {
+        let trimmed = line.trim_end();
+        if trimmed.is_empty() {
+            blocks.extend(current.take());
+            continue;
+        }
+        if ["----", "....", "////"].contains(&trimmed) {
+            blocks.extend(current.take());
+            let mut content = Vec::new();
+            let closed = lines.by_ref().any(|(_, line)| {
+                let closes = line.trim_end() == trimmed;
+                if !closes {
+                    content.push(line.to_string());
+                }
+                closes
+            });
+            if !closed {
+                return Err(format!(
+                    "The block `{trimmed}` in line {} is not closed",
+                    number + 1
+                ));
+            }
+            if trimmed != "////" {
+                blocks.push(Block::Listing(content));
+            }
+            continue;
+        }
+        if trimmed.starts_with("//") {
+            continue;
+        }
+        if let Some((level, title)) = section(trimmed) {
+            if level > MAX_SECTION_LEVEL {
+                return Err(format!(
+                    "The section in line {} is deeper than {MAX_SECTION_LEVEL} levels",
+                    number + 1
+                ));
+            }
+            blocks.extend(current.take());
+            blocks.push(Block::Section(level, title.to_string()));
+            continue;
+        }
+        if let Some((ordered, item)) = list_item(trimmed) {
+            match &mut current {
+                Some(Block::List {
+                    ordered: current_ordered,
+                    items,
+                }) if *current_ordered == ordered => items.push(item.to_string()),
+                _ => {
+                    blocks.extend(current.take());
+                    current = Some(Block::List {
+                        ordered,
+                        items: vec![item.to_string()],
+                    });
+                }
+            }
+            continue;
+        }
+        match &mut current {
+            Some(Block::Paragraph(lines)) => lines.push(trimmed.to_string()),
+            _ => {
+                blocks.extend(current.take());
+                current = Some(Block::Paragraph(vec![trimmed.to_string()]));
+            }
+        }
+    }
+    blocks.extend(current);
+    Ok(blocks)
+}
+
+/// Returns the level and the title of a section title, e.g. `== Title`
+fn section(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '=').count();
+    let title = line[level..].strip_prefix(' ')?.trim();
+    (level > 0 && !title.is_empty()).then_some((level, title))
+}
+
+/// Returns if the item is ordered and its text, e.g. for `* item`
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    let (ordered, item) = if let Some(item) = line.strip_prefix(". ") {
+        (true, item)
+    } else {
+        (false, line.strip_prefix("* ").or(line.strip_prefix("- "))?)
+    };
+    let item = item.trim();
+    (!item.is_empty()).then_some((ordered, item))
+}
+
+/// Renders the blocks as HTML
+pub fn html(blocks: &[Block]) -> String {
+    let mut res = String::new();
+    for block in blocks {
+        match block {
+            Block::Section(level, title) => {
+                res.push_str(&format!("<h{level}>{}</h{level}>\n", inline(title)));
+            }
+            Block::Paragraph(lines) => {
+                res.push_str(&format!("<p>{}</p>\n", inline(&lines.join("\n"))));
+            }
+            Block::List { ordered, items } => {
+                let tag = if *ordered { "ol" } else { "ul" };
+                res.push_str(&format!("<{tag}>\n"));
+                for item in items {
+                    res.push_str(&format!("<li>{}</li>\n", inline(item)));
+                }
+                res.push_str(&format!("</{tag}>\n"));
+            }
+            Block::Listing(lines) => {
+                res.push_str(&format!(
+                    "<pre><code>{}</code></pre>\n",
+                    escape(&lines.join("\n"))
+                ));
+            }
+        }
+    }
+    res
+}
+
+/// Renders the inline formatting of the text
+///
+/// Formatting marks must enclose a word or phrase, so that e.g. `snake_case` stays as it is.
+fn inline(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut res = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let tag = match c {
+            '*' => "strong",
+            '_' => "em",
+            '`' => "code",
+            _ => "",
+        };
+        let starts = !tag.is_empty()
+            && (i == 0 || !chars[i - 1].is_alphanumeric())
+            && chars.get(i + 1).is_some_and(|next| !next.is_whitespace());
+        let end = (i + 2..chars.len()).find(|&j| {
+            chars[j] == c
+                && !chars[j - 1].is_whitespace()
+                && !chars.get(j + 1).is_some_and(|next| next.is_alphanumeric())
+        });
+        match end {
+            Some(end) if starts => {
+                let content = chars[i + 1..end].iter().collect::<String>();
+                let content = if c == '`' {
+                    escape(&content)
+                } else {
+                    inline(&content)
+                };
+                res.push_str(&format!("<{tag}>{content}</{tag}>"));
+                i = end + 1;
+            }
+            _ => {
+                res.push_str(&escape(&c.to_string()));
+                i += 1;
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "= Title\n\nFirst line\nsecond line\n// comment\n* one\n* two\n. first\n\n----\n*code*\n\n----\n////\nhidden\n////";
+        assert_eq!(
+            parse(text).unwrap(),
+            vec![
+                Block::Section(1, "Title".to_string()),
+                Block::Paragraph(vec!["First line".to_string(), "second line".to_string()]),
+                Block::List {
+                    ordered: false,
+                    items: vec!["one".to_string(), "two".to_string()]
+                },
+                Block::List {
+                    ordered: true,
+                    items: vec!["first".to_string()]
+                },
+                Block::Listing(vec!["*code*".to_string(), "".to_string()]),
+            ]
+        );
+        assert!(parse("....\nliteral").is_err());
+        assert!(parse("======= Too deep").is_err());
+        assert_eq!(
+            parse("==Not a title").unwrap(),
+            vec![Block::Paragraph(vec!["==Not a title".to_string()])]
+        );
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(
+            inline("*bold _and italic_* `<code>`"),
+            "<strong>bold <em>and italic</em></strong> <code>&lt;code&gt;</code>"
+        );
+        assert_eq!(inline("snake_case_name"), "snake_case_name");
+        assert_eq!(inline("2 * 3 * 4"), "2 * 3 * 4");
+        assert_eq!(inline("*unclosed"), "*unclosed");
+    }
+
+    #[test]
+    fn test_html() {
+        let blocks = parse("== Steps\n\n. Fry\n. Eat\n\n<b>done</b>").unwrap();
+        assert_eq!(
+            html(&blocks),
+            "<h2>Steps</h2>\n<ol>\n<li>Fry</li>\n<li>Eat</li>\n</ol>\n<p>&lt;b&gt;done&lt;/b&gt;</p>\n"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Combined documents of many notes, e.g. all notes under a tag with `GET /notes/tag/:tag/export`
+//!
+//! A report starts with a table of contents, followed by every note with its title, tags and
+//! timestamps, like the PDF of a single note. Reports are rendered as Markdown or, with
+//! [`pdf::render_report`](crate::pdf::render_report), as PDF document.
+use serde::Deserialize;
+
+use crate::models::format::BodyFormat;
+use crate::models::note::{slugify, Note};
+use crate::models::query::SortOrder;
+
+/// The format of a report
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Pdf,
+    Md,
+}
+
+impl ReportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Pdf => "pdf",
+            ReportFormat::Md => "md",
+        }
+    }
+}
+
+/// The query of `GET /notes/tag/:tag/export`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct ReportQuery {
+    pub format: ReportFormat,
+    /// The order of the notes, the preferred order of the user if not set
+    pub sort: Option<SortOrder>,
+}
+
+/// Returns the `Content-Disposition` header of the report about the tag
+///
+/// The file is named after the slug of the label, so that quotes or control characters in
+/// labels can't break the header. Slugs with other than ASCII characters are sent as
+/// `filename*` (RFC 6266), with an ASCII fallback.
+pub fn content_disposition(label: &str, format: ReportFormat) -> String {
+    let slug = slugify(label).unwrap_or_else(|| "notes".to_string());
+    let extension = format.extension();
+    if slug.is_ascii() {
+        return format!("attachment; filename=\"{slug}.{extension}\"");
+    }
+    let ascii =
+        slugify(&slug.replace(|c: char| !c.is_ascii(), "-")).unwrap_or_else(|| "notes".to_string());
+    let encoded = slug
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'-' => char::from(byte).to_string(),
+            byte => format!("%{byte:02X}"),
+        })
+        .collect::<String>();
+    format!("attachment; filename=\"{ascii}.{extension}\"; filename*=UTF-8''{encoded}.{extension}")
+}
+
+/// Returns the anchor of the note in a Markdown report
+fn anchor(note: &Note) -> String {
+    format!("note-{}", usize::from(note.id()))
+}
+
+/// Renders the notes as a single Markdown document with a table of contents
+///
+/// Markdown bodies are included as they are, other formats as preformatted text.
+pub fn markdown(title: &str, notes: &[Note]) -> String {
+    let mut res = format!("# {title}\n\n## Contents\n\n");
+    for (number, note) in notes.iter().enumerate() {
+        res.push_str(&format!(
+            "{}. [{}](#{})\n",
+            number + 1,
+            note.title(),
+            anchor(note)
+        ));
+    }
+    for note in notes {
+        res.push_str(&format!(
+            "\n---\n\n<a id=\"{}\"></a>\n\n## {}\n\n",
+            anchor(note),
+            note.title()
+        ));
+        let mut tags = note
+            .tags()
+            .map(|tag| tag.label().to_string())
+            .collect::<Vec<String>>();
+        if !tags.is_empty() {
+            tags.sort();
+            res.push_str(&format!("Tags: {}  \n", tags.join(", ")));
+        }
+        res.push_str(&format!(
+            "Created: {}, updated: {}\n\n",
+            note.created().format("%Y-%m-%d %H:%M UTC"),
+            note.updated().format("%Y-%m-%d %H:%M UTC")
+        ));
+        let body = note.body().text();
+        match note.format() {
+            BodyFormat::Markdown => res.push_str(body.trim_end()),
+            BodyFormat::Plaintext | BodyFormat::Asciidoc => {
+                res.push_str(&format!("~~~~text\n{}\n~~~~", body.trim_end()))
+            }
+        }
+        res.push('\n');
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+
+    #[test]
+    fn test_markdown() {
+        let mut plain = serde_json::to_value(example_note()).unwrap();
+        plain["id"] = 2.into();
+        plain["title"] = "Plain".into();
+        plain["body"] = "*not* emphasized".into();
+        plain["format"] = "plaintext".into();
+        plain["tags"] = serde_json::json!([]);
+        let notes = [example_note(), serde_json::from_value(plain).unwrap()];
+
+        let report = markdown("Project", &notes);
+        assert!(report.starts_with(
+            "# Project\n\n## Contents\n\n1. [Test-Title](#note-1)\n2. [Plain](#note-2)\n"
+        ));
+        assert!(
+            report.contains("<a id=\"note-1\"></a>\n\n## Test-Title\n\nTags: tag1, tag2, tag3  \n")
+        );
+        assert!(report.contains("\n\nTest-Body\n"));
+        assert!(report.contains("~~~~text\n*not* emphasized\n~~~~\n"));
+        assert!(report.find("## Test-Title").unwrap() < report.find("## Plain").unwrap());
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition("Project X", ReportFormat::Pdf),
+            "attachment; filename=\"project-x.pdf\""
+        );
+        assert_eq!(
+            content_disposition("a\"b\r\nSet-Cookie: x", ReportFormat::Md),
+            "attachment; filename=\"a-b-set-cookie-x.md\""
+        );
+        assert_eq!(
+            content_disposition("\"\"", ReportFormat::Md),
+            "attachment; filename=\"notes.md\""
+        );
+        assert_eq!(
+            content_disposition("Übersicht 2024", ReportFormat::Pdf),
+            "attachment; filename=\"bersicht-2024.pdf\"; filename*=UTF-8''%C3%BCbersicht-2024.pdf"
+        );
+        assert_eq!(
+            content_disposition("日本", ReportFormat::Pdf),
+            "attachment; filename=\"notes.pdf\"; filename*=UTF-8''%E6%97%A5%E6%9C%AC.pdf"
+        );
+    }
+}
@@ -0,0 +1,575 @@
+//! The revision history of notes, with bodies stored as line-based deltas
+//!
+//! Storing a full copy of every revision bloats the storage of frequently edited
+//! notes. A [`RevisionLog`] stores the body of a revision as [`Delta`] against the
+//! previous revision instead, and a full snapshot every [`SNAPSHOT_INTERVAL`]
+//! revisions. Reconstructing a revision starts at the closest snapshot before it,
+//! so it applies fewer than [`SNAPSHOT_INTERVAL`] deltas.
+//!
+//! Snapshots and the latest body share their chunks with the [`Body`] of the note,
+//! only the changed lines of a delta are copied.
+//!
+//! The same deltas propose a three-way [`merge`] of edits that were based on an
+//! outdated revision, see [`EditConflict`].
+use std::borrow::Cow;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::body::Body;
+use crate::models::note::{Draft, Note};
+
+/// Every n-th revision stores the complete body
+pub const SNAPSHOT_INTERVAL: usize = 10;
+
+/// If the changed parts of two bodies have more lines than this when multiplied,
+/// they are replaced as a whole instead of being compared line by line
+pub const MAX_DIFF_CELLS: usize = 1_000_000;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Op {
+    /// Copies lines of the previous text
+    Keep(usize),
+    /// Skips lines of the previous text
+    Delete(usize),
+    /// Adds new lines, including their line breaks
+    Insert(Vec<String>),
+}
+
+/// The changes between two texts, line by line
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Delta(Vec<Op>);
+
+impl Delta {
+    /// Computes the changes that turn `old` into `new`
+    ///
+    /// Lines that both texts start or end with are always kept. The lines in
+    /// between are compared with a longest common subsequence, unless they
+    /// exceed [`MAX_DIFF_CELLS`].
+    pub fn diff(old: &Body, new: &Body) -> Self {
+        let old = old.lines().collect::<Vec<Cow<str>>>();
+        let new = new.lines().collect::<Vec<Cow<str>>>();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let old_changed = &old[prefix..old.len() - suffix];
+        let new_changed = &new[prefix..new.len() - suffix];
+
+        let mut delta = Delta::default();
+        delta.push(Op::Keep(prefix));
+        if old_changed.len().saturating_mul(new_changed.len()) <= MAX_DIFF_CELLS {
+            delta.push_lcs(old_changed, new_changed);
+        } else {
+            delta.push(Op::Delete(old_changed.len()));
+            delta.insert(new_changed);
+        }
+        delta.push(Op::Keep(suffix));
+        delta
+    }
+
+    /// Applies the changes to `old`, returns `None` if the delta was not computed for `old`
+    pub fn apply(&self, old: &str) -> Option<String> {
+        let mut lines = old.split_inclusive('\n');
+        let mut res = String::with_capacity(old.len());
+        for op in &self.0 {
+            match op {
+                Op::Keep(count) => {
+                    for _ in 0..*count {
+                        res.push_str(lines.next()?);
+                    }
+                }
+                Op::Delete(count) => {
+                    for _ in 0..*count {
+                        lines.next()?;
+                    }
+                }
+                Op::Insert(new) => new.iter().for_each(|line| res.push_str(line)),
+            }
+        }
+        if lines.next().is_some() {
+            return None;
+        }
+        Some(res)
+    }
+
+    /// Returns the number of bytes of all inserted lines
+    pub fn inserted_bytes(&self) -> usize {
+        self.0
+            .iter()
+            .map(|op| match op {
+                Op::Insert(lines) => lines.iter().map(String::len).sum(),
+                Op::Keep(_) | Op::Delete(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Returns the changed parts of the old text, in order
+    fn hunks(&self) -> Vec<Hunk> {
+        let mut hunks = vec![];
+        let mut open = false;
+        let mut pos = 0;
+        for op in &self.0 {
+            match op {
+                Op::Keep(count) => {
+                    open = false;
+                    pos += count;
+                }
+                Op::Delete(count) => {
+                    Hunk::open(&mut hunks, &mut open, pos).end += count;
+                    pos += count;
+                }
+                Op::Insert(lines) => Hunk::open(&mut hunks, &mut open, pos)
+                    .lines
+                    .extend(lines.iter().cloned()),
+            }
+        }
+        hunks
+    }
+
+    /// Appends the operation, merging it with the last one if they are of the same kind
+    fn push(&mut self, op: Op) {
+        match (self.0.last_mut(), op) {
+            (_, Op::Keep(0) | Op::Delete(0)) => {}
+            (_, Op::Insert(lines)) if lines.is_empty() => {}
+            (Some(Op::Keep(last)), Op::Keep(count)) => *last += count,
+            (Some(Op::Delete(last)), Op::Delete(count)) => *last += count,
+            (Some(Op::Insert(last)), Op::Insert(lines)) => last.extend(lines),
+            (_, op) => self.0.push(op),
+        }
+    }
+
+    fn insert(&mut self, lines: &[Cow<str>]) {
+        self.push(Op::Insert(
+            lines.iter().map(|line| line.to_string()).collect(),
+        ));
+    }
+
+    fn push_lcs(&mut self, old: &[Cow<str>], new: &[Cow<str>]) {
+        // lengths[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+        let mut lengths = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lengths[i][j] = if old[i] == new[j] {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() && j < new.len() {
+            if old[i] == new[j] {
+                self.push(Op::Keep(1));
+                i += 1;
+                j += 1;
+            } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+                self.push(Op::Delete(1));
+                i += 1;
+            } else {
+                self.insert(&new[j..=j]);
+                j += 1;
+            }
+        }
+        self.push(Op::Delete(old.len() - i));
+        self.insert(&new[j..]);
+    }
+}
+
+/// A change of the lines `start..end` of the old text to `lines`
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Hunk {
+    start: usize,
+    end: usize,
+    lines: Vec<String>,
+}
+
+impl Hunk {
+    /// Returns the last hunk if it is still `open`, otherwise starts a new one at `pos`
+    fn open<'a>(hunks: &'a mut Vec<Hunk>, open: &mut bool, pos: usize) -> &'a mut Hunk {
+        if !*open {
+            hunks.push(Hunk {
+                start: pos,
+                end: pos,
+                lines: vec![],
+            });
+            *open = true;
+        }
+        hunks.last_mut().expect("a hunk was pushed")
+    }
+}
+
+/// Replaces the lines `start..end` of `base` with the `hunks` that lie within them
+fn apply_hunks(base: &[Cow<str>], start: usize, end: usize, hunks: &[Hunk]) -> String {
+    let mut res = String::new();
+    let mut pos = start;
+    for hunk in hunks {
+        base[pos..hunk.start]
+            .iter()
+            .for_each(|line| res.push_str(line));
+        hunk.lines.iter().for_each(|line| res.push_str(line));
+        pos = hunk.end;
+    }
+    base[pos..end].iter().for_each(|line| res.push_str(line));
+    res
+}
+
+/// Appends the text as complete lines, with a line break at the end
+fn push_lines(res: &mut String, text: &str) {
+    res.push_str(text);
+    if !text.is_empty() && !text.ends_with('\n') {
+        res.push('\n');
+    }
+}
+
+/// The result of a three-way [`merge`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Merged {
+    pub text: String,
+    /// The number of parts that both sides changed differently
+    pub conflicts: usize,
+}
+
+/// Combines the changes that `server` and `client` made to `base`, line by line
+///
+/// Changes of only one side are applied. Parts that both sides changed differently,
+/// or changed in adjacent lines, are conflicts and contain both versions between
+/// `<<<<<<< server`, `=======` and `>>>>>>> client` markers.
+pub fn merge(base: &Body, server: &Body, client: &Body) -> Merged {
+    let lines = base.lines().collect::<Vec<Cow<str>>>();
+    let ours = Delta::diff(base, server).hunks();
+    let theirs = Delta::diff(base, client).hunks();
+    let mut merged = Merged::default();
+    let (mut i, mut j, mut pos) = (0, 0, 0);
+    loop {
+        let start = match (ours.get(i), theirs.get(j)) {
+            (None, None) => break,
+            (Some(a), Some(b)) => a.start.min(b.start),
+            (Some(a), None) => a.start,
+            (None, Some(b)) => b.start,
+        };
+        // collects the hunks of both sides that overlap or touch each other
+        let (first_i, first_j) = (i, j);
+        let mut end = start;
+        loop {
+            if let Some(hunk) = ours.get(i).filter(|hunk| hunk.start <= end) {
+                end = end.max(hunk.end);
+                i += 1;
+            } else if let Some(hunk) = theirs.get(j).filter(|hunk| hunk.start <= end) {
+                end = end.max(hunk.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+        lines[pos..start]
+            .iter()
+            .for_each(|line| merged.text.push_str(line));
+        let server = apply_hunks(&lines, start, end, &ours[first_i..i]);
+        let client = apply_hunks(&lines, start, end, &theirs[first_j..j]);
+        if first_i == i || server == client {
+            merged.text.push_str(&client);
+        } else if first_j == j {
+            merged.text.push_str(&server);
+        } else {
+            merged.text.push_str("<<<<<<< server\n");
+            push_lines(&mut merged.text, &server);
+            merged.text.push_str("=======\n");
+            push_lines(&mut merged.text, &client);
+            merged.text.push_str(">>>>>>> client\n");
+            merged.conflicts += 1;
+        }
+        pos = end;
+    }
+    lines[pos..]
+        .iter()
+        .for_each(|line| merged.text.push_str(line));
+    merged
+}
+
+/// A proposal that combines a rejected edit with the changes saved since its base revision
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct MergeProposal {
+    pub title: String,
+    pub body: String,
+    /// The number of conflicts, the title counts as one if both sides changed it
+    pub conflicts: usize,
+}
+
+impl MergeProposal {
+    /// Merges the title and body of the `client` draft and the `server` note, see [`merge`]
+    ///
+    /// If both sides changed the title differently, the title of the client is proposed.
+    pub fn new(base: &Revision, server: &Note, client: &Draft) -> Self {
+        let title_changed = server.title() != base.info.title && server.title() != client.title();
+        let title = if title_changed && client.title() == base.info.title {
+            server.title()
+        } else {
+            client.title()
+        };
+        let merged = merge(
+            &Body::from(base.body.as_str()),
+            server.body(),
+            &Body::from(client.body()),
+        );
+        Self {
+            title: title.to_string(),
+            body: merged.text,
+            conflicts: merged.conflicts
+                + usize::from(title_changed && client.title() != base.info.title),
+        }
+    }
+}
+
+/// The response to an edit whose `If-Match` revision is not the latest one anymore
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct EditConflict {
+    /// The current version of the note
+    pub server: Note,
+    /// The revision that the edit was based on, if it exists
+    pub base: Option<Revision>,
+    pub proposal: Option<MergeProposal>,
+}
+
+impl EditConflict {
+    pub fn new(server: Note, base: Option<Revision>, client: &Draft) -> Self {
+        let proposal = base
+            .as_ref()
+            .map(|base| MergeProposal::new(base, &server, client));
+        Self {
+            server,
+            base,
+            proposal,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum StoredBody {
+    Snapshot(Body),
+    Delta(Delta),
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    title: String,
+    body: StoredBody,
+    created: DateTime<Utc>,
+}
+
+/// A revision of a note, without its body
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct RevisionInfo {
+    /// The number of the revision, starting at 1
+    pub number: usize,
+    pub title: String,
+    pub created: DateTime<Utc>,
+}
+
+/// A complete revision of a note
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Revision {
+    #[serde(flatten)]
+    pub info: RevisionInfo,
+    pub body: String,
+}
+
+/// All revisions of a single note
+#[derive(Clone, Debug, Default)]
+pub struct RevisionLog {
+    entries: Vec<Entry>,
+    /// The body of the latest revision, to compute the next delta
+    latest: Body,
+}
+
+impl RevisionLog {
+    /// Adds a revision, unless the title and body did not change
+    ///
+    /// Returns `true` if a revision was added.
+    pub fn push(&mut self, title: &str, body: &Body, created: DateTime<Utc>) -> bool {
+        if let Some(last) = self.entries.last() {
+            if last.title == title && &self.latest == body {
+                return false;
+            }
+        }
+        let stored = if self.entries.len().is_multiple_of(SNAPSHOT_INTERVAL) {
+            StoredBody::Snapshot(body.clone())
+        } else {
+            StoredBody::Delta(Delta::diff(&self.latest, body))
+        };
+        self.entries.push(Entry {
+            title: title.to_string(),
+            body: stored,
+            created,
+        });
+        self.latest = body.clone();
+        true
+    }
+
+    /// Returns the number of stored revisions
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the number of bytes of all stored titles, snapshots and deltas
+    pub fn bytes(&self) -> usize {
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| {
+                entry.title.len()
+                    + match &entry.body {
+                        StoredBody::Snapshot(body) => body.len(),
+                        StoredBody::Delta(delta) => delta.inserted_bytes(),
+                    }
+            })
+            .sum::<usize>();
+        entries + self.latest.len()
+    }
+
+    /// Returns all revisions, oldest first
+    pub fn revisions(&self) -> Vec<RevisionInfo> {
+        (1..=self.entries.len())
+            .map(|number| self.info(number).expect("revision exists"))
+            .collect()
+    }
+
+    fn info(&self, number: usize) -> Option<RevisionInfo> {
+        let entry = self.entries.get(number.checked_sub(1)?)?;
+        Some(RevisionInfo {
+            number,
+            title: entry.title.clone(),
+            created: entry.created,
+        })
+    }
+
+    /// Reconstructs the revision with the `number`, starting at 1
+    pub fn get(&self, number: usize) -> Option<Revision> {
+        let info = self.info(number)?;
+        let index = number - 1;
+        let snapshot = self.entries[..=index]
+            .iter()
+            .rposition(|entry| matches!(entry.body, StoredBody::Snapshot(_)))
+            .expect("the first revision is a snapshot");
+        let mut body = String::new();
+        for entry in &self.entries[snapshot..=index] {
+            body = match &entry.body {
+                StoredBody::Snapshot(snapshot) => snapshot.to_string(),
+                StoredBody::Delta(delta) => delta
+                    .apply(&body)
+                    .expect("deltas are computed for the previous revision"),
+            };
+        }
+        Some(Revision { info, body })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeDelta;
+
+    fn roundtrip(old: &str, new: &str) -> Delta {
+        let delta = Delta::diff(&Body::from(old), &Body::from(new));
+        assert_eq!(delta.apply(old).as_deref(), Some(new), "{old:?} -> {new:?}");
+        delta
+    }
+
+    #[test]
+    fn test_delta() {
+        roundtrip("", "");
+        roundtrip("", "a\nb");
+        roundtrip("a\nb", "");
+        roundtrip("a\nb", "a\nb\n");
+        roundtrip("a\nb\nc\n", "a\nc\nd\n");
+        roundtrip("a\nb\nc\nd\ne\n", "x\nb\ny\nd\nz\n");
+
+        let old = (0..1000).map(|n| format!("line {n}\n")).collect::<String>();
+        let new = old.replace("line 500\n", "changed\n");
+        let delta = roundtrip(&old, &new);
+        assert_eq!(delta.inserted_bytes(), "changed\n".len());
+        assert_eq!(
+            delta.0,
+            vec![
+                Op::Keep(500),
+                Op::Delete(1),
+                Op::Insert(vec!["changed\n".to_string()]),
+                Op::Keep(499)
+            ]
+        );
+
+        assert!(delta.apply("line 0\n").is_none());
+        assert!(Delta::diff(&Body::from("a"), &Body::from("b"))
+            .apply("a\nb")
+            .is_none());
+    }
+
+    #[test]
+    fn test_revision_log() {
+        let start = Utc::now();
+        let mut log = RevisionLog::default();
+        let bodies = (0..25)
+            .map(|n| (0..=n).map(|line| format!("{line}\n")).collect::<String>())
+            .collect::<Vec<String>>();
+        for (n, body) in bodies.iter().enumerate() {
+            let created = start + TimeDelta::seconds(n as i64);
+            assert!(log.push(&format!("Title {n}"), &Body::from(body.as_str()), created));
+        }
+        assert!(!log.push("Title 24", &Body::from(bodies[24].as_str()), start));
+        assert_eq!(log.revisions().len(), 25);
+        let snapshots = log
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry.body, StoredBody::Snapshot(_)))
+            .count();
+        assert_eq!(snapshots, 3);
+        assert_eq!(log.len(), 25);
+        assert!(log.bytes() < bodies.iter().map(String::len).sum::<usize>());
+
+        for (n, body) in bodies.iter().enumerate() {
+            let revision = log.get(n + 1).unwrap();
+            assert_eq!(&revision.body, body);
+            assert_eq!(revision.info.title, format!("Title {n}"));
+        }
+        assert!(log.get(0).is_none());
+        assert!(log.get(26).is_none());
+    }
+
+    fn merged(base: &str, server: &str, client: &str) -> Merged {
+        merge(&Body::from(base), &Body::from(server), &Body::from(client))
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = "a\nb\nc\nd\ne\n";
+        let res = merged(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\nf\n");
+        assert_eq!(res.text, "A\nb\nc\nd\nE\nf\n");
+        assert_eq!(res.conflicts, 0);
+
+        // the same change on both sides
+        let res = merged(base, "a\nx\nc\nd\ne\n", "a\nx\nc\nd\ne\n");
+        assert_eq!(res, merged(base, base, "a\nx\nc\nd\ne\n"));
+        assert_eq!(res.conflicts, 0);
+
+        let res = merged(base, "a\nserver\nc\nd\ne\n", "a\nclient\nc\nd\nE\n");
+        assert_eq!(
+            res.text,
+            "a\n<<<<<<< server\nserver\n=======\nclient\n>>>>>>> client\nc\nd\nE\n"
+        );
+        assert_eq!(res.conflicts, 1);
+
+        let res = merged("", "server", "client");
+        assert_eq!(
+            res.text,
+            "<<<<<<< server\nserver\n=======\nclient\n>>>>>>> client\n"
+        );
+        assert_eq!(merged(base, "", base).text, "");
+    }
+}
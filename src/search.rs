@@ -0,0 +1,132 @@
+//! Normalization of the text of notes and search queries
+//!
+//! The [`Index`](crate::indexer::Index) and the [`SearchQuery`](crate::models::query::SearchQuery)
+//! normalize text in the same way, so that e.g. a search for `cafe` finds notes about `Café`.
+//! The steps of the pipeline are configured with [`SearchConfig`] and run in this order:
+//!
+//! 1. German umlauts are transliterated, `ä` to `ae` (disabled by default)
+//! 2. the text is decomposed (NFKD)
+//! 3. combining marks, like accents, are removed
+//! 4. the case is folded
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::SearchConfig;
+
+/// Runs all enabled steps of the normalization pipeline on `text`
+pub fn normalize(config: &SearchConfig, text: &str) -> String {
+    let text = if config.umlauts {
+        transliterate_umlauts(text)
+    } else {
+        text.to_string()
+    };
+    let decomposed: String = if config.nfkd {
+        text.nfkd().collect()
+    } else if config.diacritics {
+        // combining marks can only be removed from decomposed text
+        text.nfd().collect()
+    } else {
+        text
+    };
+    let stripped = if config.diacritics {
+        decomposed
+            .chars()
+            .filter(|c| !is_combining_mark(*c))
+            .collect()
+    } else {
+        decomposed
+    };
+    if config.case {
+        fold_case(&stripped)
+    } else {
+        stripped
+    }
+}
+
+/// Normalizes `text` and splits it into words
+pub fn words(config: &SearchConfig, text: &str) -> impl Iterator<Item = String> {
+    normalize(config, text)
+        .split(|c: char| !c.is_alphanumeric() && !is_combining_mark(c))
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<String>>()
+        .into_iter()
+}
+
+/// Replaces `ä`, `ö`, `ü` and `ß` with `ae`, `oe`, `ue` and `ss`
+fn transliterate_umlauts(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    // umlauts might be written as a vowel and a combining diaeresis
+    for c in text.nfc() {
+        match c {
+            'ä' => res.push_str("ae"),
+            'ö' => res.push_str("oe"),
+            'ü' => res.push_str("ue"),
+            'Ä' => res.push_str("Ae"),
+            'Ö' => res.push_str("Oe"),
+            'Ü' => res.push_str("Ue"),
+            'ß' => res.push_str("ss"),
+            other => res.push(other),
+        }
+    }
+    res
+}
+
+/// Converts the text to lower case, and `ß` to `ss` like the full Unicode case folding
+fn fold_case(text: &str) -> String {
+    text.to_lowercase().replace('ß', "ss")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let config = SearchConfig::default();
+        assert_eq!(normalize(&config, "Café"), "cafe");
+        // decomposed input
+        assert_eq!(normalize(&config, "Cafe\u{301}"), "cafe");
+        assert_eq!(normalize(&config, "ﬁle Straße"), "file strasse");
+        assert_eq!(normalize(&config, "Müller"), "muller");
+
+        let german = SearchConfig {
+            umlauts: true,
+            ..config
+        };
+        assert_eq!(normalize(&german, "Müller"), "mueller");
+        assert_eq!(normalize(&german, "Mu\u{308}ller"), "mueller");
+        assert_eq!(normalize(&german, "ÖL Café"), "oel cafe");
+
+        let none = SearchConfig {
+            nfkd: false,
+            diacritics: false,
+            case: false,
+            umlauts: false,
+        };
+        assert_eq!(normalize(&none, "Café"), "Café");
+
+        let accents = SearchConfig {
+            diacritics: true,
+            ..none
+        };
+        assert_eq!(normalize(&accents, "Café"), "Cafe");
+    }
+
+    #[test]
+    fn test_words() {
+        let config = SearchConfig::default();
+        assert_eq!(
+            words(&config, "Crème brûlée, it's Über").collect::<Vec<String>>(),
+            vec!["creme", "brulee", "it", "s", "uber"]
+        );
+        let keep_accents = SearchConfig {
+            diacritics: false,
+            ..config
+        };
+        assert_eq!(
+            words(&keep_accents, "Café au lait").collect::<Vec<String>>(),
+            vec!["cafe\u{301}", "au", "lait"]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Serves the [`Router`] via plain HTTP or HTTPS, depending on the [`Config`]
+use std::net::SocketAddr;
+
+use axum::http::header::HOST;
+use axum::http::{HeaderMap, StatusCode, Uri};
+use axum::response::Redirect;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use tracing::{info, warn};
+
+use crate::config::{Config, TlsConfig};
+
+/// Serves the app until the server stops
+pub async fn serve(app: Router, config: &Config) {
+    match &config.tls {
+        Some(tls) => serve_tls(app, config.address, tls).await,
+        None => {
+            info!("listening on http://{}", config.address);
+            axum::Server::bind(&config.address)
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        }
+    }
+}
+
+async fn serve_tls(app: Router, address: SocketAddr, tls: &TlsConfig) {
+    let rustls = RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+        .await
+        .expect("unable to load TLS certificate and key");
+
+    tokio::spawn(reload_on_sighup(rustls.clone(), tls.clone()));
+
+    if let Some(redirect_address) = tls.redirect_address {
+        tokio::spawn(redirect_to_https(redirect_address, address.port()));
+    }
+
+    info!("listening on https://{}", address);
+    axum_server::bind_rustls(address, rustls)
+        .serve(app.into_make_service())
+        .await
+        .unwrap();
+}
+
+/// Reloads certificate and key from disk whenever the process receives `SIGHUP`,
+/// e.g. after the certificate was renewed
+#[cfg(unix)]
+async fn reload_on_sighup(rustls: RustlsConfig, tls: TlsConfig) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup()).expect("unable to listen for SIGHUP");
+    while hangup.recv().await.is_some() {
+        match rustls.reload_from_pem_file(&tls.cert, &tls.key).await {
+            Ok(()) => info!("Reloaded TLS certificate"),
+            // the previous certificate stays active
+            Err(err) => warn!("Unable to reload TLS certificate: {}", err),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_sighup(_rustls: RustlsConfig, _tls: TlsConfig) {}
+
+/// Runs a plain HTTP server that redirects every request to HTTPS
+async fn redirect_to_https(address: SocketAddr, https_port: u16) {
+    let redirect = move |headers: HeaderMap, uri: Uri| async move {
+        let Some(host) = headers.get(HOST).and_then(|host| host.to_str().ok()) else {
+            return Err(StatusCode::BAD_REQUEST);
+        };
+        Ok(Redirect::permanent(&https_url(host, &uri, https_port)))
+    };
+
+    info!("redirecting http://{} to HTTPS", address);
+    axum::Server::bind(&address)
+        .serve(Router::new().fallback(redirect).into_make_service())
+        .await
+        .unwrap();
+}
+
+/// Builds the HTTPS URL for the `host` (as sent in the `Host` header) and `uri`
+fn https_url(host: &str, uri: &Uri, https_port: u16) -> String {
+    // the port of the Host header is the port of the HTTP server, it must be replaced
+    let hostname = match host.rsplit_once(':') {
+        Some((hostname, port)) if port.chars().all(|c| c.is_ascii_digit()) => hostname,
+        _ => host,
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    if https_port == 443 {
+        format!("https://{hostname}{path}")
+    } else {
+        format!("https://{hostname}:{https_port}{path}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_https_url() {
+        let uri: Uri = "/notes?tag=todo".parse().unwrap();
+        assert_eq!(
+            https_url("example.com", &uri, 443),
+            "https://example.com/notes?tag=todo"
+        );
+        assert_eq!(
+            https_url("example.com:80", &uri, 443),
+            "https://example.com/notes?tag=todo"
+        );
+        assert_eq!(
+            https_url("127.0.0.1:8080", &uri, 3000),
+            "https://127.0.0.1:3000/notes?tag=todo"
+        );
+        assert_eq!(
+            https_url("[::1]:8080", &Uri::from_static("/"), 3000),
+            "https://[::1]:3000/"
+        );
+    }
+}
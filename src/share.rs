@@ -0,0 +1,236 @@
+//! Sends summaries of notes to the chat services that users connected, see [`Integrations`]
+//!
+//! `POST /note/:id/send?target=slack` renders the [`Message`] and queues a
+//! [`ShareNote`](crate::tasks::ShareNote) job that delivers it, so failed deliveries are retried.
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::capture;
+use crate::models::integration::{Integrations, MatrixRoom, SlackWebhook, Target};
+use crate::models::note::Note;
+use crate::site::escape;
+
+/// Timeout of a delivery to a chat service
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The rendered summary of a note, as plain text and HTML
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Message {
+    /// Slack `mrkdwn`
+    text: String,
+    html: String,
+}
+
+/// Escapes the characters that Slack interprets as control sequences
+fn escape_slack(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl Message {
+    /// Renders the title, the first `preview_length` characters of the body and the tags
+    ///
+    /// `link` is the URL of the public page of the note, if it has one.
+    pub fn new(note: &Note, link: Option<&str>, preview_length: usize) -> Self {
+        let mut chars = note.body().chars().collect::<Vec<char>>();
+        let truncated = chars.len() > preview_length;
+        chars.truncate(preview_length);
+        let mut preview = chars.into_iter().collect::<String>().trim().to_string();
+        if truncated {
+            preview.push('…');
+        }
+        let mut tags = note.tags().map(|tag| tag.label()).collect::<Vec<&str>>();
+        tags.sort_unstable();
+        let tags = tags
+            .iter()
+            .map(|tag| format!("#{tag}"))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        let mut text = match link {
+            Some(link) => format!("*<{}|{}>*", link, escape_slack(note.title())),
+            None => format!("*{}*", escape_slack(note.title())),
+        };
+        let mut html = match link {
+            Some(link) => format!(
+                "<strong><a href=\"{}\">{}</a></strong>",
+                escape(link),
+                escape(note.title())
+            ),
+            None => format!("<strong>{}</strong>", escape(note.title())),
+        };
+        if !preview.is_empty() {
+            text.push_str(&format!("\n{}", escape_slack(&preview)));
+            html.push_str(&format!("<br>{}", escape(&preview).replace('\n', "<br>")));
+        }
+        if !tags.is_empty() {
+            text.push_str(&format!("\n_{}_", escape_slack(&tags)));
+            html.push_str(&format!("<br><em>{}</em>", escape(&tags)));
+        }
+        Self { text, html }
+    }
+
+    /// The plain text of the message, without markup
+    fn plain(&self) -> String {
+        let mut plain = String::with_capacity(self.text.len());
+        let mut in_link = false;
+        for c in self.text.chars() {
+            match c {
+                '*' | '_' => {}
+                '<' => in_link = true,
+                '|' if in_link => in_link = false,
+                '>' => {}
+                c if !in_link => plain.push(c),
+                _ => {}
+            }
+        }
+        plain
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&")
+    }
+}
+
+/// Returns the request body of a Slack incoming webhook
+fn slack_payload(message: &Message) -> serde_json::Value {
+    json!({ "text": message.text })
+}
+
+/// Returns the content of an `m.room.message` event
+fn matrix_payload(message: &Message) -> serde_json::Value {
+    json!({
+        "msgtype": "m.text",
+        "body": message.plain(),
+        "format": "org.matrix.custom.html",
+        "formatted_body": message.html,
+    })
+}
+
+/// Returns the URL to send a message to the room, `txn` makes retries idempotent
+fn matrix_url(room: &MatrixRoom, txn: &str) -> Result<Url, String> {
+    let mut url =
+        Url::parse(&room.homeserver).map_err(|err| format!("Invalid homeserver: {err}"))?;
+    url.path_segments_mut()
+        .map_err(|_| "Invalid homeserver".to_string())?
+        .pop_if_empty()
+        .extend([
+            "_matrix",
+            "client",
+            "v3",
+            "rooms",
+            &room.room_id,
+            "send",
+            "m.room.message",
+            txn,
+        ]);
+    Ok(url)
+}
+
+/// Returns a client that only connects to public addresses, see [`capture::PublicResolver`]
+fn client() -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .redirect(Policy::none())
+        .dns_resolver(Arc::new(capture::PublicResolver))
+        .timeout(TIMEOUT)
+        .build()
+        .map_err(|err| format!("Unable to create HTTP client: {err}"))
+}
+
+async fn send_slack(webhook: &SlackWebhook, message: &Message) -> Result<(), String> {
+    let response = client()?
+        .post(&webhook.webhook_url)
+        .json(&slack_payload(message))
+        .send()
+        .await
+        .map_err(|err| format!("Unable to reach Slack: {err}"))?;
+    if !response.status().is_success() {
+        return Err(format!("Slack responded with {}", response.status()));
+    }
+    Ok(())
+}
+
+async fn send_matrix(room: &MatrixRoom, message: &Message, txn: &str) -> Result<(), String> {
+    let url = matrix_url(room, txn)?;
+    // the homeserver is chosen by the user, it must not be an internal service
+    capture::check_public(&url).await.map_err(|(_, err)| err)?;
+    let response = client()?
+        .put(url)
+        .bearer_auth(&room.access_token)
+        .json(&matrix_payload(message))
+        .send()
+        .await
+        .map_err(|err| format!("Unable to reach the Matrix homeserver: {err}"))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "The Matrix homeserver responded with {}",
+            response.status()
+        ));
+    }
+    Ok(())
+}
+
+/// Delivers the message to the target, fails if the user disconnected the target
+pub async fn send(
+    integrations: &Integrations,
+    target: Target,
+    message: &Message,
+    txn: &str,
+) -> Result<(), String> {
+    match target {
+        Target::Slack => match integrations.slack() {
+            Some(webhook) => send_slack(webhook, message).await,
+            None => Err("Slack is not connected".to_string()),
+        },
+        Target::Matrix => match integrations.matrix() {
+            Some(room) => send_matrix(room, message, txn).await,
+            None => Err("Matrix is not connected".to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+
+    #[test]
+    fn test_message() {
+        let note = example_note();
+        let message = Message::new(&note, Some("https://notes.example.com/p/a&b"), 5);
+        assert!(message.text.starts_with(&format!(
+            "*<https://notes.example.com/p/a&b|{}>*\n",
+            escape_slack(note.title())
+        )));
+        assert!(message
+            .html
+            .contains("<a href=\"https://notes.example.com/p/a&amp;b\">"));
+        assert!(message.text.contains("\nTest-…\n"));
+        assert!(!message.plain().contains('<'));
+        assert!(message.plain().starts_with(note.title()));
+
+        let message = Message {
+            text: "*a &lt;b&gt;*".to_string(),
+            html: String::new(),
+        };
+        assert_eq!(message.plain(), "a <b>");
+    }
+
+    #[test]
+    fn test_matrix_url() {
+        let room = MatrixRoom {
+            homeserver: "https://matrix.example.org/".to_string(),
+            room_id: "!abc:example.org".to_string(),
+            access_token: "secret".to_string(),
+        };
+        assert_eq!(
+            matrix_url(&room, "t1").unwrap().as_str(),
+            "https://matrix.example.org/_matrix/client/v3/rooms/!abc:example.org/send/m.room.message/t1"
+        );
+    }
+}
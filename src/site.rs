@@ -0,0 +1,186 @@
+//! Minimal HTML pages and a sitemap for notes that anonymous visitors can read
+//!
+//! The pages contain the rendered body and OpenGraph meta tags, so that
+//! shared links unfurl in chat apps. The templates use `{{name}}` placeholders that
+//! [`fill`] replaces with values, which must already be escaped with [`escape`].
+use crate::models::note::Note;
+use crate::render;
+
+/// Number of characters of the body in the description of a page
+const DESCRIPTION_LENGTH: usize = 200;
+
+const NOTE_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{title}}</title>
+<meta name="description" content="{{description}}">
+<meta name="robots" content="{{robots}}">
+<link rel="canonical" href="{{url}}">
+<meta property="og:type" content="article">
+<meta property="og:title" content="{{title}}">
+<meta property="og:description" content="{{description}}">
+<meta property="og:url" content="{{url}}">
+<meta property="article:published_time" content="{{created}}">
+<meta property="article:modified_time" content="{{updated}}">
+</head>
+<body>
+<article>
+<h1>{{title}}</h1>
+{{body}}
+</article>
+</body>
+</html>
+"#;
+
+const SITEMAP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+{{urls}}</urlset>
+"#;
+
+const SITEMAP_URL: &str = "<url><loc>{{url}}</loc><lastmod>{{updated}}</lastmod></url>\n";
+
+/// Escapes the characters with a special meaning in HTML and XML
+pub fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Replaces every `{{name}}` placeholder of the template with its value
+///
+/// Placeholders without a value are kept. Values are inserted in a single pass,
+/// so placeholders within values are not replaced.
+pub fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut res = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find("}}").and_then(|end| {
+            let (_, value) = values.iter().find(|(name, _)| *name == &rest[2..end])?;
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                res.push_str(value);
+                rest = &rest[end + 2..];
+            }
+            None => {
+                res.push_str("{{");
+                rest = &rest[2..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// Returns the URL of the page of the note, `None` if the note has no slug
+pub fn url(base_url: &str, note: &Note) -> Option<String> {
+    Some(format!("{base_url}/p/{}", note.slug()?))
+}
+
+/// Renders the page of the note
+///
+/// Pages with `indexed` set to `false` ask search engines not to index them.
+pub fn page(note: &Note, base_url: &str, indexed: bool) -> String {
+    let description = note
+        .body()
+        .text()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .chars()
+        .take(DESCRIPTION_LENGTH)
+        .collect::<String>();
+    fill(
+        NOTE_PAGE,
+        &[
+            ("title", &escape(note.title())),
+            ("description", &escape(&description)),
+            ("robots", if indexed { "index" } else { "noindex" }),
+            ("url", &escape(&url(base_url, note).unwrap_or_default())),
+            ("created", &note.created().to_rfc3339()),
+            ("updated", &note.updated().to_rfc3339()),
+            ("body", &render::html(note.format(), &note.body().text())),
+        ],
+    )
+}
+
+/// Renders the sitemap with the pages of all notes with a slug
+pub fn sitemap<'a>(notes: impl IntoIterator<Item = &'a Note>, base_url: &str) -> String {
+    let urls = notes
+        .into_iter()
+        .filter_map(|note| {
+            Some(fill(
+                SITEMAP_URL,
+                &[
+                    ("url", &escape(&url(base_url, note)?)),
+                    ("updated", &note.updated().format("%Y-%m-%d").to_string()),
+                ],
+            ))
+        })
+        .collect::<String>();
+    fill(SITEMAP, &[("urls", &urls)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+    use crate::models::note::{Draft, Tags};
+    use crate::models::{Id, Visibility};
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(fill("<b>{{a}}</b>{{b}}", &[("a", "x")]), "<b>x</b>{{b}}");
+        assert_eq!(fill("{{a}}{{b}}", &[("a", "{{b}}"), ("b", "y")]), "{{b}}y");
+        assert_eq!(fill("{{a", &[("a", "x")]), "{{a");
+        assert_eq!(
+            escape(r#"<a href="x">Tom & 'Jerry'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_page() {
+        let draft = Draft::new(
+            "Fish & Chips".to_string(),
+            "# Recipe\n\nFry __everything__\n\n<script>alert(1)</script>".to_string(),
+            vec![],
+            Visibility::Public,
+        );
+        let note = Note::new(draft, Id(1), Id(1), Tags::default()).with_slug(Some("fish".into()));
+        let html = page(&note, "https://notes.example.com", true);
+        assert!(html.contains("<title>Fish &amp; Chips</title>"));
+        assert!(html.contains(r#"content="https://notes.example.com/p/fish""#));
+        assert!(html.contains(r#"<meta name="robots" content="index">"#));
+        assert!(html.contains("<h1>Recipe</h1>"));
+        assert!(html.contains("<strong>everything</strong>"));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("{{"));
+
+        assert!(page(&note, "", false).contains(r#"content="noindex""#));
+    }
+
+    #[test]
+    fn test_sitemap() {
+        let note = example_note();
+        let without_slug = example_note().with_slug(None);
+        let xml = sitemap([&note, &without_slug], "http://localhost");
+        assert!(xml.starts_with("<?xml"));
+        assert_eq!(xml.matches("<url>").count(), 1);
+        assert!(xml.contains("<loc>http://localhost/p/test-title</loc>"));
+    }
+}
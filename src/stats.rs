@@ -0,0 +1,7 @@
+//! Aggregated statistics for dashboards
+//!
+//! Statistics are materialized from the [`IndexEvent`](crate::indexer::IndexEvent)s
+//! in the background, so requests never scan the complete note storage.
+pub mod recent;
+pub mod rollup;
+pub mod tag_graph;
@@ -0,0 +1,102 @@
+//! The notes that every user viewed or edited most recently, for `GET /notes/recent`
+use std::collections::{HashMap, VecDeque};
+
+use crate::indexer::IndexEvent;
+use crate::models::Id;
+
+/// A bounded list of the most recently used notes per user, most recent first
+///
+/// Deleted notes are removed from all lists. Notes that the user may not read
+/// anymore must be filtered when the list is read.
+#[derive(Debug)]
+pub struct RecentNotes {
+    capacity: usize,
+    users: HashMap<Id, VecDeque<Id>>,
+}
+
+impl RecentNotes {
+    /// Creates empty lists that keep up to `capacity` notes per user
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            users: HashMap::new(),
+        }
+    }
+
+    /// Moves the notes of the [`IndexEvent`] to the front of the list of their user
+    pub fn apply(&mut self, event: &IndexEvent) {
+        match event {
+            IndexEvent::Added(note)
+            | IndexEvent::Updated(note)
+            | IndexEvent::Restored(note)
+            | IndexEvent::Archived(note) => self.touch(*note.user(), *note.id()),
+            IndexEvent::Viewed { user, note } => self.touch(*user, *note),
+            IndexEvent::Deleted(note) => {
+                for notes in self.users.values_mut() {
+                    notes.retain(|id| id != note.id());
+                }
+                self.users.retain(|_, notes| !notes.is_empty());
+            }
+            IndexEvent::UserPurged(user) => {
+                self.users.remove(user);
+            }
+        }
+    }
+
+    fn touch(&mut self, user: Id, note: Id) {
+        if self.capacity == 0 {
+            return;
+        }
+        let notes = self.users.entry(user).or_default();
+        notes.retain(|id| id != &note);
+        notes.push_front(note);
+        notes.truncate(self.capacity);
+    }
+
+    /// Returns the Ids of the notes that the user used most recently, most recent first
+    pub fn of(&self, user: &Id) -> Vec<Id> {
+        self.users
+            .get(user)
+            .map(|notes| notes.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+
+    #[test]
+    fn test_recent_notes() {
+        let mut recent = RecentNotes::new(2);
+        let note = example_note();
+        let owner = *note.user();
+        recent.apply(&IndexEvent::Added(note.clone()));
+        recent.apply(&IndexEvent::Viewed {
+            user: owner,
+            note: Id(2),
+        });
+        recent.apply(&IndexEvent::Viewed {
+            user: Id(99),
+            note: *note.id(),
+        });
+        assert_eq!(recent.of(&owner), vec![Id(2), *note.id()]);
+
+        recent.apply(&IndexEvent::Updated(note.clone()));
+        assert_eq!(recent.of(&owner), vec![*note.id(), Id(2)]);
+        recent.apply(&IndexEvent::Viewed {
+            user: owner,
+            note: Id(3),
+        });
+        assert_eq!(recent.of(&owner), vec![Id(3), *note.id()]);
+
+        recent.apply(&IndexEvent::Deleted(note.clone()));
+        assert_eq!(recent.of(&owner), vec![Id(3)]);
+        assert!(recent.of(&Id(99)).is_empty());
+
+        recent.apply(&IndexEvent::UserPurged(owner));
+        assert!(recent.of(&owner).is_empty());
+        assert!(recent.users.is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+//! Daily aggregates of the note activity of every user
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::dates;
+use crate::indexer::IndexEvent;
+use crate::models::Id;
+
+/// The length of the periods in which the activity is counted
+///
+/// The offsets of all timezones are multiples of it, so the periods add up to the days of
+/// every timezone.
+pub const BUCKET_SECONDS: i64 = 15 * 60;
+
+/// The activity of a single user on a single day in the timezone of the user
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DailyStats {
+    date: NaiveDate,
+    /// Number of notes created on the day
+    created: usize,
+    /// Number of edits of notes on the day
+    edited: usize,
+    /// Number of notes deleted on the day
+    deleted: usize,
+}
+
+impl DailyStats {
+    fn new(date: NaiveDate) -> Self {
+        Self {
+            date,
+            created: 0,
+            edited: 0,
+            deleted: 0,
+        }
+    }
+
+    fn add(&mut self, activity: &Activity) {
+        self.created += activity.created;
+        self.edited += activity.edited;
+        self.deleted += activity.deleted;
+    }
+}
+
+/// The sum of the [`DailyStats`] of several days
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Activity {
+    pub created: usize,
+    pub edited: usize,
+    pub deleted: usize,
+}
+
+/// Aggregates of [`BUCKET_SECONDS`], keyed by user and the start of the period (UTC)
+///
+/// Only periods with any activity are stored. They are summed up into the days of the
+/// timezone that a query asks for.
+#[derive(Debug, Default)]
+pub struct DailyRollup {
+    buckets: BTreeMap<(Id, DateTime<Utc>), Activity>,
+}
+
+impl DailyRollup {
+    /// Counts the activity of a single [`IndexEvent`]
+    pub fn apply(&mut self, event: &IndexEvent) {
+        match event {
+            IndexEvent::Added(note) => self.bucket(*note.user(), note.created()).created += 1,
+            IndexEvent::Updated(note) => self.bucket(*note.user(), note.updated()).edited += 1,
+            IndexEvent::Deleted(note) => self.bucket(*note.user(), &Utc::now()).deleted += 1,
+            // restoring a note only reverts its deletion, archiving doesn't edit it
+            IndexEvent::Restored(_) | IndexEvent::Archived(_) | IndexEvent::Viewed { .. } => {}
+            IndexEvent::UserPurged(user) => self.buckets.retain(|(id, _), _| id != user),
+        }
+    }
+
+    fn bucket(&mut self, user: Id, time: &DateTime<Utc>) -> &mut Activity {
+        let start = time.timestamp().div_euclid(BUCKET_SECONDS) * BUCKET_SECONDS;
+        let start = DateTime::from_timestamp(start, 0).expect("the start of a valid time is valid");
+        self.buckets.entry((user, start)).or_default()
+    }
+
+    /// Returns the stats of the user for all days with activity between `from` and `to`
+    /// (inclusive), days in the timezone
+    pub fn query(
+        &self,
+        user: &Id,
+        from: NaiveDate,
+        to: NaiveDate,
+        timezone: &Tz,
+    ) -> Vec<DailyStats> {
+        if from > to {
+            return vec![];
+        }
+        let start = dates::start_of_day(from, timezone);
+        let end = to.succ_opt().map_or(DateTime::<Utc>::MAX_UTC, |next| {
+            dates::start_of_day(next, timezone)
+        });
+        let mut days = Vec::<DailyStats>::new();
+        for ((_, time), activity) in self.buckets.range((*user, start)..(*user, end)) {
+            let date = dates::local_date(time, timezone);
+            match days.last_mut() {
+                Some(stats) if stats.date == date => stats.add(activity),
+                _ => {
+                    let mut stats = DailyStats::new(date);
+                    stats.add(activity);
+                    days.push(stats);
+                }
+            }
+        }
+        days
+    }
+
+    /// Sums up the stats of the user between `from` and `to` (inclusive), days in the timezone
+    pub fn activity(&self, user: &Id, from: NaiveDate, to: NaiveDate, timezone: &Tz) -> Activity {
+        let mut activity = Activity::default();
+        for stats in self.query(user, from, to, timezone) {
+            activity.created += stats.created;
+            activity.edited += stats.edited;
+            activity.deleted += stats.deleted;
+        }
+        activity
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+
+    #[test]
+    fn test_rollup() {
+        let today = Utc::now().date_naive();
+        let user = *example_note().user();
+        let mut rollup = DailyRollup::default();
+
+        rollup.apply(&IndexEvent::Added(example_note()));
+        rollup.apply(&IndexEvent::Added(example_note()));
+        rollup.apply(&IndexEvent::Updated(example_note()));
+        rollup.apply(&IndexEvent::Deleted(example_note()));
+
+        assert_eq!(
+            rollup.query(&user, today, today, &Tz::UTC),
+            vec![DailyStats {
+                date: today,
+                created: 2,
+                edited: 1,
+                deleted: 1
+            }]
+        );
+        assert!(rollup.query(&Id(666), today, today, &Tz::UTC).is_empty());
+        let activity = rollup.activity(&user, today.pred_opt().unwrap(), today, &Tz::UTC);
+        assert_eq!(
+            (activity.created, activity.edited, activity.deleted),
+            (2, 1, 1)
+        );
+        assert!(rollup
+            .query(
+                &user,
+                today.pred_opt().unwrap(),
+                today.pred_opt().unwrap(),
+                &Tz::UTC
+            )
+            .is_empty());
+        assert!(rollup
+            .query(&user, today, today.pred_opt().unwrap(), &Tz::UTC)
+            .is_empty());
+
+        rollup.apply(&IndexEvent::UserPurged(user));
+        assert!(rollup.query(&user, today, today, &Tz::UTC).is_empty());
+    }
+
+    #[test]
+    fn test_rollup_timezone() {
+        let mut rollup = DailyRollup::default();
+        let user = *example_note().user();
+        for created in [
+            "2024-05-15T21:59:00Z",
+            "2024-05-15T22:00:00Z",
+            "2024-05-16T12:00:00Z",
+        ] {
+            let mut note = serde_json::to_value(example_note()).unwrap();
+            note["created"] = created.into();
+            rollup.apply(&IndexEvent::Added(serde_json::from_value(note).unwrap()));
+        }
+        let day = |day| NaiveDate::from_ymd_opt(2024, 5, day).unwrap();
+        let created = |timezone| {
+            rollup
+                .query(&user, day(15), day(16), &timezone)
+                .into_iter()
+                .map(|stats| (stats.date, stats.created))
+                .collect::<Vec<(NaiveDate, usize)>>()
+        };
+        assert_eq!(created(Tz::UTC), vec![(day(15), 2), (day(16), 1)]);
+        assert_eq!(
+            created(Tz::Europe__Berlin),
+            vec![(day(15), 1), (day(16), 2)]
+        );
+        assert_eq!(created(Tz::Asia__Kolkata), vec![(day(16), 3)]);
+        assert_eq!(
+            rollup
+                .query(&user, day(16), day(16), &Tz::Europe__Berlin)
+                .len(),
+            1,
+            "days before `from` are not included"
+        );
+    }
+}
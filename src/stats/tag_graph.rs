@@ -0,0 +1,166 @@
+//! How often tags are used together in the notes of a user, for `GET /tags/graph`
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+
+/// A tag with the number of notes that use it
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct TagNode {
+    label: String,
+    count: usize,
+}
+
+/// Two tags that are used together, with the number of notes that use both
+///
+/// `source` is always ordered before `target`, edges are undirected.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct TagEdge {
+    source: String,
+    target: String,
+    weight: usize,
+}
+
+/// The response of `GET /tags/graph`, sorted by label
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct TagGraphView {
+    nodes: Vec<TagNode>,
+    edges: Vec<TagEdge>,
+}
+
+/// The co-occurrence of the tags of a single user
+///
+/// The graph is updated with the tags of every added and removed note, so that
+/// it never has to be computed from all notes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TagGraph {
+    counts: BTreeMap<String, usize>,
+    /// Keyed by the pair of labels, the smaller label first
+    edges: BTreeMap<(String, String), usize>,
+}
+
+impl TagGraph {
+    /// Counts the tags of a note
+    pub fn add(&mut self, tags: &HashSet<String>) {
+        for label in tags {
+            *self.counts.entry(label.clone()).or_default() += 1;
+        }
+        for pair in pairs(tags) {
+            *self.edges.entry(pair).or_default() += 1;
+        }
+    }
+
+    /// Reverts [`TagGraph::add`] for the tags of a note, tags and edges without notes are dropped
+    pub fn remove(&mut self, tags: &HashSet<String>) {
+        for label in tags {
+            if let Some(count) = self.counts.get_mut(label) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(label);
+                }
+            }
+        }
+        for pair in pairs(tags) {
+            if let Some(weight) = self.edges.get_mut(&pair) {
+                *weight -= 1;
+                if *weight == 0 {
+                    self.edges.remove(&pair);
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if the user has no tagged notes
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn view(&self) -> TagGraphView {
+        TagGraphView {
+            nodes: self
+                .counts
+                .iter()
+                .map(|(label, count)| TagNode {
+                    label: label.clone(),
+                    count: *count,
+                })
+                .collect(),
+            edges: self
+                .edges
+                .iter()
+                .map(|((source, target), weight)| TagEdge {
+                    source: source.clone(),
+                    target: target.clone(),
+                    weight: *weight,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Returns all unordered pairs of the labels, the smaller label first
+fn pairs(tags: &HashSet<String>) -> Vec<(String, String)> {
+    let mut labels = tags.iter().collect::<Vec<&String>>();
+    labels.sort();
+    let mut res = Vec::new();
+    for (i, source) in labels.iter().enumerate() {
+        for target in &labels[i + 1..] {
+            res.push(((*source).clone(), (*target).clone()));
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tags(labels: &[&str]) -> HashSet<String> {
+        labels.iter().map(|label| label.to_string()).collect()
+    }
+
+    #[test]
+    fn test_tag_graph() {
+        let mut graph = TagGraph::default();
+        graph.add(&tags(&["rust", "web", "todo"]));
+        graph.add(&tags(&["web", "rust"]));
+        graph.add(&tags(&["todo"]));
+
+        let view = graph.view();
+        assert_eq!(
+            view.nodes,
+            vec![
+                TagNode {
+                    label: "rust".to_string(),
+                    count: 2
+                },
+                TagNode {
+                    label: "todo".to_string(),
+                    count: 2
+                },
+                TagNode {
+                    label: "web".to_string(),
+                    count: 2
+                },
+            ]
+        );
+        assert_eq!(view.edges.len(), 3);
+        assert_eq!(
+            view.edges[2],
+            TagEdge {
+                source: "todo".to_string(),
+                target: "web".to_string(),
+                weight: 1
+            }
+        );
+        assert_eq!(view.edges[1].weight, 2);
+
+        graph.remove(&tags(&["rust", "web", "todo"]));
+        graph.remove(&tags(&["todo"]));
+        assert_eq!(graph.view().edges.len(), 1);
+        assert_eq!(graph.view().nodes.len(), 2);
+
+        graph.remove(&tags(&["web", "rust"]));
+        assert!(graph.is_empty());
+        assert_eq!(graph, TagGraph::default());
+    }
+}
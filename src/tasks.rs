@@ -0,0 +1,778 @@
+//! Background work of the app, executed by the [`JobQueue`](crate::jobs::JobQueue)
+use std::collections::HashSet;
+use std::thread;
+use std::time::Duration;
+
+use axum::async_trait;
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::activitypub::ActivityKind;
+use crate::auth::ANONYMOUS_USER;
+use crate::config::ExpiryAction;
+use crate::digest::{self, Digest};
+use crate::indexer::IndexEvent;
+use crate::jobs::{report_progress, Job};
+use crate::models::backup::{Backup, RestoreMode};
+use crate::models::follower::Follower;
+use crate::models::integration::Target;
+use crate::models::job::JobStatus;
+use crate::models::lifecycle::State;
+use crate::models::mention::{mentions, Notification};
+use crate::models::note::Note;
+use crate::models::{Access, Id, Visibility, VisibilityFilter};
+use crate::persistence::Persister;
+use crate::share::{self, Message};
+use crate::{integrity, publish, AppState};
+
+/// Purges the account of a user once the deletion grace period is over
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PurgeUser {
+    user: Id,
+}
+
+impl PurgeUser {
+    pub fn new(user: Id) -> Self {
+        Self { user }
+    }
+}
+
+#[async_trait]
+impl<P> Job<AppState<P>> for PurgeUser
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "purge_user";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let now = Utc::now();
+        let mut data = state.data.lock().expect("mutex was poisoned");
+        let due = data
+            .user(self.user)
+            .and_then(|user| user.purge_at())
+            .is_some_and(|purge_at| purge_at <= &now);
+        if !due {
+            // the user was purged already or the job ran too early
+            return Ok(());
+        }
+        data.purge_user(self.user);
+        state.indexer.send(IndexEvent::UserPurged(self.user));
+        info!("Purged account of user {}", usize::from(self.user));
+        Ok(())
+    }
+}
+
+/// Removes all expired undo tokens, so the deletions can't be undone anymore
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExpireUndoTokens;
+
+#[async_trait]
+impl<P> Job<AppState<P>> for ExpireUndoTokens
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "expire_undo_tokens";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let mut data = state.data.lock().expect("mutex was poisoned");
+        let count = data.expire_undo_tokens(&Utc::now());
+        if count > 0 {
+            info!("Expired {} undo tokens", count);
+        }
+        Ok(())
+    }
+}
+
+/// Removes all expired working copies of notes
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExpireWorkingCopies;
+
+#[async_trait]
+impl<P> Job<AppState<P>> for ExpireWorkingCopies
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "expire_working_copies";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let mut data = state.data.lock().expect("mutex was poisoned");
+        let count = data.expire_working_copies(&Utc::now());
+        if count > 0 {
+            info!("Expired {} working copies", count);
+        }
+        Ok(())
+    }
+}
+
+/// Deletes or archives the notes whose expiry has passed, depending on `NOTE_EXPIRY_ACTION`
+///
+/// Readers don't see expired notes even before the job runs, see [`Note::is_expired`]. The job
+/// is scheduled for the expiry of every saved note, and schedules itself for the next expiry.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExpireNotes;
+
+impl ExpireNotes {
+    /// Schedules a run for the expiry of the note, if it has one
+    pub fn schedule<P>(state: &AppState<P>, note: &Note)
+    where
+        P: for<'a> Persister<'a> + Send + 'static,
+    {
+        if let Some(at) = note.expires_at() {
+            Self::schedule_at(state, *at);
+        }
+    }
+
+    /// Schedules a run at `at`, unless a run is due before, which schedules the next run itself
+    fn schedule_at<P>(state: &AppState<P>, at: DateTime<Utc>)
+    where
+        P: for<'a> Persister<'a> + Send + 'static,
+    {
+        let scheduled = state.jobs.jobs().iter().any(|job| {
+            job.name() == <Self as Job<AppState<P>>>::NAME
+                && job.status() == JobStatus::Pending
+                && job.run_at() <= &at
+        });
+        if !scheduled {
+            state.jobs.schedule(&ExpireNotes, at);
+        }
+    }
+
+    /// Schedules the first run for the note that expires next, e.g. at startup
+    pub fn start<P>(state: &AppState<P>)
+    where
+        P: for<'a> Persister<'a> + Send + 'static,
+    {
+        let next = Self::next_expiry(&*state.data.lock().expect("mutex was poisoned"));
+        if let Some(at) = next {
+            Self::schedule_at(state, at);
+        }
+    }
+
+    /// Returns the earliest expiry of the notes that did not expire yet
+    fn next_expiry<P>(data: &P) -> Option<DateTime<Utc>>
+    where
+        P: for<'a> Persister<'a>,
+    {
+        data.notes_with(VisibilityFilter::All)
+            .filter(|note| note.state() == State::Active)
+            .filter_map(|note| note.expires_at().copied())
+            .min()
+    }
+}
+
+#[async_trait]
+impl<P> Job<AppState<P>> for ExpireNotes
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "expire_notes";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let now = Utc::now();
+        let mut data = state.data.lock().expect("mutex was poisoned");
+        let expired = data
+            .notes_with(VisibilityFilter::All)
+            .filter(|note| note.is_expired(&now))
+            .cloned()
+            .collect::<Vec<Note>>();
+        for note in &expired {
+            let id = *note.id();
+            let event = match state.config.expiry_action {
+                ExpiryAction::Delete => {
+                    data.delete_note(id);
+                    data.purge_notes(&[id]);
+                    IndexEvent::Deleted(note.clone())
+                }
+                ExpiryAction::Archive => {
+                    data.set_archived(id, true);
+                    match data.note(id) {
+                        Some(note) => IndexEvent::Archived(note.clone()),
+                        None => continue,
+                    }
+                }
+            };
+            state.indexer.send(event);
+        }
+        if !expired.is_empty() {
+            info!(
+                "Expired {} notes ({:?})",
+                expired.len(),
+                state.config.expiry_action
+            );
+        }
+        if let Some(next) = Self::next_expiry(&*data) {
+            drop(data);
+            Self::schedule_at(state, next);
+        }
+        Ok(())
+    }
+}
+
+/// Writes the static site with all public notes, see [`crate::publish`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PublishSite {
+    /// The public URL of the app, for the feed and the canonical links of the pages
+    base_url: String,
+}
+
+impl PublishSite {
+    pub fn new(base_url: String) -> Self {
+        Self { base_url }
+    }
+}
+
+#[async_trait]
+impl<P> Job<AppState<P>> for PublishSite
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "publish_site";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let Some(dir) = &state.config.publish_dir else {
+            return Err("NOTE_PUBLISH_DIR is not set".to_string());
+        };
+        let notes = {
+            let data = state.data.lock().expect("mutex was poisoned");
+            data.notes_with(VisibilityFilter::Active)
+                .filter(|note| note.visibility().allows(&ANONYMOUS_USER, Access::Listing))
+                .cloned()
+                .collect::<Vec<Note>>()
+        };
+        let files = publish::render(&notes, &self.base_url);
+        let total = files.len();
+        report_progress(0, total);
+        publish::write(dir, &files, &mut |done| report_progress(done, total))
+            .map_err(|err| format!("Unable to write the site to {}: {err}", dir.display()))?;
+        info!("Published {} files to {}", total, dir.display());
+        Ok(())
+    }
+}
+
+/// Restores a [`Backup`] that was uploaded with `POST /admin/restore`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Restore {
+    backup: Backup,
+    mode: RestoreMode,
+}
+
+impl Restore {
+    pub fn new(backup: Backup, mode: RestoreMode) -> Self {
+        Self { backup, mode }
+    }
+}
+
+/// The number of restored records between two progress reports
+const RESTORE_PROGRESS_STEP: usize = 100;
+
+#[async_trait]
+impl<P> Job<AppState<P>> for Restore
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "restore";
+
+    // a failed restore needs a look from an admin, not another attempt
+    const MAX_ATTEMPTS: u32 = 1;
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let mut data = state.data.lock().expect("mutex was poisoned");
+        // the data may have changed since the archive was validated
+        if self.mode == RestoreMode::Merge {
+            let conflicts = data.merge_conflicts(&self.backup);
+            if !conflicts.is_empty() {
+                return Err(conflicts.join("; "));
+            }
+        }
+        let previous = data.users().map(|user| *user.id()).collect::<Vec<Id>>();
+        let existing = match self.mode {
+            RestoreMode::Replace => HashSet::new(),
+            RestoreMode::Merge => data
+                .notes_with(VisibilityFilter::All)
+                .map(|note| *note.id())
+                .collect::<HashSet<Id>>(),
+        };
+        let total = self.backup.records();
+        report_progress(0, total);
+        data.restore(&self.backup, self.mode, &mut |done| {
+            if done % RESTORE_PROGRESS_STEP == 0 || done == total {
+                report_progress(done, total);
+            }
+        });
+
+        if self.mode == RestoreMode::Replace {
+            for user in previous {
+                state.indexer.send(IndexEvent::UserPurged(user));
+            }
+        }
+        let restored = self
+            .backup
+            .notes()
+            .iter()
+            .filter(|note| !existing.contains(note.id()));
+        index_restored(state, &mut *data, restored);
+        info!("Restored {} records ({:?})", total, self.mode);
+        Ok(())
+    }
+}
+
+/// Removes all tags that are not used by any active note anymore
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CollectTags;
+
+#[async_trait]
+impl<P> Job<AppState<P>> for CollectTags
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "collect_tags";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let mut data = state.data.lock().expect("mutex was poisoned");
+        let count = data.collect_tags();
+        if count > 0 {
+            info!("Removed {} unused tags", count);
+        }
+        Ok(())
+    }
+}
+
+/// Schedules [`CollectTags`] after tags of notes were removed, unless unused tags are kept
+pub fn collect_tags<P>(state: &AppState<P>)
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    if state.config.keep_tags {
+        return;
+    }
+    let at = Utc::now()
+        + TimeDelta::from_std(state.config.tag_gc_delay).expect("tag GC delay is out of range");
+    state.jobs.schedule(&CollectTags, at);
+}
+
+/// Delivers an ActivityPub activity of a user to the inbox of a follower
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Deliver {
+    user: Id,
+    inbox: String,
+    activity: Value,
+}
+
+#[async_trait]
+impl<P> Job<AppState<P>> for Deliver
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "activitypub_deliver";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let Some(federation) = &state.federation else {
+            // federation was disabled after the job was queued
+            return Ok(());
+        };
+        federation
+            .deliver(&self.user, &self.inbox, &self.activity)
+            .await
+            .map_err(|err| format!("{err:#}"))
+    }
+}
+
+/// Queues the delivery of an activity about the note to all followers of its owner
+pub fn federate<P>(state: &AppState<P>, followers: &[&Follower], kind: ActivityKind, note: &Note)
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    let Some(federation) = &state.federation else {
+        return;
+    };
+    let activity = federation.activity(kind, note);
+    for follower in followers {
+        state.jobs.enqueue(&Deliver {
+            user: *note.user(),
+            inbox: follower.inbox().to_string(),
+            activity: activity.clone(),
+        });
+    }
+}
+
+/// Stores a new follower of a user and confirms the `Follow` activity
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AcceptFollow {
+    user: Id,
+    actor: String,
+    follow: Value,
+}
+
+impl AcceptFollow {
+    pub fn new(user: Id, actor: String, follow: Value) -> Self {
+        Self {
+            user,
+            actor,
+            follow,
+        }
+    }
+}
+
+#[async_trait]
+impl<P> Job<AppState<P>> for AcceptFollow
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "activitypub_accept_follow";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let Some(federation) = &state.federation else {
+            return Ok(());
+        };
+        let inbox = federation
+            .inbox_of(&self.actor)
+            .await
+            .map_err(|err| format!("{err:#}"))?;
+        {
+            let mut data = state.data.lock().expect("mutex was poisoned");
+            if data.user(self.user).is_none() {
+                // the user was purged in the meantime
+                return Ok(());
+            }
+            data.add_follower(Follower::new(self.user, self.actor.clone(), inbox.clone()));
+        }
+        info!("{} follows user {}", self.actor, usize::from(self.user));
+        let accept = federation.accept(&self.user, &self.follow);
+        federation
+            .deliver(&self.user, &inbox, &accept)
+            .await
+            .map_err(|err| format!("{err:#}"))
+    }
+}
+
+/// Sends a [`Notification`] to a user via the configured [`Notifier`](crate::notifier::Notifier)
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Notify {
+    user: Id,
+    notification: Notification,
+}
+
+#[async_trait]
+impl<P> Job<AppState<P>> for Notify
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "notify";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let user = {
+            let data = state.data.lock().expect("mutex was poisoned");
+            match data.user(self.user) {
+                Some(user) if user.is_active() => user.clone(),
+                // users that were purged or are about to be purged are not notified
+                _ => return Ok(()),
+            }
+        };
+        state.notifier.notify(&user, &self.notification)
+    }
+}
+
+/// Sends the summary of a note to a chat service of a user, see [`share`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ShareNote {
+    user: Id,
+    target: Target,
+    message: Message,
+    /// Identifies the delivery, so that the chat service can ignore retries
+    txn: String,
+}
+
+impl ShareNote {
+    pub fn new(user: Id, target: Target, message: Message) -> Self {
+        Self {
+            user,
+            target,
+            message,
+            txn: format!("{:016x}", rand::random::<u64>()),
+        }
+    }
+}
+
+#[async_trait]
+impl<P> Job<AppState<P>> for ShareNote
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "share_note";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let integrations = {
+            let data = state.data.lock().expect("mutex was poisoned");
+            match data.user(self.user) {
+                Some(user) if user.integrations().is_connected(self.target) => {
+                    user.integrations().clone()
+                }
+                // the user was purged or disconnected the target in the meantime
+                _ => return Ok(()),
+            }
+        };
+        share::send(&integrations, self.target, &self.message, &self.txn).await
+    }
+}
+
+/// Queues a [`SendDigest`] for every user whose digest is due, then runs again in the next hour
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SendDigests;
+
+impl SendDigests {
+    /// Returns the start of the next full hour
+    fn next_run(now: &DateTime<Utc>) -> DateTime<Utc> {
+        now.duration_trunc(TimeDelta::hours(1))
+            .expect("the hour of the current time is in range")
+            + TimeDelta::hours(1)
+    }
+
+    /// Schedules the first run, unless the [`JobStore`](crate::jobs::JobStore) has one already
+    pub fn start<P>(state: &AppState<P>)
+    where
+        P: for<'a> Persister<'a> + Send + 'static,
+    {
+        let scheduled = state
+            .jobs
+            .jobs()
+            .iter()
+            .any(|job| job.name() == <Self as Job<AppState<P>>>::NAME);
+        if !scheduled {
+            state
+                .jobs
+                .schedule(&SendDigests, Self::next_run(&Utc::now()));
+        }
+    }
+}
+
+#[async_trait]
+impl<P> Job<AppState<P>> for SendDigests
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "send_digests";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let now = Utc::now();
+        let due = {
+            let data = state.data.lock().expect("mutex was poisoned");
+            data.users()
+                .filter(|user| user.is_active() && digest::is_due(user, &now))
+                .filter_map(|user| Some((*user.id(), user.preferences().digest().period()?)))
+                .collect::<Vec<(Id, TimeDelta)>>()
+        };
+        for (user, period) in due {
+            // every digest is a job of its own, so that failed emails are retried separately
+            state.jobs.enqueue(&SendDigest {
+                user,
+                since: now - period,
+            });
+        }
+        state.jobs.schedule(self, Self::next_run(&now));
+        Ok(())
+    }
+}
+
+/// Logs the problems that [`integrity::check`] finds, then runs again after the configured interval
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CheckIntegrity;
+
+impl CheckIntegrity {
+    /// Schedules the first run, unless checks are disabled or the [`JobStore`](crate::jobs::JobStore)
+    /// has one already
+    pub fn start<P>(state: &AppState<P>)
+    where
+        P: for<'a> Persister<'a> + Send + 'static,
+    {
+        let Some(next_run) = Self::next_run(state.config.integrity_interval, &Utc::now()) else {
+            return;
+        };
+        let scheduled = state
+            .jobs
+            .jobs()
+            .iter()
+            .any(|job| job.name() == <Self as Job<AppState<P>>>::NAME);
+        if !scheduled {
+            state.jobs.schedule(&CheckIntegrity, next_run);
+        }
+    }
+
+    /// Returns the time of the next run, `None` if checks are disabled
+    fn next_run(interval: Duration, now: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if interval.is_zero() {
+            return None;
+        }
+        let interval = TimeDelta::from_std(interval).expect("integrity interval is out of range");
+        Some(*now + interval)
+    }
+}
+
+#[async_trait]
+impl<P> Job<AppState<P>> for CheckIntegrity
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "check_integrity";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let now = Utc::now();
+        let report = {
+            let data = state.data.lock().expect("mutex was poisoned");
+            let base_url = state
+                .config
+                .activitypub
+                .as_ref()
+                .map(|activitypub| activitypub.base_url.as_str());
+            integrity::check(&*data, base_url, now)
+        };
+        if report.problems.is_empty() {
+            info!(
+                "Integrity check of {} notes found no problems",
+                report.notes
+            );
+        } else {
+            warn!(
+                "Integrity check of {} notes found {} problems, see GET /admin/integrity",
+                report.notes,
+                report.problems.len()
+            );
+        }
+        if let Some(next_run) = Self::next_run(state.config.integrity_interval, &now) {
+            state.jobs.schedule(self, next_run);
+        }
+        Ok(())
+    }
+}
+
+/// Sends the [`Digest`] of a user via the configured [`Mailer`](crate::mailer::Mailer)
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SendDigest {
+    user: Id,
+    /// The start of the period, fixed when the job is queued so that retries send the same digest
+    since: DateTime<Utc>,
+}
+
+#[async_trait]
+impl<P> Job<AppState<P>> for SendDigest
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    const NAME: &'static str = "send_digest";
+
+    async fn run(&self, state: &AppState<P>) -> Result<(), String> {
+        let mail = {
+            let data = state.data.lock().expect("mutex was poisoned");
+            let Some(user) = data.user(self.user).filter(|user| user.is_active()) else {
+                return Ok(());
+            };
+            let digest = Digest::collect(&*data, user, self.since);
+            if digest.is_empty() {
+                return Ok(());
+            }
+            // the user may have opted out in the meantime
+            match digest.mail(user) {
+                Some(mail) if user.preferences().digest().period().is_some() => mail,
+                _ => return Ok(()),
+            }
+        };
+        state.mailer.send(&mail).await?;
+        info!("Sent digest to user {}", usize::from(self.user));
+        Ok(())
+    }
+}
+
+/// Stores the users mentioned in the body of the note and notifies the newly mentioned ones
+///
+/// Mentioned users are only notified about notes they may read, e.g. not about private notes.
+pub fn record_mentions<P>(state: &AppState<P>, data: &mut P, note: &Note)
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    let users = mentioned_users(data, note);
+    let added = data.set_mentions(*note.id(), users);
+    for user in added
+        .into_iter()
+        .filter(|user| user != note.user() && note.visibility().allows(user, Access::Link))
+    {
+        state.jobs.enqueue(&Notify {
+            user,
+            notification: Notification::Mention {
+                note: *note.id(),
+                title: note.title().to_string(),
+                author: *note.user(),
+            },
+        });
+    }
+}
+
+/// Records the mentions of restored notes and sends the notes that are not deleted to the indexer
+///
+/// Mentioned users are not notified, because the notes are not new.
+pub fn index_restored<'n, P, I>(state: &AppState<P>, data: &mut P, notes: I)
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+    I: IntoIterator<Item = &'n Note>,
+{
+    for note in notes {
+        let users = mentioned_users(data, note);
+        data.set_mentions(*note.id(), users);
+        if note.visibility() != &Visibility::Deleted {
+            state.indexer.send(IndexEvent::Added(note.clone()));
+            ExpireNotes::schedule(state, note);
+        }
+    }
+}
+
+/// Indexes the notes that exist at startup on a background thread
+///
+/// The server answers requests right away, handlers scan the notes instead of using the
+/// [`Index`](crate::indexer::Index) until it is [ready](crate::indexer::Indexer::is_ready).
+/// The notes are read in batches of `NOTE_WARM_UP_BATCH`, so that requests only wait for
+/// the storage while a single batch is read.
+pub fn warm_up<P>(state: &AppState<P>)
+where
+    P: for<'a> Persister<'a> + Send + 'static,
+{
+    let ids = state
+        .data
+        .lock()
+        .expect("mutex was poisoned")
+        .notes_with(VisibilityFilter::Active)
+        .map(|note| *note.id())
+        .collect::<Vec<Id>>();
+    info!("Indexing {} existing notes in the background", ids.len());
+    state.indexer.begin_warm_up(ids.len());
+    let data = state.data.clone();
+    let indexer = state.indexer.clone();
+    let batch = state.config.warm_up_batch.max(1);
+    thread::Builder::new()
+        .name("warm-up".to_string())
+        .spawn(move || {
+            for ids in ids.chunks(batch) {
+                // notes that were deleted since are skipped, changed notes are read again
+                let data = data.lock().expect("mutex was poisoned");
+                let notes = ids
+                    .iter()
+                    .filter_map(|id| data.note_with(*id, VisibilityFilter::Active))
+                    .cloned()
+                    .collect::<Vec<Note>>();
+                indexer.warm_up(notes);
+            }
+            indexer.finish_warm_up();
+        })
+        .expect("unable to spawn warm-up thread");
+}
+
+/// Returns the active users that are mentioned in the body of the note
+fn mentioned_users<P>(data: &P, note: &Note) -> HashSet<Id>
+where
+    P: for<'a> Persister<'a>,
+{
+    let names = mentions(&note.body().text());
+    data.users()
+        .filter(|user| user.is_active() && names.iter().any(|name| name == user.name()))
+        .map(|user| *user.id())
+        .collect()
+}
@@ -0,0 +1,516 @@
+//! A keyboard-friendly terminal UI to list, search and edit notes
+//!
+//! Started with `note-demo --tui [URL]`, it connects to the HTTP API of a running
+//! server, because the in-memory storage only lives inside the server process.
+//! All notes are loaded once and filtered and sorted locally with the same
+//! [`NoteQuery`] and [`SortKey`] that the server uses.
+//!
+//! Keys in the list:
+//! - `↑`/`↓` or `k`/`j` select a note
+//! - `/` searches, `Enter` keeps the search and `Esc` clears it
+//! - `s` changes the sort order, `r` reloads all notes
+//! - `e` or `Enter` edits the selected note, `n` creates a new one
+//! - `q` quits
+//!
+//! In the editor, `Tab` switches between title and body, `Ctrl+S` saves and `Esc` cancels.
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+use reqwest::header::AUTHORIZATION;
+
+use crate::models::note::{Draft, Note};
+use crate::models::query::{NoteQuery, SortKey};
+use crate::models::Id;
+
+/// The number of notes requested per page when loading all notes
+const PAGE_SIZE: usize = 500;
+
+/// Client for the HTTP API of the server
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    /// A session token, the anonymous user is used without it
+    token: Option<String>,
+}
+
+impl Client {
+    /// Constructs a new [`Client`] for the server at `base_url`, e.g. `http://127.0.0.1:3000`
+    pub fn new(base_url: &str, token: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}{path}", self.base_url));
+        match &self.token {
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    /// Loads all notes of the user, page by page
+    pub async fn notes(&self) -> Result<Vec<Note>> {
+        let mut notes = vec![];
+        for page in 1.. {
+            let path = format!("/notes?full=true&page={page}&per_page={PAGE_SIZE}");
+            let batch = self
+                .request(reqwest::Method::GET, &path)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<Vec<Note>>()
+                .await?;
+            let last = batch.len() < PAGE_SIZE;
+            notes.extend(batch);
+            if last {
+                break;
+            }
+        }
+        Ok(notes)
+    }
+
+    /// Creates a new note or updates the existing note `id`
+    pub async fn save(&self, id: Option<Id>, draft: &Draft) -> Result<Note> {
+        let request = match id {
+            Some(id) => self.request(reqwest::Method::PUT, &format!("/note/{}", usize::from(id))),
+            None => self.request(reqwest::Method::POST, "/note"),
+        };
+        Ok(request
+            .json(draft)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Note>()
+            .await?)
+    }
+}
+
+/// The field of the [`Editor`] that receives the input
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Field {
+    Title,
+    Body,
+}
+
+/// A note that is being edited
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Editor {
+    /// `None` for new notes
+    id: Option<Id>,
+    /// Keeps the tags and visibility of existing notes
+    draft: Draft,
+    title: String,
+    body: String,
+    field: Field,
+}
+
+impl Editor {
+    fn new(note: Option<&Note>) -> Self {
+        Self {
+            id: note.map(|note| *note.id()),
+            draft: note.map(Draft::from).unwrap_or_default(),
+            title: note
+                .map(|note| note.title().to_string())
+                .unwrap_or_default(),
+            body: note.map(|note| note.body().to_string()).unwrap_or_default(),
+            field: Field::Title,
+        }
+    }
+
+    fn input(&mut self) -> &mut String {
+        match self.field {
+            Field::Title => &mut self.title,
+            Field::Body => &mut self.body,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Mode {
+    List,
+    Search,
+    Edit(Box<Editor>),
+}
+
+/// What the event loop must do after a key was handled
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Action {
+    None,
+    Quit,
+    Reload,
+    Save(Option<Id>, Box<Draft>),
+}
+
+/// The state of the terminal UI
+pub struct App {
+    notes: Vec<Note>,
+    search: String,
+    sort: SortKey,
+    selected: usize,
+    mode: Mode,
+    /// The last message for the user, e.g. an error
+    status: String,
+}
+
+impl App {
+    pub fn new(notes: Vec<Note>) -> Self {
+        Self {
+            notes,
+            search: String::new(),
+            sort: SortKey::default(),
+            selected: 0,
+            mode: Mode::List,
+            status: String::new(),
+        }
+    }
+
+    /// The query that the server would apply for the current search and sort order
+    fn query(&self) -> NoteQuery {
+        let q = (!self.search.is_empty()).then(|| self.search.clone());
+        NoteQuery::new(None, q, None, Some(self.sort.into()))
+    }
+
+    /// Returns the notes that match the search, in the selected order
+    pub fn visible(&self) -> Vec<&Note> {
+        let query = self.query();
+        let mut notes = self
+            .notes
+            .iter()
+            .filter(|note| query.matches(note))
+            .collect::<Vec<&Note>>();
+        notes.sort_by(|a, b| query.sort().compare(a, b));
+        notes
+    }
+
+    fn selected_note(&self) -> Option<&Note> {
+        self.visible().get(self.selected).copied()
+    }
+
+    /// Replaces all notes, e.g. after a reload
+    pub fn set_notes(&mut self, notes: Vec<Note>) {
+        self.notes = notes;
+        self.selected = self.selected.min(self.visible().len().saturating_sub(1));
+        self.status = format!("{} notes loaded", self.notes.len());
+    }
+
+    /// Closes the editor after the note was saved
+    pub fn saved(&mut self, note: Note) {
+        self.status = format!("Saved \"{}\"", note.title());
+        match self.notes.iter_mut().find(|other| other.id() == note.id()) {
+            Some(existing) => *existing = note,
+            None => self.notes.push(note),
+        }
+        self.mode = Mode::List;
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    /// Handles a key press
+    pub fn handle(&mut self, key: KeyEvent) -> Action {
+        match &mut self.mode {
+            Mode::List => self.handle_list(key),
+            Mode::Search => {
+                match key.code {
+                    KeyCode::Enter => self.mode = Mode::List,
+                    KeyCode::Esc => {
+                        self.search.clear();
+                        self.mode = Mode::List;
+                    }
+                    KeyCode::Backspace => {
+                        self.search.pop();
+                    }
+                    KeyCode::Char(c) => self.search.push(c),
+                    _ => {}
+                }
+                self.selected = 0;
+                Action::None
+            }
+            Mode::Edit(editor) => {
+                let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                match key.code {
+                    KeyCode::Char('s') if ctrl => {
+                        let draft = editor
+                            .draft
+                            .clone()
+                            .with_text(editor.title.clone(), editor.body.clone());
+                        return Action::Save(editor.id, Box::new(draft));
+                    }
+                    KeyCode::Esc => self.mode = Mode::List,
+                    KeyCode::Tab => {
+                        editor.field = match editor.field {
+                            Field::Title => Field::Body,
+                            Field::Body => Field::Title,
+                        }
+                    }
+                    KeyCode::Enter if editor.field == Field::Body => editor.body.push('\n'),
+                    KeyCode::Enter => editor.field = Field::Body,
+                    KeyCode::Backspace => {
+                        editor.input().pop();
+                    }
+                    KeyCode::Char(c) => editor.input().push(c),
+                    _ => {}
+                }
+                Action::None
+            }
+        }
+    }
+
+    fn handle_list(&mut self, key: KeyEvent) -> Action {
+        let count = self.visible().len();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('r') => return Action::Reload,
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(count.saturating_sub(1))
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('/') => self.mode = Mode::Search,
+            KeyCode::Char('s') => {
+                self.sort = match self.sort {
+                    SortKey::Id => SortKey::Title,
+                    SortKey::Title => SortKey::Created,
+                    SortKey::Created => SortKey::Updated,
+                    SortKey::Updated => SortKey::Id,
+                };
+                self.selected = 0;
+            }
+            KeyCode::Char('e') | KeyCode::Enter => {
+                if let Some(note) = self.selected_note() {
+                    self.mode = Mode::Edit(Box::new(Editor::new(Some(note))));
+                }
+            }
+            KeyCode::Char('n') => self.mode = Mode::Edit(Box::new(Editor::new(None))),
+            _ => {}
+        }
+        Action::None
+    }
+
+    /// Renders the UI
+    fn draw(&self, frame: &mut Frame) {
+        let [top, main, bottom] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let search_style = match self.mode {
+            Mode::Search => Style::default().add_modifier(Modifier::BOLD),
+            _ => Style::default(),
+        };
+        frame.render_widget(
+            Paragraph::new(self.search.as_str())
+                .style(search_style)
+                .block(Block::bordered().title(format!("Search (sorted by {:?})", self.sort))),
+            top,
+        );
+
+        match &self.mode {
+            Mode::Edit(editor) => draw_editor(frame, editor, main),
+            _ => self.draw_list(frame, main),
+        }
+
+        let help = match self.mode {
+            Mode::List => "q quit  / search  s sort  e edit  n new  r reload",
+            Mode::Search => "Enter keep search  Esc clear search",
+            Mode::Edit(_) => "Tab switch field  Ctrl+S save  Esc cancel",
+        };
+        let status = if self.status.is_empty() {
+            help.to_string()
+        } else {
+            format!("{}  |  {help}", self.status)
+        };
+        frame.render_widget(Paragraph::new(status), bottom);
+    }
+
+    fn draw_list(&self, frame: &mut Frame, area: Rect) {
+        let [list, preview] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(area);
+        let notes = self.visible();
+        let items = notes
+            .iter()
+            .map(|note| ListItem::new(note.title().to_string()))
+            .collect::<Vec<ListItem>>();
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().title(format!("Notes ({})", notes.len())))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+            list,
+            &mut state,
+        );
+
+        let text = match notes.get(self.selected) {
+            Some(note) => {
+                let mut lines = vec![
+                    Line::styled(note.title(), Style::default().add_modifier(Modifier::BOLD)),
+                    Line::from(format!(
+                        "{:?} | {}",
+                        note.visibility(),
+                        note.tags()
+                            .map(|tag| tag.label())
+                            .collect::<Vec<&str>>()
+                            .join(", ")
+                    )),
+                    Line::default(),
+                ];
+                lines.extend(
+                    note.body()
+                        .text()
+                        .lines()
+                        .map(|line| Line::from(line.to_string())),
+                );
+                lines
+            }
+            None => vec![],
+        };
+        frame.render_widget(
+            Paragraph::new(text)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered()),
+            preview,
+        );
+    }
+}
+
+fn draw_editor(frame: &mut Frame, editor: &Editor, area: Rect) {
+    let [title, body] = Layout::vertical([Constraint::Length(3), Constraint::Min(3)]).areas(area);
+    let style = |field: Field| match editor.field == field {
+        true => Style::default().add_modifier(Modifier::BOLD),
+        false => Style::default(),
+    };
+    frame.render_widget(
+        Paragraph::new(editor.title.as_str())
+            .block(Block::bordered().title("Title").style(style(Field::Title))),
+        title,
+    );
+    frame.render_widget(
+        Paragraph::new(editor.body.as_str())
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title("Body").style(style(Field::Body))),
+        body,
+    );
+}
+
+/// Runs the terminal UI until the user quits
+pub async fn run(client: Client) -> Result<()> {
+    let mut app = App::new(client.notes().await?);
+    let mut terminal = ratatui::init();
+    let res = async {
+        loop {
+            terminal.draw(|frame| app.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match app.handle(key) {
+                Action::None => {}
+                Action::Quit => return Ok(()),
+                Action::Reload => match client.notes().await {
+                    Ok(notes) => app.set_notes(notes),
+                    Err(err) => app.set_status(format!("Loading failed: {err}")),
+                },
+                Action::Save(id, draft) => match client.save(id, &draft).await {
+                    Ok(note) => app.saved(note),
+                    Err(err) => app.set_status(format!("Saving failed: {err}")),
+                },
+            }
+        }
+    }
+    .await;
+    ratatui::restore();
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::note::test_note::example_note;
+    use crate::models::note::Tags;
+    use crate::models::Visibility;
+
+    fn note(id: usize, title: &str) -> Note {
+        Note::new(
+            Draft::new(
+                title.to_string(),
+                "Body".to_string(),
+                vec![],
+                Visibility::Private,
+            ),
+            Id(id),
+            Id(0),
+            Tags::default(),
+        )
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> Action {
+        app.handle(KeyEvent::from(code))
+    }
+
+    fn titles(app: &App) -> Vec<&str> {
+        app.visible().iter().map(|note| note.title()).collect()
+    }
+
+    #[test]
+    fn test_search_and_sort() {
+        let mut app = App::new(vec![note(2, "Apples"), note(0, "Pears"), example_note()]);
+        assert_eq!(titles(&app), vec!["Pears", "Test-Title", "Apples"]);
+
+        press(&mut app, KeyCode::Char('s'));
+        assert_eq!(titles(&app), vec!["Apples", "Pears", "Test-Title"]);
+
+        press(&mut app, KeyCode::Char('/'));
+        for c in "ea".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        assert_eq!(titles(&app), vec!["Pears"]);
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(titles(&app), vec!["Pears"]);
+        assert_eq!(press(&mut app, KeyCode::Char('q')), Action::Quit);
+
+        press(&mut app, KeyCode::Char('/'));
+        press(&mut app, KeyCode::Esc);
+        assert_eq!(titles(&app).len(), 3);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut app = App::new(vec![note(0, "First"), note(1, "Second")]);
+        press(&mut app, KeyCode::Char('j'));
+        press(&mut app, KeyCode::Char('j'));
+        press(&mut app, KeyCode::Char('e'));
+        press(&mut app, KeyCode::Backspace);
+        press(&mut app, KeyCode::Char('!'));
+        press(&mut app, KeyCode::Tab);
+        press(&mut app, KeyCode::Enter);
+        press(&mut app, KeyCode::Char('x'));
+        // `s` is only a shortcut with Ctrl in the editor
+        assert_eq!(press(&mut app, KeyCode::Char('s')), Action::None);
+
+        let save = app.handle(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        let expected =
+            Draft::from(&note(1, "Second")).with_text("Secon!".to_string(), "Body\nxs".to_string());
+        assert_eq!(save, Action::Save(Some(Id(1)), Box::new(expected)));
+
+        app.saved(note(1, "Secon!"));
+        assert_eq!(titles(&app), vec!["First", "Secon!"]);
+
+        press(&mut app, KeyCode::Char('n'));
+        let save = app.handle(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        assert_eq!(save, Action::Save(None, Box::default()));
+    }
+}
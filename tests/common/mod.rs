@@ -0,0 +1,250 @@
+//! A harness to test the HTTP API end-to-end, without a network listener
+//!
+//! Every [`TestApp`] has its own empty storage. Requests are sent directly to the
+//! [`Router`] with [`ServiceExt::oneshot`], so they pass all extractors and layers
+//! like in production:
+//! ```ignore
+//! let app = TestApp::new();
+//! let note: Note = app.post("/note").json(note("Title").build()).send().await.json();
+//! ```
+#![allow(dead_code)] // not every test binary uses every helper
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::Router;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use note_demo::app;
+use note_demo::auth::USER_HEADER;
+use note_demo::config::Config;
+use note_demo::models::Id;
+use note_demo::persistence::memory::InMemoryStorage;
+use note_demo::persistence::Persister;
+use note_demo::AppState;
+
+/// The app with its state, to prepare data that the API can't create, e.g. users
+pub struct TestApp {
+    pub state: AppState<InMemoryStorage>,
+    router: Router,
+}
+
+/// The default [`Config`] of the tests, which trusts the [`USER_HEADER`] of [`TestRequest::user`]
+pub fn config() -> Config {
+    Config {
+        trust_user_header: true,
+        ..Config::default()
+    }
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        Self::with_config(config())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        let state = app::state(config);
+        let router = app::router(state.clone());
+        Self { state, router }
+    }
+
+    /// Adds a user and returns its Id, e.g. to send requests with [`TestRequest::user`]
+    pub fn add_user(&self, name: &str) -> Id {
+        let mut data = self.state.data.lock().expect("mutex was poisoned");
+        *data.add_user(name.to_string(), None).id()
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> TestRequest {
+        TestRequest {
+            router: self.router.clone(),
+            request: Request::builder().method(method).uri(path),
+            body: Body::empty(),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> TestRequest {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> TestRequest {
+        self.request(Method::POST, path)
+    }
+
+    pub fn put(&self, path: &str) -> TestRequest {
+        self.request(Method::PUT, path)
+    }
+
+    pub fn delete(&self, path: &str) -> TestRequest {
+        self.request(Method::DELETE, path)
+    }
+}
+
+/// A request that is built step by step and sent with [`TestRequest::send`]
+pub struct TestRequest {
+    router: Router,
+    request: axum::http::request::Builder,
+    body: Body,
+}
+
+impl TestRequest {
+    /// Sends the request as the user, instead of the anonymous user
+    pub fn user(self, id: Id) -> Self {
+        self.header(USER_HEADER, &usize::from(id).to_string())
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.request = self.request.header(name, value);
+        self
+    }
+
+    /// Sets the body with `Content-Type: application/json`
+    pub fn json(self, body: Value) -> Self {
+        self.header(header::CONTENT_TYPE.as_str(), "application/json")
+            .body(body.to_string())
+    }
+
+    /// Sets the body without changing the content type
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub async fn send(self) -> TestResponse {
+        let request = self.request.body(self.body).expect("invalid request");
+        let response = self
+            .router
+            .oneshot(request)
+            .await
+            .expect("the router is infallible");
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("response body can be read");
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+}
+
+/// The complete response to a [`TestRequest`]
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    /// Deserializes the JSON body and fails the test if that is not possible
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_slice(&self.body).unwrap_or_else(|err| {
+            panic!(
+                "invalid JSON response ({err}) with status {}: {}",
+                self.status,
+                self.text()
+            )
+        })
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    /// Fails the test with the body of the response if the status is not `expected`
+    pub fn assert_status(&self, expected: StatusCode) -> &Self {
+        assert_eq!(
+            self.status,
+            expected,
+            "unexpected response: {}",
+            self.text()
+        );
+        self
+    }
+}
+
+/// Builds the body of `POST /note` and `PUT /note/:id`
+pub struct NoteBody {
+    body: Value,
+}
+
+/// Starts a [`NoteBody`] with the title, an empty body and no tags
+pub fn note(title: &str) -> NoteBody {
+    NoteBody {
+        body: json!({"title": title, "body": "", "tags": []}),
+    }
+}
+
+impl NoteBody {
+    pub fn body(mut self, body: &str) -> Self {
+        self.body["body"] = json!(body);
+        self
+    }
+
+    pub fn tags(mut self, tags: &[&str]) -> Self {
+        self.body["tags"] = json!(tags);
+        self
+    }
+
+    pub fn public(mut self) -> Self {
+        self.body["visibility"] = json!("Public");
+        self
+    }
+
+    pub fn private(mut self) -> Self {
+        self.body["visibility"] = json!("Private");
+        self
+    }
+
+    /// Sets a visibility by its name, e.g. `Unlisted`
+    pub fn visibility(mut self, visibility: &str) -> Self {
+        self.body["visibility"] = json!(visibility);
+        self
+    }
+
+    /// Sets the format of the body, e.g. `asciidoc`
+    pub fn format(mut self, format: &str) -> Self {
+        self.body["format"] = json!(format);
+        self
+    }
+
+    pub fn location(mut self, lat: f64, lon: f64) -> Self {
+        self.body["location"] = json!({"lat": lat, "lon": lon});
+        self
+    }
+
+    /// Sets the kind of the note with its specific fields, e.g. `("task", json!({"done": true}))`
+    pub fn kind(mut self, kind: &str, fields: Value) -> Self {
+        self.body["kind"] = json!(kind);
+        if let Value::Object(fields) = fields {
+            for (name, value) in fields {
+                self.body[name] = value;
+            }
+        }
+        self
+    }
+
+    /// Creates the note from a template with the values of its fields
+    pub fn template(mut self, name: &str, fields: Value) -> Self {
+        self.body["template"] = json!(name);
+        self.body["fields"] = fields;
+        self
+    }
+
+    pub fn build(self) -> Value {
+        self.body
+    }
+}
+
+/// The body of `POST /note/:id/comments`
+pub fn comment(body: &str) -> Value {
+    json!({ "body": body })
+}
+
+/// The body of `POST /notes/tags`
+pub fn bulk_tag(notes: &[Id], add: &[&str], remove: &[&str]) -> Value {
+    json!({"notes": notes, "add": add, "remove": remove})
+}